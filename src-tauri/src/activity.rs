@@ -0,0 +1,176 @@
+//! Idle and active detection from session output.
+//!
+//! A session goes idle after `idle_after_secs` without output and emits
+//! `session:{id}:idle`; once idle, `active_min_bytes` of new output emit
+//! `session:{id}:active`, so a tab can be badged when something starts
+//! happening during a long soak test. Sessions start active.
+
+use crate::session::ActivityConfig;
+use bytes::Bytes;
+use serde::Serialize;
+use tauri::Emitter;
+use tokio::sync::broadcast;
+use tokio::time::{sleep_until, Duration, Instant};
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum ActivityEvent {
+    Idle {
+        /// Silence so far, at least `idle_after_secs`
+        silent_ms: u64,
+    },
+    Active {
+        /// How long the session was silent
+        idle_ms: u64,
+        /// Output since going idle
+        bytes: usize,
+    },
+}
+
+/// Idle state of one session's output
+struct ActivityTracker {
+    config: ActivityConfig,
+    last_output: Instant,
+    /// Set while idle: when output stopped and bytes seen since
+    idle: Option<(Instant, usize)>,
+}
+
+impl ActivityTracker {
+    fn new(config: ActivityConfig, now: Instant) -> Self {
+        Self {
+            config,
+            last_output: now,
+            idle: None,
+        }
+    }
+
+    fn idle_after(&self) -> Option<Duration> {
+        (self.config.idle_after_secs > 0).then(|| Duration::from_secs(self.config.idle_after_secs))
+    }
+
+    /// When the session goes idle without more output
+    fn deadline(&self) -> Option<Instant> {
+        match self.idle {
+            Some(_) => None,
+            None => self.idle_after().map(|after| self.last_output + after),
+        }
+    }
+
+    fn output(&mut self, bytes: usize, now: Instant) -> Option<ActivityEvent> {
+        let Some((since, seen)) = self.idle.as_mut() else {
+            self.last_output = now;
+            return None;
+        };
+        *seen = seen.saturating_add(bytes);
+        if *seen < self.config.active_min_bytes.max(1) {
+            return None;
+        }
+        let event = ActivityEvent::Active {
+            idle_ms: now.duration_since(*since).as_millis() as u64,
+            bytes: *seen,
+        };
+        self.idle = None;
+        self.last_output = now;
+        Some(event)
+    }
+
+    fn tick(&mut self, now: Instant) -> Option<ActivityEvent> {
+        if self.deadline().is_none_or(|deadline| now < deadline) {
+            return None;
+        }
+        self.idle = Some((self.last_output, 0));
+        Some(ActivityEvent::Idle {
+            silent_ms: now.duration_since(self.last_output).as_millis() as u64,
+        })
+    }
+}
+
+/// Watch a session's output until it ends
+pub fn spawn_activity(
+    session_id: String,
+    app_handle: tauri::AppHandle,
+    mut output: broadcast::Receiver<Bytes>,
+    config: ActivityConfig,
+) {
+    let mut tracker = ActivityTracker::new(config, Instant::now());
+    if tracker.idle_after().is_none() {
+        return;
+    }
+    let (idle_event, active_event) = (
+        format!("session:{}:idle", session_id),
+        format!("session:{}:active", session_id),
+    );
+    tokio::spawn(async move {
+        loop {
+            let deadline = tracker.deadline();
+            let event = tokio::select! {
+                received = output.recv() => match received {
+                    Ok(data) => tracker.output(data.len(), Instant::now()),
+                    // Missed chunks are still output
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        tracker.output(usize::MAX, Instant::now())
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    tracker.tick(Instant::now())
+                }
+            };
+            let Some(event) = event else {
+                continue;
+            };
+            debug!(session_id = %session_id, event = ?event, "Session activity changed");
+            let name = match event {
+                ActivityEvent::Idle { .. } => &idle_event,
+                ActivityEvent::Active { .. } => &active_event,
+            };
+            if let Err(e) = app_handle.emit(name, &event) {
+                warn!(session_id = %session_id, error = %e, "Failed to emit activity event");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_and_active() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut tracker = ActivityTracker::new(ActivityConfig::default(), start);
+
+        assert_eq!(tracker.tick(at(30)), None);
+        // Output pushes the deadline back
+        assert_eq!(tracker.output(10, at(30)), None);
+        assert_eq!(tracker.deadline(), Some(at(90)));
+        assert_eq!(tracker.tick(at(60)), None);
+        assert_eq!(
+            tracker.tick(at(90)),
+            Some(ActivityEvent::Idle { silent_ms: 60_000 })
+        );
+        assert_eq!(tracker.deadline(), None);
+
+        // A redrawn prompt is not enough to be active again
+        assert_eq!(tracker.output(20, at(100)), None);
+        assert_eq!(
+            tracker.output(60, at(120)),
+            Some(ActivityEvent::Active {
+                idle_ms: 90_000,
+                bytes: 80
+            })
+        );
+        assert_eq!(tracker.deadline(), Some(at(180)));
+
+        let disabled = ActivityTracker::new(
+            ActivityConfig {
+                idle_after_secs: 0,
+                ..ActivityConfig::default()
+            },
+            start,
+        );
+        assert_eq!(disabled.deadline(), None);
+    }
+}
@@ -0,0 +1,713 @@
+//! Alerts: desktop notifications for what an engineer would walk back to
+//! the screen for.
+//!
+//! Alert rules (`alerts.json` in the app data dir) pick events from three
+//! feeds by source, severity and a regex on the text: VRP sessions (boards
+//! going absent or offline), the syslog receiver and the SNMP trap
+//! receiver. The first enabled rule that matches raises the alert. Notify
+//! actions of automation rules always raise one, with the automation rule's
+//! cooldown as its throttle.
+//!
+//! A repeat of an alert (same rule, source, host and title) within the
+//! throttle window only bumps its count, and at most
+//! `MAX_NOTIFICATIONS_PER_MINUTE` new alerts are notified; the rest are
+//! still kept in the history. Every new or repeated alert is emitted as
+//! `alert`, and the frontend shows the OS notification when `notify` is set.
+
+use crate::events::SessionEvent;
+use crate::session::SessionManager;
+use crate::snmp::{SnmpTrap, TrapReceiver, TrapSeverity};
+use crate::syslog::{SyslogMessage, SyslogReceiver};
+use crate::vrp::VrpEvent;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+const DEFAULT_THROTTLE_SECS: u64 = 300;
+
+/// Longest throttle accepted by `save_rule`
+const MAX_THROTTLE_SECS: u64 = 86_400;
+
+/// Alerts kept for `list_alerts`
+const MAX_HISTORY: usize = 500;
+
+/// Throttle entries kept before expired ones are pruned
+const MAX_THROTTLE_ENTRIES: usize = 1024;
+
+/// New alerts notified per minute; a burst beyond that is history only
+const MAX_NOTIFICATIONS_PER_MINUTE: usize = 10;
+
+/// Trap varbinds quoted in an alert's message
+const MAX_TRAP_VARBINDS: usize = 5;
+
+/// Alert events kept for a slow `subscribe` receiver
+const FEED_CAPACITY: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum AlertError {
+    #[error("Alert rule not found: {0}")]
+    NotFound(String),
+    #[error("Invalid alert rule: {0}")]
+    Invalid(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<AlertError> for String {
+    fn from(err: AlertError) -> String {
+        err.to_string()
+    }
+}
+
+/// Most severe first, so `min_severity` keeps what sorts at or before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSeverity {
+    Critical,
+    Major,
+    Minor,
+    Warning,
+    Info,
+}
+
+impl AlertSeverity {
+    /// Syslog 0 (emergency) to 7 (debug)
+    pub fn from_syslog(severity: u8) -> Self {
+        match severity {
+            0..=2 => Self::Critical,
+            3 => Self::Major,
+            4 => Self::Warning,
+            _ => Self::Info,
+        }
+    }
+}
+
+impl From<TrapSeverity> for AlertSeverity {
+    fn from(severity: TrapSeverity) -> Self {
+        match severity {
+            TrapSeverity::Critical => Self::Critical,
+            TrapSeverity::Major => Self::Major,
+            TrapSeverity::Minor => Self::Minor,
+            TrapSeverity::Warning => Self::Warning,
+            TrapSeverity::Info | TrapSeverity::Cleared | TrapSeverity::Unknown => Self::Info,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSource {
+    /// Notify actions of automation rules
+    Trigger,
+    Vrp,
+    Syslog,
+    Trap,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_throttle() -> u64 {
+    DEFAULT_THROTTLE_SECS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertRule {
+    /// Assigned by `save_rule` when empty
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Feeds the rule applies to; empty means all of them
+    #[serde(default)]
+    pub sources: Vec<AlertSource>,
+    /// Least severe level that alerts; default any
+    #[serde(default)]
+    pub min_severity: Option<AlertSeverity>,
+    /// Regex on the alert's title or message
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Repeats within this many seconds are counted, not notified
+    #[serde(default = "default_throttle")]
+    pub throttle_secs: u64,
+}
+
+/// Something a feed saw
+#[derive(Debug, Clone)]
+pub struct AlertInput {
+    pub source: AlertSource,
+    pub severity: AlertSeverity,
+    pub title: String,
+    pub message: String,
+    /// Device address, for deduplication and the notification
+    pub host: Option<String>,
+    pub session_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub id: String,
+    /// The alert or automation rule that raised it
+    pub rule_id: String,
+    pub rule_name: String,
+    pub source: AlertSource,
+    pub severity: AlertSeverity,
+    pub title: String,
+    /// The latest repeat's message
+    pub message: String,
+    pub host: Option<String>,
+    pub session_id: Option<String>,
+    /// Unix milliseconds
+    pub first_at: i64,
+    pub last_at: i64,
+    /// Occurrences within the throttle window, from 1
+    pub count: u32,
+    /// A desktop notification was shown for it
+    pub notified: bool,
+}
+
+/// Body of `alert` events
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent {
+    pub alert: Alert,
+    /// Show a desktop notification for it
+    pub notify: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoreFile {
+    rules: Vec<AlertRule>,
+}
+
+/// Alert rules and their compiled patterns
+pub struct AlertStore {
+    path: PathBuf,
+    rules: Vec<AlertRule>,
+    patterns: HashMap<String, Regex>,
+}
+
+impl AlertStore {
+    /// A missing or unreadable file is an empty store; rules with a
+    /// pattern that no longer compiles are dropped
+    pub fn load(path: PathBuf) -> Self {
+        let rules = match std::fs::read(&path) {
+            Ok(data) => match serde_json::from_slice::<StoreFile>(&data) {
+                Ok(file) => file.rules,
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Corrupt alert store, starting empty");
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Failed to read alert store");
+                Vec::new()
+            }
+        };
+        let mut store = Self {
+            path,
+            rules: Vec::new(),
+            patterns: HashMap::new(),
+        };
+        for rule in rules {
+            match compile(&rule) {
+                Ok(pattern) => store.insert(rule, pattern),
+                Err(e) => warn!(rule_id = %rule.id, error = %e, "Dropping alert rule"),
+            }
+        }
+        store
+    }
+
+    pub fn list(&self) -> Vec<AlertRule> {
+        self.rules.clone()
+    }
+
+    /// Add a rule, or replace the rule with the same id
+    pub fn save(&mut self, mut rule: AlertRule) -> Result<AlertRule, AlertError> {
+        rule.name = rule.name.trim().to_string();
+        let pattern = compile(&rule)?;
+        if rule.id.is_empty() {
+            rule.id = Uuid::new_v4().to_string();
+        }
+        self.insert(rule.clone(), pattern);
+        self.persist()?;
+        info!(rule_id = %rule.id, name = %rule.name, "Saved alert rule");
+        Ok(rule)
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<(), AlertError> {
+        let before = self.rules.len();
+        self.rules.retain(|r| r.id != id);
+        if self.rules.len() == before {
+            return Err(AlertError::NotFound(id.to_string()));
+        }
+        self.patterns.remove(id);
+        self.persist()
+    }
+
+    /// The first enabled rule `input` matches
+    fn matching(&self, input: &AlertInput) -> Option<&AlertRule> {
+        self.rules.iter().find(|rule| {
+            rule.enabled
+                && (rule.sources.is_empty() || rule.sources.contains(&input.source))
+                && rule.min_severity.is_none_or(|min| input.severity <= min)
+                && self.patterns.get(&rule.id).is_none_or(|pattern| {
+                    pattern.is_match(&input.title) || pattern.is_match(&input.message)
+                })
+        })
+    }
+
+    fn insert(&mut self, rule: AlertRule, pattern: Option<Regex>) {
+        match pattern {
+            Some(pattern) => self.patterns.insert(rule.id.clone(), pattern),
+            None => self.patterns.remove(&rule.id),
+        };
+        match self.rules.iter_mut().find(|r| r.id == rule.id) {
+            Some(existing) => *existing = rule,
+            None => self.rules.push(rule),
+        }
+    }
+
+    fn persist(&self) -> Result<(), AlertError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = StoreFile {
+            rules: self.rules.clone(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&file)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Validate a rule and compile its pattern
+fn compile(rule: &AlertRule) -> Result<Option<Regex>, AlertError> {
+    if rule.name.trim().is_empty() {
+        return Err(AlertError::Invalid("name must not be empty".to_string()));
+    }
+    if rule.throttle_secs > MAX_THROTTLE_SECS {
+        return Err(AlertError::Invalid(format!(
+            "throttle_secs must be at most {}",
+            MAX_THROTTLE_SECS
+        )));
+    }
+    rule.pattern
+        .as_deref()
+        .filter(|pattern| !pattern.is_empty())
+        .map(Regex::new)
+        .transpose()
+        .map_err(|e| AlertError::Invalid(format!("pattern: {}", e)))
+}
+
+/// What makes two alerts the same: rule, source, host and title
+type AlertKey = (String, AlertSource, Option<String>, String);
+
+/// Alert history with deduplication and the notification rate limit
+#[derive(Default)]
+struct History {
+    alerts: VecDeque<Alert>,
+    /// When each alert's throttle window started, and the alert
+    windows: HashMap<AlertKey, (Instant, String)>,
+    /// Notifications shown in the last minute
+    shown: VecDeque<Instant>,
+}
+
+impl History {
+    fn record(
+        &mut self,
+        rule_id: &str,
+        rule_name: &str,
+        throttle: Duration,
+        input: AlertInput,
+        now: Instant,
+    ) -> AlertEvent {
+        let timestamp = chrono::Utc::now().timestamp_millis();
+        let key = (
+            rule_id.to_string(),
+            input.source,
+            input.host.clone(),
+            input.title.clone(),
+        );
+        if let Some((started, id)) = self.windows.get(&key) {
+            if now.duration_since(*started) < throttle {
+                if let Some(alert) = self.alerts.iter_mut().rfind(|a| a.id == *id) {
+                    alert.count += 1;
+                    alert.last_at = timestamp;
+                    alert.message = input.message;
+                    return AlertEvent {
+                        alert: alert.clone(),
+                        notify: false,
+                    };
+                }
+            }
+        }
+
+        while self
+            .shown
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= Duration::from_secs(60))
+        {
+            self.shown.pop_front();
+        }
+        let notify = self.shown.len() < MAX_NOTIFICATIONS_PER_MINUTE;
+        if notify {
+            self.shown.push_back(now);
+        }
+        let alert = Alert {
+            id: Uuid::new_v4().to_string(),
+            rule_id: rule_id.to_string(),
+            rule_name: rule_name.to_string(),
+            source: input.source,
+            severity: input.severity,
+            title: input.title,
+            message: input.message,
+            host: input.host,
+            session_id: input.session_id,
+            first_at: timestamp,
+            last_at: timestamp,
+            count: 1,
+            notified: notify,
+        };
+        if self.windows.len() >= MAX_THROTTLE_ENTRIES {
+            self.windows.retain(|_, (started, _)| {
+                now.duration_since(*started) < Duration::from_secs(MAX_THROTTLE_SECS)
+            });
+        }
+        self.windows.insert(key, (now, alert.id.clone()));
+        if self.alerts.len() >= MAX_HISTORY {
+            self.alerts.pop_front();
+        }
+        self.alerts.push_back(alert.clone());
+        AlertEvent { alert, notify }
+    }
+}
+
+pub struct AlertEngine {
+    app_handle: tauri::AppHandle,
+    store: Mutex<AlertStore>,
+    history: Mutex<History>,
+    /// Every `alert` event, for backend consumers
+    feed: broadcast::Sender<AlertEvent>,
+}
+
+impl AlertEngine {
+    pub fn new(app_handle: tauri::AppHandle, store: AlertStore) -> Self {
+        Self {
+            app_handle,
+            store: Mutex::new(store),
+            history: Mutex::new(History::default()),
+            feed: broadcast::channel(FEED_CAPACITY).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AlertEvent> {
+        self.feed.subscribe()
+    }
+
+    pub fn rules(&self) -> Vec<AlertRule> {
+        self.store.lock().unwrap().list()
+    }
+
+    pub fn save_rule(&self, rule: AlertRule) -> Result<AlertRule, AlertError> {
+        self.store.lock().unwrap().save(rule)
+    }
+
+    pub fn delete_rule(&self, id: &str) -> Result<(), AlertError> {
+        self.store.lock().unwrap().delete(id)
+    }
+
+    /// The newest `limit` alerts, oldest first
+    pub fn alerts(&self, limit: usize) -> Vec<Alert> {
+        let history = self.history.lock().unwrap();
+        history
+            .alerts
+            .iter()
+            .skip(history.alerts.len().saturating_sub(limit))
+            .cloned()
+            .collect()
+    }
+
+    /// Forget the history; throttle windows start over
+    pub fn clear(&self) {
+        let mut history = self.history.lock().unwrap();
+        history.alerts.clear();
+        history.windows.clear();
+    }
+
+    /// Raise an alert if a rule matches `input`
+    pub fn observe(&self, input: AlertInput) {
+        let Some(rule) = self.store.lock().unwrap().matching(&input).cloned() else {
+            return;
+        };
+        let throttle = Duration::from_secs(rule.throttle_secs);
+        self.raise(&rule.id, &rule.name, throttle, input);
+    }
+
+    /// Raise an alert on behalf of `rule_id`, e.g. an automation rule
+    pub fn raise(&self, rule_id: &str, rule_name: &str, throttle: Duration, input: AlertInput) {
+        let event = self.history.lock().unwrap().record(
+            rule_id,
+            rule_name,
+            throttle,
+            input,
+            Instant::now(),
+        );
+        if event.alert.count == 1 {
+            info!(
+                rule_id = %rule_id,
+                severity = ?event.alert.severity,
+                title = %event.alert.title,
+                notify = event.notify,
+                "Alert raised"
+            );
+        } else {
+            debug!(alert_id = %event.alert.id, count = event.alert.count, "Alert repeated");
+        }
+        if let Err(e) = self.app_handle.emit("alert", &event) {
+            warn!(error = %e, "Failed to emit alert");
+        }
+        let _ = self.feed.send(event);
+    }
+}
+
+/// Feed VRP board events, syslog messages and traps to the rules until the
+/// app exits
+pub fn spawn_feeds(
+    engine: &Arc<AlertEngine>,
+    manager: &Arc<SessionManager>,
+    syslog: &SyslogReceiver,
+    traps: &TrapReceiver,
+) {
+    let mut events = manager.events().subscribe();
+    let (alerts, manager) = (Arc::clone(engine), Arc::clone(manager));
+    tauri::async_runtime::spawn(async move {
+        while let Some(SessionEvent { session_id, event }) = next(&mut events, "VRP").await {
+            if let Some(input) = board_alert(&manager, &session_id, &event) {
+                alerts.observe(input);
+            }
+        }
+    });
+
+    let mut messages = syslog.subscribe();
+    let alerts = Arc::clone(engine);
+    tauri::async_runtime::spawn(async move {
+        while let Some(message) = next(&mut messages, "syslog").await {
+            alerts.observe(syslog_alert(message));
+        }
+    });
+
+    let mut received = traps.subscribe();
+    let alerts = Arc::clone(engine);
+    tauri::async_runtime::spawn(async move {
+        while let Some(trap) = next(&mut received, "trap").await {
+            alerts.observe(trap_alert(trap));
+        }
+    });
+}
+
+/// The next item of a feed, skipping a lag; None once it closes
+pub(crate) async fn next<T: Clone>(feed: &mut broadcast::Receiver<T>, name: &str) -> Option<T> {
+    loop {
+        match feed.recv().await {
+            Ok(item) => return Some(item),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!(feed = name, missed, "Feed fell behind, events dropped");
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// A board reported absent or offline by `display device`
+fn board_alert(manager: &SessionManager, session_id: &str, event: &VrpEvent) -> Option<AlertInput> {
+    let VrpEvent::BoardInfo(board) = event else {
+        return None;
+    };
+    let status = board.status.to_lowercase();
+    if status != "absent" && status != "offline" {
+        return None;
+    }
+    let host = manager
+        .get(session_id)
+        .map(|handle| handle.config.host.clone());
+    Some(AlertInput {
+        source: AlertSource::Vrp,
+        severity: AlertSeverity::Major,
+        title: format!(
+            "{} slot {} {}",
+            host.as_deref().unwrap_or(session_id),
+            board.slot_id,
+            status
+        ),
+        message: format!(
+            "{} board in slot {} is {}",
+            board.board_type, board.slot_id, board.status
+        ),
+        host,
+        session_id: Some(session_id.to_string()),
+    })
+}
+
+fn syslog_alert(message: SyslogMessage) -> AlertInput {
+    let tag = match (&message.app_name, &message.msg_id) {
+        (Some(app), Some(id)) => format!("{}/{}", app, id),
+        (Some(app), None) => app.clone(),
+        (None, Some(id)) => id.clone(),
+        (None, None) => "syslog".to_string(),
+    };
+    let device = message.hostname.as_deref().unwrap_or(&message.source);
+    AlertInput {
+        source: AlertSource::Syslog,
+        severity: message
+            .severity
+            .map_or(AlertSeverity::Info, AlertSeverity::from_syslog),
+        title: format!("{} {}", device, tag),
+        message: message.message,
+        session_id: message.session_ids.into_iter().next(),
+        host: Some(message.source),
+    }
+}
+
+fn trap_alert(trap: SnmpTrap) -> AlertInput {
+    let name = trap
+        .trap_name
+        .as_deref()
+        .or(trap.trap_oid.as_deref())
+        .unwrap_or("trap");
+    let title = match &trap.interface {
+        Some(interface) => format!("{} {} {}", trap.source, name, interface),
+        None => format!("{} {}", trap.source, name),
+    };
+    // sysUpTime and snmpTrapOID are already in the title
+    let message = trap
+        .varbinds
+        .iter()
+        .skip(2)
+        .take(MAX_TRAP_VARBINDS)
+        .map(|v| format!("{}={}", v.name.as_deref().unwrap_or(&v.oid), v.value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    AlertInput {
+        source: AlertSource::Trap,
+        severity: trap.severity.into(),
+        title,
+        message,
+        session_id: trap.session_ids.into_iter().next(),
+        host: Some(trap.source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(source: AlertSource, severity: AlertSeverity, title: &str) -> AlertInput {
+        AlertInput {
+            source,
+            severity,
+            title: title.to_string(),
+            message: "Interface GE0/0/1 turned into DOWN state".to_string(),
+            host: Some("10.0.0.1".to_string()),
+            session_id: None,
+        }
+    }
+
+    #[test]
+    fn test_rules_and_throttling() {
+        let dir = std::env::temp_dir().join(format!("bspt-alerts-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("alerts.json");
+        let mut store = AlertStore::load(path.clone());
+        let rule = store
+            .save(AlertRule {
+                id: String::new(),
+                name: " Link down ".to_string(),
+                enabled: true,
+                sources: vec![AlertSource::Syslog, AlertSource::Trap],
+                min_severity: Some(AlertSeverity::Warning),
+                pattern: Some("(?i)down".to_string()),
+                throttle_secs: 60,
+            })
+            .unwrap();
+        assert_eq!(rule.name, "Link down");
+        assert!(store
+            .save(AlertRule {
+                pattern: Some("(".to_string()),
+                ..rule.clone()
+            })
+            .is_err());
+
+        let store = AlertStore::load(path);
+        assert_eq!(store.list().len(), 1);
+        let syslog = input(
+            AlertSource::Syslog,
+            AlertSeverity::Major,
+            "core-1 IFNET/LINK_STATE",
+        );
+        assert_eq!(store.matching(&syslog).unwrap().id, rule.id);
+        assert!(store
+            .matching(&input(AlertSource::Syslog, AlertSeverity::Info, "core-1"))
+            .is_none());
+        assert!(store
+            .matching(&input(AlertSource::Vrp, AlertSeverity::Major, "core-1"))
+            .is_none());
+
+        let mut history = History::default();
+        let throttle = Duration::from_secs(60);
+        let start = Instant::now();
+        let first = history.record(&rule.id, &rule.name, throttle, syslog.clone(), start);
+        assert!(first.notify);
+        let repeat = history.record(
+            &rule.id,
+            &rule.name,
+            throttle,
+            syslog.clone(),
+            start + Duration::from_secs(30),
+        );
+        assert!(!repeat.notify);
+        assert_eq!(repeat.alert.id, first.alert.id);
+        assert_eq!(repeat.alert.count, 2);
+        let later = history.record(
+            &rule.id,
+            &rule.name,
+            throttle,
+            syslog,
+            start + Duration::from_secs(61),
+        );
+        assert!(later.notify);
+        assert_ne!(later.alert.id, first.alert.id);
+
+        // A burst of different alerts is capped per minute
+        let notified = (0..MAX_NOTIFICATIONS_PER_MINUTE * 2)
+            .map(|n| {
+                let burst = input(AlertSource::Trap, AlertSeverity::Major, &n.to_string());
+                history.record(
+                    &rule.id,
+                    &rule.name,
+                    throttle,
+                    burst,
+                    start + Duration::from_secs(62),
+                )
+            })
+            .filter(|event| event.notify)
+            .count();
+        assert_eq!(notified, MAX_NOTIFICATIONS_PER_MINUTE - 1);
+        assert_eq!(history.alerts.len(), 2 + MAX_NOTIFICATIONS_PER_MINUTE * 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
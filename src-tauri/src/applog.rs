@@ -0,0 +1,377 @@
+//! Backend diagnostics for the in-app debug console.
+//!
+//! `AppLogLayer` sits next to the console formatter in the `tracing`
+//! subscriber. Every event it sees is kept in a bounded in-memory ring for
+//! `get_app_logs`, appended to a rotating file in the app log dir
+//! (`bspt.log`, then `bspt.log.1`..) and broadcast for the live `app:log`
+//! stream. The filter installed in `init` decides what reaches it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{self, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::Emitter;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Entries kept in memory
+const MAX_ENTRIES: usize = 5000;
+
+/// Entries a `get_app_logs` call returns when it doesn't say
+const DEFAULT_LIMIT: usize = 500;
+
+/// Size at which `bspt.log` is rotated
+const MAX_FILE_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Rotated files kept next to the current one
+const KEEP_FILES: usize = 3;
+
+/// Entries kept for a slow `app:log` subscriber
+const STREAM_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<&tracing::Level> for LogLevel {
+    fn from(level: &tracing::Level) -> Self {
+        match *level {
+            tracing::Level::ERROR => Self::Error,
+            tracing::Level::WARN => Self::Warn,
+            tracing::Level::INFO => Self::Info,
+            tracing::Level::DEBUG => Self::Debug,
+            tracing::Level::TRACE => Self::Trace,
+        }
+    }
+}
+
+/// One `tracing` event; also the body of `app:log` events
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// Increases by one per entry, so a console can resume after a gap
+    pub seq: u64,
+    /// Unix milliseconds
+    pub timestamp_ms: i64,
+    pub level: LogLevel,
+    /// Module path, e.g. `bspt_lib::ssh`
+    pub target: String,
+    pub message: String,
+    /// The event's other fields, e.g. `session_id`
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogFilter {
+    /// Least severe level included; default everything
+    #[serde(default)]
+    pub level: Option<LogLevel>,
+    /// Substring of the target
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Case-insensitive substring of the message or a field value
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Only entries after this `seq`
+    #[serde(default)]
+    pub since: Option<u64>,
+}
+
+impl LogFilter {
+    fn matches(&self, entry: &LogEntry, text: Option<&str>) -> bool {
+        self.level.is_none_or(|level| entry.level <= level)
+            && self.since.is_none_or(|since| entry.seq > since)
+            && self
+                .target
+                .as_deref()
+                .is_none_or(|target| entry.target.contains(target))
+            && text.is_none_or(|text| {
+                entry.message.to_lowercase().contains(text)
+                    || entry
+                        .fields
+                        .values()
+                        .any(|v| v.to_lowercase().contains(text))
+            })
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size + line.len() as u64 > MAX_FILE_BYTES {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// `bspt.log.2` → `bspt.log.3` .. `bspt.log` → `bspt.log.1`, dropping
+    /// the oldest
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let numbered = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        for n in (1..KEEP_FILES).rev() {
+            if numbered(n).exists() {
+                fs::rename(numbered(n), numbered(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, numbered(1))?;
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+}
+
+/// The ring, file and stream behind `AppLogLayer`
+pub struct AppLog {
+    entries: Mutex<VecDeque<LogEntry>>,
+    next_seq: Mutex<u64>,
+    file: Mutex<Option<LogFile>>,
+    stream: broadcast::Sender<LogEntry>,
+}
+
+impl AppLog {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::new()),
+            next_seq: Mutex::new(0),
+            file: Mutex::new(None),
+            stream: broadcast::channel(STREAM_CAPACITY).0,
+        }
+    }
+
+    /// Start appending to `dir/bspt.log`; events from before are only in
+    /// memory
+    pub fn open_file(&self, dir: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(dir)?;
+        *self.file.lock().unwrap() = Some(LogFile::open(dir.join("bspt.log"))?);
+        Ok(())
+    }
+
+    fn push(&self, mut entry: LogEntry) {
+        {
+            let mut seq = self.next_seq.lock().unwrap();
+            entry.seq = *seq;
+            *seq += 1;
+        }
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            // Nowhere left to report a failing log file
+            let _ = file.write_line(&format_line(&entry));
+        }
+        let _ = self.stream.send(entry.clone());
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The newest `limit` matching entries, oldest first
+    pub fn query(&self, filter: &LogFilter, limit: Option<usize>) -> Vec<LogEntry> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        let text = filter.text.as_ref().map(|t| t.to_lowercase());
+        let entries = self.entries.lock().unwrap();
+        let mut matched: Vec<LogEntry> = entries
+            .iter()
+            .rev()
+            .filter(|entry| filter.matches(entry, text.as_deref()))
+            .take(limit)
+            .cloned()
+            .collect();
+        matched.reverse();
+        matched
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogEntry> {
+        self.stream.subscribe()
+    }
+}
+
+fn format_line(entry: &LogEntry) -> String {
+    let time = chrono::DateTime::from_timestamp_millis(entry.timestamp_ms)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ");
+    let level = format!("{:?}", entry.level).to_uppercase();
+    let mut line = format!("{} {:>5} {}: {}", time, level, entry.target, entry.message);
+    for (name, value) in &entry.fields {
+        let _ = write!(line, " {}={}", name, value);
+    }
+    line.push('\n');
+    line
+}
+
+/// Collects an event's message and fields
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format!("{:?}", value));
+    }
+}
+
+impl FieldVisitor {
+    fn record(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = value;
+        } else {
+            self.fields.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+pub struct AppLogLayer {
+    log: Arc<AppLog>,
+}
+
+impl<S: Subscriber> Layer<S> for AppLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        self.log.push(LogEntry {
+            seq: 0,
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+            level: metadata.level().into(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+        });
+    }
+}
+
+/// Install the global subscriber: the console formatter plus the app log,
+/// both behind `RUST_LOG` with debug output from this crate
+pub fn init() -> Arc<AppLog> {
+    install("bspt=debug", BoxMakeWriter::new(std::io::stdout))
+}
+
+/// Warnings and errors on stderr, keeping stdout for `bspt-cli` output
+pub fn init_cli() -> Arc<AppLog> {
+    install("bspt=warn", BoxMakeWriter::new(std::io::stderr))
+}
+
+fn install(level: &str, writer: BoxMakeWriter) -> Arc<AppLog> {
+    let log = Arc::new(AppLog::new());
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::from_default_env()
+                .add_directive(level.parse().unwrap())
+                .add_directive("russh=info".parse().unwrap()),
+        )
+        .with(tracing_subscriber::fmt::layer().with_writer(writer))
+        .with(AppLogLayer {
+            log: Arc::clone(&log),
+        })
+        .init();
+    log
+}
+
+/// Forward new entries to the frontend as `app:log` events
+pub fn spawn_emitter(app_handle: tauri::AppHandle, log: &AppLog) {
+    let mut entries = log.subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match entries.recv().await {
+                // Failures aren't logged: that entry would come straight back
+                Ok(entry) => {
+                    let _ = app_handle.emit("app:log", &entry);
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(level: LogLevel, target: &str, message: &str) -> LogEntry {
+        LogEntry {
+            seq: 0,
+            timestamp_ms: 0,
+            level,
+            target: target.to_string(),
+            message: message.to_string(),
+            fields: BTreeMap::from([("host".to_string(), "10.0.0.1".to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_ring_and_file() {
+        let dir = std::env::temp_dir().join(format!("bspt-applog-test-{}", uuid::Uuid::new_v4()));
+        let log = AppLog::new();
+        log.push(entry(LogLevel::Debug, "bspt_lib::ssh", "Connecting"));
+        log.open_file(&dir).unwrap();
+        log.push(entry(LogLevel::Warn, "bspt_lib::ssh", "Connection failed"));
+        log.push(entry(
+            LogLevel::Info,
+            "bspt_lib::textfsm",
+            "Parsed 3 records",
+        ));
+
+        let all = log.query(&LogFilter::default(), None);
+        assert_eq!(all.iter().map(|e| e.seq).collect::<Vec<_>>(), [0, 1, 2]);
+        let warnings = LogFilter {
+            level: Some(LogLevel::Info),
+            target: Some("ssh".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&warnings, None)[0].message, "Connection failed");
+        let text = LogFilter {
+            text: Some("10.0.0".to_string()),
+            since: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&text, Some(1))[0].seq, 2);
+
+        let written = fs::read_to_string(dir.join("bspt.log")).unwrap();
+        assert_eq!(written.lines().count(), 2);
+        assert!(written.starts_with(
+            "1970-01-01T00:00:00.000Z  WARN bspt_lib::ssh: Connection failed host=10.0.0.1"
+        ));
+
+        for n in 0..MAX_ENTRIES {
+            log.push(entry(LogLevel::Trace, "t", &n.to_string()));
+        }
+        assert_eq!(log.entries.lock().unwrap().len(), MAX_ENTRIES);
+        assert_eq!(
+            log.query(&LogFilter::default(), Some(1))[0].seq,
+            MAX_ENTRIES as u64 + 2
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! Passphrase-encrypted profile archives for moving a device book between
+//! machines.
+//!
+//! The archive is a small JSON envelope around a `crypto::Sealed` payload:
+//! the scrypt parameters, the AES-256-GCM nonce and the ciphertext of the
+//! exported profiles.
+
+use crate::crypto::{CryptoError, KdfParams, Sealed, LOG_N};
+use crate::profile::Profile;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+const FORMAT: &str = "bspt-profiles";
+const VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("Invalid archive: {0}")]
+    Invalid(String),
+    #[error("Wrong passphrase or corrupted archive")]
+    Decrypt,
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<CryptoError> for ArchiveError {
+    fn from(err: CryptoError) -> Self {
+        match err {
+            CryptoError::Invalid(message) => ArchiveError::Invalid(message),
+            CryptoError::Decrypt => ArchiveError::Decrypt,
+        }
+    }
+}
+
+impl From<ArchiveError> for String {
+    fn from(err: ArchiveError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    format: String,
+    version: u32,
+    #[serde(flatten)]
+    sealed: Sealed,
+}
+
+/// Plaintext inside the envelope
+#[derive(Serialize, Deserialize)]
+struct Contents {
+    /// Unix time in milliseconds
+    exported_at: u64,
+    profiles: Vec<Profile>,
+}
+
+/// Encrypt `profiles` into an archive at `path`. Saved passwords are left out
+/// unless `include_credentials` is set.
+pub fn export(
+    path: &Path,
+    profiles: Vec<Profile>,
+    passphrase: &str,
+    include_credentials: bool,
+) -> Result<usize, ArchiveError> {
+    write_archive(path, profiles, passphrase, include_credentials, LOG_N)
+}
+
+fn write_archive(
+    path: &Path,
+    profiles: Vec<Profile>,
+    passphrase: &str,
+    include_credentials: bool,
+    log_n: u8,
+) -> Result<usize, ArchiveError> {
+    let profiles: Vec<Profile> = profiles
+        .into_iter()
+        .map(|mut p| {
+            if !include_credentials {
+                p.password = None;
+            }
+            p
+        })
+        .collect();
+    let count = profiles.len();
+    let contents = Contents {
+        exported_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        profiles,
+    };
+
+    let envelope = seal(&serde_json::to_vec(&contents)?, passphrase, log_n)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(&envelope)?)?;
+    Ok(count)
+}
+
+/// Decrypt the profiles stored in the archive at `path`
+pub fn import(path: &Path, passphrase: &str) -> Result<Vec<Profile>, ArchiveError> {
+    let envelope: Envelope = serde_json::from_slice(&std::fs::read(path)?)?;
+    let plaintext = open(&envelope, passphrase)?;
+    let contents: Contents = serde_json::from_slice(&plaintext)?;
+    Ok(contents.profiles)
+}
+
+fn seal(plaintext: &[u8], passphrase: &str, log_n: u8) -> Result<Envelope, ArchiveError> {
+    let kdf = KdfParams::generate(log_n);
+    let key = kdf.derive_key(passphrase)?;
+    Ok(Envelope {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        sealed: Sealed::seal(&key, kdf, plaintext)?,
+    })
+}
+
+fn open(envelope: &Envelope, passphrase: &str) -> Result<Vec<u8>, ArchiveError> {
+    if envelope.format != FORMAT {
+        return Err(ArchiveError::Invalid(
+            "not a bspt profile archive".to_string(),
+        ));
+    }
+    if envelope.version != VERSION {
+        return Err(ArchiveError::Invalid(format!(
+            "unsupported version {}",
+            envelope.version
+        )));
+    }
+
+    let key = envelope.sealed.kdf.derive_key(passphrase)?;
+    Ok(envelope.sealed.open(&key)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests use a low scrypt cost to stay fast in debug builds
+
+    #[test]
+    fn test_seal_open_round_trip() {
+        let envelope = seal(b"device book", "correct horse", 4).unwrap();
+        assert_eq!(open(&envelope, "correct horse").unwrap(), b"device book");
+        assert!(matches!(
+            open(&envelope, "wrong"),
+            Err(ArchiveError::Decrypt)
+        ));
+
+        let mut other = envelope;
+        other.format = "something-else".to_string();
+        assert!(matches!(
+            open(&other, "correct horse"),
+            Err(ArchiveError::Invalid(_))
+        ));
+
+        assert!(matches!(seal(b"x", "", 4), Err(ArchiveError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_export_strips_credentials() {
+        let profile: Profile = serde_json::from_str(
+            r#"{"name": "r1", "host": "10.0.0.1", "port": 23, "protocol": "telnet", "password": "secret"}"#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("bspt-archive-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("book.bspt");
+
+        write_archive(&path, vec![profile.clone()], "pass", false, 4).unwrap();
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert!(!raw.contains("10.0.0.1"));
+        let imported = import(&path, "pass").unwrap();
+        assert_eq!(imported[0].host, "10.0.0.1");
+        assert!(imported[0].password.is_none());
+
+        write_archive(&path, vec![profile], "pass", true, 4).unwrap();
+        assert_eq!(
+            import(&path, "pass").unwrap()[0].password.as_deref(),
+            Some("secret")
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,716 @@
+//! Automation rules (`automation.json` in the app data dir).
+//!
+//! A rule binds a trigger on the VRP events published to the session
+//! manager's event bus to a list of actions, e.g. run `display device slot
+//! {slot}` and start logging when a board goes `Absent`. Actions are
+//! strings with `{placeholder}`s filled from the event that fired the rule,
+//! then from the session's variables (see `vars.rs`).
+//!
+//! A rule fires at most once per session and event (board slot, error
+//! message, view) within its cooldown. Besides limiting noise this stops a
+//! rule from feeding itself: the `display device slot` it runs parses as the
+//! same `Absent` board again.
+
+use crate::alerts::{AlertEngine, AlertInput, AlertSeverity, AlertSource};
+use crate::command::{self, CommandOutput, DEFAULT_COMMAND_TIMEOUT};
+use crate::events::SessionEvent;
+use crate::session::{SessionError, SessionManager};
+use crate::session_log::SessionLog;
+use crate::vars::{self, Scope};
+use crate::vrp::{VrpEvent, VrpView};
+use dashmap::DashSet;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::sync::{broadcast, Mutex};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+const DEFAULT_COOLDOWN_SECS: u64 = 60;
+
+/// Longest cooldown accepted by `save`
+const MAX_COOLDOWN_SECS: u64 = 86_400;
+
+/// Cooldown entries kept before expired ones are pruned
+const MAX_COOLDOWN_ENTRIES: usize = 1024;
+
+/// Automation events kept for a slow receiver of the engine's feed
+const FEED_CAPACITY: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum AutomationError {
+    #[error("Rule not found: {0}")]
+    NotFound(String),
+    #[error("Invalid rule: {0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Session(#[from] SessionError),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<AutomationError> for String {
+    fn from(err: AutomationError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Trigger {
+    /// `display device` reported a board in `status` (case-insensitive)
+    BoardStatus {
+        status: String,
+        #[serde(default)]
+        board_type: Option<String>,
+    },
+    /// The CLI rejected a command; `pattern` is a regex on the message
+    CommandError {
+        #[serde(default)]
+        pattern: Option<String>,
+    },
+    /// The prompt changed to `view` (any view if None)
+    ViewChange {
+        #[serde(default)]
+        view: Option<VrpView>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Raw input; use `\r` to end lines
+    Send { text: String },
+    /// Run a command and wait for the prompt; the output is reported as an
+    /// `automation` event
+    RunCommand {
+        command: String,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+        /// Also keep the output as this session variable
+        #[serde(default)]
+        store_as: Option<String>,
+    },
+    /// Set a session variable, for later rules and scripts
+    SetVariable { name: String, value: String },
+    /// Append the session's output to `path` until it disconnects
+    StartLogging {
+        path: String,
+        #[serde(default)]
+        timestamps: bool,
+    },
+    /// Desktop notification, raised as an alert (see alerts.rs)
+    Notify {
+        title: String,
+        #[serde(default)]
+        body: String,
+        /// Default `Info`
+        #[serde(default)]
+        severity: Option<AlertSeverity>,
+    },
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_cooldown() -> u64 {
+    DEFAULT_COOLDOWN_SECS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutomationRule {
+    /// Assigned by `save` when empty
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub trigger: Trigger,
+    pub actions: Vec<Action>,
+    #[serde(default = "default_cooldown")]
+    pub cooldown_secs: u64,
+}
+
+/// Emitted as `automation` events
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AutomationEvent {
+    Fired {
+        rule_id: String,
+        rule_name: String,
+        session_id: String,
+    },
+    CommandResult {
+        rule_id: String,
+        session_id: String,
+        result: CommandOutput,
+    },
+    Notify {
+        rule_id: String,
+        session_id: String,
+        title: String,
+        body: String,
+    },
+    Failed {
+        rule_id: String,
+        session_id: String,
+        error: String,
+    },
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoreFile {
+    rules: Vec<AutomationRule>,
+}
+
+pub struct AutomationStore {
+    path: PathBuf,
+    rules: Vec<AutomationRule>,
+}
+
+impl AutomationStore {
+    /// A missing or unreadable file is an empty store
+    pub fn load(path: PathBuf) -> Self {
+        let rules = match std::fs::read(&path) {
+            Ok(data) => match serde_json::from_slice::<StoreFile>(&data) {
+                Ok(file) => file.rules,
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Corrupt automation store, starting empty");
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Failed to read automation store");
+                Vec::new()
+            }
+        };
+        Self { path, rules }
+    }
+
+    pub fn list(&self) -> Vec<AutomationRule> {
+        self.rules.clone()
+    }
+
+    /// Add a rule, or replace the rule with the same id
+    pub fn save(&mut self, mut rule: AutomationRule) -> Result<AutomationRule, AutomationError> {
+        validate(&rule)?;
+        if rule.id.is_empty() {
+            rule.id = Uuid::new_v4().to_string();
+        }
+        rule.name = rule.name.trim().to_string();
+        match self.rules.iter_mut().find(|r| r.id == rule.id) {
+            Some(existing) => *existing = rule.clone(),
+            None => self.rules.push(rule.clone()),
+        }
+        self.persist()?;
+        info!(rule_id = %rule.id, name = %rule.name, "Saved automation rule");
+        Ok(rule)
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<(), AutomationError> {
+        let before = self.rules.len();
+        self.rules.retain(|r| r.id != id);
+        if self.rules.len() == before {
+            return Err(AutomationError::NotFound(id.to_string()));
+        }
+        self.persist()
+    }
+
+    fn persist(&self) -> Result<(), AutomationError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = StoreFile {
+            rules: self.rules.clone(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&file)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn validate(rule: &AutomationRule) -> Result<(), AutomationError> {
+    let invalid = |message: &str| Err(AutomationError::Invalid(message.to_string()));
+    if rule.name.trim().is_empty() {
+        return invalid("name must not be empty");
+    }
+    if rule.actions.is_empty() {
+        return invalid("a rule needs at least one action");
+    }
+    if rule.cooldown_secs > MAX_COOLDOWN_SECS {
+        return invalid("cooldown must be at most a day");
+    }
+    match &rule.trigger {
+        Trigger::BoardStatus { status, .. } if status.trim().is_empty() => {
+            return invalid("board status must not be empty");
+        }
+        Trigger::CommandError {
+            pattern: Some(pattern),
+        } => {
+            if let Err(e) = Regex::new(pattern) {
+                return Err(AutomationError::Invalid(format!("invalid pattern: {}", e)));
+            }
+        }
+        _ => {}
+    }
+    for action in &rule.actions {
+        let empty = match action {
+            Action::Send { text } => text.is_empty(),
+            Action::RunCommand {
+                store_as: Some(name),
+                ..
+            }
+            | Action::SetVariable { name, .. }
+                if !vars::is_valid_name(name) =>
+            {
+                return Err(AutomationError::Invalid(format!(
+                    "invalid variable name `{}`",
+                    name
+                )));
+            }
+            Action::RunCommand { command, .. } => command.trim().is_empty(),
+            Action::SetVariable { .. } => false,
+            Action::StartLogging { path, .. } => path.trim().is_empty(),
+            Action::Notify { title, .. } => title.trim().is_empty(),
+        };
+        if empty {
+            return invalid("action text must not be empty");
+        }
+    }
+    Ok(())
+}
+
+/// Placeholder values of an event that fires `trigger`, plus the key the
+/// cooldown is tracked under
+fn matches(trigger: &Trigger, event: &VrpEvent) -> Option<(String, HashMap<&'static str, String>)> {
+    let mut vars = HashMap::new();
+    let key = match (trigger, event) {
+        (Trigger::BoardStatus { status, board_type }, VrpEvent::BoardInfo(board)) => {
+            if !board.status.eq_ignore_ascii_case(status.trim()) {
+                return None;
+            }
+            if let Some(board_type) = board_type {
+                if !board.board_type.eq_ignore_ascii_case(board_type.trim()) {
+                    return None;
+                }
+            }
+            vars.insert("slot", board.slot_id.clone());
+            vars.insert("sub_slot", board.sub_slot.clone());
+            vars.insert("board_type", board.board_type.clone());
+            vars.insert("status", board.status.clone());
+            format!("{}/{}", board.slot_id, board.sub_slot)
+        }
+        (Trigger::CommandError { pattern }, VrpEvent::CommandError { message }) => {
+            if let Some(pattern) = pattern {
+                // Validated on save
+                let re = Regex::new(pattern).ok()?;
+                if !re.is_match(message) {
+                    return None;
+                }
+            }
+            vars.insert("message", message.clone());
+            message.clone()
+        }
+        (Trigger::ViewChange { view }, VrpEvent::ViewChange { view: current, .. }) => {
+            if view.is_some_and(|view| view != *current) {
+                return None;
+            }
+            let name = serde_json::to_value(current)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_default();
+            vars.insert("view", name.clone());
+            name
+        }
+        _ => return None,
+    };
+    Some((key, vars))
+}
+
+/// Replace `{name}` placeholders; unknown names are left as they are
+/// Fill `{name}` from the event's `vars`, then from the session variables
+fn expand(
+    template: &str,
+    vars: &HashMap<&'static str, String>,
+    session: &BTreeMap<String, String>,
+) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        match tail.find('}').and_then(|end| {
+            let name = &tail[1..end];
+            vars.get(name)
+                .or_else(|| session.get(name))
+                .map(|v| (end, v))
+        }) {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &tail[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Evaluate rules against the manager's event bus until the app exits.
+/// Returns a feed of the `automation` events for backend consumers
+pub fn spawn_engine(
+    manager: Arc<SessionManager>,
+    store: Arc<Mutex<AutomationStore>>,
+    alerts: Arc<AlertEngine>,
+) -> broadcast::Sender<AutomationEvent> {
+    let mut events = manager.events().subscribe();
+    let logging: Arc<DashSet<(String, PathBuf)>> = Arc::new(DashSet::new());
+    let (feed, _) = broadcast::channel(FEED_CAPACITY);
+    let sender = feed.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut hostnames: HashMap<String, String> = HashMap::new();
+        let mut fired: HashMap<(String, String, String), Instant> = HashMap::new();
+        loop {
+            let SessionEvent { session_id, event } = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(
+                        missed = missed,
+                        "Automation engine fell behind, events dropped"
+                    );
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if let VrpEvent::ViewChange { hostname, .. } = &event {
+                hostnames.insert(session_id.clone(), hostname.clone());
+            }
+            hostnames.retain(|id, _| manager.get(id).is_some());
+
+            let rules = store.lock().await.list();
+            for rule in rules.into_iter().filter(|r| r.enabled) {
+                let Some((key, mut vars)) = matches(&rule.trigger, &event) else {
+                    continue;
+                };
+                let cooldown = Duration::from_secs(rule.cooldown_secs);
+                let fired_key = (rule.id.clone(), session_id.clone(), key);
+                if fired
+                    .get(&fired_key)
+                    .is_some_and(|at| at.elapsed() < cooldown)
+                {
+                    debug!(rule_id = %rule.id, session_id = %session_id, "Automation rule cooling down");
+                    continue;
+                }
+                if fired.len() >= MAX_COOLDOWN_ENTRIES {
+                    fired.retain(|_, at| at.elapsed() < Duration::from_secs(MAX_COOLDOWN_SECS));
+                }
+                fired.insert(fired_key, Instant::now());
+
+                vars.insert("session", session_id.clone());
+                vars.insert(
+                    "hostname",
+                    hostnames.get(&session_id).cloned().unwrap_or_default(),
+                );
+                let manager = Arc::clone(&manager);
+                let logging = Arc::clone(&logging);
+                let alerts = Arc::clone(&alerts);
+                let feed = sender.clone();
+                let session_id = session_id.clone();
+                tokio::spawn(async move {
+                    run_actions(
+                        &manager,
+                        &logging,
+                        &alerts,
+                        &feed,
+                        &rule,
+                        &session_id,
+                        &vars,
+                    )
+                    .await;
+                });
+            }
+        }
+    });
+    feed
+}
+
+async fn run_actions(
+    manager: &SessionManager,
+    logging: &Arc<DashSet<(String, PathBuf)>>,
+    alerts: &AlertEngine,
+    feed: &broadcast::Sender<AutomationEvent>,
+    rule: &AutomationRule,
+    session_id: &str,
+    vars: &HashMap<&'static str, String>,
+) {
+    info!(rule_id = %rule.id, name = %rule.name, session_id = %session_id, "Automation rule fired");
+    let emit = |event: AutomationEvent| {
+        if let Err(e) = manager.app_handle().emit("automation", &event) {
+            warn!(error = %e, "Failed to emit automation event");
+        }
+        let _ = feed.send(event);
+    };
+    emit(AutomationEvent::Fired {
+        rule_id: rule.id.clone(),
+        rule_name: rule.name.clone(),
+        session_id: session_id.to_string(),
+    });
+
+    let scope = Scope::Session(session_id.to_string());
+    for action in &rule.actions {
+        // Earlier actions may have set variables
+        let session = manager.variables().snapshot(&scope);
+        let expand = |template: &str| expand(template, vars, &session);
+        let result = match action {
+            Action::Send { text } => manager
+                .send_data(session_id, expand(text).into_bytes())
+                .await
+                .map_err(AutomationError::from),
+            Action::RunCommand {
+                command,
+                timeout_ms,
+                store_as,
+            } => {
+                let timeout = timeout_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_COMMAND_TIMEOUT);
+                command::run_command(manager, session_id, &expand(command), timeout)
+                    .await
+                    .map(|result| {
+                        if let Some(name) = store_as {
+                            // Validated on save
+                            let _ = manager.variables().set(&scope, name, result.output.clone());
+                        }
+                        emit(AutomationEvent::CommandResult {
+                            rule_id: rule.id.clone(),
+                            session_id: session_id.to_string(),
+                            result,
+                        })
+                    })
+                    .map_err(AutomationError::from)
+            }
+            Action::StartLogging { path, timestamps } => start_logging(
+                manager,
+                logging,
+                session_id,
+                PathBuf::from(expand(path)),
+                *timestamps,
+            ),
+            Action::SetVariable { name, value } => manager
+                .variables()
+                .set(&scope, name, expand(value))
+                .map_err(|e| AutomationError::Invalid(e.to_string())),
+            Action::Notify {
+                title,
+                body,
+                severity,
+            } => {
+                let (title, body) = (expand(title), expand(body));
+                alerts.raise(
+                    &rule.id,
+                    &rule.name,
+                    Duration::from_secs(rule.cooldown_secs),
+                    AlertInput {
+                        source: AlertSource::Trigger,
+                        severity: severity.unwrap_or(AlertSeverity::Info),
+                        title: title.clone(),
+                        message: body.clone(),
+                        host: manager.get(session_id).map(|h| h.config.host.clone()),
+                        session_id: Some(session_id.to_string()),
+                    },
+                );
+                emit(AutomationEvent::Notify {
+                    rule_id: rule.id.clone(),
+                    session_id: session_id.to_string(),
+                    title,
+                    body,
+                });
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            warn!(rule_id = %rule.id, session_id = %session_id, error = %e, "Automation action failed");
+            emit(AutomationEvent::Failed {
+                rule_id: rule.id.clone(),
+                session_id: session_id.to_string(),
+                error: e.to_string(),
+            });
+            return;
+        }
+    }
+}
+
+/// Write the session's output to `path` until the session goes away; a
+/// session already logging to `path` is left alone
+fn start_logging(
+    manager: &SessionManager,
+    logging: &Arc<DashSet<(String, PathBuf)>>,
+    session_id: &str,
+    path: PathBuf,
+    timestamps: bool,
+) -> Result<(), AutomationError> {
+    let key = (session_id.to_string(), path);
+    if logging.contains(&key) {
+        return Ok(());
+    }
+    let mut output = manager.subscribe_output(session_id)?;
+    let mut log = SessionLog::open(&key.1, timestamps)?;
+    info!(session_id = %session_id, path = %key.1.display(), "Automation started session log");
+    logging.insert(key.clone());
+    let logging = Arc::clone(logging);
+    tokio::spawn(async move {
+        loop {
+            match output.recv().await {
+                Ok(data) => {
+                    if let Err(e) = log.write(&data) {
+                        warn!(path = %log.path().display(), error = %e, "Failed to write session log");
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(path = %log.path().display(), missed = missed, "Session log fell behind, output dropped");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+        logging.remove(&key);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vrp::BoardInfo;
+
+    fn board(slot: &str, board_type: &str, status: &str) -> VrpEvent {
+        VrpEvent::BoardInfo(BoardInfo {
+            slot_id: slot.to_string(),
+            sub_slot: "-".to_string(),
+            board_type: board_type.to_string(),
+            status: status.to_string(),
+            ip: None,
+        })
+    }
+
+    #[test]
+    fn test_matches_and_expand() {
+        let absent = Trigger::BoardStatus {
+            status: "absent".to_string(),
+            board_type: None,
+        };
+        let (key, vars) = matches(&absent, &board("3", "CR5D00E4XF90", "Absent")).unwrap();
+        assert_eq!(key, "3/-");
+        assert_eq!(
+            expand(
+                "display device slot {slot} {unknown} {",
+                &vars,
+                &BTreeMap::new()
+            ),
+            "display device slot 3 {unknown} {"
+        );
+        assert!(matches(&absent, &board("3", "CR5D00E4XF90", "Normal")).is_none());
+        let typed = Trigger::BoardStatus {
+            status: "Absent".to_string(),
+            board_type: Some("MPU".to_string()),
+        };
+        assert!(matches(&typed, &board("3", "CR5D00E4XF90", "Absent")).is_none());
+
+        let error = VrpEvent::CommandError {
+            message: "Unrecognized command found at '^' position.".to_string(),
+        };
+        let any_error = Trigger::CommandError { pattern: None };
+        let (_, vars) = matches(&any_error, &error).unwrap();
+        let session = BTreeMap::from([
+            ("serial".to_string(), "2102351931P0".to_string()),
+            ("message".to_string(), "shadowed by the event".to_string()),
+        ]);
+        assert_eq!(
+            expand("{message} {serial}", &vars, &session),
+            "Unrecognized command found at '^' position. 2102351931P0"
+        );
+        let wrong = Trigger::CommandError {
+            pattern: Some("^Wrong parameter".to_string()),
+        };
+        assert!(matches(&wrong, &error).is_none());
+        assert!(matches(&any_error, &board("3", "x", "Absent")).is_none());
+
+        let system = Trigger::ViewChange {
+            view: Some(VrpView::System),
+        };
+        let view = |view| VrpEvent::ViewChange {
+            view,
+            hostname: "core-1".to_string(),
+        };
+        assert_eq!(
+            matches(&system, &view(VrpView::System)).unwrap().0,
+            "system"
+        );
+        assert!(matches(&system, &view(VrpView::User)).is_none());
+    }
+
+    #[test]
+    fn test_store() {
+        let dir =
+            std::env::temp_dir().join(format!("bspt-automation-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("automation.json");
+        let mut store = AutomationStore::load(path.clone());
+
+        let rule: AutomationRule = serde_json::from_str(
+            r#"{
+                "name": "absent board",
+                "trigger": {"event": "board_status", "status": "Absent"},
+                "actions": [
+                    {"action": "run_command", "command": "display device slot {slot}"},
+                    {"action": "start_logging", "path": "/tmp/{hostname}.log"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(rule.enabled);
+        assert_eq!(rule.cooldown_secs, DEFAULT_COOLDOWN_SECS);
+        let saved = store.save(rule).unwrap();
+        assert!(!saved.id.is_empty());
+
+        let mut renamed = saved.clone();
+        renamed.name = "renamed".to_string();
+        store.save(renamed).unwrap();
+
+        let mut invalid = saved.clone();
+        invalid.actions.clear();
+        assert!(matches!(
+            store.save(invalid),
+            Err(AutomationError::Invalid(_))
+        ));
+        let mut invalid = saved.clone();
+        invalid.trigger = Trigger::CommandError {
+            pattern: Some("(".to_string()),
+        };
+        assert!(store.save(invalid).is_err());
+
+        let mut store = AutomationStore::load(path);
+        assert_eq!(store.list().len(), 1);
+        assert_eq!(store.list()[0].name, "renamed");
+        store.delete(&saved.id).unwrap();
+        assert!(matches!(
+            store.delete(&saved.id),
+            Err(AutomationError::NotFound(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
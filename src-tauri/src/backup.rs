@@ -0,0 +1,469 @@
+//! Versioned device configuration backups (`backups/` in the app data dir).
+//!
+//! Each device has a directory holding `index.json` (snapshots, oldest
+//! first) and one `{sha256}.cfg` file per distinct configuration, so a
+//! capture identical to the latest snapshot is skipped and older versions
+//! share storage.
+
+use crate::settings::{BackupSettings, SettingsStore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Context lines around each change in a diff
+const DIFF_CONTEXT: usize = 3;
+
+/// Largest changed region (old lines × new lines) diffed line by line;
+/// bigger ones are shown as replaced wholesale
+const DIFF_MAX_CELLS: usize = 4_000_000;
+
+/// How often the scheduler checks whether a backup is due
+const SCHEDULER_TICK: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error("Snapshot not found: {0}")]
+    NotFound(String),
+    #[error("Invalid device id: {0}")]
+    InvalidDevice(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<BackupError> for String {
+    fn from(err: BackupError) -> String {
+        err.to_string()
+    }
+}
+
+/// One stored version of a device configuration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Snapshot {
+    /// Unique per device; the capture time in milliseconds
+    pub id: String,
+    /// Unix seconds
+    pub taken_at: i64,
+    /// SHA-256 of the stored text, hex
+    pub hash: String,
+    pub lines: usize,
+    pub bytes: usize,
+}
+
+/// Outcome of backing up one device
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupResult {
+    pub device_id: String,
+    pub name: String,
+    /// The new snapshot, or the latest one if nothing changed
+    pub snapshot: Option<Snapshot>,
+    pub changed: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOp {
+    Same,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+/// Changed lines with surrounding context; line numbers are 1-based
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub new_start: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+pub struct BackupStore {
+    dir: PathBuf,
+}
+
+impl BackupStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Store `config` unless it matches the latest snapshot. Returns the
+    /// snapshot and whether it is new.
+    pub fn save(
+        &self,
+        device_id: &str,
+        config: &str,
+        keep: usize,
+    ) -> Result<(Snapshot, bool), BackupError> {
+        let config = normalize(config);
+        let hash = format!("{:x}", Sha256::digest(config.as_bytes()));
+        let mut index = self.history(device_id)?;
+        if let Some(latest) = index.last() {
+            if latest.hash == hash {
+                return Ok((latest.clone(), false));
+            }
+        }
+
+        let now = chrono::Utc::now();
+        let mut id = now.timestamp_millis();
+        // Two captures within a millisecond still get distinct ids
+        if let Some(last) = index.last().and_then(|s| s.id.parse::<i64>().ok()) {
+            id = id.max(last + 1);
+        }
+        let snapshot = Snapshot {
+            id: id.to_string(),
+            taken_at: now.timestamp(),
+            hash,
+            lines: config.lines().count(),
+            bytes: config.len(),
+        };
+
+        let dir = self.device_dir(device_id)?;
+        std::fs::create_dir_all(&dir)?;
+        let content = dir.join(format!("{}.cfg", snapshot.hash));
+        if !content.exists() {
+            write_atomic(&content, config.as_bytes())?;
+        }
+        index.push(snapshot.clone());
+
+        let pruned: Vec<Snapshot> = if index.len() > keep.max(1) {
+            index.drain(..index.len() - keep.max(1)).collect()
+        } else {
+            Vec::new()
+        };
+        write_atomic(&dir.join("index.json"), &serde_json::to_vec_pretty(&index)?)?;
+        for old in pruned {
+            if !index.iter().any(|s| s.hash == old.hash) {
+                let _ = std::fs::remove_file(dir.join(format!("{}.cfg", old.hash)));
+            }
+        }
+
+        info!(device_id, snapshot = %snapshot.id, lines = snapshot.lines, "Saved config backup");
+        Ok((snapshot, true))
+    }
+
+    /// Snapshots of a device, oldest first
+    pub fn history(&self, device_id: &str) -> Result<Vec<Snapshot>, BackupError> {
+        let path = self.device_dir(device_id)?.join("index.json");
+        match std::fs::read(&path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn read(&self, device_id: &str, snapshot_id: &str) -> Result<String, BackupError> {
+        let snapshot = self
+            .history(device_id)?
+            .into_iter()
+            .find(|s| s.id == snapshot_id)
+            .ok_or_else(|| BackupError::NotFound(snapshot_id.to_string()))?;
+        let path = self
+            .device_dir(device_id)?
+            .join(format!("{}.cfg", snapshot.hash));
+        Ok(std::fs::read_to_string(path)?)
+    }
+
+    /// Changes from snapshot `from` to snapshot `to`
+    pub fn diff(
+        &self,
+        device_id: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<DiffHunk>, BackupError> {
+        let old = self.read(device_id, from)?;
+        let new = self.read(device_id, to)?;
+        Ok(diff_lines(&old, &new))
+    }
+
+    /// Device ids are profile ids or host names; anything that could leave
+    /// the backup directory is refused
+    fn device_dir(&self, device_id: &str) -> Result<PathBuf, BackupError> {
+        let valid = !device_id.is_empty()
+            && device_id != "."
+            && device_id != ".."
+            && !device_id.contains(['/', '\\', ':']);
+        if !valid {
+            return Err(BackupError::InvalidDevice(device_id.to_string()));
+        }
+        Ok(self.dir.join(device_id))
+    }
+}
+
+/// Trailing whitespace and blank lines at the ends don't make a new version
+fn normalize(config: &str) -> String {
+    let lines: Vec<&str> = config.lines().map(str::trim_end).collect();
+    let start = lines
+        .iter()
+        .position(|l| !l.is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(start, |i| i + 1);
+    let mut text = lines[start..end].join("\n");
+    text.push('\n');
+    text
+}
+
+fn write_atomic(path: &std::path::Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Line diff grouped into hunks with `DIFF_CONTEXT` lines of context
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffHunk> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff_ops(&old, &new);
+
+    let mut hunks: Vec<DiffHunk> = Vec::new();
+    let (mut old_line, mut new_line) = (1, 1);
+    let mut pending: Option<(DiffHunk, usize)> = None; // hunk, trailing context
+    for (index, (op, text)) in ops.iter().enumerate() {
+        let changed_ahead = ops[index..]
+            .iter()
+            .take(DIFF_CONTEXT + 1)
+            .any(|(op, _)| *op != DiffOp::Same);
+        // Hunks open DIFF_CONTEXT lines before a change
+        if pending.is_none() && changed_ahead {
+            let hunk = DiffHunk {
+                old_start: old_line,
+                new_start: new_line,
+                lines: Vec::new(),
+            };
+            pending = Some((hunk, 0));
+        }
+        if let Some((hunk, trailing)) = &mut pending {
+            if *op == DiffOp::Same && !changed_ahead && *trailing >= DIFF_CONTEXT {
+                hunks.push(pending.take().expect("open hunk").0);
+            } else {
+                hunk.lines.push(DiffLine {
+                    op: *op,
+                    text: text.to_string(),
+                });
+                *trailing = if *op == DiffOp::Same {
+                    *trailing + 1
+                } else {
+                    0
+                };
+            }
+        }
+        match op {
+            DiffOp::Same => {
+                old_line += 1;
+                new_line += 1;
+            }
+            DiffOp::Removed => old_line += 1,
+            DiffOp::Added => new_line += 1,
+        }
+    }
+    if let Some((hunk, _)) = pending {
+        hunks.push(hunk);
+    }
+    hunks
+}
+
+/// Longest-common-subsequence edit script after trimming the common prefix
+/// and suffix
+fn diff_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(DiffOp, &'a str)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut ops: Vec<(DiffOp, &str)> = old[..prefix].iter().map(|l| (DiffOp::Same, *l)).collect();
+    if a.len() * b.len() > DIFF_MAX_CELLS {
+        ops.extend(a.iter().map(|l| (DiffOp::Removed, *l)));
+        ops.extend(b.iter().map(|l| (DiffOp::Added, *l)));
+    } else {
+        // lcs[i][j]: common lines of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                ops.push((DiffOp::Same, a[i]));
+                i += 1;
+                j += 1;
+            } else if i < a.len()
+                && (j == b.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+            {
+                // Removals before additions on a tie
+                ops.push((DiffOp::Removed, a[i]));
+                i += 1;
+            } else {
+                ops.push((DiffOp::Added, b[j]));
+                j += 1;
+            }
+        }
+    }
+    ops.extend(old[old.len() - suffix..].iter().map(|l| (DiffOp::Same, *l)));
+    ops
+}
+
+/// Call `backup` whenever `BackupSettings.interval_mins` has passed since
+/// the last scheduled run; changes to the settings apply on the next check
+pub fn spawn_scheduler<F, Fut>(settings: Arc<Mutex<SettingsStore>>, backup: F)
+where
+    F: Fn(BackupSettings) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    tauri::async_runtime::spawn(async move {
+        let mut last_run = Instant::now();
+        let mut interval = tokio::time::interval(SCHEDULER_TICK);
+        loop {
+            interval.tick().await;
+            let config = settings.lock().await.get().backup;
+            let due = Duration::from_secs(config.interval_mins * 60);
+            if config.interval_mins == 0
+                || config.profile_ids.is_empty()
+                || last_run.elapsed() < due
+            {
+                continue;
+            }
+            last_run = Instant::now();
+            info!(
+                devices = config.profile_ids.len(),
+                "Running scheduled config backup"
+            );
+            backup(config).await;
+        }
+    });
+}
+
+/// Log the failures of a scheduled run, which has no caller to report to
+pub fn log_failures(results: &[BackupResult]) {
+    for result in results.iter().filter(|r| r.error.is_some()) {
+        warn!(
+            device = %result.name,
+            error = result.error.as_deref().unwrap_or_default(),
+            "Scheduled config backup failed"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(hunk: &DiffHunk) -> Vec<String> {
+        hunk.lines
+            .iter()
+            .map(|l| {
+                let sign = match l.op {
+                    DiffOp::Same => ' ',
+                    DiffOp::Added => '+',
+                    DiffOp::Removed => '-',
+                };
+                format!("{}{}", sign, l.text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_save_dedup_prune() {
+        let dir = std::env::temp_dir().join(format!("bspt-backup-test-{}", uuid::Uuid::new_v4()));
+        let store = BackupStore::new(dir.clone());
+
+        let (first, changed) = store.save("core-1", "sysname core-1\n#\n", 2).unwrap();
+        assert!(changed);
+        // Trailing whitespace only: same version
+        let (same, changed) = store
+            .save("core-1", "\nsysname core-1  \n#\n\n", 2)
+            .unwrap();
+        assert!(!changed);
+        assert_eq!(same, first);
+
+        let (second, _) = store.save("core-1", "sysname core-2\n#\n", 2).unwrap();
+        let (third, _) = store.save("core-1", "sysname core-3\n#\n", 2).unwrap();
+        let history = store.history("core-1").unwrap();
+        assert_eq!(history, [second.clone(), third.clone()]);
+        assert!(!dir
+            .join("core-1")
+            .join(format!("{}.cfg", first.hash))
+            .exists());
+        assert!(matches!(
+            store.read("core-1", &first.id),
+            Err(BackupError::NotFound(_))
+        ));
+
+        let hunks = store.diff("core-1", &second.id, &third.id).unwrap();
+        assert_eq!(
+            lines(&hunks[0]),
+            ["-sysname core-2", "+sysname core-3", " #"]
+        );
+
+        assert!(store.history("other").unwrap().is_empty());
+        assert!(matches!(
+            store.history("../etc"),
+            Err(BackupError::InvalidDevice(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_diff_hunks() {
+        let old: Vec<String> = (1..=20).map(|i| format!("line {}", i)).collect();
+        let mut new = old.clone();
+        new[1] = "line two".to_string();
+        new.remove(14);
+        new.push("line 21".to_string());
+        let hunks = diff_lines(&old.join("\n"), &new.join("\n"));
+
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].old_start, hunks[0].new_start), (1, 1));
+        assert_eq!(
+            lines(&hunks[0]),
+            [
+                " line 1",
+                "-line 2",
+                "+line two",
+                " line 3",
+                " line 4",
+                " line 5"
+            ]
+        );
+        assert_eq!((hunks[1].old_start, hunks[1].new_start), (12, 12));
+        assert_eq!(
+            lines(&hunks[1]),
+            [
+                " line 12", " line 13", " line 14", "-line 15", " line 16", " line 17", " line 18",
+                " line 19", " line 20", "+line 21"
+            ]
+        );
+        assert!(diff_lines("a\nb", "a\nb").is_empty());
+    }
+}
@@ -0,0 +1,674 @@
+//! Batch jobs: run one command list on many devices.
+//!
+//! Each target gets its own session, opened with at most `concurrency`
+//! connections at a time. Commands run in order through
+//! `command::run_command`; progress is emitted as `batch:{job_id}` events
+//! and the last event carries the whole `BatchReport`.
+
+use crate::command::{self, CommandOutput, OutputWatcher, DEFAULT_COMMAND_TIMEOUT};
+use crate::device_archive::{self, DeviceArchive};
+use crate::dryrun::DryRunReport;
+use crate::jobs::JobControl;
+use crate::precheck;
+use crate::profile::Profile;
+use crate::session::{
+    run_session, DeviceDialect, Protocol, SessionConfig, SessionError, SessionManager,
+};
+use crate::settings::Settings;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::sync::{oneshot, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Connections opened at once when a job doesn't say
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+pub const MAX_CONCURRENCY: usize = 64;
+
+/// Time from starting a session to its first CLI prompt, login included
+pub const DEFAULT_LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("CSV line {line}: {message}")]
+    Csv { line: usize, message: String },
+    #[error("Invalid batch job: {0}")]
+    Invalid(String),
+}
+
+impl From<BatchError> for String {
+    fn from(err: BatchError) -> String {
+        err.to_string()
+    }
+}
+
+/// One device of a job
+#[derive(Debug, Clone)]
+pub struct BatchTarget {
+    /// Profile id, or the host for CSV rows
+    pub id: String,
+    pub name: String,
+    pub config: SessionConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct BatchOptions {
+    /// Default `DEFAULT_CONCURRENCY`
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// Per command; default `command::DEFAULT_COMMAND_TIMEOUT`
+    #[serde(default)]
+    pub command_timeout_ms: Option<u64>,
+    /// Keep running a device's commands after one fails
+    #[serde(default)]
+    pub continue_on_error: bool,
+    /// Probe each device's port first and fail it at once when closed,
+    /// instead of after the connect timeout. Devices behind jump hosts are
+    /// not probed.
+    #[serde(default)]
+    pub skip_unreachable: bool,
+}
+
+/// Outcome of one command on one device
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum CommandResult {
+    Ok(CommandOutput),
+    Failed {
+        command: String,
+        error: String,
+    },
+    /// Not run after an earlier failure
+    Skipped {
+        command: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceResult {
+    /// `BatchTarget::id`
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    /// Connected and every command completed
+    pub success: bool,
+    /// Connect or login failure
+    pub error: Option<String>,
+    pub commands: Vec<CommandResult>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReport {
+    pub job_id: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// In target order
+    pub devices: Vec<DeviceResult>,
+    pub duration_ms: u64,
+}
+
+/// Body of `batch:{job_id}` events
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchEvent {
+    DeviceStarted {
+        name: String,
+    },
+    DeviceFinished {
+        result: DeviceResult,
+        completed: usize,
+        total: usize,
+    },
+    Finished {
+        report: BatchReport,
+    },
+}
+
+/// A command list and the devices to run it on
+pub struct BatchJob {
+    pub targets: Vec<BatchTarget>,
+    pub commands: Vec<String>,
+    pub options: BatchOptions,
+}
+
+impl BatchJob {
+    pub fn validate(&self) -> Result<(), BatchError> {
+        if self.targets.is_empty() {
+            return Err(BatchError::Invalid("no targets".to_string()));
+        }
+        if self.commands.iter().all(|c| c.trim().is_empty()) {
+            return Err(BatchError::Invalid("no commands".to_string()));
+        }
+        if let Some(concurrency) = self.options.concurrency {
+            if concurrency == 0 || concurrency > MAX_CONCURRENCY {
+                return Err(BatchError::Invalid(format!(
+                    "concurrency must be 1 to {}",
+                    MAX_CONCURRENCY
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// What `run` would send to each target, without connecting. Targets
+    /// listed twice or lacking credentials are reported as issues.
+    pub fn dry_run(&self) -> Result<DryRunReport, BatchError> {
+        self.validate()?;
+        let timeout = self
+            .options
+            .command_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_COMMAND_TIMEOUT);
+        let mut report = DryRunReport::default();
+        let mut seen = HashSet::new();
+        for target in &self.targets {
+            let config = &target.config;
+            let name = Some(target.name.as_str());
+            if !seen.insert((config.host.as_str(), config.port)) {
+                report.issue(
+                    name,
+                    None,
+                    format!("{}:{} is listed more than once", config.host, config.port),
+                );
+            }
+            if config.protocol == Protocol::Ssh && config.username.is_empty() {
+                report.issue(name, None, "no username for SSH".to_string());
+            }
+            if config.password.is_empty() {
+                report.issue(name, None, "no password; sign-in may fail".to_string());
+            }
+            let commands = self.commands.iter().filter(|c| !c.trim().is_empty());
+            for (index, command) in commands.enumerate() {
+                report.send(
+                    &target.name,
+                    &config.host,
+                    index + 1,
+                    format!("{}\r", command),
+                );
+                report.wait(format!("prompt ({}s)", timeout.as_secs()));
+            }
+        }
+        Ok(report)
+    }
+
+    /// Start the job in the background; returns its id
+    pub fn spawn(
+        self,
+        manager: Arc<SessionManager>,
+        settings: Settings,
+        job: JobControl,
+    ) -> String {
+        let job_id = job.id().to_string();
+        tokio::spawn(async move {
+            let report = self.run(&job, manager.clone(), &settings).await;
+            info!(
+                job_id = %report.job_id,
+                succeeded = report.succeeded,
+                failed = report.failed,
+                "Batch job finished"
+            );
+            if let Some(archive) = DeviceArchive::from_settings(&settings.archive) {
+                device_archive::archive_transcripts(&archive, &report);
+            }
+            let summary = format!("{} succeeded, {} failed", report.succeeded, report.failed);
+            emit(&manager, job.id(), BatchEvent::Finished { report });
+            job.finish(Ok(summary));
+        });
+        job_id
+    }
+
+    /// Run the job to completion, emitting its progress events. Pausing the
+    /// job holds devices that have not started yet; cancelling fails them.
+    pub async fn run(
+        self,
+        job: &JobControl,
+        manager: Arc<SessionManager>,
+        settings: &Settings,
+    ) -> BatchReport {
+        let job_id = job.id();
+        let started = Instant::now();
+        let total = self.targets.len();
+        let concurrency = self.options.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+        info!(job_id = %job_id, targets = total, concurrency, "Starting batch job");
+        job.progress(0, total as u64, None);
+
+        let permits = Arc::new(Semaphore::new(concurrency));
+        let commands: Arc<[String]> = self
+            .commands
+            .into_iter()
+            .filter(|c| !c.trim().is_empty())
+            .collect();
+        let mut tasks = JoinSet::new();
+        for (index, mut target) in self.targets.into_iter().enumerate() {
+            settings.apply(&mut target.config, job_id);
+            let permits = Arc::clone(&permits);
+            let manager = Arc::clone(&manager);
+            let commands = Arc::clone(&commands);
+            let options = self.options;
+            let job_id = job_id.to_string();
+            let signal = job.signal();
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await.expect("semaphore not closed");
+                if let Err(e) = signal.checkpoint().await {
+                    let result = DeviceResult {
+                        id: target.id,
+                        name: target.name,
+                        host: target.config.host,
+                        success: false,
+                        error: Some(e.to_string()),
+                        commands: Vec::new(),
+                        duration_ms: 0,
+                    };
+                    return (index, result);
+                }
+                emit(
+                    &manager,
+                    &job_id,
+                    BatchEvent::DeviceStarted {
+                        name: target.name.clone(),
+                    },
+                );
+                (
+                    index,
+                    run_device(&manager, target, &commands, options).await,
+                )
+            });
+        }
+
+        let mut devices: Vec<Option<DeviceResult>> = vec![None; total];
+        let mut completed = 0;
+        while let Some(joined) = tasks.join_next().await {
+            let Ok((index, result)) = joined else {
+                warn!(job_id = %job_id, "Batch device task panicked");
+                continue;
+            };
+            completed += 1;
+            job.progress(completed as u64, total as u64, Some(result.name.clone()));
+            emit(
+                &manager,
+                job_id,
+                BatchEvent::DeviceFinished {
+                    result: result.clone(),
+                    completed,
+                    total,
+                },
+            );
+            devices[index] = Some(result);
+        }
+
+        let devices: Vec<DeviceResult> = devices.into_iter().flatten().collect();
+        let succeeded = devices.iter().filter(|d| d.success).count();
+        BatchReport {
+            job_id: job_id.to_string(),
+            succeeded,
+            failed: total - succeeded,
+            devices,
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+/// Run one command on several open sessions at once; results are keyed by
+/// session id
+pub async fn run_command_multi(
+    manager: &Arc<SessionManager>,
+    session_ids: &[String],
+    command: &str,
+    timeout: Duration,
+) -> HashMap<String, CommandResult> {
+    let mut tasks = JoinSet::new();
+    for session_id in session_ids {
+        let manager = Arc::clone(manager);
+        let session_id = session_id.clone();
+        let command = command.to_string();
+        tasks.spawn(async move {
+            let result = match command::run_command(&manager, &session_id, &command, timeout).await
+            {
+                Ok(output) => CommandResult::Ok(output),
+                Err(e) => CommandResult::Failed {
+                    command,
+                    error: e.to_string(),
+                },
+            };
+            (session_id, result)
+        });
+    }
+
+    let mut results = HashMap::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((session_id, result)) => {
+                results.insert(session_id, result);
+            }
+            Err(e) => warn!(error = %e, "Multi-session command task panicked"),
+        }
+    }
+    results
+}
+
+/// Connect, log in, run the commands and disconnect
+async fn run_device(
+    manager: &Arc<SessionManager>,
+    target: BatchTarget,
+    commands: &[String],
+    options: BatchOptions,
+) -> DeviceResult {
+    let started = Instant::now();
+    let mut result = DeviceResult {
+        id: target.id.clone(),
+        name: target.name.clone(),
+        host: target.config.host.clone(),
+        success: false,
+        error: None,
+        commands: Vec::new(),
+        duration_ms: 0,
+    };
+
+    let timeout = options
+        .command_timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_COMMAND_TIMEOUT);
+    if options.skip_unreachable && target.config.jump_hosts.is_empty() {
+        let probe = precheck::probe_port(
+            &target.config.host,
+            target.config.port,
+            precheck::DEFAULT_PROBE_TIMEOUT,
+        )
+        .await;
+        if let Some(error) = probe.error {
+            info!(host = %result.host, error = %error, "Skipping unreachable batch device");
+            result.error = Some(format!("unreachable: {}", error));
+            result.duration_ms = started.elapsed().as_millis() as u64;
+            return result;
+        }
+    }
+    match DeviceSession::open(manager, &target.config).await {
+        Ok(session) => {
+            let mut failed = false;
+            for command in commands {
+                if failed && !options.continue_on_error {
+                    result.commands.push(CommandResult::Skipped {
+                        command: command.clone(),
+                    });
+                    continue;
+                }
+                match command::run_command(manager, &session.id, command, timeout).await {
+                    Ok(output) => result.commands.push(CommandResult::Ok(output)),
+                    Err(e) => {
+                        failed = true;
+                        result.commands.push(CommandResult::Failed {
+                            command: command.clone(),
+                            error: e.to_string(),
+                        });
+                    }
+                }
+            }
+            result.success = !failed;
+            session.close(manager).await;
+        }
+        Err(e) => {
+            warn!(host = %result.host, error = %e, "Batch device failed to connect");
+            result.error = Some(e.to_string());
+        }
+    }
+
+    result.duration_ms = started.elapsed().as_millis() as u64;
+    result
+}
+
+/// A session opened by a background job rather than a terminal tab
+pub struct DeviceSession {
+    pub id: String,
+    ended: oneshot::Receiver<Result<(), SessionError>>,
+}
+
+impl DeviceSession {
+    /// Start a session and wait until it shows a CLI prompt, logging in if
+    /// the device asks
+    pub async fn open(
+        manager: &Arc<SessionManager>,
+        config: &SessionConfig,
+    ) -> Result<Self, SessionError> {
+        let id = SessionManager::generate_session_id();
+        let (ended_tx, ended) = oneshot::channel();
+        {
+            let manager = Arc::clone(manager);
+            let id = id.clone();
+            let config = config.clone();
+            tokio::spawn(async move {
+                let _ = ended_tx.send(run_session(id, config, manager).await);
+            });
+        }
+
+        let mut session = Self { id, ended };
+        let connected = tokio::select! {
+            connected = sign_in(manager, &session.id, config) => connected,
+            ended = &mut session.ended => Err(match ended {
+                Ok(Err(e)) => e,
+                _ => SessionError::ConnectionFailed("session ended before login".to_string()),
+            }),
+        };
+        match connected {
+            Ok(()) => Ok(session),
+            Err(e) => {
+                session.close(manager).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Disconnect and wait for the session task to finish
+    pub async fn close(self, manager: &SessionManager) {
+        if manager.disconnect(&self.id).await.is_ok() {
+            let _ = self.ended.await;
+        }
+    }
+}
+
+/// Wait for the session task to register the session, then log in
+async fn sign_in(
+    manager: &SessionManager,
+    session_id: &str,
+    config: &SessionConfig,
+) -> Result<(), SessionError> {
+    let deadline = Instant::now() + DEFAULT_LOGIN_TIMEOUT;
+    // Sessions are registered before they connect, so nothing is missed
+    let mut watcher = loop {
+        match OutputWatcher::new(manager, session_id) {
+            Ok(watcher) => break watcher,
+            Err(SessionError::NotFound(_)) if Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    command::login(
+        &mut watcher,
+        manager,
+        session_id,
+        &config.username,
+        &config.password,
+        deadline.saturating_duration_since(Instant::now()),
+    )
+    .await?;
+    Ok(())
+}
+
+fn emit(manager: &SessionManager, job_id: &str, event: BatchEvent) {
+    let name = format!("batch:{}", job_id);
+    if let Err(e) = manager.app_handle().emit(&name, &event) {
+        warn!(job_id = %job_id, error = %e, "Failed to emit batch event");
+    }
+}
+
+/// Targets from CSV text with a header row. `host` is required; `name`,
+/// `port`, `protocol` (`ssh` default, `telnet`), `username`, `password` and
+/// `dialect` are optional. Fields may be double-quoted.
+pub fn parse_targets_csv(text: &str) -> Result<Vec<BatchTarget>, BatchError> {
+    let mut rows = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+    let Some((_, header)) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    let columns: Vec<String> = split_csv_line(header)
+        .map_err(|message| BatchError::Csv { line: 1, message })?
+        .into_iter()
+        .map(|c| c.trim().to_lowercase())
+        .collect();
+    if !columns.iter().any(|c| c == "host") {
+        return Err(BatchError::Csv {
+            line: 1,
+            message: "missing `host` column".to_string(),
+        });
+    }
+
+    let mut targets = Vec::new();
+    for (index, row) in rows {
+        let line = index + 1;
+        let fail = |message: String| BatchError::Csv { line, message };
+        let fields = split_csv_line(row).map_err(fail)?;
+        let field = |name: &str| {
+            columns
+                .iter()
+                .position(|c| c == name)
+                .and_then(|i| fields.get(i))
+                .map(|f| f.trim())
+                .filter(|f| !f.is_empty())
+        };
+
+        let host = field("host").ok_or_else(|| fail("empty host".to_string()))?;
+        let protocol = match field("protocol").map(str::to_lowercase).as_deref() {
+            None | Some("ssh") => Protocol::Ssh,
+            Some("telnet") => Protocol::Telnet,
+            Some(other) => return Err(fail(format!("unknown protocol `{}`", other))),
+        };
+        let port = match field("port") {
+            Some(port) => port
+                .parse()
+                .map_err(|_| fail(format!("invalid port `{}`", port)))?,
+            None if protocol == Protocol::Telnet => 23,
+            None => 22,
+        };
+        let dialect: DeviceDialect = match field("dialect") {
+            Some(dialect) => {
+                serde_json::from_value(serde_json::Value::String(dialect.to_lowercase()))
+                    .map_err(|_| fail(format!("unknown dialect `{}`", dialect)))?
+            }
+            None => DeviceDialect::default(),
+        };
+
+        let name = field("name").unwrap_or(host).to_string();
+        let profile = Profile {
+            name: name.clone(),
+            username: field("username").unwrap_or_default().to_string(),
+            password: field("password").map(str::to_string),
+            dialect,
+            ..Profile::adhoc(host, port, protocol)
+        };
+        targets.push(BatchTarget {
+            id: host.to_string(),
+            name,
+            config: profile.session_config(None),
+        });
+    }
+    Ok(targets)
+}
+
+/// Comma-separated fields; `"..."` quotes a field, `""` inside it is a quote
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_targets_csv() {
+        let csv = "\
+name,host,protocol,port,username,password,dialect
+core-1,10.0.0.1,ssh,,admin,\"pa,ss\"\"word\",vrp
+# spare
+edge,10.0.0.2,telnet,2323,,,comware
+
+,10.0.0.3,,,,,
+";
+        let targets = parse_targets_csv(csv).unwrap();
+        assert_eq!(targets.len(), 3);
+
+        assert_eq!(targets[0].name, "core-1");
+        assert_eq!(targets[0].config.port, 22);
+        assert_eq!(targets[0].config.password, "pa,ss\"word");
+
+        assert_eq!(targets[1].config.protocol, Protocol::Telnet);
+        assert_eq!(targets[1].config.port, 2323);
+        assert_eq!(targets[1].config.dialect, DeviceDialect::Comware);
+
+        // Unnamed rows are named after the host
+        assert_eq!(targets[2].name, "10.0.0.3");
+        assert_eq!(targets[2].config.protocol, Protocol::Ssh);
+
+        for (bad, line) in [
+            ("name\nr1", 1),
+            ("host,protocol\n10.0.0.1,serial", 2),
+            ("host,port\n\n10.0.0.1,99999", 3),
+            ("host\n\"10.0.0.1", 2),
+        ] {
+            match parse_targets_csv(bad) {
+                Err(BatchError::Csv { line: at, .. }) => assert_eq!(at, line, "{}", bad),
+                other => panic!("{}: {:?}", bad, other.map(|t| t.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let targets = parse_targets_csv("host\n10.0.0.1").unwrap();
+        let job = |commands: &[&str], concurrency| BatchJob {
+            targets: targets.clone(),
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+            options: BatchOptions {
+                concurrency,
+                ..Default::default()
+            },
+        };
+        assert!(job(&["display version"], None).validate().is_ok());
+        assert!(job(&[" "], None).validate().is_err());
+        assert!(job(&["display version"], Some(0)).validate().is_err());
+        assert!(job(&["display version"], Some(MAX_CONCURRENCY + 1))
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::charset::Charset;
+use crate::emitter::spawn_observed_emitter;
+use crate::lines::LineAssembler;
+use crate::ringbuffer::{BufferConfig, SessionRingBuffer};
+use crate::scrollback::Scrollback;
+use crate::session::{PayloadEncoding, Protocol, SessionConfig};
+use crate::telnet::TelnetParser;
+use crate::vrp::VrpParser;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{Duration, Instant};
+use tracing::info;
+use uuid::Uuid;
+
+/// Largest accepted `total_bytes`: 1GB
+const MAX_TOTAL_BYTES: usize = 1024 * 1024 * 1024;
+
+/// Where synthetic output enters the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BenchmarkMode {
+    /// Push straight into the ring buffer
+    Pipeline,
+    /// Serve over a loopback TCP socket and run the telnet read path
+    Telnet,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkOptions {
+    pub total_bytes: usize,
+    /// Size of each producer write
+    pub chunk_size: usize,
+    pub payload_encoding: PayloadEncoding,
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        Self {
+            total_bytes: 16 * 1024 * 1024,
+            chunk_size: 4096,
+            payload_encoding: PayloadEncoding::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkReport {
+    pub mode: BenchmarkMode,
+    pub payload_encoding: PayloadEncoding,
+    pub bytes: u64,
+    /// Data events emitted
+    pub events: u64,
+    pub duration_ms: f64,
+    pub throughput_mb_s: f64,
+    /// Producer write to emit, per write
+    pub latency_p50_ms: f64,
+    pub latency_p90_ms: f64,
+    pub latency_p99_ms: f64,
+    pub latency_max_ms: f64,
+}
+
+/// Write offsets and times, matched against emitted bytes for latency
+type Marks = Arc<std::sync::Mutex<VecDeque<(u64, Instant)>>>;
+
+/// Run synthetic output through the buffer → emitter path and measure it.
+///
+/// Events go to an unused `session:benchmark-*` channel, so payload
+/// serialization and IPC dispatch are included. Drains are acknowledged as
+/// soon as a chunk is emitted, i.e. an infinitely fast frontend.
+pub async fn run_benchmark(
+    app_handle: tauri::AppHandle,
+    mode: BenchmarkMode,
+    options: BenchmarkOptions,
+) -> Result<BenchmarkReport, String> {
+    if options.chunk_size == 0 || options.total_bytes == 0 {
+        return Err("Benchmark sizes must be positive".to_string());
+    }
+    if options.total_bytes > MAX_TOTAL_BYTES {
+        return Err(format!("Benchmark is limited to {} bytes", MAX_TOTAL_BYTES));
+    }
+
+    let session_id = format!("benchmark-{}", Uuid::new_v4());
+    let config = SessionConfig {
+        host: "benchmark".to_string(),
+        port: 0,
+        protocol: Protocol::Telnet,
+        username: String::new(),
+        password: String::new(),
+        auth: Default::default(),
+        cols: 80,
+        rows: 24,
+        payload_encoding: options.payload_encoding,
+        buffer: Some(BufferConfig::default()),
+        charset: Charset::Utf8,
+        rate_limit: None,
+        delivery: None,
+        log_file: None,
+        log_timestamps: false,
+        capture_file: None,
+        keepalive: None,
+        activity: None,
+        terminal_type: None,
+        newline: Default::default(),
+        dialect: Default::default(),
+        jump_hosts: Vec::new(),
+        algorithms: None,
+        hardening: None,
+        connect_timeout_ms: None,
+        auth_methods: Vec::new(),
+        subsystem: None,
+        env: HashMap::new(),
+        plugins: Default::default(),
+    };
+
+    let buffer = Arc::new(Mutex::new(SessionRingBuffer::new(
+        session_id.clone(),
+        config.buffer.unwrap_or_default(),
+    )));
+    let (drain_tx, drain_rx) = mpsc::channel::<()>(16);
+    let (observer_tx, mut observer_rx) = mpsc::unbounded_channel();
+    let (emitter, backpressure) = spawn_observed_emitter(
+        session_id.clone(),
+        app_handle,
+        Arc::clone(&buffer),
+        Arc::new(Mutex::new(Scrollback::default())),
+        &config,
+        drain_rx,
+        observer_tx,
+    );
+
+    let mut pause_rx = backpressure.subscribe();
+
+    let marks: Marks = Arc::default();
+    let consumer = {
+        let marks = Arc::clone(&marks);
+        tokio::spawn(async move {
+            let mut emitted = 0u64;
+            let mut events = 0u64;
+            let mut latencies = Vec::new();
+            while let Some(len) = observer_rx.recv().await {
+                let now = Instant::now();
+                emitted += len as u64;
+                events += 1;
+                let _ = drain_tx.try_send(());
+
+                let mut marks = marks.lock().unwrap();
+                while marks.front().is_some_and(|&(end, _)| end <= emitted) {
+                    if let Some((_, at)) = marks.pop_front() {
+                        latencies.push(now - at);
+                    }
+                }
+            }
+            (emitted, events, latencies)
+        })
+    };
+
+    let data = synthetic_output(options.chunk_size);
+    let start = Instant::now();
+
+    match mode {
+        BenchmarkMode::Pipeline => {
+            let mut offset = 0u64;
+            while offset < options.total_bytes as u64 {
+                let _ = pause_rx.wait_for(|paused| !paused).await;
+                offset += data.len() as u64;
+                marks.lock().unwrap().push_back((offset, Instant::now()));
+                let mut buf = buffer.lock().await;
+                buf.push_bytes(data.clone().into());
+                backpressure.update(&buf);
+                drop(buf);
+                emitter.wake();
+            }
+        }
+        BenchmarkMode::Telnet => {
+            let listener = TcpListener::bind("127.0.0.1:0")
+                .await
+                .map_err(|e| e.to_string())?;
+            let addr = listener.local_addr().map_err(|e| e.to_string())?;
+
+            let server = {
+                let marks = Arc::clone(&marks);
+                let data = data.clone();
+                let total = options.total_bytes as u64;
+                tokio::spawn(async move {
+                    let (mut socket, _) = listener.accept().await?;
+                    let mut offset = 0u64;
+                    while offset < total {
+                        offset += data.len() as u64;
+                        marks.lock().unwrap().push_back((offset, Instant::now()));
+                        socket.write_all(&data).await?;
+                    }
+                    socket.shutdown().await
+                })
+            };
+
+            let mut stream = TcpStream::connect(addr).await.map_err(|e| e.to_string())?;
+            let mut telnet_parser = TelnetParser::new();
+            let mut line_assembler = LineAssembler::new();
+            let mut vrp_parser = VrpParser::new();
+            let mut read_buf = [0u8; 4096];
+            loop {
+                let n = stream
+                    .read(&mut read_buf)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if n == 0 {
+                    break;
+                }
+                let (data, _) = telnet_parser.parse(&read_buf[..n]);
+                let lines = line_assembler.push(&data);
+                vrp_parser.parse(&lines, &mut line_assembler);
+
+                let mut buf = buffer.lock().await;
+                buf.push_bytes(data.into());
+                backpressure.update(&buf);
+                drop(buf);
+                emitter.wake();
+                let _ = pause_rx.wait_for(|paused| !paused).await;
+            }
+
+            server
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    emitter.close().await;
+    let (bytes, events, mut latencies) = consumer.await.map_err(|e| e.to_string())?;
+    let elapsed = start.elapsed();
+
+    latencies.sort();
+    let report = BenchmarkReport {
+        mode,
+        payload_encoding: options.payload_encoding,
+        bytes,
+        events,
+        duration_ms: as_ms(elapsed),
+        throughput_mb_s: bytes as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64(),
+        latency_p50_ms: as_ms(percentile(&latencies, 50.0)),
+        latency_p90_ms: as_ms(percentile(&latencies, 90.0)),
+        latency_p99_ms: as_ms(percentile(&latencies, 99.0)),
+        latency_max_ms: as_ms(latencies.last().copied().unwrap_or_default()),
+    };
+    info!(?report, "Benchmark finished");
+    Ok(report)
+}
+
+/// Device-like `display interface brief` lines, cut to `len` bytes
+fn synthetic_output(len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len + 64);
+    let mut i = 0;
+    while out.len() < len {
+        out.extend_from_slice(
+            format!(
+                "GigabitEthernet0/0/{:<4} up    up    0%    0%    0    0\r\n",
+                i
+            )
+            .as_bytes(),
+        );
+        i += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+/// Nearest-rank percentile of sorted `values`
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn as_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&values, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&values, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&values, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&values[..1], 90.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_synthetic_output() {
+        let data = synthetic_output(4096);
+        assert_eq!(data.len(), 4096);
+        assert!(data.starts_with(b"GigabitEthernet0/0/0 "));
+    }
+}
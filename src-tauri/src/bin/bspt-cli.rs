@@ -0,0 +1,3 @@
+fn main() {
+    bspt_lib::run_cli()
+}
@@ -0,0 +1,283 @@
+//! Session handoff bundles: one JSON file with what a colleague needs to
+//! pick up a debugging session.
+//!
+//! A bundle holds the session's transcript with escape sequences removed,
+//! how it was connected, the hostname and board table found in the
+//! transcript, and the device's recent alarms and tracer hits from the
+//! timeline. Passwords never go in: the config is reduced to host, port and
+//! user, the session's own passwords are masked wherever they show up, and
+//! so are secrets in configuration lines such as `password cipher ...`.
+
+use crate::charset::Charset;
+use crate::lines::LineAssembler;
+use crate::scrollback::ANSI_RE;
+use crate::session::{DeviceDialect, Protocol, SessionConfig, SessionError, SessionManager};
+use crate::timeline::{Timeline, TimelineEntry, TimelineQuery, TimelineSource};
+use crate::vrp::{BoardInfo, VrpEvent, VrpParser};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::LazyLock;
+use tracing::info;
+
+const FORMAT: &str = "bspt-session-bundle";
+const VERSION: u32 = 1;
+
+/// Scrollback lines put in the transcript
+const TRANSCRIPT_LINES: u64 = 50_000;
+
+/// Newest timeline entries of each kind
+const TIMELINE_LIMIT: usize = 500;
+
+const MASK: &str = "********";
+
+// VRP/Comware `cipher X`, `irreversible-cipher X`, `simple X`; IOS
+// `secret 5 X`, `password 7 X`, `key 7 X`
+static SECRET_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(irreversible-cipher|cipher|simple|(?:secret|password|key)\s+\d)\s+\S+")
+        .unwrap()
+});
+
+/// The connection, without credentials
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleDevice {
+    pub session_id: String,
+    pub host: String,
+    pub port: u16,
+    pub protocol: Protocol,
+    pub username: String,
+    pub dialect: DeviceDialect,
+    pub charset: Charset,
+    /// Jump hosts as `user@host:port`, outermost first
+    pub jump_hosts: Vec<String>,
+    /// From the last prompt in the transcript
+    pub hostname: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionBundle {
+    pub format: String,
+    pub version: u32,
+    /// Unix milliseconds
+    pub exported_at: i64,
+    pub device: BundleDevice,
+    /// Latest `display device` row of each slot in the transcript
+    pub boards: Vec<BoardInfo>,
+    /// VRP events, syslog messages and traps, oldest first
+    pub alarms: Vec<TimelineEntry>,
+    /// Log lines matched to source code, oldest first
+    pub tracer_hits: Vec<TimelineEntry>,
+    pub transcript: String,
+}
+
+/// What `export_session_bundle` wrote
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleSummary {
+    pub path: String,
+    pub bytes: usize,
+    pub transcript_lines: usize,
+    pub boards: usize,
+    pub alarms: usize,
+    pub tracer_hits: usize,
+}
+
+/// Collect the bundle of a running session
+pub async fn collect(
+    manager: &SessionManager,
+    timeline: &Timeline,
+    session_id: &str,
+) -> Result<SessionBundle, SessionError> {
+    let handle = manager
+        .get(session_id)
+        .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+    let raw = handle.scrollback.lock().await.tail(TRANSCRIPT_LINES);
+    let config = &handle.config;
+
+    let mut assembler = LineAssembler::new();
+    let mut lines = assembler.push(&raw);
+    if !assembler.partial().is_empty() {
+        lines.push(assembler.partial().to_string());
+    }
+    let lines: Vec<String> = lines
+        .iter()
+        .map(|line| ANSI_RE.replace_all(line, "").into_owned())
+        .collect();
+    let (hostname, boards) = scan(config.dialect, &lines);
+
+    let query = |sources: Vec<TimelineSource>| {
+        timeline.query(&TimelineQuery {
+            device: Some(config.host.clone()),
+            sources,
+            limit: Some(TIMELINE_LIMIT),
+            ..TimelineQuery::default()
+        })
+    };
+    let alarms = query(vec![
+        TimelineSource::Vrp,
+        TimelineSource::Syslog,
+        TimelineSource::Trap,
+    ]);
+    let tracer_hits = query(vec![TimelineSource::Tracer]);
+
+    let secrets = secrets(config);
+    let mask = |text: &str| redact(text, &secrets);
+    let masked = |entries: Vec<TimelineEntry>| -> Vec<TimelineEntry> {
+        entries
+            .into_iter()
+            .map(|entry| TimelineEntry {
+                title: mask(&entry.title),
+                detail: mask(&entry.detail),
+                ..entry
+            })
+            .collect()
+    };
+
+    Ok(SessionBundle {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        exported_at: chrono::Utc::now().timestamp_millis(),
+        device: BundleDevice {
+            session_id: session_id.to_string(),
+            host: config.host.clone(),
+            port: config.port,
+            protocol: config.protocol,
+            username: config.username.clone(),
+            dialect: config.dialect,
+            charset: config.charset,
+            jump_hosts: config
+                .jump_hosts
+                .iter()
+                .map(|jump| format!("{}@{}:{}", jump.username, jump.host, jump.port))
+                .collect(),
+            hostname,
+        },
+        boards,
+        alarms: masked(alarms),
+        tracer_hits: masked(tracer_hits),
+        transcript: mask(&lines.join("\n")),
+    })
+}
+
+/// Write a session's bundle to `path` (replaced)
+pub async fn export(
+    manager: &SessionManager,
+    timeline: &Timeline,
+    session_id: &str,
+    path: &Path,
+) -> Result<BundleSummary, SessionError> {
+    let bundle = collect(manager, timeline, session_id).await?;
+    let json = serde_json::to_vec_pretty(&bundle).map_err(std::io::Error::from)?;
+    tokio::fs::write(path, &json).await?;
+    info!(session_id = %session_id, path = %path.display(), bytes = json.len(), "Exported session bundle");
+    Ok(BundleSummary {
+        path: path.display().to_string(),
+        bytes: json.len(),
+        transcript_lines: bundle.transcript.lines().count(),
+        boards: bundle.boards.len(),
+        alarms: bundle.alarms.len(),
+        tracer_hits: bundle.tracer_hits.len(),
+    })
+}
+
+/// Hostname of the last prompt and the latest row of each board
+fn scan(dialect: DeviceDialect, lines: &[String]) -> (Option<String>, Vec<BoardInfo>) {
+    let mut parser = VrpParser::with_dialect(dialect);
+    let mut hostname = None;
+    let mut boards: Vec<BoardInfo> = Vec::new();
+    let mut assembler = LineAssembler::new();
+    for line in lines {
+        let (events, _) = parser.parse(std::slice::from_ref(line), &mut assembler);
+        for event in events {
+            match event {
+                VrpEvent::ViewChange { hostname: name, .. } => hostname = Some(name),
+                VrpEvent::BoardInfo(board) => {
+                    boards
+                        .retain(|b| (&b.slot_id, &b.sub_slot) != (&board.slot_id, &board.sub_slot));
+                    boards.push(board);
+                }
+                _ => {}
+            }
+        }
+    }
+    boards.sort_by_key(|b| (b.slot_id.parse::<u32>().ok(), b.sub_slot.clone()));
+    (hostname, boards)
+}
+
+/// Passwords and key passphrases of the session and its jump hosts,
+/// longest first so one containing another is masked whole
+fn secrets(config: &SessionConfig) -> Vec<String> {
+    let mut secrets: Vec<String> = std::iter::once(&config.password)
+        .chain(config.jump_hosts.iter().map(|jump| &jump.password))
+        .chain(config.auth.passphrase())
+        .chain(
+            config
+                .jump_hosts
+                .iter()
+                .filter_map(|jump| jump.auth.passphrase()),
+        )
+        .filter(|password| !password.is_empty())
+        .cloned()
+        .collect();
+    secrets.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    secrets.dedup();
+    secrets
+}
+
+fn redact(text: &str, secrets: &[String]) -> String {
+    let mut text = SECRET_RE
+        .replace_all(text, |caps: &regex::Captures| {
+            format!("{} {}", &caps[1], MASK)
+        })
+        .into_owned();
+    for secret in secrets {
+        text = text.replace(secret.as_str(), MASK);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_and_redact() {
+        let lines: Vec<String> = [
+            "<core-1>display device",
+            "0    -    SRUC    Present Master   192.168.1.1",
+            "1    0    LPU     Offline",
+            "<core-1>display device",
+            "1    0    LPU     Present Slave    10.0.0.1",
+            "<core-1>",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+        let (hostname, boards) = scan(DeviceDialect::Vrp, &lines);
+        assert_eq!(hostname.as_deref(), Some("core-1"));
+        assert_eq!(boards.len(), 2);
+        assert_eq!(boards[1].slot_id, "1");
+        assert_eq!(boards[1].status, "Present");
+
+        let secrets = vec!["Hunter2!".to_string()];
+        assert_eq!(
+            redact(
+                " local-user admin password irreversible-cipher $1a$xyz$",
+                &secrets
+            ),
+            " local-user admin password irreversible-cipher ********"
+        );
+        assert_eq!(
+            redact("snmp-agent community read cipher %^%#abc", &secrets),
+            "snmp-agent community read cipher ********"
+        );
+        assert_eq!(
+            redact("enable secret 5 $1$mERr$abc", &secrets),
+            "enable secret 5 ********"
+        );
+        assert_eq!(
+            redact("echo Hunter2! > /tmp/x", &secrets),
+            "echo ******** > /tmp/x"
+        );
+        assert_eq!(redact("display clock", &secrets), "display clock");
+    }
+}
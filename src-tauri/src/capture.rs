@@ -0,0 +1,389 @@
+//! Raw capture of a session's byte streams, for reproducing parser bugs
+//! from the field.
+//!
+//! A capture records exactly what the transport read and wrote, before
+//! telnet parsing or charset decoding: telnet negotiation included, SSH
+//! channel data after decryption. The file is JSON lines, a header followed
+//! by one record per read or write with its offset from the start and the
+//! data in base64. `replay` feeds the inbound records back through the same
+//! pipeline a telnet session uses and reports what came out.
+
+use crate::charset::{Charset, Transcoder};
+use crate::lines::LineAssembler;
+use crate::session::{DeviceDialect, Protocol, SessionConfig};
+use crate::telnet::TelnetParser;
+use crate::vrp::{VrpEvent, VrpParser};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Capture format version written in the header
+const VERSION: u32 = 1;
+
+/// Replayed output returned in a report
+const MAX_OUTPUT: usize = 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[error("Session is already capturing to {0}")]
+    AlreadyCapturing(String),
+    #[error("Session is not capturing")]
+    NotCapturing,
+    #[error("Invalid capture file: {0}")]
+    Invalid(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<CaptureError> for String {
+    fn from(err: CaptureError) -> String {
+        err.to_string()
+    }
+}
+
+/// First line of a capture file; what replay needs to rebuild the pipeline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureHeader {
+    pub version: u32,
+    pub session_id: String,
+    pub host: String,
+    pub port: u16,
+    pub protocol: Protocol,
+    pub charset: Charset,
+    pub dialect: DeviceDialect,
+    /// Unix milliseconds
+    pub started_at: i64,
+}
+
+impl CaptureHeader {
+    pub fn new(session_id: &str, config: &SessionConfig) -> Self {
+        Self {
+            version: VERSION,
+            session_id: session_id.to_string(),
+            host: config.host.clone(),
+            port: config.port,
+            protocol: config.protocol,
+            charset: config.charset,
+            dialect: config.dialect,
+            started_at: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Read from the device
+    In,
+    /// Written to the device
+    Out,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Microseconds since the capture started
+    pub offset_us: u64,
+    pub dir: Direction,
+    /// Base64
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureStatus {
+    pub path: String,
+    pub started_at: i64,
+    pub records: u64,
+    pub bytes: u64,
+}
+
+struct CaptureWriter {
+    file: File,
+    started: Instant,
+    status: CaptureStatus,
+}
+
+/// Capture switch shared by a session's transport and its handle. Writes go
+/// straight to the file; a failed write stops the capture.
+#[derive(Clone, Default)]
+pub struct CaptureTap {
+    writer: Arc<Mutex<Option<CaptureWriter>>>,
+}
+
+impl CaptureTap {
+    /// A tap capturing to the session's `capture_file` from the start, if set
+    pub fn for_session(session_id: &str, config: &SessionConfig) -> Self {
+        let tap = Self::default();
+        if let Some(path) = &config.capture_file {
+            if let Err(e) = tap.start(Path::new(path), CaptureHeader::new(session_id, config)) {
+                warn!(session_id = %session_id, path, error = %e, "Failed to start capture");
+            }
+        }
+        tap
+    }
+
+    /// Start capturing to `path`, replacing the file
+    pub fn start(&self, path: &Path, header: CaptureHeader) -> Result<CaptureStatus, CaptureError> {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(current) = writer.as_ref() {
+            return Err(CaptureError::AlreadyCapturing(current.status.path.clone()));
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(path)?;
+        let mut line = serde_json::to_vec(&header)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+
+        let status = CaptureStatus {
+            path: path.display().to_string(),
+            started_at: header.started_at,
+            records: 0,
+            bytes: 0,
+        };
+        info!(session_id = %header.session_id, path = %status.path, "Capture started");
+        *writer = Some(CaptureWriter {
+            file,
+            started: Instant::now(),
+            status: status.clone(),
+        });
+        Ok(status)
+    }
+
+    pub fn stop(&self) -> Result<CaptureStatus, CaptureError> {
+        let writer = self
+            .writer
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(CaptureError::NotCapturing)?;
+        info!(path = %writer.status.path, records = writer.status.records, "Capture stopped");
+        Ok(writer.status)
+    }
+
+    pub fn status(&self) -> Option<CaptureStatus> {
+        self.writer
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|w| w.status.clone())
+    }
+
+    pub fn inbound(&self, data: &[u8]) {
+        self.record(Direction::In, data);
+    }
+
+    pub fn outbound(&self, data: &[u8]) {
+        self.record(Direction::Out, data);
+    }
+
+    fn record(&self, dir: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let mut guard = self.writer.lock().unwrap();
+        let Some(writer) = guard.as_mut() else {
+            return;
+        };
+        let record = CaptureRecord {
+            offset_us: writer.started.elapsed().as_micros() as u64,
+            dir,
+            data: STANDARD.encode(data),
+        };
+        let written = serde_json::to_vec(&record)
+            .map_err(io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                writer.file.write_all(&line)
+            });
+        match written {
+            Ok(()) => {
+                writer.status.records += 1;
+                writer.status.bytes += data.len() as u64;
+            }
+            Err(e) => {
+                warn!(path = %writer.status.path, error = %e, "Capture write failed, capture stopped");
+                *guard = None;
+            }
+        }
+    }
+}
+
+/// A VRP event raised while replaying
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayEvent {
+    /// Offset of the record that raised it
+    pub offset_ms: u64,
+    pub event: VrpEvent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayReport {
+    pub header: CaptureHeader,
+    pub records: usize,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub duration_ms: u64,
+    /// Telnet negotiation commands parsed from the inbound stream
+    pub telnet_commands: usize,
+    /// Complete lines seen by the VRP parser
+    pub lines: usize,
+    pub events: Vec<ReplayEvent>,
+    /// Automatic replies the parser would have sent (pagination)
+    pub auto_responses: usize,
+    /// Decoded output as the terminal received it, up to 1MB
+    pub output: String,
+    pub output_truncated: bool,
+}
+
+/// Read a capture file
+pub fn load(path: &Path) -> Result<(CaptureHeader, Vec<CaptureRecord>), CaptureError> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: CaptureHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(CaptureError::Invalid("empty file".to_string())),
+    };
+    if header.version > VERSION {
+        return Err(CaptureError::Invalid(format!(
+            "version {} is newer than this app reads",
+            header.version
+        )));
+    }
+    let mut records = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line)?);
+    }
+    Ok((header, records))
+}
+
+/// Feed a capture's inbound bytes through telnet parsing (telnet captures),
+/// charset decoding, line assembly and the VRP parser of its dialect
+pub fn replay(
+    header: CaptureHeader,
+    records: &[CaptureRecord],
+) -> Result<ReplayReport, CaptureError> {
+    let mut telnet = TelnetParser::new();
+    let mut transcoder = Transcoder::new(header.session_id.clone(), header.charset);
+    let mut assembler = LineAssembler::new();
+    let mut vrp = VrpParser::with_dialect(header.dialect);
+    let mut report = ReplayReport {
+        header,
+        records: records.len(),
+        bytes_in: 0,
+        bytes_out: 0,
+        duration_ms: records.last().map_or(0, |r| r.offset_us / 1000),
+        telnet_commands: 0,
+        lines: 0,
+        events: Vec::new(),
+        auto_responses: 0,
+        output: String::new(),
+        output_truncated: false,
+    };
+
+    for record in records {
+        let data = STANDARD.decode(&record.data).map_err(|e| {
+            CaptureError::Invalid(format!("record at {}us: {}", record.offset_us, e))
+        })?;
+        if record.dir == Direction::Out {
+            report.bytes_out += data.len() as u64;
+            continue;
+        }
+        report.bytes_in += data.len() as u64;
+
+        let data = match report.header.protocol {
+            Protocol::Telnet => {
+                let (data, commands) = telnet.parse(&data);
+                report.telnet_commands += commands.len();
+                data
+            }
+            Protocol::Ssh => data,
+        };
+        let data = transcoder.decode(data);
+        let lines = assembler.push(&data);
+        report.lines += lines.len();
+        let (events, auto_response) = vrp.parse(&lines, &mut assembler);
+        report.auto_responses += usize::from(auto_response.is_some());
+        report
+            .events
+            .extend(events.into_iter().map(|event| ReplayEvent {
+                offset_ms: record.offset_us / 1000,
+                event,
+            }));
+
+        if report.output.len() < MAX_OUTPUT {
+            report.output.push_str(&String::from_utf8_lossy(&data));
+        } else {
+            report.output_truncated = true;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_and_replay() {
+        let dir = std::env::temp_dir().join(format!("bspt-capture-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("core-1.capture");
+        let header = CaptureHeader {
+            version: VERSION,
+            session_id: "s1".to_string(),
+            host: "10.0.0.1".to_string(),
+            port: 23,
+            protocol: Protocol::Telnet,
+            charset: Charset::default(),
+            dialect: DeviceDialect::Vrp,
+            started_at: 0,
+        };
+
+        let tap = CaptureTap::default();
+        // Nothing is recorded before the capture starts
+        tap.inbound(b"lost");
+        tap.start(&path, header.clone()).unwrap();
+        assert!(matches!(
+            tap.start(&path, header),
+            Err(CaptureError::AlreadyCapturing(_))
+        ));
+        // IAC DO NAWS, then a prompt and a rejected command split across reads
+        tap.inbound(&[255, 253, 31]);
+        tap.inbound(b"<core-1>");
+        tap.outbound(b"disp foo\r");
+        tap.inbound(b"disp foo\r\nError: Unrecognized com");
+        tap.inbound(b"mand found at '^' position.\r\n<core-1>");
+        let status = tap.stop().unwrap();
+        assert_eq!(status.records, 5);
+        tap.inbound(b"after stop");
+
+        let (header, records) = load(&path).unwrap();
+        assert_eq!(header.host, "10.0.0.1");
+        assert_eq!(records.len(), 5);
+        assert_eq!(STANDARD.decode(&records[0].data).unwrap(), [255, 253, 31]);
+
+        let report = replay(header, &records).unwrap();
+        assert_eq!(report.telnet_commands, 1);
+        assert_eq!(report.bytes_out, 9);
+        assert!(report.output.starts_with("<core-1>disp foo"));
+        assert!(!report.output.contains('\u{fffd}'));
+        assert!(report.events.iter().any(|e| matches!(
+            &e.event,
+            VrpEvent::CommandError { message } if message.starts_with("Unrecognized command")
+        )));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
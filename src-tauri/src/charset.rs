@@ -0,0 +1,205 @@
+use encoding_rs::{Decoder, GB18030, GBK};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Character set of output received from a device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Charset {
+    #[default]
+    Utf8,
+    /// GBK double-byte encoding (Chinese-locale VRP)
+    Gbk,
+    Gb18030,
+    /// ISO-8859-1, one byte per code point
+    Latin1,
+    /// Decide from the first non-ASCII output, see `Transcoder`
+    Auto,
+}
+
+/// Streaming transcoder from a device charset to UTF-8.
+///
+/// Runs on raw device output before it reaches the VRP parser and the
+/// ring buffer, so everything downstream only deals with UTF-8. Multibyte
+/// sequences split across reads are carried over by the decoder.
+///
+/// `Charset::Auto` passes ASCII through untouched and resolves once output
+/// has non-ASCII bytes beyond a possibly incomplete tail: valid UTF-8
+/// selects UTF-8, anything else selects GB18030 (a superset of GBK, which
+/// Huawei devices use with `language-mode chinese`). A tail that could start
+/// a UTF-8 character is held back until the next read decides.
+pub struct Transcoder {
+    session_id: String,
+    charset: Charset,
+    decoder: Option<Decoder>,
+    /// Undecided tail held back while `Auto`
+    pending: Vec<u8>,
+}
+
+impl Transcoder {
+    pub fn new(session_id: String, charset: Charset) -> Self {
+        let mut transcoder = Self {
+            session_id,
+            charset,
+            decoder: None,
+            pending: Vec::new(),
+        };
+        transcoder.set_charset(charset);
+        transcoder
+    }
+
+    /// Charset in use; `Auto` until detection has run.
+    #[cfg(test)]
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+        self.decoder = match charset {
+            Charset::Gbk => Some(GBK.new_decoder_without_bom_handling()),
+            Charset::Gb18030 => Some(GB18030.new_decoder_without_bom_handling()),
+            Charset::Utf8 | Charset::Latin1 | Charset::Auto => None,
+        };
+    }
+
+    /// Convert a chunk of device output to UTF-8.
+    pub fn decode(&mut self, mut data: Vec<u8>) -> Vec<u8> {
+        if self.charset == Charset::Auto {
+            if !self.pending.is_empty() {
+                data.splice(..0, std::mem::take(&mut self.pending));
+            }
+            if data.is_ascii() {
+                return data;
+            }
+            let complete = data.len() - utf8_incomplete_tail(&data);
+            // Only the tail is non-ASCII, e.g. a GBK lead byte at the end
+            // of the read: not enough to tell yet
+            if data[..complete].is_ascii() {
+                self.pending = data.split_off(complete);
+                return data;
+            }
+            let complete = &data[..complete];
+            let detected = if std::str::from_utf8(complete).is_ok() {
+                Charset::Utf8
+            } else {
+                Charset::Gb18030
+            };
+            info!(session_id = %self.session_id, charset = ?detected, "Detected output charset");
+            self.set_charset(detected);
+        }
+
+        match self.charset {
+            Charset::Latin1 => data
+                .iter()
+                .map(|&b| b as char)
+                .collect::<String>()
+                .into_bytes(),
+            _ => match self.decoder.as_mut() {
+                Some(decoder) => {
+                    let capacity = decoder
+                        .max_utf8_buffer_length(data.len())
+                        .unwrap_or(data.len() * 3);
+                    let mut out = String::with_capacity(capacity);
+                    let _ = decoder.decode_to_string(&data, &mut out, false);
+                    out.into_bytes()
+                }
+                // UTF-8 passes through, partial characters are handled at emit
+                None => data,
+            },
+        }
+    }
+}
+
+/// Number of trailing bytes of UTF-8 `data` that start a multibyte
+/// sequence which isn't complete yet.
+///
+/// Invalid bytes are not held back, so a corrupt stream can't stall output.
+pub fn utf8_incomplete_tail(data: &[u8]) -> usize {
+    // A sequence is at most 4 bytes, so only the last 3 can be incomplete
+    for back in 1..=data.len().min(3) {
+        let byte = data[data.len() - back];
+        if byte & 0xC0 == 0x80 {
+            // Continuation byte, keep looking for the lead
+            continue;
+        }
+        let width = match byte {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return 0,
+        };
+        return if width > back { back } else { 0 };
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_incomplete_tail() {
+        let text = "接口 up".as_bytes();
+        assert_eq!(utf8_incomplete_tail(text), 0);
+        // 接 = E6 8E A5
+        assert_eq!(utf8_incomplete_tail(&text[..1]), 1);
+        assert_eq!(utf8_incomplete_tail(&text[..2]), 2);
+        assert_eq!(utf8_incomplete_tail(&text[..3]), 0);
+        assert_eq!(utf8_incomplete_tail(&text[..4]), 1);
+        // Invalid lead bytes are passed through
+        assert_eq!(utf8_incomplete_tail(b"ab\xFF"), 0);
+        assert_eq!(utf8_incomplete_tail(b"ab\x80\x80\x80"), 0);
+    }
+
+    #[test]
+    fn test_gbk_split_across_reads() {
+        // 接口 in GBK = BD D3 BF DA
+        let mut transcoder = Transcoder::new("test".to_string(), Charset::Gbk);
+        let mut out = transcoder.decode(vec![b'a', 0xBD]);
+        assert_eq!(out, b"a");
+        out.extend(transcoder.decode(vec![0xD3, 0xBF]));
+        out.extend(transcoder.decode(vec![0xDA, b'\n']));
+        assert_eq!(String::from_utf8(out).unwrap(), "a接口\n");
+    }
+
+    #[test]
+    fn test_latin1() {
+        let mut transcoder = Transcoder::new("test".to_string(), Charset::Latin1);
+        assert_eq!(transcoder.decode(vec![b'x', 0xE9]), "xé".as_bytes());
+    }
+
+    #[test]
+    fn test_auto_detect() {
+        let mut transcoder = Transcoder::new("test".to_string(), Charset::Auto);
+        assert_eq!(transcoder.decode(b"<HUAWEI>".to_vec()), b"<HUAWEI>");
+        assert_eq!(transcoder.charset(), Charset::Auto);
+        let out = transcoder.decode(vec![0xD0, 0xC5, 0xCF, 0xA2, b':']);
+        assert_eq!(transcoder.charset(), Charset::Gb18030);
+        assert_eq!(String::from_utf8(out).unwrap(), "信息:");
+
+        let mut transcoder = Transcoder::new("test".to_string(), Charset::Auto);
+        let out = transcoder.decode("信息:".as_bytes().to_vec());
+        assert_eq!(transcoder.charset(), Charset::Utf8);
+        assert_eq!(out, "信息:".as_bytes());
+    }
+
+    #[test]
+    fn test_auto_detect_split_lead_byte() {
+        // 中 in GBK = D6 D0, D6 alone could also start a UTF-8 character
+        let mut transcoder = Transcoder::new("test".to_string(), Charset::Auto);
+        assert_eq!(transcoder.decode(b"abc\xD6".to_vec()), b"abc");
+        assert_eq!(transcoder.charset(), Charset::Auto);
+        let mut out = transcoder.decode(vec![0xD0, 0xCE, 0xC4]);
+        assert_eq!(transcoder.charset(), Charset::Gb18030);
+        out.extend(transcoder.decode(vec![b'\n']));
+        assert_eq!(String::from_utf8(out).unwrap(), "中文\n");
+
+        // The same split in UTF-8: 中 = E4 B8 AD
+        let mut transcoder = Transcoder::new("test".to_string(), Charset::Auto);
+        assert_eq!(transcoder.decode(b"abc\xE4\xB8".to_vec()), b"abc");
+        let out = transcoder.decode(vec![0xAD, b'\n']);
+        assert_eq!(transcoder.charset(), Charset::Utf8);
+        assert_eq!(String::from_utf8(out).unwrap(), "中\n");
+    }
+}
@@ -0,0 +1,426 @@
+//! Headless command line (`bspt-cli`).
+//!
+//! The binary starts the same backend as the desktop app without opening a
+//! window. Profiles, settings and the vault come from the app's own data
+//! directories, and devices are driven by the batch engine, so a CI job gets
+//! the login handling, pagination and VRP parsing the terminal uses. On
+//! Linux the webview toolkit still wants a display: run it under `xvfb-run`
+//! on servers without one.
+
+use crate::batch::{self, BatchJob, BatchOptions, BatchReport, BatchTarget, CommandResult};
+use crate::jobs::{JobKind, JobRegistry};
+use crate::profile::{Profile, ProfileStore};
+use crate::session::{Protocol, SessionManager};
+use crate::settings::SettingsStore;
+use crate::tracer::LogTracer;
+use crate::vault::Vault;
+use std::path::Path;
+use std::sync::Arc;
+use tauri::Manager;
+use tokio::sync::Mutex;
+
+/// Password of `--host` targets
+const PASSWORD_ENV: &str = "BSPT_PASSWORD";
+
+/// Unlocks the credential vault before profiles are resolved
+const VAULT_ENV: &str = "BSPT_VAULT_PASSPHRASE";
+
+pub const USAGE: &str = "\
+Usage: bspt-cli <command> [options]
+
+Commands:
+  run         Run commands on devices and print their output
+  trace       Match log lines (arguments, else stdin) to source code
+  profiles    List saved profiles
+
+run:
+  --profile <id>               Saved profile (repeatable)
+  --host <[user@]host[:port]>  Device without a profile (repeatable)
+  --telnet                     --host devices use telnet instead of SSH
+  --csv <file>                 Devices from a CSV inventory, as run_batch reads
+  -c, --command <command>      Command to run, in order (repeatable)
+  --concurrency <n>            Devices connected at once (default 8)
+  --timeout <secs>             Per command timeout
+  --continue-on-error          Keep running a device's commands after a failure
+  --json                       Print the batch report as JSON
+
+trace:
+  --index <dir>                Source tree to index (required)
+  --json                       One JSON object per line
+
+profiles:
+  --json                       Print the profiles as JSON
+
+Environment:
+  BSPT_PASSWORD                Password of --host devices
+  BSPT_VAULT_PASSPHRASE        Unlocks the vault for profiles that use it
+
+Exit status is 0 on success, 1 when a device, command or lookup failed and
+2 on a usage error.";
+
+/// A parsed command line
+#[derive(Debug)]
+pub enum Invocation {
+    Help,
+    Run(RunArgs),
+    Trace {
+        index: String,
+        lines: Vec<String>,
+        json: bool,
+    },
+    Profiles {
+        json: bool,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct RunArgs {
+    pub profiles: Vec<String>,
+    pub hosts: Vec<String>,
+    pub telnet: bool,
+    pub csv: Option<String>,
+    pub commands: Vec<String>,
+    pub options: BatchOptions,
+    pub json: bool,
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Invocation, String> {
+    let mut args = args.into_iter();
+    let Some(command) = args.next() else {
+        return Ok(Invocation::Help);
+    };
+    match command.as_str() {
+        "-h" | "--help" | "help" => Ok(Invocation::Help),
+        "run" => {
+            let mut run = RunArgs::default();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--profile" => run.profiles.push(value(&arg, &mut args)?),
+                    "--host" => run.hosts.push(value(&arg, &mut args)?),
+                    "--telnet" => run.telnet = true,
+                    "--csv" => run.csv = Some(value(&arg, &mut args)?),
+                    "-c" | "--command" => run.commands.push(value(&arg, &mut args)?),
+                    "--concurrency" => {
+                        run.options.concurrency = Some(number(&arg, &value(&arg, &mut args)?)?)
+                    }
+                    "--timeout" => {
+                        let secs: u64 = number(&arg, &value(&arg, &mut args)?)?;
+                        run.options.command_timeout_ms = Some(secs * 1000);
+                    }
+                    "--continue-on-error" => run.options.continue_on_error = true,
+                    "--json" => run.json = true,
+                    other => return Err(format!("unknown option `{}` for run", other)),
+                }
+            }
+            if run.profiles.is_empty() && run.hosts.is_empty() && run.csv.is_none() {
+                return Err("run needs --profile, --host or --csv".to_string());
+            }
+            if run.commands.is_empty() {
+                return Err("run needs at least one --command".to_string());
+            }
+            Ok(Invocation::Run(run))
+        }
+        "trace" => {
+            let (mut index, mut lines, mut json) = (None, Vec::new(), false);
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--index" => index = Some(value(&arg, &mut args)?),
+                    "--json" => json = true,
+                    other if other.starts_with("--") => {
+                        return Err(format!("unknown option `{}` for trace", other))
+                    }
+                    _ => lines.push(arg),
+                }
+            }
+            let index = index.ok_or("trace needs --index")?;
+            Ok(Invocation::Trace { index, lines, json })
+        }
+        "profiles" => match args.next().as_deref() {
+            None => Ok(Invocation::Profiles { json: false }),
+            Some("--json") => Ok(Invocation::Profiles { json: true }),
+            Some(other) => Err(format!("unknown option `{}` for profiles", other)),
+        },
+        other => Err(format!("unknown command `{}`", other)),
+    }
+}
+
+fn value(flag: &str, args: &mut impl Iterator<Item = String>) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} needs a value", flag))
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got `{}`", flag, value))
+}
+
+/// A `--host` argument, `[user@]host[:port]`; IPv6 addresses with a port go
+/// in brackets
+fn host_target(spec: &str, telnet: bool, password: &str) -> Result<BatchTarget, String> {
+    let (username, address) = match spec.rsplit_once('@') {
+        Some((username, address)) => (username, address),
+        None => ("", spec),
+    };
+    let (host, port) = match address.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) if port.starts_with(':') => (host, Some(&port[1..])),
+            _ => return Err(format!("invalid host `{}`", spec)),
+        },
+        // A bare IPv6 address has no port
+        None => match address.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (address, None),
+        },
+    };
+    if host.is_empty() {
+        return Err(format!("invalid host `{}`", spec));
+    }
+    let protocol = if telnet {
+        Protocol::Telnet
+    } else {
+        Protocol::Ssh
+    };
+    let port = match port {
+        Some(port) => number("--host", port)?,
+        None if telnet => 23,
+        None => 22,
+    };
+    let profile = Profile {
+        username: username.to_string(),
+        password: Some(password.to_string()),
+        ..Profile::adhoc(host, port, protocol)
+    };
+    Ok(BatchTarget {
+        id: host.to_string(),
+        name: host.to_string(),
+        config: profile.session_config(None),
+    })
+}
+
+/// Run an invocation against the started backend; returns the exit status
+pub async fn execute(app_handle: &tauri::AppHandle, invocation: Invocation) -> i32 {
+    let result = match invocation {
+        Invocation::Help => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+        Invocation::Run(args) => run(app_handle, args).await,
+        Invocation::Trace { index, lines, json } => trace(app_handle, &index, lines, json).await,
+        Invocation::Profiles { json } => profiles(app_handle, json).await,
+    };
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("bspt-cli: {}", e);
+            1
+        }
+    }
+}
+
+async fn run(app_handle: &tauri::AppHandle, args: RunArgs) -> Result<bool, String> {
+    let profiles = app_handle.state::<Arc<Mutex<ProfileStore>>>();
+    let vault = app_handle.state::<Arc<Mutex<Vault>>>();
+    if let Ok(passphrase) = std::env::var(VAULT_ENV) {
+        vault.lock().await.unlock(&passphrase)?;
+    }
+
+    let mut targets = Vec::new();
+    for id in &args.profiles {
+        let (profile, config) =
+            crate::profile_session_config(id, None, app_handle, &profiles, &vault, None).await?;
+        targets.push(BatchTarget {
+            id: profile.id,
+            name: profile.name,
+            config,
+        });
+    }
+    let password = std::env::var(PASSWORD_ENV).unwrap_or_default();
+    for host in &args.hosts {
+        targets.push(host_target(host, args.telnet, &password)?);
+    }
+    if let Some(path) = &args.csv {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        targets.extend(batch::parse_targets_csv(&text)?);
+    }
+
+    let job = BatchJob {
+        targets,
+        commands: args.commands,
+        options: args.options,
+    };
+    job.validate()?;
+    let settings = app_handle
+        .state::<Arc<Mutex<SettingsStore>>>()
+        .lock()
+        .await
+        .get();
+    let manager = Arc::clone(&app_handle.state::<Arc<SessionManager>>());
+    let control = app_handle.state::<Arc<JobRegistry>>().start(
+        JobKind::Batch,
+        format!("bspt-cli, {} devices", job.targets.len()),
+    );
+    let report = job.run(&control, manager, &settings).await;
+    let summary = format!("{} succeeded, {} failed", report.succeeded, report.failed);
+    control.finish(Ok(summary.clone()));
+
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?
+        );
+    } else {
+        print!("{}", transcript(&report));
+        eprintln!("{}", summary);
+    }
+    Ok(report.failed == 0)
+}
+
+/// What each device answered, in target order
+fn transcript(report: &BatchReport) -> String {
+    let mut text = String::new();
+    for device in &report.devices {
+        let status = match &device.error {
+            Some(error) => format!("failed: {}", error),
+            None if device.success => "ok".to_string(),
+            None => "failed".to_string(),
+        };
+        text.push_str(&format!(
+            "== {} ({}) {} in {}ms\n",
+            device.name, device.host, status, device.duration_ms
+        ));
+        for result in &device.commands {
+            match result {
+                CommandResult::Ok(output) => {
+                    text.push_str(&format!("{}{}\n", output.prompt, output.command));
+                    if !output.output.is_empty() {
+                        text.push_str(&output.output);
+                        text.push('\n');
+                    }
+                }
+                CommandResult::Failed { command, error } => {
+                    text.push_str(&format!("! {}: {}\n", command, error))
+                }
+                CommandResult::Skipped { command } => {
+                    text.push_str(&format!("- {}: skipped\n", command))
+                }
+            }
+        }
+    }
+    text
+}
+
+async fn trace(
+    app_handle: &tauri::AppHandle,
+    index: &str,
+    mut lines: Vec<String>,
+    json: bool,
+) -> Result<bool, String> {
+    if lines.is_empty() {
+        let text = tokio::task::spawn_blocking(|| std::io::read_to_string(std::io::stdin()))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+        lines = text.lines().map(str::to_string).collect();
+    }
+
+    let tracer = app_handle.state::<Arc<Mutex<LogTracer>>>();
+    let mut tracer = tracer.lock().await;
+    let stats = tracer
+        .index_directory(Path::new(index))
+        .map_err(|e| e.to_string())?;
+    eprintln!(
+        "Indexed {} patterns from {} files",
+        stats.patterns_indexed, stats.files_scanned
+    );
+    for line in lines.iter().filter(|l| !l.trim().is_empty()) {
+        let location = tracer.match_log(line);
+        if json {
+            let entry = serde_json::json!({ "line": line, "location": location });
+            println!("{}", entry);
+        } else if let Some(location) = location {
+            println!("{}:{}: {}", location.file, location.line, line);
+        }
+    }
+    Ok(true)
+}
+
+async fn profiles(app_handle: &tauri::AppHandle, json: bool) -> Result<bool, String> {
+    let profiles = app_handle
+        .state::<Arc<Mutex<ProfileStore>>>()
+        .lock()
+        .await
+        .list();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&profiles).map_err(|e| e.to_string())?
+        );
+        return Ok(true);
+    }
+    for profile in profiles {
+        let protocol = match profile.protocol {
+            Protocol::Ssh => "ssh",
+            Protocol::Telnet => "telnet",
+        };
+        println!(
+            "{}\t{}\t{}://{}:{}",
+            profile.id,
+            profile.path(),
+            protocol,
+            profile.host,
+            profile.port
+        );
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let Invocation::Run(run) = parse(args(
+            "run --host admin@10.0.0.1:2222 --profile p1 -c display --timeout 5 --json",
+        ))
+        .unwrap() else {
+            panic!("expected run");
+        };
+        assert_eq!(run.hosts, ["admin@10.0.0.1:2222"]);
+        assert_eq!(run.profiles, ["p1"]);
+        assert_eq!(run.commands, ["display"]);
+        assert_eq!(run.options.command_timeout_ms, Some(5000));
+        assert!(run.json);
+
+        assert!(parse(args("run --host core-1")).is_err());
+        assert!(parse(args("run -c display")).is_err());
+        assert!(parse(args("trace line")).is_err());
+        assert!(matches!(parse(Vec::new()), Ok(Invocation::Help)));
+        assert!(matches!(
+            parse(args("trace --index src a b")),
+            Ok(Invocation::Trace { lines, .. }) if lines == ["a", "b"]
+        ));
+
+        let target = host_target("admin@10.0.0.1:2222", false, "secret").unwrap();
+        assert_eq!(target.config.port, 2222);
+        assert_eq!(target.config.username, "admin");
+        assert_eq!(target.config.password, "secret");
+        let target = host_target("fe80::1", true, "").unwrap();
+        assert_eq!(
+            (target.config.host.as_str(), target.config.port),
+            ("fe80::1", 23)
+        );
+        let target = host_target("[fe80::1]:830", false, "").unwrap();
+        assert_eq!(
+            (target.config.host.as_str(), target.config.port),
+            ("fe80::1", 830)
+        );
+        assert!(host_target("core-1:ssh", false, "").is_err());
+    }
+}
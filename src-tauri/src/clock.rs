@@ -0,0 +1,524 @@
+//! Clock skew audit across devices.
+//!
+//! Every selected session runs its dialect's clock command, and the time it
+//! prints is compared with the local clock at the midpoint of the command's
+//! round trip. Given an NTP server, the local clock is checked against it
+//! first (SNTP), so the offsets are against true time rather than this
+//! machine's. Device clocks print whole seconds, so offsets within about a
+//! second are noise; skews past `SKEWED_MS` are flagged, since they put the
+//! device's logs out of order on the event timeline.
+
+use crate::command;
+use crate::jobs::JobControl;
+use crate::session::{DeviceDialect, SessionManager};
+use crate::topology::prompt_hostname;
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::Serialize;
+use std::io;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+/// Offset from which a device is flagged as skewed
+pub const SKEWED_MS: i64 = 2000;
+
+const NTP_PORT: u16 = 123;
+const NTP_TIMEOUT: Duration = Duration::from_secs(3);
+/// Seconds from 1900, the NTP epoch, to 1970
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+// VRP and `date`: `2024-01-15 10:30:45+08:00`, the offset only on newer VRP
+static ISO_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(\d{4})-(\d{2})-(\d{2})[ T](\d{2}):(\d{2}):(\d{2})(?:\.(\d+))?(Z|[+-]\d{2}:?\d{2})?",
+    )
+    .unwrap()
+});
+
+// VRP: `Time Zone(China-Standard-Time) : UTC+08:00`
+static VRP_ZONE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)time zone\s*\(([^)]*)\)\s*:\s*UTC\s*(?:([+-])(\d{1,2}):(\d{2}))?").unwrap()
+});
+
+// Comware: `10:30:45.123 UTC Mon 01/15/2024`
+static COMWARE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\d{1,2}):(\d{2}):(\d{2})(?:\.(\d+))?\s+(\S+)\s+\w{3}\s+(\d{2})/(\d{2})/(\d{4})")
+        .unwrap()
+});
+
+// Comware: `Time Zone : Beijing add 08:00:00`
+static COMWARE_ZONE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)time zone\s*:\s*(\S+)\s+(add|minus)\s+(\d{1,2}):(\d{2})").unwrap()
+});
+
+// IOS: `*10:30:45.123 UTC Mon Jan 15 2024`
+static IOS_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"([*.]?)(\d{1,2}):(\d{2}):(\d{2})(?:\.(\d+))?\s+(\S+)\s+\w{3}\s+(\w{3})\s+(\d{1,2})\s+(\d{4})")
+        .unwrap()
+});
+
+/// The command printing the device clock, per dialect
+pub fn clock_command(dialect: DeviceDialect) -> Option<&'static str> {
+    match dialect {
+        DeviceDialect::Vrp | DeviceDialect::Comware => Some("display clock"),
+        DeviceDialect::Ios => Some("show clock"),
+        DeviceDialect::Linux => Some("date -u '+%Y-%m-%d %H:%M:%S'"),
+        DeviceDialect::Mml | DeviceDialect::Generic => None,
+    }
+}
+
+/// Time printed by a device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceTime {
+    /// Unix milliseconds
+    pub utc_ms: i64,
+    /// Time zone name, when the device printed one
+    pub zone: Option<String>,
+}
+
+fn number(text: Option<regex::Match>) -> u32 {
+    text.and_then(|m| m.as_str().parse().ok()).unwrap_or(0)
+}
+
+/// Milliseconds of a fraction such as `123` or `5`
+fn millis(fraction: Option<regex::Match>) -> u32 {
+    fraction.map_or(0, |m| {
+        let digits: String = m.as_str().chars().chain("000".chars()).take(3).collect();
+        digits.parse().unwrap_or(0)
+    })
+}
+
+fn utc_ms(date: (i32, u32, u32), time: (u32, u32, u32, u32), offset_min: i64) -> Option<i64> {
+    let local = NaiveDate::from_ymd_opt(date.0, date.1, date.2)?
+        .and_hms_milli_opt(time.0, time.1, time.2, time.3)?
+        .and_utc()
+        .timestamp_millis();
+    Some(local - offset_min * 60_000)
+}
+
+/// `+08:00`, `-0500` or `Z` in minutes
+fn zone_offset(text: &str) -> Option<i64> {
+    if text == "Z" {
+        return Some(0);
+    }
+    let sign = if text.starts_with('-') { -1 } else { 1 };
+    let digits: String = text.chars().filter(|c| c.is_ascii_digit()).collect();
+    let hours: i64 = digits.get(..2)?.parse().ok()?;
+    let minutes: i64 = digits.get(2..4)?.parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+fn signed_minutes(negative: bool, hours: u32, minutes: u32) -> i64 {
+    let total = (hours * 60 + minutes) as i64;
+    if negative {
+        -total
+    } else {
+        total
+    }
+}
+
+/// Read the device time from the clock command's output
+pub fn parse_clock(dialect: DeviceDialect, output: &str) -> Result<DeviceTime, String> {
+    match dialect {
+        DeviceDialect::Comware => parse_comware(output),
+        DeviceDialect::Ios => parse_ios(output),
+        _ => parse_iso(output),
+    }
+}
+
+fn parse_iso(output: &str) -> Result<DeviceTime, String> {
+    let caps = ISO_RE
+        .captures(output)
+        .ok_or_else(|| "no date and time in the output".to_string())?;
+    let zone = VRP_ZONE_RE.captures(output);
+    // The inline offset includes daylight saving, the zone line doesn't
+    let offset = match (caps.get(8), &zone) {
+        (Some(inline), _) => zone_offset(inline.as_str()).unwrap_or(0),
+        (None, Some(zone)) => signed_minutes(
+            zone.get(2).is_some_and(|s| s.as_str() == "-"),
+            number(zone.get(3)),
+            number(zone.get(4)),
+        ),
+        // Devices default to UTC, as does `date -u`
+        (None, None) => 0,
+    };
+    let utc_ms = utc_ms(
+        (
+            number(caps.get(1)) as i32,
+            number(caps.get(2)),
+            number(caps.get(3)),
+        ),
+        (
+            number(caps.get(4)),
+            number(caps.get(5)),
+            number(caps.get(6)),
+            millis(caps.get(7)),
+        ),
+        offset,
+    )
+    .ok_or_else(|| format!("invalid date `{}`", &caps[0]))?;
+    Ok(DeviceTime {
+        utc_ms,
+        zone: zone.map(|zone| zone[1].to_string()),
+    })
+}
+
+fn parse_comware(output: &str) -> Result<DeviceTime, String> {
+    let caps = COMWARE_RE
+        .captures(output)
+        .ok_or_else(|| "no date and time in the output".to_string())?;
+    let name = caps[5].to_string();
+    let offset = match COMWARE_ZONE_RE.captures(output) {
+        Some(zone) => signed_minutes(
+            zone[2].eq_ignore_ascii_case("minus"),
+            number(zone.get(3)),
+            number(zone.get(4)),
+        ),
+        None if is_utc(&name) => 0,
+        None => return Err(format!("no offset for time zone {}", name)),
+    };
+    let utc_ms = utc_ms(
+        (
+            number(caps.get(8)) as i32,
+            number(caps.get(6)),
+            number(caps.get(7)),
+        ),
+        (
+            number(caps.get(1)),
+            number(caps.get(2)),
+            number(caps.get(3)),
+            millis(caps.get(4)),
+        ),
+        offset,
+    )
+    .ok_or_else(|| format!("invalid date `{}`", &caps[0]))?;
+    Ok(DeviceTime {
+        utc_ms,
+        zone: Some(name),
+    })
+}
+
+fn parse_ios(output: &str) -> Result<DeviceTime, String> {
+    let caps = IOS_RE
+        .captures(output)
+        .ok_or_else(|| "no date and time in the output".to_string())?;
+    // `*` marks a clock that is not authoritative, normally never set
+    if &caps[1] == "*" {
+        return Err("the clock is not authoritative (`*`)".to_string());
+    }
+    let name = caps[6].to_string();
+    // IOS prints only an abbreviation, which can mean several offsets
+    if !is_utc(&name) {
+        return Err(format!("no offset for time zone {}", name));
+    }
+    let month = MONTHS
+        .iter()
+        .position(|m| caps[7].eq_ignore_ascii_case(m))
+        .ok_or_else(|| format!("unknown month {}", &caps[7]))? as u32
+        + 1;
+    let utc_ms = utc_ms(
+        (number(caps.get(9)) as i32, month, number(caps.get(8))),
+        (
+            number(caps.get(2)),
+            number(caps.get(3)),
+            number(caps.get(4)),
+            millis(caps.get(5)),
+        ),
+        0,
+    )
+    .ok_or_else(|| format!("invalid date `{}`", &caps[0]))?;
+    Ok(DeviceTime {
+        utc_ms,
+        zone: Some(name),
+    })
+}
+
+fn is_utc(zone: &str) -> bool {
+    zone.eq_ignore_ascii_case("utc") || zone.eq_ignore_ascii_case("gmt")
+}
+
+/// Offset of the local clock from an SNTP reply, sent at `sent_ms` and
+/// received at `received_ms` local time
+fn sntp_offset(reply: &[u8], sent_ms: i64, received_ms: i64) -> Option<i64> {
+    if reply.len() < 48 || reply[0] & 0x07 != 4 || reply[1] == 0 {
+        return None;
+    }
+    let timestamp = |at: usize| {
+        let seconds = u32::from_be_bytes(reply[at..at + 4].try_into().unwrap()) as i64;
+        let fraction = u32::from_be_bytes(reply[at + 4..at + 8].try_into().unwrap()) as i64;
+        (seconds - NTP_UNIX_OFFSET) * 1000 + ((fraction * 1000) >> 32)
+    };
+    let server_received = timestamp(32);
+    let server_sent = timestamp(40);
+    Some(((server_received - sent_ms) + (server_sent - received_ms)) / 2)
+}
+
+/// How far the local clock is behind `server` (`host` or `host:port`),
+/// in milliseconds
+pub async fn ntp_offset(server: &str) -> io::Result<i64> {
+    let addr = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:{}", server, NTP_PORT)
+    };
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(&addr).await?;
+
+    // Version 4, client mode
+    let mut request = [0u8; 48];
+    request[0] = 0x23;
+    let sent_ms = chrono::Utc::now().timestamp_millis();
+    socket.send(&request).await?;
+    let mut reply = [0u8; 64];
+    let len = tokio::time::timeout(NTP_TIMEOUT, socket.recv(&mut reply))
+        .await
+        .map_err(|_| {
+            io::Error::new(io::ErrorKind::TimedOut, format!("no reply from {}", addr))
+        })??;
+    let received_ms = chrono::Utc::now().timestamp_millis();
+    sntp_offset(&reply[..len], sent_ms, received_ms).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid reply from {}", addr),
+        )
+    })
+}
+
+/// One device's clock against the reference time
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceClock {
+    pub session_id: String,
+    pub host: String,
+    /// Prompt hostname, or the host
+    pub name: String,
+    /// First line of the clock output, as printed
+    pub printed: Option<String>,
+    pub zone: Option<String>,
+    /// Device time, Unix milliseconds
+    pub device_ms: Option<i64>,
+    /// Device time minus reference time; positive when the device is ahead
+    pub offset_ms: Option<i64>,
+    /// Half the command's round trip, plus the printed time's resolution
+    pub uncertainty_ms: Option<i64>,
+    /// The offset is at least `SKEWED_MS`
+    pub skewed: bool,
+    pub error: Option<String>,
+}
+
+/// Result of `audit_clocks`
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockAudit {
+    pub ntp_server: Option<String>,
+    /// How far the local clock is behind the NTP server
+    pub local_offset_ms: Option<i64>,
+    pub ntp_error: Option<String>,
+    /// Unix milliseconds of the audit
+    pub checked_at: i64,
+    /// In the order of the selected sessions
+    pub devices: Vec<DeviceClock>,
+    /// Largest offset in either direction
+    pub max_skew_ms: Option<i64>,
+}
+
+async fn check_device(
+    manager: &SessionManager,
+    session_id: &str,
+    local_offset_ms: i64,
+    timeout: Duration,
+) -> DeviceClock {
+    let mut device = DeviceClock {
+        session_id: session_id.to_string(),
+        host: String::new(),
+        name: session_id.to_string(),
+        printed: None,
+        zone: None,
+        device_ms: None,
+        offset_ms: None,
+        uncertainty_ms: None,
+        skewed: false,
+        error: None,
+    };
+    let Some(handle) = manager.get(session_id) else {
+        device.error = Some("session not found".to_string());
+        return device;
+    };
+    let dialect = handle.config.dialect;
+    device.host = handle.config.host.clone();
+    device.name = device.host.clone();
+    drop(handle);
+
+    let Some(command) = clock_command(dialect) else {
+        device.error = Some(format!("no clock command for {:?} devices", dialect));
+        return device;
+    };
+    let sent_ms = chrono::Utc::now().timestamp_millis();
+    let output = match command::run_command(manager, session_id, command, timeout).await {
+        Ok(output) => output,
+        Err(e) => {
+            device.error = Some(format!("`{}` failed: {}", command, e));
+            return device;
+        }
+    };
+    let received_ms = chrono::Utc::now().timestamp_millis();
+    if let Some(name) = prompt_hostname(&output.prompt) {
+        device.name = name;
+    }
+    if let Some(line) = command::rejection(&output.output) {
+        device.error = Some(format!("`{}` rejected: {}", command, line));
+        return device;
+    }
+    device.printed = output
+        .output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string);
+    match parse_clock(dialect, &output.output) {
+        Ok(time) => {
+            let reference = (sent_ms + received_ms) / 2 + local_offset_ms;
+            let offset = time.utc_ms - reference;
+            device.zone = time.zone;
+            device.device_ms = Some(time.utc_ms);
+            device.offset_ms = Some(offset);
+            device.uncertainty_ms = Some((received_ms - sent_ms) / 2 + 1000);
+            device.skewed = offset.abs() >= SKEWED_MS;
+        }
+        Err(e) => device.error = Some(e),
+    }
+    device
+}
+
+/// Check the clocks of all sessions at once
+pub async fn audit(
+    manager: &Arc<SessionManager>,
+    session_ids: &[String],
+    ntp_server: Option<String>,
+    timeout: Duration,
+    job: &JobControl,
+) -> ClockAudit {
+    let (local_offset_ms, ntp_error) = match &ntp_server {
+        Some(server) => match ntp_offset(server).await {
+            Ok(offset) => (Some(offset), None),
+            Err(e) => {
+                warn!(server = %server, error = %e, "NTP query failed, using the local clock");
+                (None, Some(e.to_string()))
+            }
+        },
+        None => (None, None),
+    };
+    let total = session_ids.len() as u64;
+    let mut tasks = JoinSet::new();
+    for (index, session_id) in session_ids.iter().cloned().enumerate() {
+        let manager = Arc::clone(manager);
+        let offset = local_offset_ms.unwrap_or(0);
+        tasks.spawn(async move {
+            (
+                index,
+                check_device(&manager, &session_id, offset, timeout).await,
+            )
+        });
+    }
+
+    let mut devices: Vec<Option<DeviceClock>> = vec![None; session_ids.len()];
+    let mut completed = 0;
+    loop {
+        let joined = tokio::select! {
+            joined = tasks.join_next() => joined,
+            _ = job.cancelled() => break,
+        };
+        let Some(joined) = joined else {
+            break;
+        };
+        let Ok((index, device)) = joined else {
+            warn!(job_id = %job.id(), "Clock task panicked");
+            continue;
+        };
+        completed += 1;
+        job.progress(completed, total, Some(device.name.clone()));
+        devices[index] = Some(device);
+    }
+
+    let devices: Vec<DeviceClock> = devices.into_iter().flatten().collect();
+    let max_skew_ms = devices
+        .iter()
+        .filter_map(|device| device.offset_ms)
+        .max_by_key(|offset| offset.abs());
+    info!(
+        sessions = session_ids.len(),
+        skewed = devices.iter().filter(|d| d.skewed).count(),
+        "Clock audit finished"
+    );
+    ClockAudit {
+        ntp_server,
+        local_offset_ms,
+        ntp_error,
+        checked_at: chrono::Utc::now().timestamp_millis(),
+        devices,
+        max_skew_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_clocks_and_sntp() {
+        // 2024-01-15 02:30:45 UTC
+        let utc = 1_705_285_845_000;
+
+        let vrp = "2024-01-15 10:30:45+08:00\nMonday\nTime Zone(China-Standard-Time) : UTC+08:00\n";
+        let time = parse_clock(DeviceDialect::Vrp, vrp).unwrap();
+        assert_eq!(time.utc_ms, utc);
+        assert_eq!(time.zone.as_deref(), Some("China-Standard-Time"));
+
+        // Older VRP: offset only in the zone line
+        let vrp = "2024-01-14 21:30:45\nSunday\nTime Zone(EST) : UTC-05:00\n";
+        assert_eq!(parse_clock(DeviceDialect::Vrp, vrp).unwrap().utc_ms, utc);
+
+        let comware = "10:30:45.250 Beijing Mon 01/15/2024\nTime Zone : Beijing add 08:00:00\n";
+        assert_eq!(
+            parse_clock(DeviceDialect::Comware, comware).unwrap().utc_ms,
+            utc + 250
+        );
+
+        let ios = "02:30:45.5 UTC Mon Jan 15 2024\n";
+        assert_eq!(
+            parse_clock(DeviceDialect::Ios, ios).unwrap().utc_ms,
+            utc + 500
+        );
+        assert!(parse_clock(DeviceDialect::Ios, "*02:30:45.5 UTC Mon Jan 15 2024").is_err());
+        assert!(parse_clock(DeviceDialect::Ios, "02:30:45.5 CST Mon Jan 15 2024").is_err());
+
+        assert_eq!(
+            parse_clock(DeviceDialect::Linux, "2024-01-15 02:30:45\n")
+                .unwrap()
+                .utc_ms,
+            utc
+        );
+        assert!(parse_clock(DeviceDialect::Linux, "date: invalid option").is_err());
+
+        // Server clock 1.5s ahead, 40ms each way
+        let mut reply = [0u8; 48];
+        reply[0] = 0x24;
+        reply[1] = 2;
+        let server_ms = utc + 1500 + 40;
+        let seconds = (server_ms / 1000 + NTP_UNIX_OFFSET) as u32;
+        let fraction = (((server_ms % 1000) << 32) / 1000) as u32 + 1;
+        for at in [32, 40] {
+            reply[at..at + 4].copy_from_slice(&seconds.to_be_bytes());
+            reply[at + 4..at + 8].copy_from_slice(&fraction.to_be_bytes());
+        }
+        assert_eq!(sntp_offset(&reply, utc, utc + 80), Some(1500));
+        // Kiss-of-death (stratum 0)
+        reply[1] = 0;
+        assert_eq!(sntp_offset(&reply, utc, utc + 80), None);
+    }
+}
@@ -0,0 +1,389 @@
+//! Request/response use of an interactive session: send a command and
+//! collect its output up to the next prompt.
+//!
+//! Output is taken from the session's output tap (`SessionManager::
+//! subscribe_output`), so it is the same text the terminal shows, with
+//! escape sequences, CRs and pager prompts removed.
+
+use crate::scrollback::ANSI_RE;
+use crate::session::{SessionError, SessionManager};
+use bytes::Bytes;
+use regex::Regex;
+use serde::Serialize;
+use std::sync::LazyLock;
+use tokio::sync::broadcast;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long `run_command` waits for the prompt when no timeout is given
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A prompt at the very end of the output: `<Huawei>`, `[Huawei-Vlanif10]`,
+/// `Router#`, `root@board:~$`, `[root@board ~]#`
+pub static PROMPT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|\n)(<[^<>\n]+>|\[[^\[\]\n]+\]|[\w.@:~/()\[\] -]*[#$%>]) ?\z").unwrap()
+});
+
+/// A login prompt or, once signed in, a CLI prompt
+static SIGN_IN_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?i:(?:^|\n)[^\n]*?(?:(?P<user>user ?name|login)|(?P<password>password)) ?: ?\z)|{}",
+        PROMPT_RE.as_str()
+    ))
+    .unwrap()
+});
+
+/// Confirmation prompts: `Continue? [Y/N]:`, `Save? [yes/no]:`,
+/// `Proceed with reload? [confirm]`, `Destination filename [x.bin]?`
+pub static CONFIRM_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:\[(?:y/n|yes/no)\]|\[confirm\]|filename \[[^\]\n]*\]\?)\s*:?\s*\z").unwrap()
+});
+
+/// A confirmation prompt or a CLI prompt
+pub static RESPONSE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!("{}|{}", CONFIRM_RE.as_str(), PROMPT_RE.as_str())).unwrap()
+});
+
+/// A line of the CLI rejecting a command
+static REJECTED_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?m)^\s*(?:Error:|%\s*(?:Invalid|Incomplete|Ambiguous|Error|Unrecognized)).*$")
+        .unwrap()
+});
+
+// VRP/Comware and IOS pagers, which the transport answers, and the
+// back-blank-back sequence that erases them once answered
+static PAGER_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[ \t]*-{2,4}\s*More\s*-{2,4} ?|\x1b\[\d+D +\x1b\[\d+D").unwrap());
+
+/// Result of `OutputWatcher::expect`
+#[derive(Debug, Clone)]
+pub struct Expected {
+    /// Output between the previous match and this one
+    pub before: String,
+    pub matched: String,
+}
+
+/// Result of `run_command`
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandOutput {
+    pub command: String,
+    /// Output without the echoed command and the final prompt
+    pub output: String,
+    pub prompt: String,
+    pub duration_ms: u64,
+}
+
+/// Cleaned session output received since the watcher was created, consumed
+/// by successive `expect` calls
+pub struct OutputWatcher {
+    session_id: String,
+    rx: broadcast::Receiver<Bytes>,
+    /// Incomplete escape sequence at the end of the last chunk
+    carry: String,
+    text: String,
+}
+
+impl OutputWatcher {
+    /// Watch output from now on; subscribe before sending what it answers
+    pub fn new(manager: &SessionManager, session_id: &str) -> Result<Self, SessionError> {
+        Ok(Self::from_receiver(
+            session_id,
+            manager.subscribe_output(session_id)?,
+        ))
+    }
+
+    fn from_receiver(session_id: &str, rx: broadcast::Receiver<Bytes>) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            rx,
+            carry: String::new(),
+            text: String::new(),
+        }
+    }
+
+    /// Wait until `pattern` matches the output after the previous match
+    pub async fn expect(
+        &mut self,
+        pattern: &Regex,
+        timeout: Duration,
+    ) -> Result<Expected, SessionError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(found) = pattern.find(&self.text) {
+                let expected = Expected {
+                    before: self.text[..found.start()].to_string(),
+                    matched: found.as_str().to_string(),
+                };
+                self.text.drain(..found.end());
+                return Ok(expected);
+            }
+
+            match tokio::time::timeout_at(deadline, self.rx.recv()).await {
+                Ok(Ok(chunk)) => self.push(&String::from_utf8_lossy(&chunk)),
+                Ok(Err(broadcast::error::RecvError::Lagged(missed))) => {
+                    warn!(session_id = %self.session_id, missed, "Output watcher fell behind");
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => {
+                    return Err(SessionError::ChannelError("session closed".to_string()));
+                }
+                Err(_) => {
+                    return Err(SessionError::Timeout(format!(
+                        "no match for `{}` within {}s",
+                        pattern.as_str(),
+                        timeout.as_secs()
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Append a chunk, holding back an escape sequence cut off at its end
+    fn push(&mut self, chunk: &str) {
+        self.carry.push_str(chunk);
+        let complete = match self.carry.rfind('\x1b') {
+            Some(at)
+                if ANSI_RE
+                    .find_at(&self.carry, at)
+                    .is_none_or(|m| m.start() != at) =>
+            {
+                at
+            }
+            _ => self.carry.len(),
+        };
+        let rest = self.carry.split_off(complete);
+        let unpaged = PAGER_RE.replace_all(&self.carry, "");
+        self.text.push_str(&ANSI_RE.replace_all(&unpaged, ""));
+        self.carry = rest;
+    }
+}
+
+/// Send `command` and return its output once the device shows a prompt
+/// again. The command is sent with a CR, translated by the session's newline
+/// mode.
+pub async fn run_command(
+    manager: &SessionManager,
+    session_id: &str,
+    command: &str,
+    timeout: Duration,
+) -> Result<CommandOutput, SessionError> {
+    let started = Instant::now();
+    let mut watcher = OutputWatcher::new(manager, session_id)?;
+    manager
+        .send_data(session_id, format!("{}\r", command).into_bytes())
+        .await?;
+    let expected = watcher.expect(&PROMPT_RE, timeout).await?;
+    manager.metrics().record_command(started.elapsed());
+    debug!(session_id = %session_id, command, bytes = expected.before.len(), "Command finished");
+
+    Ok(CommandOutput {
+        command: command.to_string(),
+        output: strip_echo(&expected.before, command),
+        prompt: expected.matched.trim().to_string(),
+        duration_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// Answer username and password prompts until the device shows a CLI
+/// prompt, which is returned. Sessions that authenticate in the transport
+/// (SSH) usually go straight to the prompt.
+pub async fn login(
+    watcher: &mut OutputWatcher,
+    manager: &SessionManager,
+    session_id: &str,
+    username: &str,
+    password: &str,
+    timeout: Duration,
+) -> Result<String, SessionError> {
+    let deadline = Instant::now() + timeout;
+    let (mut sent_user, mut sent_password) = (false, false);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let expected = watcher.expect(&SIGN_IN_RE, remaining).await?;
+        let captures = SIGN_IN_RE
+            .captures(&expected.matched)
+            .expect("matched text matches");
+        // A second prompt for the same thing means it was refused
+        let reply = if captures.name("user").is_some() && !sent_user {
+            sent_user = true;
+            username
+        } else if captures.name("password").is_some() && !sent_password {
+            sent_password = true;
+            password
+        } else if captures.name("user").is_some() || captures.name("password").is_some() {
+            return Err(SessionError::AuthenticationFailed(
+                "login prompt repeated".to_string(),
+            ));
+        } else {
+            return Ok(expected.matched.trim().to_string());
+        };
+        manager
+            .send_data(session_id, format!("{}\r", reply).into_bytes())
+            .await?;
+    }
+}
+
+/// Reply to a confirmation prompt
+pub fn confirmation_reply(prompt: &str) -> &'static str {
+    let prompt = prompt.to_ascii_lowercase();
+    if prompt.contains("[yes/no]") {
+        "yes\r"
+    } else if prompt.contains("[y/n]") {
+        "y\r"
+    } else {
+        // `[confirm]` and default file names take Enter
+        "\r"
+    }
+}
+
+/// Send `command`, answering confirmations and each prompt matching one of
+/// `replies` (patterns anchored at the end, like `password: ?\z`) with its
+/// text, and return the output once a CLI prompt follows. Replies never
+/// appear in the output.
+pub async fn run_answering(
+    manager: &SessionManager,
+    session_id: &str,
+    command: &str,
+    replies: &[(&Regex, &str)],
+    timeout: Duration,
+) -> Result<String, SessionError> {
+    let with_replies;
+    let response: &Regex = if replies.is_empty() {
+        &RESPONSE_RE
+    } else {
+        // Groups keep each pattern's inline flags to itself
+        let mut patterns: Vec<String> = replies
+            .iter()
+            .map(|(re, _)| format!("(?:{})", re.as_str()))
+            .collect();
+        patterns.push(format!("(?:{})", RESPONSE_RE.as_str()));
+        with_replies = Regex::new(&patterns.join("|")).expect("reply patterns are valid regexes");
+        &with_replies
+    };
+
+    let started = Instant::now();
+    let deadline = started + timeout;
+    let mut watcher = OutputWatcher::new(manager, session_id)?;
+    manager
+        .send_data(session_id, format!("{}\r", command).into_bytes())
+        .await?;
+    let mut output = String::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let expected = watcher.expect(response, remaining).await?;
+        output.push_str(&expected.before);
+        let reply = match replies
+            .iter()
+            .find(|(re, _)| re.is_match(&expected.matched))
+        {
+            Some((_, text)) => format!("{}\r", text),
+            None if CONFIRM_RE.is_match(&expected.matched) => {
+                confirmation_reply(&expected.matched).to_string()
+            }
+            None => {
+                manager.metrics().record_command(started.elapsed());
+                return Ok(output.trim().to_string());
+            }
+        };
+        output.push_str(&expected.matched);
+        manager.send_data(session_id, reply.into_bytes()).await?;
+    }
+}
+
+/// The first line of `output` where the CLI rejected a command
+pub fn rejection(output: &str) -> Option<&str> {
+    REJECTED_RE.find(output).map(|line| line.as_str().trim())
+}
+
+/// Drop the echoed command line and trailing whitespace
+fn strip_echo(output: &str, command: &str) -> String {
+    let output = match output.split_once('\n') {
+        Some((first, rest)) if first.trim_end().ends_with(command.trim()) => rest,
+        _ => output,
+    };
+    output.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watcher() -> (broadcast::Sender<Bytes>, OutputWatcher) {
+        let (tx, rx) = broadcast::channel(16);
+        (tx, OutputWatcher::from_receiver("test", rx))
+    }
+
+    #[tokio::test]
+    async fn test_expect_prompt_across_chunks() {
+        let (tx, mut watcher) = watcher();
+        for chunk in [
+            "display clock\r\n2024-05-01 14:30:05\r\n  ---- More ----\x1b[",
+            "16D                \x1b[16DUTC\r\n<Hua",
+            "wei>",
+        ] {
+            tx.send(Bytes::from(chunk)).unwrap();
+        }
+
+        let expected = watcher
+            .expect(&PROMPT_RE, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(expected.matched.trim(), "<Huawei>");
+        assert_eq!(
+            strip_echo(&expected.before, "display clock"),
+            "2024-05-01 14:30:05\nUTC"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expect_timeout_and_close() {
+        let (tx, mut watcher) = watcher();
+        tx.send(Bytes::from("still running")).unwrap();
+        assert!(matches!(
+            watcher.expect(&PROMPT_RE, Duration::from_millis(20)).await,
+            Err(SessionError::Timeout(_))
+        ));
+
+        drop(tx);
+        assert!(matches!(
+            watcher.expect(&PROMPT_RE, Duration::from_secs(1)).await,
+            Err(SessionError::ChannelError(_))
+        ));
+    }
+
+    #[test]
+    fn test_prompt_patterns() {
+        for prompt in [
+            "<Huawei>",
+            "[~HUAWEI-GigabitEthernet0/0/1]",
+            "Router#",
+            "root@board:~$ ",
+            "[root@board ~]# ",
+        ] {
+            assert!(
+                PROMPT_RE.is_match(&format!("output\n{}", prompt)),
+                "{}",
+                prompt
+            );
+        }
+        assert!(!PROMPT_RE.is_match("output\nnot a prompt"));
+
+        let user = SIGN_IN_RE.captures("Huawei\r\nUsername:").unwrap();
+        assert!(user.name("user").is_some());
+        let password = SIGN_IN_RE.captures("\nroot@board's password: ").unwrap();
+        assert!(password.name("password").is_some());
+        let prompt = SIGN_IN_RE.captures("Info: last login\n<Huawei>").unwrap();
+        assert!(prompt.name("user").is_none() && prompt.name("password").is_none());
+
+        for (prompt, reply) in [
+            ("System will reboot! Continue? [Y/N]:", "y\r"),
+            ("Save? [yes/no]: ", "yes\r"),
+            ("Proceed with reload? [confirm]", "\r"),
+            ("Destination filename [c2900-15.6.bin]? ", "\r"),
+        ] {
+            assert!(CONFIRM_RE.is_match(prompt), "{}", prompt);
+            assert_eq!(confirmation_reply(prompt), reply);
+        }
+        assert!(!CONFIRM_RE.is_match("<Huawei>"));
+        assert!(RESPONSE_RE.is_match("\n<Huawei>"));
+    }
+}
@@ -0,0 +1,371 @@
+//! Compliance rules evaluated against device configs and command output.
+//!
+//! A rule asserts that some line must (or must not) match a pattern. With
+//! a `section` pattern the assertion applies to every matching config
+//! section instead: the header line plus the indented lines under it, as in
+//! VRP `interface GigabitEthernet0/0/1` blocks.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ComplianceError {
+    #[error("Rule {rule}: {message}")]
+    InvalidRule { rule: String, message: String },
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<ComplianceError> for String {
+    fn from(err: ComplianceError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Assertion {
+    MustContain,
+    MustNotContain,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    #[default]
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub id: String,
+    #[serde(default)]
+    pub description: String,
+    pub assert: Assertion,
+    /// Matched against each line, trimmed
+    pub pattern: String,
+    /// `pattern` is a regex rather than a literal substring
+    #[serde(default)]
+    pub regex: bool,
+    /// Regex selecting section header lines; None checks the whole text
+    #[serde(default)]
+    pub section: Option<String>,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+/// A line that made a rule pass or fail; `line` is 1-based
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Evidence {
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleResult {
+    pub rule_id: String,
+    pub severity: Severity,
+    pub passed: bool,
+    pub message: String,
+    /// Offending lines of a `must_not_contain` rule, section headers missing
+    /// a `must_contain` line
+    pub evidence: Vec<Evidence>,
+}
+
+/// Compliance of one device
+#[derive(Debug, Clone, Serialize)]
+pub struct ComplianceReport {
+    pub device_id: String,
+    /// Snapshot id or command the text came from
+    pub source: String,
+    /// No failed rule of `error` severity
+    pub passed: bool,
+    pub failed: usize,
+    pub results: Vec<RuleResult>,
+    /// Set when there was nothing to check
+    pub error: Option<String>,
+}
+
+impl ComplianceReport {
+    /// A device that could not be checked
+    pub fn unavailable(device_id: &str, source: &str, error: String) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            source: source.to_string(),
+            passed: false,
+            failed: 0,
+            results: Vec::new(),
+            error: Some(error),
+        }
+    }
+}
+
+/// Rules with their patterns compiled
+pub struct RuleSet {
+    rules: Vec<(Rule, Regex, Option<Regex>)>,
+}
+
+impl RuleSet {
+    pub fn new(rules: Vec<Rule>) -> Result<Self, ComplianceError> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let invalid = |message: String| ComplianceError::InvalidRule {
+                rule: rule.id.clone(),
+                message,
+            };
+            if rule.pattern.trim().is_empty() {
+                return Err(invalid("empty pattern".to_string()));
+            }
+            let source = if rule.regex {
+                rule.pattern.clone()
+            } else {
+                regex::escape(rule.pattern.trim())
+            };
+            let pattern =
+                Regex::new(&source).map_err(|e| invalid(format!("invalid pattern: {}", e)))?;
+            let section = rule
+                .section
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| invalid(format!("invalid section: {}", e)))?;
+            compiled.push((rule, pattern, section));
+        }
+        Ok(Self { rules: compiled })
+    }
+
+    pub fn check(&self, device_id: &str, source: &str, text: &str) -> ComplianceReport {
+        let lines: Vec<&str> = text.lines().collect();
+        let results: Vec<RuleResult> = self
+            .rules
+            .iter()
+            .map(|(rule, pattern, section)| check_rule(rule, pattern, section.as_ref(), &lines))
+            .collect();
+        let failed = results.iter().filter(|r| !r.passed).count();
+        ComplianceReport {
+            device_id: device_id.to_string(),
+            source: source.to_string(),
+            passed: !results
+                .iter()
+                .any(|r| !r.passed && r.severity == Severity::Error),
+            failed,
+            results,
+            error: None,
+        }
+    }
+}
+
+/// Rules from a JSON file holding an array of `Rule`, validated
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>, ComplianceError> {
+    let rules: Vec<Rule> = serde_json::from_slice(&std::fs::read(path)?)?;
+    RuleSet::new(rules.clone())?;
+    Ok(rules)
+}
+
+fn check_rule(rule: &Rule, pattern: &Regex, section: Option<&Regex>, lines: &[&str]) -> RuleResult {
+    let matches = |range: std::ops::Range<usize>| -> Vec<Evidence> {
+        range
+            .filter(|&i| pattern.is_match(lines[i].trim()))
+            .map(|i| Evidence {
+                line: i + 1,
+                text: lines[i].trim_end().to_string(),
+            })
+            .collect()
+    };
+    let scopes: Vec<std::ops::Range<usize>> = match section {
+        None => std::iter::once(0..lines.len()).collect(),
+        Some(section) => sections(section, lines),
+    };
+
+    let (passed, evidence) = match rule.assert {
+        Assertion::MustNotContain => {
+            let found: Vec<Evidence> = scopes.into_iter().flat_map(matches).collect();
+            (found.is_empty(), found)
+        }
+        Assertion::MustContain => {
+            let missing: Vec<std::ops::Range<usize>> = scopes
+                .into_iter()
+                .filter(|scope| matches(scope.clone()).is_empty())
+                .collect();
+            // Sections missing the line, by their header
+            let headers = missing
+                .iter()
+                .filter(|_| section.is_some())
+                .map(|scope| Evidence {
+                    line: scope.start + 1,
+                    text: lines[scope.start].trim_end().to_string(),
+                })
+                .collect();
+            (missing.is_empty(), headers)
+        }
+    };
+
+    let label = if rule.description.is_empty() {
+        format!("`{}`", rule.pattern)
+    } else {
+        rule.description.clone()
+    };
+    let message = match (rule.assert, passed, section.is_some()) {
+        (_, true, _) => format!("{}: ok", label),
+        (Assertion::MustContain, false, false) => format!("{}: missing", label),
+        (Assertion::MustContain, false, true) => {
+            format!("{}: missing in {} section(s)", label, evidence.len())
+        }
+        (Assertion::MustNotContain, false, _) => {
+            format!("{}: found on {} line(s)", label, evidence.len())
+        }
+    };
+    RuleResult {
+        rule_id: rule.id.clone(),
+        severity: rule.severity,
+        passed,
+        message,
+        evidence,
+    }
+}
+
+/// Line ranges of the sections whose unindented header matches `header`;
+/// a section runs until the next unindented line
+fn sections(header: &Regex, lines: &[&str]) -> Vec<std::ops::Range<usize>> {
+    let top_level = |line: &str| !line.is_empty() && !line.starts_with([' ', '\t']);
+    let mut ranges = Vec::new();
+    for (start, line) in lines.iter().enumerate() {
+        if top_level(line) && header.is_match(line.trim_end()) {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|l| top_level(l))
+                .map_or(lines.len(), |i| start + 1 + i);
+            ranges.push(start..end);
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+sysname core-1
+#
+telnet server enable
+stelnet server enable
+#
+interface GigabitEthernet0/0/1
+ description uplink
+ port link-type trunk
+#
+interface GigabitEthernet0/0/2
+ port link-type access
+#
+";
+
+    fn rule(id: &str, assert: Assertion, pattern: &str, section: Option<&str>) -> Rule {
+        Rule {
+            id: id.to_string(),
+            description: String::new(),
+            assert,
+            pattern: pattern.to_string(),
+            regex: false,
+            section: section.map(str::to_string),
+            severity: Severity::Error,
+        }
+    }
+
+    #[test]
+    fn test_check_rules() {
+        let mut warn_only = rule(
+            "no-http",
+            Assertion::MustNotContain,
+            "http server enable",
+            None,
+        );
+        warn_only.severity = Severity::Warning;
+        let rules = RuleSet::new(vec![
+            rule("ssh", Assertion::MustContain, "stelnet server enable", None),
+            rule(
+                "no-telnet",
+                Assertion::MustNotContain,
+                "telnet server enable",
+                None,
+            ),
+            rule(
+                "describe",
+                Assertion::MustContain,
+                "description",
+                Some("^interface GigabitEthernet"),
+            ),
+            warn_only,
+        ])
+        .unwrap();
+        let report = rules.check("core-1", "1714570205000", CONFIG);
+
+        let results: Vec<(&str, bool)> = report
+            .results
+            .iter()
+            .map(|r| (r.rule_id.as_str(), r.passed))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("ssh", true),
+                // Literal patterns match anywhere in the line
+                ("no-telnet", false),
+                ("describe", false),
+                ("no-http", true)
+            ]
+        );
+        assert_eq!(
+            report.results[1].evidence,
+            [
+                Evidence {
+                    line: 3,
+                    text: "telnet server enable".to_string()
+                },
+                Evidence {
+                    line: 4,
+                    text: "stelnet server enable".to_string()
+                }
+            ]
+        );
+        assert_eq!(report.results[2].evidence[0].line, 10);
+        assert_eq!(report.failed, 2);
+        assert!(!report.passed);
+
+        let mut anchored = rule(
+            "no-telnet",
+            Assertion::MustNotContain,
+            "^telnet server enable$",
+            None,
+        );
+        anchored.regex = true;
+        let report = RuleSet::new(vec![anchored])
+            .unwrap()
+            .check("core-1", "", CONFIG);
+        assert_eq!(report.results[0].evidence.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let mut bad = rule("bad", Assertion::MustContain, "(", None);
+        bad.regex = true;
+        assert!(RuleSet::new(vec![bad]).is_err());
+        assert!(RuleSet::new(vec![rule("empty", Assertion::MustContain, " ", None)]).is_err());
+        assert!(RuleSet::new(vec![rule(
+            "section",
+            Assertion::MustContain,
+            "x",
+            Some("[")
+        )])
+        .is_err());
+        // Literal patterns are escaped
+        assert!(RuleSet::new(vec![rule("literal", Assertion::MustContain, "(", None)]).is_ok());
+    }
+}
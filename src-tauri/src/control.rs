@@ -0,0 +1,641 @@
+//! Local control API for external test tools.
+//!
+//! An HTTP server on 127.0.0.1, off until `start_control_api`, mirroring the
+//! session commands so pytest or a CI rig can drive the running app. Every
+//! request needs `Authorization: Bearer <token>`, or `?token=` for clients
+//! that can't set headers (`EventSource`). The token is generated on start
+//! unless one is given, and written with the address to `control.json` in
+//! the app data dir for test fixtures to pick up; only the user can read
+//! the file, and it is removed on stop.
+//!
+//! - `GET /sessions` - Open sessions
+//! - `POST /sessions` - Create one from a `SessionConfig` body, `{session_id}`
+//! - `DELETE /sessions/{id}` - Disconnect
+//! - `POST /sessions/{id}/input` - `{data}` sent as if typed
+//! - `POST /sessions/{id}/command` - `{command, timeout_ms}`, a `CommandOutput`
+//! - `GET /sessions/{id}/output` - Server-sent events: `output` with each
+//!   chunk as a JSON string, `closed` when the session ends
+//! - `GET /sessions/{id}/unacked` - With acked delivery, the emitted chunks
+//!   not acked yet as `[{seq, data}]`, oldest first
+//! - `POST /sessions/{id}/ack` - `{seq}`, releases chunks up to `seq`
+
+use crate::charset::utf8_incomplete_tail;
+use crate::command::{self, DEFAULT_COMMAND_TIMEOUT};
+use crate::session::{Protocol, SessionConfig, SessionError, SessionManager};
+use crate::settings::SettingsStore;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinSet;
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_PORT: u16 = 9470;
+
+/// Largest request, headers and body
+const MAX_REQUEST: usize = 1024 * 1024;
+
+/// Time a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Comment sent on idle output streams; also when a closed session is noticed
+const STREAM_PING: Duration = Duration::from_secs(15);
+
+#[derive(Error, Debug)]
+pub enum ControlError {
+    #[error("Control API is already running")]
+    AlreadyRunning,
+    #[error("Control API is not running")]
+    NotRunning,
+    #[error("Token must not be empty")]
+    EmptyToken,
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<ControlError> for String {
+    fn from(err: ControlError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ControlStatus {
+    pub running: bool,
+    pub addr: Option<String>,
+    pub token: Option<String>,
+}
+
+/// What the API drives
+#[derive(Clone)]
+pub struct ControlContext {
+    pub manager: Arc<SessionManager>,
+    pub settings: Arc<tokio::sync::Mutex<SettingsStore>>,
+}
+
+/// `GET /sessions` entry
+#[derive(Debug, Clone, Serialize)]
+pub struct ControlSession {
+    pub session_id: String,
+    pub host: String,
+    pub port: u16,
+    pub protocol: Protocol,
+}
+
+#[derive(Deserialize)]
+struct InputBody {
+    data: String,
+}
+
+/// `GET /sessions/{id}/unacked` entry
+#[derive(Debug, Clone, Serialize)]
+pub struct UnackedChunk {
+    pub seq: u64,
+    pub data: String,
+}
+
+#[derive(Deserialize)]
+struct AckBody {
+    seq: u64,
+}
+
+#[derive(Deserialize)]
+struct CommandBody {
+    command: String,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+struct Running {
+    status: ControlStatus,
+    /// Dropped to stop the server
+    _shutdown: watch::Sender<()>,
+}
+
+/// The server, started and stopped from the frontend
+pub struct ControlServer {
+    running: Mutex<Option<Running>>,
+    /// `control.json`, present while running
+    discovery: PathBuf,
+}
+
+impl ControlServer {
+    pub fn new(discovery: PathBuf) -> Self {
+        Self {
+            running: Mutex::new(None),
+            discovery,
+        }
+    }
+
+    /// Listen on `port` with `token`, or a new random token
+    pub async fn start(
+        self: &Arc<Self>,
+        port: u16,
+        token: Option<String>,
+        context: ControlContext,
+    ) -> Result<ControlStatus, ControlError> {
+        if self.running.lock().unwrap().is_some() {
+            return Err(ControlError::AlreadyRunning);
+        }
+        let token = match token {
+            Some(token) if token.trim().is_empty() => return Err(ControlError::EmptyToken),
+            Some(token) => token,
+            None => uuid::Uuid::new_v4().simple().to_string(),
+        };
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+        let status = ControlStatus {
+            running: true,
+            addr: listener.local_addr().ok().map(|a| a.to_string()),
+            token: Some(token.clone()),
+        };
+        if let Some(parent) = self.discovery.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_private(&self.discovery, &serde_json::to_vec_pretty(&status)?)?;
+
+        let (shutdown, stopped) = watch::channel(());
+        tokio::spawn(listen(listener, Arc::new(token), context, stopped));
+        info!(addr = ?status.addr, "Control API started");
+        *self.running.lock().unwrap() = Some(Running {
+            status: status.clone(),
+            _shutdown: shutdown,
+        });
+        Ok(status)
+    }
+
+    pub fn stop(&self) -> Result<(), ControlError> {
+        self.running
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(ControlError::NotRunning)?;
+        if let Err(e) = std::fs::remove_file(&self.discovery) {
+            warn!(path = %self.discovery.display(), error = %e, "Failed to remove control.json");
+        }
+        info!("Control API stopped");
+        Ok(())
+    }
+
+    pub fn status(&self) -> ControlStatus {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|r| r.status.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Write a file only the user can read: `control.json` holds the token
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    // The mode only applies to a file being created
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(data)
+}
+
+async fn listen(
+    listener: TcpListener,
+    token: Arc<String>,
+    context: ControlContext,
+    mut stopped: watch::Receiver<()>,
+) {
+    // Output streams run until the server stops
+    let mut clients = JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    debug!(error = %e, "Control accept failed");
+                    continue;
+                }
+            },
+            _ = stopped.changed() => break,
+        };
+        while clients.try_join_next().is_some() {}
+        let (token, context) = (Arc::clone(&token), context.clone());
+        clients.spawn(async move {
+            if let Err(e) = serve(stream, &token, &context).await {
+                debug!(error = %e, "Control request failed");
+            }
+        });
+    }
+    clients.shutdown().await;
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn authorized(&self, token: &str) -> bool {
+        let bearer = self
+            .authorization
+            .as_deref()
+            .and_then(|h| h.strip_prefix("Bearer "));
+        let query = self
+            .query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="));
+        bearer
+            .or(query)
+            .is_some_and(|given| same_token(given.trim(), token))
+    }
+}
+
+/// Compare without stopping at the first difference
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let read = async {
+        let head_end = loop {
+            if let Some(at) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                break at + 4;
+            }
+            let n = stream.read(&mut buf).await?;
+            if n == 0 || data.len() + n > MAX_REQUEST {
+                return Ok(None);
+            }
+            data.extend_from_slice(&buf[..n]);
+        };
+
+        let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+        let mut lines = head.lines();
+        let mut parts = lines.next().unwrap_or("").split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut length = 0;
+        let mut authorization = None;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+        if head_end + length > MAX_REQUEST {
+            return Ok(None);
+        }
+        while data.len() < head_end + length {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+        Ok(Some(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            authorization,
+            body: data[head_end..head_end + length].to_vec(),
+        }))
+    };
+    tokio::time::timeout(REQUEST_TIMEOUT, read)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))?
+}
+
+#[derive(Debug, PartialEq)]
+enum Route<'a> {
+    ListSessions,
+    CreateSession,
+    Disconnect(&'a str),
+    Input(&'a str),
+    Command(&'a str),
+    Output(&'a str),
+    Unacked(&'a str),
+    Ack(&'a str),
+}
+
+/// Route of a request, or the status to answer with
+fn route<'a>(method: &str, path: &'a str) -> Result<Route<'a>, &'static str> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let route = match segments.as_slice() {
+        ["sessions"] => match method {
+            "GET" => Route::ListSessions,
+            "POST" => Route::CreateSession,
+            _ => return Err("405 Method Not Allowed"),
+        },
+        ["sessions", id] => match method {
+            "DELETE" => Route::Disconnect(id),
+            _ => return Err("405 Method Not Allowed"),
+        },
+        ["sessions", id, action] => match (method, *action) {
+            ("POST", "input") => Route::Input(id),
+            ("POST", "command") => Route::Command(id),
+            ("GET", "output") => Route::Output(id),
+            ("GET", "unacked") => Route::Unacked(id),
+            ("POST", "ack") => Route::Ack(id),
+            (_, "input" | "command" | "output" | "unacked" | "ack") => {
+                return Err("405 Method Not Allowed")
+            }
+            _ => return Err("404 Not Found"),
+        },
+        _ => return Err("404 Not Found"),
+    };
+    Ok(route)
+}
+
+/// Status and JSON body of a failed request
+type Failure = (&'static str, String);
+
+fn bad_request(e: impl ToString) -> Failure {
+    ("400 Bad Request", e.to_string())
+}
+
+fn session_failure(e: SessionError) -> Failure {
+    match e {
+        SessionError::NotFound(_) => ("404 Not Found", e.to_string()),
+        SessionError::InvalidConfig(_) => ("400 Bad Request", e.to_string()),
+        e => ("500 Internal Server Error", e.to_string()),
+    }
+}
+
+async fn serve(mut stream: TcpStream, token: &str, context: &ControlContext) -> io::Result<()> {
+    let Some(request) = read_request(&mut stream).await? else {
+        return Ok(());
+    };
+    if !request.authorized(token) {
+        let body = serde_json::json!({ "error": "missing or wrong token" });
+        return respond(&mut stream, "401 Unauthorized", &body).await;
+    }
+    let route = match route(&request.method, &request.path) {
+        Ok(route) => route,
+        Err(status) => {
+            let body = serde_json::json!({ "error": status });
+            return respond(&mut stream, status, &body).await;
+        }
+    };
+    if let Route::Output(session_id) = route {
+        return stream_output(stream, session_id, &context.manager).await;
+    }
+    match handle(route, &request.body, context).await {
+        Ok(body) => respond(&mut stream, "200 OK", &body).await,
+        Err((status, error)) => {
+            respond(&mut stream, status, &serde_json::json!({ "error": error })).await
+        }
+    }
+}
+
+async fn handle(
+    route: Route<'_>,
+    body: &[u8],
+    context: &ControlContext,
+) -> Result<serde_json::Value, Failure> {
+    let manager = &context.manager;
+    let value = match route {
+        Route::ListSessions => {
+            let sessions: Vec<ControlSession> = manager
+                .list()
+                .iter()
+                .map(|handle| ControlSession {
+                    session_id: handle.id.clone(),
+                    host: handle.config.host.clone(),
+                    port: handle.config.port,
+                    protocol: handle.config.protocol,
+                })
+                .collect();
+            serde_json::to_value(sessions)
+        }
+        Route::CreateSession => {
+            let config: SessionConfig = serde_json::from_slice(body).map_err(bad_request)?;
+            let settings = context.settings.lock().await.get();
+            let session_id = crate::start_session(config, Arc::clone(manager), &settings)
+                .map_err(bad_request)?;
+            Ok(serde_json::json!({ "session_id": session_id }))
+        }
+        Route::Disconnect(session_id) => {
+            info!(session_id = %session_id, "Disconnecting session from the control API");
+            manager
+                .disconnect(session_id)
+                .await
+                .map_err(session_failure)?;
+            Ok(serde_json::Value::Null)
+        }
+        Route::Input(session_id) => {
+            let input: InputBody = serde_json::from_slice(body).map_err(bad_request)?;
+            manager
+                .send_data(session_id, input.data.into_bytes())
+                .await
+                .map_err(session_failure)?;
+            Ok(serde_json::Value::Null)
+        }
+        Route::Command(session_id) => {
+            let request: CommandBody = serde_json::from_slice(body).map_err(bad_request)?;
+            let timeout = request
+                .timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_COMMAND_TIMEOUT);
+            let output = command::run_command(manager, session_id, &request.command, timeout)
+                .await
+                .map_err(session_failure)?;
+            serde_json::to_value(output)
+        }
+        Route::Unacked(session_id) => {
+            let chunks: Vec<UnackedChunk> = manager
+                .unacked_output(session_id)
+                .await
+                .map_err(session_failure)?
+                .into_iter()
+                .map(|(seq, data)| UnackedChunk {
+                    seq,
+                    data: String::from_utf8_lossy(&data).into_owned(),
+                })
+                .collect();
+            serde_json::to_value(chunks)
+        }
+        Route::Ack(session_id) => {
+            let ack: AckBody = serde_json::from_slice(body).map_err(bad_request)?;
+            manager
+                .ack_output(session_id, ack.seq)
+                .await
+                .map_err(session_failure)?;
+            Ok(serde_json::Value::Null)
+        }
+        Route::Output(_) => unreachable!("streamed by serve"),
+    };
+    value.map_err(|e| ("500 Internal Server Error", e.to_string()))
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &serde_json::Value) -> io::Result<()> {
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Server-sent events frame of an output chunk; an incomplete UTF-8
+/// sequence at the end is carried into the next chunk
+fn output_event(carry: &mut Vec<u8>, chunk: &[u8]) -> Option<String> {
+    carry.extend_from_slice(chunk);
+    let complete = carry.len() - utf8_incomplete_tail(carry);
+    if complete == 0 {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&carry[..complete]).into_owned();
+    carry.drain(..complete);
+    Some(format!(
+        "event: output\ndata: {}\n\n",
+        serde_json::Value::String(text)
+    ))
+}
+
+async fn stream_output(
+    mut stream: TcpStream,
+    session_id: &str,
+    manager: &SessionManager,
+) -> io::Result<()> {
+    let mut output = match manager.subscribe_output(session_id) {
+        Ok(output) => output,
+        Err(e) => {
+            let (status, error) = session_failure(e);
+            return respond(&mut stream, status, &serde_json::json!({ "error": error })).await;
+        }
+    };
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+
+    let mut carry = Vec::new();
+    let mut ping = tokio::time::interval(STREAM_PING);
+    ping.tick().await;
+    loop {
+        let frame = tokio::select! {
+            received = output.recv() => match received {
+                Ok(chunk) => match output_event(&mut carry, &chunk) {
+                    Some(frame) => frame,
+                    None => continue,
+                },
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    format!("event: lagged\ndata: {}\n\n", missed)
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = ping.tick() => {
+                if manager.get(session_id).is_none() {
+                    break;
+                }
+                ": ping\n\n".to_string()
+            }
+        };
+        stream.write_all(frame.as_bytes()).await?;
+    }
+    stream.write_all(b"event: closed\ndata: null\n\n").await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes_auth_and_output_frames() {
+        assert_eq!(route("GET", "/sessions"), Ok(Route::ListSessions));
+        assert_eq!(route("POST", "/sessions/"), Ok(Route::CreateSession));
+        assert_eq!(route("DELETE", "/sessions/s1"), Ok(Route::Disconnect("s1")));
+        assert_eq!(
+            route("POST", "/sessions/s1/command"),
+            Ok(Route::Command("s1"))
+        );
+        assert_eq!(route("GET", "/sessions/s1/output"), Ok(Route::Output("s1")));
+        assert_eq!(route("POST", "/sessions/s1/ack"), Ok(Route::Ack("s1")));
+        assert_eq!(
+            route("GET", "/sessions/s1/ack"),
+            Err("405 Method Not Allowed")
+        );
+        assert_eq!(
+            route("GET", "/sessions/s1/input"),
+            Err("405 Method Not Allowed")
+        );
+        assert_eq!(route("GET", "/sessions/s1/other"), Err("404 Not Found"));
+        assert_eq!(route("GET", "/"), Err("404 Not Found"));
+
+        let request = |authorization: Option<&str>, query: &str| Request {
+            method: "GET".to_string(),
+            path: "/sessions".to_string(),
+            query: query.to_string(),
+            authorization: authorization.map(str::to_string),
+            body: Vec::new(),
+        };
+        assert!(request(Some("Bearer abc123"), "").authorized("abc123"));
+        assert!(request(None, "x=1&token=abc123").authorized("abc123"));
+        assert!(!request(Some("Bearer abc124"), "").authorized("abc123"));
+        assert!(!request(Some("abc123"), "").authorized("abc123"));
+        assert!(!request(None, "").authorized("abc123"));
+
+        // "设备" split inside its second character
+        let text = "<设备>\r\n".as_bytes();
+        let mut carry = Vec::new();
+        assert_eq!(
+            output_event(&mut carry, &text[..5]).unwrap(),
+            "event: output\ndata: \"<设\"\n\n"
+        );
+        assert_eq!(carry.len(), 1);
+        assert!(output_event(&mut carry, &text[5..6]).is_none());
+        assert_eq!(
+            output_event(&mut carry, &text[6..]).unwrap(),
+            "event: output\ndata: \"备>\\r\\n\"\n\n"
+        );
+        assert!(carry.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_discovery_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("bspt-control-{}.json", uuid::Uuid::new_v4()));
+        // A file left by an older version keeps no wider mode
+        std::fs::write(&path, b"{}").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, b"{\"token\":\"abc123\"}").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(std::fs::read(&path).unwrap(), b"{\"token\":\"abc123\"}");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::info;
+use uuid::Uuid;
+
+/// How long `connect_profile` waits for the frontend to answer a prompt
+pub const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Error, Debug)]
+pub enum CredentialError {
+    #[error("Credential prompt cancelled")]
+    Cancelled,
+    #[error("No credentials supplied within {0}s")]
+    TimedOut(u64),
+    #[error("Failed to request credentials: {0}")]
+    EmitFailed(String),
+}
+
+impl From<CredentialError> for String {
+    fn from(err: CredentialError) -> String {
+        err.to_string()
+    }
+}
+
+/// Payload of the `credential-request` event
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialRequest {
+    /// Passed back to `provide_credentials` / `cancel_credentials`
+    pub request_id: String,
+    pub profile_id: String,
+    pub profile_name: String,
+    pub host: String,
+    /// Saved username, to prefill the prompt
+    pub username: String,
+}
+
+/// Answer to a credential request
+#[derive(Debug, Clone, Deserialize)]
+pub struct Credentials {
+    /// None keeps the profile's username
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password or one-time token
+    pub password: String,
+}
+
+/// Credential prompts waiting for the frontend, keyed by request id
+pub struct CredentialBroker {
+    pending: DashMap<String, oneshot::Sender<Credentials>>,
+}
+
+impl CredentialBroker {
+    pub fn new() -> Self {
+        Self {
+            pending: DashMap::new(),
+        }
+    }
+
+    /// Emit `credential-request` and wait up to `timeout` for the answer
+    pub async fn request(
+        &self,
+        app_handle: &AppHandle,
+        mut request: CredentialRequest,
+        timeout: Duration,
+    ) -> Result<Credentials, CredentialError> {
+        let (request_id, rx) = self.register();
+        request.request_id = request_id.clone();
+        info!(request_id = %request_id, profile = %request.profile_name, "Requesting credentials");
+        if let Err(e) = app_handle.emit("credential-request", &request) {
+            self.pending.remove(&request_id);
+            return Err(CredentialError::EmitFailed(e.to_string()));
+        }
+        self.wait(&request_id, rx, timeout).await
+    }
+
+    /// Resolve a pending request; false if it is unknown or already over
+    pub fn provide(&self, request_id: &str, credentials: Credentials) -> bool {
+        match self.pending.remove(request_id) {
+            Some((_, tx)) => tx.send(credentials).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn cancel(&self, request_id: &str) -> bool {
+        // Dropping the sender wakes the waiting connect with Cancelled
+        self.pending.remove(request_id).is_some()
+    }
+
+    fn register(&self) -> (String, oneshot::Receiver<Credentials>) {
+        let request_id = Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(request_id.clone(), tx);
+        (request_id, rx)
+    }
+
+    async fn wait(
+        &self,
+        request_id: &str,
+        rx: oneshot::Receiver<Credentials>,
+        timeout: Duration,
+    ) -> Result<Credentials, CredentialError> {
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(credentials)) => Ok(credentials),
+            Ok(Err(_)) => Err(CredentialError::Cancelled),
+            Err(_) => {
+                self.pending.remove(request_id);
+                Err(CredentialError::TimedOut(timeout.as_secs()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(password: &str) -> Credentials {
+        Credentials {
+            username: None,
+            password: password.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_provide_cancel_timeout() {
+        let broker = CredentialBroker::new();
+
+        let (id, rx) = broker.register();
+        assert!(broker.provide(&id, token("otp-123456")));
+        let credentials = broker.wait(&id, rx, PROMPT_TIMEOUT).await.unwrap();
+        assert_eq!(credentials.password, "otp-123456");
+        assert!(!broker.provide(&id, token("again")));
+
+        let (id, rx) = broker.register();
+        assert!(broker.cancel(&id));
+        assert!(matches!(
+            broker.wait(&id, rx, PROMPT_TIMEOUT).await,
+            Err(CredentialError::Cancelled)
+        ));
+
+        let (id, rx) = broker.register();
+        assert!(matches!(
+            broker.wait(&id, rx, Duration::from_millis(10)).await,
+            Err(CredentialError::TimedOut(_))
+        ));
+        assert!(!broker.cancel(&id));
+    }
+}
@@ -0,0 +1,132 @@
+//! Passphrase-based encryption shared by profile archives and the vault:
+//! scrypt derives an AES-256-GCM key, so a wrong passphrase and tampered
+//! data both fail authentication.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// scrypt cost (2^15 iterations, 32MB), the same as age uses for passphrases
+pub const LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Error, Debug)]
+pub enum CryptoError {
+    #[error("Invalid encrypted data: {0}")]
+    Invalid(String),
+    #[error("Wrong passphrase or corrupted data")]
+    Decrypt,
+}
+
+/// Derived key; kept instead of the passphrase while data stays unlocked
+pub type SecretKey = Key<Aes256Gcm>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    /// Base64
+    pub salt: String,
+}
+
+impl KdfParams {
+    /// Fresh random salt at the given cost
+    pub fn generate(log_n: u8) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            log_n,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt: STANDARD.encode(salt),
+        }
+    }
+
+    pub fn derive_key(&self, passphrase: &str) -> Result<SecretKey, CryptoError> {
+        if passphrase.is_empty() {
+            return Err(CryptoError::Invalid("passphrase is empty".to_string()));
+        }
+        let salt = decode("salt", &self.salt)?;
+        let params = scrypt::Params::new(self.log_n, self.r, self.p, 32)
+            .map_err(|e| CryptoError::Invalid(format!("scrypt parameters: {e}")))?;
+        let mut key = SecretKey::default();
+        scrypt::scrypt(passphrase.as_bytes(), &salt, &params, &mut key)
+            .map_err(|e| CryptoError::Invalid(format!("scrypt: {e}")))?;
+        Ok(key)
+    }
+}
+
+/// Ciphertext plus everything needed to decrypt it except the passphrase
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sealed {
+    pub kdf: KdfParams,
+    /// Base64
+    pub nonce: String,
+    /// Base64
+    pub ciphertext: String,
+}
+
+impl Sealed {
+    /// Encrypt with a key derived from `kdf`; a new nonce is drawn each time
+    pub fn seal(key: &SecretKey, kdf: KdfParams, plaintext: &[u8]) -> Result<Self, CryptoError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(key)
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| CryptoError::Invalid("encryption failed".to_string()))?;
+        Ok(Self {
+            kdf,
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+    }
+
+    pub fn open(&self, key: &SecretKey) -> Result<Vec<u8>, CryptoError> {
+        let nonce = decode("nonce", &self.nonce)?;
+        let ciphertext = decode("ciphertext", &self.ciphertext)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(CryptoError::Invalid("bad nonce length".to_string()));
+        }
+        Aes256Gcm::new(key)
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| CryptoError::Decrypt)
+    }
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, CryptoError> {
+    STANDARD
+        .decode(value)
+        .map_err(|_| CryptoError::Invalid(format!("{field} is not base64")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open_round_trip() {
+        // Low cost keeps the test fast in debug builds
+        let kdf = KdfParams::generate(4);
+        let key = kdf.derive_key("correct horse").unwrap();
+        let sealed = Sealed::seal(&key, kdf.clone(), b"device book").unwrap();
+        assert_eq!(sealed.open(&key).unwrap(), b"device book");
+
+        let wrong = kdf.derive_key("wrong").unwrap();
+        assert!(matches!(sealed.open(&wrong), Err(CryptoError::Decrypt)));
+
+        let mut tampered = sealed;
+        let mut bytes = STANDARD.decode(&tampered.ciphertext).unwrap();
+        bytes[0] ^= 1;
+        tampered.ciphertext = STANDARD.encode(bytes);
+        assert!(matches!(tampered.open(&key), Err(CryptoError::Decrypt)));
+
+        assert!(matches!(kdf.derive_key(""), Err(CryptoError::Invalid(_))));
+    }
+}
@@ -0,0 +1,507 @@
+//! Per-device archive of logs, configurations, alarms and transcripts.
+//!
+//! With `Settings.archive.dir` set, everything kept about a device lands in
+//! `<dir>/<host>/<kind>/`: session logs (when no `session_log_dir` is set),
+//! configurations that changed in a backup, syslog messages and traps from
+//! the device as daily JSON-lines files, and batch job transcripts. A
+//! background task applies the age and size limits hourly; files written in
+//! the last hour are never removed, since a session may still be logging to
+//! them.
+
+use crate::backup::Snapshot;
+use crate::batch::{BatchReport, CommandResult};
+use crate::session::SessionManager;
+use crate::settings::{ArchiveSettings, SettingsStore};
+use crate::snmp::TrapReceiver;
+use crate::syslog::SyslogReceiver;
+use crate::{alerts, timeline};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// How often the retention limits are applied
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Files younger than this are kept whatever the limits say
+const MIN_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Error, Debug)]
+pub enum DeviceArchiveError {
+    #[error("Device archive is disabled (set archive.dir in the settings)")]
+    Disabled,
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl From<DeviceArchiveError> for String {
+    fn from(err: DeviceArchiveError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveKind {
+    Logs,
+    Configs,
+    Alarms,
+    Transcripts,
+}
+
+impl ArchiveKind {
+    const ALL: [ArchiveKind; 4] = [Self::Logs, Self::Configs, Self::Alarms, Self::Transcripts];
+
+    fn dir_name(self) -> &'static str {
+        match self {
+            Self::Logs => "logs",
+            Self::Configs => "configs",
+            Self::Alarms => "alarms",
+            Self::Transcripts => "transcripts",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchivedFile {
+    pub kind: ArchiveKind,
+    pub name: String,
+    pub path: String,
+    pub bytes: u64,
+    /// Unix milliseconds
+    pub modified: i64,
+}
+
+/// What `get_device_archive` returns, newest files first
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceListing {
+    pub host: String,
+    pub dir: String,
+    pub bytes: u64,
+    pub files: Vec<ArchivedFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceSummary {
+    pub host: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PruneReport {
+    pub removed_files: usize,
+    pub removed_bytes: u64,
+}
+
+pub struct DeviceArchive {
+    root: PathBuf,
+    max_age: Option<Duration>,
+    max_device_bytes: Option<u64>,
+}
+
+impl DeviceArchive {
+    /// None while `archive.dir` is unset
+    pub fn from_settings(settings: &ArchiveSettings) -> Option<Self> {
+        let root = settings.dir.as_ref()?;
+        Some(Self {
+            root: PathBuf::from(root),
+            max_age: (settings.max_age_days > 0)
+                .then(|| Duration::from_secs(settings.max_age_days * 24 * 60 * 60)),
+            max_device_bytes: (settings.max_device_mb > 0)
+                .then(|| settings.max_device_mb * 1024 * 1024),
+        })
+    }
+
+    pub fn dir(&self, host: &str, kind: ArchiveKind) -> PathBuf {
+        self.root.join(device_name(host)).join(kind.dir_name())
+    }
+
+    /// Write `name` under the device's `kind` directory, replacing it
+    pub fn write(
+        &self,
+        host: &str,
+        kind: ArchiveKind,
+        name: &str,
+        data: &[u8],
+    ) -> io::Result<PathBuf> {
+        let dir = self.dir(host, kind);
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(name);
+        std::fs::write(&path, data)?;
+        Ok(path)
+    }
+
+    /// Append one line to a JSON-lines file
+    pub fn append_line(
+        &self,
+        host: &str,
+        kind: ArchiveKind,
+        name: &str,
+        line: &[u8],
+    ) -> io::Result<()> {
+        let dir = self.dir(host, kind);
+        std::fs::create_dir_all(&dir)?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(name))?;
+        let mut data = line.to_vec();
+        data.push(b'\n');
+        file.write_all(&data)
+    }
+
+    pub fn list(&self, host: &str) -> io::Result<DeviceListing> {
+        let dir = self.root.join(device_name(host));
+        let mut files = self.files(&dir)?;
+        files.sort_by_key(|f| std::cmp::Reverse(f.modified));
+        Ok(DeviceListing {
+            host: host.to_string(),
+            dir: dir.display().to_string(),
+            bytes: files.iter().map(|f| f.bytes).sum(),
+            files,
+        })
+    }
+
+    /// Devices with an archive directory, sorted
+    pub fn devices(&self) -> io::Result<Vec<DeviceSummary>> {
+        let mut devices = Vec::new();
+        for entry in read_dir(&self.root)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let files = self.files(&entry.path())?;
+            devices.push(DeviceSummary {
+                host: entry.file_name().to_string_lossy().into_owned(),
+                files: files.len(),
+                bytes: files.iter().map(|f| f.bytes).sum(),
+            });
+        }
+        devices.sort_by(|a, b| a.host.cmp(&b.host));
+        Ok(devices)
+    }
+
+    /// Remove files past the age limit, then the oldest files of devices
+    /// over the size limit
+    pub fn enforce(&self, now: SystemTime) -> io::Result<PruneReport> {
+        let mut report = PruneReport::default();
+        for entry in read_dir(&self.root)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let mut files = self.files(&entry.path())?;
+            // Oldest first
+            files.sort_by_key(|f| f.modified);
+            let mut total: u64 = files.iter().map(|f| f.bytes).sum();
+            for file in files {
+                let age = now
+                    .duration_since(UNIX_EPOCH + Duration::from_millis(file.modified.max(0) as u64))
+                    .unwrap_or_default();
+                if age < MIN_FILE_AGE {
+                    continue;
+                }
+                let expired = self.max_age.is_some_and(|max| age > max);
+                let over = self.max_device_bytes.is_some_and(|max| total > max);
+                if !expired && !over {
+                    continue;
+                }
+                match std::fs::remove_file(&file.path) {
+                    Ok(()) => {
+                        total -= file.bytes;
+                        report.removed_files += 1;
+                        report.removed_bytes += file.bytes;
+                    }
+                    Err(e) => warn!(path = %file.path, error = %e, "Failed to prune archived file"),
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn files(&self, device_dir: &Path) -> io::Result<Vec<ArchivedFile>> {
+        let mut files = Vec::new();
+        for kind in ArchiveKind::ALL {
+            for entry in read_dir(&device_dir.join(kind.dir_name()))? {
+                let entry = entry?;
+                let meta = entry.metadata()?;
+                if !meta.is_file() {
+                    continue;
+                }
+                let modified = meta
+                    .modified()
+                    .ok()
+                    .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_millis() as i64);
+                files.push(ArchivedFile {
+                    kind,
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    path: entry.path().display().to_string(),
+                    bytes: meta.len(),
+                    modified,
+                });
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Entries of `dir`, none if it doesn't exist
+fn read_dir(dir: &Path) -> io::Result<Vec<io::Result<std::fs::DirEntry>>> {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => Ok(entries.collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Directory name of a host; characters unsafe in file names become `_`
+pub fn device_name(host: &str) -> String {
+    host.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Session log of a session without a `log_file`
+pub fn log_path(root: &str, host: &str, session_id: &str) -> PathBuf {
+    Path::new(root)
+        .join(device_name(host))
+        .join(ArchiveKind::Logs.dir_name())
+        .join(format!("{}-{}.log", device_name(host), session_id))
+}
+
+async fn current(settings: &Mutex<SettingsStore>) -> Option<DeviceArchive> {
+    DeviceArchive::from_settings(&settings.lock().await.get().archive)
+}
+
+/// Archive syslog messages and traps while the archive is enabled
+pub fn spawn_feeds(
+    settings: &Arc<Mutex<SettingsStore>>,
+    manager: &Arc<SessionManager>,
+    syslog: &SyslogReceiver,
+    traps: &TrapReceiver,
+) {
+    let mut messages = syslog.subscribe();
+    let (store, sessions) = (Arc::clone(settings), Arc::clone(manager));
+    tauri::async_runtime::spawn(async move {
+        while let Some(message) = alerts::next(&mut messages, "archive syslog").await {
+            let Some(archive) = current(&store).await else {
+                continue;
+            };
+            let host = timeline::device(&sessions, &message.session_ids, &message.source);
+            let line = serde_json::json!({ "type": "syslog", "message": message });
+            append_alarm(&archive, &host, &line);
+        }
+    });
+
+    let mut received = traps.subscribe();
+    let (store, sessions) = (Arc::clone(settings), Arc::clone(manager));
+    tauri::async_runtime::spawn(async move {
+        while let Some(trap) = alerts::next(&mut received, "archive trap").await {
+            let Some(archive) = current(&store).await else {
+                continue;
+            };
+            let host = timeline::device(&sessions, &trap.session_ids, &trap.source);
+            let line = serde_json::json!({ "type": "trap", "trap": trap });
+            append_alarm(&archive, &host, &line);
+        }
+    });
+}
+
+/// One file per local day
+fn append_alarm(archive: &DeviceArchive, host: &str, line: &serde_json::Value) {
+    let name = format!("{}.jsonl", chrono::Local::now().format("%Y-%m-%d"));
+    let written = serde_json::to_vec(line)
+        .map_err(io::Error::from)
+        .and_then(|line| archive.append_line(host, ArchiveKind::Alarms, &name, &line));
+    if let Err(e) = written {
+        warn!(host, error = %e, "Failed to archive alarm");
+    }
+}
+
+/// Keep a configuration that changed in a backup
+pub fn archive_config(archive: &DeviceArchive, host: &str, snapshot: &Snapshot, text: &str) {
+    let taken = chrono::DateTime::from_timestamp(snapshot.taken_at, 0).unwrap_or_default();
+    let name = format!(
+        "{}-{}.cfg",
+        taken.format("%Y%m%d-%H%M%S"),
+        &snapshot.hash[..snapshot.hash.len().min(12)]
+    );
+    if let Err(e) = archive.write(host, ArchiveKind::Configs, &name, text.as_bytes()) {
+        warn!(host, error = %e, "Failed to archive configuration");
+    }
+}
+
+/// Keep what each device of a finished batch job answered
+pub fn archive_transcripts(archive: &DeviceArchive, report: &BatchReport) {
+    let name = format!(
+        "{}-{}.txt",
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        report.job_id
+    );
+    for device in &report.devices {
+        let mut text = format!(
+            "# {} ({}) job {}\n",
+            device.name, device.host, report.job_id
+        );
+        if let Some(error) = &device.error {
+            text.push_str(&format!("# error: {}\n", error));
+        }
+        for result in &device.commands {
+            match result {
+                CommandResult::Ok(output) => text.push_str(&format!(
+                    "\n{}{}\n{}\n",
+                    output.prompt, output.command, output.output
+                )),
+                CommandResult::Failed { command, error } => {
+                    text.push_str(&format!("\n> {}\n# failed: {}\n", command, error))
+                }
+                CommandResult::Skipped { command } => {
+                    text.push_str(&format!("\n> {}\n# skipped\n", command))
+                }
+            }
+        }
+        if let Err(e) = archive.write(
+            &device.host,
+            ArchiveKind::Transcripts,
+            &name,
+            text.as_bytes(),
+        ) {
+            warn!(host = %device.host, error = %e, "Failed to archive transcript");
+        }
+    }
+}
+
+/// Apply the retention limits every hour
+pub fn spawn_retention(settings: Arc<Mutex<SettingsStore>>) {
+    tauri::async_runtime::spawn(async move {
+        let mut ticker = tokio::time::interval(RETENTION_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let Some(archive) = current(&settings).await else {
+                continue;
+            };
+            match tokio::task::spawn_blocking(move || archive.enforce(SystemTime::now())).await {
+                Ok(Ok(report)) if report.removed_files > 0 => info!(
+                    files = report.removed_files,
+                    bytes = report.removed_bytes,
+                    "Pruned device archive"
+                ),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => warn!(error = %e, "Device archive retention failed"),
+                Err(e) => warn!(error = %e, "Device archive retention task failed"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_and_retention() {
+        let dir = std::env::temp_dir().join(format!("bspt-archive-test-{}", uuid::Uuid::new_v4()));
+        let archive = DeviceArchive::from_settings(&ArchiveSettings {
+            dir: Some(dir.display().to_string()),
+            max_age_days: 30,
+            max_device_mb: 1,
+        })
+        .unwrap();
+
+        let config = archive
+            .write(
+                "10.0.0.1",
+                ArchiveKind::Configs,
+                "a.cfg",
+                b"sysname core-1\n",
+            )
+            .unwrap();
+        assert!(config.ends_with("10.0.0.1/configs/a.cfg"));
+        archive
+            .append_line("10.0.0.1", ArchiveKind::Alarms, "day.jsonl", b"{}")
+            .unwrap();
+        archive
+            .append_line("10.0.0.1", ArchiveKind::Alarms, "day.jsonl", b"{}")
+            .unwrap();
+        // Over the size limit once `big` is an hour old
+        archive
+            .write(
+                "fe80::1",
+                ArchiveKind::Transcripts,
+                "big.txt",
+                &vec![b'x'; 1024 * 1024],
+            )
+            .unwrap();
+        archive
+            .write("fe80::1", ArchiveKind::Transcripts, "small.txt", b"ok")
+            .unwrap();
+
+        let listing = archive.list("10.0.0.1").unwrap();
+        assert_eq!(listing.files.len(), 2);
+        assert_eq!(listing.bytes, 15 + 6);
+        let devices = archive.devices().unwrap();
+        let hosts: Vec<&str> = devices.iter().map(|d| d.host.as_str()).collect();
+        assert_eq!(hosts, ["10.0.0.1", "fe80__1"]);
+
+        // Fresh files are kept whatever the limits
+        assert_eq!(archive.enforce(SystemTime::now()).unwrap().removed_files, 0);
+
+        // 40 days on, everything is past the age limit
+        let later = SystemTime::now() + Duration::from_secs(40 * 24 * 60 * 60);
+        let report = archive.enforce(later).unwrap();
+        assert_eq!(report.removed_files, 4);
+        assert!(archive.list("fe80::1").unwrap().files.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_limit_removes_oldest() {
+        let dir = std::env::temp_dir().join(format!("bspt-archive-test-{}", uuid::Uuid::new_v4()));
+        let archive = DeviceArchive::from_settings(&ArchiveSettings {
+            dir: Some(dir.display().to_string()),
+            max_age_days: 0,
+            max_device_mb: 1,
+        })
+        .unwrap();
+        let old = archive
+            .write(
+                "core-1",
+                ArchiveKind::Logs,
+                "old.log",
+                &vec![b'x'; 1024 * 1024],
+            )
+            .unwrap();
+        let file = std::fs::File::options().write(true).open(&old).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60))
+            .unwrap();
+        archive
+            .write("core-1", ArchiveKind::Logs, "new.log", b"prompt")
+            .unwrap();
+
+        let report = archive.enforce(SystemTime::now()).unwrap();
+        assert_eq!(report.removed_files, 1);
+        let names: Vec<String> = archive
+            .list("core-1")
+            .unwrap()
+            .files
+            .into_iter()
+            .map(|f| f.name)
+            .collect();
+        assert_eq!(names, ["new.log"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
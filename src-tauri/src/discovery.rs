@@ -0,0 +1,309 @@
+//! Subnet scan for populating a lab's device list.
+//!
+//! Every address of an IPv4 CIDR block gets a TCP connect to its
+//! management ports, like precheck.rs but over addresses nobody listed yet.
+//! With banners on, the first bytes an open port sends (the SSH version
+//! line, a telnet login banner) are kept and matched against known vendors
+//! to suggest a dialect.
+
+use crate::session::DeviceDialect;
+use crate::telnet::TelnetParser;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+pub const DEFAULT_SCAN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Largest block scanned, a /20
+pub const MAX_SCAN_HOSTS: u64 = 4096;
+const DEFAULT_CONCURRENCY: usize = 128;
+const MAX_CONCURRENCY: usize = 512;
+/// Wait for a banner after connecting
+const BANNER_TIMEOUT: Duration = Duration::from_millis(1500);
+const MAX_BANNER_BYTES: usize = 512;
+const MAX_BANNER_CHARS: usize = 200;
+
+/// Banner patterns, first match wins, with the dialect they suggest
+const VENDORS: &[(&str, &str, Option<DeviceDialect>)] = &[
+    ("huawei", "huawei", Some(DeviceDialect::Vrp)),
+    ("h3c", "h3c", Some(DeviceDialect::Comware)),
+    ("comware", "h3c", Some(DeviceDialect::Comware)),
+    ("cisco", "cisco", Some(DeviceDialect::Ios)),
+    (
+        "user access verification",
+        "cisco",
+        Some(DeviceDialect::Ios),
+    ),
+    ("junos", "juniper", None),
+    ("juniper", "juniper", None),
+    ("rosssh", "mikrotik", None),
+    ("openssh", "openssh", Some(DeviceDialect::Linux)),
+    ("dropbear", "dropbear", Some(DeviceDialect::Linux)),
+];
+
+#[derive(Error, Debug)]
+pub enum ScanError {
+    #[error("Invalid CIDR: {0}")]
+    InvalidCidr(String),
+    #[error("Subnet has {hosts} addresses, at most {MAX_SCAN_HOSTS} are scanned")]
+    TooLarge { hosts: u64 },
+}
+
+impl From<ScanError> for String {
+    fn from(err: ScanError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ScanOptions {
+    /// Read what open ports send first and guess the vendor
+    #[serde(default)]
+    pub banners: bool,
+    /// Per connect; default `DEFAULT_SCAN_TIMEOUT`
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Connects at once; default 128, max 512
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenPort {
+    pub port: u16,
+    pub latency_ms: f64,
+    /// First line or lines sent by the port, when banners were asked for
+    pub banner: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredHost {
+    pub host: String,
+    pub ports: Vec<OpenPort>,
+    /// Guessed from the banners
+    pub vendor: Option<String>,
+    pub dialect: Option<DeviceDialect>,
+}
+
+/// Addresses of `10.0.0.0/24`-style blocks, or of a bare address. Network
+/// and broadcast addresses are left out of blocks larger than a /31
+fn hosts(cidr: &str) -> Result<Vec<Ipv4Addr>, ScanError> {
+    let invalid = || ScanError::InvalidCidr(cidr.to_string());
+    let (addr, prefix) = match cidr.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, prefix.parse::<u32>().map_err(|_| invalid())?),
+        None => (cidr.trim(), 32),
+    };
+    let addr: Ipv4Addr = addr.parse().map_err(|_| invalid())?;
+    if prefix > 32 {
+        return Err(invalid());
+    }
+    let count = 1u64 << (32 - prefix);
+    let first = u32::from(addr) & u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    let range = match prefix {
+        31 | 32 => 0..count,
+        _ => 1..count - 1,
+    };
+    if range.end - range.start > MAX_SCAN_HOSTS {
+        return Err(ScanError::TooLarge {
+            hosts: range.end - range.start,
+        });
+    }
+    Ok(range.map(|i| Ipv4Addr::from(first + i as u32)).collect())
+}
+
+/// Vendor and dialect suggested by a banner
+fn guess_vendor(banner: &str) -> Option<(&'static str, Option<DeviceDialect>)> {
+    let banner = banner.to_lowercase();
+    VENDORS
+        .iter()
+        .find(|(pattern, _, _)| banner.contains(pattern))
+        .map(|&(_, vendor, dialect)| (vendor, dialect))
+}
+
+/// Printable start of what a port sent, without telnet negotiation
+fn clean_banner(data: &[u8]) -> Option<String> {
+    let (data, _) = TelnetParser::new().parse(data);
+    let text = String::from_utf8_lossy(&data);
+    let banner = text
+        .lines()
+        .map(|line| line.trim().replace(|c: char| c.is_control(), ""))
+        .filter(|line| !line.is_empty())
+        .take(3)
+        .collect::<Vec<_>>()
+        .join(" | ");
+    (!banner.is_empty()).then(|| banner.chars().take(MAX_BANNER_CHARS).collect())
+}
+
+async fn probe(addr: Ipv4Addr, port: u16, timeout: Duration, banners: bool) -> Option<OpenPort> {
+    let started = Instant::now();
+    let mut stream = match tokio::time::timeout(timeout, TcpStream::connect((addr, port))).await {
+        Ok(Ok(stream)) => stream,
+        _ => return None,
+    };
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let mut banner = None;
+    if banners {
+        let mut data = Vec::new();
+        let mut buf = [0u8; MAX_BANNER_BYTES];
+        let deadline = Instant::now() + BANNER_TIMEOUT;
+        // Until a whole line arrived, or the port stays silent
+        while data.len() < MAX_BANNER_BYTES && !data.contains(&b'\n') {
+            match tokio::time::timeout_at(deadline, stream.read(&mut buf)).await {
+                Ok(Ok(n)) if n > 0 => data.extend_from_slice(&buf[..n]),
+                _ => break,
+            }
+        }
+        banner = clean_banner(&data);
+    }
+    debug!(host = %addr, port, banner = ?banner, "Found open port");
+    Some(OpenPort {
+        port,
+        latency_ms,
+        banner,
+    })
+}
+
+/// Probe every address of `cidr` on `ports`; hosts with an open port are
+/// returned in address order
+pub async fn scan_subnet(
+    cidr: &str,
+    ports: &[u16],
+    options: &ScanOptions,
+) -> Result<Vec<DiscoveredHost>, ScanError> {
+    let addrs = hosts(cidr)?;
+    let timeout = options
+        .timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_SCAN_TIMEOUT);
+    let concurrency = options
+        .concurrency
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .clamp(1, MAX_CONCURRENCY);
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let started = Instant::now();
+
+    let mut tasks = JoinSet::new();
+    for (index, &addr) in addrs.iter().enumerate() {
+        for &port in ports {
+            let permits = Arc::clone(&permits);
+            let banners = options.banners;
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await.expect("semaphore not closed");
+                (index, probe(addr, port, timeout, banners).await)
+            });
+        }
+    }
+
+    let mut open: Vec<Vec<OpenPort>> = vec![Vec::new(); addrs.len()];
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((index, Some(port))) = joined {
+            open[index].push(port);
+        }
+    }
+    let discovered: Vec<DiscoveredHost> = addrs
+        .iter()
+        .zip(open)
+        .filter(|(_, ports)| !ports.is_empty())
+        .map(|(addr, mut ports)| {
+            ports.sort_by_key(|p| p.port);
+            let guess = ports
+                .iter()
+                .filter_map(|p| p.banner.as_deref())
+                .find_map(guess_vendor);
+            DiscoveredHost {
+                host: addr.to_string(),
+                ports,
+                vendor: guess.map(|(vendor, _)| vendor.to_string()),
+                dialect: guess.and_then(|(_, dialect)| dialect),
+            }
+        })
+        .collect();
+    info!(
+        cidr,
+        addresses = addrs.len(),
+        found = discovered.len(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "Subnet scan finished"
+    );
+    Ok(discovered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_scan_subnet() {
+        let all = hosts("10.1.2.77/24").unwrap();
+        assert_eq!(all.len(), 254);
+        assert_eq!(all[0], Ipv4Addr::new(10, 1, 2, 1));
+        assert_eq!(all[253], Ipv4Addr::new(10, 1, 2, 254));
+        assert_eq!(hosts("10.1.2.0/31").unwrap().len(), 2);
+        assert_eq!(hosts("10.1.2.9").unwrap(), [Ipv4Addr::new(10, 1, 2, 9)]);
+        assert!(matches!(
+            hosts("10.0.0.0/16"),
+            Err(ScanError::TooLarge { hosts: 65534 })
+        ));
+        assert!(hosts("10.0.0.0/33").is_err() && hosts("fe80::/64").is_err());
+
+        assert_eq!(
+            guess_vendor("SSH-2.0-HUAWEI-1.5"),
+            Some(("huawei", Some(DeviceDialect::Vrp)))
+        );
+        assert_eq!(
+            guess_vendor("SSH-2.0-Comware-7.1.064"),
+            Some(("h3c", Some(DeviceDialect::Comware)))
+        );
+        assert_eq!(
+            guess_vendor("SSH-2.0-OpenSSH_9.6"),
+            Some(("openssh", Some(DeviceDialect::Linux)))
+        );
+        assert_eq!(guess_vendor("login:"), None);
+        assert_eq!(
+            clean_banner(
+                b"\xff\xfb\x01\xff\xfb\x03\r\n\r\nUser Access Verification\r\n\r\nUsername: "
+            )
+            .as_deref(),
+            Some("User Access Verification | Username:")
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(b"SSH-2.0-HUAWEI-1.5\r\n").await;
+            }
+        });
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let options = ScanOptions {
+            banners: true,
+            timeout_ms: Some(500),
+            ..Default::default()
+        };
+        let found = scan_subnet("127.0.0.1/32", &[closed, port], &options)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].ports.len(), 1);
+        assert_eq!(
+            found[0].ports[0].banner.as_deref(),
+            Some("SSH-2.0-HUAWEI-1.5")
+        );
+        assert_eq!(found[0].vendor.as_deref(), Some("huawei"));
+        assert_eq!(found[0].dialect, Some(DeviceDialect::Vrp));
+    }
+}
@@ -0,0 +1,64 @@
+//! Dry runs: what an automation would send, without sending it.
+//!
+//! Scripts, batch jobs and macros each plan their run into a
+//! `DryRunReport`: every piece of input in the order it would go out, the
+//! session or device it goes to and what the run waits for afterwards.
+//! Nothing connects and nothing is transmitted, so values a device would
+//! answer with (`$output` and friends) stand in as placeholders.
+
+use serde::Serialize;
+
+/// One input a run would send
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedSend {
+    /// Session id, or the batch target's name
+    pub target: String,
+    pub host: String,
+    /// Script line, or the command or step number (from 1)
+    pub step: usize,
+    /// Exactly what would be written, line endings included
+    pub data: String,
+    /// What the run waits for after sending, e.g. a prompt or a delay
+    pub wait: Option<String>,
+}
+
+/// Something that would fail or needs a look before the real run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DryRunIssue {
+    pub target: Option<String>,
+    pub step: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DryRunReport {
+    pub sends: Vec<PlannedSend>,
+    pub issues: Vec<DryRunIssue>,
+}
+
+impl DryRunReport {
+    pub fn send(&mut self, target: &str, host: &str, step: usize, data: String) {
+        self.sends.push(PlannedSend {
+            target: target.to_string(),
+            host: host.to_string(),
+            step,
+            data,
+            wait: None,
+        });
+    }
+
+    /// Set what the last send waits for; a wait before any send is dropped
+    pub fn wait(&mut self, wait: String) {
+        if let Some(last) = self.sends.last_mut() {
+            last.wait = Some(wait);
+        }
+    }
+
+    pub fn issue(&mut self, target: Option<&str>, step: Option<usize>, message: String) {
+        self.issues.push(DryRunIssue {
+            target: target.map(str::to_string),
+            step,
+            message,
+        });
+    }
+}
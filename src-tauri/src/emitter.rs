@@ -0,0 +1,693 @@
+use crate::charset::utf8_incomplete_tail;
+use crate::ratelimit::RateLimiter;
+use crate::ringbuffer::{BackpressureController, BufferStats, DeliveryConfig, SessionRingBuffer};
+use crate::scrollback::Scrollback;
+use crate::session::{PayloadEncoding, SessionConfig};
+use crate::session_log::SessionLog;
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+use tauri::Emitter;
+use tokio::sync::{broadcast, mpsc, watch, Mutex, OwnedMutexGuard};
+use tokio::time::{sleep_until, Duration, Instant};
+use tracing::{debug, error, warn};
+
+/// Initial minimum time between two data events for one session (~60 events/s)
+const FLUSH_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Bounds of the adaptive flush interval
+const MIN_FLUSH_INTERVAL: Duration = Duration::from_millis(4);
+const MAX_FLUSH_INTERVAL: Duration = Duration::from_millis(64);
+
+/// Initial largest chunk emitted in a single event; a full batch is sent immediately
+const BATCH_BYTES: usize = 32 * 1024;
+
+/// Bounds of the adaptive batch size
+const MIN_BATCH_BYTES: usize = 8 * 1024;
+const MAX_BATCH_BYTES: usize = 128 * 1024;
+
+/// An ack within this long means the frontend keeps up: batch less
+const KEEPING_UP_LAG: Duration = Duration::from_millis(16);
+
+/// An ack taking this long means the frontend lags: batch more
+const FALLING_BEHIND_LAG: Duration = Duration::from_millis(100);
+
+/// Bytes that may be emitted before the frontend acknowledges a drain
+const ACK_WINDOW_BYTES: usize = 256 * 1024;
+
+/// Minimum time between two `session:{id}:buffer` stats events
+const STATS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Keep emitting without an ack after this long, so views that never
+/// call `notify_buffer_drained` can't stall a session forever
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Chunks an output subscriber may fall behind before it misses some
+const OUTPUT_TAP_CAPACITY: usize = 256;
+
+/// Handle used by a transport to tell the emitter that data was pushed.
+///
+/// Dropping every handle makes the emitter flush what is left and exit.
+#[derive(Clone)]
+pub struct EmitterHandle {
+    wake_tx: mpsc::Sender<()>,
+    /// Set once the emitter has flushed and exited
+    done_rx: watch::Receiver<bool>,
+    output_tx: broadcast::Sender<Bytes>,
+}
+
+impl EmitterHandle {
+    /// Wake the emitter after pushing data into the buffer.
+    /// Wake-ups coalesce, so this is cheap to call per read.
+    pub fn wake(&self) {
+        let _ = self.wake_tx.try_send(());
+    }
+
+    /// Live copy of the session output, as recorded in the scrollback; kept
+    /// in the `SessionHandle` for backend consumers such as `run_command`
+    pub fn output(&self) -> broadcast::Sender<Bytes> {
+        self.output_tx.clone()
+    }
+
+    /// Drop this handle and wait until everything still buffered has been
+    /// emitted and logged. Transports call this before reporting the
+    /// session as disconnected, so the last output is never lost.
+    /// Returns once every other clone of the handle is dropped as well.
+    pub async fn close(self) {
+        let Self {
+            wake_tx,
+            mut done_rx,
+            ..
+        } = self;
+        drop(wake_tx);
+        let _ = done_rx.wait_for(|done| *done).await;
+    }
+}
+
+/// Body of a `session:{id}` data event
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Payload<'a> {
+    /// Serialized as a JSON number array, same as a Vec<u8>
+    Json(&'a [u8]),
+    Base64(String),
+}
+
+impl<'a> Payload<'a> {
+    pub(crate) fn encode(encoding: PayloadEncoding, chunk: &'a [u8]) -> Self {
+        match encoding {
+            PayloadEncoding::Json => Payload::Json(chunk),
+            PayloadEncoding::Base64 => {
+                Payload::Base64(base64::engine::general_purpose::STANDARD.encode(chunk))
+            }
+        }
+    }
+}
+
+/// Body of a `session:{id}` data event with acked delivery
+#[derive(Clone, Serialize)]
+struct AckedPayload<'a> {
+    /// Pass to `ack_output`; a resent chunk keeps its number
+    seq: u64,
+    data: Payload<'a>,
+}
+
+/// Batch size and flush interval, adapted to how long the frontend takes to
+/// ack a drain: small, frequent batches for low latency while it keeps up,
+/// large, rare ones while it falls behind.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BatchTuner {
+    batch_bytes: usize,
+    interval: Duration,
+}
+
+impl BatchTuner {
+    fn new() -> Self {
+        Self {
+            batch_bytes: BATCH_BYTES,
+            interval: FLUSH_INTERVAL,
+        }
+    }
+
+    /// Adjust for `lag`, the time the oldest unacked event waited for its ack
+    fn observe(&mut self, lag: Duration) {
+        if lag <= KEEPING_UP_LAG {
+            self.batch_bytes = (self.batch_bytes / 2).max(MIN_BATCH_BYTES);
+            self.interval = (self.interval / 2).max(MIN_FLUSH_INTERVAL);
+        } else if lag >= FALLING_BEHIND_LAG {
+            self.batch_bytes = (self.batch_bytes * 2).min(MAX_BATCH_BYTES);
+            self.interval = (self.interval * 2).min(MAX_FLUSH_INTERVAL);
+        }
+    }
+}
+
+/// Body of a `session:{id}:data_loss` event
+#[derive(Clone, Serialize)]
+struct DataLossEvent {
+    /// Bytes dropped since the previous event
+    dropped_bytes: u64,
+    /// Bytes dropped since the session started
+    total_dropped: u64,
+}
+
+/// Drains a session's `SessionRingBuffer` and emits coalesced chunks as
+/// `session:{id}` events.
+///
+/// Flow control:
+/// 1. Transport pushes data and calls `EmitterHandle::wake`
+/// 2. Emitter pops up to a batch at most once per flush interval
+/// 3. Emitted bytes count against `ACK_WINDOW_BYTES` until the frontend
+///    calls `notify_buffer_drained`; a full window stops popping, so the
+///    buffer fills and the transport pauses at the high watermark
+/// 4. Once the buffer is at the low watermark, the shared
+///    `BackpressureController` resumes the transport's reads
+///
+/// Batch size and interval follow the ack latency (see `BatchTuner`).
+///
+/// Buffered output is UTF-8 (see `charset::Transcoder`). A multibyte
+/// character split across chunks is held back and prepended to the next
+/// chunk, so each event decodes cleanly on its own.
+///
+/// Bytes discarded by a drop overflow policy are reported as
+/// `session:{id}:data_loss` before the next batch is emitted.
+///
+/// Everything popped from the buffer is appended to the session scrollback
+/// and the session log, if configured. With a rate limit, chunks over the cap are skipped instead of
+/// emitted and summarized as `session:{id}:skipped`.
+///
+/// Buffer stats are emitted as `session:{id}:buffer`, throttled to
+/// `STATS_INTERVAL` while data flows and once more when the buffer empties.
+///
+/// With acked delivery (`SessionConfig.delivery`) each emitted chunk is held
+/// in the buffer under a sequence number and sent as `{seq, data}`. Popping
+/// stops while `max_unacked_bytes` are held; once the oldest held chunk has
+/// waited `ack_timeout_ms`, every held chunk is sent again in order, so a
+/// reloaded webview gets what it missed and drops sequence numbers it has
+/// already seen.
+struct SessionEmitter {
+    session_id: String,
+    event_name: String,
+    stats_event_name: String,
+    data_loss_event_name: String,
+    skipped_event_name: String,
+    app_handle: tauri::AppHandle,
+    buffer: Arc<Mutex<SessionRingBuffer>>,
+    scrollback: Arc<Mutex<Scrollback>>,
+    /// Subscribers to recorded output
+    output_tx: broadcast::Sender<Bytes>,
+    encoding: PayloadEncoding,
+    /// Trailing partial character from the previous chunk
+    carry: Option<Bytes>,
+    log: Option<SessionLog>,
+    limiter: Option<RateLimiter>,
+    delivery: DeliveryConfig,
+    backpressure: BackpressureController,
+    /// Receives the size of every emitted chunk (benchmarks)
+    observer: Option<mpsc::UnboundedSender<usize>>,
+    /// Bytes emitted since the last drain acknowledgement
+    in_flight: usize,
+    /// When the oldest unacknowledged event was emitted
+    first_unacked: Option<Instant>,
+    tuner: BatchTuner,
+    last_emit: Instant,
+    last_stats: Instant,
+}
+
+/// Spawn the emitter task for a session.
+///
+/// `config` selects the wire format of data events, the session log and the
+/// rate limit. `drain_rx` receives frontend drain notifications. The
+/// returned controller holds the pause state of the transport's reads: the
+/// transport updates it after pushes and honors it in its read loop, the
+/// emitter resumes it as the buffer drains.
+pub fn spawn_emitter(
+    session_id: String,
+    app_handle: tauri::AppHandle,
+    buffer: Arc<Mutex<SessionRingBuffer>>,
+    scrollback: Arc<Mutex<Scrollback>>,
+    config: &SessionConfig,
+    drain_rx: mpsc::Receiver<()>,
+) -> (EmitterHandle, BackpressureController) {
+    spawn(
+        session_id, app_handle, buffer, scrollback, config, drain_rx, None,
+    )
+}
+
+/// Spawn an emitter that also reports each emitted chunk's size to `observer`.
+pub(crate) fn spawn_observed_emitter(
+    session_id: String,
+    app_handle: tauri::AppHandle,
+    buffer: Arc<Mutex<SessionRingBuffer>>,
+    scrollback: Arc<Mutex<Scrollback>>,
+    config: &SessionConfig,
+    drain_rx: mpsc::Receiver<()>,
+    observer: mpsc::UnboundedSender<usize>,
+) -> (EmitterHandle, BackpressureController) {
+    let observer = Some(observer);
+    spawn(
+        session_id, app_handle, buffer, scrollback, config, drain_rx, observer,
+    )
+}
+
+fn spawn(
+    session_id: String,
+    app_handle: tauri::AppHandle,
+    buffer: Arc<Mutex<SessionRingBuffer>>,
+    scrollback: Arc<Mutex<Scrollback>>,
+    config: &SessionConfig,
+    drain_rx: mpsc::Receiver<()>,
+    observer: Option<mpsc::UnboundedSender<usize>>,
+) -> (EmitterHandle, BackpressureController) {
+    let (wake_tx, wake_rx) = mpsc::channel(1);
+    let (done_tx, done_rx) = watch::channel(false);
+    let backpressure = BackpressureController::new(session_id.clone());
+    let (output_tx, _) = broadcast::channel(OUTPUT_TAP_CAPACITY);
+
+    let log = config
+        .log_file
+        .as_deref()
+        .and_then(|path| match SessionLog::open(Path::new(path), config.log_timestamps) {
+            Ok(log) => Some(log),
+            Err(e) => {
+                warn!(session_id = %session_id, path, error = %e, "Failed to open session log");
+                None
+            }
+        });
+
+    let emitter = SessionEmitter {
+        event_name: format!("session:{}", session_id),
+        stats_event_name: format!("session:{}:buffer", session_id),
+        data_loss_event_name: format!("session:{}:data_loss", session_id),
+        skipped_event_name: format!("session:{}:skipped", session_id),
+        session_id,
+        app_handle,
+        buffer,
+        scrollback,
+        output_tx: output_tx.clone(),
+        encoding: config.payload_encoding,
+        carry: None,
+        log,
+        limiter: config
+            .rate_limit
+            .map(|limit| RateLimiter::new(limit, Instant::now())),
+        delivery: config.delivery.unwrap_or_default(),
+        backpressure: backpressure.clone(),
+        observer,
+        in_flight: 0,
+        first_unacked: None,
+        tuner: BatchTuner::new(),
+        last_emit: Instant::now() - FLUSH_INTERVAL,
+        last_stats: Instant::now() - STATS_INTERVAL,
+    };
+    tokio::spawn(emitter.run(wake_rx, drain_rx, done_tx));
+
+    let handle = EmitterHandle {
+        wake_tx,
+        done_rx,
+        output_tx,
+    };
+    (handle, backpressure)
+}
+
+impl SessionEmitter {
+    async fn run(
+        mut self,
+        mut wake_rx: mpsc::Receiver<()>,
+        mut drain_rx: mpsc::Receiver<()>,
+        done_tx: watch::Sender<bool>,
+    ) {
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            let timer = async {
+                match deadline {
+                    Some(at) => sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                woke = wake_rx.recv() => {
+                    if woke.is_none() {
+                        // Transport has ended
+                        break;
+                    }
+                }
+                Some(()) = drain_rx.recv() => {
+                    let lag = self.first_unacked.map(|at| at.elapsed()).unwrap_or_default();
+                    self.acknowledge(lag);
+                }
+                _ = timer => {}
+            }
+
+            deadline = self.pump().await;
+        }
+
+        self.flush_all().await;
+        let _ = done_tx.send(true);
+        debug!(session_id = %self.session_id, "Emitter stopped");
+    }
+
+    /// Emit one batch if allowed, and with acked delivery resend what timed
+    /// out; returns when to try again
+    async fn pump(&mut self) -> Option<Instant> {
+        let next = self.pump_batch().await;
+        if !self.delivery.acked() {
+            return next;
+        }
+
+        let timeout = self.delivery.ack_timeout();
+        let (overdue, redelivery) = {
+            let mut buf = self.buffer.lock().await;
+            let overdue = buf.take_overdue(std::time::Instant::now(), timeout);
+            let redelivery = buf.next_redelivery(timeout).map(Instant::from_std);
+            (overdue, redelivery)
+        };
+        if !overdue.is_empty() {
+            debug!(session_id = %self.session_id, chunks = overdue.len(), "No output ack in time, resending");
+        }
+        for (seq, chunk) in overdue {
+            self.emit(Some(seq), chunk);
+        }
+        next.into_iter().chain(redelivery).min()
+    }
+
+    /// Emit one batch if allowed; returns when to try again
+    async fn pump_batch(&mut self) -> Option<Instant> {
+        let now = Instant::now();
+        self.report_data_loss().await;
+
+        if self.in_flight >= ACK_WINDOW_BYTES {
+            let timeout_at = self.last_emit + ACK_TIMEOUT;
+            if now < timeout_at {
+                return Some(timeout_at);
+            }
+            debug!(session_id = %self.session_id, "No drain ack, resuming emission");
+            self.acknowledge(ACK_TIMEOUT);
+        }
+
+        let (chunk, remaining, stats) = {
+            let mut buf = self.buffer.lock().await;
+            if buf.is_empty() {
+                return None;
+            }
+            let pending = buf.len();
+            if self.delivery.acked() && buf.unacked_len() >= self.delivery.max_unacked_bytes {
+                // Resumed by an ack or the redelivery timeout
+                return None;
+            }
+
+            let next_allowed = self.last_emit + self.tuner.interval;
+            if now < next_allowed && pending < self.tuner.batch_bytes {
+                return Some(next_allowed);
+            }
+
+            let chunk = buf.pop_chunk(self.tuner.batch_bytes);
+            let stats =
+                (buf.is_empty() || now >= self.last_stats + STATS_INTERVAL).then(|| buf.stats());
+            self.backpressure.update(&buf);
+            (chunk, buf.len(), stats)
+        };
+
+        let chunk = chunk.and_then(|chunk| {
+            debug!(
+                session_id = %self.session_id,
+                bytes = chunk.len(),
+                queued_ms = chunk.arrived.elapsed().as_millis() as u64,
+                "Popped buffered data"
+            );
+            self.split_at_boundary(chunk.data)
+        });
+        if let Some(chunk) = chunk {
+            let _recorded = self.record(&chunk).await;
+            if self.delivery.acked() {
+                let seq = {
+                    let mut buf = self.buffer.lock().await;
+                    let seq = buf.hold(chunk.clone(), now.into_std());
+                    self.backpressure.update(&buf);
+                    seq
+                };
+                self.in_flight += chunk.len();
+                self.first_unacked.get_or_insert(now);
+                self.last_emit = now;
+                self.emit(Some(seq), chunk);
+                return self.schedule(now, remaining, stats);
+            }
+            let admitted = match self.limiter.as_mut() {
+                Some(limiter) => limiter.admit(&chunk, now),
+                None => true,
+            };
+            if admitted {
+                // Report the gap before output resumes
+                self.report_skipped(now, true);
+                self.in_flight += chunk.len();
+                self.first_unacked.get_or_insert(now);
+                self.last_emit = now;
+                self.emit(None, chunk);
+            }
+        }
+        self.report_skipped(now, remaining == 0);
+        self.schedule(now, remaining, stats)
+    }
+
+    /// Emit the stats taken with the last pop; returns when to pop next
+    fn schedule(
+        &mut self,
+        now: Instant,
+        remaining: usize,
+        stats: Option<BufferStats>,
+    ) -> Option<Instant> {
+        if let Some(stats) = stats {
+            self.last_stats = now;
+            if let Err(e) = self.app_handle.emit(&self.stats_event_name, stats) {
+                error!(session_id = %self.session_id, error = %e, "Failed to emit buffer stats");
+            }
+        }
+
+        match remaining {
+            0 => None,
+            n if n >= self.tuner.batch_bytes => Some(now),
+            _ => Some(now + self.tuner.interval),
+        }
+    }
+
+    /// Reopen the ack window and adapt batching to how long the ack took
+    fn acknowledge(&mut self, lag: Duration) {
+        self.in_flight = 0;
+        self.first_unacked = None;
+
+        let before = self.tuner;
+        self.tuner.observe(lag);
+        if self.tuner != before {
+            debug!(
+                session_id = %self.session_id,
+                lag_ms = lag.as_millis() as u64,
+                batch_bytes = self.tuner.batch_bytes,
+                interval_ms = self.tuner.interval.as_millis() as u64,
+                "Adapted emit batching"
+            );
+        }
+    }
+
+    /// Prepend the held-back carry and hold back a new trailing partial
+    /// character; returns None if nothing is complete yet
+    fn split_at_boundary(&mut self, chunk: Bytes) -> Option<Bytes> {
+        let mut chunk = match self.carry.take() {
+            Some(carry) => {
+                let mut joined = BytesMut::with_capacity(carry.len() + chunk.len());
+                joined.extend_from_slice(&carry);
+                joined.extend_from_slice(&chunk);
+                joined.freeze()
+            }
+            None => chunk,
+        };
+
+        let tail = utf8_incomplete_tail(&chunk);
+        if tail > 0 {
+            self.carry = Some(chunk.split_off(chunk.len() - tail));
+        }
+        (!chunk.is_empty()).then_some(chunk)
+    }
+
+    /// Keep output in the scrollback and session log, and pass it to
+    /// output subscribers. The scrollback stays locked until the caller
+    /// has emitted the data, so a view replaying it (see views.rs) never
+    /// gets a chunk both in its replay and as a live event.
+    async fn record(&mut self, data: &[u8]) -> OwnedMutexGuard<Scrollback> {
+        let mut scrollback = Arc::clone(&self.scrollback).lock_owned().await;
+        if data.is_empty() {
+            return scrollback;
+        }
+        scrollback.append(data);
+        if self.output_tx.receiver_count() > 0 {
+            let _ = self.output_tx.send(Bytes::copy_from_slice(data));
+        }
+
+        if let Some(log) = self.log.as_mut() {
+            if let Err(e) = log.write(data) {
+                error!(
+                    session_id = %self.session_id,
+                    path = %log.path().display(),
+                    error = %e,
+                    "Session log write failed, logging stopped"
+                );
+                self.log = None;
+            }
+        }
+        scrollback
+    }
+
+    fn report_skipped(&mut self, now: Instant, force: bool) {
+        let Some(summary) = self
+            .limiter
+            .as_mut()
+            .and_then(|limiter| limiter.take_summary(now, force))
+        else {
+            return;
+        };
+
+        debug!(
+            session_id = %self.session_id,
+            skipped_bytes = summary.skipped_bytes,
+            skipped_lines = summary.skipped_lines,
+            "Output skipped by rate limit"
+        );
+        if let Err(e) = self.app_handle.emit(&self.skipped_event_name, summary) {
+            error!(session_id = %self.session_id, error = %e, "Failed to emit skip summary");
+        }
+    }
+
+    async fn report_data_loss(&self) {
+        let (dropped_bytes, total_dropped) = {
+            let mut buf = self.buffer.lock().await;
+            (buf.take_dropped(), buf.stats().total_dropped)
+        };
+        if dropped_bytes == 0 {
+            return;
+        }
+
+        warn!(session_id = %self.session_id, dropped_bytes, "Output dropped on buffer overflow");
+        let event = DataLossEvent {
+            dropped_bytes,
+            total_dropped,
+        };
+        if let Err(e) = self.app_handle.emit(&self.data_loss_event_name, event) {
+            error!(session_id = %self.session_id, error = %e, "Failed to emit data loss event");
+        }
+    }
+
+    /// Emit everything still buffered, ignoring the ack window and rate limit
+    async fn flush_all(&mut self) {
+        self.report_data_loss().await;
+        self.report_skipped(Instant::now(), true);
+
+        let remaining = self.buffer.lock().await.drain_all();
+        let now = std::time::Instant::now();
+        let _recorded = self.record(&remaining).await;
+
+        let mut data = self.carry.take().map(Vec::from).unwrap_or_default();
+        data.extend_from_slice(&remaining);
+        let mut data = Bytes::from(data);
+        while !data.is_empty() {
+            let chunk = data.split_to(MAX_BATCH_BYTES.min(data.len()));
+            let seq = match self.delivery.acked() {
+                true => Some(self.buffer.lock().await.hold(chunk.clone(), now)),
+                false => None,
+            };
+            self.emit(seq, chunk);
+        }
+    }
+
+    /// Emit a chunk, numbered with acked delivery
+    fn emit(&self, seq: Option<u64>, chunk: Bytes) {
+        debug!(session_id = %self.session_id, bytes = chunk.len(), seq, "Emitting data");
+        let data = Payload::encode(self.encoding, &chunk);
+        let sent = match seq {
+            Some(seq) => self
+                .app_handle
+                .emit(&self.event_name, AckedPayload { seq, data }),
+            None => self.app_handle.emit(&self.event_name, data),
+        };
+        if let Err(e) = sent {
+            error!(
+                session_id = %self.session_id,
+                error = %e,
+                "Failed to emit data event"
+            );
+        }
+        if let Some(observer) = &self.observer {
+            let _ = observer.send(chunk.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_output(len: usize) -> Vec<u8> {
+        b"GigabitEthernet0/0/1    up    up    10.0.0.1/24\r\n"
+            .iter()
+            .copied()
+            .cycle()
+            .take(len)
+            .collect()
+    }
+
+    #[test]
+    fn test_base64_payload_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let json = serde_json::to_string(&Payload::encode(PayloadEncoding::Base64, &data)).unwrap();
+        let encoded: String = serde_json::from_str(&json).unwrap();
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let json = serde_json::to_string(&Payload::encode(PayloadEncoding::Json, b"ok")).unwrap();
+        assert_eq!(json, "[111,107]");
+    }
+
+    #[test]
+    fn test_batch_tuner() {
+        let mut tuner = BatchTuner::new();
+        tuner.observe(Duration::from_millis(2));
+        assert_eq!(tuner.batch_bytes, BATCH_BYTES / 2);
+        assert_eq!(tuner.interval, FLUSH_INTERVAL / 2);
+
+        // In-between lag holds the current setting
+        tuner.observe(Duration::from_millis(50));
+        assert_eq!(tuner.batch_bytes, BATCH_BYTES / 2);
+
+        for _ in 0..10 {
+            tuner.observe(Duration::ZERO);
+        }
+        assert_eq!(tuner.batch_bytes, MIN_BATCH_BYTES);
+        assert_eq!(tuner.interval, MIN_FLUSH_INTERVAL);
+
+        for _ in 0..10 {
+            tuner.observe(ACK_TIMEOUT);
+        }
+        assert_eq!(tuner.batch_bytes, MAX_BATCH_BYTES);
+        assert_eq!(tuner.interval, MAX_FLUSH_INTERVAL);
+    }
+
+    #[test]
+    fn test_payload_encoding_size() {
+        let data = sample_output(MAX_BATCH_BYTES);
+        let ratio = |encoding| {
+            let size = serde_json::to_string(&Payload::encode(encoding, &data))
+                .unwrap()
+                .len();
+            size as f64 / data.len() as f64
+        };
+
+        // Text output is ~3.7 bytes per byte as a number array, 4/3 as base64
+        let json = ratio(PayloadEncoding::Json);
+        assert!((3.0..4.5).contains(&json), "json ratio {}", json);
+        let base64 = ratio(PayloadEncoding::Base64);
+        assert!((1.3..1.4).contains(&base64), "base64 ratio {}", base64);
+    }
+}
@@ -0,0 +1,74 @@
+//! Backend event bus.
+//!
+//! Session tasks publish the VRP events they parse here in addition to
+//! emitting them to the frontend, so backend consumers (automation rules)
+//! can react without a window being open. Sessions with the pipeline's
+//! tracer stage enabled publish their output lines as well.
+
+use crate::vrp::VrpEvent;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Events kept for a slow subscriber before it starts losing them
+const EVENT_BUS_CAPACITY: usize = 256;
+
+/// Lines kept for a slow subscriber; output comes in bursts
+const LINE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionEvent {
+    pub session_id: String,
+    pub event: VrpEvent,
+}
+
+/// A complete output line, see pipeline.rs
+#[derive(Debug, Clone)]
+pub struct SessionLine {
+    pub session_id: String,
+    pub line: String,
+}
+
+pub struct EventBus {
+    tx: broadcast::Sender<SessionEvent>,
+    lines: broadcast::Sender<SessionLine>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        let (lines, _) = broadcast::channel(LINE_CAPACITY);
+        Self { tx, lines }
+    }
+}
+
+impl EventBus {
+    /// Events are dropped while nobody is subscribed
+    pub fn publish(&self, session_id: &str, event: &VrpEvent) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.tx.send(SessionEvent {
+            session_id: session_id.to_string(),
+            event: event.clone(),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Lines are dropped while nobody is subscribed
+    pub fn publish_line(&self, session_id: &str, line: String) {
+        if self.lines.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.lines.send(SessionLine {
+            session_id: session_id.to_string(),
+            line,
+        });
+    }
+
+    pub fn subscribe_lines(&self) -> broadcast::Receiver<SessionLine> {
+        self.lines.subscribe()
+    }
+}
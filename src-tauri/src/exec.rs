@@ -0,0 +1,187 @@
+//! One-off SSH commands without a PTY or a session.
+//!
+//! `run_command` connects with a `SessionConfig` (jump hosts, key and
+//! algorithms included), sends a single `exec` request and collects stdout,
+//! stderr and the exit status, then disconnects. Nothing goes through the
+//! interactive data path, so there are no prompts, pagers or echoes to
+//! strip, which suits scripted polling of Linux boards. Host keys are
+//! stored on first use and changed ones refused, as for NETCONF.
+
+use crate::charset::Transcoder;
+use crate::known_hosts::{HostKeyCheck, KnownHosts};
+use crate::session::{Protocol, SessionConfig, SessionError};
+use crate::ssh;
+use russh::{ChannelMsg, Disconnect, Sig};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Output kept per stream; the rest is dropped and `truncated` set
+const MAX_OUTPUT: usize = 4 * 1024 * 1024;
+
+/// Result of `run_command`
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecOutput {
+    pub command: String,
+    /// Decoded with the config's charset
+    pub stdout: String,
+    pub stderr: String,
+    /// None if the server sent none, e.g. the command was killed
+    pub exit_status: Option<u32>,
+    /// Signal name without `SIG`, when the command was killed by one
+    pub exit_signal: Option<String>,
+    /// Output beyond 4 MiB on either stream was dropped
+    pub truncated: bool,
+    pub duration_ms: u64,
+}
+
+/// stdout and stderr as they arrive
+#[derive(Default)]
+struct Collector {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    truncated: bool,
+}
+
+impl Collector {
+    fn push(&mut self, stderr: bool, data: &[u8], limit: usize) {
+        let buf = if stderr {
+            &mut self.stderr
+        } else {
+            &mut self.stdout
+        };
+        let room = limit.saturating_sub(buf.len());
+        if data.len() > room {
+            self.truncated = true;
+        }
+        buf.extend_from_slice(&data[..data.len().min(room)]);
+    }
+}
+
+/// Run `command` on the host of `config`, waiting up to `timeout` for it to
+/// exit
+pub async fn run_command(
+    config: &SessionConfig,
+    command: &str,
+    known_hosts: &Arc<KnownHosts>,
+    timeout: Duration,
+) -> Result<ExecOutput, SessionError> {
+    if config.protocol != Protocol::Ssh {
+        return Err(SessionError::InvalidConfig(
+            "Commands without a PTY need SSH".to_string(),
+        ));
+    }
+    let id = Uuid::new_v4().to_string();
+    let started = Instant::now();
+    let ssh_config = ssh::client_config(config)?;
+    let key = ssh::load_key(&config.auth)?;
+
+    let run = async {
+        let (mut session, _jump_sessions) = ssh::connect(
+            &id,
+            (&config.host, config.port),
+            &config.jump_hosts,
+            Arc::new(ssh_config),
+            &HostKeyCheck::accept_new(known_hosts),
+            None,
+            None,
+            None,
+            config.connect_timeout(),
+        )
+        .await?;
+        ssh::authenticate(
+            &mut session,
+            &config.username,
+            &config.password,
+            key,
+            &config.auth_methods,
+        )
+        .await?;
+
+        let mut channel = session
+            .channel_open_session()
+            .await
+            .map_err(|e| SessionError::ChannelError(e.to_string()))?;
+        ssh::send_env(&id, &mut channel, &config.env).await;
+        channel
+            .exec(true, command)
+            .await
+            .map_err(|e| SessionError::ChannelError(e.to_string()))?;
+
+        let mut collected = Collector::default();
+        let (mut exit_status, mut exit_signal) = (None, None);
+        // Exit status may come after EOF, so read until the channel closes
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Data { data } => collected.push(false, &data, MAX_OUTPUT),
+                // Extended data type 1 is stderr
+                ChannelMsg::ExtendedData { data, ext: 1 } => {
+                    collected.push(true, &data, MAX_OUTPUT)
+                }
+                ChannelMsg::ExitStatus {
+                    exit_status: status,
+                } => exit_status = Some(status),
+                ChannelMsg::ExitSignal { signal_name, .. } => {
+                    exit_signal = Some(match signal_name {
+                        Sig::Custom(name) => name,
+                        sig => format!("{:?}", sig),
+                    })
+                }
+                ChannelMsg::Failure => {
+                    return Err(SessionError::ChannelError(
+                        "Server refused to run the command".to_string(),
+                    ))
+                }
+                _ => {}
+            }
+        }
+        let _ = session
+            .disconnect(Disconnect::ByApplication, "", "en")
+            .await;
+        Ok((collected, exit_status, exit_signal))
+    };
+
+    let (collected, exit_status, exit_signal) =
+        tokio::time::timeout(timeout, run).await.map_err(|_| {
+            warn!(id = %id, host = %config.host, command = %command, "Command timed out");
+            SessionError::Timeout(format!("{} did not exit within {:?}", command, timeout))
+        })??;
+
+    let decode = |bytes: Vec<u8>| {
+        let text = Transcoder::new(id.clone(), config.charset).decode(bytes);
+        String::from_utf8_lossy(&text).into_owned()
+    };
+    let output = ExecOutput {
+        command: command.to_string(),
+        stdout: decode(collected.stdout),
+        stderr: decode(collected.stderr),
+        exit_status,
+        exit_signal,
+        truncated: collected.truncated,
+        duration_ms: started.elapsed().as_millis() as u64,
+    };
+    info!(id = %id, host = %config.host, command = %command, exit_status = ?output.exit_status, duration_ms = output.duration_ms, "Command finished");
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collector_truncates_per_stream() {
+        let mut collected = Collector::default();
+        collected.push(false, b"uptime ", 10);
+        collected.push(true, b"warning", 10);
+        assert!(!collected.truncated);
+        collected.push(false, b"12 days", 10);
+        assert_eq!(collected.stdout, b"uptime 12 ");
+        assert_eq!(collected.stderr, b"warning");
+        assert!(collected.truncated);
+        collected.push(false, b"more", 10);
+        assert_eq!(collected.stdout.len(), 10);
+    }
+}
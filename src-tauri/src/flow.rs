@@ -0,0 +1,206 @@
+//! Backpressure on the SSH transport.
+//!
+//! russh adjusts channel windows as soon as data arrives and queues that data
+//! without bound, so not draining the channel doesn't slow the server down.
+//! Instead the socket to the first hop is read through `Gated`: while the
+//! `BackpressureController` of any session on the connection is paused,
+//! reads stop and TCP's own window stops the server, as for telnet. The
+//! sessions share the socket, so one stalled tab holds back its siblings
+//! rather than letting its own output grow without bound. Every `TRICKLE_EVERY` of pause
+//! one `TRICKLE` of bytes is let through, so keepalive replies (and with
+//! them the connection) survive a frontend that stalls for long.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::watch;
+use tokio::time::{Instant, Sleep};
+
+/// Bytes let through per trickle, more than one full SSH packet
+const TRICKLE: usize = 64 * 1024;
+
+/// Pause after which a trickle is let through
+const TRICKLE_EVERY: Duration = Duration::from_secs(5);
+
+/// Pause state of the sessions on one connection, shared by its gated
+/// socket
+#[derive(Default)]
+pub struct ReadGate {
+    pauses: Mutex<Vec<watch::Receiver<bool>>>,
+    /// The read waiting for the pause to end
+    waker: Mutex<Option<Waker>>,
+}
+
+impl ReadGate {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Also pause while `pause_rx`, from `BackpressureController::subscribe`,
+    /// is set, until its controller is dropped
+    pub fn attach(self: &Arc<Self>, pause_rx: watch::Receiver<bool>) {
+        self.pauses.lock().unwrap().push(pause_rx.clone());
+        let watcher = Arc::downgrade(self);
+        let mut rx = pause_rx;
+        tokio::spawn(async move {
+            while rx.changed().await.is_ok() {
+                let resumed = !*rx.borrow();
+                match watcher.upgrade() {
+                    Some(gate) if resumed => gate.wake(),
+                    Some(_) => {}
+                    None => return,
+                }
+            }
+            if let Some(gate) = watcher.upgrade() {
+                gate.wake();
+            }
+        });
+    }
+
+    /// A dropped controller, whose session ended while other sessions use
+    /// the connection, no longer pauses
+    fn paused(&self) -> bool {
+        let mut pauses = self.pauses.lock().unwrap();
+        pauses.retain(|rx| rx.has_changed().is_ok());
+        pauses.iter().any(|rx| *rx.borrow())
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A stream whose reads stop while its gate is paused; without a gate it
+/// passes everything through
+pub struct Gated<S> {
+    inner: S,
+    gate: Option<Arc<ReadGate>>,
+    trickle: Pin<Box<Sleep>>,
+    trickle_every: Duration,
+    /// Bytes left of the current trickle
+    allowance: usize,
+    /// Whether the trickle timer runs for the current pause
+    armed: bool,
+}
+
+impl<S> Gated<S> {
+    pub fn new(inner: S, gate: Option<Arc<ReadGate>>) -> Self {
+        Self {
+            inner,
+            gate,
+            trickle: Box::pin(tokio::time::sleep(TRICKLE_EVERY)),
+            trickle_every: TRICKLE_EVERY,
+            allowance: 0,
+            armed: false,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Gated<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let paused = match &this.gate {
+            Some(gate) if gate.paused() => {
+                *gate.waker.lock().unwrap() = Some(cx.waker().clone());
+                // The pause may have ended before the waker was stored
+                gate.paused()
+            }
+            _ => false,
+        };
+        if !paused {
+            this.armed = false;
+            this.allowance = 0;
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        if !this.armed {
+            this.armed = true;
+            this.trickle
+                .as_mut()
+                .reset(Instant::now() + this.trickle_every);
+        }
+        if this.allowance == 0 {
+            if this.trickle.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.allowance = TRICKLE;
+            this.trickle
+                .as_mut()
+                .reset(Instant::now() + this.trickle_every);
+        }
+        let mut chunk = vec![0; this.allowance.min(buf.remaining())];
+        let mut limited = ReadBuf::new(&mut chunk);
+        let result = Pin::new(&mut this.inner).poll_read(cx, &mut limited);
+        this.allowance -= limited.filled().len();
+        buf.put_slice(limited.filled());
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Gated<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, data)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_gated_reads() {
+        let (client, mut server) = tokio::io::duplex(256 * 1024);
+        let (pause_tx, pause_rx) = watch::channel(false);
+        let gate = ReadGate::new();
+        gate.attach(pause_rx);
+        let mut gated = Gated::new(client, Some(gate));
+        let mut buf = vec![0; 256 * 1024];
+
+        server.write_all(b"<HUAWEI>").await.unwrap();
+        assert_eq!(gated.read(&mut buf).await.unwrap(), 8);
+
+        // Paused: nothing is read until the pause ends
+        pause_tx.send_replace(true);
+        server.write_all(b"display version").await.unwrap();
+        let read = tokio::time::timeout(Duration::from_millis(100), gated.read(&mut buf));
+        assert!(read.await.is_err());
+        pause_tx.send_replace(false);
+        let read = tokio::time::timeout(Duration::from_secs(1), gated.read(&mut buf));
+        assert_eq!(read.await.unwrap().unwrap(), 15);
+
+        // A long pause lets one trickle through
+        gated.trickle_every = Duration::from_millis(50);
+        pause_tx.send_replace(true);
+        server.write_all(&vec![b'x'; 100 * 1024]).await.unwrap();
+        let read = tokio::time::timeout(Duration::from_secs(1), gated.read(&mut buf));
+        assert_eq!(read.await.unwrap().unwrap(), TRICKLE);
+        let read = tokio::time::timeout(Duration::from_millis(20), gated.read(&mut buf));
+        assert!(read.await.is_err());
+        pause_tx.send_replace(false);
+        let read = tokio::time::timeout(Duration::from_secs(1), gated.read(&mut buf));
+        assert_eq!(read.await.unwrap().unwrap(), 100 * 1024 - TRICKLE);
+    }
+}
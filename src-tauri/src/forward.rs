@@ -0,0 +1,465 @@
+//! Forwarding of what the app sees to a lab's log collector.
+//!
+//! With `Settings.forwarding.url` set, session audit lines (a connection
+//! reaching Ready and ending), new alerts and automation rule hits are sent
+//! as JSON records:
+//! - `udp://host[:port]` and `tcp://host[:port]` (port 514) - RFC 5424
+//!   syslog, facility local0, with the record as the message; TCP frames
+//!   are octet-counted (RFC 6587) and the connection is kept open
+//! - `http://host[:port]/path` - one `POST` of the record per event
+//!
+//! A repeat of an alert within its throttle window is not sent again.
+//! Records wait in a bounded queue; a full queue drops them, and a
+//! collector that fails to take one loses it. Both are counted in
+//! `get_forwarder_status`.
+
+use crate::alerts;
+use crate::alerts::{AlertEngine, AlertEvent, AlertSeverity};
+use crate::automation::AutomationEvent;
+use crate::history::ConnectionRecord;
+use crate::session::SessionManager;
+use crate::settings::SettingsStore;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tracing::{info, warn};
+use url::Url;
+
+/// Records waiting for the collector
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Connecting plus writing one record
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SYSLOG_PORT: u16 = 514;
+
+/// local0
+const FACILITY: u8 = 16;
+
+const APP_NAME: &str = "bspt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardKind {
+    /// A session connected or ended
+    Session,
+    Alert,
+    /// An automation rule fired
+    Trigger,
+}
+
+/// Event forwarding, the `forwarding` section of the settings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ForwardSettings {
+    /// Collector URL; None disables forwarding
+    pub url: Option<String>,
+    /// Kinds forwarded; empty forwards all of them
+    pub events: Vec<ForwardKind>,
+    /// Least severe alert forwarded; default any
+    pub min_severity: Option<AlertSeverity>,
+}
+
+impl ForwardSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.url {
+            Some(url) => Target::parse(url).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    fn wants(&self, record: &ForwardRecord) -> bool {
+        (self.events.is_empty() || self.events.contains(&record.kind))
+            && (record.kind != ForwardKind::Alert
+                || self.min_severity.is_none_or(|min| record.severity <= min))
+    }
+}
+
+/// One forwarded event
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwardRecord {
+    pub app: &'static str,
+    pub kind: ForwardKind,
+    /// Unix milliseconds
+    pub at: i64,
+    pub severity: AlertSeverity,
+    pub host: Option<String>,
+    pub session_id: Option<String>,
+    pub message: String,
+    /// The connection record, alert or rule hit
+    pub data: serde_json::Value,
+}
+
+impl ForwardRecord {
+    fn new(kind: ForwardKind, severity: AlertSeverity, message: String) -> Self {
+        Self {
+            app: APP_NAME,
+            kind,
+            at: chrono::Utc::now().timestamp_millis(),
+            severity,
+            host: None,
+            session_id: None,
+            message,
+            data: serde_json::Value::Null,
+        }
+    }
+
+    fn session(record: &ConnectionRecord) -> Self {
+        let protocol = format!("{:?}", record.protocol).to_lowercase();
+        let peer = format!(
+            "{}@{}:{} ({})",
+            record.username, record.host, record.port, protocol
+        );
+        let message = match record.duration_ms {
+            Some(ms) => format!("{} disconnected after {}s", peer, ms / 1000),
+            None => format!("{} connected", peer),
+        };
+        Self {
+            host: Some(record.host.clone()),
+            session_id: Some(record.session_id.clone()),
+            data: serde_json::to_value(record).unwrap_or_default(),
+            ..Self::new(ForwardKind::Session, AlertSeverity::Info, message)
+        }
+    }
+
+    fn alert(event: &AlertEvent) -> Self {
+        let alert = &event.alert;
+        Self {
+            host: alert.host.clone(),
+            session_id: alert.session_id.clone(),
+            data: serde_json::to_value(alert).unwrap_or_default(),
+            ..Self::new(
+                ForwardKind::Alert,
+                alert.severity,
+                format!("{}: {}", alert.title, alert.message),
+            )
+        }
+    }
+
+    fn trigger(manager: &SessionManager, rule_id: &str, rule_name: &str, session_id: &str) -> Self {
+        Self {
+            host: manager.get(session_id).map(|h| h.config.host.clone()),
+            session_id: Some(session_id.to_string()),
+            data: serde_json::json!({ "rule_id": rule_id, "rule_name": rule_name }),
+            ..Self::new(
+                ForwardKind::Trigger,
+                AlertSeverity::Info,
+                format!("Automation rule {} fired", rule_name),
+            )
+        }
+    }
+
+    /// RFC 5424 with the JSON record as the message
+    fn syslog_line(&self) -> String {
+        let severity = match self.severity {
+            AlertSeverity::Critical => 2,
+            AlertSeverity::Major => 3,
+            AlertSeverity::Minor | AlertSeverity::Warning => 4,
+            AlertSeverity::Info => 6,
+        };
+        let timestamp = chrono::DateTime::from_timestamp_millis(self.at)
+            .unwrap_or_default()
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let kind = serde_json::to_value(self.kind).unwrap_or_default();
+        format!(
+            "<{}>1 {} {} {} - {} - {}",
+            FACILITY * 8 + severity,
+            timestamp,
+            self.host.as_deref().unwrap_or("-"),
+            APP_NAME,
+            kind.as_str().unwrap_or("-"),
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+}
+
+/// Where records go, parsed from `ForwardSettings.url`
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Udp(String),
+    Tcp(String),
+    Http {
+        addr: String,
+        host: String,
+        path: String,
+    },
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Self, String> {
+        let parsed = Url::parse(url).map_err(|e| format!("forwarding url {}: {}", url, e))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| format!("forwarding url {} has no host", url))?;
+        let addr = |default: u16| format!("{}:{}", host, parsed.port().unwrap_or(default));
+        match parsed.scheme() {
+            "udp" => Ok(Self::Udp(addr(SYSLOG_PORT))),
+            "tcp" => Ok(Self::Tcp(addr(SYSLOG_PORT))),
+            "http" => {
+                let path = match parsed.query() {
+                    Some(query) => format!("{}?{}", parsed.path(), query),
+                    None => parsed.path().to_string(),
+                };
+                Ok(Self::Http {
+                    addr: addr(80),
+                    host: parsed[url::Position::BeforeHost..url::Position::AfterPort].to_string(),
+                    path,
+                })
+            }
+            scheme => Err(format!(
+                "forwarding url scheme {} is not supported (udp, tcp or http)",
+                scheme
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ForwarderStatus {
+    pub sent: u64,
+    /// Dropped because the queue was full
+    pub dropped: u64,
+    /// Lost because the collector didn't take them
+    pub failed: u64,
+    pub last_error: Option<String>,
+    /// Unix milliseconds
+    pub last_sent_at: Option<i64>,
+}
+
+pub struct Forwarder {
+    queue: mpsc::Sender<(Target, ForwardRecord)>,
+    status: Arc<StdMutex<ForwarderStatus>>,
+}
+
+impl Forwarder {
+    /// Start the delivery task
+    pub fn new() -> Self {
+        let (queue, records) = mpsc::channel(QUEUE_CAPACITY);
+        let status = Arc::new(StdMutex::new(ForwarderStatus::default()));
+        tauri::async_runtime::spawn(deliver(records, Arc::clone(&status)));
+        Self { queue, status }
+    }
+
+    pub fn status(&self) -> ForwarderStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Queue `record` if the settings forward it
+    fn forward(&self, settings: &ForwardSettings, record: ForwardRecord) {
+        let Some(url) = &settings.url else {
+            return;
+        };
+        if !settings.wants(&record) {
+            return;
+        }
+        // Validated when the settings were saved
+        let Ok(target) = Target::parse(url) else {
+            return;
+        };
+        if self.queue.try_send((target, record)).is_err() {
+            self.status.lock().unwrap().dropped += 1;
+        }
+    }
+}
+
+async fn deliver(
+    mut records: mpsc::Receiver<(Target, ForwardRecord)>,
+    status: Arc<StdMutex<ForwarderStatus>>,
+) {
+    let mut udp: Option<UdpSocket> = None;
+    let mut tcp: Option<(String, TcpStream)> = None;
+    while let Some((target, record)) = records.recv().await {
+        let sent = tokio::time::timeout(SEND_TIMEOUT, send(&target, &record, &mut udp, &mut tcp))
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")));
+        let mut status = status.lock().unwrap();
+        match sent {
+            Ok(()) => {
+                status.sent += 1;
+                status.last_sent_at = Some(chrono::Utc::now().timestamp_millis());
+            }
+            Err(e) => {
+                if status.last_error.as_deref() != Some(&e.to_string()) {
+                    warn!(target = ?target, error = %e, "Failed to forward event");
+                }
+                // Reconnect for the next record
+                tcp = None;
+                status.failed += 1;
+                status.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+async fn send(
+    target: &Target,
+    record: &ForwardRecord,
+    udp: &mut Option<UdpSocket>,
+    tcp: &mut Option<(String, TcpStream)>,
+) -> io::Result<()> {
+    match target {
+        Target::Udp(addr) => {
+            if udp.is_none() {
+                *udp = Some(UdpSocket::bind("0.0.0.0:0").await?);
+            }
+            let line = record.syslog_line();
+            udp.as_ref().unwrap().send_to(line.as_bytes(), addr).await?;
+        }
+        Target::Tcp(addr) => {
+            if tcp.as_ref().is_none_or(|(connected, _)| connected != addr) {
+                *tcp = Some((addr.clone(), TcpStream::connect(addr).await?));
+                info!(target = %addr, "Connected to syslog collector");
+            }
+            let line = record.syslog_line();
+            let frame = format!("{} {}", line.len(), line);
+            let (_, stream) = tcp.as_mut().unwrap();
+            stream.write_all(frame.as_bytes()).await?;
+        }
+        Target::Http { addr, host, path } => {
+            let body = serde_json::to_vec(record).map_err(io::Error::from)?;
+            post(addr, host, path, &body).await?;
+        }
+    }
+    Ok(())
+}
+
+/// POST `body` and check for a 2xx status
+async fn post(addr: &str, host: &str, path: &str, body: &[u8]) -> io::Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        host,
+        APP_NAME,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut reply = Vec::new();
+    let mut chunk = [0u8; 512];
+    while !reply.contains(&b'\n') && reply.len() < 4096 {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        reply.extend_from_slice(&chunk[..n]);
+    }
+    let status_line = String::from_utf8_lossy(&reply);
+    let status_line = status_line.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "collector answered {:?}",
+            status_line.trim()
+        ))),
+    }
+}
+
+/// Forward connection records, alerts and automation hits while
+/// `forwarding.url` is set
+pub fn spawn_feeds(
+    forwarder: &Arc<Forwarder>,
+    settings: &Arc<Mutex<SettingsStore>>,
+    manager: &Arc<SessionManager>,
+    alert_engine: &AlertEngine,
+    automation: &broadcast::Sender<AutomationEvent>,
+) {
+    let mut connections = manager.history().subscribe();
+    let (sink, store) = (Arc::clone(forwarder), Arc::clone(settings));
+    tauri::async_runtime::spawn(async move {
+        while let Some(record) = alerts::next(&mut connections, "forward sessions").await {
+            let settings = store.lock().await.get().forwarding;
+            sink.forward(&settings, ForwardRecord::session(&record));
+        }
+    });
+
+    let mut raised = alert_engine.subscribe();
+    let (sink, store) = (Arc::clone(forwarder), Arc::clone(settings));
+    tauri::async_runtime::spawn(async move {
+        while let Some(event) = alerts::next(&mut raised, "forward alerts").await {
+            if event.alert.count > 1 {
+                continue;
+            }
+            let settings = store.lock().await.get().forwarding;
+            sink.forward(&settings, ForwardRecord::alert(&event));
+        }
+    });
+
+    let mut hits = automation.subscribe();
+    let (sink, store, sessions) = (
+        Arc::clone(forwarder),
+        Arc::clone(settings),
+        Arc::clone(manager),
+    );
+    tauri::async_runtime::spawn(async move {
+        while let Some(event) = alerts::next(&mut hits, "forward triggers").await {
+            let AutomationEvent::Fired {
+                rule_id,
+                rule_name,
+                session_id,
+            } = event
+            else {
+                continue;
+            };
+            let settings = store.lock().await.get().forwarding;
+            let record = ForwardRecord::trigger(&sessions, &rule_id, &rule_name, &session_id);
+            sink.forward(&settings, record);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_and_syslog_line() {
+        assert_eq!(
+            Target::parse("udp://10.0.0.9").unwrap(),
+            Target::Udp("10.0.0.9:514".to_string())
+        );
+        assert_eq!(
+            Target::parse("tcp://logs.lab:6514").unwrap(),
+            Target::Tcp("logs.lab:6514".to_string())
+        );
+        assert_eq!(
+            Target::parse("http://collector:8080/ingest?source=bspt").unwrap(),
+            Target::Http {
+                addr: "collector:8080".to_string(),
+                host: "collector:8080".to_string(),
+                path: "/ingest?source=bspt".to_string(),
+            }
+        );
+        assert!(Target::parse("https://collector/ingest").is_err());
+        assert!(Target::parse("not a url").is_err());
+
+        let mut record = ForwardRecord::new(
+            ForwardKind::Alert,
+            AlertSeverity::Major,
+            "core-1 slot 2 offline".to_string(),
+        );
+        record.at = 1_700_000_000_123;
+        record.host = Some("10.0.0.1".to_string());
+        let line = record.syslog_line();
+        assert!(line.starts_with("<131>1 2023-11-14T22:13:20.123Z 10.0.0.1 bspt - alert - {"));
+        assert!(line.contains(r#""message":"core-1 slot 2 offline""#));
+
+        let settings = ForwardSettings {
+            url: Some("udp://10.0.0.9".to_string()),
+            events: vec![ForwardKind::Alert, ForwardKind::Trigger],
+            min_severity: Some(AlertSeverity::Warning),
+        };
+        assert!(settings.wants(&record));
+        record.severity = AlertSeverity::Info;
+        assert!(!settings.wants(&record));
+        record.kind = ForwardKind::Trigger;
+        assert!(settings.wants(&record));
+        record.kind = ForwardKind::Session;
+        assert!(!settings.wants(&record));
+    }
+}
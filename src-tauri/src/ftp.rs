@@ -0,0 +1,940 @@
+//! FTP server (RFC 959) for devices whose clients don't speak TFTP.
+//!
+//! One configured user, jailed to a root directory: paths are virtual (`/`
+//! is the root) and `..` stops there. Passive (`PASV`, `EPSV`) and active
+//! (`PORT`) data connections are supported, both only to the client's own
+//! address. Transfers are always binary; `TYPE A` is accepted but files go
+//! out unchanged, which is what copying images and configurations needs.
+//! Uploads, deletes and new directories need `allow_write`. Every `RETR`
+//! and `STOR` is recorded and emitted as `ftp` events.
+//!
+//! There is no FTPS, and no SFTP: serving it would need an SFTP subsystem
+//! the dependencies don't provide.
+
+use crate::tftp::{TransferDirection, TransferState};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::Metadata;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::{timeout, Instant};
+use tracing::{debug, info, warn};
+
+pub const FTP_PORT: u16 = 21;
+
+/// Transfers kept for `list_ftp_transfers`
+const MAX_TRANSFERS: usize = 200;
+
+/// Least time between two progress events of one transfer
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A control connection with no command for this long is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Wait for the data connection after `LIST`, `RETR` or `STOR`
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Failed `PASS` attempts before the connection is closed
+const MAX_LOGIN_ATTEMPTS: u32 = 3;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Error, Debug)]
+pub enum FtpError {
+    #[error("FTP server already running")]
+    AlreadyRunning,
+    #[error("FTP server not running")]
+    NotRunning,
+    #[error("Invalid FTP server options: {0}")]
+    Invalid(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl From<FtpError> for String {
+    fn from(err: FtpError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FtpOptions {
+    /// Directory the user is jailed to; must exist
+    pub root: String,
+    pub username: String,
+    pub password: String,
+    /// Default `0.0.0.0`
+    #[serde(default)]
+    pub bind: Option<String>,
+    /// Default `FTP_PORT`
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Accept uploads, deletes and new directories
+    #[serde(default)]
+    pub allow_write: bool,
+    /// Client IPs accepted; empty accepts any
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+    /// IPv4 address `PASV` gives out; default the one the client connected
+    /// to, which is wrong behind NAT
+    #[serde(default)]
+    pub passive_address: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FtpStatus {
+    pub running: bool,
+    pub addr: Option<String>,
+    pub root: Option<String>,
+    pub username: Option<String>,
+    pub allow_write: bool,
+}
+
+/// One `RETR` or `STOR`; also the body of `ftp` events
+#[derive(Debug, Clone, Serialize)]
+pub struct FtpTransfer {
+    pub id: String,
+    pub peer: String,
+    /// Virtual path, e.g. `/images/V800R021.cc`
+    pub file: String,
+    pub direction: TransferDirection,
+    pub bytes: u64,
+    /// The file size for downloads
+    pub total: Option<u64>,
+    pub state: TransferState,
+    pub error: Option<String>,
+    /// Unix milliseconds
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+/// What sessions need from the options
+struct Config {
+    root: PathBuf,
+    username: String,
+    password: String,
+    allow_write: bool,
+    allowed: Vec<IpAddr>,
+    passive_address: Option<Ipv4Addr>,
+}
+
+struct Running {
+    status: FtpStatus,
+    /// Dropped to stop the server and its sessions
+    _shutdown: watch::Sender<()>,
+}
+
+/// The server, started and stopped from the frontend
+pub struct FtpService {
+    running: Mutex<Option<Running>>,
+    transfers: Mutex<VecDeque<FtpTransfer>>,
+}
+
+impl FtpService {
+    pub fn new() -> Self {
+        Self {
+            running: Mutex::new(None),
+            transfers: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub async fn start(
+        self: &Arc<Self>,
+        options: FtpOptions,
+        app_handle: tauri::AppHandle,
+    ) -> Result<FtpStatus, FtpError> {
+        if self.running.lock().unwrap().is_some() {
+            return Err(FtpError::AlreadyRunning);
+        }
+        let root = PathBuf::from(&options.root);
+        if !root.is_dir() {
+            return Err(FtpError::Invalid(format!(
+                "{} is not a directory",
+                options.root
+            )));
+        }
+        if options.username.is_empty() || options.password.is_empty() {
+            return Err(FtpError::Invalid(
+                "a username and password are required".to_string(),
+            ));
+        }
+        let allowed = options
+            .allowed_clients
+            .iter()
+            .map(|client| {
+                client
+                    .parse::<IpAddr>()
+                    .map(|ip| ip.to_canonical())
+                    .map_err(|_| FtpError::Invalid(format!("not an IP address: {}", client)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let passive_address = options
+            .passive_address
+            .as_deref()
+            .map(|address| {
+                address
+                    .parse::<Ipv4Addr>()
+                    .map_err(|_| FtpError::Invalid(format!("not an IPv4 address: {}", address)))
+            })
+            .transpose()?;
+
+        let bind = options.bind.as_deref().unwrap_or("0.0.0.0");
+        let listener = TcpListener::bind((bind, options.port.unwrap_or(FTP_PORT))).await?;
+        let status = FtpStatus {
+            running: true,
+            addr: listener.local_addr().ok().map(|a| a.to_string()),
+            root: Some(root.display().to_string()),
+            username: Some(options.username.clone()),
+            allow_write: options.allow_write,
+        };
+        let config = Arc::new(Config {
+            root,
+            username: options.username,
+            password: options.password,
+            allow_write: options.allow_write,
+            allowed,
+            passive_address,
+        });
+        let (shutdown, stopped) = watch::channel(());
+        let service = Arc::clone(self);
+        tokio::spawn(async move { service.listen(listener, config, app_handle, stopped).await });
+
+        info!(addr = ?status.addr, root = ?status.root, "FTP server started");
+        *self.running.lock().unwrap() = Some(Running {
+            status: status.clone(),
+            _shutdown: shutdown,
+        });
+        Ok(status)
+    }
+
+    /// Stop listening; open sessions and their transfers are closed
+    pub fn stop(&self) -> Result<(), FtpError> {
+        self.running
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(FtpError::NotRunning)?;
+        info!("FTP server stopped");
+        Ok(())
+    }
+
+    pub fn status(&self) -> FtpStatus {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|r| r.status.clone())
+            .unwrap_or_default()
+    }
+
+    /// Recent transfers, oldest first
+    pub fn transfers(&self) -> Vec<FtpTransfer> {
+        self.transfers.lock().unwrap().iter().cloned().collect()
+    }
+
+    async fn listen(
+        self: Arc<Self>,
+        listener: TcpListener,
+        config: Arc<Config>,
+        app_handle: tauri::AppHandle,
+        mut stopped: watch::Receiver<()>,
+    ) {
+        let mut sessions = JoinSet::new();
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!(error = %e, "FTP accept failed");
+                        continue;
+                    }
+                },
+                _ = stopped.changed() => break,
+            };
+            while sessions.try_join_next().is_some() {}
+            if !config.allowed.is_empty() && !config.allowed.contains(&peer.ip().to_canonical()) {
+                warn!(peer = %peer, "FTP connection from a client not allowed");
+                continue;
+            }
+            let session = Session {
+                service: Arc::clone(&self),
+                config: Arc::clone(&config),
+                app_handle: app_handle.clone(),
+                peer,
+                local: match stream.local_addr() {
+                    Ok(local) => local,
+                    Err(_) => continue,
+                },
+                user: None,
+                logged_in: false,
+                failures: 0,
+                cwd: Vec::new(),
+                data: None,
+            };
+            sessions.spawn(session.run(stream));
+        }
+
+        sessions.shutdown().await;
+        let aborted: Vec<FtpTransfer> = self
+            .transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| t.state == TransferState::Active)
+            .cloned()
+            .collect();
+        for transfer in aborted {
+            self.finish(
+                transfer,
+                Err(io::Error::other("server stopped")),
+                &app_handle,
+            );
+        }
+    }
+
+    fn begin(
+        &self,
+        peer: SocketAddr,
+        file: String,
+        direction: TransferDirection,
+        total: Option<u64>,
+        app_handle: &tauri::AppHandle,
+    ) -> FtpTransfer {
+        let transfer = FtpTransfer {
+            id: uuid::Uuid::new_v4().to_string(),
+            peer: peer.to_string(),
+            file,
+            direction,
+            bytes: 0,
+            total,
+            state: TransferState::Active,
+            error: None,
+            started_at: chrono::Utc::now().timestamp_millis(),
+            finished_at: None,
+        };
+        info!(peer = %peer, file = %transfer.file, ?direction, "FTP transfer started");
+        self.record(&transfer, app_handle);
+        transfer
+    }
+
+    /// A progress callback recording and emitting at most every
+    /// `PROGRESS_INTERVAL`
+    fn progress<'a>(
+        &'a self,
+        transfer: &FtpTransfer,
+        app_handle: &'a tauri::AppHandle,
+    ) -> impl FnMut(u64) + 'a {
+        let mut transfer = transfer.clone();
+        let mut last = Instant::now();
+        move |bytes| {
+            transfer.bytes = bytes;
+            if last.elapsed() >= PROGRESS_INTERVAL {
+                last = Instant::now();
+                self.record(&transfer, app_handle);
+            }
+        }
+    }
+
+    fn finish(
+        &self,
+        mut transfer: FtpTransfer,
+        result: io::Result<u64>,
+        app_handle: &tauri::AppHandle,
+    ) {
+        match result {
+            Ok(bytes) => {
+                info!(peer = %transfer.peer, file = %transfer.file, bytes, "FTP transfer finished");
+                transfer.bytes = bytes;
+                transfer.state = TransferState::Finished;
+            }
+            Err(e) => {
+                warn!(peer = %transfer.peer, file = %transfer.file, error = %e, "FTP transfer failed");
+                if let Some(recorded) = self
+                    .transfers
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .rfind(|t| t.id == transfer.id)
+                {
+                    transfer.bytes = recorded.bytes;
+                }
+                transfer.state = TransferState::Failed;
+                transfer.error = Some(e.to_string());
+            }
+        }
+        transfer.finished_at = Some(chrono::Utc::now().timestamp_millis());
+        self.record(&transfer, app_handle);
+    }
+
+    /// Store the transfer's latest state and emit it as `ftp`
+    fn record(&self, transfer: &FtpTransfer, app_handle: &tauri::AppHandle) {
+        {
+            let mut transfers = self.transfers.lock().unwrap();
+            match transfers.iter_mut().rfind(|t| t.id == transfer.id) {
+                Some(existing) => *existing = transfer.clone(),
+                None => {
+                    if transfers.len() >= MAX_TRANSFERS {
+                        transfers.pop_front();
+                    }
+                    transfers.push_back(transfer.clone());
+                }
+            }
+        }
+        if let Err(e) = app_handle.emit("ftp", transfer) {
+            warn!(error = %e, "Failed to emit FTP transfer");
+        }
+    }
+}
+
+/// Where the next data connection comes from
+enum DataChannel {
+    /// `PASV` / `EPSV`: the client connects to us
+    Passive(TcpListener),
+    /// `PORT`: we connect to the client
+    Active(SocketAddr),
+}
+
+/// One control connection
+struct Session {
+    service: Arc<FtpService>,
+    config: Arc<Config>,
+    app_handle: tauri::AppHandle,
+    peer: SocketAddr,
+    local: SocketAddr,
+    user: Option<String>,
+    logged_in: bool,
+    failures: u32,
+    /// Virtual path of the current directory
+    cwd: Vec<String>,
+    data: Option<DataChannel>,
+}
+
+impl Session {
+    async fn run(mut self, stream: TcpStream) {
+        let (read, mut control) = stream.into_split();
+        let mut lines = BufReader::new(read);
+        if reply(&mut control, 220, "bspt FTP server ready")
+            .await
+            .is_err()
+        {
+            return;
+        }
+        debug!(peer = %self.peer, "FTP session started");
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match timeout(IDLE_TIMEOUT, lines.read_line(&mut line)).await {
+                Ok(Ok(0)) | Ok(Err(_)) => break,
+                Ok(Ok(_)) => {}
+                Err(_) => {
+                    let _ = reply(&mut control, 421, "Idle timeout").await;
+                    break;
+                }
+            }
+            let command = line.trim_end_matches(['\r', '\n']);
+            let (verb, arg) = command.split_once(' ').unwrap_or((command, ""));
+            match self
+                .handle(&verb.to_ascii_uppercase(), arg, &mut control)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) | Err(_) => break,
+            }
+        }
+        debug!(peer = %self.peer, "FTP session ended");
+    }
+
+    /// Answer one command; `false` ends the session
+    async fn handle(
+        &mut self,
+        verb: &str,
+        arg: &str,
+        control: &mut OwnedWriteHalf,
+    ) -> io::Result<bool> {
+        match verb {
+            "USER" => {
+                self.user = Some(arg.to_string());
+                self.logged_in = false;
+                reply(control, 331, "Password required").await?;
+            }
+            "PASS" => {
+                if self.user.as_deref() == Some(self.config.username.as_str())
+                    && arg == self.config.password
+                {
+                    self.logged_in = true;
+                    info!(peer = %self.peer, user = %self.config.username, "FTP login");
+                    reply(control, 230, "Logged in").await?;
+                } else {
+                    self.failures += 1;
+                    warn!(peer = %self.peer, user = ?self.user, "FTP login failed");
+                    reply(control, 530, "Login incorrect").await?;
+                    return Ok(self.failures < MAX_LOGIN_ATTEMPTS);
+                }
+            }
+            "QUIT" => {
+                reply(control, 221, "Goodbye").await?;
+                return Ok(false);
+            }
+            "SYST" => reply(control, 215, "UNIX Type: L8").await?,
+            "FEAT" => control
+                .write_all(
+                    b"211-Features:\r\n EPSV\r\n MDTM\r\n PASV\r\n SIZE\r\n UTF8\r\n211 End\r\n",
+                )
+                .await?,
+            "NOOP" => reply(control, 200, "OK").await?,
+            "OPTS" if arg.eq_ignore_ascii_case("UTF8 ON") => {
+                reply(control, 200, "Always in UTF8 mode").await?
+            }
+            _ if !self.logged_in => reply(control, 530, "Please log in with USER and PASS").await?,
+            "PWD" | "XPWD" => {
+                let cwd = format!("\"{}\" is the current directory", display(&self.cwd));
+                reply(control, 257, &cwd).await?
+            }
+            "CWD" | "XCWD" => match self.resolve(arg) {
+                Some((parts, path)) if path.is_dir() => {
+                    self.cwd = parts;
+                    reply(control, 250, "Directory changed").await?
+                }
+                _ => reply(control, 550, "No such directory").await?,
+            },
+            "CDUP" | "XCUP" => {
+                self.cwd.pop();
+                reply(control, 250, "Directory changed").await?
+            }
+            "TYPE" => reply(control, 200, "Type set").await?,
+            "MODE" if arg.eq_ignore_ascii_case("S") => reply(control, 200, "Mode set").await?,
+            "STRU" if arg.eq_ignore_ascii_case("F") => reply(control, 200, "Structure set").await?,
+            "PASV" => self.passive(false, control).await?,
+            "EPSV" => self.passive(true, control).await?,
+            "PORT" => match parse_port(arg) {
+                Some(addr) if addr.ip() == self.peer.ip().to_canonical() => {
+                    self.data = Some(DataChannel::Active(addr));
+                    reply(control, 200, "PORT command successful").await?
+                }
+                // Connecting anywhere else would be an FTP bounce
+                _ => reply(control, 500, "Illegal PORT command").await?,
+            },
+            "LIST" | "NLST" => self.list(verb == "NLST", arg, control).await?,
+            "SIZE" | "MDTM" => {
+                let metadata = match self.resolve(arg) {
+                    Some((_, path)) => tokio::fs::metadata(path).await.ok(),
+                    None => None,
+                };
+                match metadata.filter(|m| m.is_file()) {
+                    Some(m) if verb == "SIZE" => reply(control, 213, &m.len().to_string()).await?,
+                    Some(m) => {
+                        let modified = modified(&m).format("%Y%m%d%H%M%S").to_string();
+                        reply(control, 213, &modified).await?
+                    }
+                    None => reply(control, 550, "No such file").await?,
+                }
+            }
+            "RETR" => self.retrieve(arg, control).await?,
+            "STOR" => self.store(arg, control).await?,
+            "DELE" | "MKD" | "XMKD" | "RMD" | "XRMD" => self.modify(verb, arg, control).await?,
+            _ => reply(control, 502, "Command not implemented").await?,
+        }
+        Ok(true)
+    }
+
+    fn resolve(&self, arg: &str) -> Option<(Vec<String>, PathBuf)> {
+        resolve(&self.config.root, &self.cwd, arg)
+    }
+
+    async fn passive(&mut self, extended: bool, control: &mut OwnedWriteHalf) -> io::Result<()> {
+        let listener = match TcpListener::bind(SocketAddr::new(self.local.ip(), 0)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!(peer = %self.peer, error = %e, "Failed to open a passive port");
+                return reply(control, 425, "Can't open data connection").await;
+            }
+        };
+        let port = listener.local_addr()?.port();
+        if extended {
+            let message = format!("Entering Extended Passive Mode (|||{}|)", port);
+            reply(control, 229, &message).await?;
+        } else {
+            let local = match self.local.ip().to_canonical() {
+                IpAddr::V4(ip) => Some(ip),
+                IpAddr::V6(_) => None,
+            };
+            let Some(ip) = self.config.passive_address.or(local) else {
+                return reply(control, 425, "PASV needs IPv4, use EPSV").await;
+            };
+            let [a, b, c, d] = ip.octets();
+            let message = format!(
+                "Entering Passive Mode ({},{},{},{},{},{})",
+                a,
+                b,
+                c,
+                d,
+                port >> 8,
+                port & 0xff
+            );
+            reply(control, 227, &message).await?;
+        }
+        self.data = Some(DataChannel::Passive(listener));
+        Ok(())
+    }
+
+    /// The data connection set up by the last `PASV`, `EPSV` or `PORT`
+    async fn open_data(&mut self) -> io::Result<TcpStream> {
+        let timed_out = |_| io::Error::new(io::ErrorKind::TimedOut, "no data connection");
+        match self.data.take() {
+            Some(DataChannel::Passive(listener)) => loop {
+                let (stream, from) = timeout(DATA_TIMEOUT, listener.accept())
+                    .await
+                    .map_err(timed_out)??;
+                if from.ip().to_canonical() == self.peer.ip().to_canonical() {
+                    return Ok(stream);
+                }
+                warn!(peer = %self.peer, from = %from, "Refusing a data connection from another address");
+            },
+            Some(DataChannel::Active(addr)) => timeout(DATA_TIMEOUT, TcpStream::connect(addr))
+                .await
+                .map_err(timed_out)?,
+            None => Err(io::Error::other("no PORT or PASV")),
+        }
+    }
+
+    async fn list(
+        &mut self,
+        names_only: bool,
+        arg: &str,
+        control: &mut OwnedWriteHalf,
+    ) -> io::Result<()> {
+        // Options such as `-la` aren't supported and are skipped
+        let arg = arg
+            .split_whitespace()
+            .filter(|part| !part.starts_with('-'))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let Some((_, path)) = self.resolve(&arg) else {
+            return reply(control, 550, "No such file or directory").await;
+        };
+        let Ok(listing) = listing(&path, names_only).await else {
+            return reply(control, 550, "No such file or directory").await;
+        };
+        reply(control, 150, "Here comes the directory listing").await?;
+        let sent = async {
+            let mut data = self.open_data().await?;
+            data.write_all(listing.as_bytes()).await?;
+            data.shutdown().await
+        }
+        .await;
+        match sent {
+            Ok(()) => reply(control, 226, "Directory send OK").await,
+            Err(e) => reply(control, 425, &format!("Listing failed: {}", e)).await,
+        }
+    }
+
+    async fn retrieve(&mut self, arg: &str, control: &mut OwnedWriteHalf) -> io::Result<()> {
+        let opened = match self.resolve(arg) {
+            Some((parts, path)) => match tokio::fs::File::open(&path).await {
+                Ok(file) => match file.metadata().await {
+                    Ok(metadata) if metadata.is_file() => Some((parts, file, metadata.len())),
+                    _ => None,
+                },
+                Err(_) => None,
+            },
+            None => None,
+        };
+        let Some((parts, mut file, size)) = opened else {
+            return reply(control, 550, "No such file").await;
+        };
+        let name = display(&parts);
+        let message = format!(
+            "Opening BINARY mode data connection for {} ({} bytes)",
+            name, size
+        );
+        reply(control, 150, &message).await?;
+        let mut data = match self.open_data().await {
+            Ok(data) => data,
+            Err(e) => {
+                return reply(control, 425, &format!("Can't open data connection: {}", e)).await
+            }
+        };
+
+        let service = &self.service;
+        let transfer = service.begin(
+            self.peer,
+            name,
+            TransferDirection::Read,
+            Some(size),
+            &self.app_handle,
+        );
+        let mut progress = service.progress(&transfer, &self.app_handle);
+        let result = async {
+            let sent = copy(&mut file, &mut data, &mut progress).await?;
+            data.shutdown().await?;
+            Ok(sent)
+        }
+        .await;
+        let done = result.is_ok();
+        service.finish(transfer, result, &self.app_handle);
+        if done {
+            reply(control, 226, "Transfer complete").await
+        } else {
+            reply(control, 426, "Transfer aborted").await
+        }
+    }
+
+    /// Uploads go to `name.part` first, so a failed one leaves any previous
+    /// file in place
+    async fn store(&mut self, arg: &str, control: &mut OwnedWriteHalf) -> io::Result<()> {
+        if !self.config.allow_write {
+            return reply(control, 550, "Permission denied").await;
+        }
+        let Some((parts, path)) = self.resolve(arg).filter(|(parts, _)| !parts.is_empty()) else {
+            return reply(control, 553, "File name not allowed").await;
+        };
+        let part = PathBuf::from(format!("{}.part", path.display()));
+        let Ok(mut file) = tokio::fs::File::create(&part).await else {
+            return reply(control, 553, "Can't create file").await;
+        };
+        let name = display(&parts);
+        reply(control, 150, &format!("Ok to send data for {}", name)).await?;
+        let mut data = match self.open_data().await {
+            Ok(data) => data,
+            Err(e) => {
+                drop(file);
+                let _ = tokio::fs::remove_file(&part).await;
+                return reply(control, 425, &format!("Can't open data connection: {}", e)).await;
+            }
+        };
+
+        let service = &self.service;
+        let transfer = service.begin(
+            self.peer,
+            name,
+            TransferDirection::Write,
+            None,
+            &self.app_handle,
+        );
+        let mut progress = service.progress(&transfer, &self.app_handle);
+        let mut result = copy(&mut data, &mut file, &mut progress).await;
+        drop(file);
+        if result.is_ok() {
+            if let Err(e) = tokio::fs::rename(&part, &path).await {
+                result = Err(e);
+            }
+        }
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&part).await;
+        }
+        let done = result.is_ok();
+        service.finish(transfer, result, &self.app_handle);
+        if done {
+            reply(control, 226, "Transfer complete").await
+        } else {
+            reply(control, 451, "Transfer aborted").await
+        }
+    }
+
+    async fn modify(
+        &mut self,
+        verb: &str,
+        arg: &str,
+        control: &mut OwnedWriteHalf,
+    ) -> io::Result<()> {
+        if !self.config.allow_write {
+            return reply(control, 550, "Permission denied").await;
+        }
+        let Some((parts, path)) = self.resolve(arg).filter(|(parts, _)| !parts.is_empty()) else {
+            return reply(control, 550, "Not allowed").await;
+        };
+        let result = match verb {
+            "DELE" => tokio::fs::remove_file(&path).await,
+            "RMD" | "XRMD" => tokio::fs::remove_dir(&path).await,
+            _ => tokio::fs::create_dir(&path).await,
+        };
+        match (result, verb) {
+            (Ok(()), "MKD" | "XMKD") => {
+                let message = format!("\"{}\" created", display(&parts));
+                reply(control, 257, &message).await
+            }
+            (Ok(()), _) => reply(control, 250, "Done").await,
+            (Err(e), _) => reply(control, 550, &e.to_string()).await,
+        }
+    }
+}
+
+async fn reply(control: &mut OwnedWriteHalf, code: u16, message: &str) -> io::Result<()> {
+    control
+        .write_all(format!("{} {}\r\n", code, message).as_bytes())
+        .await
+}
+
+/// The virtual path `arg` names from `cwd`, and where that is under `root`.
+/// `..` stops at the root; backslashes and drive prefixes are refused.
+fn resolve(root: &Path, cwd: &[String], arg: &str) -> Option<(Vec<String>, PathBuf)> {
+    let mut parts = if arg.starts_with('/') {
+        Vec::new()
+    } else {
+        cwd.to_vec()
+    };
+    for part in arg.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part if part.contains(['\\', ':']) => return None,
+            part => parts.push(part.to_string()),
+        }
+    }
+    let path = parts
+        .iter()
+        .fold(root.to_path_buf(), |path, part| path.join(part));
+    Some((parts, path))
+}
+
+fn display(parts: &[String]) -> String {
+    format!("/{}", parts.join("/"))
+}
+
+/// `a,b,c,d,p1,p2` from a `PORT` command
+fn parse_port(arg: &str) -> Option<SocketAddr> {
+    let numbers = arg
+        .split(',')
+        .map(|n| n.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let [a, b, c, d, p1, p2] = numbers[..] else {
+        return None;
+    };
+    let port = u16::from_be_bytes([p1, p2]);
+    Some(SocketAddr::new(IpAddr::from([a, b, c, d]), port))
+}
+
+fn modified(metadata: &Metadata) -> chrono::DateTime<chrono::Utc> {
+    metadata.modified().map(Into::into).unwrap_or_default()
+}
+
+/// `ls -l` style lines, or only names for `NLST`
+async fn listing(path: &Path, names_only: bool) -> io::Result<String> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let mut entries = Vec::new();
+    if metadata.is_dir() {
+        let mut dir = tokio::fs::read_dir(path).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if let Ok(metadata) = entry.metadata().await {
+                entries.push((entry.file_name().to_string_lossy().into_owned(), metadata));
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+    } else {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        entries.push((name, metadata));
+    }
+    let now = chrono::Utc::now();
+    Ok(entries
+        .iter()
+        .map(|(name, metadata)| {
+            if names_only {
+                format!("{}\r\n", name)
+            } else {
+                list_line(
+                    name,
+                    metadata.is_dir(),
+                    metadata.len(),
+                    modified(metadata),
+                    now,
+                )
+            }
+        })
+        .collect())
+}
+
+fn list_line(
+    name: &str,
+    is_dir: bool,
+    size: u64,
+    modified: chrono::DateTime<chrono::Utc>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let mode = if is_dir { "drwxr-xr-x" } else { "-rw-r--r--" };
+    // Like `ls`: the year instead of the time for files over half a year old
+    let date = if now - modified > chrono::Duration::days(180) {
+        modified.format("%b %d  %Y")
+    } else {
+        modified.format("%b %d %H:%M")
+    };
+    format!("{} 1 ftp ftp {:>12} {} {}\r\n", mode, size, date, name)
+}
+
+async fn copy<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    progress: &mut impl FnMut(u64),
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut total = 0u64;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n]).await?;
+        total += n as u64;
+        progress(total);
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_paths_and_listing() {
+        let root = Path::new("/srv/ftp");
+        let cwd = vec!["images".to_string()];
+        let (parts, path) = resolve(root, &cwd, "V800R021.cc").unwrap();
+        assert_eq!(display(&parts), "/images/V800R021.cc");
+        assert_eq!(path, root.join("images").join("V800R021.cc"));
+        let (parts, path) = resolve(root, &cwd, "../../../etc/passwd").unwrap();
+        assert_eq!(display(&parts), "/etc/passwd");
+        assert_eq!(path, root.join("etc").join("passwd"));
+        assert_eq!(resolve(root, &cwd, "/").unwrap().1, root);
+        assert!(resolve(root, &cwd, "flash:/vrpcfg.zip").is_none());
+        assert!(resolve(root, &cwd, "..\\secret").is_none());
+
+        assert_eq!(
+            parse_port("10,0,0,1,4,1"),
+            Some("10.0.0.1:1025".parse().unwrap())
+        );
+        assert_eq!(parse_port("10,0,0,1,4"), None);
+        assert_eq!(parse_port("10,0,0,256,4,1"), None);
+
+        let now = chrono::Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let recent = chrono::Utc.with_ymd_and_hms(2024, 5, 20, 8, 5, 0).unwrap();
+        let old = chrono::Utc.with_ymd_and_hms(2023, 1, 2, 0, 0, 0).unwrap();
+        assert_eq!(
+            list_line("V800R021.cc", false, 123456, recent, now),
+            "-rw-r--r-- 1 ftp ftp       123456 May 20 08:05 V800R021.cc\r\n"
+        );
+        assert_eq!(
+            list_line("configs", true, 4096, old, now),
+            "drwxr-xr-x 1 ftp ftp         4096 Jan 02  2023 configs\r\n"
+        );
+    }
+}
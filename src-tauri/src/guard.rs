@@ -0,0 +1,519 @@
+//! Change guard: pre-change snapshots and post-change verification
+//! (`guards/` in the app data dir).
+//!
+//! Before a maintenance window a snapshot captures a set of command outputs
+//! on a session, by default the interface and routing tables of the
+//! device's dialect. Verifying re-runs the same commands afterwards and
+//! reports what got worse: interfaces that went down, routes that
+//! disappeared, commands that now fail, plus a line diff of every output.
+
+use crate::backup::{self, DiffHunk};
+use crate::command;
+use crate::session::{DeviceDialect, SessionError, SessionManager};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+/// An IPv4 prefix in a routing table line
+static PREFIX_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d{1,3}(?:\.\d{1,3}){3}/\d{1,2}\b").unwrap());
+
+#[derive(Error, Debug)]
+pub enum GuardError {
+    #[error("Snapshot not found: {0}")]
+    NotFound(String),
+    #[error("No commands to capture")]
+    NoCommands,
+    #[error("`{command}` failed: {source}")]
+    Command {
+        command: String,
+        source: SessionError,
+    },
+    #[error("{0}")]
+    Session(#[from] SessionError),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<GuardError> for String {
+    fn from(err: GuardError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capture {
+    pub command: String,
+    pub output: String,
+}
+
+/// Command outputs taken before a change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardSnapshot {
+    pub id: String,
+    pub label: String,
+    pub host: String,
+    /// Unix seconds
+    pub taken_at: i64,
+    pub captures: Vec<Capture>,
+}
+
+/// `GuardSnapshot` without the outputs, for listings
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub label: String,
+    pub host: String,
+    pub taken_at: i64,
+    pub commands: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Finding {
+    InterfaceDown { interface: String },
+    InterfaceMissing { interface: String },
+    InterfaceUp { interface: String },
+    RouteMissing { prefix: String },
+    RouteAdded { prefix: String },
+    CommandFailed { command: String, error: String },
+}
+
+impl Finding {
+    /// Whether the finding fails the verification; new interfaces and
+    /// routes are reported but expected from most changes
+    pub fn is_regression(&self) -> bool {
+        !matches!(
+            self,
+            Finding::InterfaceUp { .. } | Finding::RouteAdded { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandDiff {
+    pub command: String,
+    pub hunks: Vec<DiffHunk>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerificationReport {
+    pub snapshot_id: String,
+    pub label: String,
+    pub host: String,
+    /// Unix seconds
+    pub verified_at: i64,
+    /// No finding is a regression
+    pub passed: bool,
+    pub findings: Vec<Finding>,
+    /// Outputs that changed
+    pub diffs: Vec<CommandDiff>,
+}
+
+/// Commands captured when the snapshot request names none
+pub fn default_commands(dialect: DeviceDialect) -> &'static [&'static str] {
+    match dialect {
+        DeviceDialect::Vrp | DeviceDialect::Comware => {
+            &["display interface brief", "display ip routing-table"]
+        }
+        DeviceDialect::Ios => &["show ip interface brief", "show ip route"],
+        DeviceDialect::Linux => &["ip -br link", "ip route"],
+        DeviceDialect::Mml | DeviceDialect::Generic => &[],
+    }
+}
+
+pub struct GuardStore {
+    dir: PathBuf,
+}
+
+impl GuardStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn save(&self, snapshot: &GuardSnapshot) -> Result<(), GuardError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(&snapshot.id)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(snapshot)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Snapshots, newest first; unreadable files are skipped
+    pub fn list(&self) -> Result<Vec<SnapshotInfo>, GuardError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Ok(data) = std::fs::read(&path) else {
+                continue;
+            };
+            let Ok(snapshot) = serde_json::from_slice::<GuardSnapshot>(&data) else {
+                continue;
+            };
+            snapshots.push(SnapshotInfo {
+                commands: snapshot.captures.into_iter().map(|c| c.command).collect(),
+                id: snapshot.id,
+                label: snapshot.label,
+                host: snapshot.host,
+                taken_at: snapshot.taken_at,
+            });
+        }
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.taken_at));
+        Ok(snapshots)
+    }
+
+    pub fn read(&self, id: &str) -> Result<GuardSnapshot, GuardError> {
+        match std::fs::read(self.path(id)?) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(GuardError::NotFound(id.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), GuardError> {
+        match std::fs::remove_file(self.path(id)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(GuardError::NotFound(id.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Ids are uuids; anything else could leave the directory
+    fn path(&self, id: &str) -> Result<PathBuf, GuardError> {
+        Uuid::parse_str(id).map_err(|_| GuardError::NotFound(id.to_string()))?;
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+}
+
+/// Run `commands` (default: the dialect's) on a session and keep their
+/// output. Any failing command fails the capture, so a snapshot is always
+/// complete.
+pub async fn capture(
+    manager: &SessionManager,
+    session_id: &str,
+    label: String,
+    commands: Option<Vec<String>>,
+    timeout: Duration,
+) -> Result<GuardSnapshot, GuardError> {
+    let handle = manager
+        .get(session_id)
+        .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+    let commands: Vec<String> = match commands {
+        Some(commands) => commands
+            .into_iter()
+            .filter(|c| !c.trim().is_empty())
+            .collect(),
+        None => default_commands(handle.config.dialect)
+            .iter()
+            .map(|c| c.to_string())
+            .collect(),
+    };
+    if commands.is_empty() {
+        return Err(GuardError::NoCommands);
+    }
+
+    let mut captures = Vec::new();
+    for command in commands {
+        let output = command::run_command(manager, session_id, &command, timeout)
+            .await
+            .map_err(|source| GuardError::Command {
+                command: command.clone(),
+                source,
+            })?;
+        captures.push(Capture {
+            command,
+            output: output.output,
+        });
+    }
+    info!(session_id = %session_id, commands = captures.len(), "Captured pre-change snapshot");
+    Ok(GuardSnapshot {
+        id: Uuid::new_v4().to_string(),
+        label,
+        host: handle.config.host.clone(),
+        taken_at: chrono::Utc::now().timestamp(),
+        captures,
+    })
+}
+
+/// Re-run the snapshot's commands on a session, usually the same device
+/// after the change, and compare
+pub async fn verify(
+    manager: &SessionManager,
+    session_id: &str,
+    snapshot: &GuardSnapshot,
+    timeout: Duration,
+) -> Result<VerificationReport, GuardError> {
+    let handle = manager
+        .get(session_id)
+        .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+    let mut after = Vec::new();
+    for capture in &snapshot.captures {
+        let output = command::run_command(manager, session_id, &capture.command, timeout)
+            .await
+            .map(|output| output.output)
+            .map_err(|e| e.to_string());
+        after.push(output);
+    }
+    let report = compare(snapshot, &handle.config.host, &after);
+    info!(
+        session_id = %session_id,
+        snapshot = %snapshot.id,
+        passed = report.passed,
+        findings = report.findings.len(),
+        "Verified change"
+    );
+    Ok(report)
+}
+
+/// Compare each capture with its output after the change, in order
+fn compare(
+    snapshot: &GuardSnapshot,
+    host: &str,
+    after: &[Result<String, String>],
+) -> VerificationReport {
+    let mut findings = Vec::new();
+    let mut diffs = Vec::new();
+    for (capture, output) in snapshot.captures.iter().zip(after) {
+        let output = match output {
+            Ok(output) => output,
+            Err(error) => {
+                findings.push(Finding::CommandFailed {
+                    command: capture.command.clone(),
+                    error: error.clone(),
+                });
+                continue;
+            }
+        };
+        let hunks = backup::diff_lines(&capture.output, output);
+        if !hunks.is_empty() {
+            diffs.push(CommandDiff {
+                command: capture.command.clone(),
+                hunks,
+            });
+        }
+
+        let command = capture.command.to_ascii_lowercase();
+        if command.contains("rout") {
+            let before = route_prefixes(&capture.output);
+            let now = route_prefixes(output);
+            for prefix in before.difference(&now) {
+                findings.push(Finding::RouteMissing {
+                    prefix: prefix.clone(),
+                });
+            }
+            for prefix in now.difference(&before) {
+                findings.push(Finding::RouteAdded {
+                    prefix: prefix.clone(),
+                });
+            }
+        } else if command.contains("interface") || command.contains("link") {
+            let now = interface_states(output);
+            for (interface, was_up) in interface_states(&capture.output) {
+                let finding = match now.get(&interface) {
+                    None => Finding::InterfaceMissing { interface },
+                    Some(false) if was_up => Finding::InterfaceDown { interface },
+                    Some(true) if !was_up => Finding::InterfaceUp { interface },
+                    Some(_) => continue,
+                };
+                findings.push(finding);
+            }
+        }
+    }
+
+    VerificationReport {
+        snapshot_id: snapshot.id.clone(),
+        label: snapshot.label.clone(),
+        host: host.to_string(),
+        verified_at: chrono::Utc::now().timestamp(),
+        passed: !findings.iter().any(Finding::is_regression),
+        findings,
+        diffs,
+    }
+}
+
+/// Interface name to whether it is up, from brief interface tables: the
+/// first `up` or `down` column after the name is the physical state (VRP
+/// marks admin down as `*down`, IOS as `administratively down`)
+fn interface_states(text: &str) -> BTreeMap<String, bool> {
+    let mut states = BTreeMap::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(name) = fields.next() else {
+            continue;
+        };
+        // Interface names start with a letter and carry a number
+        let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.contains(|c: char| c.is_ascii_digit());
+        if !is_name {
+            continue;
+        }
+        let state = fields.find_map(|field| {
+            match field
+                .trim_start_matches(['*', '^'])
+                .to_ascii_lowercase()
+                .as_str()
+            {
+                "up" => Some(true),
+                "down" => Some(false),
+                _ => None,
+            }
+        });
+        if let Some(up) = state {
+            states.insert(name.trim_end_matches(':').to_string(), up);
+        }
+    }
+    states
+}
+
+fn route_prefixes(text: &str) -> BTreeSet<String> {
+    PREFIX_RE
+        .find_iter(text)
+        .map(|m| m.as_str().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_findings() {
+        let snapshot = GuardSnapshot {
+            id: Uuid::new_v4().to_string(),
+            label: "core upgrade".to_string(),
+            host: "10.0.0.1".to_string(),
+            taken_at: 0,
+            captures: vec![
+                Capture {
+                    command: "display interface brief".to_string(),
+                    output: "Interface                   PHY   Protocol InUti OutUti\n\
+                             GigabitEthernet0/0/1        up    up       0.01%  0.01%\n\
+                             GigabitEthernet0/0/2        up    up       0%     0%\n\
+                             GigabitEthernet0/0/3        *down down     0%     0%\n\
+                             LoopBack0                   up    up(s)    0%     0%\n"
+                        .to_string(),
+                },
+                Capture {
+                    command: "display ip routing-table".to_string(),
+                    output: "10.1.0.0/16  OSPF  10  2  D  10.0.0.2  GigabitEthernet0/0/1\n\
+                             10.2.0.0/16  OSPF  10  2  D  10.0.0.3  GigabitEthernet0/0/2\n"
+                        .to_string(),
+                },
+                Capture {
+                    command: "display bgp peer".to_string(),
+                    output: String::new(),
+                },
+            ],
+        };
+        let after = vec![
+            Ok("Interface                   PHY   Protocol InUti OutUti\n\
+                GigabitEthernet0/0/1        up    up       0.01%  0.01%\n\
+                GigabitEthernet0/0/2        down  down     0%     0%\n\
+                GigabitEthernet0/0/3        up    up       0%     0%\n"
+                .to_string()),
+            Ok(
+                "10.1.0.0/16  OSPF  10  2  D  10.0.0.2  GigabitEthernet0/0/1\n\
+                10.3.0.0/16  Static 60 0  RD 10.0.0.4  GigabitEthernet0/0/1\n"
+                    .to_string(),
+            ),
+            Err("Timed out".to_string()),
+        ];
+
+        let report = compare(&snapshot, "10.0.0.1", &after);
+        assert!(!report.passed);
+        assert_eq!(
+            report.findings,
+            vec![
+                Finding::InterfaceDown {
+                    interface: "GigabitEthernet0/0/2".to_string()
+                },
+                Finding::InterfaceUp {
+                    interface: "GigabitEthernet0/0/3".to_string()
+                },
+                Finding::InterfaceMissing {
+                    interface: "LoopBack0".to_string()
+                },
+                Finding::RouteMissing {
+                    prefix: "10.2.0.0/16".to_string()
+                },
+                Finding::RouteAdded {
+                    prefix: "10.3.0.0/16".to_string()
+                },
+                Finding::CommandFailed {
+                    command: "display bgp peer".to_string(),
+                    error: "Timed out".to_string(),
+                },
+            ]
+        );
+        assert_eq!(report.diffs.len(), 2);
+
+        let unchanged: Vec<Result<String, String>> = snapshot
+            .captures
+            .iter()
+            .map(|c| Ok(c.output.clone()))
+            .collect();
+        let report = compare(&snapshot, "10.0.0.1", &unchanged);
+        assert!(report.passed);
+        assert!(report.findings.is_empty() && report.diffs.is_empty());
+    }
+
+    #[test]
+    fn test_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("bspt-guard-test-{}", uuid::Uuid::new_v4()));
+        let store = GuardStore::new(dir.clone());
+        assert!(store.list().unwrap().is_empty());
+
+        let snapshot = GuardSnapshot {
+            id: Uuid::new_v4().to_string(),
+            label: "before".to_string(),
+            host: "10.0.0.1".to_string(),
+            taken_at: 1_700_000_000,
+            captures: vec![Capture {
+                command: "ip route".to_string(),
+                output: "default via 10.0.0.254 dev eth0\n".to_string(),
+            }],
+        };
+        store.save(&snapshot).unwrap();
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].commands, vec!["ip route"]);
+        assert_eq!(
+            store.read(&snapshot.id).unwrap().captures[0].command,
+            "ip route"
+        );
+        assert!(matches!(
+            store.read("../settings"),
+            Err(GuardError::NotFound(_))
+        ));
+
+        store.delete(&snapshot.id).unwrap();
+        assert!(matches!(
+            store.read(&snapshot.id),
+            Err(GuardError::NotFound(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
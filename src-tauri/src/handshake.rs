@@ -0,0 +1,377 @@
+//! What happened in the SSH handshake with a session's target, for
+//! troubleshooting devices that refuse to negotiate or authenticate.
+//!
+//! russh doesn't expose the identification strings or the negotiated
+//! algorithms, so the target's transport is wrapped in a `Tap` that keeps
+//! the first bytes each way. Both start in the clear with the SSH-2.0
+//! identification line and a KEXINIT, from which the algorithm lists of
+//! both sides are read and negotiated as in RFC 4253 7.1. The pre-auth
+//! banner and the host key come from the `SshHandler`. The result is
+//! emitted as `session:{id}:ssh_info` once authentication is over, or when
+//! the handshake fails.
+
+use russh::keys::key::PublicKey;
+use serde::Serialize;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tauri::{AppHandle, Emitter};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{info, warn};
+
+/// Bytes kept each way, enough for the identification and KEXINIT
+const CAPTURE: usize = 16 * 1024;
+
+const MSG_KEXINIT: u8 = 20;
+
+/// Name-lists of a KEXINIT. Ciphers, MACs and compression are the
+/// client-to-server lists, which servers don't set apart from the
+/// server-to-client ones in practice
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AlgorithmLists {
+    pub kex: Vec<String>,
+    pub host_key: Vec<String>,
+    pub cipher: Vec<String>,
+    pub mac: Vec<String>,
+    pub compression: Vec<String>,
+}
+
+/// The first of the client's algorithms the server also offers; None where
+/// there is none, which is what ends a handshake
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Negotiated {
+    pub kex: Option<String>,
+    pub host_key: Option<String>,
+    pub cipher: Option<String>,
+    /// `<implicit>` with an AEAD cipher
+    pub mac: Option<String>,
+    pub compression: Option<String>,
+}
+
+/// Payload of `session:{id}:ssh_info`
+#[derive(Debug, Clone, Serialize)]
+pub struct SshInfo {
+    pub session_id: String,
+    pub host: String,
+    pub port: u16,
+    /// e.g. `SSH-2.0-HUAWEI-1.5`
+    pub server_version: Option<String>,
+    pub client_version: Option<String>,
+    /// Pre-auth banner, if the server sent one
+    pub banner: Option<String>,
+    /// Algorithm and SHA256 fingerprint of the host key offered
+    pub host_key: Option<String>,
+    pub server_algorithms: Option<AlgorithmLists>,
+    pub client_algorithms: Option<AlgorithmLists>,
+    /// None until both KEXINITs were seen
+    pub negotiated: Option<Negotiated>,
+    /// Why connecting or authenticating failed
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Captured {
+    received: Vec<u8>,
+    sent: Vec<u8>,
+    banner: Option<String>,
+    host_key: Option<String>,
+}
+
+/// Handshake details of one connection, filled in while it's set up
+pub struct HandshakeLog {
+    host: String,
+    port: u16,
+    captured: Mutex<Captured>,
+}
+
+impl HandshakeLog {
+    pub fn new(host: &str, port: u16) -> Arc<Self> {
+        Arc::new(Self {
+            host: host.to_string(),
+            port,
+            captured: Mutex::new(Captured::default()),
+        })
+    }
+
+    /// Wrap the target's transport
+    pub fn tap<S>(self: &Arc<Self>, stream: S) -> Tap<S> {
+        Tap {
+            inner: stream,
+            log: Arc::clone(self),
+            receive_left: CAPTURE,
+            send_left: CAPTURE,
+        }
+    }
+
+    pub fn banner(&self, banner: &str) {
+        let mut captured = self.captured.lock().unwrap();
+        // Servers may send the banner in several messages
+        captured
+            .banner
+            .get_or_insert_with(String::new)
+            .push_str(banner);
+    }
+
+    pub fn host_key(&self, key: &PublicKey) {
+        self.captured.lock().unwrap().host_key =
+            Some(format!("{} SHA256:{}", key.name(), key.fingerprint()));
+    }
+
+    pub fn info(&self, session_id: &str, error: Option<String>) -> SshInfo {
+        let captured = self.captured.lock().unwrap();
+        let (server_version, server_algorithms) = parse(&captured.received);
+        let (client_version, client_algorithms) = parse(&captured.sent);
+        let negotiated = match (&client_algorithms, &server_algorithms) {
+            (Some(client), Some(server)) => Some(negotiate(client, server)),
+            _ => None,
+        };
+        SshInfo {
+            session_id: session_id.to_string(),
+            host: self.host.clone(),
+            port: self.port,
+            server_version,
+            client_version,
+            banner: captured.banner.clone(),
+            host_key: captured.host_key.clone(),
+            server_algorithms,
+            client_algorithms,
+            negotiated,
+            error,
+        }
+    }
+
+    pub fn emit(&self, app_handle: &AppHandle, session_id: &str, error: Option<String>) {
+        let info = self.info(session_id, error);
+        info!(session_id = %session_id, server_version = ?info.server_version, negotiated = ?info.negotiated, "SSH handshake");
+        let event = format!("session:{}:ssh_info", session_id);
+        if let Err(e) = app_handle.emit(&event, &info) {
+            warn!(session_id = %session_id, error = %e, "Failed to emit SSH info");
+        }
+    }
+
+    fn record(&self, sent: bool, data: &[u8]) {
+        let mut captured = self.captured.lock().unwrap();
+        let buf = if sent {
+            &mut captured.sent
+        } else {
+            &mut captured.received
+        };
+        buf.extend_from_slice(data);
+    }
+}
+
+/// A transport that copies its first `CAPTURE` bytes each way to the log
+pub struct Tap<S> {
+    inner: S,
+    log: Arc<HandshakeLog>,
+    receive_left: usize,
+    send_left: usize,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tap<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if this.receive_left > 0 {
+            let read = &buf.filled()[before..];
+            let kept = &read[..read.len().min(this.receive_left)];
+            this.receive_left -= kept.len();
+            this.log.record(false, kept);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tap<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, data))?;
+        if this.send_left > 0 {
+            let kept = &data[..n.min(this.send_left)];
+            this.send_left -= kept.len();
+            this.log.record(true, kept);
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// The identification line and the KEXINIT that follows it. Servers may
+/// send other lines before the identification
+fn parse(mut data: &[u8]) -> (Option<String>, Option<AlgorithmLists>) {
+    let version = loop {
+        let Some(end) = data.iter().position(|&b| b == b'\n') else {
+            return (None, None);
+        };
+        let line = &data[..end];
+        data = &data[end + 1..];
+        if line.starts_with(b"SSH-") {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            break String::from_utf8_lossy(line).into_owned();
+        }
+    };
+    (Some(version), kexinit(data))
+}
+
+/// The name-lists of an unencrypted KEXINIT packet
+fn kexinit(data: &[u8]) -> Option<AlgorithmLists> {
+    let len = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let padding = *data.get(4)? as usize;
+    let payload = data.get(5..4 + len.checked_sub(padding)?)?;
+    let (&kind, rest) = payload.split_first()?;
+    if kind != MSG_KEXINIT {
+        return None;
+    }
+    // Cookie, then ten name-lists
+    let mut rest = rest.get(16..)?;
+    let mut lists = Vec::new();
+    for _ in 0..8 {
+        let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let names = std::str::from_utf8(rest.get(4..4 + len)?).ok()?;
+        lists.push(
+            names
+                .split(',')
+                .filter(|n| !n.is_empty())
+                .map(str::to_string)
+                .collect(),
+        );
+        rest = &rest[4 + len..];
+    }
+    let mut lists = lists.into_iter();
+    let mut next = || lists.next().unwrap_or_default();
+    let (kex, host_key, cipher) = (next(), next(), next());
+    let _server_to_client_cipher = next();
+    let mac = next();
+    let _server_to_client_mac = next();
+    Some(AlgorithmLists {
+        kex,
+        host_key,
+        cipher,
+        mac,
+        compression: next(),
+    })
+}
+
+fn negotiate(client: &AlgorithmLists, server: &AlgorithmLists) -> Negotiated {
+    let pick = |client: &[String], server: &[String]| {
+        client.iter().find(|name| server.contains(name)).cloned()
+    };
+    let cipher = pick(&client.cipher, &server.cipher);
+    let aead = cipher
+        .as_deref()
+        .is_some_and(|c| c.contains("gcm") || c.starts_with("chacha20-poly1305"));
+    Negotiated {
+        kex: pick(&client.kex, &server.kex),
+        host_key: pick(&client.host_key, &server.host_key),
+        mac: if aead {
+            Some("<implicit>".to_string())
+        } else {
+            pick(&client.mac, &server.mac)
+        },
+        cipher,
+        compression: pick(&client.compression, &server.compression),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kexinit_packet(lists: [&str; 10]) -> Vec<u8> {
+        let mut payload = vec![MSG_KEXINIT];
+        payload.extend_from_slice(&[0u8; 16]);
+        for list in lists {
+            payload.extend_from_slice(&(list.len() as u32).to_be_bytes());
+            payload.extend_from_slice(list.as_bytes());
+        }
+        payload.extend_from_slice(&[0, 0, 0, 0, 0]);
+        let padding = 4;
+        let mut packet = ((payload.len() + 1 + padding) as u32)
+            .to_be_bytes()
+            .to_vec();
+        packet.push(padding as u8);
+        packet.extend(payload);
+        packet.extend_from_slice(&[0u8; 4]);
+        packet
+    }
+
+    #[tokio::test]
+    async fn test_handshake_capture() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut server_side = b"Welcome\r\nSSH-2.0-HUAWEI-1.5\r\n".to_vec();
+        server_side.extend(kexinit_packet([
+            "diffie-hellman-group1-sha1,diffie-hellman-group14-sha1",
+            "ssh-rsa",
+            "aes128-cbc,aes256-ctr",
+            "aes128-cbc,aes256-ctr",
+            "hmac-sha1",
+            "hmac-sha1",
+            "none",
+            "none",
+            "",
+            "",
+        ]));
+        let mut client_side = b"SSH-2.0-russh_0.44.1\r\n".to_vec();
+        client_side.extend(kexinit_packet([
+            "curve25519-sha256,diffie-hellman-group14-sha1,ext-info-c",
+            "ssh-ed25519,rsa-sha2-256",
+            "chacha20-poly1305@openssh.com,aes256-ctr",
+            "chacha20-poly1305@openssh.com,aes256-ctr",
+            "hmac-sha2-256,hmac-sha1",
+            "hmac-sha2-256,hmac-sha1",
+            "none",
+            "none",
+            "",
+            "",
+        ]));
+
+        let log = HandshakeLog::new("10.0.0.1", 22);
+        let (ours, mut theirs) = tokio::io::duplex(64 * 1024);
+        let mut tap = log.tap(ours);
+        tap.write_all(&client_side).await.unwrap();
+        theirs.write_all(&server_side).await.unwrap();
+        let mut received = vec![0u8; server_side.len()];
+        tap.read_exact(&mut received).await.unwrap();
+        log.banner("Authorized users only\r\n");
+
+        let info = log.info("s1", Some("Authentication rejected".to_string()));
+        assert_eq!(info.server_version.as_deref(), Some("SSH-2.0-HUAWEI-1.5"));
+        assert_eq!(info.client_version.as_deref(), Some("SSH-2.0-russh_0.44.1"));
+        assert_eq!(info.banner.as_deref(), Some("Authorized users only\r\n"));
+        let server = info.server_algorithms.unwrap();
+        assert_eq!(server.host_key, ["ssh-rsa"]);
+        assert_eq!(server.compression, ["none"]);
+        assert_eq!(
+            info.negotiated,
+            Some(Negotiated {
+                kex: Some("diffie-hellman-group14-sha1".to_string()),
+                host_key: None,
+                cipher: Some("aes256-ctr".to_string()),
+                mac: Some("hmac-sha1".to_string()),
+                compression: Some("none".to_string()),
+            })
+        );
+
+        // Nothing useful before the identification line arrives
+        let empty = HandshakeLog::new("10.0.0.2", 22).info("s2", None);
+        assert!(empty.server_version.is_none() && empty.negotiated.is_none());
+    }
+}
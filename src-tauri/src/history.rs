@@ -0,0 +1,343 @@
+use crate::charset::Charset;
+use crate::plugin::PluginSelection;
+use crate::session::{
+    AuthMethod, DeviceDialect, JumpHost, NewlineMode, PayloadEncoding, Protocol, SessionConfig,
+    SshAlgorithms, SshAuth, SshHardening,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Records kept; older ones are dropped
+const MAX_RECORDS: usize = 500;
+
+/// Records returned by `get_recent_connections` when no limit is given
+pub const DEFAULT_RECENT_LIMIT: usize = 20;
+
+/// Records kept for a slow `subscribe` receiver
+const FEED_CAPACITY: usize = 64;
+
+/// One connection that reached Ready
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionRecord {
+    pub id: String,
+    pub session_id: String,
+    pub host: String,
+    pub port: u16,
+    pub protocol: Protocol,
+    pub username: String,
+    /// Without inline keys and passphrases
+    #[serde(default)]
+    pub auth: SshAuth,
+    pub cols: u32,
+    pub rows: u32,
+    #[serde(default)]
+    pub charset: Charset,
+    #[serde(default)]
+    pub payload_encoding: PayloadEncoding,
+    #[serde(default)]
+    pub terminal_type: Option<String>,
+    #[serde(default)]
+    pub newline: NewlineMode,
+    #[serde(default)]
+    pub dialect: DeviceDialect,
+    /// Jump hosts without their passwords
+    #[serde(default)]
+    pub jump_hosts: Vec<JumpHost>,
+    #[serde(default)]
+    pub algorithms: Option<SshAlgorithms>,
+    #[serde(default)]
+    pub hardening: Option<SshHardening>,
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub auth_methods: Vec<AuthMethod>,
+    #[serde(default)]
+    pub subsystem: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub plugins: PluginSelection,
+    /// Unix time in milliseconds
+    pub connected_at: u64,
+    /// None while connected, or if the app exited first
+    pub duration_ms: Option<u64>,
+}
+
+impl ConnectionRecord {
+    /// Config for connecting again; buffer, keepalive and logging come from
+    /// the global settings
+    pub fn session_config(&self, password: Option<String>) -> SessionConfig {
+        SessionConfig {
+            host: self.host.clone(),
+            port: self.port,
+            protocol: self.protocol,
+            username: self.username.clone(),
+            password: password.unwrap_or_default(),
+            auth: self.auth.clone(),
+            cols: self.cols,
+            rows: self.rows,
+            payload_encoding: self.payload_encoding,
+            buffer: None,
+            charset: self.charset,
+            rate_limit: None,
+            delivery: None,
+            log_file: None,
+            log_timestamps: false,
+            capture_file: None,
+            keepalive: None,
+            activity: None,
+            terminal_type: self.terminal_type.clone(),
+            newline: self.newline,
+            dialect: self.dialect,
+            jump_hosts: self.jump_hosts.clone(),
+            algorithms: self.algorithms.clone(),
+            hardening: self.hardening,
+            connect_timeout_ms: self.connect_timeout_ms,
+            auth_methods: self.auth_methods.clone(),
+            subsystem: self.subsystem.clone(),
+            env: self.env.clone(),
+            plugins: self.plugins.clone(),
+        }
+    }
+}
+
+/// Successful connections, newest first, persisted as `history.json`.
+///
+/// Written by the session tasks when they reach Ready and when they end, so
+/// every connection is recorded whether or not it came from a profile.
+/// Passwords are never stored.
+pub struct ConnectionHistory {
+    /// None keeps the history in memory only
+    path: Option<PathBuf>,
+    records: Mutex<VecDeque<ConnectionRecord>>,
+    /// Each record as it is added and again when it ends
+    feed: broadcast::Sender<ConnectionRecord>,
+}
+
+impl Default for ConnectionHistory {
+    fn default() -> Self {
+        let (feed, _) = broadcast::channel(FEED_CAPACITY);
+        Self {
+            path: None,
+            records: Mutex::new(VecDeque::new()),
+            feed,
+        }
+    }
+}
+
+impl ConnectionHistory {
+    pub fn load(path: PathBuf) -> Self {
+        let records = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!(path = %path.display(), error = %e, "Corrupt connection history, starting empty");
+                VecDeque::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Failed to read connection history");
+                VecDeque::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            records: Mutex::new(records),
+            ..Self::default()
+        }
+    }
+
+    /// Record that `session_id` reached Ready with `config`
+    pub fn connected(&self, session_id: &str, config: &SessionConfig) {
+        let record = ConnectionRecord {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            host: config.host.clone(),
+            port: config.port,
+            protocol: config.protocol,
+            username: config.username.clone(),
+            auth: config.auth.without_secrets(),
+            cols: config.cols,
+            rows: config.rows,
+            charset: config.charset,
+            payload_encoding: config.payload_encoding,
+            terminal_type: config.terminal_type.clone(),
+            newline: config.newline,
+            dialect: config.dialect,
+            jump_hosts: config
+                .jump_hosts
+                .iter()
+                .map(|jump| JumpHost {
+                    password: String::new(),
+                    auth: jump.auth.without_secrets(),
+                    ..jump.clone()
+                })
+                .collect(),
+            algorithms: config.algorithms.clone(),
+            hardening: config.hardening,
+            connect_timeout_ms: config.connect_timeout_ms,
+            auth_methods: config.auth_methods.clone(),
+            subsystem: config.subsystem.clone(),
+            env: config.env.clone(),
+            plugins: config.plugins.clone(),
+            connected_at: now_ms(),
+            duration_ms: None,
+        };
+
+        let _ = self.feed.send(record.clone());
+        let mut records = self.records.lock().unwrap();
+        records.push_front(record);
+        records.truncate(MAX_RECORDS);
+        self.persist(&records);
+    }
+
+    /// Record the duration of `session_id` once it ends. Sessions that never
+    /// reached Ready have no record and are ignored.
+    pub fn disconnected(&self, session_id: &str) {
+        let mut records = self.records.lock().unwrap();
+        let Some(record) = records
+            .iter_mut()
+            .find(|r| r.session_id == session_id && r.duration_ms.is_none())
+        else {
+            return;
+        };
+        record.duration_ms = Some(now_ms().saturating_sub(record.connected_at));
+        let _ = self.feed.send(record.clone());
+        self.persist(&records);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionRecord> {
+        self.feed.subscribe()
+    }
+
+    /// Up to `limit` records, newest first
+    pub fn recent(&self, limit: usize) -> Vec<ConnectionRecord> {
+        let records = self.records.lock().unwrap();
+        records.iter().take(limit).cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<ConnectionRecord> {
+        let records = self.records.lock().unwrap();
+        records.iter().find(|r| r.id == id).cloned()
+    }
+
+    fn persist(&self, records: &VecDeque<ConnectionRecord>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = (|| -> io::Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec(records)?)?;
+            std::fs::rename(&tmp, path)
+        })();
+        if let Err(e) = result {
+            warn!(path = %path.display(), error = %e, "Failed to write connection history");
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(host: &str) -> SessionConfig {
+        ConnectionRecord {
+            id: String::new(),
+            session_id: String::new(),
+            host: host.to_string(),
+            port: 23,
+            protocol: Protocol::Telnet,
+            username: "admin".to_string(),
+            auth: SshAuth::Password,
+            cols: 120,
+            rows: 40,
+            charset: Charset::Gbk,
+            payload_encoding: PayloadEncoding::Base64,
+            terminal_type: None,
+            newline: NewlineMode::CrLf,
+            dialect: DeviceDialect::Comware,
+            jump_hosts: Vec::new(),
+            algorithms: None,
+            hardening: None,
+            connect_timeout_ms: None,
+            auth_methods: Vec::new(),
+            subsystem: None,
+            env: HashMap::new(),
+            plugins: Default::default(),
+            connected_at: 0,
+            duration_ms: None,
+        }
+        .session_config(Some("secret".to_string()))
+    }
+
+    #[test]
+    fn test_history_round_trip() {
+        let dir = std::env::temp_dir().join(format!("bspt-history-test-{}", Uuid::new_v4()));
+        let path = dir.join("history.json");
+        let history = ConnectionHistory::load(path.clone());
+
+        history.connected("s1", &config("10.0.0.1"));
+        let mut via_bastion = config("10.0.0.2");
+        via_bastion.jump_hosts.push(JumpHost {
+            host: "bastion".to_string(),
+            port: 22,
+            username: "ops".to_string(),
+            password: "jump secret".to_string(),
+            auth: SshAuth::PublicKey {
+                path: Some("~/.ssh/bastion".to_string()),
+                pem: None,
+                passphrase: Some("key secret".to_string()),
+            },
+        });
+        via_bastion.connect_timeout_ms = Some(5_000);
+        history.connected("s2", &via_bastion);
+        history.disconnected("s1");
+        history.disconnected("unknown");
+
+        let history = ConnectionHistory::load(path.clone());
+        let recent = history.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].host, "10.0.0.2");
+        assert_eq!(recent[0].jump_hosts[0].host, "bastion");
+        assert_eq!(
+            recent[0].jump_hosts[0].auth.without_secrets(),
+            recent[0].jump_hosts[0].auth
+        );
+        assert!(recent[0].jump_hosts[0].auth.passphrase().is_none());
+        assert_eq!(
+            recent[0].session_config(None).connect_timeout_ms,
+            Some(5_000)
+        );
+        assert!(recent[0].duration_ms.is_none());
+        assert!(recent[1].duration_ms.is_some());
+        assert_eq!(history.recent(1).len(), 1);
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+
+        let again = history.get(&recent[1].id).unwrap().session_config(None);
+        assert_eq!(again.host, "10.0.0.1");
+        assert_eq!((again.cols, again.charset), (120, Charset::Gbk));
+        assert_eq!(
+            (again.newline, again.dialect),
+            (NewlineMode::CrLf, DeviceDialect::Comware)
+        );
+        assert!(again.password.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
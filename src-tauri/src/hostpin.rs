@@ -0,0 +1,250 @@
+//! Pinned host key fingerprints, one record per device and key type.
+//!
+//! The first key accepted for a device is pinned in `host_pins.json`,
+//! apart from known_hosts, so answering a host key prompt or editing the
+//! known_hosts file never moves a pin. A handshake presenting a different
+//! key of a pinned type skips the ordinary prompt: it raises
+//! `session:{id}:hostkey_changed` with the pinned and the presented
+//! fingerprints and stays blocked until `repin_host_key` pins the new key or
+//! refuses it. Refusing, or leaving the alarm unanswered for
+//! `PROMPT_TIMEOUT`, aborts the handshake. Connects with no session to
+//! alarm on (NETCONF, `exec_command`) always refuse a key that differs from
+//! its pin.
+
+use crate::known_hosts::PROMPT_TIMEOUT;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostPin {
+    pub host: String,
+    pub port: u16,
+    /// e.g. `ssh-ed25519`
+    pub algorithm: String,
+    /// `SHA256:` and the unpadded base64 digest
+    pub fingerprint: String,
+    /// Unix time in milliseconds
+    pub pinned_at: u64,
+}
+
+/// Payload of `session:{id}:hostkey_changed`
+#[derive(Debug, Clone, Serialize)]
+pub struct HostKeyChanged {
+    /// Passed back to `repin_host_key`
+    pub request_id: String,
+    pub session_id: String,
+    pub host: String,
+    pub port: u16,
+    pub algorithm: String,
+    /// The pinned key's
+    pub old_fingerprint: String,
+    /// The key the server presented
+    pub new_fingerprint: String,
+    /// When the old key was pinned, Unix time in milliseconds
+    pub pinned_at: u64,
+}
+
+/// How a server's key compares with the pins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinCheck {
+    /// No key of this type is pinned for the device
+    Unpinned,
+    Matches,
+    /// A different key of this type is pinned
+    Differs(HostPin),
+}
+
+/// The pin file, plus the alarms waiting for `repin_host_key`
+#[derive(Default)]
+pub struct HostPins {
+    /// None keeps pins in memory only
+    path: Option<PathBuf>,
+    pins: Mutex<Vec<HostPin>>,
+    pending: DashMap<String, oneshot::Sender<bool>>,
+}
+
+impl HostPins {
+    pub fn load(path: PathBuf) -> Self {
+        let pins = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!(path = %path.display(), error = %e, "Corrupt host key pins, starting empty");
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Failed to read host key pins");
+                Vec::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            pins: Mutex::new(pins),
+            ..Self::default()
+        }
+    }
+
+    pub fn check(&self, host: &str, port: u16, algorithm: &str, fingerprint: &str) -> PinCheck {
+        let pins = self.pins.lock().unwrap();
+        match pins
+            .iter()
+            .find(|pin| pin.host == host && pin.port == port && pin.algorithm == algorithm)
+        {
+            None => PinCheck::Unpinned,
+            Some(pin) if pin.fingerprint == fingerprint => PinCheck::Matches,
+            Some(pin) => PinCheck::Differs(pin.clone()),
+        }
+    }
+
+    /// Pin `fingerprint` for the device, replacing its pin of the same type
+    pub fn pin(&self, host: &str, port: u16, algorithm: &str, fingerprint: &str) {
+        let mut pins = self.pins.lock().unwrap();
+        pins.retain(|pin| !(pin.host == host && pin.port == port && pin.algorithm == algorithm));
+        pins.push(HostPin {
+            host: host.to_string(),
+            port,
+            algorithm: algorithm.to_string(),
+            fingerprint: fingerprint.to_string(),
+            pinned_at: now_ms(),
+        });
+        self.persist(&pins);
+    }
+
+    /// Pin `fingerprint` unless the device has a key of this type pinned
+    pub fn pin_new(&self, host: &str, port: u16, algorithm: &str, fingerprint: &str) {
+        if self.check(host, port, algorithm, fingerprint) == PinCheck::Unpinned {
+            info!(host = %host, port = port, fingerprint = %fingerprint, "Host key pinned");
+            self.pin(host, port, algorithm, fingerprint);
+        }
+    }
+
+    /// Pins sorted by device
+    pub fn list(&self) -> Vec<HostPin> {
+        let mut pins = self.pins.lock().unwrap().clone();
+        pins.sort_by(|a, b| (&a.host, a.port, &a.algorithm).cmp(&(&b.host, b.port, &b.algorithm)));
+        pins
+    }
+
+    /// Resolve a pending alarm, `repin` pinning the new key; false if it is
+    /// unknown or already over
+    pub fn answer(&self, request_id: &str, repin: bool) -> bool {
+        match self.pending.remove(request_id) {
+            Some((_, tx)) => tx.send(repin).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Raise the alarm and wait for the user to re-pin
+    pub(crate) async fn alarm(&self, app_handle: &AppHandle, mut alarm: HostKeyChanged) -> bool {
+        let request_id = Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(request_id.clone(), tx);
+        alarm.request_id = request_id.clone();
+
+        warn!(
+            session_id = %alarm.session_id,
+            host = %alarm.host,
+            port = alarm.port,
+            old = %alarm.old_fingerprint,
+            new = %alarm.new_fingerprint,
+            "HOST KEY CHANGED, waiting for the key to be re-pinned"
+        );
+        let event = format!("session:{}:hostkey_changed", alarm.session_id);
+        if let Err(e) = app_handle.emit(&event, &alarm) {
+            warn!(session_id = %alarm.session_id, error = %e, "Failed to emit host key alarm");
+            self.pending.remove(&request_id);
+            return false;
+        }
+        self.wait(&request_id, rx, PROMPT_TIMEOUT).await
+    }
+
+    async fn wait(&self, request_id: &str, rx: oneshot::Receiver<bool>, timeout: Duration) -> bool {
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(repin) => repin.unwrap_or(false),
+            Err(_) => {
+                self.pending.remove(request_id);
+                false
+            }
+        }
+    }
+
+    fn persist(&self, pins: &[HostPin]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = (|| -> io::Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec(pins)?)?;
+            std::fs::rename(&tmp, path)
+        })();
+        if let Err(e) = result {
+            warn!(path = %path.display(), error = %e, "Failed to write host key pins");
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pins() {
+        let path = std::env::temp_dir().join(format!("bspt-host-pins-{}.json", Uuid::new_v4()));
+        let pins = HostPins::load(path.clone());
+        assert_eq!(
+            pins.check("10.0.0.1", 22, "ssh-ed25519", "SHA256:a"),
+            PinCheck::Unpinned
+        );
+        pins.pin("10.0.0.1", 22, "ssh-ed25519", "SHA256:a");
+        pins.pin("10.0.0.1", 22, "rsa-sha2-256", "SHA256:r");
+
+        let pins = HostPins::load(path.clone());
+        assert_eq!(
+            pins.check("10.0.0.1", 22, "ssh-ed25519", "SHA256:a"),
+            PinCheck::Matches
+        );
+        let PinCheck::Differs(old) = pins.check("10.0.0.1", 22, "ssh-ed25519", "SHA256:b") else {
+            panic!("expected a changed key");
+        };
+        assert_eq!(old.fingerprint, "SHA256:a");
+        // Another port is another device
+        assert_eq!(
+            pins.check("10.0.0.1", 2222, "ssh-ed25519", "SHA256:b"),
+            PinCheck::Unpinned
+        );
+        pins.pin("10.0.0.1", 22, "ssh-ed25519", "SHA256:b");
+        assert_eq!(pins.list().len(), 2);
+        assert_eq!(
+            pins.check("10.0.0.1", 22, "ssh-ed25519", "SHA256:b"),
+            PinCheck::Matches
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        pins.pending.insert("r1".to_string(), tx);
+        assert!(pins.answer("r1", true));
+        assert!(pins.wait("r1", rx, PROMPT_TIMEOUT).await);
+        let (tx, rx) = oneshot::channel();
+        pins.pending.insert("r2".to_string(), tx);
+        assert!(!pins.wait("r2", rx, Duration::from_millis(10)).await);
+        assert!(!pins.answer("r2", true));
+    }
+}
@@ -0,0 +1,440 @@
+//! Shared job model for long-running automation (`jobs.json` in the app
+//! data dir).
+//!
+//! Scripts, batch jobs, scheduled backups, upgrades and watches register with the
+//! `JobRegistry` and get a `JobControl`. Through it they report progress and
+//! honour pause and cancel requests at their own checkpoints: between script
+//! statements, before each batch device, between upgrade steps. Finished
+//! jobs are kept in a bounded history with their outcome.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::sync::{broadcast, watch};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Finished jobs kept in the history
+const MAX_HISTORY: usize = 200;
+
+/// Updates kept for a slow subscriber
+const UPDATE_CAPACITY: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum JobError {
+    #[error("Job not found: {0}")]
+    NotFound(String),
+    #[error("Job cancelled")]
+    Cancelled,
+}
+
+impl From<JobError> for String {
+    fn from(err: JobError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Script,
+    Batch,
+    Backup,
+    Upgrade,
+    Rotation,
+    Topology,
+    ClockAudit,
+    /// `watch_command`, until cancelled
+    Watch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    /// Asked to pause; the job stops at its next checkpoint
+    Paused,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: JobKind,
+    pub name: String,
+    pub state: JobState,
+    /// Unix seconds
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// Units of work done out of `total` (devices, statements, steps)
+    pub done: u64,
+    pub total: u64,
+    pub message: Option<String>,
+    /// Summary of a finished job, or its error
+    pub outcome: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    Run,
+    Pause,
+    Cancel,
+}
+
+struct Running {
+    info: JobInfo,
+    signal: watch::Sender<Signal>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct HistoryFile {
+    jobs: Vec<JobInfo>,
+}
+
+/// Running jobs plus the history of finished ones
+pub struct JobRegistry {
+    path: PathBuf,
+    running: Mutex<Vec<Running>>,
+    history: Mutex<Vec<JobInfo>>,
+    updates: broadcast::Sender<JobInfo>,
+}
+
+impl JobRegistry {
+    /// A missing or unreadable history file starts an empty history
+    pub fn load(path: PathBuf) -> Self {
+        let history = match std::fs::read(&path) {
+            Ok(data) => match serde_json::from_slice::<HistoryFile>(&data) {
+                Ok(file) => file.jobs,
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Corrupt job history, starting empty");
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Failed to read job history");
+                Vec::new()
+            }
+        };
+        let (updates, _) = broadcast::channel(UPDATE_CAPACITY);
+        Self {
+            path,
+            running: Mutex::new(Vec::new()),
+            history: Mutex::new(history),
+            updates,
+        }
+    }
+
+    /// Register a job; it is running until `JobControl::finish`
+    pub fn start(self: &Arc<Self>, kind: JobKind, name: impl Into<String>) -> JobControl {
+        let info = JobInfo {
+            id: Uuid::new_v4().to_string(),
+            kind,
+            name: name.into(),
+            state: JobState::Running,
+            started_at: chrono::Utc::now().timestamp(),
+            finished_at: None,
+            done: 0,
+            total: 0,
+            message: None,
+            outcome: None,
+        };
+        info!(job_id = %info.id, kind = ?kind, name = %info.name, "Job started");
+        let (signal, signal_rx) = watch::channel(Signal::Run);
+        self.publish(&info);
+        let id = info.id.clone();
+        self.running.lock().unwrap().push(Running { info, signal });
+        JobControl {
+            id,
+            registry: Arc::clone(self),
+            signal: JobSignal(signal_rx),
+            finished: false,
+        }
+    }
+
+    /// Running jobs, then the history, newest first
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self
+            .running
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.info.clone())
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.started_at));
+        jobs.extend(self.history.lock().unwrap().iter().rev().cloned());
+        jobs
+    }
+
+    pub fn pause(&self, id: &str) -> Result<(), JobError> {
+        self.signal(id, Signal::Pause, JobState::Paused)
+    }
+
+    pub fn resume(&self, id: &str) -> Result<(), JobError> {
+        self.signal(id, Signal::Run, JobState::Running)
+    }
+
+    /// The job stops at its next checkpoint and finishes as cancelled
+    pub fn cancel(&self, id: &str) -> Result<(), JobError> {
+        self.signal(id, Signal::Cancel, JobState::Running)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobInfo> {
+        self.updates.subscribe()
+    }
+
+    fn signal(&self, id: &str, signal: Signal, state: JobState) -> Result<(), JobError> {
+        let info = {
+            let mut running = self.running.lock().unwrap();
+            let job = running
+                .iter_mut()
+                .find(|r| r.info.id == id)
+                .ok_or_else(|| JobError::NotFound(id.to_string()))?;
+            // A cancelled job stays cancelled
+            if *job.signal.borrow() == Signal::Cancel {
+                return Ok(());
+            }
+            job.signal.send_replace(signal);
+            job.info.state = state;
+            job.info.clone()
+        };
+        info!(job_id = %id, signal = ?signal, "Job signalled");
+        self.publish(&info);
+        Ok(())
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut JobInfo)) {
+        let info = {
+            let mut running = self.running.lock().unwrap();
+            let Some(job) = running.iter_mut().find(|r| r.info.id == id) else {
+                return;
+            };
+            change(&mut job.info);
+            job.info.clone()
+        };
+        self.publish(&info);
+    }
+
+    fn finish(&self, id: &str, state: JobState, outcome: Option<String>) {
+        let info = {
+            let mut running = self.running.lock().unwrap();
+            let Some(index) = running.iter().position(|r| r.info.id == id) else {
+                return;
+            };
+            let mut info = running.remove(index).info;
+            info.state = state;
+            info.outcome = outcome;
+            info.finished_at = Some(chrono::Utc::now().timestamp());
+            info
+        };
+        info!(job_id = %id, state = ?state, "Job finished");
+        self.publish(&info);
+
+        let mut history = self.history.lock().unwrap();
+        history.push(info);
+        if history.len() > MAX_HISTORY {
+            let excess = history.len() - MAX_HISTORY;
+            history.drain(..excess);
+        }
+        if let Err(e) = self.persist(&history) {
+            warn!(path = %self.path.display(), error = %e, "Failed to save job history");
+        }
+    }
+
+    fn persist(&self, history: &[JobInfo]) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = HistoryFile {
+            jobs: history.to_vec(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&file)?)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+
+    fn publish(&self, info: &JobInfo) {
+        if self.updates.receiver_count() > 0 {
+            let _ = self.updates.send(info.clone());
+        }
+    }
+}
+
+/// Emit every job update as `job:{id}:progress` until the app exits
+pub fn spawn_emitter(app_handle: tauri::AppHandle, registry: &JobRegistry) {
+    let mut updates = registry.subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let info = match updates.recv().await {
+                Ok(info) => info,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(missed = missed, "Job event emitter fell behind");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let name = format!("job:{}:progress", info.id);
+            if let Err(e) = app_handle.emit(&name, &info) {
+                warn!(job_id = %info.id, error = %e, "Failed to emit job event");
+            }
+        }
+    });
+}
+
+/// A running job's side of the registry. Dropping it without `finish`
+/// (a panicking task) records the job as failed.
+pub struct JobControl {
+    id: String,
+    registry: Arc<JobRegistry>,
+    signal: JobSignal,
+    finished: bool,
+}
+
+impl JobControl {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Wait while the job is paused; fails once it is cancelled
+    pub async fn checkpoint(&self) -> Result<(), JobError> {
+        self.signal.checkpoint().await
+    }
+
+    /// Resolves when the job is cancelled, to interrupt long waits
+    pub async fn cancelled(&self) {
+        self.signal.cancelled().await
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.signal.is_cancelled()
+    }
+
+    /// For tasks the job spawns, such as one per batch device
+    pub fn signal(&self) -> JobSignal {
+        self.signal.clone()
+    }
+
+    /// Pause from within the job, as at an upgrade's `pause_before` step;
+    /// the next `checkpoint` waits for a resume
+    pub fn pause(&self) {
+        let _ = self.registry.pause(&self.id);
+    }
+
+    pub fn progress(&self, done: u64, total: u64, message: Option<String>) {
+        self.registry.update(&self.id, |info| {
+            info.done = done;
+            info.total = total;
+            info.message = message;
+        });
+    }
+
+    /// Record the outcome; a cancelled job finishes as cancelled
+    pub fn finish(mut self, result: Result<String, String>) {
+        self.finished = true;
+        let (state, outcome) = match result {
+            _ if self.is_cancelled() => (JobState::Cancelled, None),
+            Ok(summary) => (JobState::Succeeded, Some(summary)),
+            Err(error) => (JobState::Failed, Some(error)),
+        };
+        self.registry.finish(&self.id, state, outcome);
+    }
+}
+
+/// Pause and cancel requests for one job
+#[derive(Clone)]
+pub struct JobSignal(watch::Receiver<Signal>);
+
+impl JobSignal {
+    pub async fn checkpoint(&self) -> Result<(), JobError> {
+        let mut signal = self.0.clone();
+        loop {
+            match *signal.borrow_and_update() {
+                Signal::Run => return Ok(()),
+                Signal::Cancel => return Err(JobError::Cancelled),
+                Signal::Pause => {}
+            }
+            if signal.changed().await.is_err() {
+                return Err(JobError::Cancelled);
+            }
+        }
+    }
+
+    pub async fn cancelled(&self) {
+        let mut signal = self.0.clone();
+        while *signal.borrow_and_update() != Signal::Cancel {
+            if signal.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow() == Signal::Cancel
+    }
+}
+
+impl Drop for JobControl {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.registry.finish(
+                &self.id,
+                JobState::Failed,
+                Some("job ended unexpectedly".to_string()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_job_lifecycle() {
+        let dir = std::env::temp_dir().join(format!("bspt-jobs-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("jobs.json");
+        let registry = Arc::new(JobRegistry::load(path.clone()));
+        let mut updates = registry.subscribe();
+
+        let job = registry.start(JobKind::Batch, "12 devices");
+        job.progress(3, 12, None);
+        assert_eq!(updates.recv().await.unwrap().state, JobState::Running);
+        assert_eq!(updates.recv().await.unwrap().done, 3);
+        job.checkpoint().await.unwrap();
+
+        registry.pause(job.id()).unwrap();
+        assert_eq!(registry.list()[0].state, JobState::Paused);
+        let paused = tokio::time::timeout(Duration::from_millis(50), job.checkpoint()).await;
+        assert!(paused.is_err(), "checkpoint waits while paused");
+        registry.resume(job.id()).unwrap();
+        job.checkpoint().await.unwrap();
+
+        registry.cancel(job.id()).unwrap();
+        // Cancelling wins over a later resume
+        registry.resume(job.id()).unwrap();
+        assert!(matches!(job.checkpoint().await, Err(JobError::Cancelled)));
+        job.cancelled().await;
+        let id = job.id().to_string();
+        job.finish(Ok("done".to_string()));
+
+        let other = registry.start(JobKind::Script, "collect");
+        drop(other);
+        assert!(matches!(registry.pause(&id), Err(JobError::NotFound(_))));
+
+        let registry = JobRegistry::load(path);
+        let jobs = registry.list();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].state, JobState::Failed);
+        assert_eq!(jobs[1].state, JobState::Cancelled);
+        assert_eq!(jobs[1].id, id);
+        assert!(jobs[1].finished_at.is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
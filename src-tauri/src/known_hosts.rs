@@ -0,0 +1,395 @@
+//! Host keys of SSH servers, checked on every handshake.
+//!
+//! Keys are kept in OpenSSH known_hosts format (`host key-type base64`,
+//! with `[host]:port` off port 22) in the app data directory. A key the
+//! store doesn't have, or one that differs from the stored key of the same
+//! type, is put to the user: `session:{id}:hostkey` carries its fingerprint
+//! and `answer_host_key` accepts it, storing it in place of any old key, or
+//! rejects it, which aborts the handshake. A prompt nobody answers rejects
+//! after `PROMPT_TIMEOUT`.
+//!
+//! NETCONF connects have no session to prompt on. They store unknown keys
+//! on first use but refuse changed ones.
+//!
+//! Accepted keys are also pinned, and a key that differs from its pin
+//! raises an alarm instead of this prompt, see hostpin.rs.
+
+use crate::hostpin::{HostKeyChanged, HostPin, HostPins, PinCheck};
+use dashmap::DashMap;
+use russh::keys::key::PublicKey;
+use russh::keys::PublicKeyBase64;
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// How long a handshake waits for `answer_host_key`
+pub const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How a server's key compares with the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyStatus {
+    Known,
+    /// No key of this type is stored for the host
+    Unknown,
+    /// A different key of this type is stored for the host
+    Changed,
+}
+
+/// Payload of `session:{id}:hostkey`
+#[derive(Debug, Clone, Serialize)]
+pub struct HostKeyPrompt {
+    /// Passed back to `answer_host_key`
+    pub request_id: String,
+    pub session_id: String,
+    pub host: String,
+    pub port: u16,
+    /// e.g. `ssh-ed25519`
+    pub algorithm: String,
+    /// `SHA256:` and the unpadded base64 digest, as `ssh-keygen -l` prints
+    pub fingerprint: String,
+    pub status: HostKeyStatus,
+}
+
+/// The known_hosts file, plus the prompts waiting for an answer
+pub struct KnownHosts {
+    /// None keeps accepted keys in memory only
+    path: Option<PathBuf>,
+    /// Lines of the file, comments included
+    lines: Mutex<Vec<String>>,
+    pending: DashMap<String, oneshot::Sender<bool>>,
+    pins: HostPins,
+}
+
+impl Default for KnownHosts {
+    fn default() -> Self {
+        Self {
+            path: None,
+            lines: Mutex::new(Vec::new()),
+            pending: DashMap::new(),
+            pins: HostPins::default(),
+        }
+    }
+}
+
+impl KnownHosts {
+    pub fn load(path: PathBuf) -> Self {
+        let lines = match std::fs::read_to_string(&path) {
+            Ok(text) => text.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Failed to read known hosts");
+                Vec::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            lines: Mutex::new(lines),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_pins(mut self, pins: HostPins) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    pub fn pins(&self) -> &HostPins {
+        &self.pins
+    }
+
+    pub fn check(&self, host: &str, port: u16, key: &PublicKey) -> HostKeyStatus {
+        let name = host_name(host, port);
+        let (algorithm, encoded) = (key.name(), key.public_key_base64());
+        let lines = self.lines.lock().unwrap();
+        let mut status = HostKeyStatus::Unknown;
+        for entry in lines.iter().filter_map(|line| Entry::parse(line)) {
+            if entry.algorithm == algorithm && entry.names().any(|n| n == name) {
+                if entry.key == encoded {
+                    return HostKeyStatus::Known;
+                }
+                status = HostKeyStatus::Changed;
+            }
+        }
+        status
+    }
+
+    /// Store `key` for the host, dropping its other keys of the same type
+    pub fn learn(&self, host: &str, port: u16, key: &PublicKey) {
+        let name = host_name(host, port);
+        let algorithm = key.name();
+        let mut lines = self.lines.lock().unwrap();
+        lines.retain_mut(|line| {
+            let Some(entry) = Entry::parse(line) else {
+                return true;
+            };
+            if entry.algorithm != algorithm || !entry.names().any(|n| n == name) {
+                return true;
+            }
+            // Keep the line for the other hosts sharing it
+            let others: Vec<&str> = entry.names().filter(|n| *n != name).collect();
+            if others.is_empty() {
+                return false;
+            }
+            *line = format!("{} {} {}", others.join(","), entry.algorithm, entry.key);
+            true
+        });
+        lines.push(format!(
+            "{} {} {}",
+            name,
+            algorithm,
+            key.public_key_base64()
+        ));
+        self.persist(&lines);
+    }
+
+    /// Resolve a pending prompt; false if it is unknown or already over
+    pub fn answer(&self, request_id: &str, accept: bool) -> bool {
+        match self.pending.remove(request_id) {
+            Some((_, tx)) => tx.send(accept).is_ok(),
+            None => false,
+        }
+    }
+
+    async fn ask(&self, app_handle: &AppHandle, mut prompt: HostKeyPrompt) -> bool {
+        let request_id = Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(request_id.clone(), tx);
+        prompt.request_id = request_id.clone();
+
+        let event = format!("session:{}:hostkey", prompt.session_id);
+        if let Err(e) = app_handle.emit(&event, &prompt) {
+            warn!(session_id = %prompt.session_id, error = %e, "Failed to emit host key prompt");
+            self.pending.remove(&request_id);
+            return false;
+        }
+        self.wait(&request_id, rx, PROMPT_TIMEOUT).await
+    }
+
+    async fn wait(&self, request_id: &str, rx: oneshot::Receiver<bool>, timeout: Duration) -> bool {
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(answer) => answer.unwrap_or(false),
+            Err(_) => {
+                self.pending.remove(request_id);
+                false
+            }
+        }
+    }
+
+    fn persist(&self, lines: &[String]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = (|| -> io::Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = path.with_extension("tmp");
+            let mut text = lines.join("\n");
+            text.push('\n');
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)
+        })();
+        if let Err(e) = result {
+            warn!(path = %path.display(), error = %e, "Failed to write known hosts");
+        }
+    }
+}
+
+/// How one connect verifies the keys of the servers it reaches
+#[derive(Clone)]
+pub struct HostKeyCheck {
+    known_hosts: Arc<KnownHosts>,
+    /// Where to prompt; None stores unknown keys on first use
+    prompt: Option<AppHandle>,
+}
+
+impl HostKeyCheck {
+    /// Ask the user about unknown and changed keys
+    pub fn prompt(known_hosts: &Arc<KnownHosts>, app_handle: &AppHandle) -> Self {
+        Self {
+            known_hosts: Arc::clone(known_hosts),
+            prompt: Some(app_handle.clone()),
+        }
+    }
+
+    /// Store unknown keys without asking and refuse changed ones
+    pub fn accept_new(known_hosts: &Arc<KnownHosts>) -> Self {
+        Self {
+            known_hosts: Arc::clone(known_hosts),
+            prompt: None,
+        }
+    }
+
+    /// Whether the handshake with `host` may go on with `key`
+    pub async fn verify(&self, session_id: &str, host: &str, port: u16, key: &PublicKey) -> bool {
+        let fingerprint = format!("SHA256:{}", key.fingerprint());
+        let pins = &self.known_hosts.pins;
+        if let PinCheck::Differs(pin) = pins.check(host, port, key.name(), &fingerprint) {
+            return self.repin(session_id, key, pin, fingerprint).await;
+        }
+
+        let status = self.known_hosts.check(host, port, key);
+        let accepted = match (status, &self.prompt) {
+            (HostKeyStatus::Known, _) => {
+                pins.pin_new(host, port, key.name(), &fingerprint);
+                return true;
+            }
+            (HostKeyStatus::Unknown, None) => true,
+            (HostKeyStatus::Changed, None) => false,
+            (status, Some(app_handle)) => {
+                let prompt = HostKeyPrompt {
+                    request_id: String::new(),
+                    session_id: session_id.to_string(),
+                    host: host.to_string(),
+                    port,
+                    algorithm: key.name().to_string(),
+                    fingerprint: fingerprint.clone(),
+                    status,
+                };
+                self.known_hosts.ask(app_handle, prompt).await
+            }
+        };
+        if accepted {
+            info!(session_id = %session_id, host = %host, port = port, fingerprint = %fingerprint, status = ?status, "Host key accepted");
+            self.known_hosts.learn(host, port, key);
+            pins.pin_new(host, port, key.name(), &fingerprint);
+        } else {
+            warn!(session_id = %session_id, host = %host, port = port, fingerprint = %fingerprint, status = ?status, "Host key rejected");
+        }
+        accepted
+    }
+
+    /// A key that differs from its pin: alarm and wait for `repin_host_key`,
+    /// or refuse without a session to alarm on
+    async fn repin(
+        &self,
+        session_id: &str,
+        key: &PublicKey,
+        pin: HostPin,
+        fingerprint: String,
+    ) -> bool {
+        let repinned = match &self.prompt {
+            Some(app_handle) => {
+                let alarm = HostKeyChanged {
+                    request_id: String::new(),
+                    session_id: session_id.to_string(),
+                    host: pin.host.clone(),
+                    port: pin.port,
+                    algorithm: pin.algorithm.clone(),
+                    old_fingerprint: pin.fingerprint.clone(),
+                    new_fingerprint: fingerprint.clone(),
+                    pinned_at: pin.pinned_at,
+                };
+                self.known_hosts.pins.alarm(app_handle, alarm).await
+            }
+            None => false,
+        };
+        if !repinned {
+            warn!(session_id = %session_id, host = %pin.host, port = pin.port, old = %pin.fingerprint, new = %fingerprint, "Host key differs from its pin, refused");
+            return false;
+        }
+        info!(session_id = %session_id, host = %pin.host, port = pin.port, old = %pin.fingerprint, new = %fingerprint, "Host key re-pinned");
+        self.known_hosts
+            .pins
+            .pin(&pin.host, pin.port, &pin.algorithm, &fingerprint);
+        self.known_hosts.learn(&pin.host, pin.port, key);
+        true
+    }
+}
+
+/// One `hosts key-type key` line; markers and hashed hosts aren't matched
+struct Entry<'a> {
+    hosts: &'a str,
+    algorithm: &'a str,
+    key: &'a str,
+}
+
+impl<'a> Entry<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('@') {
+            return None;
+        }
+        let mut fields = line.split_whitespace();
+        Some(Self {
+            hosts: fields.next()?,
+            algorithm: fields.next()?,
+            key: fields.next()?,
+        })
+    }
+
+    fn names(&self) -> impl Iterator<Item = &'a str> {
+        self.hosts.split(',')
+    }
+}
+
+fn host_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::key::KeyPair;
+
+    fn public_key() -> PublicKey {
+        KeyPair::generate_ed25519()
+            .unwrap()
+            .clone_public_key()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_store_and_prompt() {
+        let path = std::env::temp_dir().join(format!("bspt-known-hosts-{}", Uuid::new_v4()));
+        let (key, other) = (public_key(), public_key());
+
+        let store = KnownHosts::load(path.clone());
+        assert_eq!(store.check("10.0.0.1", 22, &key), HostKeyStatus::Unknown);
+        store.learn("10.0.0.1", 22, &key);
+        store.learn("10.0.0.1", 2222, &other);
+        assert_eq!(store.check("10.0.0.1", 22, &key), HostKeyStatus::Known);
+        assert_eq!(store.check("10.0.0.1", 22, &other), HostKeyStatus::Changed);
+
+        let store = KnownHosts::load(path.clone());
+        assert_eq!(store.check("10.0.0.1", 2222, &other), HostKeyStatus::Known);
+        store.learn("10.0.0.1", 22, &other);
+        assert_eq!(store.check("10.0.0.1", 22, &other), HostKeyStatus::Known);
+        assert_eq!(store.check("10.0.0.1", 22, &key), HostKeyStatus::Changed);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.starts_with("[10.0.0.1]:2222 ssh-ed25519 "));
+        std::fs::remove_file(&path).unwrap();
+
+        let check = HostKeyCheck::accept_new(&Arc::new(KnownHosts::default()));
+        assert!(check.verify("nc", "10.0.0.2", 830, &key).await);
+        assert!(check.verify("nc", "10.0.0.2", 830, &key).await);
+        assert!(!check.verify("nc", "10.0.0.2", 830, &other).await);
+        // The pin holds even once known_hosts has the new key
+        assert_eq!(check.known_hosts.pins().list().len(), 1);
+        check.known_hosts.learn("10.0.0.2", 830, &other);
+        assert!(!check.verify("nc", "10.0.0.2", 830, &other).await);
+
+        let (tx, rx) = oneshot::channel();
+        store.pending.insert("r1".to_string(), tx);
+        assert!(store.answer("r1", true));
+        assert!(store.wait("r1", rx, PROMPT_TIMEOUT).await);
+        assert!(!store.answer("r1", false));
+        let (tx, rx) = oneshot::channel();
+        store.pending.insert("r2".to_string(), tx);
+        assert!(!store.wait("r2", rx, Duration::from_millis(10)).await);
+        assert!(!store.answer("r2", true));
+    }
+}
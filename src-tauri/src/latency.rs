@@ -0,0 +1,231 @@
+//! Round-trip latency per session, so a slow console shows up as a number
+//! instead of a complaint about the app.
+//!
+//! Keystrokes are the probes: the first input sent on a quiet line is
+//! timestamped and the first output after it, normally its echo, closes the
+//! sample. Input sent while output is still flowing is not timed, since the
+//! next chunk would not be its echo. `probe` sends a bare CR for an explicit
+//! measurement and waits for the prompt it brings back.
+
+use crate::command::{OutputWatcher, PROMPT_RE};
+use crate::session::{SessionError, SessionManager};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+use tracing::debug;
+
+/// Samples kept per session for the rolling stats
+const WINDOW: usize = 32;
+
+/// Output this recent makes the line busy, and input sent on it untimed
+const QUIET: Duration = Duration::from_millis(250);
+
+/// A probe without output for this long counts as unanswered
+const PROBE_EXPIRY: Duration = Duration::from_secs(10);
+
+/// Stats are emitted at most this often per session
+const EMIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Average round trip from which a session is flagged as laggy
+pub const LAGGY_MS: f64 = 500.0;
+
+/// Rolling round-trip stats of a session, sent as `session:{id}:latency`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyStats {
+    /// Samples in the window (at most 32)
+    pub samples: usize,
+    pub last_ms: Option<f64>,
+    pub min_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    /// Mean difference between consecutive samples
+    pub jitter_ms: Option<f64>,
+    /// Average round trip is at least `LAGGY_MS`
+    pub laggy: bool,
+    /// Probes that got no output within 10s
+    pub unanswered: u64,
+    /// Unix milliseconds of the last sample
+    pub last_sample_at: Option<i64>,
+}
+
+#[derive(Default)]
+struct SessionLatency {
+    /// When the pending probe was sent
+    pending: Option<Instant>,
+    last_received: Option<Instant>,
+    samples: VecDeque<f64>,
+    unanswered: u64,
+    last_sample_at: Option<i64>,
+    last_emit: Option<Instant>,
+}
+
+impl SessionLatency {
+    fn expire(&mut self, now: Instant) {
+        if self
+            .pending
+            .is_some_and(|sent| now.duration_since(sent) > PROBE_EXPIRY)
+        {
+            self.pending = None;
+            self.unanswered += 1;
+        }
+    }
+
+    fn stats(&self) -> LatencyStats {
+        let samples = &self.samples;
+        if samples.is_empty() {
+            return LatencyStats {
+                unanswered: self.unanswered,
+                ..LatencyStats::default()
+            };
+        }
+        let mut sorted: Vec<f64> = samples.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let avg = sorted.iter().sum::<f64>() / sorted.len() as f64;
+        // Nearest rank
+        let rank = ((sorted.len() as f64 * 0.95).ceil() as usize).max(1);
+        let jitter = (samples.len() > 1).then(|| {
+            let diffs: f64 = samples
+                .iter()
+                .zip(samples.iter().skip(1))
+                .map(|(a, b)| (b - a).abs())
+                .sum();
+            diffs / (samples.len() - 1) as f64
+        });
+        LatencyStats {
+            samples: samples.len(),
+            last_ms: samples.back().copied(),
+            min_ms: sorted.first().copied(),
+            max_ms: sorted.last().copied(),
+            avg_ms: Some(avg),
+            p95_ms: Some(sorted[rank - 1]),
+            jitter_ms: jitter,
+            laggy: avg >= LAGGY_MS,
+            unanswered: self.unanswered,
+            last_sample_at: self.last_sample_at,
+        }
+    }
+}
+
+/// Pending probes and samples of every session
+#[derive(Default)]
+pub struct LatencyTracker {
+    sessions: DashMap<String, SessionLatency>,
+}
+
+impl LatencyTracker {
+    /// Time input sent to a session unless a probe is pending or the line
+    /// is busy; `force` times it anyway
+    pub fn sent(&self, session_id: &str, force: bool) {
+        let now = Instant::now();
+        let mut session = self.sessions.entry(session_id.to_string()).or_default();
+        session.expire(now);
+        let busy = session
+            .last_received
+            .is_some_and(|at| now.duration_since(at) < QUIET);
+        if force || (session.pending.is_none() && !busy) {
+            session.pending = Some(now);
+        }
+    }
+
+    /// Close the pending probe with output that just arrived. Returns the
+    /// stats when a sample was taken and they are due to be emitted.
+    pub fn received(&self, session_id: &str) -> Option<LatencyStats> {
+        let now = Instant::now();
+        let mut session = self.sessions.entry(session_id.to_string()).or_default();
+        session.expire(now);
+        session.last_received = Some(now);
+        let sent = session.pending.take()?;
+
+        let ms = now.duration_since(sent).as_secs_f64() * 1000.0;
+        if session.samples.len() == WINDOW {
+            session.samples.pop_front();
+        }
+        session.samples.push_back(ms);
+        session.last_sample_at = Some(chrono::Utc::now().timestamp_millis());
+        debug!(session_id = %session_id, rtt_ms = ms, "Latency sample");
+
+        if session
+            .last_emit
+            .is_some_and(|at| now.duration_since(at) < EMIT_INTERVAL)
+        {
+            return None;
+        }
+        session.last_emit = Some(now);
+        Some(session.stats())
+    }
+
+    pub fn stats(&self, session_id: &str) -> LatencyStats {
+        self.sessions
+            .get(session_id)
+            .map(|session| session.stats())
+            .unwrap_or_default()
+    }
+
+    pub fn remove(&self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+}
+
+/// Record output of a session and emit its stats when a sample was taken
+pub fn on_received(manager: &SessionManager, session_id: &str) {
+    if let Some(stats) = manager.latency().received(session_id) {
+        let _ = manager
+            .app_handle()
+            .emit(&format!("session:{}:latency", session_id), &stats);
+    }
+}
+
+/// Send a CR, timed even on a busy line, and wait for the prompt it brings
+/// back. The sample is its first output, the prompt only ends the probe.
+pub async fn probe(
+    manager: &SessionManager,
+    session_id: &str,
+    timeout: Duration,
+) -> Result<LatencyStats, SessionError> {
+    let mut watcher = OutputWatcher::new(manager, session_id)?;
+    manager.latency().sent(session_id, true);
+    manager.send_data(session_id, b"\r".to_vec()).await?;
+    watcher.expect(&PROMPT_RE, timeout).await?;
+    Ok(manager.latency().stats(session_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_and_busy_line() {
+        let tracker = LatencyTracker::default();
+        assert!(tracker.received("s1").is_none());
+
+        // Output just arrived, so the line is busy and input is not timed
+        tracker.sent("s1", false);
+        assert!(tracker.received("s1").is_none());
+        assert_eq!(tracker.stats("s1").samples, 0);
+
+        // A forced probe is timed anyway, and the first sample is emitted
+        tracker.sent("s1", true);
+        std::thread::sleep(Duration::from_millis(20));
+        let stats = tracker.received("s1").unwrap();
+        assert_eq!(stats.samples, 1);
+        assert!(stats.last_ms.unwrap() >= 20.0);
+        assert!(!stats.laggy);
+        // The echo closed the probe: more output adds no sample
+        assert!(tracker.received("s1").is_none());
+
+        // Quiet again: a keystroke is timed, but emission is throttled
+        std::thread::sleep(QUIET);
+        tracker.sent("s1", false);
+        assert!(tracker.received("s1").is_none());
+        let stats = tracker.stats("s1");
+        assert_eq!(stats.samples, 2);
+        assert!(stats.min_ms.unwrap() <= stats.max_ms.unwrap());
+        assert!(stats.jitter_ms.is_some());
+
+        tracker.remove("s1");
+        assert_eq!(tracker.stats("s1").samples, 0);
+    }
+}
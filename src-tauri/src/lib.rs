@@ -1,20 +1,146 @@
+mod activity;
+mod alerts;
+mod applog;
+mod archive;
+mod automation;
+mod backup;
+mod batch;
+mod benchmark;
+mod bundle;
+mod capture;
+mod charset;
+mod cli;
+mod clock;
+mod command;
+mod compliance;
+mod control;
+mod credentials;
+mod crypto;
+mod deeplink;
+mod device_archive;
+mod discovery;
+mod dryrun;
+mod emitter;
+mod events;
+mod exec;
+mod flow;
+mod forward;
+mod ftp;
+mod guard;
+mod handshake;
+mod history;
+mod hostpin;
+mod jobs;
+mod known_hosts;
+mod latency;
+mod lines;
+mod macros;
+mod metrics;
+mod mux;
+mod netconf;
+mod passphrase;
+mod pipeline;
+mod plugin;
+mod plugin_session;
+mod precheck;
+mod profile;
+mod ratelimit;
 mod reconnect;
 mod ringbuffer;
+mod rotate;
+mod script;
+mod scrollback;
+mod securecrt;
 mod session;
+mod session_log;
+mod settings;
+mod sftp;
+mod snmp;
 mod ssh;
+mod ssh_config;
+mod syslog;
 mod telnet;
+mod textfsm;
+mod tftp;
+mod timeline;
+mod topology;
 mod tracer;
+mod tunnel;
+mod upgrade;
+mod vars;
+mod vault;
+mod views;
 mod vrp;
+mod watch;
+mod zmodem;
 
+use alerts::{Alert, AlertEngine, AlertRule, AlertStore};
+use applog::{AppLog, LogEntry, LogFilter};
+use automation::{AutomationRule, AutomationStore};
+use backup::{BackupResult, BackupStore, DiffHunk, Snapshot};
+use batch::{BatchJob, BatchOptions, BatchTarget, CommandResult};
+use benchmark::{BenchmarkMode, BenchmarkOptions, BenchmarkReport};
+use bundle::BundleSummary;
+use capture::{CaptureHeader, CaptureStatus, ReplayReport};
+use clock::ClockAudit;
+use command::CommandOutput;
+use compliance::{ComplianceReport, Rule, RuleSet};
+use control::{ControlContext, ControlServer, ControlStatus};
+use credentials::{CredentialBroker, CredentialRequest, Credentials};
 use dashmap::DashMap;
+use deeplink::{DeepLink, DeepLinkError, DeepLinks, LinkState, LinkTarget};
+use device_archive::{
+    DeviceArchive, DeviceArchiveError, DeviceListing, DeviceSummary, PruneReport,
+};
+use discovery::{DiscoveredHost, ScanOptions};
+use dryrun::DryRunReport;
+use exec::ExecOutput;
+use forward::{Forwarder, ForwarderStatus};
+use ftp::{FtpOptions, FtpService, FtpStatus, FtpTransfer};
+use guard::{GuardSnapshot, GuardStore, SnapshotInfo, VerificationReport};
+use history::{ConnectionHistory, ConnectionRecord};
+use hostpin::{HostPin, HostPins};
+use jobs::{JobInfo, JobKind, JobRegistry};
+use known_hosts::KnownHosts;
+use latency::LatencyStats;
+use macros::{Macro, MacroStore, PlaybackOptions};
+use metrics::{ExporterContext, ExporterStatus, MetricsExporter, MetricsSnapshot};
+use netconf::{Datastore, DefaultOperation, NetconfConfig, NetconfManager, NetconfSessionInfo};
+use pipeline::{Stage, StageStatus};
+use plugin::PluginInfo;
+use precheck::{HostReachability, PrecheckOptions};
+use profile::{ConflictPolicy, ImportReport, Profile, ProfileStore};
 use reconnect::ReconnectController;
-use session::{Protocol, ReconnectPolicy, SessionConfig, SessionManager};
+use ringbuffer::{BufferConfig, BufferStats, MemoryStats};
+use rotate::{RotationOptions, RotationResult, RotationTarget};
+use script::{ScriptContext, ScriptInfo, ScriptManager};
+use scrollback::{Marker, ScrollbackPage, SearchMatch};
+use session::{JumpHost, Protocol, ReconnectPolicy, SessionConfig, SessionManager};
+use settings::{Settings, SettingsStore};
+use sftp::{SftpListing, SftpTransfer};
+use snmp::{SnmpTrap, TrapContext, TrapOptions, TrapReceiver, TrapStatus};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::Arc;
-use tauri::Manager;
+use std::time::Duration;
+use syslog::{SyslogContext, SyslogMessage, SyslogOptions, SyslogReceiver, SyslogStatus};
+use tauri::{Emitter, Manager};
+use textfsm::{ParsedOutput, Template};
+use tftp::{TftpOptions, TftpService, TftpStatus, TftpTransfer};
+use timeline::{Timeline, TimelineEntry, TimelineQuery};
 use tokio::sync::{mpsc, Mutex};
-use tracer::{IndexStats, LogTracer, SourceLocation, TracerStats};
-use tracing::info;
+use topology::Topology;
+use tracer::{
+    BlameInfo, IndexStats, LogTracer, ResolvedFrame, SourceLocation, SymbolLocation, TracerLimits,
+    TracerStats,
+};
+use tracing::{info, warn};
+use tunnel::ForwardInfo;
+use upgrade::UpgradeOptions;
+use vars::{Scope, VariableScope};
+use vault::{Vault, VaultStatus};
+use views::View;
+use zmodem::ZmodemCommand;
 
 /// Manages active reconnection attempts
 struct ReconnectManager {
@@ -51,9 +177,53 @@ impl ReconnectManager {
 async fn create_session(
     config: SessionConfig,
     state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<String, String> {
+    let settings = settings.lock().await.get();
+    start_session(config, Arc::clone(&state), &settings)
+}
+
+/// Fill in global defaults, validate `config` and spawn its session task;
+/// returns the session id
+fn start_session(
+    mut config: SessionConfig,
+    manager: Arc<SessionManager>,
+    settings: &Settings,
 ) -> Result<String, String> {
     let session_id = SessionManager::generate_session_id();
-    let manager = Arc::clone(&state);
+    settings.apply(&mut config, &session_id);
+    config.buffer.unwrap_or_default().validate()?;
+    config.auth.validate()?;
+    for jump in &config.jump_hosts {
+        jump.auth.validate()?;
+    }
+    config.delivery.unwrap_or_default().validate(
+        &config.buffer.unwrap_or_default(),
+        config.rate_limit.is_some(),
+    )?;
+    if (config.protocol != Protocol::Ssh || config.plugins.transport.is_some())
+        && !config.jump_hosts.is_empty()
+    {
+        return Err("Jump hosts are only supported for SSH sessions".to_string());
+    }
+    if (config.protocol != Protocol::Ssh || config.plugins.transport.is_some())
+        && config.subsystem.is_some()
+    {
+        return Err("Subsystems are only supported for SSH sessions".to_string());
+    }
+    if (config.protocol != Protocol::Ssh || config.plugins.transport.is_some())
+        && !config.env.is_empty()
+    {
+        return Err("Environment variables are only supported for SSH sessions".to_string());
+    }
+    if let Some(name) = config
+        .env
+        .keys()
+        .find(|name| name.is_empty() || name.contains(['=', '\0']))
+    {
+        return Err(format!("Invalid environment variable name {:?}", name));
+    }
+    manager.plugins().check(&config.plugins)?;
 
     info!(
         session_id = %session_id,
@@ -68,12 +238,7 @@ async fn create_session(
 
     // Spawn the session task
     tokio::spawn(async move {
-        let result = match config_clone.protocol {
-            Protocol::Ssh => ssh::run_ssh_session(id.clone(), config_clone, manager).await,
-            Protocol::Telnet => telnet::run_telnet_session(id.clone(), config_clone, manager).await,
-        };
-
-        if let Err(e) = result {
+        if let Err(e) = session::run_session(id.clone(), config_clone, manager).await {
             tracing::error!(session_id = %id, error = %e, "Session error");
         }
     });
@@ -81,12 +246,62 @@ async fn create_session(
     Ok(session_id)
 }
 
+/// Open another terminal on the SSH connection of `session_id` instead of
+/// logging in again; returns the new session id. The connection stays open
+/// until its last session closes, see mux.rs.
+#[tauri::command]
+async fn open_ssh_channel(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<String, String> {
+    let parent = state
+        .get(&session_id)
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+    if state.connections().get(&session_id).is_none() {
+        return Err(format!("Session {} has no SSH connection", session_id));
+    }
+    let id = SessionManager::generate_session_id();
+    // Logs and captures are per session
+    let mut config = parent.config.clone();
+    config.log_file = None;
+    config.capture_file = None;
+    settings.lock().await.get().apply(&mut config, &id);
+    let processors = state
+        .plugins()
+        .processors(&config.plugins.processors)
+        .map_err(|e| e.to_string())?;
+
+    let manager = Arc::clone(&state);
+    let channel_id = id.clone();
+    tokio::spawn(async move {
+        if let Err(e) =
+            ssh::run_ssh_channel(channel_id.clone(), &session_id, config, manager, processors).await
+        {
+            tracing::error!(session_id = %channel_id, error = %e, "Session error");
+        }
+    });
+    Ok(id)
+}
+
+/// Sessions on the same SSH connection as `session_id`, itself included;
+/// empty if it has none
+#[tauri::command]
+async fn list_shared_sessions(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<String>, String> {
+    Ok(state.connections().siblings(&session_id))
+}
+
 #[tauri::command]
 async fn send_input(
     session_id: String,
     data: Vec<u8>,
     state: tauri::State<'_, Arc<SessionManager>>,
+    macros: tauri::State<'_, Arc<Mutex<MacroStore>>>,
 ) -> Result<(), String> {
+    macros.lock().await.record(&session_id, &data);
     state.send_data(&session_id, data).await.map_err(|e| e.to_string())
 }
 
@@ -99,14 +314,167 @@ async fn disconnect_session(
     state.disconnect(&session_id).await.map_err(|e| e.to_string())
 }
 
+/// The calling window's terminal size; with several windows on the session
+/// the device gets the smallest (see views.rs)
 #[tauri::command]
 async fn resize_terminal(
     session_id: String,
     cols: u32,
     rows: u32,
+    window: tauri::Window,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    Ok(views::resize(&state, &session_id, window.label(), cols, rows).await?)
+}
+
+/// Show a session in another window too, replaying its scrollback there
+#[tauri::command]
+async fn attach_view(
+    session_id: String,
+    window_label: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<View>, String> {
+    Ok(views::attach(&state, &session_id, &window_label).await?)
+}
+
+#[tauri::command]
+async fn detach_view(
+    session_id: String,
+    window_label: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    Ok(views::detach(&state, &session_id, &window_label).await?)
+}
+
+#[tauri::command]
+async fn list_views(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<View>, String> {
+    Ok(state.views().list(&session_id))
+}
+
+/// Forward `local_port` (0 picks a free one) on `bind_address`, loopback
+/// by default, to `remote_host:remote_port` over an SSH session, like
+/// `ssh -L`
+#[tauri::command]
+async fn open_local_forward(
+    session_id: String,
+    local_port: u16,
+    remote_host: String,
+    remote_port: u16,
+    bind_address: Option<String>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<ForwardInfo, String> {
+    Ok(tunnel::open_local_forward(
+        &state,
+        &session_id,
+        bind_address.as_deref(),
+        local_port,
+        &remote_host,
+        remote_port,
+    )
+    .await?)
+}
+
+/// Have the server listen on `remote_port` (0 lets it pick) on
+/// `bind_address`, its loopback by default, and forward each connection to
+/// `target_host:target_port` from here, like `ssh -R`
+#[tauri::command]
+async fn open_remote_forward(
+    session_id: String,
+    remote_port: u16,
+    target_host: String,
+    target_port: u16,
+    bind_address: Option<String>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<ForwardInfo, String> {
+    Ok(tunnel::open_remote_forward(
+        &state,
+        &session_id,
+        bind_address.as_deref(),
+        remote_port,
+        &target_host,
+        target_port,
+    )
+    .await?)
+}
+
+/// Run a SOCKS5 proxy on `local_port` (0 picks a free one) on
+/// `bind_address`, loopback by default, whose connections go out over an
+/// SSH session, like `ssh -D`
+#[tauri::command]
+async fn open_dynamic_forward(
+    session_id: String,
+    local_port: u16,
+    bind_address: Option<String>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<ForwardInfo, String> {
+    Ok(
+        tunnel::open_dynamic_forward(&state, &session_id, bind_address.as_deref(), local_port)
+            .await?,
+    )
+}
+
+/// Port forwards of one session, or of all sessions
+#[tauri::command]
+async fn list_forwards(
+    session_id: Option<String>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<ForwardInfo>, String> {
+    Ok(state.tunnels().list(session_id.as_deref()))
+}
+
+/// Stop a port forward; false if it is unknown
+#[tauri::command]
+async fn close_forward(
+    forward_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<bool, String> {
+    Ok(state.tunnels().close(&forward_id).await)
+}
+
+/// List a remote directory over SFTP; an empty path lists the login
+/// directory
+#[tauri::command]
+async fn sftp_list(
+    session_id: String,
+    path: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<SftpListing, String> {
+    Ok(sftp::list(&state, &session_id, &path).await?)
+}
+
+/// Copy a remote file to disk, with `session:{id}:sftp` progress
+#[tauri::command]
+async fn sftp_download(
+    session_id: String,
+    remote_path: String,
+    local_path: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<SftpTransfer, String> {
+    Ok(sftp::download(&state, &session_id, &remote_path, &local_path).await?)
+}
+
+/// Copy a file from disk to the device, with `session:{id}:sftp` progress
+#[tauri::command]
+async fn sftp_upload(
+    session_id: String,
+    local_path: String,
+    remote_path: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<SftpTransfer, String> {
+    Ok(sftp::upload(&state, &session_id, &local_path, &remote_path).await?)
+}
+
+/// Remove a remote file or empty directory
+#[tauri::command]
+async fn sftp_delete(
+    session_id: String,
+    path: String,
     state: tauri::State<'_, Arc<SessionManager>>,
 ) -> Result<(), String> {
-    state.resize(&session_id, cols, rows).await.map_err(|e| e.to_string())
+    Ok(sftp::delete(&state, &session_id, &path).await?)
 }
 
 #[tauri::command]
@@ -129,6 +497,21 @@ async fn set_auto_pagination(
     state.set_auto_pagination(&session_id, enabled).await.map_err(|e| e.to_string())
 }
 
+/// Send a BREAK to put a device behind a console server into its boot menu.
+/// SSH sends an RFC 4335 break request of `duration_ms` (default 500). Telnet
+/// sends IAC BREAK, which has no length: `duration_ms` is ignored and the
+/// server decides.
+#[tauri::command]
+async fn send_break(
+    session_id: String,
+    duration_ms: Option<u32>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    Ok(state
+        .send_break(&session_id, duration_ms.unwrap_or(500))
+        .await?)
+}
+
 #[tauri::command]
 async fn notify_buffer_drained(
     session_id: String,
@@ -137,6 +520,150 @@ async fn notify_buffer_drained(
     state.notify_drained(&session_id).await.map_err(|e| e.to_string())
 }
 
+/// Ack output of a session with acked delivery, up to chunk `seq`
+#[tauri::command]
+async fn ack_output(
+    session_id: String,
+    seq: u64,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    Ok(state.ack_output(&session_id, seq).await?)
+}
+
+#[tauri::command]
+async fn set_buffer_config(
+    session_id: String,
+    config: BufferConfig,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    state
+        .set_buffer_config(&session_id, config)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_buffer_stats(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<BufferStats, String> {
+    state
+        .buffer_stats(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Lines from `start_line`, or with `marker_id` from `before` lines ahead
+/// of that marker
+#[tauri::command]
+async fn fetch_scrollback(
+    session_id: String,
+    start_line: Option<u64>,
+    count: usize,
+    marker_id: Option<String>,
+    before: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<ScrollbackPage, String> {
+    let page = match marker_id {
+        Some(marker_id) => {
+            state
+                .fetch_from_marker(&session_id, &marker_id, before.unwrap_or(0), count)
+                .await
+        }
+        None => {
+            state
+                .fetch_scrollback(&session_id, start_line.unwrap_or(0), count)
+                .await
+        }
+    };
+    page.map_err(|e| e.to_string())
+}
+
+/// Mark the current end of a session's output, to fetch from later
+#[tauri::command]
+async fn add_marker(
+    session_id: String,
+    label: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Marker, String> {
+    Ok(state.add_marker(&session_id, label).await?)
+}
+
+/// A session's markers, oldest first
+#[tauri::command]
+async fn list_markers(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<Marker>, String> {
+    Ok(state.markers(&session_id).await?)
+}
+
+/// Stages of a session's output data path, in order
+#[tauri::command]
+async fn get_pipeline(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<StageStatus>, String> {
+    Ok(state.pipeline(&session_id)?)
+}
+
+/// Switch a stage of a session's data path on or off while it runs
+#[tauri::command]
+async fn set_pipeline_stage(
+    session_id: String,
+    stage: Stage,
+    enabled: bool,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<StageStatus>, String> {
+    Ok(state.set_pipeline_stage(&session_id, stage, enabled)?)
+}
+
+#[tauri::command]
+async fn search_output(
+    session_id: String,
+    query: String,
+    is_regex: Option<bool>,
+    case_sensitive: Option<bool>,
+    max_results: Option<usize>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<SearchMatch>, String> {
+    state
+        .search_output(
+            &session_id,
+            &query,
+            is_regex.unwrap_or(false),
+            case_sensitive.unwrap_or(false),
+            max_results.unwrap_or(100),
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn run_benchmark(
+    mode: BenchmarkMode,
+    options: Option<BenchmarkOptions>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<BenchmarkReport, String> {
+    let app_handle = state.app_handle().clone();
+    benchmark::run_benchmark(app_handle, mode, options.unwrap_or_default()).await
+}
+
+#[tauri::command]
+async fn get_memory_stats(
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<MemoryStats, String> {
+    Ok(state.memory_budget().stats())
+}
+
+#[tauri::command]
+async fn set_memory_cap(
+    cap: usize,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    state.memory_budget().set_cap(cap)
+}
+
 #[tauri::command]
 async fn index_source_directory(
     path: String,
@@ -149,13 +676,60 @@ async fn index_source_directory(
         .map_err(|e| e.to_string())
 }
 
+/// Match a log line to its source; with `session_id`, a match is also
+/// recorded on that device's timeline
 #[tauri::command]
 async fn match_log_line(
     line: String,
+    session_id: Option<String>,
     state: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+    manager: tauri::State<'_, Arc<SessionManager>>,
+    timeline: tauri::State<'_, Arc<Timeline>>,
 ) -> Result<Option<SourceLocation>, String> {
     let tracer = state.lock().await;
-    Ok(tracer.match_log(&line).cloned())
+    let location = tracer.match_log(&line).cloned();
+    if let (Some(session_id), Some(location)) = (session_id, &location) {
+        let at = format!("{}:{}", location.file, location.line);
+        timeline::traced(&timeline, &manager, &session_id, &line, at);
+    }
+    Ok(location)
+}
+
+#[tauri::command]
+async fn resolve_symbol(
+    name: String,
+    state: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+) -> Result<Vec<SymbolLocation>, String> {
+    let tracer = state.lock().await;
+    Ok(tracer.resolve_symbol(&name))
+}
+
+#[tauri::command]
+async fn resolve_backtrace(
+    text: String,
+    state: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+) -> Result<Vec<ResolvedFrame>, String> {
+    let tracer = state.lock().await;
+    Ok(tracer.resolve_backtrace(&text))
+}
+
+#[tauri::command]
+async fn get_blame(file: String, line: u32) -> Result<BlameInfo, String> {
+    tokio::task::spawn_blocking(move || {
+        tracer::git_blame(Path::new(&file), line).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn set_tracer_limits(
+    limits: TracerLimits,
+    state: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+) -> Result<(), String> {
+    let mut tracer = state.lock().await;
+    tracer.set_limits(limits);
+    Ok(())
 }
 
 #[tauri::command]
@@ -166,102 +740,2602 @@ async fn get_tracer_stats(
     Ok(tracer.get_stats())
 }
 
-/// Attempt to reconnect a session with the given configuration
-/// Uses exponential backoff strategy
 #[tauri::command]
-async fn reconnect_session(
-    session_id: String,
-    config: SessionConfig,
-    policy: Option<ReconnectPolicy>,
-    session_state: tauri::State<'_, Arc<SessionManager>>,
-    reconnect_state: tauri::State<'_, Arc<ReconnectManager>>,
-) -> Result<String, String> {
-    let policy = policy.unwrap_or_default();
-    let manager = Arc::clone(&session_state);
+async fn save_profile(
+    profile: Profile,
+    state: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<Profile, String> {
+    let mut store = state.lock().await;
+    Ok(store.save(profile)?)
+}
 
-    info!(
-        session_id = %session_id,
-        host = %config.host,
-        port = config.port,
-        protocol = ?config.protocol,
-        "Starting reconnection"
-    );
+#[tauri::command]
+async fn list_profiles(
+    state: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<Vec<Profile>, String> {
+    let store = state.lock().await;
+    Ok(store.list())
+}
 
-    let controller = ReconnectController::new(session_id.clone(), config, policy);
+#[tauri::command]
+async fn delete_profile(
+    id: String,
+    state: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<(), String> {
+    let mut store = state.lock().await;
+    Ok(store.delete(&id)?)
+}
 
-    // Register cancel handle
-    reconnect_state.register(session_id.clone(), controller.get_cancel_handle());
+/// Quick-connect search over name, host, folder and tags
+#[tauri::command]
+async fn search_profiles(
+    query: String,
+    limit: Option<usize>,
+    state: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<Vec<Profile>, String> {
+    let store = state.lock().await;
+    Ok(store.search(&query, limit.unwrap_or(profile::DEFAULT_SEARCH_LIMIT)))
+}
 
-    // Run reconnection
-    let result = controller.run(manager).await;
+/// Write all profiles to a passphrase-encrypted archive; saved passwords
+/// are only included with `include_credentials`. Returns the profile count.
+#[tauri::command]
+async fn export_profiles(
+    path: String,
+    passphrase: String,
+    include_credentials: Option<bool>,
+    state: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<usize, String> {
+    let profiles = state.lock().await.list();
+    let include_credentials = include_credentials.unwrap_or(false);
+    let count = tokio::task::spawn_blocking(move || {
+        archive::export(Path::new(&path), profiles, &passphrase, include_credentials)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    info!(count, include_credentials, "Exported profiles");
+    Ok(count)
+}
 
-    // Clean up cancel handle
-    reconnect_state.remove(&session_id);
+/// Import profiles from an archive written by `export_profiles`
+#[tauri::command]
+async fn import_profiles(
+    path: String,
+    passphrase: String,
+    on_conflict: Option<ConflictPolicy>,
+    state: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<ImportReport, String> {
+    let profiles =
+        tokio::task::spawn_blocking(move || archive::import(Path::new(&path), &passphrase))
+            .await
+            .map_err(|e| e.to_string())??;
 
-    result
+    let mut store = state.lock().await;
+    let report = store.import(profiles, on_conflict.unwrap_or_default())?;
+    info!(
+        imported = report.imported.len(),
+        skipped = report.skipped.len(),
+        "Imported profile archive"
+    );
+    Ok(report)
 }
 
-/// Cancel an ongoing reconnection attempt
+/// Import a SecureCRT `Sessions` directory as profiles
 #[tauri::command]
-async fn cancel_reconnect(
-    session_id: String,
-    reconnect_state: tauri::State<'_, Arc<ReconnectManager>>,
-) -> Result<bool, String> {
-    info!(session_id = %session_id, "Cancelling reconnection");
-    Ok(reconnect_state.cancel(&session_id).await)
-}
+async fn import_securecrt(
+    path: String,
+    on_conflict: Option<ConflictPolicy>,
+    state: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<ImportReport, String> {
+    let path = std::path::PathBuf::from(path);
+    let parsed = tokio::task::spawn_blocking(move || securecrt::read_sessions(&path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("bspt=debug".parse().unwrap())
-                .add_directive("russh=info".parse().unwrap()),
-        )
-        .init();
+    let mut store = state.lock().await;
+    let report = store.import(parsed.imported, on_conflict.unwrap_or_default())?;
+    let report = merge_import(report, parsed.skipped, parsed.warnings);
+    info!(
+        imported = report.imported.len(),
+        skipped = report.skipped.len(),
+        "Imported SecureCRT sessions"
+    );
+    Ok(report)
+}
 
-    let builder = tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .setup(|app| {
-            let session_manager = SessionManager::new(app.handle().clone());
-            app.manage(Arc::new(session_manager));
+/// Import `Host` blocks from an OpenSSH config, `~/.ssh/config` by default
+#[tauri::command]
+async fn import_ssh_config(
+    path: Option<String>,
+    on_conflict: Option<ConflictPolicy>,
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<ImportReport, String> {
+    let home = app_handle.path().home_dir().map_err(|e| e.to_string())?;
+    let path = path
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| home.join(".ssh").join("config"));
+    let config = ssh_config::SshConfig::load(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let parsed = ssh_config::profiles(&config, &home);
 
-            // Initialize ReconnectManager for managing reconnection attempts
-            let reconnect_manager = ReconnectManager::new();
-            app.manage(Arc::new(reconnect_manager));
+    let mut store = state.lock().await;
+    let report = store.import(parsed.imported, on_conflict.unwrap_or_default())?;
+    let report = merge_import(report, parsed.skipped, parsed.warnings);
+    info!(
+        imported = report.imported.len(),
+        skipped = report.skipped.len(),
+        "Imported ssh config hosts"
+    );
+    Ok(report)
+}
 
-            // Initialize LogTracer for log-to-source mapping
-            let log_tracer = LogTracer::new();
-            app.manage(Arc::new(Mutex::new(log_tracer)));
+/// What connecting to an OpenSSH config alias would use, to prefill a
+/// session: host, port, user, identity file and `ProxyJump` hops
+#[tauri::command]
+async fn resolve_ssh_host(
+    alias: String,
+    path: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<ssh_config::ResolvedHost, String> {
+    let home = app_handle.path().home_dir().map_err(|e| e.to_string())?;
+    let path = path
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| home.join(".ssh").join("config"));
+    let config = ssh_config::SshConfig::load(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(ssh_config::resolve_host(&config, &alias, &home))
+}
 
-            #[cfg(target_os = "windows")]
-            {
-                use window_vibrancy::apply_acrylic;
-                let window = app.get_webview_window("main").unwrap();
-                apply_acrylic(&window, Some((18, 18, 18, 125)))
-                    .expect("Unsupported platform! 'apply_acrylic' is only supported on Windows");
-            }
+/// Add what the reader reported to the store's import report, keeping only
+/// warnings about entries that were imported (or not tied to one entry)
+fn merge_import(
+    mut report: ImportReport,
+    skipped: Vec<profile::ImportIssue>,
+    warnings: Vec<profile::ImportIssue>,
+) -> ImportReport {
+    let rejected: Vec<_> = report.skipped.iter().map(|s| s.source.clone()).collect();
+    report.skipped.extend(skipped);
+    report.warnings.extend(
+        warnings
+            .into_iter()
+            .filter(|w| !rejected.contains(&w.source)),
+    );
+    report
+}
+
+/// Username and password for connecting `profile`: `password` if given, a
+/// `credential-request` prompt for `prompt_credentials` profiles, else the
+/// saved password or the vault's when it is unlocked. Without a broker
+/// (unattended runs) prompting profiles fail instead.
+async fn profile_credentials(
+    profile: &Profile,
+    password: Option<String>,
+    app_handle: &tauri::AppHandle,
+    vault: &Mutex<Vault>,
+    credentials: Option<&CredentialBroker>,
+) -> Result<(String, String), String> {
+    if let Some(password) = password {
+        return Ok((profile.username.clone(), password));
+    }
+    if profile.prompt_credentials {
+        let Some(credentials) = credentials else {
+            return Err(format!(
+                "Profile {} asks for credentials on every connect",
+                profile.name
+            ));
+        };
+        let request = CredentialRequest {
+            request_id: String::new(),
+            profile_id: profile.id.clone(),
+            profile_name: profile.name.clone(),
+            host: profile.host.clone(),
+            username: profile.username.clone(),
+        };
+        let supplied = credentials
+            .request(app_handle, request, credentials::PROMPT_TIMEOUT)
+            .await?;
+        let username = supplied
+            .username
+            .unwrap_or_else(|| profile.username.clone());
+        return Ok((username, supplied.password));
+    }
+    let password = match &profile.password {
+        Some(password) => password.clone(),
+        // A locked or missing vault just means no stored password
+        None => vault
+            .lock()
+            .await
+            .secret(&vault::profile_secret_name(&profile.id))
+            .ok()
+            .flatten()
+            .unwrap_or_default(),
+    };
+    Ok((profile.username.clone(), password))
+}
+
+/// Session config for a saved profile with its credentials and jump chain
+/// resolved (see `connect_profile`)
+async fn profile_session_config(
+    id: &str,
+    password: Option<String>,
+    app_handle: &tauri::AppHandle,
+    profiles: &Mutex<ProfileStore>,
+    vault: &Mutex<Vault>,
+    credentials: Option<&CredentialBroker>,
+) -> Result<(Profile, SessionConfig), String> {
+    let (profile, jumps) = {
+        let store = profiles.lock().await;
+        let profile = store
+            .get(id)
+            .ok_or_else(|| profile::ProfileError::NotFound(id.to_string()))?;
+        let jumps: Vec<Profile> = store.jump_chain(profile)?.into_iter().cloned().collect();
+        (profile.clone(), jumps)
+    };
+
+    let (username, password) =
+        profile_credentials(&profile, password, app_handle, vault, credentials).await?;
+    let mut config = profile.session_config(Some(password));
+    config.username = username;
+    for jump in &jumps {
+        let (username, password) =
+            profile_credentials(jump, None, app_handle, vault, credentials).await?;
+        config.jump_hosts.push(JumpHost {
+            host: jump.host.clone(),
+            port: jump.port,
+            username,
+            password,
+            auth: jump.ssh_auth(),
+        });
+    }
+    Ok((profile, config))
+}
+
+/// Open a session from a saved profile; `password` overrides the saved one.
+/// Profiles without a saved password fall back to the vault when it is
+/// unlocked. Profiles with `prompt_credentials` emit `credential-request` and
+/// wait for `provide_credentials` before the session starts. A
+/// `jump_profile` chain becomes the session's jump hosts, each with its own
+/// credentials.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn connect_profile(
+    id: String,
+    password: Option<String>,
+    app_handle: tauri::AppHandle,
+    profiles: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+    vault: tauri::State<'_, Arc<Mutex<Vault>>>,
+    credentials: tauri::State<'_, Arc<CredentialBroker>>,
+) -> Result<String, String> {
+    let (profile, config) = profile_session_config(
+        &id,
+        password,
+        &app_handle,
+        &profiles,
+        &vault,
+        Some(&credentials),
+    )
+    .await?;
+    info!(profile = %profile.name, jumps = config.jump_hosts.len(), "Connecting profile");
+
+    let settings = settings.lock().await.get();
+    start_session(config, Arc::clone(&state), &settings)
+}
+
+/// The saved profile a `bspt://profile/` link names, by name or id
+async fn link_profile(app_handle: &tauri::AppHandle, name: &str) -> Result<Profile, String> {
+    let profiles = app_handle.state::<Arc<Mutex<ProfileStore>>>();
+    let store = profiles.lock().await;
+    store
+        .list()
+        .into_iter()
+        .find(|p| p.name == name)
+        .or_else(|| store.get(name).cloned())
+        .ok_or_else(|| profile::ProfileError::NotFound(name.to_string()).to_string())
+}
+
+/// Start the session a link points at, as `create_session` or
+/// `connect_profile` would. SSH links ask for the password with
+/// `credential-request`, telnet ones leave the login to the device.
+async fn link_session(
+    app_handle: &tauri::AppHandle,
+    target: &LinkTarget,
+) -> Result<String, String> {
+    let vault = app_handle.state::<Arc<Mutex<Vault>>>();
+    let credentials = app_handle.state::<Arc<CredentialBroker>>();
+    let config = match target {
+        LinkTarget::Host {
+            protocol,
+            host,
+            port,
+            username,
+        } => {
+            let mut profile = Profile::adhoc(host, *port, *protocol);
+            profile.username = username.clone().unwrap_or_default();
+            profile.prompt_credentials = *protocol == Protocol::Ssh;
+            let (username, password) =
+                profile_credentials(&profile, None, app_handle, &vault, Some(&credentials)).await?;
+            let mut config = profile.session_config(Some(password));
+            config.username = username;
+            config
+        }
+        LinkTarget::Profile { name } => {
+            let profile = link_profile(app_handle, name).await?;
+            let profiles = app_handle.state::<Arc<Mutex<ProfileStore>>>();
+            let (_, config) = profile_session_config(
+                &profile.id,
+                None,
+                app_handle,
+                &profiles,
+                &vault,
+                Some(&credentials),
+            )
+            .await?;
+            config
+        }
+    };
+    let settings = app_handle
+        .state::<Arc<Mutex<SettingsStore>>>()
+        .lock()
+        .await
+        .get();
+    let state = app_handle.state::<Arc<SessionManager>>();
+    start_session(config, Arc::clone(&state), &settings)
+}
+
+/// Open a link taken out of pending, recording how it went
+async fn open_link(app_handle: &tauri::AppHandle, link: DeepLink) -> DeepLink {
+    let state = match link_session(app_handle, &link.target).await {
+        Ok(session_id) => LinkState::Opened { session_id },
+        Err(error) => LinkState::Failed { error },
+    };
+    app_handle
+        .state::<Arc<DeepLinks>>()
+        .update(app_handle, &link.link_id, state.clone());
+    DeepLink { state, ..link }
+}
+
+/// Open `url` when it points at a trusted host or a saved profile; other
+/// links stay pending behind a `deep-link:confirm` event
+async fn handle_deep_link(app_handle: &tauri::AppHandle, url: &str) -> Result<DeepLink, String> {
+    let settings = app_handle
+        .state::<Arc<Mutex<SettingsStore>>>()
+        .lock()
+        .await
+        .get()
+        .deep_links;
+    if !settings.enabled {
+        return Err(DeepLinkError::Disabled.into());
+    }
+    let target = deeplink::parse(url)?;
+    let trusted = match &target {
+        LinkTarget::Host { host, .. } => settings.trusts(host),
+        LinkTarget::Profile { name } => link_profile(app_handle, name).await.is_ok(),
+    };
+    let links = app_handle.state::<Arc<DeepLinks>>();
+    let link = links.add(url, target);
+    if !trusted {
+        links.update(app_handle, &link.link_id, LinkState::Pending);
+        return Ok(link);
+    }
+    let link = links.take_pending(&link.link_id, LinkState::Opening)?;
+    Ok(open_link(app_handle, link).await)
+}
+
+fn spawn_deep_link(app_handle: tauri::AppHandle, url: String) {
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = handle_deep_link(&app_handle, &url).await {
+            warn!(url = %url, error = %e, "Ignoring deep link");
+        }
+    });
+}
+
+/// Handle an `ssh://`, `telnet://` or `bspt://profile/` link as if the OS
+/// had opened it; the returned link is `opened`, `failed` or `pending`
+#[tauri::command]
+async fn open_deep_link(url: String, app_handle: tauri::AppHandle) -> Result<DeepLink, String> {
+    handle_deep_link(&app_handle, &url).await
+}
+
+/// Links received lately, including any that arrived before the frontend
+/// listened
+#[tauri::command]
+async fn list_deep_links(links: tauri::State<'_, Arc<DeepLinks>>) -> Result<Vec<DeepLink>, String> {
+    Ok(links.list())
+}
+
+/// Open a pending link after the user agreed to it
+#[tauri::command]
+async fn confirm_deep_link(
+    link_id: String,
+    app_handle: tauri::AppHandle,
+    links: tauri::State<'_, Arc<DeepLinks>>,
+) -> Result<DeepLink, String> {
+    let link = links.take_pending(&link_id, LinkState::Opening)?;
+    Ok(open_link(&app_handle, link).await)
+}
+
+#[tauri::command]
+async fn dismiss_deep_link(
+    link_id: String,
+    app_handle: tauri::AppHandle,
+    links: tauri::State<'_, Arc<DeepLinks>>,
+) -> Result<(), String> {
+    links.take_pending(&link_id, LinkState::Dismissed)?;
+    links.update(&app_handle, &link_id, LinkState::Dismissed);
+    Ok(())
+}
+
+/// Make this executable the handler of `schemes` (default ssh, telnet and
+/// bspt) for the current user on Linux and Windows; returns the schemes
+#[tauri::command]
+async fn register_url_handlers(schemes: Option<Vec<String>>) -> Result<Vec<String>, String> {
+    let schemes =
+        schemes.unwrap_or_else(|| deeplink::SCHEMES.iter().map(|s| s.to_string()).collect());
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    deeplink::register(&schemes, &exe)?;
+    info!(schemes = ?schemes, exe = %exe.display(), "Registered URL handlers");
+    Ok(schemes)
+}
+
+/// Run `commands` on every profile in `profile_ids` and every row of the CSV
+/// file at `csv_path`; returns the job id whose `batch:{job_id}` events
+/// report progress and the final report. Credentials are resolved (and
+/// prompted for) before any device connects.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn run_batch(
+    profile_ids: Vec<String>,
+    csv_path: Option<String>,
+    commands: Vec<String>,
+    options: Option<BatchOptions>,
+    app_handle: tauri::AppHandle,
+    profiles: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+    vault: tauri::State<'_, Arc<Mutex<Vault>>>,
+    credentials: tauri::State<'_, Arc<CredentialBroker>>,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<String, String> {
+    let mut targets = Vec::new();
+    for id in &profile_ids {
+        let (profile, config) =
+            profile_session_config(id, None, &app_handle, &profiles, &vault, Some(&credentials))
+                .await?;
+        targets.push(BatchTarget {
+            id: profile.id,
+            name: profile.name,
+            config,
+        });
+    }
+    if let Some(path) = csv_path {
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        targets.extend(batch::parse_targets_csv(&text)?);
+    }
+
+    let job = BatchJob {
+        targets,
+        commands,
+        options: options.unwrap_or_default(),
+    };
+    job.validate()?;
+    let settings = settings.lock().await.get();
+    let control = jobs.start(JobKind::Batch, format!("{} devices", job.targets.len()));
+    Ok(job.spawn(Arc::clone(&state), settings, control))
+}
+
+/// Plan a batch job without connecting: the commands each device would get.
+/// Profiles whose credentials can't be resolved without asking (prompting
+/// profiles, a locked vault behind a jump host) are reported as issues.
+#[tauri::command]
+async fn dry_run_batch(
+    profile_ids: Vec<String>,
+    csv_path: Option<String>,
+    commands: Vec<String>,
+    options: Option<BatchOptions>,
+    app_handle: tauri::AppHandle,
+    profiles: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+    vault: tauri::State<'_, Arc<Mutex<Vault>>>,
+) -> Result<DryRunReport, String> {
+    let mut targets = Vec::new();
+    let mut unresolved = Vec::new();
+    for id in &profile_ids {
+        match profile_session_config(id, None, &app_handle, &profiles, &vault, None).await {
+            Ok((profile, config)) => targets.push(BatchTarget {
+                id: profile.id,
+                name: profile.name,
+                config,
+            }),
+            Err(error) => unresolved.push((id.clone(), error)),
+        }
+    }
+    if let Some(path) = csv_path {
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        targets.extend(batch::parse_targets_csv(&text)?);
+    }
+    if let (true, Some((_, error))) = (targets.is_empty(), unresolved.first()) {
+        return Err(error.clone());
+    }
+
+    let job = BatchJob {
+        targets,
+        commands,
+        options: options.unwrap_or_default(),
+    };
+    let mut report = job.dry_run()?;
+    for (id, error) in unresolved {
+        report.issue(Some(&id), None, error);
+    }
+    Ok(report)
+}
+
+/// TCP-probe the management ports of `hosts` (default 22 and 23),
+/// optionally pinging them too, and report reachability and latency per host
+#[tauri::command]
+async fn precheck_targets(
+    hosts: Vec<String>,
+    options: Option<PrecheckOptions>,
+) -> Result<Vec<HostReachability>, String> {
+    Ok(precheck::sweep(&hosts, &options.unwrap_or_default()).await)
+}
+
+/// Find hosts with open management ports (default 22 and 23) in an IPv4
+/// block of up to 4096 addresses, optionally with banners and a vendor guess
+#[tauri::command]
+async fn scan_subnet(
+    cidr: String,
+    ports: Option<Vec<u16>>,
+    options: Option<ScanOptions>,
+) -> Result<Vec<DiscoveredHost>, String> {
+    let ports = ports
+        .filter(|ports| !ports.is_empty())
+        .unwrap_or_else(|| precheck::DEFAULT_PORTS.to_vec());
+    Ok(discovery::scan_subnet(&cidr, &ports, &options.unwrap_or_default()).await?)
+}
+
+/// Capture `BackupSettings.command` on each profile and store the output as
+/// a new snapshot unless it is unchanged
+#[allow(clippy::too_many_arguments)]
+async fn backup_profiles(
+    profile_ids: &[String],
+    app_handle: &tauri::AppHandle,
+    profiles: &Mutex<ProfileStore>,
+    manager: &Arc<SessionManager>,
+    settings: Settings,
+    vault: &Mutex<Vault>,
+    credentials: Option<&CredentialBroker>,
+    backups: &Mutex<BackupStore>,
+    jobs: &Arc<JobRegistry>,
+) -> Vec<BackupResult> {
+    let control = jobs.start(JobKind::Backup, format!("{} profiles", profile_ids.len()));
+    let mut results = Vec::new();
+    let mut targets = Vec::new();
+    for id in profile_ids {
+        match profile_session_config(id, None, app_handle, profiles, vault, credentials).await {
+            Ok((profile, config)) => targets.push(BatchTarget {
+                id: profile.id,
+                name: profile.name,
+                config,
+            }),
+            Err(error) => results.push(BackupResult {
+                device_id: id.clone(),
+                name: id.clone(),
+                snapshot: None,
+                changed: false,
+                error: Some(error),
+            }),
+        }
+    }
+    if targets.is_empty() {
+        control.finish(Err("no profile could be backed up".to_string()));
+        return results;
+    }
+
+    let config = settings.backup.clone();
+    let archive = DeviceArchive::from_settings(&settings.archive);
+    let job = BatchJob {
+        targets,
+        commands: vec![config.command.clone()],
+        options: BatchOptions::default(),
+    };
+    let report = job.run(&control, Arc::clone(manager), &settings).await;
+
+    let store = backups.lock().await;
+    for device in report.devices {
+        let mut result = BackupResult {
+            device_id: device.id,
+            name: device.name,
+            snapshot: None,
+            changed: false,
+            error: device.error,
+        };
+        match device.commands.into_iter().next() {
+            Some(CommandResult::Ok(output)) => {
+                match store.save(&result.device_id, &output.output, config.keep) {
+                    Ok((snapshot, changed)) => {
+                        if let (true, Some(archive)) = (changed, &archive) {
+                            device_archive::archive_config(
+                                archive,
+                                &device.host,
+                                &snapshot,
+                                &output.output,
+                            );
+                        }
+                        result.snapshot = Some(snapshot);
+                        result.changed = changed;
+                    }
+                    Err(e) => result.error = Some(e.to_string()),
+                }
+            }
+            Some(CommandResult::Failed { error, .. }) => result.error = Some(error),
+            _ => {}
+        }
+        results.push(result);
+    }
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    control.finish(Ok(format!(
+        "{} backed up, {} failed",
+        results.len() - failed,
+        failed
+    )));
+    results
+}
+
+/// Back up the configuration of the given profiles now (default: the
+/// scheduled ones)
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn backup_configs(
+    profile_ids: Option<Vec<String>>,
+    app_handle: tauri::AppHandle,
+    profiles: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+    vault: tauri::State<'_, Arc<Mutex<Vault>>>,
+    credentials: tauri::State<'_, Arc<CredentialBroker>>,
+    backups: tauri::State<'_, Arc<Mutex<BackupStore>>>,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<Vec<BackupResult>, String> {
+    let settings = settings.lock().await.get();
+    let profile_ids = profile_ids.unwrap_or_else(|| settings.backup.profile_ids.clone());
+    Ok(backup_profiles(
+        &profile_ids,
+        &app_handle,
+        &profiles,
+        &state,
+        settings,
+        &vault,
+        Some(&credentials),
+        &backups,
+        &jobs,
+    )
+    .await)
+}
+
+/// Stored configuration versions of a device, oldest first
+#[tauri::command]
+async fn list_config_backups(
+    device_id: String,
+    state: tauri::State<'_, Arc<Mutex<BackupStore>>>,
+) -> Result<Vec<Snapshot>, String> {
+    Ok(state.lock().await.history(&device_id)?)
+}
+
+#[tauri::command]
+async fn get_config_backup(
+    device_id: String,
+    snapshot_id: String,
+    state: tauri::State<'_, Arc<Mutex<BackupStore>>>,
+) -> Result<String, String> {
+    Ok(state.lock().await.read(&device_id, &snapshot_id)?)
+}
+
+#[tauri::command]
+async fn diff_config_backups(
+    device_id: String,
+    from: String,
+    to: String,
+    state: tauri::State<'_, Arc<Mutex<BackupStore>>>,
+) -> Result<Vec<DiffHunk>, String> {
+    Ok(state.lock().await.diff(&device_id, &from, &to)?)
+}
+
+/// Read and validate a JSON file of compliance rules
+#[tauri::command]
+async fn load_compliance_rules(path: String) -> Result<Vec<Rule>, String> {
+    Ok(compliance::load_rules(Path::new(&path))?)
+}
+
+/// Check the latest config backup of each device against `rules`
+#[tauri::command]
+async fn check_compliance(
+    rules: Vec<Rule>,
+    device_ids: Vec<String>,
+    backups: tauri::State<'_, Arc<Mutex<BackupStore>>>,
+) -> Result<Vec<ComplianceReport>, String> {
+    let rules = RuleSet::new(rules)?;
+    let store = backups.lock().await;
+    let mut reports = Vec::new();
+    for device_id in &device_ids {
+        let latest = store.history(device_id)?.pop();
+        let report = match latest {
+            Some(snapshot) => match store.read(device_id, &snapshot.id) {
+                Ok(config) => rules.check(device_id, &snapshot.id, &config),
+                Err(e) => ComplianceReport::unavailable(device_id, &snapshot.id, e.to_string()),
+            },
+            None => ComplianceReport::unavailable(device_id, "", "no config backup".to_string()),
+        };
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Run `command` on an open session and check its output against `rules`
+#[tauri::command]
+async fn check_compliance_live(
+    rules: Vec<Rule>,
+    session_id: String,
+    command: String,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<ComplianceReport, String> {
+    let rules = RuleSet::new(rules)?;
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    let output = command::run_command(&state, &session_id, &command, timeout).await?;
+    Ok(rules.check(&session_id, &command, &output.output))
+}
+
+/// Answer a `credential-request`; false if the request already ended
+#[tauri::command]
+async fn provide_credentials(
+    request_id: String,
+    credentials: Credentials,
+    state: tauri::State<'_, Arc<CredentialBroker>>,
+) -> Result<bool, String> {
+    Ok(state.provide(&request_id, credentials))
+}
+
+/// Answer a `session:{id}:hostkey` prompt: accept stores the key and lets
+/// the handshake go on, reject aborts it. False if the prompt already ended.
+#[tauri::command]
+async fn answer_host_key(
+    request_id: String,
+    accept: bool,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<bool, String> {
+    Ok(state.known_hosts().answer(&request_id, accept))
+}
+
+/// Answer a `session:{id}:hostkey_changed` alarm: repin pins the new key
+/// (and stores it in known_hosts) and lets the handshake go on, false
+/// aborts it. False if the alarm already ended.
+#[tauri::command]
+async fn repin_host_key(
+    request_id: String,
+    repin: bool,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<bool, String> {
+    Ok(state.known_hosts().pins().answer(&request_id, repin))
+}
+
+/// Pinned host key fingerprints, see hostpin.rs
+#[tauri::command]
+async fn list_host_pins(
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<HostPin>, String> {
+    Ok(state.known_hosts().pins().list())
+}
+
+/// Answer a `session:{id}:passphrase` prompt; None cancels the connect.
+/// False if the prompt already ended.
+#[tauri::command]
+async fn answer_passphrase(
+    request_id: String,
+    passphrase: Option<String>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<bool, String> {
+    Ok(state.passphrases().answer(&request_id, passphrase))
+}
+
+/// Abort the connect waiting on a `credential-request`
+#[tauri::command]
+async fn cancel_credentials(
+    request_id: String,
+    state: tauri::State<'_, Arc<CredentialBroker>>,
+) -> Result<bool, String> {
+    Ok(state.cancel(&request_id))
+}
+
+#[tauri::command]
+async fn get_settings(
+    state: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<Settings, String> {
+    Ok(state.lock().await.get())
+}
+
+/// Save settings; they apply to sessions started afterwards
+#[tauri::command]
+async fn set_settings(
+    settings: Settings,
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+    tracer: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+    vault: tauri::State<'_, Arc<Mutex<Vault>>>,
+) -> Result<(), String> {
+    state.lock().await.set(settings.clone())?;
+    settings::apply_live(&settings, &tracer, &vault).await;
+    app_handle
+        .emit("settings-changed", &settings)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn vault_status(state: tauri::State<'_, Arc<Mutex<Vault>>>) -> Result<VaultStatus, String> {
+    Ok(state.lock().await.status())
+}
+
+/// Create the vault with a master password; it starts out unlocked
+#[tauri::command]
+async fn create_vault(
+    master_password: String,
+    state: tauri::State<'_, Arc<Mutex<Vault>>>,
+) -> Result<(), String> {
+    Ok(state.lock().await.create(&master_password)?)
+}
+
+#[tauri::command]
+async fn unlock_vault(
+    master_password: String,
+    state: tauri::State<'_, Arc<Mutex<Vault>>>,
+) -> Result<(), String> {
+    Ok(state.lock().await.unlock(&master_password)?)
+}
+
+#[tauri::command]
+async fn lock_vault(state: tauri::State<'_, Arc<Mutex<Vault>>>) -> Result<(), String> {
+    state.lock().await.lock();
+    Ok(())
+}
+
+/// Re-encrypt the vault under a new master password
+#[tauri::command]
+async fn change_vault_password(
+    old_password: String,
+    new_password: String,
+    state: tauri::State<'_, Arc<Mutex<Vault>>>,
+) -> Result<(), String> {
+    Ok(state
+        .lock()
+        .await
+        .change_password(&old_password, &new_password)?)
+}
+
+/// Store a profile's password in the unlocked vault
+#[tauri::command]
+async fn set_vault_password(
+    profile_id: String,
+    password: String,
+    state: tauri::State<'_, Arc<Mutex<Vault>>>,
+) -> Result<(), String> {
+    Ok(state
+        .lock()
+        .await
+        .set_secret(&vault::profile_secret_name(&profile_id), password)?)
+}
+
+#[tauri::command]
+async fn delete_vault_password(
+    profile_id: String,
+    state: tauri::State<'_, Arc<Mutex<Vault>>>,
+) -> Result<(), String> {
+    Ok(state
+        .lock()
+        .await
+        .remove_secret(&vault::profile_secret_name(&profile_id))?)
+}
+
+/// Most recent successful connections, newest first
+#[tauri::command]
+async fn get_recent_connections(
+    limit: Option<usize>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<ConnectionRecord>, String> {
+    Ok(state
+        .history()
+        .recent(limit.unwrap_or(history::DEFAULT_RECENT_LIMIT)))
+}
+
+/// Connect again to a host from the history
+#[tauri::command]
+async fn connect_recent(
+    id: String,
+    password: Option<String>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<String, String> {
+    let record = state
+        .history()
+        .get(&id)
+        .ok_or_else(|| format!("Connection not found in history: {}", id))?;
+    info!(host = %record.host, "Connecting from history");
+    let settings = settings.lock().await.get();
+    start_session(
+        record.session_config(password),
+        Arc::clone(&state),
+        &settings,
+    )
+}
+
+/// Send a command and wait for the next prompt
+#[tauri::command]
+async fn run_command(
+    session_id: String,
+    command: String,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<CommandOutput, String> {
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    Ok(command::run_command(&state, &session_id, &command, timeout).await?)
+}
+
+/// Run one command over a new SSH connection without a PTY and return its
+/// stdout, stderr and exit status; the interactive `run_command` needs a
+/// session and its prompt
+#[tauri::command]
+async fn exec_command(
+    config: SessionConfig,
+    command: String,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<ExecOutput, String> {
+    config.auth.validate()?;
+    for jump in &config.jump_hosts {
+        jump.auth.validate()?;
+    }
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    Ok(exec::run_command(&config, &command, state.known_hosts(), timeout).await?)
+}
+
+/// Send a command to several sessions concurrently and wait for each
+/// prompt; returns the outcome per session id
+#[tauri::command]
+async fn run_command_multi(
+    session_ids: Vec<String>,
+    command: String,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<HashMap<String, CommandResult>, String> {
+    let mut unique = HashSet::new();
+    let session_ids: Vec<String> = session_ids
+        .into_iter()
+        .filter(|id| unique.insert(id.clone()))
+        .collect();
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    Ok(batch::run_command_multi(&state, &session_ids, &command, timeout).await)
+}
+
+/// Run a command and parse its output with the TextFSM template file at
+/// `template` into records
+#[tauri::command]
+async fn run_command_parsed(
+    session_id: String,
+    command: String,
+    template: String,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<ParsedOutput, String> {
+    let source = std::fs::read_to_string(&template).map_err(|e| format!("{}: {}", template, e))?;
+    let parser = Template::parse(&source)?;
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    let output = command::run_command(&state, &session_id, &command, timeout).await?;
+    let records = parser.parse_text(&output.output)?;
+    Ok(ParsedOutput { output, records })
+}
+
+/// Capture command outputs on a session before a change (default: the
+/// dialect's interface and routing tables) and store them as a snapshot
+#[tauri::command]
+async fn capture_change_snapshot(
+    session_id: String,
+    label: String,
+    commands: Option<Vec<String>>,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    guards: tauri::State<'_, Arc<Mutex<GuardStore>>>,
+) -> Result<GuardSnapshot, String> {
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    let snapshot = guard::capture(&state, &session_id, label, commands, timeout).await?;
+    guards.lock().await.save(&snapshot)?;
+    Ok(snapshot)
+}
+
+#[tauri::command]
+async fn list_change_snapshots(
+    guards: tauri::State<'_, Arc<Mutex<GuardStore>>>,
+) -> Result<Vec<SnapshotInfo>, String> {
+    Ok(guards.lock().await.list()?)
+}
+
+#[tauri::command]
+async fn delete_change_snapshot(
+    snapshot_id: String,
+    guards: tauri::State<'_, Arc<Mutex<GuardStore>>>,
+) -> Result<(), String> {
+    Ok(guards.lock().await.delete(&snapshot_id)?)
+}
+
+/// Re-run a snapshot's commands on a session after the change and report
+/// interfaces down, routes missing and every changed output
+#[tauri::command]
+async fn verify_change(
+    snapshot_id: String,
+    session_id: String,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    guards: tauri::State<'_, Arc<Mutex<GuardStore>>>,
+) -> Result<VerificationReport, String> {
+    let snapshot = guards.lock().await.read(&snapshot_id)?;
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    Ok(guard::verify(&state, &session_id, &snapshot, timeout).await?)
+}
+
+/// Variables of one scope, or of every non-empty scope
+#[tauri::command]
+async fn list_variables(
+    scope: Option<Scope>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<VariableScope>, String> {
+    let variables = state.variables();
+    Ok(match scope {
+        Some(scope) => vec![VariableScope {
+            variables: variables.snapshot(&scope),
+            scope,
+        }],
+        None => variables.list(),
+    })
+}
+
+#[tauri::command]
+async fn set_variable(
+    scope: Scope,
+    name: String,
+    value: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    Ok(state.variables().set(&scope, &name, value)?)
+}
+
+/// Remove one variable, or the whole scope without `name`
+#[tauri::command]
+async fn delete_variable(
+    scope: Scope,
+    name: Option<String>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<bool, String> {
+    let variables = state.variables();
+    Ok(match name {
+        Some(name) => variables.remove(&scope, &name),
+        None => {
+            variables.clear(&scope);
+            true
+        }
+    })
+}
+
+/// Load (or reload) an automation script from a file
+#[tauri::command]
+async fn load_script(
+    path: String,
+    state: tauri::State<'_, Arc<ScriptManager>>,
+) -> Result<ScriptInfo, String> {
+    Ok(state.load(Path::new(&path))?)
+}
+
+#[tauri::command]
+async fn list_scripts(
+    state: tauri::State<'_, Arc<ScriptManager>>,
+) -> Result<Vec<ScriptInfo>, String> {
+    Ok(state.list())
+}
+
+/// Start a loaded script on a session; returns the run id whose
+/// `script:{run_id}` events report progress. The script can switch to the
+/// sessions in `targets` with `use`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn run_script(
+    name: String,
+    session_id: String,
+    targets: Option<Vec<String>>,
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, Arc<ScriptManager>>,
+    sessions: tauri::State<'_, Arc<SessionManager>>,
+    tracer: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<String, String> {
+    let script_sessions = script_sessions(&sessions, session_id, targets)?;
+    let ctx = ScriptContext {
+        app_handle,
+        manager: Arc::clone(&sessions),
+        tracer: Arc::clone(&tracer),
+        sessions: script_sessions.into_iter().map(|(id, _)| id).collect(),
+        run_id: String::new(),
+    };
+    Ok(state.start(&name, ctx, &jobs)?)
+}
+
+/// Plan a loaded script on a session without sending anything: every input
+/// it would send, what it waits for and where it would fail
+#[tauri::command]
+async fn dry_run_script(
+    name: String,
+    session_id: String,
+    targets: Option<Vec<String>>,
+    state: tauri::State<'_, Arc<ScriptManager>>,
+    sessions: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<DryRunReport, String> {
+    let vars = sessions
+        .variables()
+        .snapshot(&Scope::Session(session_id.clone()));
+    let script_sessions = script_sessions(&sessions, session_id, targets)?;
+    Ok(state.dry_run(&name, &script_sessions, vars)?)
+}
+
+/// `session_id` and the other `targets` of a script run with their hosts
+fn script_sessions(
+    sessions: &SessionManager,
+    session_id: String,
+    targets: Option<Vec<String>>,
+) -> Result<Vec<(String, String)>, String> {
+    let mut script_sessions = Vec::new();
+    for id in std::iter::once(session_id).chain(targets.into_iter().flatten()) {
+        if script_sessions.iter().any(|(other, _)| *other == id) {
+            continue;
+        }
+        let handle = sessions
+            .get(&id)
+            .ok_or_else(|| format!("Session not found: {}", id))?;
+        script_sessions.push((id, handle.config.host.clone()));
+    }
+    Ok(script_sessions)
+}
+
+/// Same as `cancel_job` with the run id; false if the script already ended
+#[tauri::command]
+async fn stop_script(
+    run_id: String,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<bool, String> {
+    info!(run_id = %run_id, "Stopping script");
+    Ok(jobs.cancel(&run_id).is_ok())
+}
+
+/// Record what `send_input` sends to a session until `stop_macro_record`
+#[tauri::command]
+async fn start_macro_record(
+    session_id: String,
+    state: tauri::State<'_, Arc<Mutex<MacroStore>>>,
+) -> Result<(), String> {
+    Ok(state.lock().await.start_recording(&session_id)?)
+}
+
+/// Save the recording as `name` (default `macro-<date>-<time>`)
+#[tauri::command]
+async fn stop_macro_record(
+    name: Option<String>,
+    state: tauri::State<'_, Arc<Mutex<MacroStore>>>,
+) -> Result<Macro, String> {
+    let name = name.unwrap_or_else(|| {
+        chrono::Local::now()
+            .format("macro-%Y%m%d-%H%M%S")
+            .to_string()
+    });
+    Ok(state.lock().await.stop_recording(&name)?)
+}
+
+#[tauri::command]
+async fn list_macros(
+    state: tauri::State<'_, Arc<Mutex<MacroStore>>>,
+) -> Result<Vec<Macro>, String> {
+    Ok(state.lock().await.list())
+}
+
+#[tauri::command]
+async fn delete_macro(
+    name: String,
+    state: tauri::State<'_, Arc<Mutex<MacroStore>>>,
+) -> Result<(), String> {
+    Ok(state.lock().await.delete(&name)?)
+}
+
+/// Replay a macro on a session; resolves once every step was sent
+#[tauri::command]
+async fn play_macro(
+    name: String,
+    session_id: String,
+    options: Option<PlaybackOptions>,
+    state: tauri::State<'_, Arc<Mutex<MacroStore>>>,
+    sessions: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    let recorded = state.lock().await.get(&name)?;
+    Ok(macros::play(
+        &sessions,
+        &session_id,
+        &recorded,
+        options.unwrap_or_default(),
+    )
+    .await?)
+}
+
+/// What `play_macro` would send to a session, without sending it
+#[tauri::command]
+async fn dry_run_macro(
+    name: String,
+    session_id: String,
+    options: Option<PlaybackOptions>,
+    state: tauri::State<'_, Arc<Mutex<MacroStore>>>,
+    sessions: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<DryRunReport, String> {
+    let handle = sessions
+        .get(&session_id)
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+    let recorded = state.lock().await.get(&name)?;
+    Ok(macros::dry_run(
+        &session_id,
+        &handle.config.host,
+        &recorded,
+        options.unwrap_or_default(),
+    )?)
+}
+
+/// Upgrade a profile's device to a local firmware image; returns the job
+/// id whose `upgrade:{job_id}` events report each step. Steps listed in
+/// `pause_before` wait for `resume_job`.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn start_upgrade(
+    profile_id: String,
+    password: Option<String>,
+    options: UpgradeOptions,
+    app_handle: tauri::AppHandle,
+    profiles: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+    vault: tauri::State<'_, Arc<Mutex<Vault>>>,
+    credentials: tauri::State<'_, Arc<CredentialBroker>>,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<String, String> {
+    let (_, mut config) = profile_session_config(
+        &profile_id,
+        password,
+        &app_handle,
+        &profiles,
+        &vault,
+        Some(&credentials),
+    )
+    .await?;
+    settings.lock().await.get().apply(&mut config, &profile_id);
+    Ok(upgrade::start_upgrade(
+        &jobs,
+        Arc::clone(&state),
+        config,
+        options,
+    )?)
+}
+
+/// Change the login user's password on every profile in `profile_ids` to
+/// `new_password`, signing in again with it to verify. A verified password
+/// replaces the profile's saved one, or its vault entry when the vault is
+/// unlocked; profiles that prompt for credentials keep nothing.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn rotate_passwords(
+    profile_ids: Vec<String>,
+    new_password: String,
+    options: Option<RotationOptions>,
+    app_handle: tauri::AppHandle,
+    profiles: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+    vault: tauri::State<'_, Arc<Mutex<Vault>>>,
+    credentials: tauri::State<'_, Arc<CredentialBroker>>,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<Vec<RotationResult>, String> {
+    let settings = settings.lock().await.get();
+    let mut targets = Vec::new();
+    for id in &profile_ids {
+        let (profile, mut config) =
+            profile_session_config(id, None, &app_handle, &profiles, &vault, Some(&credentials))
+                .await?;
+        settings.apply(&mut config, id);
+        targets.push(RotationTarget {
+            id: profile.id,
+            name: profile.name,
+            config,
+        });
+    }
+    rotate::validate(&targets, &new_password)?;
+
+    let control = jobs.start(JobKind::Rotation, format!("{} devices", targets.len()));
+    let mut results = rotate::rotate(
+        &state,
+        targets,
+        &new_password,
+        options.unwrap_or_default(),
+        &control,
+    )
+    .await;
+
+    for result in results.iter_mut().filter(|r| r.verified) {
+        let mut store = profiles.lock().await;
+        let Some(profile) = store.get(&result.id).cloned() else {
+            continue;
+        };
+        // A credential prompt may have signed in as someone else
+        if profile.prompt_credentials || profile.username != result.username {
+            continue;
+        }
+        let stored = if profile.password.is_some() {
+            store
+                .save(Profile {
+                    password: Some(new_password.clone()),
+                    ..profile
+                })
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        } else {
+            let mut vault = vault.lock().await;
+            if !vault.status().unlocked {
+                continue;
+            }
+            vault
+                .set_secret(
+                    &vault::profile_secret_name(&profile.id),
+                    new_password.clone(),
+                )
+                .map_err(|e| e.to_string())
+        };
+        match stored {
+            Ok(()) => result.stored = true,
+            Err(e) => result.error = Some(format!("verified, but storing failed: {}", e)),
+        }
+    }
+
+    let failed = results.iter().filter(|r| !r.verified).count();
+    control.finish(Ok(format!(
+        "{} rotated, {} failed",
+        results.len() - failed,
+        failed
+    )));
+    Ok(results)
+}
+
+/// Run the LLDP neighbor command of each session's dialect on every
+/// session in `session_ids` and merge the tables into an adjacency graph
+#[tauri::command]
+async fn collect_topology(
+    session_ids: Vec<String>,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<Topology, String> {
+    if session_ids.is_empty() {
+        return Err("No sessions selected".to_string());
+    }
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    let control = jobs.start(JobKind::Topology, format!("{} devices", session_ids.len()));
+    let topology = topology::collect(&state, &session_ids, timeout, &control).await;
+    let failed = topology
+        .devices
+        .iter()
+        .filter(|d| d.error.is_some())
+        .count();
+    control.finish(Ok(format!(
+        "{} nodes, {} links, {} failed",
+        topology.nodes.len(),
+        topology.links.len(),
+        failed
+    )));
+    Ok(topology)
+}
+
+/// Run `command` every `interval_ms` (default 2s) and emit the lines that
+/// changed as `watch:{id}`; returns the watch id, stopped with `cancel_job`
+#[tauri::command]
+async fn watch_command(
+    session_id: String,
+    command: String,
+    interval_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<String, String> {
+    let interval = interval_ms
+        .map(Duration::from_millis)
+        .unwrap_or(watch::DEFAULT_INTERVAL);
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    Ok(watch::start(
+        &state,
+        &jobs,
+        &session_id,
+        &command,
+        interval,
+        timeout,
+    )?)
+}
+
+/// Compare the clock of every session in `session_ids` with the local
+/// clock, corrected against `ntp_server` when one is given
+#[tauri::command]
+async fn audit_clocks(
+    session_ids: Vec<String>,
+    ntp_server: Option<String>,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<ClockAudit, String> {
+    if session_ids.is_empty() {
+        return Err("No sessions selected".to_string());
+    }
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(command::DEFAULT_COMMAND_TIMEOUT);
+    let control = jobs.start(
+        JobKind::ClockAudit,
+        format!("{} devices", session_ids.len()),
+    );
+    let audit = clock::audit(&state, &session_ids, ntp_server, timeout, &control).await;
+    let skewed = audit.devices.iter().filter(|d| d.skewed).count();
+    let failed = audit.devices.iter().filter(|d| d.error.is_some()).count();
+    control.finish(Ok(format!(
+        "{} skewed, {} failed, max skew {}",
+        skewed,
+        failed,
+        audit
+            .max_skew_ms
+            .map_or("unknown".to_string(), |ms| format!("{}ms", ms))
+    )));
+    Ok(audit)
+}
+
+/// Running jobs, then finished ones newest first
+#[tauri::command]
+async fn list_jobs(jobs: tauri::State<'_, Arc<JobRegistry>>) -> Result<Vec<JobInfo>, String> {
+    Ok(jobs.list())
+}
+
+/// Hold a job at its next checkpoint until `resume_job`
+#[tauri::command]
+async fn pause_job(job_id: String, jobs: tauri::State<'_, Arc<JobRegistry>>) -> Result<(), String> {
+    Ok(jobs.pause(&job_id)?)
+}
+
+#[tauri::command]
+async fn resume_job(
+    job_id: String,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<(), String> {
+    Ok(jobs.resume(&job_id)?)
+}
+
+/// Stop a job at its next checkpoint; work in flight (a command, a file
+/// transfer) finishes first
+#[tauri::command]
+async fn cancel_job(
+    job_id: String,
+    jobs: tauri::State<'_, Arc<JobRegistry>>,
+) -> Result<(), String> {
+    info!(job_id = %job_id, "Cancelling job");
+    Ok(jobs.cancel(&job_id)?)
+}
+
+/// Add an automation rule, or replace the rule with the same id
+#[tauri::command]
+async fn save_automation_rule(
+    rule: AutomationRule,
+    state: tauri::State<'_, Arc<Mutex<AutomationStore>>>,
+) -> Result<AutomationRule, String> {
+    Ok(state.lock().await.save(rule)?)
+}
+
+#[tauri::command]
+async fn list_automation_rules(
+    state: tauri::State<'_, Arc<Mutex<AutomationStore>>>,
+) -> Result<Vec<AutomationRule>, String> {
+    Ok(state.lock().await.list())
+}
+
+#[tauri::command]
+async fn delete_automation_rule(
+    id: String,
+    state: tauri::State<'_, Arc<Mutex<AutomationStore>>>,
+) -> Result<(), String> {
+    Ok(state.lock().await.delete(&id)?)
+}
+
+/// Attempt to reconnect a session with the given configuration
+/// Uses exponential backoff strategy
+#[tauri::command]
+async fn reconnect_session(
+    session_id: String,
+    mut config: SessionConfig,
+    policy: Option<ReconnectPolicy>,
+    session_state: tauri::State<'_, Arc<SessionManager>>,
+    reconnect_state: tauri::State<'_, Arc<ReconnectManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<String, String> {
+    let settings = settings.lock().await.get();
+    settings.apply(&mut config, &session_id);
+    let policy = policy.unwrap_or(settings.reconnect);
+    let manager = Arc::clone(&session_state);
+
+    info!(
+        session_id = %session_id,
+        host = %config.host,
+        port = config.port,
+        protocol = ?config.protocol,
+        "Starting reconnection"
+    );
+
+    let controller = ReconnectController::new(session_id.clone(), config, policy);
+
+    // Register cancel handle
+    reconnect_state.register(session_id.clone(), controller.get_cancel_handle());
+
+    // Run reconnection
+    let result = controller.run(manager).await;
+
+    // Clean up cancel handle
+    reconnect_state.remove(&session_id);
+
+    result
+}
+
+/// Start receiving syslog from devices (default UDP 514 on all addresses);
+/// messages arrive as `syslog` events
+#[tauri::command]
+async fn start_syslog(
+    options: Option<SyslogOptions>,
+    receiver: tauri::State<'_, Arc<SyslogReceiver>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    profiles: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+    tracer: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+) -> Result<SyslogStatus, String> {
+    let ctx = SyslogContext {
+        manager: Arc::clone(&state),
+        profiles: Arc::clone(&profiles),
+        tracer: Arc::clone(&tracer),
+    };
+    Ok(receiver.start(options.unwrap_or_default(), ctx).await?)
+}
+
+#[tauri::command]
+async fn stop_syslog(receiver: tauri::State<'_, Arc<SyslogReceiver>>) -> Result<(), String> {
+    Ok(receiver.stop()?)
+}
+
+#[tauri::command]
+async fn get_syslog_status(
+    receiver: tauri::State<'_, Arc<SyslogReceiver>>,
+) -> Result<SyslogStatus, String> {
+    Ok(receiver.status())
+}
+
+/// The newest received syslog messages (default 200, at most 1000)
+#[tauri::command]
+async fn list_syslog_messages(
+    limit: Option<usize>,
+    receiver: tauri::State<'_, Arc<SyslogReceiver>>,
+) -> Result<Vec<SyslogMessage>, String> {
+    Ok(receiver.recent(limit.unwrap_or(200)))
+}
+
+/// Start receiving SNMP traps and informs (default UDP 162 on all
+/// addresses); traps arrive as `snmp-trap` events
+#[tauri::command]
+async fn start_trap_receiver(
+    options: Option<TrapOptions>,
+    receiver: tauri::State<'_, Arc<TrapReceiver>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    profiles: tauri::State<'_, Arc<Mutex<ProfileStore>>>,
+) -> Result<TrapStatus, String> {
+    let ctx = TrapContext {
+        manager: Arc::clone(&state),
+        profiles: Arc::clone(&profiles),
+    };
+    Ok(receiver.start(options.unwrap_or_default(), ctx).await?)
+}
+
+#[tauri::command]
+async fn stop_trap_receiver(receiver: tauri::State<'_, Arc<TrapReceiver>>) -> Result<(), String> {
+    Ok(receiver.stop()?)
+}
+
+#[tauri::command]
+async fn get_trap_receiver_status(
+    receiver: tauri::State<'_, Arc<TrapReceiver>>,
+) -> Result<TrapStatus, String> {
+    Ok(receiver.status())
+}
+
+/// The newest received traps (default 200, at most 1000)
+#[tauri::command]
+async fn list_snmp_traps(
+    limit: Option<usize>,
+    receiver: tauri::State<'_, Arc<TrapReceiver>>,
+) -> Result<Vec<SnmpTrap>, String> {
+    Ok(receiver.recent(limit.unwrap_or(200)))
+}
+
+/// Start the TFTP server on `root` (default UDP 69 on all addresses);
+/// transfers arrive as `tftp` events
+#[tauri::command]
+async fn start_tftp_server(
+    options: TftpOptions,
+    service: tauri::State<'_, Arc<TftpService>>,
+    app_handle: tauri::AppHandle,
+) -> Result<TftpStatus, String> {
+    Ok(service.start(options, app_handle).await?)
+}
+
+#[tauri::command]
+async fn stop_tftp_server(service: tauri::State<'_, Arc<TftpService>>) -> Result<(), String> {
+    Ok(service.stop()?)
+}
+
+#[tauri::command]
+async fn get_tftp_status(
+    service: tauri::State<'_, Arc<TftpService>>,
+) -> Result<TftpStatus, String> {
+    Ok(service.status())
+}
+
+/// Recent TFTP transfers (at most 200), oldest first
+#[tauri::command]
+async fn list_tftp_transfers(
+    service: tauri::State<'_, Arc<TftpService>>,
+) -> Result<Vec<TftpTransfer>, String> {
+    Ok(service.transfers())
+}
+
+/// Start the FTP server for one user jailed to `root` (default TCP 21 on
+/// all addresses); transfers arrive as `ftp` events
+#[tauri::command]
+async fn start_ftp_server(
+    options: FtpOptions,
+    service: tauri::State<'_, Arc<FtpService>>,
+    app_handle: tauri::AppHandle,
+) -> Result<FtpStatus, String> {
+    Ok(service.start(options, app_handle).await?)
+}
+
+#[tauri::command]
+async fn stop_ftp_server(service: tauri::State<'_, Arc<FtpService>>) -> Result<(), String> {
+    Ok(service.stop()?)
+}
+
+#[tauri::command]
+async fn get_ftp_status(service: tauri::State<'_, Arc<FtpService>>) -> Result<FtpStatus, String> {
+    Ok(service.status())
+}
+
+/// Recent FTP transfers (at most 200), oldest first
+#[tauri::command]
+async fn list_ftp_transfers(
+    service: tauri::State<'_, Arc<FtpService>>,
+) -> Result<Vec<FtpTransfer>, String> {
+    Ok(service.transfers())
+}
+
+#[tauri::command]
+async fn list_alert_rules(
+    alerts: tauri::State<'_, Arc<AlertEngine>>,
+) -> Result<Vec<AlertRule>, String> {
+    Ok(alerts.rules())
+}
+
+/// Add an alert rule, or replace the rule with the same id
+#[tauri::command]
+async fn save_alert_rule(
+    rule: AlertRule,
+    alerts: tauri::State<'_, Arc<AlertEngine>>,
+) -> Result<AlertRule, String> {
+    Ok(alerts.save_rule(rule)?)
+}
+
+#[tauri::command]
+async fn delete_alert_rule(
+    id: String,
+    alerts: tauri::State<'_, Arc<AlertEngine>>,
+) -> Result<(), String> {
+    Ok(alerts.delete_rule(&id)?)
+}
+
+/// The newest alerts (default 200, at most 500), oldest first
+#[tauri::command]
+async fn list_alerts(
+    limit: Option<usize>,
+    alerts: tauri::State<'_, Arc<AlertEngine>>,
+) -> Result<Vec<Alert>, String> {
+    Ok(alerts.alerts(limit.unwrap_or(200)))
+}
+
+#[tauri::command]
+async fn clear_alerts(alerts: tauri::State<'_, Arc<AlertEngine>>) -> Result<(), String> {
+    alerts.clear();
+    Ok(())
+}
+
+/// Throughput, reconnect, backpressure, tracer and command latency counters
+#[tauri::command]
+async fn get_metrics(
+    state: tauri::State<'_, Arc<SessionManager>>,
+    tracer: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+) -> Result<MetricsSnapshot, String> {
+    Ok(metrics::collect(&state, &tracer).await)
+}
+
+/// Record a session's raw byte streams to `path` (replaced) until stopped
+/// or disconnected
+#[tauri::command]
+async fn start_capture(
+    session_id: String,
+    path: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<CaptureStatus, String> {
+    let handle = state
+        .get(&session_id)
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+    let header = CaptureHeader::new(&session_id, &handle.config);
+    Ok(handle.capture.start(Path::new(&path), header)?)
+}
+
+#[tauri::command]
+async fn stop_capture(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<CaptureStatus, String> {
+    let handle = state
+        .get(&session_id)
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+    Ok(handle.capture.stop()?)
+}
+
+#[tauri::command]
+async fn get_capture_status(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Option<CaptureStatus>, String> {
+    let handle = state
+        .get(&session_id)
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+    Ok(handle.capture.status())
+}
+
+/// Feed a capture file back through the telnet, charset and VRP parsers
+#[tauri::command]
+async fn replay_capture(path: String) -> Result<ReplayReport, String> {
+    tokio::task::spawn_blocking(move || {
+        let (header, records) = capture::load(Path::new(&path))?;
+        Ok(capture::replay(header, &records)?)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Save the files of the device's pending `sz` into `directory`
+#[tauri::command]
+async fn zmodem_receive(
+    session_id: String,
+    directory: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    Ok(state
+        .zmodem(&session_id, ZmodemCommand::Receive(directory.into()))
+        .await?)
+}
+
+/// Answer the device's pending `rz` with these files
+#[tauri::command]
+async fn zmodem_send(
+    session_id: String,
+    files: Vec<String>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    let files = files.into_iter().map(Into::into).collect();
+    Ok(state
+        .zmodem(&session_id, ZmodemCommand::Send(files))
+        .await?)
+}
+
+/// Refuse a pending offer or abort a running transfer
+#[tauri::command]
+async fn zmodem_cancel(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    Ok(state.zmodem(&session_id, ZmodemCommand::Cancel).await?)
+}
+
+/// Timeline entries of one or all devices, oldest first
+#[tauri::command]
+async fn query_timeline(
+    query: Option<TimelineQuery>,
+    timeline: tauri::State<'_, Arc<Timeline>>,
+) -> Result<Vec<TimelineEntry>, String> {
+    Ok(timeline.query(&query.unwrap_or_default()))
+}
+
+#[tauri::command]
+async fn list_timeline_devices(
+    timeline: tauri::State<'_, Arc<Timeline>>,
+) -> Result<Vec<String>, String> {
+    Ok(timeline.devices())
+}
+
+/// Mark the current end of a session's output on its device's timeline
+#[tauri::command]
+async fn add_timeline_marker(
+    session_id: String,
+    label: String,
+    timeline: tauri::State<'_, Arc<Timeline>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<TimelineEntry, String> {
+    timeline::mark(&timeline, &state, &session_id, label)
+        .await
+        .ok_or_else(|| format!("Session not found: {}", session_id))
+}
+
+/// Forget the timeline of one device, or of all devices
+#[tauri::command]
+async fn clear_timeline(
+    device: Option<String>,
+    timeline: tauri::State<'_, Arc<Timeline>>,
+) -> Result<(), String> {
+    timeline.clear(device.as_deref());
+    Ok(())
+}
+
+/// Write a session's transcript, boards, recent alarms and tracer hits to
+/// one file for handing to a colleague; credentials are left out
+#[tauri::command]
+async fn export_session_bundle(
+    session_id: String,
+    path: String,
+    timeline: tauri::State<'_, Arc<Timeline>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<BundleSummary, String> {
+    Ok(bundle::export(&state, &timeline, &session_id, Path::new(&path)).await?)
+}
+
+/// Counters of the remote event forwarder (`forwarding` in the settings)
+#[tauri::command]
+async fn get_forwarder_status(
+    state: tauri::State<'_, Arc<Forwarder>>,
+) -> Result<ForwarderStatus, String> {
+    Ok(state.status())
+}
+
+/// Rolling round-trip stats of a session (also sent as `session:{id}:latency`)
+#[tauri::command]
+async fn get_session_latency(
+    session_id: String,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<LatencyStats, String> {
+    state
+        .get(&session_id)
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+    Ok(state.latency().stats(&session_id))
+}
+
+/// Send a CR and time its echo, waiting up to `timeout_ms` (default 10s)
+/// for the prompt
+#[tauri::command]
+async fn probe_latency(
+    session_id: String,
+    timeout_ms: Option<u64>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<LatencyStats, String> {
+    let timeout = Duration::from_millis(timeout_ms.unwrap_or(10_000));
+    Ok(latency::probe(&state, &session_id, timeout).await?)
+}
+
+/// Serve the metrics as Prometheus text on 127.0.0.1 (default port 9464)
+#[tauri::command]
+async fn start_metrics_exporter(
+    port: Option<u16>,
+    exporter: tauri::State<'_, Arc<MetricsExporter>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    tracer: tauri::State<'_, Arc<Mutex<LogTracer>>>,
+) -> Result<ExporterStatus, String> {
+    let ctx = ExporterContext {
+        manager: Arc::clone(&state),
+        tracer: Arc::clone(&tracer),
+    };
+    Ok(exporter
+        .start(port.unwrap_or(metrics::DEFAULT_PORT), ctx)
+        .await?)
+}
+
+#[tauri::command]
+async fn stop_metrics_exporter(
+    exporter: tauri::State<'_, Arc<MetricsExporter>>,
+) -> Result<(), String> {
+    Ok(exporter.stop()?)
+}
+
+#[tauri::command]
+async fn get_metrics_exporter_status(
+    exporter: tauri::State<'_, Arc<MetricsExporter>>,
+) -> Result<ExporterStatus, String> {
+    Ok(exporter.status())
+}
+
+/// Serve the session commands over HTTP on 127.0.0.1 (default port 9470)
+/// for external test tools; all requests need the returned token
+#[tauri::command]
+async fn start_control_api(
+    port: Option<u16>,
+    token: Option<String>,
+    server: tauri::State<'_, Arc<ControlServer>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<ControlStatus, String> {
+    let ctx = ControlContext {
+        manager: Arc::clone(&state),
+        settings: Arc::clone(&settings),
+    };
+    Ok(server
+        .start(port.unwrap_or(control::DEFAULT_PORT), token, ctx)
+        .await?)
+}
+
+#[tauri::command]
+async fn stop_control_api(server: tauri::State<'_, Arc<ControlServer>>) -> Result<(), String> {
+    Ok(server.stop()?)
+}
+
+#[tauri::command]
+async fn get_control_api_status(
+    server: tauri::State<'_, Arc<ControlServer>>,
+) -> Result<ControlStatus, String> {
+    Ok(server.status())
+}
+
+/// Open a NETCONF session (SSH `netconf` subsystem, default port 830) and
+/// exchange hellos
+#[tauri::command]
+async fn netconf_connect(
+    config: NetconfConfig,
+    netconf: tauri::State<'_, Arc<NetconfManager>>,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<NetconfSessionInfo, String> {
+    Ok(netconf.connect(&config, state.known_hosts()).await?)
+}
+
+/// Send an operation (the content of `<rpc>`) and return the raw reply
+#[tauri::command]
+async fn netconf_rpc(
+    id: String,
+    xml: String,
+    netconf: tauri::State<'_, Arc<NetconfManager>>,
+) -> Result<String, String> {
+    Ok(netconf.get(&id)?.lock().await.rpc(&xml).await?)
+}
+
+/// Inner XML of `<data>` from `<get>`, with an optional subtree filter
+#[tauri::command]
+async fn netconf_get(
+    id: String,
+    filter: Option<String>,
+    netconf: tauri::State<'_, Arc<NetconfManager>>,
+) -> Result<String, String> {
+    let client = netconf.get(&id)?;
+    let data = client.lock().await.get(filter.as_deref()).await?;
+    Ok(data)
+}
+
+/// Inner XML of `<data>` from `<get-config>` (default source: running)
+#[tauri::command]
+async fn netconf_get_config(
+    id: String,
+    source: Option<Datastore>,
+    filter: Option<String>,
+    netconf: tauri::State<'_, Arc<NetconfManager>>,
+) -> Result<String, String> {
+    let client = netconf.get(&id)?;
+    let data = client
+        .lock()
+        .await
+        .get_config(source.unwrap_or(Datastore::Running), filter.as_deref())
+        .await?;
+    Ok(data)
+}
+
+/// `<edit-config>` with `config` as the content of `<config>`; the target
+/// defaults to candidate when the device has one, else running
+#[tauri::command]
+async fn netconf_edit_config(
+    id: String,
+    config: String,
+    target: Option<Datastore>,
+    default_operation: Option<DefaultOperation>,
+    netconf: tauri::State<'_, Arc<NetconfManager>>,
+) -> Result<(), String> {
+    let client = netconf.get(&id)?;
+    let edited = client
+        .lock()
+        .await
+        .edit_config(target, &config, default_operation)
+        .await;
+    Ok(edited?)
+}
+
+#[tauri::command]
+async fn netconf_commit(
+    id: String,
+    netconf: tauri::State<'_, Arc<NetconfManager>>,
+) -> Result<(), String> {
+    let client = netconf.get(&id)?;
+    let committed = client.lock().await.commit().await;
+    Ok(committed?)
+}
+
+/// Send `<close-session>` and disconnect
+#[tauri::command]
+async fn netconf_close(
+    id: String,
+    netconf: tauri::State<'_, Arc<NetconfManager>>,
+) -> Result<(), String> {
+    Ok(netconf.close(&id).await?)
+}
+
+#[tauri::command]
+async fn list_netconf_sessions(
+    netconf: tauri::State<'_, Arc<NetconfManager>>,
+) -> Result<Vec<NetconfSessionInfo>, String> {
+    Ok(netconf.list().await)
+}
+
+/// Plugins found in `<app data>/plugins/` at startup or the last reload
+#[tauri::command]
+async fn list_plugins(
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<PluginInfo>, String> {
+    Ok(state.plugins().list())
+}
+
+/// Scan the plugins directory again. Running sessions keep the plugins they
+/// started with
+#[tauri::command]
+async fn reload_plugins(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<Vec<PluginInfo>, String> {
+    let dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("plugins");
+    let manager = Arc::clone(&state);
+    tokio::task::spawn_blocking(move || manager.plugins().load_dir(&dir))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn open_archive(
+    settings: &Mutex<SettingsStore>,
+) -> Result<DeviceArchive, DeviceArchiveError> {
+    DeviceArchive::from_settings(&settings.lock().await.get().archive)
+        .ok_or(DeviceArchiveError::Disabled)
+}
+
+/// Files archived for a device (session logs, configs, alarms and
+/// transcripts), newest first
+#[tauri::command]
+async fn get_device_archive(
+    host: String,
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<DeviceListing, String> {
+    let archive = open_archive(&settings).await?;
+    tokio::task::spawn_blocking(move || archive.list(&host).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn list_archive_devices(
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<Vec<DeviceSummary>, String> {
+    let archive = open_archive(&settings).await?;
+    tokio::task::spawn_blocking(move || archive.devices().map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Apply the archive's age and size limits now rather than on the hour
+#[tauri::command]
+async fn prune_device_archive(
+    settings: tauri::State<'_, Arc<Mutex<SettingsStore>>>,
+) -> Result<PruneReport, String> {
+    let archive = open_archive(&settings).await?;
+    tokio::task::spawn_blocking(move || {
+        archive
+            .enforce(std::time::SystemTime::now())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Recent backend log entries (newest `limit`, default 500, oldest first);
+/// new ones arrive as `app:log` events
+#[tauri::command]
+async fn get_app_logs(
+    filter: Option<LogFilter>,
+    limit: Option<usize>,
+    app_log: tauri::State<'_, Arc<AppLog>>,
+) -> Result<Vec<LogEntry>, String> {
+    Ok(app_log.query(&filter.unwrap_or_default(), limit))
+}
+
+/// Cancel an ongoing reconnection attempt
+#[tauri::command]
+async fn cancel_reconnect(
+    session_id: String,
+    reconnect_state: tauri::State<'_, Arc<ReconnectManager>>,
+) -> Result<bool, String> {
+    info!(session_id = %session_id, "Cancelling reconnection");
+    Ok(reconnect_state.cancel(&session_id).await)
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    // Console output plus the in-app debug console
+    let app_log = applog::init();
+
+    // The OS passes a clicked link as an argument, or on macOS as an event
+    let mut launch_links = deeplink::from_args(std::env::args().skip(1));
+    builder(app_log)
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(move |app_handle, event| {
+            let urls = match event {
+                tauri::RunEvent::Ready => std::mem::take(&mut launch_links),
+                #[cfg(any(target_os = "macos", target_os = "ios"))]
+                tauri::RunEvent::Opened { urls } => urls.iter().map(|u| u.to_string()).collect(),
+                _ => return,
+            };
+            for url in urls {
+                spawn_deep_link(app_handle.clone(), url);
+            }
+        });
+}
+
+/// Entry point of `bspt-cli`: the same backend with no window, running one
+/// command line and exiting with its status (see cli.rs)
+pub fn run_cli() {
+    let invocation = match cli::parse(std::env::args().skip(1)) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("bspt-cli: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let app_log = applog::init_cli();
+
+    let mut context = tauri::generate_context!();
+    context.config_mut().app.windows.clear();
+    let app = builder(app_log)
+        .build(context)
+        .expect("error while building tauri application");
+    let mut invocation = Some(invocation);
+    app.run(move |app_handle, event| {
+        if !matches!(event, tauri::RunEvent::Ready) {
+            return;
+        }
+        let Some(invocation) = invocation.take() else {
+            return;
+        };
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            let code = cli::execute(&app_handle, invocation).await;
+            app_handle.exit(code);
+        });
+    });
+}
+
+/// The app with its state, background tasks and commands; windows come from
+/// the context it is run with
+fn builder(app_log: Arc<AppLog>) -> tauri::Builder {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::Destroyed = event {
+                let Some(manager) = window.try_state::<Arc<SessionManager>>() else {
+                    return;
+                };
+                let manager = Arc::clone(&manager);
+                let label = window.label().to_string();
+                tauri::async_runtime::spawn(async move {
+                    views::window_closed(&manager, &label).await;
+                });
+            }
+        })
+        .setup(move |app| {
+            // Rotating log file and the live `app:log` stream
+            let log_dir = app.path().app_log_dir()?;
+            if let Err(e) = app_log.open_file(&log_dir) {
+                warn!(dir = %log_dir.display(), error = %e, "Failed to open the app log file");
+            }
+            applog::spawn_emitter(app.handle().clone(), &app_log);
+            app.manage(Arc::clone(&app_log));
+
+            let history_path = app.path().app_data_dir()?.join("history.json");
+            let known_hosts_path = app.path().app_data_dir()?.join("known_hosts");
+            let pins_path = app.path().app_data_dir()?.join("host_pins.json");
+            let session_manager = SessionManager::new(app.handle().clone())
+                .with_history(ConnectionHistory::load(history_path))
+                .with_known_hosts(
+                    KnownHosts::load(known_hosts_path).with_pins(HostPins::load(pins_path)),
+                );
+            session_manager
+                .plugins()
+                .load_dir(&app.path().app_data_dir()?.join("plugins"));
+            let session_manager = Arc::new(session_manager);
+            app.manage(Arc::clone(&session_manager));
+
+            // Syslog and SNMP traps from devices, started from the frontend
+            let syslog = Arc::new(SyslogReceiver::new());
+            app.manage(Arc::clone(&syslog));
+            let traps = Arc::new(TrapReceiver::new());
+            app.manage(Arc::clone(&traps));
+
+            // Alert rules over VRP events, syslog, traps and automation
+            let alerts_path = app.path().app_data_dir()?.join("alerts.json");
+            let alerts = Arc::new(AlertEngine::new(
+                app.handle().clone(),
+                AlertStore::load(alerts_path),
+            ));
+            alerts::spawn_feeds(&alerts, &session_manager, &syslog, &traps);
+            app.manage(Arc::clone(&alerts));
+
+            // Per-device timeline of VRP events, syslog, traps and markers
+            let timeline = Arc::new(Timeline::default());
+            timeline::spawn_feeds(&timeline, &session_manager, &syslog, &traps);
+            app.manage(Arc::clone(&timeline));
+
+            // TFTP and FTP servers for device transfers, started from the frontend
+            app.manage(Arc::new(TftpService::new()));
+            app.manage(Arc::new(FtpService::new()));
+
+            // Credential prompts for profiles that store no password
+            app.manage(Arc::new(CredentialBroker::new()));
+            // Links opened from tickets and wikis, some awaiting confirmation
+            app.manage(Arc::new(DeepLinks::default()));
+            app.manage(Arc::new(ScriptManager::new()));
+
+            // Scripts, batch jobs, backups and upgrades, with their history
+            let jobs_path = app.path().app_data_dir()?.join("jobs.json");
+            let jobs = Arc::new(JobRegistry::load(jobs_path));
+            jobs::spawn_emitter(app.handle().clone(), &jobs);
+            app.manage(Arc::clone(&jobs));
+            let macros_path = app.path().app_data_dir()?.join("macros.json");
+            app.manage(Arc::new(Mutex::new(MacroStore::load(macros_path))));
+
+            // Rules reacting to the VRP events of every session
+            let automation_path = app.path().app_data_dir()?.join("automation.json");
+            let automation = Arc::new(Mutex::new(AutomationStore::load(automation_path)));
+            let automation_events = automation::spawn_engine(
+                Arc::clone(&session_manager),
+                Arc::clone(&automation),
+                Arc::clone(&alerts),
+            );
+            app.manage(automation);
+
+            // Initialize ReconnectManager for managing reconnection attempts
+            let reconnect_manager = ReconnectManager::new();
+            app.manage(Arc::new(reconnect_manager));
+
+            // Global settings, watched for outside edits
+            let settings_path = app.path().app_config_dir()?.join("settings.json");
+            let settings_store = SettingsStore::load(settings_path);
+
+            // Initialize LogTracer for log-to-source mapping
+            let mut log_tracer = LogTracer::new();
+            log_tracer.set_limits(settings_store.get().tracer);
+            let log_tracer = Arc::new(Mutex::new(log_tracer));
+            app.manage(Arc::clone(&log_tracer));
+            // Output lines of sessions with the tracer stage enabled
+            pipeline::spawn_tracer(&timeline, &session_manager, &log_tracer);
+            app.manage(Arc::new(MetricsExporter::new()));
+
+            // NETCONF sessions, kept apart from the terminal sessions
+            app.manage(Arc::new(NetconfManager::default()));
+
+            let control_path = app.path().app_data_dir()?.join("control.json");
+            app.manage(Arc::new(ControlServer::new(control_path)));
+
+            // Credential vault, locked until the frontend unlocks it
+            let vault_path = app.path().app_data_dir()?.join("vault.json");
+            let auto_lock = Duration::from_secs(settings_store.get().vault.auto_lock_secs);
+            let vault = Arc::new(Mutex::new(Vault::new(vault_path, auto_lock)));
+            vault::spawn_auto_lock(app.handle().clone(), Arc::clone(&vault));
+            app.manage(Arc::clone(&vault));
+
+            let settings_store = Arc::new(Mutex::new(settings_store));
+            settings::spawn_watcher(
+                app.handle().clone(),
+                Arc::clone(&settings_store),
+                log_tracer,
+                Arc::clone(&vault),
+            );
+            app.manage(Arc::clone(&settings_store));
+
+            // Per-device archive, pruned hourly while `archive.dir` is set
+            device_archive::spawn_feeds(&settings_store, &session_manager, &syslog, &traps);
+            device_archive::spawn_retention(Arc::clone(&settings_store));
+
+            // Session audit lines, alerts and rule hits to a remote collector
+            let forwarder = Arc::new(Forwarder::new());
+            forward::spawn_feeds(
+                &forwarder,
+                &settings_store,
+                &session_manager,
+                &alerts,
+                &automation_events,
+            );
+            app.manage(forwarder);
+
+            // Connection profiles persisted in the app data directory
+            let profiles_path = app.path().app_data_dir()?.join("profiles.json");
+            let profiles = Arc::new(Mutex::new(ProfileStore::load(profiles_path)));
+            app.manage(Arc::clone(&profiles));
+
+            // Versioned config backups, taken on the settings' schedule
+            let backups_dir = app.path().app_data_dir()?.join("backups");
+            let backups = Arc::new(Mutex::new(BackupStore::new(backups_dir)));
+            app.manage(Arc::clone(&backups));
+            let guards_dir = app.path().app_data_dir()?.join("guards");
+            app.manage(Arc::new(Mutex::new(GuardStore::new(guards_dir))));
+            let app_handle = app.handle().clone();
+            backup::spawn_scheduler(Arc::clone(&settings_store), move |config| {
+                let app_handle = app_handle.clone();
+                let profiles = Arc::clone(&profiles);
+                let manager = Arc::clone(&session_manager);
+                let settings = Arc::clone(&settings_store);
+                let vault = Arc::clone(&vault);
+                let backups = Arc::clone(&backups);
+                let jobs = Arc::clone(&jobs);
+                async move {
+                    let settings = settings.lock().await.get();
+                    let results = backup_profiles(
+                        &config.profile_ids,
+                        &app_handle,
+                        &profiles,
+                        &manager,
+                        settings,
+                        &vault,
+                        None,
+                        &backups,
+                        &jobs,
+                    )
+                    .await;
+                    backup::log_failures(&results);
+                }
+            });
+
+            #[cfg(target_os = "windows")]
+            {
+                use window_vibrancy::apply_acrylic;
+                // bspt-cli runs without a window
+                if let Some(window) = app.get_webview_window("main") {
+                    apply_acrylic(&window, Some((18, 18, 18, 125))).expect(
+                        "Unsupported platform! 'apply_acrylic' is only supported on Windows",
+                    );
+                }
+            }
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             create_session,
+            open_ssh_channel,
+            list_shared_sessions,
+            open_deep_link,
+            list_deep_links,
+            confirm_deep_link,
+            dismiss_deep_link,
+            register_url_handlers,
             send_input,
             disconnect_session,
             resize_terminal,
+            attach_view,
+            detach_view,
+            list_views,
+            open_local_forward,
+            open_remote_forward,
+            open_dynamic_forward,
+            list_forwards,
+            close_forward,
+            sftp_list,
+            sftp_download,
+            sftp_upload,
+            sftp_delete,
             scan_boards,
             set_auto_pagination,
+            send_break,
             notify_buffer_drained,
+            ack_output,
+            set_buffer_config,
+            get_buffer_stats,
+            fetch_scrollback,
+            add_marker,
+            list_markers,
+            get_pipeline,
+            set_pipeline_stage,
+            search_output,
+            run_benchmark,
+            get_memory_stats,
+            set_memory_cap,
             index_source_directory,
             match_log_line,
+            resolve_symbol,
+            resolve_backtrace,
+            get_blame,
+            set_tracer_limits,
             get_tracer_stats,
+            save_profile,
+            list_profiles,
+            search_profiles,
+            delete_profile,
+            connect_profile,
+            run_batch,
+            dry_run_batch,
+            precheck_targets,
+            scan_subnet,
+            backup_configs,
+            list_config_backups,
+            get_config_backup,
+            diff_config_backups,
+            load_compliance_rules,
+            check_compliance,
+            check_compliance_live,
+            provide_credentials,
+            cancel_credentials,
+            answer_host_key,
+            repin_host_key,
+            list_host_pins,
+            answer_passphrase,
+            import_securecrt,
+            import_ssh_config,
+            resolve_ssh_host,
+            export_profiles,
+            import_profiles,
+            get_settings,
+            get_recent_connections,
+            connect_recent,
+            set_settings,
+            vault_status,
+            create_vault,
+            unlock_vault,
+            lock_vault,
+            change_vault_password,
+            set_vault_password,
+            delete_vault_password,
+            run_command,
+            exec_command,
+            run_command_multi,
+            run_command_parsed,
+            capture_change_snapshot,
+            list_change_snapshots,
+            delete_change_snapshot,
+            verify_change,
+            list_variables,
+            set_variable,
+            delete_variable,
+            load_script,
+            list_scripts,
+            run_script,
+            dry_run_script,
+            stop_script,
+            start_macro_record,
+            stop_macro_record,
+            list_macros,
+            delete_macro,
+            play_macro,
+            dry_run_macro,
+            start_upgrade,
+            rotate_passwords,
+            collect_topology,
+            audit_clocks,
+            watch_command,
+            list_jobs,
+            pause_job,
+            resume_job,
+            cancel_job,
+            save_automation_rule,
+            list_automation_rules,
+            delete_automation_rule,
             reconnect_session,
-            cancel_reconnect
-        ]);
-
-    builder
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+            cancel_reconnect,
+            get_app_logs,
+            start_syslog,
+            stop_syslog,
+            get_syslog_status,
+            list_syslog_messages,
+            start_trap_receiver,
+            stop_trap_receiver,
+            get_trap_receiver_status,
+            list_snmp_traps,
+            start_tftp_server,
+            stop_tftp_server,
+            get_tftp_status,
+            list_tftp_transfers,
+            start_ftp_server,
+            stop_ftp_server,
+            get_ftp_status,
+            list_ftp_transfers,
+            list_alert_rules,
+            save_alert_rule,
+            delete_alert_rule,
+            list_alerts,
+            clear_alerts,
+            get_metrics,
+            start_metrics_exporter,
+            stop_metrics_exporter,
+            get_metrics_exporter_status,
+            get_session_latency,
+            probe_latency,
+            query_timeline,
+            list_timeline_devices,
+            add_timeline_marker,
+            clear_timeline,
+            export_session_bundle,
+            get_forwarder_status,
+            start_capture,
+            stop_capture,
+            get_capture_status,
+            replay_capture,
+            zmodem_receive,
+            zmodem_send,
+            zmodem_cancel,
+            get_device_archive,
+            list_archive_devices,
+            prune_device_archive,
+            start_control_api,
+            stop_control_api,
+            get_control_api_status,
+            list_plugins,
+            reload_plugins,
+            netconf_connect,
+            netconf_rpc,
+            netconf_get,
+            netconf_get_config,
+            netconf_edit_config,
+            netconf_commit,
+            netconf_close,
+            list_netconf_sessions
+        ])
 }
@@ -0,0 +1,117 @@
+use crate::charset::utf8_incomplete_tail;
+use regex::Regex;
+
+/// A partial line longer than this is handed out as a line anyway, so
+/// output without newlines can't grow the buffer without bound
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Turns arbitrarily split output chunks into whole lines for backend
+/// consumers (VRP prompt/board detection, triggers).
+///
+/// The terminal still receives raw chunks; this stage only decides what a
+/// "line" is, once per session, so consumers don't keep their own buffers.
+pub struct LineAssembler {
+    /// Output after the last newline, e.g. a prompt waiting for input
+    partial: String,
+    /// Trailing bytes of a UTF-8 character split across reads
+    carry: Vec<u8>,
+}
+
+impl Default for LineAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineAssembler {
+    pub fn new() -> Self {
+        Self {
+            partial: String::new(),
+            carry: Vec::new(),
+        }
+    }
+
+    /// Append UTF-8 output and return the lines it completed, without
+    /// their `\r\n`/`\n` terminators
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        self.carry.extend_from_slice(data);
+        let split = self.carry.len() - utf8_incomplete_tail(&self.carry);
+        let rest = self.carry.split_off(split);
+        self.partial.push_str(&String::from_utf8_lossy(&self.carry));
+        self.carry = rest;
+
+        let mut lines = Vec::new();
+        while let Some(newline) = self.partial.find('\n') {
+            let mut line: String = self.partial.drain(..=newline).collect();
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+            lines.push(line);
+        }
+
+        if self.partial.len() > MAX_LINE_LEN {
+            lines.push(std::mem::take(&mut self.partial));
+        }
+        lines
+    }
+
+    /// Output after the last complete line
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+
+    /// Remove matches of `re` from the partial line so later reads don't
+    /// see them again. Returns whether anything matched.
+    pub fn strip_partial(&mut self, re: &Regex) -> bool {
+        if !re.is_match(&self.partial) {
+            return false;
+        }
+        self.partial = re.replace_all(&self.partial, "").into_owned();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_split_across_reads() {
+        let mut lines = LineAssembler::new();
+        assert!(lines.push(b"display ver").is_empty());
+        assert_eq!(
+            lines.push(b"sion\r\nVRP (R) software\r\n<Hu"),
+            ["display version", "VRP (R) software"]
+        );
+        assert_eq!(lines.partial(), "<Hu");
+        assert!(lines.push(b"awei>").is_empty());
+        assert_eq!(lines.partial(), "<Huawei>");
+    }
+
+    #[test]
+    fn test_utf8_split_across_reads() {
+        let text = "接口\n".as_bytes();
+        let mut lines = LineAssembler::new();
+        assert!(lines.push(&text[..2]).is_empty());
+        assert_eq!(lines.push(&text[2..]), ["接口"]);
+    }
+
+    #[test]
+    fn test_long_partial_is_flushed() {
+        let mut lines = LineAssembler::new();
+        let long = vec![b'x'; MAX_LINE_LEN + 1];
+        assert_eq!(lines.push(&long).len(), 1);
+        assert_eq!(lines.partial(), "");
+    }
+
+    #[test]
+    fn test_strip_partial() {
+        let re = Regex::new(r"----\s*More\s*----").unwrap();
+        let mut lines = LineAssembler::new();
+        lines.push(b"line\r\n  ---- More ----");
+        assert!(lines.strip_partial(&re));
+        assert_eq!(lines.partial(), "  ");
+        assert!(!lines.strip_partial(&re));
+    }
+}
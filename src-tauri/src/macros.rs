@@ -0,0 +1,316 @@
+//! Keystroke macros (`macros.json` in the app data dir).
+//!
+//! While recording, everything `send_input` sends to the recorded session is
+//! kept with the delay since the previous input. Playback sends the steps
+//! again, either with the recorded timing or waiting for the device prompt
+//! after every line.
+
+use crate::command::{OutputWatcher, DEFAULT_COMMAND_TIMEOUT, PROMPT_RE};
+use crate::dryrun::DryRunReport;
+use crate::session::{SessionError, SessionManager};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Longest pause kept between two recorded inputs
+const MAX_STEP_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+pub enum MacroError {
+    #[error("Already recording session {0}")]
+    AlreadyRecording(String),
+    #[error("Not recording")]
+    NotRecording,
+    #[error("Macro not found: {0}")]
+    NotFound(String),
+    #[error("Invalid macro: {0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Session(#[from] SessionError),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<MacroError> for String {
+    fn from(err: MacroError) -> String {
+        err.to_string()
+    }
+}
+
+/// One recorded input
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MacroStep {
+    /// Time since the previous step (or the start of the recording)
+    pub delay_ms: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+    /// Unix seconds
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct PlaybackOptions {
+    /// After each step ending a line, wait for the prompt instead of
+    /// replaying the recorded delay
+    pub wait_for_prompt: bool,
+    /// Per prompt wait
+    pub prompt_timeout_ms: u64,
+    /// Recorded delays are divided by this; ignored when waiting for prompts
+    pub speed: f64,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self {
+            wait_for_prompt: true,
+            prompt_timeout_ms: DEFAULT_COMMAND_TIMEOUT.as_millis() as u64,
+            speed: 1.0,
+        }
+    }
+}
+
+struct Recording {
+    session_id: String,
+    last_input: Instant,
+    steps: Vec<MacroStep>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoreFile {
+    macros: Vec<Macro>,
+}
+
+/// Saved macros plus the recording in progress, if any
+pub struct MacroStore {
+    path: PathBuf,
+    macros: Vec<Macro>,
+    recording: Option<Recording>,
+}
+
+impl MacroStore {
+    /// A missing or unreadable file is an empty store
+    pub fn load(path: PathBuf) -> Self {
+        let macros = match std::fs::read(&path) {
+            Ok(data) => match serde_json::from_slice::<StoreFile>(&data) {
+                Ok(file) => file.macros,
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Corrupt macro store, starting empty");
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Failed to read macro store");
+                Vec::new()
+            }
+        };
+        Self {
+            path,
+            macros,
+            recording: None,
+        }
+    }
+
+    pub fn start_recording(&mut self, session_id: &str) -> Result<(), MacroError> {
+        if let Some(recording) = &self.recording {
+            return Err(MacroError::AlreadyRecording(recording.session_id.clone()));
+        }
+        info!(session_id = %session_id, "Recording macro");
+        self.recording = Some(Recording {
+            session_id: session_id.to_string(),
+            last_input: Instant::now(),
+            steps: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Input sent to a session; kept if that session is being recorded
+    pub fn record(&mut self, session_id: &str, data: &[u8]) {
+        let Some(recording) = self.recording.as_mut() else {
+            return;
+        };
+        if recording.session_id != session_id || data.is_empty() {
+            return;
+        }
+        let delay = recording.last_input.elapsed().min(MAX_STEP_DELAY);
+        recording.last_input = Instant::now();
+        recording.steps.push(MacroStep {
+            delay_ms: delay.as_millis() as u64,
+            data: data.to_vec(),
+        });
+    }
+
+    /// End the recording and save it as `name`, replacing a macro of the
+    /// same name
+    pub fn stop_recording(&mut self, name: &str) -> Result<Macro, MacroError> {
+        if name.trim().is_empty() {
+            return Err(MacroError::Invalid("name must not be empty".to_string()));
+        }
+        let recording = self.recording.take().ok_or(MacroError::NotRecording)?;
+        let recorded = Macro {
+            name: name.trim().to_string(),
+            steps: recording.steps,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        self.macros.retain(|m| m.name != recorded.name);
+        self.macros.push(recorded.clone());
+        self.persist()?;
+        info!(name = %recorded.name, steps = recorded.steps.len(), "Saved macro");
+        Ok(recorded)
+    }
+
+    pub fn list(&self) -> Vec<Macro> {
+        let mut macros = self.macros.clone();
+        macros.sort_by_key(|m| m.name.to_lowercase());
+        macros
+    }
+
+    pub fn get(&self, name: &str) -> Result<Macro, MacroError> {
+        self.macros
+            .iter()
+            .find(|m| m.name == name)
+            .cloned()
+            .ok_or_else(|| MacroError::NotFound(name.to_string()))
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), MacroError> {
+        let before = self.macros.len();
+        self.macros.retain(|m| m.name != name);
+        if self.macros.len() == before {
+            return Err(MacroError::NotFound(name.to_string()));
+        }
+        self.persist()
+    }
+
+    fn persist(&self) -> Result<(), MacroError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = StoreFile {
+            macros: self.macros.clone(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&file)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Send the steps of `recorded` to a session
+pub async fn play(
+    manager: &SessionManager,
+    session_id: &str,
+    recorded: &Macro,
+    options: PlaybackOptions,
+) -> Result<(), MacroError> {
+    check_options(&options)?;
+    info!(session_id = %session_id, name = %recorded.name, "Playing macro");
+    let prompt_timeout = Duration::from_millis(options.prompt_timeout_ms);
+    let mut watcher = OutputWatcher::new(manager, session_id)?;
+    for step in &recorded.steps {
+        if !options.wait_for_prompt {
+            let delay = Duration::from_millis(step.delay_ms).div_f64(options.speed);
+            tokio::time::sleep(delay).await;
+        }
+        manager.send_data(session_id, step.data.clone()).await?;
+        if options.wait_for_prompt && ends_line(&step.data) {
+            watcher.expect(&PROMPT_RE, prompt_timeout).await?;
+        }
+    }
+    Ok(())
+}
+
+/// What `play` would send to the session, without sending it
+pub fn dry_run(
+    session_id: &str,
+    host: &str,
+    recorded: &Macro,
+    options: PlaybackOptions,
+) -> Result<DryRunReport, MacroError> {
+    check_options(&options)?;
+    let mut report = DryRunReport::default();
+    if recorded.steps.is_empty() {
+        report.issue(Some(session_id), None, "the macro has no steps".to_string());
+    }
+    for (index, step) in recorded.steps.iter().enumerate() {
+        let data = String::from_utf8_lossy(&step.data).into_owned();
+        report.send(session_id, host, index + 1, data);
+        if options.wait_for_prompt {
+            if ends_line(&step.data) {
+                report.wait(format!("prompt ({} ms)", options.prompt_timeout_ms));
+            }
+        } else if let Some(next) = recorded.steps.get(index + 1) {
+            let delay = Duration::from_millis(next.delay_ms).div_f64(options.speed);
+            report.wait(format!("{} ms", delay.as_millis()));
+        }
+    }
+    Ok(report)
+}
+
+fn check_options(options: &PlaybackOptions) -> Result<(), MacroError> {
+    if options.speed.is_nan() || options.speed <= 0.0 {
+        return Err(MacroError::Invalid("speed must be positive".to_string()));
+    }
+    Ok(())
+}
+
+fn ends_line(data: &[u8]) -> bool {
+    matches!(data.last(), Some(b'\r' | b'\n'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_store() {
+        let dir = std::env::temp_dir().join(format!("bspt-macros-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("macros.json");
+        let mut store = MacroStore::load(path.clone());
+
+        assert!(matches!(
+            store.stop_recording("none"),
+            Err(MacroError::NotRecording)
+        ));
+        store.start_recording("s1").unwrap();
+        assert!(matches!(
+            store.start_recording("s2"),
+            Err(MacroError::AlreadyRecording(_))
+        ));
+        store.record("s1", b"display version\r");
+        store.record("s2", b"not recorded\r");
+        store.record("s1", b"");
+        store.record("s1", b"quit\r");
+
+        let recorded = store.stop_recording("version").unwrap();
+        assert_eq!(recorded.steps.len(), 2);
+        assert_eq!(recorded.steps[1].data, b"quit\r");
+        assert!(store.recording.is_none());
+
+        // Recording a name again replaces the macro
+        store.start_recording("s1").unwrap();
+        store.record("s1", b"display clock\r");
+        store.stop_recording("version").unwrap();
+
+        let store = MacroStore::load(path);
+        assert_eq!(store.list().len(), 1);
+        assert_eq!(
+            store.get("version").unwrap().steps[0].data,
+            b"display clock\r"
+        );
+        assert!(matches!(store.get("other"), Err(MacroError::NotFound(_))));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,659 @@
+//! Counters for watching long tests: session throughput, reconnects,
+//! backpressure pauses, tracer hits and command latencies.
+//!
+//! The transports and helpers count into the `Metrics` held by the
+//! `SessionManager`; buffer and tracer counters are read from their owners
+//! when a snapshot is collected. `get_metrics` returns the snapshot and the
+//! optional `MetricsExporter` serves it as Prometheus text on a localhost
+//! port, so a scraper can follow a soak test without the UI.
+
+use crate::session::SessionManager;
+use crate::tracer::LogTracer;
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+/// Port of the Prometheus exporter unless another is given
+pub const DEFAULT_PORT: u16 = 9464;
+
+/// Upper bounds of the command latency buckets, in ms
+pub const LATENCY_BUCKETS_MS: [u64; 9] = [50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+
+/// Rates are recomputed at most this often, however often snapshots are taken
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest scrape request head accepted
+const MAX_REQUEST: usize = 8 * 1024;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum MetricsError {
+    #[error("Metrics exporter already running")]
+    AlreadyRunning,
+    #[error("Metrics exporter not running")]
+    NotRunning,
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl From<MetricsError> for String {
+    fn from(err: MetricsError) -> String {
+        err.to_string()
+    }
+}
+
+/// Throughput and buffer counters of one session
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionMetrics {
+    pub session_id: String,
+    pub host: String,
+    /// Bytes received from the device
+    pub bytes_in: u64,
+    /// Bytes sent to the device
+    pub bytes_out: u64,
+    pub bytes_in_per_sec: f64,
+    pub bytes_out_per_sec: f64,
+    pub buffer_fill_percent: usize,
+    pub paused: bool,
+    /// Times reads were paused for backpressure
+    pub pause_count: u64,
+    pub bytes_dropped: u64,
+    /// Rolling average round trip, see latency.rs
+    pub rtt_avg_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReconnectMetrics {
+    pub attempts: u64,
+    pub successes: u64,
+    /// Reconnections that gave up after all attempts
+    pub failures: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TracerMetrics {
+    pub lookups: u64,
+    pub matches: u64,
+    /// `matches / lookups`, 0 before the first lookup
+    pub match_rate: f64,
+}
+
+/// Cumulative bucket of the latency histogram
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyBucket {
+    /// Upper bound in ms, `None` for the overflow bucket
+    pub le_ms: Option<u64>,
+    /// Commands that took at most `le_ms`
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyMetrics {
+    pub count: u64,
+    pub sum_ms: u64,
+    pub max_ms: u64,
+    pub avg_ms: f64,
+    pub buckets: Vec<LatencyBucket>,
+}
+
+/// Everything `get_metrics` returns
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub collected_at: i64,
+    pub sessions: Vec<SessionMetrics>,
+    pub reconnects: ReconnectMetrics,
+    pub tracer: TracerMetrics,
+    /// Commands run through `run_command` and `run_answering`
+    pub commands: LatencyMetrics,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Per bucket, the last one for slower commands
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    count: u64,
+    sum_ms: u64,
+    max_ms: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, ms: u64) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&le| ms <= le)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    fn snapshot(&self) -> LatencyMetrics {
+        let mut cumulative = 0;
+        let buckets = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                cumulative += count;
+                LatencyBucket {
+                    le_ms: LATENCY_BUCKETS_MS.get(i).copied(),
+                    count: cumulative,
+                }
+            })
+            .collect();
+        LatencyMetrics {
+            count: self.count,
+            sum_ms: self.sum_ms,
+            max_ms: self.max_ms,
+            avg_ms: if self.count == 0 {
+                0.0
+            } else {
+                self.sum_ms as f64 / self.count as f64
+            },
+            buckets,
+        }
+    }
+}
+
+/// Totals at the last rate computation
+struct RateSample {
+    at: Instant,
+    bytes_in: u64,
+    bytes_out: u64,
+    in_per_sec: f64,
+    out_per_sec: f64,
+}
+
+/// Counters recorded as things happen
+#[derive(Default)]
+pub struct Metrics {
+    reconnect_attempts: AtomicU64,
+    reconnect_successes: AtomicU64,
+    reconnect_failures: AtomicU64,
+    /// Bytes sent per session
+    sent: DashMap<String, u64>,
+    commands: Mutex<Histogram>,
+    rates: Mutex<HashMap<String, RateSample>>,
+}
+
+impl Metrics {
+    pub fn record_sent(&self, session_id: &str, bytes: usize) {
+        *self.sent.entry(session_id.to_string()).or_default() += bytes as u64;
+    }
+
+    pub fn record_reconnect_attempt(&self) {
+        self.reconnect_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self, succeeded: bool) {
+        let counter = if succeeded {
+            &self.reconnect_successes
+        } else {
+            &self.reconnect_failures
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_command(&self, elapsed: Duration) {
+        self.commands
+            .lock()
+            .unwrap()
+            .record(elapsed.as_millis() as u64);
+    }
+
+    /// Rates of a session, recomputed once `RATE_INTERVAL` has passed
+    fn rates(&self, session_id: &str, bytes_in: u64, bytes_out: u64) -> (f64, f64) {
+        let now = Instant::now();
+        let mut rates = self.rates.lock().unwrap();
+        let sample = rates
+            .entry(session_id.to_string())
+            .or_insert_with(|| RateSample {
+                at: now,
+                bytes_in,
+                bytes_out,
+                in_per_sec: 0.0,
+                out_per_sec: 0.0,
+            });
+        let elapsed = now.duration_since(sample.at);
+        if elapsed >= RATE_INTERVAL {
+            let secs = elapsed.as_secs_f64();
+            sample.in_per_sec = bytes_in.saturating_sub(sample.bytes_in) as f64 / secs;
+            sample.out_per_sec = bytes_out.saturating_sub(sample.bytes_out) as f64 / secs;
+            sample.at = now;
+            sample.bytes_in = bytes_in;
+            sample.bytes_out = bytes_out;
+        }
+        (sample.in_per_sec, sample.out_per_sec)
+    }
+
+    /// Forget counters of sessions that are gone
+    fn retain(&self, live: &[String]) {
+        self.sent.retain(|id, _| live.contains(id));
+        self.rates.lock().unwrap().retain(|id, _| live.contains(id));
+    }
+}
+
+/// Snapshot the counters of `manager` and its sessions
+pub async fn collect(
+    manager: &SessionManager,
+    tracer: &tokio::sync::Mutex<LogTracer>,
+) -> MetricsSnapshot {
+    let metrics = manager.metrics();
+    let mut sessions = Vec::new();
+    for handle in manager.list() {
+        let stats = handle.buffer.lock().await.stats();
+        let bytes_out = metrics.sent.get(&handle.id).map_or(0, |sent| *sent);
+        let (bytes_in_per_sec, bytes_out_per_sec) =
+            metrics.rates(&handle.id, stats.total_pushed, bytes_out);
+        sessions.push(SessionMetrics {
+            session_id: handle.id.clone(),
+            host: handle.config.host.clone(),
+            bytes_in: stats.total_pushed,
+            bytes_out,
+            bytes_in_per_sec,
+            bytes_out_per_sec,
+            buffer_fill_percent: stats.fill_percent,
+            paused: stats.paused,
+            pause_count: stats.pause_count,
+            bytes_dropped: stats.total_dropped,
+            rtt_avg_ms: manager.latency().stats(&handle.id).avg_ms,
+        });
+    }
+    let live: Vec<String> = sessions.iter().map(|s| s.session_id.clone()).collect();
+    metrics.retain(&live);
+
+    let (lookups, matches) = tracer.lock().await.match_counts();
+    MetricsSnapshot {
+        collected_at: chrono::Utc::now().timestamp_millis(),
+        sessions,
+        reconnects: ReconnectMetrics {
+            attempts: metrics.reconnect_attempts.load(Ordering::Relaxed),
+            successes: metrics.reconnect_successes.load(Ordering::Relaxed),
+            failures: metrics.reconnect_failures.load(Ordering::Relaxed),
+        },
+        tracer: TracerMetrics {
+            lookups,
+            matches,
+            match_rate: if lookups == 0 {
+                0.0
+            } else {
+                matches as f64 / lookups as f64
+            },
+        },
+        commands: metrics.commands.lock().unwrap().snapshot(),
+    }
+}
+
+/// Prometheus text exposition (format 0.0.4) of a snapshot
+pub fn render(snapshot: &MetricsSnapshot) -> String {
+    let mut out = String::new();
+    family(
+        &mut out,
+        "session_bytes_in_total",
+        "counter",
+        "Bytes received from the device.",
+    );
+    family(
+        &mut out,
+        "session_bytes_out_total",
+        "counter",
+        "Bytes sent to the device.",
+    );
+    family(
+        &mut out,
+        "session_buffer_pauses_total",
+        "counter",
+        "Reads paused for backpressure.",
+    );
+    family(
+        &mut out,
+        "session_bytes_dropped_total",
+        "counter",
+        "Bytes dropped by the output buffer.",
+    );
+    family(
+        &mut out,
+        "session_rtt_milliseconds",
+        "gauge",
+        "Rolling average round trip.",
+    );
+    family(
+        &mut out,
+        "session_buffer_fill_percent",
+        "gauge",
+        "Output buffer fill.",
+    );
+    for session in &snapshot.sessions {
+        let labels = format!(
+            "session_id=\"{}\",host=\"{}\"",
+            escape(&session.session_id),
+            escape(&session.host)
+        );
+        let _ = writeln!(
+            out,
+            "bspt_session_bytes_in_total{{{}}} {}",
+            labels, session.bytes_in
+        );
+        let _ = writeln!(
+            out,
+            "bspt_session_bytes_out_total{{{}}} {}",
+            labels, session.bytes_out
+        );
+        let _ = writeln!(
+            out,
+            "bspt_session_buffer_pauses_total{{{}}} {}",
+            labels, session.pause_count
+        );
+        let _ = writeln!(
+            out,
+            "bspt_session_bytes_dropped_total{{{}}} {}",
+            labels, session.bytes_dropped
+        );
+        let _ = writeln!(
+            out,
+            "bspt_session_buffer_fill_percent{{{}}} {}",
+            labels, session.buffer_fill_percent
+        );
+        if let Some(rtt) = session.rtt_avg_ms {
+            let _ = writeln!(out, "bspt_session_rtt_milliseconds{{{}}} {}", labels, rtt);
+        }
+    }
+
+    let counters = [
+        (
+            "reconnect_attempts_total",
+            "Reconnection attempts.",
+            snapshot.reconnects.attempts,
+        ),
+        (
+            "reconnect_successes_total",
+            "Successful reconnections.",
+            snapshot.reconnects.successes,
+        ),
+        (
+            "reconnect_failures_total",
+            "Reconnections that gave up.",
+            snapshot.reconnects.failures,
+        ),
+        (
+            "tracer_lookups_total",
+            "Log lines looked up in the tracer.",
+            snapshot.tracer.lookups,
+        ),
+        (
+            "tracer_matches_total",
+            "Log lines matched to a source location.",
+            snapshot.tracer.matches,
+        ),
+    ];
+    for (name, help, value) in counters {
+        family(&mut out, name, "counter", help);
+        let _ = writeln!(out, "bspt_{} {}", name, value);
+    }
+
+    // Prometheus histograms are in seconds
+    let commands = &snapshot.commands;
+    family(
+        &mut out,
+        "command_duration_seconds",
+        "histogram",
+        "Command latency.",
+    );
+    for bucket in &commands.buckets {
+        let le = match bucket.le_ms {
+            Some(ms) => (ms as f64 / 1000.0).to_string(),
+            None => "+Inf".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "bspt_command_duration_seconds_bucket{{le=\"{}\"}} {}",
+            le, bucket.count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "bspt_command_duration_seconds_sum {}",
+        commands.sum_ms as f64 / 1000.0
+    );
+    let _ = writeln!(
+        out,
+        "bspt_command_duration_seconds_count {}",
+        commands.count
+    );
+    out
+}
+
+/// `# HELP` and `# TYPE` lines of a metric family
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP bspt_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE bspt_{} {}", name, kind);
+}
+
+/// Escape a Prometheus label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExporterStatus {
+    pub running: bool,
+    pub addr: Option<String>,
+}
+
+/// What a scrape needs to collect a snapshot
+#[derive(Clone)]
+pub struct ExporterContext {
+    pub manager: Arc<SessionManager>,
+    pub tracer: Arc<tokio::sync::Mutex<LogTracer>>,
+}
+
+struct Running {
+    status: ExporterStatus,
+    /// Dropping it stops the listener
+    _shutdown: watch::Sender<()>,
+}
+
+/// Prometheus endpoint on 127.0.0.1; scrapes of any path but `/` and
+/// `/metrics` get a 404
+pub struct MetricsExporter {
+    running: Mutex<Option<Running>>,
+}
+
+impl MetricsExporter {
+    pub fn new() -> Self {
+        Self {
+            running: Mutex::new(None),
+        }
+    }
+
+    pub async fn start(
+        self: &Arc<Self>,
+        port: u16,
+        context: ExporterContext,
+    ) -> Result<ExporterStatus, MetricsError> {
+        if self.running.lock().unwrap().is_some() {
+            return Err(MetricsError::AlreadyRunning);
+        }
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+        let status = ExporterStatus {
+            running: true,
+            addr: listener.local_addr().ok().map(|a| a.to_string()),
+        };
+        let (shutdown, stopped) = watch::channel(());
+        tokio::spawn(listen(listener, context, stopped));
+
+        info!(addr = ?status.addr, "Metrics exporter started");
+        *self.running.lock().unwrap() = Some(Running {
+            status: status.clone(),
+            _shutdown: shutdown,
+        });
+        Ok(status)
+    }
+
+    pub fn stop(&self) -> Result<(), MetricsError> {
+        self.running
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(MetricsError::NotRunning)?;
+        info!("Metrics exporter stopped");
+        Ok(())
+    }
+
+    pub fn status(&self) -> ExporterStatus {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|r| r.status.clone())
+            .unwrap_or_default()
+    }
+}
+
+async fn listen(listener: TcpListener, context: ExporterContext, mut stopped: watch::Receiver<()>) {
+    let mut scrapes = JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    debug!(error = %e, "Metrics accept failed");
+                    continue;
+                }
+            },
+            _ = stopped.changed() => break,
+        };
+        while scrapes.try_join_next().is_some() {}
+        let context = context.clone();
+        scrapes.spawn(async move {
+            if let Err(e) = serve(stream, &context).await {
+                debug!(error = %e, "Metrics scrape failed");
+            }
+        });
+    }
+    scrapes.shutdown().await;
+}
+
+async fn serve(mut stream: TcpStream, context: &ExporterContext) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut buf))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))??;
+        if n == 0 || request.len() + n > MAX_REQUEST {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&request);
+    let mut parts = head.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = path.split('?').next().unwrap_or(path);
+    let (status, body) = if method != "GET" {
+        ("405 Method Not Allowed", String::new())
+    } else if path == "/" || path == "/metrics" {
+        let snapshot = collect(&context.manager, &context.tracer).await;
+        ("200 OK", render(&snapshot))
+    } else {
+        ("404 Not Found", String::new())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_and_exposition() {
+        let metrics = Metrics::default();
+        for ms in [20, 80, 400, 60_000] {
+            metrics.record_command(Duration::from_millis(ms));
+        }
+        metrics.record_reconnect_attempt();
+        metrics.record_reconnect_attempt();
+        metrics.record_reconnect(true);
+
+        let commands = metrics.commands.lock().unwrap().snapshot();
+        assert_eq!(commands.count, 4);
+        assert_eq!(commands.max_ms, 60_000);
+        let at = |le| {
+            commands
+                .buckets
+                .iter()
+                .find(|b| b.le_ms == le)
+                .unwrap()
+                .count
+        };
+        assert_eq!(at(Some(50)), 1);
+        assert_eq!(at(Some(100)), 2);
+        assert_eq!(at(Some(500)), 3);
+        assert_eq!(at(Some(30000)), 3);
+        assert_eq!(at(None), 4);
+
+        let snapshot = MetricsSnapshot {
+            collected_at: 0,
+            sessions: vec![SessionMetrics {
+                session_id: "s1".to_string(),
+                host: "10.0.0.\"1".to_string(),
+                bytes_in: 1024,
+                bytes_out: 16,
+                bytes_in_per_sec: 0.0,
+                bytes_out_per_sec: 0.0,
+                buffer_fill_percent: 5,
+                paused: false,
+                pause_count: 2,
+                bytes_dropped: 0,
+                rtt_avg_ms: Some(12.5),
+            }],
+            reconnects: ReconnectMetrics {
+                attempts: metrics.reconnect_attempts.load(Ordering::Relaxed),
+                successes: metrics.reconnect_successes.load(Ordering::Relaxed),
+                failures: 0,
+            },
+            tracer: TracerMetrics {
+                lookups: 0,
+                matches: 0,
+                match_rate: 0.0,
+            },
+            commands,
+        };
+        let text = render(&snapshot);
+        assert!(text
+            .contains("bspt_session_bytes_in_total{session_id=\"s1\",host=\"10.0.0.\\\"1\"} 1024"));
+        assert!(text.contains("bspt_session_buffer_pauses_total{session_id=\"s1\""));
+        assert!(text.contains(
+            "bspt_session_rtt_milliseconds{session_id=\"s1\",host=\"10.0.0.\\\"1\"} 12.5"
+        ));
+        assert!(text.contains("bspt_reconnect_attempts_total 2"));
+        assert!(text.contains("bspt_command_duration_seconds_bucket{le=\"0.1\"} 2"));
+        assert!(text.contains("bspt_command_duration_seconds_bucket{le=\"+Inf\"} 4"));
+        assert!(text.contains("bspt_command_duration_seconds_count 4"));
+    }
+}
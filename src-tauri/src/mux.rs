@@ -0,0 +1,175 @@
+//! SSH connections shared by several sessions.
+//!
+//! A VRP device has few VTY lines, so a second terminal on the same device
+//! can be a second PTY channel on an open connection instead of a second
+//! login: `open_ssh_channel` starts a session on the connection of an
+//! existing one. Every session on a connection holds a reference to it in
+//! the `ConnectionPool`; the connection, and the jump sessions carrying it,
+//! stay open until the last of them ends, whichever opened it.
+
+use crate::flow::ReadGate;
+use crate::ssh::SshHandler;
+use crate::tunnel::SshConnection;
+use russh::client;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// A connection the pool closes once its last session leaves
+pub trait Disconnect {
+    fn disconnect(&self) -> impl Future<Output = ()> + Send;
+}
+
+/// An authenticated SSH connection and the jump sessions it runs through
+pub struct SharedConnection {
+    connection: SshConnection,
+    _jump_sessions: Vec<client::Handle<SshHandler>>,
+    /// Gate on the socket the connection reads, see flow.rs
+    read_gate: Arc<ReadGate>,
+}
+
+impl SharedConnection {
+    pub(crate) fn new(
+        connection: client::Handle<SshHandler>,
+        jump_sessions: Vec<client::Handle<SshHandler>>,
+        read_gate: Arc<ReadGate>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            connection: Arc::new(tokio::sync::Mutex::new(connection)),
+            _jump_sessions: jump_sessions,
+            read_gate,
+        })
+    }
+
+    pub fn connection(&self) -> &SshConnection {
+        &self.connection
+    }
+
+    /// Every session opened on the connection attaches its backpressure
+    pub fn read_gate(&self) -> &Arc<ReadGate> {
+        &self.read_gate
+    }
+}
+
+impl Disconnect for SharedConnection {
+    async fn disconnect(&self) {
+        let _ = self
+            .connection
+            .lock()
+            .await
+            .disconnect(russh::Disconnect::ByApplication, "", "en")
+            .await;
+    }
+}
+
+/// The connection each SSH session runs on, owned by `SessionManager`
+pub struct ConnectionPool<C = SharedConnection> {
+    sessions: Mutex<HashMap<String, Arc<C>>>,
+}
+
+impl<C> Default for ConnectionPool<C> {
+    fn default() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<C> ConnectionPool<C> {
+    /// Count `session_id` as a user of `connection`
+    pub fn share(&self, session_id: &str, connection: Arc<C>) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), connection);
+    }
+
+    pub fn get(&self, session_id: &str) -> Option<Arc<C>> {
+        self.sessions.lock().unwrap().get(session_id).cloned()
+    }
+
+    /// Sessions on the same connection as `session_id`, itself included
+    pub fn siblings(&self, session_id: &str) -> Vec<String> {
+        let sessions = self.sessions.lock().unwrap();
+        let Some(connection) = sessions.get(session_id) else {
+            return Vec::new();
+        };
+        let mut siblings: Vec<String> = sessions
+            .iter()
+            .filter(|(_, other)| Arc::ptr_eq(other, connection))
+            .map(|(id, _)| id.clone())
+            .collect();
+        siblings.sort();
+        siblings
+    }
+
+    /// Drop the reference of `session_id`; the connection when no other
+    /// session uses it, for the caller to disconnect
+    pub fn release(&self, session_id: &str) -> Option<Arc<C>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let connection = sessions.remove(session_id)?;
+        let shared = sessions
+            .values()
+            .any(|other| Arc::ptr_eq(other, &connection));
+        (!shared).then_some(connection)
+    }
+}
+
+impl<C: Disconnect> ConnectionPool<C> {
+    /// Release `session_id`, disconnecting the connection if no other
+    /// session uses it
+    pub async fn leave(&self, session_id: &str) {
+        if let Some(connection) = self.release(session_id) {
+            connection.disconnect().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_last_release_returns_connection() {
+        let pool = ConnectionPool::default();
+        let core = Arc::new("core-sw");
+        pool.share("s1", Arc::clone(&core));
+        pool.share("s2", Arc::clone(&core));
+        pool.share("s3", Arc::new("access-sw"));
+        assert_eq!(pool.siblings("s2"), ["s1", "s2"]);
+        assert!(pool.siblings("gone").is_empty());
+
+        // The session that connected can end first
+        assert!(pool.release("s1").is_none());
+        assert!(pool.get("s1").is_none());
+        assert_eq!(pool.release("s2").as_deref(), Some(&"core-sw"));
+        assert!(pool.release("s2").is_none());
+        assert_eq!(pool.release("s3").as_deref(), Some(&"access-sw"));
+    }
+
+    #[derive(Default)]
+    struct Counted(AtomicUsize);
+
+    impl Disconnect for Counted {
+        async fn disconnect(&self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_last_leave_disconnects() {
+        let pool = ConnectionPool::default();
+        let connection = Arc::new(Counted::default());
+        pool.share("s1", Arc::clone(&connection));
+        pool.share("s2", Arc::clone(&connection));
+
+        pool.leave("s1").await;
+        assert_eq!(connection.0.load(Ordering::SeqCst), 0);
+        pool.leave("s2").await;
+        assert_eq!(connection.0.load(Ordering::SeqCst), 1);
+        // Already gone
+        pool.leave("s2").await;
+        assert_eq!(connection.0.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,931 @@
+//! NETCONF over SSH (RFC 6241/6242), without YANG models.
+//!
+//! A NETCONF session is an SSH channel on the `netconf` subsystem rather
+//! than a terminal: after the `<hello>` exchange it carries RPCs and their
+//! replies, framed with `]]>]]>` (base:1.0) or in chunks (base:1.1, used
+//! when both sides advertise it). Requests and replies are XML strings;
+//! the caller builds filters and configs for the device's models.
+
+use crate::known_hosts::{HostKeyCheck, KnownHosts};
+use crate::session::{JumpHost, SessionError};
+use crate::ssh::{self, SshHandler};
+use dashmap::DashMap;
+use quick_xml::events::attributes::AttrError;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use russh::{client, Channel, ChannelMsg};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+pub const DEFAULT_PORT: u16 = 830;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+const BASE_NS: &str = "urn:ietf:params:xml:ns:netconf:base:1.0";
+const BASE_1_0: &str = "urn:ietf:params:netconf:base:1.0";
+const BASE_1_1: &str = "urn:ietf:params:netconf:base:1.1";
+const CANDIDATE: &str = "urn:ietf:params:netconf:capability:candidate:1.0";
+const END_OF_MESSAGE: &[u8] = b"]]>]]>";
+
+#[derive(Error, Debug)]
+pub enum NetconfError {
+    #[error("NETCONF session not found: {0}")]
+    NotFound(String),
+    #[error("Connection failed: {0}")]
+    ConnectionFailed(String),
+    #[error("Authentication failed: {0}")]
+    AuthenticationFailed(String),
+    #[error("Invalid framing: {0}")]
+    Framing(String),
+    #[error("Invalid XML: {0}")]
+    Xml(String),
+    #[error("RPC failed: {}", format_errors(.0))]
+    Rpc(Vec<RpcError>),
+    #[error("Device does not support {0}")]
+    Unsupported(String),
+    #[error("Timed out waiting for a reply")]
+    Timeout,
+    #[error("Session closed by the device")]
+    Closed,
+}
+
+impl From<NetconfError> for String {
+    fn from(err: NetconfError) -> String {
+        err.to_string()
+    }
+}
+
+impl From<SessionError> for NetconfError {
+    fn from(err: SessionError) -> Self {
+        match err {
+            SessionError::AuthenticationFailed(e) => NetconfError::AuthenticationFailed(e),
+            e => NetconfError::ConnectionFailed(e.to_string()),
+        }
+    }
+}
+
+impl From<quick_xml::Error> for NetconfError {
+    fn from(err: quick_xml::Error) -> Self {
+        NetconfError::Xml(err.to_string())
+    }
+}
+
+impl From<AttrError> for NetconfError {
+    fn from(err: AttrError) -> Self {
+        NetconfError::Xml(err.to_string())
+    }
+}
+
+fn format_errors(errors: &[RpcError]) -> String {
+    errors
+        .iter()
+        .map(|e| match &e.message {
+            Some(message) => format!("{}: {}", e.tag, message),
+            None => e.tag.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NetconfConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// SSH hosts to tunnel through, outermost first
+    #[serde(default)]
+    pub jump_hosts: Vec<JumpHost>,
+    /// Wait for each reply, None is 30 seconds
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// base:1.0, messages end with `]]>]]>`
+    EndOfMessage,
+    /// base:1.1, `\n#<len>\n` chunks ending with `\n##\n`
+    Chunked,
+}
+
+impl Framing {
+    /// base:1.1 when the server's hello has it, as ours always does
+    fn negotiate(capabilities: &[String]) -> Self {
+        match capabilities.iter().any(|c| c == BASE_1_1) {
+            true => Framing::Chunked,
+            false => Framing::EndOfMessage,
+        }
+    }
+
+    fn encode(self, message: &str) -> Vec<u8> {
+        match self {
+            Framing::EndOfMessage => [message.as_bytes(), END_OF_MESSAGE].concat(),
+            Framing::Chunked => format!("\n#{}\n{}\n##\n", message.len(), message).into_bytes(),
+        }
+    }
+}
+
+/// Splits the channel's byte stream into messages. Framing may change
+/// between messages, so they are taken one at a time.
+struct FrameDecoder {
+    framing: Framing,
+    buf: Vec<u8>,
+    /// Chunks of the message being received
+    chunks: Vec<u8>,
+}
+
+impl FrameDecoder {
+    fn new() -> Self {
+        Self {
+            framing: Framing::EndOfMessage,
+            buf: Vec::new(),
+            chunks: Vec::new(),
+        }
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    fn next_message(&mut self) -> Result<Option<String>, NetconfError> {
+        let message = match self.framing {
+            Framing::EndOfMessage => {
+                let Some(end) = self
+                    .buf
+                    .windows(END_OF_MESSAGE.len())
+                    .position(|w| w == END_OF_MESSAGE)
+                else {
+                    return Ok(None);
+                };
+                let message = self.buf[..end].to_vec();
+                self.buf.drain(..end + END_OF_MESSAGE.len());
+                message
+            }
+            Framing::Chunked => loop {
+                if self.buf.starts_with(b"\n##\n") {
+                    self.buf.drain(..4);
+                    break std::mem::take(&mut self.chunks);
+                }
+                let Some((len, header)) = self.chunk_header()? else {
+                    return Ok(None);
+                };
+                if self.buf.len() < header + len {
+                    return Ok(None);
+                }
+                self.chunks
+                    .extend_from_slice(&self.buf[header..header + len]);
+                self.buf.drain(..header + len);
+            },
+        };
+        String::from_utf8(message)
+            .map(|message| Some(message.trim().to_string()))
+            .map_err(|e| NetconfError::Framing(e.to_string()))
+    }
+
+    /// Size of the next chunk and of its `\n#<len>\n` header, None until
+    /// the header is complete
+    fn chunk_header(&self) -> Result<Option<(usize, usize)>, NetconfError> {
+        let invalid = || {
+            let start = String::from_utf8_lossy(&self.buf[..self.buf.len().min(16)]).into_owned();
+            NetconfError::Framing(format!("expected a chunk header, got {:?}", start))
+        };
+        if self.buf.len() < 2 {
+            return Ok(None);
+        }
+        if !self.buf.starts_with(b"\n#") {
+            return Err(invalid());
+        }
+        let digits = self.buf[2..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        // The largest chunk is 4294967295 bytes, ten digits
+        if digits > 10 {
+            return Err(invalid());
+        }
+        match self.buf.get(2 + digits) {
+            // Start of the end marker `\n##\n`
+            Some(b'#') if self.buf.len() == 3 => Ok(None),
+            None => Ok(None),
+            Some(b'\n') if digits > 0 => {
+                let len = std::str::from_utf8(&self.buf[2..2 + digits])
+                    .ok()
+                    .and_then(|len| len.parse::<usize>().ok())
+                    .filter(|len| *len > 0)
+                    .ok_or_else(invalid)?;
+                Ok(Some((len, 3 + digits)))
+            }
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+/// Our `<hello>`, offering both framings
+fn hello() -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><hello xmlns="{}"><capabilities><capability>{}</capability><capability>{}</capability></capabilities></hello>"#,
+        BASE_NS, BASE_1_0, BASE_1_1
+    )
+}
+
+/// NETCONF typed in a terminal, for SSH sessions whose subsystem is
+/// `netconf`. The framing is handled here so the user never types it:
+/// the hello is sent on start, received messages are shown one per
+/// paragraph, and what the user types is echoed locally (the subsystem
+/// has no PTY) and sent as one message when it ends with `]]>]]>`,
+/// in chunks once both sides agreed on base:1.1. If the server's framing
+/// can't be decoded, its output is shown as it comes.
+pub(crate) struct TerminalFramer {
+    decoder: FrameDecoder,
+    /// The server's hello has been received
+    ready: bool,
+    /// Output passes through undecoded
+    raw: bool,
+    /// The message being typed
+    typed: Vec<u8>,
+    /// Inside an escape sequence from the terminal, such as an arrow key
+    escape: bool,
+}
+
+impl TerminalFramer {
+    pub(crate) fn new() -> Self {
+        Self {
+            decoder: FrameDecoder::new(),
+            ready: false,
+            raw: false,
+            typed: Vec::new(),
+            escape: false,
+        }
+    }
+
+    /// Our hello, to send as soon as the subsystem has started
+    pub(crate) fn hello(&self) -> Vec<u8> {
+        Framing::EndOfMessage.encode(&hello())
+    }
+
+    /// Terminal output for data from the server
+    pub(crate) fn inbound(&mut self, data: &[u8]) -> Vec<u8> {
+        if self.raw {
+            return data.to_vec();
+        }
+        self.decoder.extend(data);
+        let mut out = Vec::new();
+        loop {
+            match self.decoder.next_message() {
+                Ok(Some(message)) => {
+                    let hello = (!self.ready).then(|| parse_hello(&message).ok());
+                    if let Some((capabilities, _)) = hello.flatten() {
+                        self.ready = true;
+                        self.decoder.framing = Framing::negotiate(&capabilities);
+                        debug!(framing = ?self.decoder.framing, "NETCONF hello received");
+                    }
+                    out.extend_from_slice(message.replace('\n', "\r\n").as_bytes());
+                    out.extend_from_slice(b"\r\n\r\n");
+                }
+                Ok(None) => return out,
+                Err(e) => {
+                    warn!(error = %e, "NETCONF framing lost, showing output as is");
+                    self.raw = true;
+                    out.append(&mut self.decoder.buf);
+                    return out;
+                }
+            }
+        }
+    }
+
+    /// What to send to the server and what to echo for keystrokes
+    pub(crate) fn outbound(&mut self, data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let (mut send, mut echo) = (Vec::new(), Vec::new());
+        for &byte in data {
+            if self.escape {
+                self.escape = !(byte.is_ascii_alphabetic() || byte == b'~');
+                continue;
+            }
+            match byte {
+                0x1b => self.escape = true,
+                b'\r' | b'\n' => {
+                    self.typed.push(b'\n');
+                    echo.extend_from_slice(b"\r\n");
+                }
+                // Not past the start of the line, the echo can't go back up
+                0x7f | 0x08 if self.typed.last() != Some(&b'\n') => {
+                    // A whole character, not just its last UTF-8 byte
+                    while let Some(byte) = self.typed.pop() {
+                        if byte & 0xc0 != 0x80 {
+                            echo.extend_from_slice(b"\x08 \x08");
+                            break;
+                        }
+                    }
+                }
+                b'\t' | 0x20.. => {
+                    self.typed.push(byte);
+                    echo.push(byte);
+                }
+                _ => {}
+            }
+            if self.typed.ends_with(END_OF_MESSAGE) {
+                self.typed.truncate(self.typed.len() - END_OF_MESSAGE.len());
+                let message = String::from_utf8_lossy(&self.typed).trim().to_string();
+                self.typed.clear();
+                if !message.is_empty() {
+                    send.extend(self.decoder.framing.encode(&message));
+                }
+            }
+        }
+        (send, echo)
+    }
+}
+
+/// `<rpc-error>` of a reply
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RpcError {
+    /// transport, rpc, protocol or application
+    pub error_type: String,
+    pub tag: String,
+    /// error or warning
+    pub severity: String,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Reply {
+    message_id: Option<String>,
+    /// Inner XML of `<data>`
+    data: Option<String>,
+    errors: Vec<RpcError>,
+}
+
+impl Reply {
+    /// Warnings alone don't fail a request
+    fn check(self) -> Result<Self, NetconfError> {
+        match self.errors.iter().any(|e| e.severity != "warning") {
+            true => Err(NetconfError::Rpc(self.errors)),
+            false => Ok(self),
+        }
+    }
+}
+
+/// Capabilities and session id from the server's `<hello>`
+fn parse_hello(xml: &str) -> Result<(Vec<String>, Option<u32>), NetconfError> {
+    let mut reader = Reader::from_str(xml);
+    let (mut capabilities, mut session_id, mut hello) = (Vec::new(), None, false);
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"hello" => hello = true,
+                b"capability" => capabilities.push(read_text(&mut reader, &e)?),
+                b"session-id" => session_id = read_text(&mut reader, &e)?.parse().ok(),
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if !hello {
+        return Err(NetconfError::Xml("expected <hello>".to_string()));
+    }
+    Ok((capabilities, session_id))
+}
+
+/// Parse an `<rpc-reply>`; None for other messages, such as notifications
+fn parse_reply(xml: &str) -> Result<Option<Reply>, NetconfError> {
+    let mut reader = Reader::from_str(xml);
+    let mut reply: Option<Reply> = None;
+    let mut error: Option<RpcError> = None;
+    loop {
+        let event = reader.read_event()?;
+        let (start, empty) = match &event {
+            Event::Start(e) => (Some(e), false),
+            Event::Empty(e) => (Some(e), true),
+            Event::End(e) if e.local_name().as_ref() == b"rpc-error" => {
+                if let (Some(reply), Some(error)) = (reply.as_mut(), error.take()) {
+                    reply.errors.push(error);
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        let Some(e) = start else { continue };
+        let Some(reply) = reply.as_mut() else {
+            if e.local_name().as_ref() != b"rpc-reply" {
+                return Ok(None);
+            }
+            let message_id = e
+                .try_get_attribute("message-id")?
+                .map(|id| id.unescape_value().map(|id| id.into_owned()))
+                .transpose()?;
+            reply = Some(Reply {
+                message_id,
+                ..Reply::default()
+            });
+            if empty {
+                break;
+            }
+            continue;
+        };
+        if empty {
+            continue;
+        }
+        match (e.local_name().as_ref(), error.as_mut()) {
+            (b"rpc-error", _) => error = Some(RpcError::default()),
+            (b"error-type", Some(error)) => error.error_type = read_text(&mut reader, e)?,
+            (b"error-tag", Some(error)) => error.tag = read_text(&mut reader, e)?,
+            (b"error-severity", Some(error)) => error.severity = read_text(&mut reader, e)?,
+            (b"error-message", Some(error)) => error.message = Some(read_text(&mut reader, e)?),
+            (b"data", None) => {
+                let span = reader.read_to_end(e.name())?;
+                reply.data = Some(
+                    xml[span.start as usize..span.end as usize]
+                        .trim()
+                        .to_string(),
+                );
+            }
+            _ => {}
+        }
+    }
+    match reply {
+        Some(reply) => Ok(Some(reply)),
+        None => Err(NetconfError::Xml("empty message".to_string())),
+    }
+}
+
+fn read_text(
+    reader: &mut Reader<&[u8]>,
+    start: &BytesStart,
+) -> Result<String, NetconfError> {
+    let raw = reader.read_text(start.name())?;
+    quick_xml::escape::unescape(&raw)
+        .map(|text| text.trim().to_string())
+        .map_err(|e| NetconfError::Xml(e.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Datastore {
+    Running,
+    Candidate,
+    Startup,
+}
+
+impl Datastore {
+    fn element(self) -> &'static str {
+        match self {
+            Datastore::Running => "<running/>",
+            Datastore::Candidate => "<candidate/>",
+            Datastore::Startup => "<startup/>",
+        }
+    }
+}
+
+/// How `<edit-config>` treats config without an `operation` attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultOperation {
+    Merge,
+    Replace,
+    None,
+}
+
+impl DefaultOperation {
+    fn as_str(self) -> &'static str {
+        match self {
+            DefaultOperation::Merge => "merge",
+            DefaultOperation::Replace => "replace",
+            DefaultOperation::None => "none",
+        }
+    }
+}
+
+fn filter(filter: Option<&str>) -> String {
+    match filter {
+        Some(filter) => format!(r#"<filter type="subtree">{}</filter>"#, filter),
+        None => String::new(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetconfSessionInfo {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+    /// Assigned by the device in its `<hello>`
+    pub session_id: Option<u32>,
+    pub capabilities: Vec<String>,
+    pub framing: Framing,
+}
+
+/// One NETCONF session; requests are sent one at a time
+pub struct NetconfClient {
+    info: NetconfSessionInfo,
+    session: client::Handle<SshHandler>,
+    /// Carry the tunnel, see ssh.rs
+    _jump_sessions: Vec<client::Handle<SshHandler>>,
+    channel: Channel<client::Msg>,
+    decoder: FrameDecoder,
+    next_message_id: u64,
+    timeout: Duration,
+}
+
+impl NetconfClient {
+    /// Connect, start the `netconf` subsystem and exchange hellos. Host
+    /// keys are stored on first use, see known_hosts.rs.
+    pub async fn connect(
+        config: &NetconfConfig,
+        known_hosts: &Arc<KnownHosts>,
+    ) -> Result<Self, NetconfError> {
+        let id = Uuid::new_v4().to_string();
+        info!(id = %id, host = %config.host, port = config.port, "Opening NETCONF session");
+        let timeout = Duration::from_secs(config.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let (mut session, jump_sessions) = ssh::connect(
+            &id,
+            (&config.host, config.port),
+            &config.jump_hosts,
+            Arc::new(client::Config::default()),
+            &HostKeyCheck::accept_new(known_hosts),
+            None,
+            None,
+            None,
+            Some(timeout),
+        )
+        .await?;
+        let accepted = session
+            .authenticate_password(&config.username, &config.password)
+            .await
+            .map_err(|e| NetconfError::AuthenticationFailed(e.to_string()))?;
+        if !accepted {
+            return Err(NetconfError::AuthenticationFailed(
+                "Authentication rejected".to_string(),
+            ));
+        }
+
+        let mut channel = session
+            .channel_open_session()
+            .await
+            .map_err(|e| NetconfError::ConnectionFailed(e.to_string()))?;
+        channel
+            .request_subsystem(true, "netconf")
+            .await
+            .map_err(|e| NetconfError::ConnectionFailed(e.to_string()))?;
+        loop {
+            match tokio::time::timeout(timeout, channel.wait()).await {
+                Ok(Some(ChannelMsg::Success)) => break,
+                Ok(Some(ChannelMsg::Failure)) => {
+                    return Err(NetconfError::Unsupported(
+                        "the netconf subsystem".to_string(),
+                    ))
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return Err(NetconfError::Closed),
+                Err(_) => return Err(NetconfError::Timeout),
+            }
+        }
+
+        let mut client = Self {
+            info: NetconfSessionInfo {
+                id,
+                host: config.host.clone(),
+                port: config.port,
+                session_id: None,
+                capabilities: Vec::new(),
+                framing: Framing::EndOfMessage,
+            },
+            session,
+            _jump_sessions: jump_sessions,
+            channel,
+            decoder: FrameDecoder::new(),
+            next_message_id: 1,
+            timeout,
+        };
+        client.send(&hello()).await?;
+        let server_hello = client.receive().await?;
+        let (capabilities, session_id) = parse_hello(&server_hello)?;
+        let framing = Framing::negotiate(&capabilities);
+        client.decoder.framing = framing;
+        client.info.framing = framing;
+        client.info.capabilities = capabilities;
+        client.info.session_id = session_id;
+        info!(id = %client.info.id, session_id = ?session_id, framing = ?framing, "NETCONF session ready");
+        Ok(client)
+    }
+
+    pub fn info(&self) -> &NetconfSessionInfo {
+        &self.info
+    }
+
+    fn supports(&self, capability: &str) -> bool {
+        self.info
+            .capabilities
+            .iter()
+            .any(|c| c.split('?').next() == Some(capability))
+    }
+
+    async fn send(&mut self, message: &str) -> Result<(), NetconfError> {
+        debug!(id = %self.info.id, bytes = message.len(), "Sending NETCONF message");
+        let framed = self.info.framing.encode(message);
+        self.channel
+            .data(&framed[..])
+            .await
+            .map_err(|_| NetconfError::Closed)
+    }
+
+    async fn receive(&mut self) -> Result<String, NetconfError> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            if let Some(message) = self.decoder.next_message()? {
+                return Ok(message);
+            }
+            match tokio::time::timeout_at(deadline, self.channel.wait()).await {
+                Ok(Some(ChannelMsg::Data { data })) => self.decoder.extend(&data),
+                Ok(Some(ChannelMsg::Eof | ChannelMsg::Close) | None) => {
+                    return Err(NetconfError::Closed)
+                }
+                Ok(Some(_)) => {}
+                Err(_) => return Err(NetconfError::Timeout),
+            }
+        }
+    }
+
+    /// Send an operation and return the raw `<rpc-reply>`, whatever it holds
+    pub async fn rpc(&mut self, operation: &str) -> Result<String, NetconfError> {
+        self.request(operation).await.map(|(xml, _)| xml)
+    }
+
+    async fn request(&mut self, operation: &str) -> Result<(String, Reply), NetconfError> {
+        let message_id = self.next_message_id.to_string();
+        self.next_message_id += 1;
+        let rpc = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><rpc message-id="{}" xmlns="{}">{}</rpc>"#,
+            message_id, BASE_NS, operation
+        );
+        self.send(&rpc).await?;
+        loop {
+            let xml = self.receive().await?;
+            match parse_reply(&xml)? {
+                Some(reply) if reply.message_id.as_deref() == Some(message_id.as_str()) => {
+                    return Ok((xml, reply))
+                }
+                Some(reply) => {
+                    warn!(id = %self.info.id, message_id = ?reply.message_id, "Ignoring reply to another request")
+                }
+                None => debug!(id = %self.info.id, "Ignoring NETCONF notification"),
+            }
+        }
+    }
+
+    /// Data of `<get>`, optionally narrowed by a subtree filter
+    pub async fn get(&mut self, subtree: Option<&str>) -> Result<String, NetconfError> {
+        let (_, reply) = self
+            .request(&format!("<get>{}</get>", filter(subtree)))
+            .await?;
+        Ok(reply.check()?.data.unwrap_or_default())
+    }
+
+    pub async fn get_config(
+        &mut self,
+        source: Datastore,
+        subtree: Option<&str>,
+    ) -> Result<String, NetconfError> {
+        let operation = format!(
+            "<get-config><source>{}</source>{}</get-config>",
+            source.element(),
+            filter(subtree)
+        );
+        let (_, reply) = self.request(&operation).await?;
+        Ok(reply.check()?.data.unwrap_or_default())
+    }
+
+    /// Apply the contents of a `<config>` element. Without a target, the
+    /// candidate datastore is edited when the device has one
+    pub async fn edit_config(
+        &mut self,
+        target: Option<Datastore>,
+        config: &str,
+        default_operation: Option<DefaultOperation>,
+    ) -> Result<(), NetconfError> {
+        let target = target.unwrap_or(match self.supports(CANDIDATE) {
+            true => Datastore::Candidate,
+            false => Datastore::Running,
+        });
+        if target == Datastore::Candidate && !self.supports(CANDIDATE) {
+            return Err(NetconfError::Unsupported(
+                "the candidate datastore".to_string(),
+            ));
+        }
+        let default_operation = default_operation
+            .map(|op| format!("<default-operation>{}</default-operation>", op.as_str()))
+            .unwrap_or_default();
+        let operation = format!(
+            "<edit-config><target>{}</target>{}<config>{}</config></edit-config>",
+            target.element(),
+            default_operation,
+            config
+        );
+        self.request(&operation).await?.1.check()?;
+        Ok(())
+    }
+
+    /// Commit the candidate datastore to running
+    pub async fn commit(&mut self) -> Result<(), NetconfError> {
+        if !self.supports(CANDIDATE) {
+            return Err(NetconfError::Unsupported(
+                "the candidate datastore".to_string(),
+            ));
+        }
+        self.request("<commit/>").await?.1.check()?;
+        Ok(())
+    }
+
+    /// End the session politely; the connection closes either way
+    pub async fn close(&mut self) {
+        self.timeout = self.timeout.min(Duration::from_secs(5));
+        if let Err(e) = self.request("<close-session/>").await {
+            debug!(id = %self.info.id, error = %e, "close-session failed");
+        }
+        let _ = self.channel.close().await;
+        let _ = self
+            .session
+            .disconnect(russh::Disconnect::ByApplication, "", "en")
+            .await;
+        info!(id = %self.info.id, "NETCONF session closed");
+    }
+}
+
+/// Open NETCONF sessions by id
+#[derive(Default)]
+pub struct NetconfManager {
+    sessions: DashMap<String, Arc<Mutex<NetconfClient>>>,
+}
+
+impl NetconfManager {
+    pub async fn connect(
+        &self,
+        config: &NetconfConfig,
+        known_hosts: &Arc<KnownHosts>,
+    ) -> Result<NetconfSessionInfo, NetconfError> {
+        let client = NetconfClient::connect(config, known_hosts).await?;
+        let info = client.info().clone();
+        self.sessions
+            .insert(info.id.clone(), Arc::new(Mutex::new(client)));
+        Ok(info)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Arc<Mutex<NetconfClient>>, NetconfError> {
+        self.sessions
+            .get(id)
+            .map(|r| Arc::clone(&r))
+            .ok_or_else(|| NetconfError::NotFound(id.to_string()))
+    }
+
+    pub async fn list(&self) -> Vec<NetconfSessionInfo> {
+        let clients: Vec<_> = self
+            .sessions
+            .iter()
+            .map(|r| Arc::clone(r.value()))
+            .collect();
+        let mut sessions = Vec::with_capacity(clients.len());
+        for client in clients {
+            sessions.push(client.lock().await.info().clone());
+        }
+        sessions
+    }
+
+    pub async fn close(&self, id: &str) -> Result<(), NetconfError> {
+        let (_, client) = self
+            .sessions
+            .remove(id)
+            .ok_or_else(|| NetconfError::NotFound(id.to_string()))?;
+        // A request in flight finishes first
+        client.lock().await.close().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_framing() {
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(b"<hello/>]]>]]><rpc-reply");
+        assert_eq!(decoder.next_message().unwrap().as_deref(), Some("<hello/>"));
+        assert_eq!(decoder.next_message().unwrap(), None);
+
+        // Chunks split anywhere, several to a message
+        decoder.framing = Framing::Chunked;
+        decoder.buf.clear();
+        let mut messages = Vec::new();
+        for byte in b"\n#4\n<ok/\n#1\n>\n##\n\n#3\nabc\n##\n" {
+            decoder.extend(&[*byte]);
+            messages.extend(decoder.next_message().unwrap());
+        }
+        assert_eq!(messages, ["<ok/>", "abc"]);
+        assert_eq!(decoder.next_message().unwrap(), None);
+
+        assert_eq!(
+            String::from_utf8(Framing::Chunked.encode("<ok/>")).unwrap(),
+            "\n#5\n<ok/>\n##\n"
+        );
+        decoder.extend(b"\n#0\n");
+        assert!(matches!(
+            decoder.next_message(),
+            Err(NetconfError::Framing(_))
+        ));
+    }
+
+    #[test]
+    fn test_terminal_framer() {
+        let mut framer = TerminalFramer::new();
+        assert!(framer.hello().ends_with(END_OF_MESSAGE));
+
+        // Typed with a typo, sent on the end marker, before the hello
+        let (send, echo) = framer.outbound(b"<get/\x7f/>\r]]>]]>");
+        assert_eq!(send, b"<get/>]]>]]>");
+        assert_eq!(echo, b"<get/\x08 \x08/>\r\n]]>]]>");
+
+        let hello = format!(
+            r#"<hello xmlns="{}"><capabilities><capability>{}</capability></capabilities></hello>]]>]]>"#,
+            BASE_NS, BASE_1_1
+        );
+        let shown = framer.inbound(hello.as_bytes());
+        assert!(shown.ends_with(b"</hello>\r\n\r\n"));
+        assert_eq!(framer.decoder.framing, Framing::Chunked);
+
+        // Chunked both ways from here, arrow keys ignored
+        let (send, _) = framer.outbound(b"<ok/>\x1b[A]]>]]>");
+        assert_eq!(send, b"\n#5\n<ok/>\n##\n");
+        assert_eq!(
+            framer.inbound(b"\n#9\n<a>\n</a>\n\n##\n"),
+            b"<a>\r\n</a>\r\n\r\n"
+        );
+        assert!(framer.inbound(b"\n#3").is_empty());
+        assert_eq!(framer.inbound(b"x\n"), b"\n#3x\n");
+        assert_eq!(framer.inbound(b"<raw>"), b"<raw>");
+    }
+
+    #[test]
+    fn test_parse_messages() {
+        let hello = r#"<hello xmlns="urn:ietf:params:xml:ns:netconf:base:1.0">
+            <capabilities>
+              <capability>urn:ietf:params:netconf:base:1.1</capability>
+              <capability>urn:ietf:params:netconf:capability:candidate:1.0</capability>
+              <capability>http://www.huawei.com/netconf/vrp?module=huawei-ifm&amp;revision=2020-03-06</capability>
+            </capabilities>
+            <session-id>42</session-id>
+          </hello>"#;
+        let (capabilities, session_id) = parse_hello(hello).unwrap();
+        assert_eq!(capabilities.len(), 3);
+        assert!(capabilities[2].ends_with("huawei-ifm&revision=2020-03-06"));
+        assert_eq!(session_id, Some(42));
+        assert!(parse_hello("<rpc-reply/>").is_err());
+
+        let reply = parse_reply(
+            r#"<rpc-reply message-id="7" xmlns="urn:ietf:params:xml:ns:netconf:base:1.0">
+                 <data><ifm xmlns="urn:huawei:yang:huawei-ifm"><interfaces/></ifm></data>
+               </rpc-reply>"#,
+        )
+        .unwrap()
+        .unwrap()
+        .check()
+        .unwrap();
+        assert_eq!(reply.message_id.as_deref(), Some("7"));
+        assert_eq!(
+            reply.data.as_deref(),
+            Some(r#"<ifm xmlns="urn:huawei:yang:huawei-ifm"><interfaces/></ifm>"#)
+        );
+
+        let reply = parse_reply(
+            r#"<rpc-reply message-id="8"><rpc-error>
+                 <error-type>application</error-type><error-tag>invalid-value</error-tag>
+                 <error-severity>error</error-severity>
+                 <error-message xml:lang="en">Interface &lt;10GE1/0/99&gt; does not exist.</error-message>
+               </rpc-error></rpc-reply>"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            reply.check().unwrap_err().to_string(),
+            "RPC failed: invalid-value: Interface <10GE1/0/99> does not exist."
+        );
+
+        let ok = parse_reply(r#"<rpc-reply message-id="9"><ok/></rpc-reply>"#)
+            .unwrap()
+            .unwrap();
+        assert!(ok.check().is_ok());
+        let notification = r#"<notification xmlns="urn:ietf:params:xml:ns:netconf:notification:1.0"><eventTime>2026-01-01T00:00:00Z</eventTime></notification>"#;
+        assert!(parse_reply(notification).unwrap().is_none());
+    }
+}
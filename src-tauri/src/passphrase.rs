@@ -0,0 +1,191 @@
+//! Passphrases of encrypted private keys, asked for while connecting.
+//!
+//! An SSH session whose key (or a jump host's key) is encrypted and comes
+//! without a passphrase, or with a wrong one, leaves authentication pending:
+//! `session:{id}:passphrase` names the key and `answer_passphrase` resumes
+//! the connect with the user's answer. A wrong answer prompts again, up to
+//! `MAX_ATTEMPTS` times. Cancelling, or leaving the prompt unanswered for
+//! `PROMPT_TIMEOUT`, fails authentication. Answers are used for this connect
+//! only and never stored.
+
+use crate::session::{SessionError, SshAuth};
+use crate::ssh;
+use dashmap::DashMap;
+use russh::keys::key::KeyPair;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// How long a connect waits for `answer_passphrase`
+pub const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Prompts per key before authentication gives up
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// Payload of `session:{id}:passphrase`
+#[derive(Debug, Clone, Serialize)]
+pub struct PassphrasePrompt {
+    /// Passed back to `answer_passphrase`
+    pub request_id: String,
+    pub session_id: String,
+    /// The target, or the jump host the key belongs to
+    pub host: String,
+    pub username: String,
+    /// Key path as configured, or `inline key` for a PEM
+    pub key: String,
+    /// 1 for the first prompt of this key
+    pub attempt: u32,
+    /// The previous answer was wrong
+    pub retry: bool,
+}
+
+/// Passphrase prompts waiting for the frontend, keyed by request id
+#[derive(Default)]
+pub struct PassphrasePrompts {
+    /// None cancels the prompt
+    pending: DashMap<String, oneshot::Sender<Option<String>>>,
+}
+
+impl PassphrasePrompts {
+    /// Decode the key of `auth`, prompting on `session:{id}:passphrase`
+    /// while it is encrypted and the passphrase is missing or wrong. The
+    /// accepted passphrase is kept in `auth`. None for password auth.
+    pub async fn unlock(
+        &self,
+        app_handle: &AppHandle,
+        session_id: &str,
+        host: &str,
+        username: &str,
+        auth: &mut SshAuth,
+    ) -> Result<Option<Arc<KeyPair>>, SessionError> {
+        let Some(pem) = ssh::key_pem(auth)? else {
+            return Ok(None);
+        };
+        let SshAuth::PublicKey {
+            path, passphrase, ..
+        } = auth
+        else {
+            return Ok(None);
+        };
+        let mut attempt = 0;
+        loop {
+            let retry = match ssh::decode_key(&pem, passphrase.as_deref()) {
+                Ok(key) => return Ok(Some(key)),
+                Err(russh::keys::Error::KeyIsEncrypted) => false,
+                // With a passphrase, any failure of a key that is encrypted
+                // means the passphrase is wrong
+                Err(e) if passphrase.is_some() && encrypted(&pem) => {
+                    warn!(session_id = %session_id, host = %host, error = %e, "Wrong key passphrase");
+                    true
+                }
+                Err(e) => return Err(ssh::key_error(e)),
+            };
+            if attempt == MAX_ATTEMPTS {
+                return Err(SessionError::AuthenticationFailed(
+                    "Wrong passphrase for private key".to_string(),
+                ));
+            }
+            attempt += 1;
+            let prompt = PassphrasePrompt {
+                request_id: String::new(),
+                session_id: session_id.to_string(),
+                host: host.to_string(),
+                username: username.to_string(),
+                key: path.clone().unwrap_or_else(|| "inline key".to_string()),
+                attempt,
+                retry,
+            };
+            match self.ask(app_handle, prompt).await {
+                Some(answer) => *passphrase = Some(answer),
+                None => {
+                    return Err(SessionError::AuthenticationFailed(
+                        "No passphrase given for private key".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Resolve a pending prompt, None cancelling it; false if it is unknown
+    /// or already over
+    pub fn answer(&self, request_id: &str, passphrase: Option<String>) -> bool {
+        match self.pending.remove(request_id) {
+            Some((_, tx)) => tx.send(passphrase).is_ok(),
+            None => false,
+        }
+    }
+
+    async fn ask(&self, app_handle: &AppHandle, mut prompt: PassphrasePrompt) -> Option<String> {
+        let request_id = Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(request_id.clone(), tx);
+        prompt.request_id = request_id.clone();
+
+        info!(session_id = %prompt.session_id, host = %prompt.host, key = %prompt.key, attempt = prompt.attempt, "Asking for key passphrase");
+        let event = format!("session:{}:passphrase", prompt.session_id);
+        if let Err(e) = app_handle.emit(&event, &prompt) {
+            warn!(session_id = %prompt.session_id, error = %e, "Failed to emit passphrase prompt");
+            self.pending.remove(&request_id);
+            return None;
+        }
+        self.wait(&request_id, rx, PROMPT_TIMEOUT).await
+    }
+
+    async fn wait(
+        &self,
+        request_id: &str,
+        rx: oneshot::Receiver<Option<String>>,
+        timeout: Duration,
+    ) -> Option<String> {
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(answer) => answer.ok().flatten(),
+            Err(_) => {
+                self.pending.remove(request_id);
+                None
+            }
+        }
+    }
+}
+
+/// Whether the key needs a passphrase at all
+fn encrypted(pem: &str) -> bool {
+    matches!(
+        ssh::decode_key(pem, None),
+        Err(russh::keys::Error::KeyIsEncrypted)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_answer_and_wait() {
+        let prompts = PassphrasePrompts::default();
+        let (tx, rx) = oneshot::channel();
+        prompts.pending.insert("r1".to_string(), tx);
+        assert!(prompts.answer("r1", Some("bspt".to_string())));
+        assert_eq!(
+            prompts.wait("r1", rx, PROMPT_TIMEOUT).await.as_deref(),
+            Some("bspt")
+        );
+        assert!(!prompts.answer("r1", None));
+
+        let (tx, rx) = oneshot::channel();
+        prompts.pending.insert("r2".to_string(), tx);
+        assert!(prompts.answer("r2", None));
+        assert!(prompts.wait("r2", rx, PROMPT_TIMEOUT).await.is_none());
+
+        let (tx, rx) = oneshot::channel();
+        prompts.pending.insert("r3".to_string(), tx);
+        assert!(prompts
+            .wait("r3", rx, Duration::from_millis(10))
+            .await
+            .is_none());
+        assert!(!prompts.answer("r3", Some("late".to_string())));
+    }
+}
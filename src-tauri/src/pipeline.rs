@@ -0,0 +1,315 @@
+//! The per-session output data path as an ordered pipeline.
+//!
+//! Every transport hands what it reads to a `Pipeline`, which runs the
+//! stages in order:
+//!
+//! transport → charset → processors → lines → dialect → triggers → tracer → emit
+//!
+//! `charset` decodes device output to UTF-8, `processors` are the plugin
+//! output processors, `lines` assembles whole lines, `dialect` parses them
+//! for VRP events (emitted as `session:{id}:vrp`, answering pagination),
+//! `triggers` publishes those events on the backend bus (automation,
+//! alerts, timeline), `tracer` matches lines to source code and records
+//! hits on the timeline, and `emit` hands the output to the ring buffer,
+//! scrollback and frontend.
+//!
+//! Each session's stages can be switched on and off while it runs. The
+//! transport can't; a stage downstream of a disabled one gets no input
+//! (`StageStatus::active`). The tracer starts disabled, and SSH sessions
+//! start without `lines`, `dialect` and `triggers`, as before the pipeline.
+
+use crate::alerts;
+use crate::charset::Transcoder;
+use crate::events::SessionLine;
+use crate::lines::LineAssembler;
+use crate::plugin::ProcessorChain;
+use crate::scrollback::ANSI_RE;
+use crate::session::{SessionConfig, SessionError, SessionManager};
+use crate::timeline::{self, Timeline};
+use crate::tracer::LogTracer;
+use crate::vrp::{VrpEvent, VrpParser};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use tauri::Emitter;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+/// A step of the data path, in pipeline order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Transport,
+    Charset,
+    Processors,
+    Lines,
+    Dialect,
+    Triggers,
+    Tracer,
+    Emit,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 8] = [
+        Stage::Transport,
+        Stage::Charset,
+        Stage::Processors,
+        Stage::Lines,
+        Stage::Dialect,
+        Stage::Triggers,
+        Stage::Tracer,
+        Stage::Emit,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Stages whose output this one consumes, besides the byte path
+    fn inputs(self) -> &'static [Stage] {
+        match self {
+            Stage::Dialect | Stage::Tracer => &[Stage::Lines],
+            Stage::Triggers => &[Stage::Lines, Stage::Dialect],
+            _ => &[],
+        }
+    }
+}
+
+/// One stage of a session's pipeline, as listed by `get_pipeline`
+#[derive(Debug, Clone, Serialize)]
+pub struct StageStatus {
+    pub stage: Stage,
+    pub enabled: bool,
+    /// Enabled and fed: every stage it reads from is enabled too
+    pub active: bool,
+}
+
+/// Which stages of a session run, shared by its pipeline and its
+/// `SessionHandle`
+pub struct PipelineStages(AtomicU8);
+
+impl Default for PipelineStages {
+    fn default() -> Self {
+        Self::with(&[Stage::Tracer])
+    }
+}
+
+impl PipelineStages {
+    /// Stages of an SSH session, which parsed no lines before the pipeline
+    pub fn without_parsing() -> Self {
+        Self::with(&[Stage::Lines, Stage::Dialect, Stage::Triggers, Stage::Tracer])
+    }
+
+    fn with(disabled: &[Stage]) -> Self {
+        let bits = Stage::ALL
+            .iter()
+            .filter(|stage| !disabled.contains(stage))
+            .fold(0, |bits, stage| bits | stage.bit());
+        Self(AtomicU8::new(bits))
+    }
+
+    pub fn enabled(&self, stage: Stage) -> bool {
+        self.0.load(Ordering::Relaxed) & stage.bit() != 0
+    }
+
+    fn active(&self, stage: Stage) -> bool {
+        self.enabled(stage) && stage.inputs().iter().all(|input| self.enabled(*input))
+    }
+
+    pub fn set(&self, stage: Stage, enabled: bool) -> Result<(), SessionError> {
+        if stage == Stage::Transport {
+            return Err(SessionError::InvalidConfig(
+                "the transport stage can't be disabled".to_string(),
+            ));
+        }
+        if enabled {
+            self.0.fetch_or(stage.bit(), Ordering::Relaxed);
+        } else {
+            self.0.fetch_and(!stage.bit(), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> Vec<StageStatus> {
+        Stage::ALL
+            .iter()
+            .map(|&stage| StageStatus {
+                stage,
+                enabled: self.enabled(stage),
+                active: self.active(stage),
+            })
+            .collect()
+    }
+}
+
+/// What the pipeline made of one read
+pub struct Processed {
+    /// Output for the ring buffer, empty while `emit` is disabled
+    pub data: Vec<u8>,
+    /// Bytes to send back, e.g. a space for a pagination prompt
+    pub auto_response: Option<Vec<u8>>,
+}
+
+/// The data path of one session, owned by its transport task
+pub struct Pipeline {
+    session_id: String,
+    vrp_event_name: String,
+    manager: Arc<SessionManager>,
+    stages: Arc<PipelineStages>,
+    transcoder: Transcoder,
+    processors: ProcessorChain,
+    assembler: LineAssembler,
+    parser: VrpParser,
+}
+
+impl Pipeline {
+    pub fn new(
+        session_id: &str,
+        config: &SessionConfig,
+        manager: &Arc<SessionManager>,
+        processors: ProcessorChain,
+        stages: Arc<PipelineStages>,
+    ) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            vrp_event_name: format!("session:{}:vrp", session_id),
+            manager: Arc::clone(manager),
+            stages,
+            transcoder: Transcoder::new(session_id.to_string(), config.charset),
+            processors,
+            assembler: LineAssembler::new(),
+            parser: manager.plugins().parser(config),
+        }
+    }
+
+    pub fn set_auto_pagination(&mut self, enabled: bool) {
+        self.parser.auto_pagination = enabled;
+    }
+
+    /// Run bytes read from the transport through the enabled stages
+    pub fn process(&mut self, data: Vec<u8>) -> Processed {
+        let stages = &self.stages;
+        let data = if stages.enabled(Stage::Charset) {
+            self.transcoder.decode(data)
+        } else {
+            data
+        };
+        let data = if stages.enabled(Stage::Processors) {
+            self.processors.process(data)
+        } else {
+            data
+        };
+
+        let mut auto_response = None;
+        if stages.enabled(Stage::Lines) {
+            let lines = self.assembler.push(&data);
+            if stages.enabled(Stage::Dialect) {
+                let (events, response) = self.parser.parse(&lines, &mut self.assembler);
+                auto_response = response;
+                for event in events {
+                    self.dispatch(&event);
+                }
+            }
+            if stages.enabled(Stage::Tracer) {
+                for line in lines {
+                    self.manager.events().publish_line(&self.session_id, line);
+                }
+            }
+        } else if !self.assembler.partial().is_empty() {
+            // Don't join a stale partial line to output after re-enabling
+            self.assembler = LineAssembler::new();
+        }
+
+        Processed {
+            data: if stages.enabled(Stage::Emit) {
+                data
+            } else {
+                Vec::new()
+            },
+            auto_response,
+        }
+    }
+
+    fn dispatch(&self, event: &VrpEvent) {
+        let session_id = &self.session_id;
+        if let Err(e) = self.manager.app_handle().emit(&self.vrp_event_name, event) {
+            warn!(session_id = %session_id, error = %e, "Failed to emit VRP event");
+        }
+        if self.stages.enabled(Stage::Triggers) {
+            self.manager.events().publish(session_id, event);
+        }
+        match event {
+            VrpEvent::ViewChange { view, hostname } => {
+                debug!(session_id = %session_id, view = ?view, hostname = %hostname, "VRP view change");
+            }
+            VrpEvent::Pagination {
+                detected,
+                auto_handled,
+            } => {
+                debug!(session_id = %session_id, detected = detected, auto_handled = auto_handled, "VRP pagination");
+            }
+            VrpEvent::BoardInfo(board) => {
+                debug!(session_id = %session_id, slot = %board.slot_id, board_type = %board.board_type, "VRP board detected");
+            }
+            VrpEvent::CommandError { message } => {
+                debug!(session_id = %session_id, message = %message, "VRP command error");
+            }
+        }
+    }
+}
+
+/// Match the lines of sessions with the tracer stage enabled against the
+/// source index, recording hits on the timeline
+pub fn spawn_tracer(
+    timeline: &Arc<Timeline>,
+    manager: &Arc<SessionManager>,
+    tracer: &Arc<Mutex<LogTracer>>,
+) {
+    let mut lines = manager.events().subscribe_lines();
+    let (timeline, manager, tracer) = (
+        Arc::clone(timeline),
+        Arc::clone(manager),
+        Arc::clone(tracer),
+    );
+    tauri::async_runtime::spawn(async move {
+        while let Some(SessionLine { session_id, line }) =
+            alerts::next(&mut lines, "pipeline tracer").await
+        {
+            let line = ANSI_RE.replace_all(&line, "");
+            let location = tracer
+                .lock()
+                .await
+                .match_log(&line)
+                .map(|location| format!("{}:{}", location.file, location.line));
+            if let Some(location) = location {
+                timeline::traced(&timeline, &manager, &session_id, &line, location);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_switches() {
+        let stages = PipelineStages::default();
+        assert!(stages.enabled(Stage::Dialect));
+        assert!(!stages.enabled(Stage::Tracer));
+        assert!(stages.set(Stage::Transport, false).is_err());
+
+        stages.set(Stage::Lines, false).unwrap();
+        let status = stages.status();
+        let dialect = status.iter().find(|s| s.stage == Stage::Dialect).unwrap();
+        assert!(dialect.enabled && !dialect.active);
+        let emit = status.iter().find(|s| s.stage == Stage::Emit).unwrap();
+        assert!(emit.active);
+
+        stages.set(Stage::Lines, true).unwrap();
+        assert!(stages.active(Stage::Triggers));
+
+        let ssh = PipelineStages::without_parsing();
+        assert!(ssh.enabled(Stage::Charset) && !ssh.enabled(Stage::Lines));
+    }
+}
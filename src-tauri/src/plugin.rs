@@ -0,0 +1,749 @@
+//! Plugins: transports, device dialects and output processors added
+//! without forking the app.
+//!
+//! A plugin is a directory under `<app data>/plugins/` with a `plugin.json`
+//! manifest. Dialects are declared in the manifest as patterns and need no
+//! code. Transports and output processors live in a dynamic library next to
+//! the manifest that exports `bspt_plugin_v1`, returning a `PluginVTable`.
+//! Sessions pick plugins by name in `SessionConfig.plugins`.
+
+use crate::session::SessionConfig;
+use crate::vrp::VrpParser;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Version of the library ABI below
+pub const API_VERSION: u32 = 1;
+/// `PluginVTable.create` kinds
+pub const KIND_TRANSPORT: u32 = 1;
+pub const KIND_PROCESSOR: u32 = 2;
+
+const MANIFEST: &str = "plugin.json";
+const ENTRY_SYMBOL: &[u8] = b"bspt_plugin_v1\0";
+
+#[derive(Error, Debug)]
+pub enum PluginError {
+    #[error("Invalid plugin manifest: {0}")]
+    Invalid(String),
+    #[error("Plugin API version {0} is not supported (expected {API_VERSION})")]
+    Version(u32),
+    #[error("Failed to load plugin library: {0}")]
+    Library(String),
+    #[error("No plugin provides {kind} `{name}`")]
+    NotFound { kind: &'static str, name: String },
+    #[error("Plugin failed: {0}")]
+    Failed(String),
+    #[error("Connection closed")]
+    Closed,
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<PluginError> for String {
+    fn from(err: PluginError) -> String {
+        err.to_string()
+    }
+}
+
+/// Plugins a session uses, by the names their manifests declare
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginSelection {
+    /// Connects instead of `protocol`
+    pub transport: Option<String>,
+    /// Parses output instead of `dialect`
+    pub dialect: Option<String>,
+    /// Applied in order to output after charset decoding
+    pub processors: Vec<String>,
+}
+
+/// Connection over a protocol the app doesn't speak. Calls block; a
+/// session drives its transport from a thread of its own.
+pub trait Transport: Send {
+    fn connect(&mut self) -> Result<(), PluginError>;
+    /// Wait up to `timeout` for output: Ok(0) when none arrived,
+    /// `PluginError::Closed` once the device hung up
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, PluginError>;
+    fn write(&mut self, data: &[u8]) -> Result<(), PluginError>;
+    fn resize(&mut self, cols: u32, rows: u32) -> Result<(), PluginError>;
+}
+
+/// Pager and error handling of a vendor CLI, for the output parser
+pub trait Dialect: Send + Sync {
+    fn pagination(&self) -> Option<&Regex>;
+    /// Sent to page on when auto-pagination is on
+    fn pagination_response(&self) -> &[u8] {
+        b" "
+    }
+    /// Matches a line rejecting a command, group 1 being the message
+    fn command_error(&self) -> Option<&Regex>;
+}
+
+/// Rewrites decoded output before it is buffered, logged and parsed
+pub trait OutputProcessor: Send {
+    fn process(&mut self, data: Vec<u8>) -> Vec<u8>;
+}
+
+/// Output processors of a session, applied in order
+#[derive(Default)]
+pub struct ProcessorChain(Vec<Box<dyn OutputProcessor>>);
+
+impl ProcessorChain {
+    pub fn process(&mut self, data: Vec<u8>) -> Vec<u8> {
+        self.0
+            .iter_mut()
+            .fold(data, |data, processor| processor.process(data))
+    }
+}
+
+/// Bytes handed out by a library, released with `PluginVTable.free_buffer`
+#[repr(C)]
+pub struct PluginBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+/// Functions of a plugin library. Instances are opaque pointers returned by
+/// `create`; every function but `create` and `last_error` returns 0 on
+/// success. An instance is only used from one thread at a time, though not
+/// always the same one.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginVTable {
+    pub api_version: u32,
+    /// `kind` is `KIND_TRANSPORT` or `KIND_PROCESSOR`, `config` the session's
+    /// `SessionConfig` as JSON. Null on failure
+    pub create:
+        unsafe extern "C" fn(kind: u32, name: *const c_char, config: *const c_char) -> *mut c_void,
+    pub destroy: unsafe extern "C" fn(instance: *mut c_void),
+    pub connect: unsafe extern "C" fn(instance: *mut c_void) -> i32,
+    /// Bytes read, 0 on timeout, -1 once closed, below -1 on failure
+    pub read: unsafe extern "C" fn(
+        instance: *mut c_void,
+        buf: *mut u8,
+        len: usize,
+        timeout_ms: u32,
+    ) -> isize,
+    pub write: unsafe extern "C" fn(instance: *mut c_void, data: *const u8, len: usize) -> i32,
+    pub resize: unsafe extern "C" fn(instance: *mut c_void, cols: u32, rows: u32) -> i32,
+    pub process: unsafe extern "C" fn(
+        instance: *mut c_void,
+        data: *const u8,
+        len: usize,
+        out: *mut PluginBuffer,
+    ) -> i32,
+    pub free_buffer: unsafe extern "C" fn(buffer: PluginBuffer),
+    /// Message of the last failure of `instance`, or of `create` when it is
+    /// null. Valid until the next call; null when there is none
+    pub last_error: unsafe extern "C" fn(instance: *mut c_void) -> *const c_char,
+}
+
+type EntryFn = unsafe extern "C" fn() -> *const PluginVTable;
+
+/// A loaded library; instances keep it loaded
+struct PluginLibrary {
+    vtable: PluginVTable,
+    _library: libloading::Library,
+}
+
+impl PluginLibrary {
+    fn load(path: &Path) -> Result<Self, PluginError> {
+        // SAFETY: loading runs the library's initialisers; plugins are
+        // trusted like the app itself
+        unsafe {
+            let library = libloading::Library::new(path)
+                .map_err(|e| PluginError::Library(format!("{}: {}", path.display(), e)))?;
+            let entry = library
+                .get::<EntryFn>(ENTRY_SYMBOL)
+                .map_err(|e| PluginError::Library(e.to_string()))?;
+            let vtable = entry();
+            if vtable.is_null() {
+                return Err(PluginError::Library(
+                    "bspt_plugin_v1 returned null".to_string(),
+                ));
+            }
+            let vtable = *vtable;
+            if vtable.api_version != API_VERSION {
+                return Err(PluginError::Version(vtable.api_version));
+            }
+            Ok(Self {
+                vtable,
+                _library: library,
+            })
+        }
+    }
+
+    fn create(
+        self: &Arc<Self>,
+        kind: u32,
+        name: &str,
+        config: &str,
+    ) -> Result<Instance, PluginError> {
+        let name = CString::new(name).map_err(|e| PluginError::Failed(e.to_string()))?;
+        let config = CString::new(config).map_err(|e| PluginError::Failed(e.to_string()))?;
+        // SAFETY: both strings outlive the call
+        let ptr = unsafe { (self.vtable.create)(kind, name.as_ptr(), config.as_ptr()) };
+        let instance = Instance {
+            library: Arc::clone(self),
+            ptr,
+        };
+        if ptr.is_null() {
+            return Err(instance.error(-1));
+        }
+        Ok(instance)
+    }
+}
+
+/// A transport or processor created by a library
+struct Instance {
+    library: Arc<PluginLibrary>,
+    ptr: *mut c_void,
+}
+
+// SAFETY: the ABI allows instances to move between threads, and `&mut self`
+// on every use keeps them to one thread at a time
+unsafe impl Send for Instance {}
+
+impl Instance {
+    fn vtable(&self) -> &PluginVTable {
+        &self.library.vtable
+    }
+
+    /// The library's message for a failed call
+    fn error(&self, code: isize) -> PluginError {
+        // SAFETY: the message is copied before any other call
+        let message = unsafe {
+            let message = (self.vtable().last_error)(self.ptr);
+            (!message.is_null()).then(|| CStr::from_ptr(message).to_string_lossy().into_owned())
+        };
+        PluginError::Failed(message.unwrap_or_else(|| format!("error {}", code)))
+    }
+
+    fn check(&self, code: i32) -> Result<(), PluginError> {
+        match code {
+            0 => Ok(()),
+            code => Err(self.error(code as isize)),
+        }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            // SAFETY: created by this library and not used after
+            unsafe { (self.vtable().destroy)(self.ptr) };
+        }
+    }
+}
+
+struct LibraryTransport(Instance);
+
+impl Transport for LibraryTransport {
+    fn connect(&mut self) -> Result<(), PluginError> {
+        // SAFETY: the instance is valid until dropped
+        let code = unsafe { (self.0.vtable().connect)(self.0.ptr) };
+        self.0.check(code)
+    }
+
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, PluginError> {
+        let timeout_ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+        // SAFETY: the library writes at most `buf.len()` bytes
+        let n =
+            unsafe { (self.0.vtable().read)(self.0.ptr, buf.as_mut_ptr(), buf.len(), timeout_ms) };
+        match n {
+            n if n >= 0 => Ok((n as usize).min(buf.len())),
+            -1 => Err(PluginError::Closed),
+            n => Err(self.0.error(n)),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), PluginError> {
+        // SAFETY: `data` outlives the call
+        let code = unsafe { (self.0.vtable().write)(self.0.ptr, data.as_ptr(), data.len()) };
+        self.0.check(code)
+    }
+
+    fn resize(&mut self, cols: u32, rows: u32) -> Result<(), PluginError> {
+        // SAFETY: the instance is valid until dropped
+        let code = unsafe { (self.0.vtable().resize)(self.0.ptr, cols, rows) };
+        self.0.check(code)
+    }
+}
+
+struct LibraryProcessor {
+    instance: Instance,
+    name: String,
+}
+
+impl OutputProcessor for LibraryProcessor {
+    /// Output passes through unchanged when the library fails
+    fn process(&mut self, data: Vec<u8>) -> Vec<u8> {
+        let vtable = *self.instance.vtable();
+        let mut out = PluginBuffer {
+            data: std::ptr::null_mut(),
+            len: 0,
+            capacity: 0,
+        };
+        // SAFETY: `data` outlives the call; `out` is copied before it is
+        // handed back to the library
+        unsafe {
+            let code = (vtable.process)(self.instance.ptr, data.as_ptr(), data.len(), &mut out);
+            if code != 0 {
+                warn!(processor = %self.name, error = %self.instance.error(code as isize), "Output processor failed");
+                return data;
+            }
+            let processed = match out.data.is_null() {
+                true => Vec::new(),
+                false => std::slice::from_raw_parts(out.data, out.len).to_vec(),
+            };
+            (vtable.free_buffer)(out);
+            processed
+        }
+    }
+}
+
+/// Dialect declared in a manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DialectSpec {
+    pub name: String,
+    /// Pager prompt
+    #[serde(default)]
+    pub pagination: Option<String>,
+    #[serde(default = "default_pagination_response")]
+    pub pagination_response: String,
+    /// Line rejecting a command, group 1 being the message
+    #[serde(default)]
+    pub command_error: Option<String>,
+}
+
+fn default_pagination_response() -> String {
+    " ".to_string()
+}
+
+struct DeclaredDialect {
+    pagination: Option<Regex>,
+    pagination_response: Vec<u8>,
+    command_error: Option<Regex>,
+}
+
+impl DeclaredDialect {
+    fn compile(spec: &DialectSpec) -> Result<Self, PluginError> {
+        let compile = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| PluginError::Invalid(format!("dialect `{}`: {}", spec.name, e)))
+        };
+        let command_error = compile(&spec.command_error)?;
+        if command_error
+            .as_ref()
+            .is_some_and(|re| re.captures_len() < 2)
+        {
+            return Err(PluginError::Invalid(format!(
+                "dialect `{}`: command_error needs a capture group",
+                spec.name
+            )));
+        }
+        Ok(Self {
+            pagination: compile(&spec.pagination)?,
+            pagination_response: spec.pagination_response.clone().into_bytes(),
+            command_error,
+        })
+    }
+}
+
+impl Dialect for DeclaredDialect {
+    fn pagination(&self) -> Option<&Regex> {
+        self.pagination.as_ref()
+    }
+
+    fn pagination_response(&self) -> &[u8] {
+        &self.pagination_response
+    }
+
+    fn command_error(&self) -> Option<&Regex> {
+        self.command_error.as_ref()
+    }
+}
+
+/// `plugin.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: String,
+    pub api_version: u32,
+    /// Library file next to the manifest. A bare name gets the platform's
+    /// prefix and suffix: `acme` is `libacme.so`, `acme.dll` or `libacme.dylib`
+    #[serde(default)]
+    pub library: Option<String>,
+    /// Transports the library creates
+    #[serde(default)]
+    pub transports: Vec<String>,
+    /// Output processors the library creates
+    #[serde(default)]
+    pub processors: Vec<String>,
+    #[serde(default)]
+    pub dialects: Vec<DialectSpec>,
+}
+
+impl PluginManifest {
+    fn library_path(&self, dir: &Path) -> Option<PathBuf> {
+        let library = self.library.as_deref()?;
+        Some(match Path::new(library).extension() {
+            Some(_) => dir.join(library),
+            None => dir.join(libloading::library_filename(library)),
+        })
+    }
+}
+
+/// A plugin directory as loaded
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub version: String,
+    pub dir: PathBuf,
+    pub transports: Vec<String>,
+    pub processors: Vec<String>,
+    pub dialects: Vec<String>,
+    /// Why the plugin, or part of it, isn't available
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    plugins: Vec<PluginInfo>,
+    transports: HashMap<String, Arc<PluginLibrary>>,
+    processors: HashMap<String, Arc<PluginLibrary>>,
+    dialects: HashMap<String, Arc<DeclaredDialect>>,
+}
+
+/// Plugins found in the plugins directory
+#[derive(Default)]
+pub struct PluginRegistry {
+    loaded: RwLock<Loaded>,
+}
+
+impl PluginRegistry {
+    /// Replace the loaded plugins with those under `dir`. Sessions already
+    /// using a library keep it loaded until they end.
+    pub fn load_dir(&self, dir: &Path) -> Vec<PluginInfo> {
+        let mut loaded = Loaded::default();
+        let mut dirs: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.join(MANIFEST).is_file())
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(dir = %dir.display(), error = %e, "Failed to read plugins directory");
+                Vec::new()
+            }
+        };
+        dirs.sort();
+        for plugin_dir in dirs {
+            let info = loaded.add(&plugin_dir);
+            match &info.error {
+                Some(error) => {
+                    warn!(plugin = %info.name, dir = %plugin_dir.display(), error = %error, "Plugin not loaded")
+                }
+                None => info!(plugin = %info.name, version = %info.version, "Plugin loaded"),
+            }
+            loaded.plugins.push(info);
+        }
+        let plugins = loaded.plugins.clone();
+        *self.loaded.write().unwrap_or_else(|e| e.into_inner()) = loaded;
+        plugins
+    }
+
+    pub fn list(&self) -> Vec<PluginInfo> {
+        self.read().plugins.clone()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Loaded> {
+        self.loaded.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Fail when a session names a plugin that isn't loaded
+    pub fn check(&self, selection: &PluginSelection) -> Result<(), PluginError> {
+        let loaded = self.read();
+        let not_found = |kind, name: &String| {
+            Err(PluginError::NotFound {
+                kind,
+                name: name.clone(),
+            })
+        };
+        if let Some(name) = selection
+            .transport
+            .iter()
+            .find(|name| !loaded.transports.contains_key(*name))
+        {
+            return not_found("transport", name);
+        }
+        if let Some(name) = selection
+            .dialect
+            .iter()
+            .find(|name| !loaded.dialects.contains_key(*name))
+        {
+            return not_found("dialect", name);
+        }
+        if let Some(name) = selection
+            .processors
+            .iter()
+            .find(|name| !loaded.processors.contains_key(*name))
+        {
+            return not_found("output processor", name);
+        }
+        Ok(())
+    }
+
+    pub fn transport(
+        &self,
+        name: &str,
+        config: &SessionConfig,
+    ) -> Result<Box<dyn Transport>, PluginError> {
+        let library =
+            self.read()
+                .transports
+                .get(name)
+                .cloned()
+                .ok_or_else(|| PluginError::NotFound {
+                    kind: "transport",
+                    name: name.to_string(),
+                })?;
+        let config = serde_json::to_string(config)?;
+        let instance = library.create(KIND_TRANSPORT, name, &config)?;
+        Ok(Box::new(LibraryTransport(instance)))
+    }
+
+    pub fn processors(&self, names: &[String]) -> Result<ProcessorChain, PluginError> {
+        let mut chain = ProcessorChain::default();
+        for name in names {
+            let library =
+                self.read()
+                    .processors
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| PluginError::NotFound {
+                        kind: "output processor",
+                        name: name.clone(),
+                    })?;
+            let instance = library.create(KIND_PROCESSOR, name, "{}")?;
+            chain.0.push(Box::new(LibraryProcessor {
+                instance,
+                name: name.clone(),
+            }));
+        }
+        Ok(chain)
+    }
+
+    pub fn dialect(&self, name: &str) -> Result<Arc<dyn Dialect>, PluginError> {
+        let dialect = self.read().dialects.get(name).cloned();
+        dialect
+            .map(|dialect| dialect as Arc<dyn Dialect>)
+            .ok_or_else(|| PluginError::NotFound {
+                kind: "dialect",
+                name: name.to_string(),
+            })
+    }
+
+    /// Output parser of a session: its plugin dialect when it names one that
+    /// is loaded, else its built-in dialect
+    pub fn parser(&self, config: &SessionConfig) -> VrpParser {
+        let Some(name) = &config.plugins.dialect else {
+            return VrpParser::with_dialect(config.dialect);
+        };
+        match self.dialect(name) {
+            Ok(dialect) => VrpParser::with_plugin(dialect),
+            Err(e) => {
+                warn!(error = %e, "Falling back to the built-in dialect");
+                VrpParser::with_dialect(config.dialect)
+            }
+        }
+    }
+}
+
+impl Loaded {
+    /// Load one plugin directory; what loads is registered even when the
+    /// rest fails
+    fn add(&mut self, dir: &Path) -> PluginInfo {
+        let manifest = fs::read_to_string(dir.join(MANIFEST))
+            .map_err(PluginError::from)
+            .and_then(|json| Ok(serde_json::from_str::<PluginManifest>(&json)?));
+        let manifest = match manifest {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                return PluginInfo {
+                    name: dir
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    version: String::new(),
+                    dir: dir.to_path_buf(),
+                    transports: Vec::new(),
+                    processors: Vec::new(),
+                    dialects: Vec::new(),
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        let mut info = PluginInfo {
+            name: manifest.name.clone(),
+            version: manifest.version.clone(),
+            dir: dir.to_path_buf(),
+            transports: Vec::new(),
+            processors: Vec::new(),
+            dialects: Vec::new(),
+            error: None,
+        };
+        let mut errors = Vec::new();
+        if manifest.api_version != API_VERSION {
+            info.error = Some(PluginError::Version(manifest.api_version).to_string());
+            return info;
+        }
+
+        for spec in &manifest.dialects {
+            if self.dialects.contains_key(&spec.name) {
+                errors.push(format!("dialect `{}` is already provided", spec.name));
+                continue;
+            }
+            match DeclaredDialect::compile(spec) {
+                Ok(dialect) => {
+                    self.dialects.insert(spec.name.clone(), Arc::new(dialect));
+                    info.dialects.push(spec.name.clone());
+                }
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+
+        match manifest.library_path(dir) {
+            Some(path) => match PluginLibrary::load(&path) {
+                Ok(library) => {
+                    let library = Arc::new(library);
+                    for (names, registered, provided, kind) in [
+                        (
+                            &manifest.transports,
+                            &mut self.transports,
+                            &mut info.transports,
+                            "transport",
+                        ),
+                        (
+                            &manifest.processors,
+                            &mut self.processors,
+                            &mut info.processors,
+                            "output processor",
+                        ),
+                    ] {
+                        for name in names {
+                            if registered.contains_key(name) {
+                                errors.push(format!("{} `{}` is already provided", kind, name));
+                                continue;
+                            }
+                            registered.insert(name.clone(), Arc::clone(&library));
+                            provided.push(name.clone());
+                        }
+                    }
+                }
+                Err(e) => errors.push(e.to_string()),
+            },
+            None if !manifest.transports.is_empty() || !manifest.processors.is_empty() => {
+                errors.push("transports and processors need a library".to_string())
+            }
+            None => {}
+        }
+
+        if !errors.is_empty() {
+            info.error = Some(errors.join("; "));
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::LineAssembler;
+    use crate::profile::Profile;
+    use crate::session::Protocol;
+    use crate::vrp::VrpEvent;
+
+    #[test]
+    fn test_manifest_dialect() {
+        let dir = std::env::temp_dir().join(format!("bspt-plugin-test-{}", uuid::Uuid::new_v4()));
+        let write = |name: &str, manifest: &str| {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            fs::write(dir.join(name).join(MANIFEST), manifest).unwrap();
+        };
+        write(
+            "acme",
+            r#"{"name": "acme", "version": "1.0.0", "api_version": 1,
+                "dialects": [{"name": "acme-os", "pagination": "<--more-->",
+                              "pagination_response": "\n",
+                              "command_error": "^\\s*ERR:\\s*(.+)$"}]}"#,
+        );
+        write(
+            "broken",
+            r#"{"name": "broken", "version": "0.1.0", "api_version": 1,
+                "library": "missing", "transports": ["acme-mgmt"],
+                "dialects": [{"name": "nogroup", "command_error": "ERR"}]}"#,
+        );
+        write(
+            "future",
+            r#"{"name": "future", "version": "9.0.0", "api_version": 9}"#,
+        );
+
+        let registry = PluginRegistry::default();
+        let plugins = registry.load_dir(&dir);
+        let names: Vec<_> = plugins.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["acme", "broken", "future"]);
+        assert_eq!(plugins[0].dialects, ["acme-os"]);
+        assert!(plugins[0].error.is_none());
+        let error = plugins[1].error.as_deref().unwrap();
+        assert!(
+            error.contains("capture group") && error.contains("library"),
+            "{}",
+            error
+        );
+        assert!(plugins[1].transports.is_empty());
+        assert!(plugins[2].error.as_deref().unwrap().contains("version 9"));
+
+        let mut config = Profile::adhoc("10.0.0.1", 23, Protocol::Telnet).session_config(None);
+        assert!(matches!(
+            registry.transport("acme-mgmt", &config),
+            Err(PluginError::NotFound { .. })
+        ));
+
+        config.plugins.dialect = Some("acme-os".to_string());
+        let mut parser = registry.parser(&config);
+        let mut assembler = LineAssembler::new();
+        let lines = assembler.push(b"ERR: bad command\r\nline\r\n<--more-->");
+        let (events, response) = parser.parse(&lines, &mut assembler);
+        assert!(matches!(
+            &events[0],
+            VrpEvent::Pagination {
+                auto_handled: true,
+                ..
+            }
+        ));
+        assert!(
+            matches!(&events[1], VrpEvent::CommandError { message } if message == "bad command")
+        );
+        assert_eq!(response.as_deref(), Some(&b"\n"[..]));
+        assert_eq!(assembler.partial(), "");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
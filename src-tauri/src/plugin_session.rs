@@ -0,0 +1,298 @@
+//! Sessions over a plugin transport.
+//!
+//! The transport's calls block, so it runs on a thread of its own: the
+//! thread polls for output and forwards it to the session task, and takes
+//! writes and resizes from it between polls.
+
+use crate::activity::spawn_activity;
+use crate::capture::CaptureTap;
+use crate::emitter::spawn_emitter;
+use crate::latency;
+use crate::pipeline::{Pipeline, PipelineStages};
+use crate::plugin::{PluginError, ProcessorChain, Transport};
+use crate::ringbuffer::SessionRingBuffer;
+use crate::scrollback::Scrollback;
+use crate::session::{SessionConfig, SessionError, SessionHandle, SessionManager, SessionState};
+use crate::zmodem::{ZmodemCommand, ZmodemTap};
+use bytes::Bytes;
+use std::sync::{mpsc as std_mpsc, Arc};
+use std::time::Duration;
+use tauri::Emitter;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, error, info, warn};
+
+/// How long a read waits for output before queued writes are sent
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+enum TransportCommand {
+    Write(Vec<u8>),
+    Resize(u32, u32),
+}
+
+/// Drive a connected transport until it closes, fails or the session task
+/// hangs up
+fn drive(
+    mut transport: Box<dyn Transport>,
+    commands: std_mpsc::Receiver<TransportCommand>,
+    output: mpsc::Sender<Result<Vec<u8>, PluginError>>,
+) {
+    let mut read_buf = [0u8; 4096];
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(TransportCommand::Write(data)) => {
+                    if let Err(e) = transport.write(&data) {
+                        let _ = output.blocking_send(Err(e));
+                        return;
+                    }
+                }
+                Ok(TransportCommand::Resize(cols, rows)) => {
+                    if let Err(e) = transport.resize(cols, rows) {
+                        warn!(error = %e, "Plugin transport failed to resize");
+                    }
+                }
+                Err(std_mpsc::TryRecvError::Empty) => break,
+                Err(std_mpsc::TryRecvError::Disconnected) => return,
+            }
+        }
+        // A full channel blocks here, which stops reading while the session
+        // is paused by backpressure
+        let sent = match transport.read(&mut read_buf, POLL_INTERVAL) {
+            Ok(0) => continue,
+            Ok(n) => output.blocking_send(Ok(read_buf[..n].to_vec())),
+            Err(e) => {
+                let _ = output.blocking_send(Err(e));
+                return;
+            }
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+pub async fn run_plugin_session(
+    session_id: String,
+    config: SessionConfig,
+    manager: Arc<SessionManager>,
+    processors: ProcessorChain,
+) -> Result<(), SessionError> {
+    let app_handle = manager.app_handle().clone();
+    let name = config.plugins.transport.clone().unwrap_or_default();
+
+    // Create channels for communication
+    let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+    let (resize_tx, mut resize_rx) = mpsc::channel::<(u32, u32)>(16);
+    let (auto_pagination_tx, mut auto_pagination_rx) = mpsc::channel::<bool>(16);
+    let (drain_tx, drain_rx) = mpsc::channel::<()>(16);
+    let (zmodem_tx, mut zmodem_rx) = mpsc::channel::<ZmodemCommand>(4);
+
+    // Create ring buffer for backpressure
+    let buffer = SessionRingBuffer::new(session_id.clone(), config.buffer.unwrap_or_default())
+        .with_budget(Arc::clone(manager.memory_budget()));
+    let buffer = Arc::new(Mutex::new(buffer));
+    let scrollback = Arc::new(Mutex::new(Scrollback::default()));
+
+    // Emitter task drains the buffer to the frontend in coalesced chunks
+    let (emitter, backpressure) = spawn_emitter(
+        session_id.clone(),
+        app_handle.clone(),
+        Arc::clone(&buffer),
+        Arc::clone(&scrollback),
+        &config,
+        drain_rx,
+    );
+
+    // Raw byte capture, from the start when the config asks for it
+    let capture = CaptureTap::for_session(&session_id, &config);
+
+    // Store session handle
+    let stages = Arc::new(PipelineStages::default());
+    let handle = SessionHandle {
+        id: session_id.clone(),
+        config: config.clone(),
+        state: SessionState::Connecting,
+        input_tx,
+        shutdown_tx,
+        resize_tx,
+        auto_pagination_tx: Some(auto_pagination_tx),
+        break_tx: None,
+        buffer: Arc::clone(&buffer),
+        scrollback,
+        drain_tx,
+        backpressure: backpressure.clone(),
+        output: emitter.output(),
+        capture: capture.clone(),
+        zmodem_tx,
+        pipeline: Arc::clone(&stages),
+    };
+    manager.insert(handle);
+    spawn_activity(
+        session_id.clone(),
+        app_handle.clone(),
+        emitter.output().subscribe(),
+        config.activity.unwrap_or_default(),
+    );
+
+    emit_state(&app_handle, &session_id, SessionState::Connecting);
+    info!(session_id = %session_id, transport = %name, host = %config.host, "Connecting over plugin transport");
+
+    let connect_manager = Arc::clone(&manager);
+    let connect_config = config.clone();
+    let connected = tokio::task::spawn_blocking(move || {
+        let mut transport = connect_manager
+            .plugins()
+            .transport(&name, &connect_config)?;
+        transport.connect()?;
+        Ok::<_, PluginError>(transport)
+    })
+    .await
+    .unwrap_or_else(|e| Err(PluginError::Failed(e.to_string())));
+    let transport = match connected {
+        Ok(transport) => transport,
+        Err(e) => {
+            error!(session_id = %session_id, error = %e, "Plugin transport connection failed");
+            emit_state(&app_handle, &session_id, SessionState::Error);
+            manager.remove(&session_id);
+            return Err(SessionError::ConnectionFailed(e.to_string()));
+        }
+    };
+
+    emit_state(&app_handle, &session_id, SessionState::Connected);
+    emit_state(&app_handle, &session_id, SessionState::Ready);
+    info!(session_id = %session_id, "Plugin session ready");
+    manager.history().connected(&session_id, &config);
+
+    let (command_tx, command_rx) = std_mpsc::channel();
+    let (output_tx, mut output_rx) = mpsc::channel(64);
+    std::thread::spawn(move || drive(transport, command_rx, output_tx));
+
+    let mut pipeline = Pipeline::new(&session_id, &config, &manager, processors, stages);
+    let mut zmodem = ZmodemTap::new(&session_id, &app_handle, false);
+
+    // Backpressure state, shared with the emitter
+    let mut pause_rx = backpressure.subscribe();
+
+    loop {
+        let is_paused = *pause_rx.borrow();
+        let zmodem_deadline = zmodem.deadline();
+        tokio::select! {
+            // Output from the transport thread (only if not paused due to backpressure)
+            received = output_rx.recv(), if !is_paused => {
+                let data = match received {
+                    Some(Ok(data)) => data,
+                    Some(Err(PluginError::Closed)) | None => {
+                        info!(session_id = %session_id, "Plugin transport closed");
+                        break;
+                    }
+                    Some(Err(e)) => {
+                        error!(session_id = %session_id, error = %e, "Plugin transport failed");
+                        break;
+                    }
+                };
+                capture.inbound(&data);
+                let (data, reply) = zmodem.inbound(data);
+                if !reply.is_empty() {
+                    capture.outbound(&reply);
+                    let _ = command_tx.send(TransportCommand::Write(reply));
+                }
+                if data.is_empty() {
+                    continue;
+                }
+                let processed = pipeline.process(data);
+                if let Some(response) = processed.auto_response {
+                    capture.outbound(&response);
+                    let _ = command_tx.send(TransportCommand::Write(response));
+                }
+
+                let data = processed.data;
+                if !data.is_empty() {
+                    debug!(session_id = %session_id, bytes = data.len(), "Received data from plugin transport");
+                    let mut buf = buffer.lock().await;
+                    buf.push_bytes(Bytes::from(data));
+                    backpressure.update(&buf);
+                    drop(buf);
+                    emitter.wake();
+                    latency::on_received(&manager, &session_id);
+                }
+            }
+
+            // Pause state changed, re-evaluate the read guard
+            Ok(()) = pause_rx.changed() => {}
+
+            // Handle input from frontend
+            Some(data) = input_rx.recv() => {
+                // Keystrokes would corrupt a running transfer
+                if zmodem.active() {
+                    continue;
+                }
+                debug!(session_id = %session_id, bytes = data.len(), "Sending data to plugin transport");
+                let data = config.newline.translate(data);
+                capture.outbound(&data);
+                if command_tx.send(TransportCommand::Write(data)).is_err() {
+                    break;
+                }
+            }
+
+            // Answer to a ZMODEM offer
+            Some(command) = zmodem_rx.recv() => {
+                let reply = zmodem.command(command);
+                capture.outbound(&reply);
+                if command_tx.send(TransportCommand::Write(reply)).is_err() {
+                    break;
+                }
+            }
+
+            // ZMODEM peer gone quiet, or no answer to the offer
+            _ = sleep_until(zmodem_deadline.unwrap_or_else(Instant::now)), if zmodem_deadline.is_some() => {
+                let reply = zmodem.expire();
+                capture.outbound(&reply);
+                if command_tx.send(TransportCommand::Write(reply)).is_err() {
+                    break;
+                }
+            }
+
+            // Handle resize requests
+            Some((cols, rows)) = resize_rx.recv() => {
+                debug!(session_id = %session_id, cols = cols, rows = rows, "Resizing terminal");
+                let _ = command_tx.send(TransportCommand::Resize(cols, rows));
+            }
+
+            // Handle auto-pagination toggle
+            Some(enabled) = auto_pagination_rx.recv() => {
+                debug!(session_id = %session_id, enabled = enabled, "Setting auto-pagination");
+                pipeline.set_auto_pagination(enabled);
+            }
+
+            // Handle shutdown request
+            _ = shutdown_rx.recv() => {
+                info!(session_id = %session_id, "Shutdown requested");
+                break;
+            }
+        }
+    }
+
+    // Cleanup: the transport thread ends, dropping the transport, once it
+    // sees the command channel closed
+    info!(session_id = %session_id, "Plugin session ending");
+    drop(command_tx);
+    emitter.close().await;
+    emit_state(&app_handle, &session_id, SessionState::Disconnected);
+    manager.history().disconnected(&session_id);
+    manager.remove(&session_id);
+
+    Ok(())
+}
+
+fn emit_state(app_handle: &tauri::AppHandle, session_id: &str, state: SessionState) {
+    let event_name = format!("session:{}:state", session_id);
+    if let Err(e) = app_handle.emit(&event_name, state) {
+        error!(
+            session_id = %session_id,
+            error = %e,
+            "Failed to emit state event"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Reachability sweep over a list of hosts.
+//!
+//! Each host gets a TCP connect to its management ports (SSH and telnet by
+//! default) and optionally one ping through the system `ping` binary, since
+//! raw ICMP sockets need privileges. Hosts are probed concurrently so a lab
+//! full of powered-off gear is swept in one connect timeout.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+pub const DEFAULT_PORTS: [u16; 2] = [22, 23];
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_CONCURRENCY: usize = 64;
+const MAX_CONCURRENCY: usize = 256;
+
+/// `time=0.412 ms` (Linux, macOS) or `time<1ms` (Windows)
+static PING_TIME_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"time[=<]\s*([\d.]+)\s*ms").unwrap());
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PrecheckOptions {
+    /// Default `DEFAULT_PORTS`
+    #[serde(default)]
+    pub ports: Option<Vec<u16>>,
+    #[serde(default)]
+    pub ping: bool,
+    /// Per probe; default `DEFAULT_PROBE_TIMEOUT`
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Hosts probed at once; default 64, max 256
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortProbe {
+    pub port: u16,
+    pub open: bool,
+    /// Connect time of an open port
+    pub latency_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostReachability {
+    pub host: String,
+    /// Some port is open or the ping was answered
+    pub reachable: bool,
+    pub ports: Vec<PortProbe>,
+    /// Round trip of the ping, when asked for and answered
+    pub ping_ms: Option<f64>,
+}
+
+/// Probe every host; results keep the order of `hosts`
+pub async fn sweep(hosts: &[String], options: &PrecheckOptions) -> Vec<HostReachability> {
+    let ports: Arc<[u16]> = options
+        .ports
+        .clone()
+        .filter(|ports| !ports.is_empty())
+        .unwrap_or_else(|| DEFAULT_PORTS.to_vec())
+        .into();
+    let timeout = options
+        .timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_PROBE_TIMEOUT);
+    let concurrency = options
+        .concurrency
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .clamp(1, MAX_CONCURRENCY);
+    let permits = Arc::new(Semaphore::new(concurrency));
+
+    let mut tasks = JoinSet::new();
+    for (index, host) in hosts.iter().enumerate() {
+        let host = host.trim().to_string();
+        let ports = Arc::clone(&ports);
+        let permits = Arc::clone(&permits);
+        let ping = options.ping;
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await.expect("semaphore not closed");
+            (index, probe_host(host, &ports, ping, timeout).await)
+        });
+    }
+
+    let mut results: Vec<Option<HostReachability>> = vec![None; hosts.len()];
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((index, result)) = joined {
+            results[index] = Some(result);
+        }
+    }
+    let results: Vec<HostReachability> = results.into_iter().flatten().collect();
+    info!(
+        hosts = results.len(),
+        reachable = results.iter().filter(|r| r.reachable).count(),
+        "Reachability sweep finished"
+    );
+    results
+}
+
+async fn probe_host(
+    host: String,
+    ports: &[u16],
+    ping: bool,
+    timeout: Duration,
+) -> HostReachability {
+    let mut probes = JoinSet::new();
+    for (index, &port) in ports.iter().enumerate() {
+        let host = host.clone();
+        probes.spawn(async move { (index, probe_port(&host, port, timeout).await) });
+    }
+    let ping_ms = if ping {
+        ping_host(&host, timeout).await
+    } else {
+        None
+    };
+    let mut results: Vec<Option<PortProbe>> = vec![None; ports.len()];
+    while let Some(joined) = probes.join_next().await {
+        if let Ok((index, probe)) = joined {
+            results[index] = Some(probe);
+        }
+    }
+    let ports: Vec<PortProbe> = results.into_iter().flatten().collect();
+    HostReachability {
+        reachable: ping_ms.is_some() || ports.iter().any(|p| p.open),
+        host,
+        ports,
+        ping_ms,
+    }
+}
+
+/// TCP connect to `host:port` within `timeout`
+pub async fn probe_port(host: &str, port: u16, timeout: Duration) -> PortProbe {
+    let started = Instant::now();
+    let error = match tokio::time::timeout(timeout, TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some("timed out".to_string()),
+    };
+    debug!(host, port, error = ?error, "Probed port");
+    PortProbe {
+        port,
+        open: error.is_none(),
+        latency_ms: error
+            .is_none()
+            .then(|| started.elapsed().as_secs_f64() * 1000.0),
+        error,
+    }
+}
+
+/// One echo request through the system `ping`; None when unanswered or
+/// `ping` is unavailable
+async fn ping_host(host: &str, timeout: Duration) -> Option<f64> {
+    // A host starting with `-` would be read as an option
+    if host.is_empty() || host.starts_with('-') {
+        return None;
+    }
+    let mut command = tokio::process::Command::new("ping");
+    if cfg!(windows) {
+        command.args(["-n", "1", "-w", &timeout.as_millis().to_string()]);
+    } else {
+        let secs = timeout.as_secs().max(1).to_string();
+        command.args(["-c", "1", "-W", &secs]);
+    }
+    command.arg(host).kill_on_drop(true);
+    // The binary's own timeout may be rounded up to whole seconds
+    let output = tokio::time::timeout(timeout + Duration::from_secs(1), command.output())
+        .await
+        .ok()?
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_ping_time(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_ping_time(output: &str) -> Option<f64> {
+    PING_TIME_RE
+        .captures(output)
+        .and_then(|caps| caps[1].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_sweep_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        // A port that was just free is almost certainly still closed
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let options = PrecheckOptions {
+            ports: Some(vec![open, closed]),
+            timeout_ms: Some(500),
+            ..Default::default()
+        };
+        let results = sweep(&["127.0.0.1".to_string()], &options).await;
+        assert_eq!(results.len(), 1);
+        let host = &results[0];
+        assert!(host.reachable);
+        assert!(host.ports[0].open && host.ports[0].latency_ms.is_some());
+        assert!(!host.ports[1].open && host.ports[1].error.is_some());
+        assert_eq!(host.ping_ms, None);
+
+        assert_eq!(
+            parse_ping_time("64 bytes from 10.0.0.1: icmp_seq=1 ttl=64 time=0.412 ms"),
+            Some(0.412)
+        );
+        assert_eq!(
+            parse_ping_time("Reply from 10.0.0.1: bytes=32 time<1ms TTL=128"),
+            Some(1.0)
+        );
+        assert_eq!(parse_ping_time("Request timed out."), None);
+    }
+}
@@ -0,0 +1,915 @@
+use crate::charset::Charset;
+use crate::plugin::PluginSelection;
+use crate::session::{
+    DeviceDialect, NewlineMode, PayloadEncoding, Protocol, SessionConfig, SshAuth,
+    DEFAULT_TERMINAL_TYPE,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Version written to `profiles.json`
+const STORE_VERSION: u32 = 1;
+
+/// Longest jump chain `ProfileStore::jump_chain` follows
+const MAX_JUMP_DEPTH: usize = 8;
+
+/// Results returned by `search_profiles` when no limit is given
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+/// Score added to favorites in `ProfileStore::search`
+const FAVORITE_BONUS: i64 = 15;
+
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("Profile not found: {0}")]
+    NotFound(String),
+    #[error("Invalid profile: {0}")]
+    Invalid(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<ProfileError> for String {
+    fn from(err: ProfileError) -> String {
+        err.to_string()
+    }
+}
+
+/// Terminal settings applied when a profile connects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminalSettings {
+    /// Sent in the SSH PTY request and telnet TERMINAL-TYPE negotiation
+    pub terminal_type: String,
+    /// Initial size, until the frontend resizes
+    pub cols: u32,
+    pub rows: u32,
+    pub charset: Charset,
+    pub newline: NewlineMode,
+    pub payload_encoding: PayloadEncoding,
+}
+
+impl Default for TerminalSettings {
+    fn default() -> Self {
+        Self {
+            terminal_type: DEFAULT_TERMINAL_TYPE.to_string(),
+            cols: 80,
+            rows: 24,
+            charset: Charset::default(),
+            newline: NewlineMode::default(),
+            payload_encoding: PayloadEncoding::default(),
+        }
+    }
+}
+
+/// Session logging that starts automatically when a profile connects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingPolicy {
+    /// Log path with placeholders `{name}`, `{folder}`, `{host}`, `{date}`
+    /// (`2024-05-01`) and `{time}` (`143005`), expanded at connect time
+    pub path_template: String,
+    /// Prefix each logged line with its arrival time
+    #[serde(default)]
+    pub timestamps: bool,
+}
+
+impl LoggingPolicy {
+    fn log_path(&self, profile: &Profile) -> String {
+        let now = chrono::Local::now();
+        self.path_template
+            .replace("{name}", &path_safe(&profile.name))
+            .replace("{folder}", &profile.folder)
+            .replace("{host}", &path_safe(&profile.host))
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{time}", &now.format("%H%M%S").to_string())
+    }
+}
+
+/// Replace characters that can't appear in a file name component
+fn path_safe(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Saved connection details for one device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    /// Assigned on first save when empty
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    /// Slash-separated folder path, e.g. `Lab/Core`; empty for the top level
+    #[serde(default)]
+    pub folder: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub favorite: bool,
+    pub host: String,
+    pub port: u16,
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub username: String,
+    /// Omitted to ask for it on connect
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Never store a password; `connect_profile` asks the frontend for
+    /// credentials every time (rotating TACACS passwords, one-time tokens)
+    #[serde(default)]
+    pub prompt_credentials: bool,
+    /// Private key path for SSH; when set, sessions authenticate with it
+    /// instead of the password
+    #[serde(default)]
+    pub identity_file: Option<String>,
+    /// OpenSSH `ProxyJump` spec (`[user@]host[:port]`, comma separated);
+    /// not used to connect yet
+    #[serde(default)]
+    pub proxy_jump: Option<String>,
+    /// Id of an SSH profile to tunnel through; that profile may have its
+    /// own jump profile
+    #[serde(default)]
+    pub jump_profile: Option<String>,
+    /// See `SessionConfig.connect_timeout_ms`; None uses the default
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub terminal: TerminalSettings,
+    #[serde(default)]
+    pub dialect: DeviceDialect,
+    #[serde(default)]
+    pub plugins: PluginSelection,
+    /// Log every session of this profile, None leaves it to the global
+    /// settings
+    #[serde(default)]
+    pub logging: Option<LoggingPolicy>,
+    /// Unix time in milliseconds
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+impl Profile {
+    /// An unsaved profile for a bare host (batch CSV rows, `bspt-cli --host`)
+    pub fn adhoc(host: &str, port: u16, protocol: Protocol) -> Self {
+        Self {
+            id: String::new(),
+            name: host.to_string(),
+            folder: String::new(),
+            tags: Vec::new(),
+            favorite: false,
+            host: host.to_string(),
+            port,
+            protocol,
+            username: String::new(),
+            password: None,
+            prompt_credentials: false,
+            identity_file: None,
+            proxy_jump: None,
+            jump_profile: None,
+            connect_timeout_ms: None,
+            terminal: TerminalSettings::default(),
+            dialect: DeviceDialect::default(),
+            logging: None,
+            plugins: Default::default(),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ProfileError> {
+        if self.name.trim().is_empty() {
+            return Err(ProfileError::Invalid("name is empty".to_string()));
+        }
+        if self.host.trim().is_empty() {
+            return Err(ProfileError::Invalid("host is empty".to_string()));
+        }
+        if self.port == 0 {
+            return Err(ProfileError::Invalid("port must be non-zero".to_string()));
+        }
+        if self.terminal.terminal_type.trim().is_empty() {
+            return Err(ProfileError::Invalid("terminal type is empty".to_string()));
+        }
+        if self.terminal.cols == 0 || self.terminal.rows == 0 {
+            return Err(ProfileError::Invalid(
+                "terminal size must be non-zero".to_string(),
+            ));
+        }
+        if let Some(logging) = &self.logging {
+            if logging.path_template.trim().is_empty() {
+                return Err(ProfileError::Invalid("log path is empty".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// `folder/name`, or just the name at the top level
+    pub fn path(&self) -> String {
+        if self.folder.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}/{}", self.folder, self.name)
+        }
+    }
+
+    /// Trim the folder path and tags so they compare cleanly
+    fn normalize(&mut self) {
+        if self.prompt_credentials {
+            self.password = None;
+        }
+        self.folder = self
+            .folder
+            .split('/')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let mut tags: Vec<String> = Vec::new();
+        for tag in &self.tags {
+            let tag = tag.trim();
+            if !tag.is_empty() && !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+                tags.push(tag.to_string());
+            }
+        }
+        self.tags = tags;
+    }
+
+    /// Best fuzzy score of `term` against the name, tags, host and folder
+    fn match_term(&self, term: &str) -> Option<i64> {
+        let name = fuzzy_score(term, &self.name).map(|s| s * 2);
+        let tags = self.tags.iter().filter_map(|t| fuzzy_score(term, t)).max();
+        let host = fuzzy_score(term, &self.host);
+        let folder = fuzzy_score(term, &self.folder);
+        [name, tags, host, folder].into_iter().flatten().max()
+    }
+
+    /// Key authentication with `identity_file` for SSH, else password
+    pub fn ssh_auth(&self) -> SshAuth {
+        match &self.identity_file {
+            Some(path) if self.protocol == Protocol::Ssh => SshAuth::PublicKey {
+                path: Some(path.clone()),
+                pem: None,
+                passphrase: None,
+            },
+            _ => SshAuth::Password,
+        }
+    }
+
+    /// Session config for connecting this profile. `password` overrides the
+    /// saved one.
+    pub fn session_config(&self, password: Option<String>) -> SessionConfig {
+        SessionConfig {
+            host: self.host.clone(),
+            port: self.port,
+            protocol: self.protocol,
+            username: self.username.clone(),
+            password: password
+                .or_else(|| self.password.clone())
+                .unwrap_or_default(),
+            auth: self.ssh_auth(),
+            cols: self.terminal.cols,
+            rows: self.terminal.rows,
+            payload_encoding: self.terminal.payload_encoding,
+            buffer: None,
+            charset: self.terminal.charset,
+            rate_limit: None,
+            delivery: None,
+            log_file: self.logging.as_ref().map(|l| l.log_path(self)),
+            log_timestamps: self.logging.as_ref().is_some_and(|l| l.timestamps),
+            capture_file: None,
+            keepalive: None,
+            activity: None,
+            terminal_type: Some(self.terminal.terminal_type.clone()),
+            newline: self.terminal.newline,
+            dialect: self.dialect,
+            jump_hosts: Vec::new(),
+            algorithms: None,
+            hardening: None,
+            connect_timeout_ms: self.connect_timeout_ms,
+            auth_methods: Vec::new(),
+            subsystem: None,
+            env: HashMap::new(),
+            plugins: self.plugins.clone(),
+        }
+    }
+}
+
+/// What to do with an imported profile whose name is already taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Keep the existing profile and report the import as skipped
+    #[default]
+    Skip,
+    /// Replace the existing profile, keeping its id
+    Overwrite,
+    /// Import under a free name such as `core-1 (2)`
+    Rename,
+}
+
+/// A source entry that was skipped or only partly imported
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportIssue {
+    /// Entry in the source, e.g. a session file or Host block
+    pub source: String,
+    pub message: String,
+}
+
+/// Outcome of importing profiles from another tool
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: Vec<Profile>,
+    pub skipped: Vec<ImportIssue>,
+    /// Settings that could not be carried over for imported profiles
+    pub warnings: Vec<ImportIssue>,
+}
+
+/// On-disk layout of `profiles.json`
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+    version: u32,
+    profiles: Vec<Profile>,
+}
+
+/// Connection profiles persisted as JSON in the app data directory.
+///
+/// Every change is written straight away via a temp file and rename, so a
+/// crash never leaves a half-written store behind.
+pub struct ProfileStore {
+    path: PathBuf,
+    profiles: Vec<Profile>,
+}
+
+impl ProfileStore {
+    /// Load the store at `path`. A missing file is an empty store; an
+    /// unreadable one is moved aside to `*.bak` so it isn't overwritten.
+    pub fn load(path: PathBuf) -> Self {
+        let profiles = match std::fs::read(&path) {
+            Ok(data) => match serde_json::from_slice::<StoreFile>(&data) {
+                Ok(file) => file.profiles,
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Corrupt profile store, starting empty");
+                    if let Err(e) = std::fs::rename(&path, path.with_extension("json.bak")) {
+                        warn!(error = %e, "Failed to back up corrupt profile store");
+                    }
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Failed to read profile store");
+                Vec::new()
+            }
+        };
+
+        info!(path = %path.display(), count = profiles.len(), "Loaded connection profiles");
+        Self { path, profiles }
+    }
+
+    #[cfg(test)]
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// All profiles, sorted by folder then name.
+    pub fn list(&self) -> Vec<Profile> {
+        let mut profiles = self.profiles.clone();
+        profiles.sort_by_key(|p| (p.folder.to_lowercase(), p.name.to_lowercase()));
+        profiles
+    }
+
+    /// Quick-connect search. Every whitespace-separated term must fuzzy-match
+    /// the name, a tag, the host or the folder; best matches come first and
+    /// favorites are ranked up. An empty query lists favorites first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<Profile> {
+        let terms: Vec<_> = query.split_whitespace().collect();
+        let mut scored: Vec<(i64, &Profile)> = self
+            .profiles
+            .iter()
+            .filter_map(|profile| {
+                let score: i64 = terms
+                    .iter()
+                    .map(|term| profile.match_term(term))
+                    .sum::<Option<i64>>()?;
+                let bonus = if profile.favorite { FAVORITE_BONUS } else { 0 };
+                Some((score + bonus, profile))
+            })
+            .collect();
+
+        scored.sort_by(|(a, pa), (b, pb)| {
+            b.cmp(a)
+                .then_with(|| pa.name.to_lowercase().cmp(&pb.name.to_lowercase()))
+        });
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, p)| p.clone())
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.id == id)
+    }
+
+    /// Jump profiles `profile` is reached through, outermost first. Fails on
+    /// a missing or non-SSH jump profile and on cycles.
+    pub fn jump_chain(&self, profile: &Profile) -> Result<Vec<&Profile>, ProfileError> {
+        let mut chain: Vec<&Profile> = Vec::new();
+        let mut next = profile.jump_profile.as_deref();
+        while let Some(id) = next {
+            if id == profile.id || chain.iter().any(|p| p.id == id) {
+                return Err(ProfileError::Invalid(format!(
+                    "jump chain of {} loops back to {}",
+                    profile.name, id
+                )));
+            }
+            if chain.len() == MAX_JUMP_DEPTH {
+                return Err(ProfileError::Invalid(format!(
+                    "jump chain of {} is longer than {}",
+                    profile.name, MAX_JUMP_DEPTH
+                )));
+            }
+            let jump = self
+                .get(id)
+                .ok_or_else(|| ProfileError::NotFound(id.to_string()))?;
+            if jump.protocol != Protocol::Ssh {
+                return Err(ProfileError::Invalid(format!(
+                    "jump profile {} is not SSH",
+                    jump.name
+                )));
+            }
+            chain.push(jump);
+            next = jump.jump_profile.as_deref();
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// Create a profile (empty or unknown id) or replace an existing one.
+    pub fn save(&mut self, mut profile: Profile) -> Result<Profile, ProfileError> {
+        profile.validate()?;
+        profile.normalize();
+        self.jump_chain(&profile)?;
+        let now = now_ms();
+        profile.updated_at = now;
+
+        match self.profiles.iter_mut().find(|p| p.id == profile.id) {
+            Some(existing) if !profile.id.is_empty() => {
+                profile.created_at = existing.created_at;
+                *existing = profile.clone();
+            }
+            _ => {
+                if profile.id.is_empty() {
+                    profile.id = Uuid::new_v4().to_string();
+                }
+                profile.created_at = now;
+                self.profiles.push(profile.clone());
+            }
+        }
+
+        self.persist()?;
+        Ok(profile)
+    }
+
+    /// Add imported profiles in one write, resolving name clashes with
+    /// existing profiles according to `policy`.
+    pub fn import(
+        &mut self,
+        profiles: Vec<Profile>,
+        policy: ConflictPolicy,
+    ) -> Result<ImportReport, ProfileError> {
+        let mut report = ImportReport::default();
+        let now = now_ms();
+
+        for mut profile in profiles {
+            if let Err(e) = profile.validate() {
+                report.skipped.push(ImportIssue {
+                    source: profile.path(),
+                    message: e.to_string(),
+                });
+                continue;
+            }
+            profile.normalize();
+            profile.created_at = now;
+            profile.updated_at = now;
+
+            match self.position_by_name(&profile.folder, &profile.name) {
+                None => {
+                    profile.id = Uuid::new_v4().to_string();
+                    self.profiles.push(profile.clone());
+                }
+                Some(_) if policy == ConflictPolicy::Skip => {
+                    report.skipped.push(ImportIssue {
+                        source: profile.path(),
+                        message: "a profile with this name already exists".to_string(),
+                    });
+                    continue;
+                }
+                Some(index) if policy == ConflictPolicy::Overwrite => {
+                    let existing = &mut self.profiles[index];
+                    profile.id = existing.id.clone();
+                    profile.created_at = existing.created_at;
+                    *existing = profile.clone();
+                }
+                Some(_) => {
+                    profile.name = self.free_name(&profile.folder, &profile.name);
+                    profile.id = Uuid::new_v4().to_string();
+                    self.profiles.push(profile.clone());
+                }
+            }
+            report.imported.push(profile);
+        }
+
+        if !report.imported.is_empty() {
+            self.persist()?;
+        }
+        Ok(report)
+    }
+
+    /// Names are unique within a folder, compared case-insensitively
+    fn position_by_name(&self, folder: &str, name: &str) -> Option<usize> {
+        self.profiles.iter().position(|p| {
+            p.folder.eq_ignore_ascii_case(folder) && p.name.eq_ignore_ascii_case(name)
+        })
+    }
+
+    fn free_name(&self, folder: &str, name: &str) -> String {
+        (2..)
+            .map(|n| format!("{name} ({n})"))
+            .find(|candidate| self.position_by_name(folder, candidate).is_none())
+            .expect("unbounded range")
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<(), ProfileError> {
+        let index = self
+            .profiles
+            .iter()
+            .position(|p| p.id == id)
+            .ok_or_else(|| ProfileError::NotFound(id.to_string()))?;
+        self.profiles.remove(index);
+        self.persist()
+    }
+
+    fn persist(&self) -> Result<(), ProfileError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = StoreFile {
+            version: STORE_VERSION,
+            profiles: self.profiles.clone(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&file)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Subsequence match of `query` in `text`, ignoring case. Each matched
+/// character scores, with bonuses for runs and word starts and a penalty for
+/// skipped characters; a plain substring or prefix match scores highest.
+fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let query = query.to_lowercase();
+    let text = text.to_lowercase();
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let mut wanted = query.chars().peekable();
+    let mut score = 0;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    let mut started = false;
+    for c in text.chars() {
+        let Some(&q) = wanted.peek() else {
+            break;
+        };
+        if c == q {
+            let word_start = prev.is_none_or(|p| !p.is_alphanumeric());
+            score += 1 + if prev_matched { 5 } else { 0 } + if word_start { 8 } else { 0 };
+            wanted.next();
+            prev_matched = true;
+            started = true;
+        } else {
+            if started {
+                score -= 1;
+            }
+            prev_matched = false;
+        }
+        prev = Some(c);
+    }
+    if wanted.peek().is_some() {
+        return None;
+    }
+
+    if text.starts_with(&query) {
+        score += 30;
+    } else if text.contains(&query) {
+        score += 20;
+    }
+    Some(score)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store() -> (PathBuf, ProfileStore) {
+        let dir = std::env::temp_dir().join(format!("bspt-profiles-test-{}", Uuid::new_v4()));
+        let path = dir.join("profiles.json");
+        (dir, ProfileStore::load(path))
+    }
+
+    fn router(name: &str) -> Profile {
+        Profile {
+            id: String::new(),
+            name: name.to_string(),
+            folder: String::new(),
+            tags: Vec::new(),
+            favorite: false,
+            host: "192.168.1.1".to_string(),
+            port: 23,
+            protocol: Protocol::Telnet,
+            username: "admin".to_string(),
+            password: None,
+            prompt_credentials: false,
+            identity_file: None,
+            proxy_jump: None,
+            jump_profile: None,
+            connect_timeout_ms: None,
+            terminal: TerminalSettings::default(),
+            dialect: DeviceDialect::Vrp,
+            logging: None,
+            plugins: Default::default(),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_crud_round_trip() {
+        let (dir, mut store) = temp_store();
+        let core = store.save(router("core-1")).unwrap();
+        assert!(!core.id.is_empty());
+        store.save(router("Access-2")).unwrap();
+
+        let mut renamed = core.clone();
+        renamed.name = "core-01".to_string();
+        let renamed = store.save(renamed).unwrap();
+        assert_eq!(renamed.id, core.id);
+        assert_eq!(renamed.created_at, core.created_at);
+
+        // Reloaded from disk, sorted by name
+        let store = ProfileStore::load(store.path().to_path_buf());
+        let names: Vec<_> = store.list().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, ["Access-2", "core-01"]);
+
+        let mut store = store;
+        store.delete(&core.id).unwrap();
+        assert!(store.get(&core.id).is_none());
+        assert!(matches!(
+            store.delete(&core.id),
+            Err(ProfileError::NotFound(_))
+        ));
+
+        // Prompting profiles never keep a password
+        let mut tacacs = router("tacacs-1");
+        tacacs.password = Some("rotated".to_string());
+        tacacs.prompt_credentials = true;
+        assert!(store.save(tacacs).unwrap().password.is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_validation_and_corrupt_file() {
+        let (dir, mut store) = temp_store();
+        let mut invalid = router("r1");
+        invalid.host = " ".to_string();
+        assert!(matches!(store.save(invalid), Err(ProfileError::Invalid(_))));
+        let mut invalid = router("r1");
+        invalid.terminal.rows = 0;
+        assert!(matches!(store.save(invalid), Err(ProfileError::Invalid(_))));
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(store.path(), b"{ not json").unwrap();
+        let store = ProfileStore::load(store.path().to_path_buf());
+        assert!(store.list().is_empty());
+        assert!(dir.join("profiles.json.bak").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_conflicts() {
+        let (dir, mut store) = temp_store();
+        let existing = store.save(router("core-1")).unwrap();
+
+        let mut no_host = router("core-2");
+        no_host.host.clear();
+        let report = store
+            .import(vec![router("CORE-1"), no_host], ConflictPolicy::Skip)
+            .unwrap();
+        assert!(report.imported.is_empty());
+        assert_eq!(report.skipped.len(), 2);
+
+        let report = store
+            .import(vec![router("core-1")], ConflictPolicy::Rename)
+            .unwrap();
+        assert_eq!(report.imported[0].name, "core-1 (2)");
+
+        let mut moved = router("core-1");
+        moved.host = "10.0.0.1".to_string();
+        let report = store
+            .import(vec![moved], ConflictPolicy::Overwrite)
+            .unwrap();
+        assert_eq!(report.imported[0].id, existing.id);
+
+        let store = ProfileStore::load(store.path().to_path_buf());
+        assert_eq!(store.list().len(), 2);
+        assert_eq!(store.get(&existing.id).unwrap().host, "10.0.0.1");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_folders_and_search() {
+        let (dir, mut store) = temp_store();
+        let mut core = router("core-router-7");
+        core.folder = " Lab / Core/ ".to_string();
+        core.tags = vec!["ne40e".to_string(), " NE40E ".to_string(), "".to_string()];
+        let core = store.save(core).unwrap();
+        assert_eq!(core.folder, "Lab/Core");
+        assert_eq!(core.tags, ["ne40e"]);
+
+        // Same name in another folder is not a conflict
+        let mut other = router("core-router-7");
+        other.folder = "Prod".to_string();
+        let report = store.import(vec![other], ConflictPolicy::Skip).unwrap();
+        assert_eq!(report.imported.len(), 1);
+
+        let mut bastion = router("lab-bastion");
+        bastion.host = "10.20.0.1".to_string();
+        bastion.favorite = true;
+        store.save(bastion).unwrap();
+
+        let names = |results: Vec<Profile>| -> Vec<String> {
+            results.into_iter().map(|p| p.path()).collect()
+        };
+        assert_eq!(names(store.search("cr7", 10)).len(), 2);
+        assert_eq!(
+            names(store.search("cr7 lab", 10)),
+            ["Lab/Core/core-router-7"]
+        );
+        assert_eq!(names(store.search("ne40", 10)), ["Lab/Core/core-router-7"]);
+        assert_eq!(names(store.search("10.20", 10)), ["lab-bastion"]);
+        assert!(store.search("zzz", 10).is_empty());
+        // Empty query: favorites first, then limited
+        assert_eq!(names(store.search("", 1)), ["lab-bastion"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("cr", "core-router").is_some());
+        assert!(fuzzy_score("rc", "core").is_none());
+        // Prefix beats word-start subsequence beats scattered letters
+        let prefix = fuzzy_score("core", "core-router").unwrap();
+        let words = fuzzy_score("cr", "core-router").unwrap();
+        let scattered = fuzzy_score("oe", "core-router").unwrap();
+        assert!(prefix > words && words > scattered);
+    }
+
+    #[test]
+    fn test_logging_policy() {
+        let mut profile = router("core/1");
+        profile.folder = "Prod/Core".to_string();
+        profile.logging = Some(LoggingPolicy {
+            path_template: "/logs/{folder}/{name}-{host}-{date}.log".to_string(),
+            timestamps: true,
+        });
+        let config = profile.session_config(None);
+        let log_file = config.log_file.unwrap();
+        assert!(log_file.starts_with("/logs/Prod/Core/core_1-192.168.1.1-20"));
+        assert!(!log_file.contains('{'));
+        assert!(config.log_timestamps);
+
+        profile.logging.as_mut().unwrap().path_template = " ".to_string();
+        assert!(matches!(profile.validate(), Err(ProfileError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_session_config_password_override() {
+        let mut profile = router("r1");
+        profile.password = Some("saved".to_string());
+        assert_eq!(profile.session_config(None).password, "saved");
+        assert_eq!(
+            profile.session_config(Some("typed".to_string())).password,
+            "typed"
+        );
+        assert_eq!(profile.session_config(None).cols, 80);
+        assert_eq!(profile.session_config(None).connect_timeout_ms, None);
+        profile.connect_timeout_ms = Some(60_000);
+        assert_eq!(
+            profile.session_config(None).connect_timeout_ms,
+            Some(60_000)
+        );
+    }
+
+    #[test]
+    fn test_terminal_and_dialect_applied() {
+        // Profiles saved before these settings existed keep the old behaviour
+        let old: Profile = serde_json::from_str(
+            r#"{"name": "r1", "host": "10.0.0.1", "port": 23, "protocol": "telnet", "terminal": {"cols": 132}}"#,
+        )
+        .unwrap();
+        let config = old.session_config(None);
+        assert_eq!(config.terminal_type(), DEFAULT_TERMINAL_TYPE);
+        assert_eq!((config.cols, config.rows), (132, 24));
+        assert_eq!(
+            (config.newline, config.dialect),
+            (NewlineMode::Cr, DeviceDialect::Vrp)
+        );
+
+        let mut profile = router("sw1");
+        profile.terminal.terminal_type = "vt100".to_string();
+        profile.terminal.newline = NewlineMode::CrLf;
+        profile.dialect = DeviceDialect::Ios;
+        let mut config = profile.session_config(None);
+        assert_eq!(config.terminal_type(), "vt100");
+        config.env.insert("TERM".to_string(), "xterm".to_string());
+        assert_eq!(config.terminal_type(), "xterm");
+        assert_eq!(config.dialect, DeviceDialect::Ios);
+        assert_eq!(
+            config.newline.translate(b"show ver\r".to_vec()),
+            b"show ver\r\n"
+        );
+        assert_eq!(NewlineMode::Lf.translate(b"ls\r".to_vec()), b"ls\n");
+    }
+
+    #[test]
+    fn test_jump_chain() {
+        let (dir, mut store) = temp_store();
+        let mut bastion = router("lab-bastion");
+        bastion.protocol = Protocol::Ssh;
+        let bastion = store.save(bastion).unwrap();
+        let mut gateway = router("lab-gw");
+        gateway.protocol = Protocol::Ssh;
+        gateway.jump_profile = Some(bastion.id.clone());
+        let gateway = store.save(gateway).unwrap();
+
+        let mut core = router("core-router-7");
+        core.jump_profile = Some(gateway.id.clone());
+        let core = store.save(core).unwrap();
+        let names: Vec<_> = store
+            .jump_chain(&core)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, ["lab-bastion", "lab-gw"]);
+
+        // Telnet profiles can't carry a tunnel
+        let mut via_core = router("via-core");
+        via_core.jump_profile = Some(core.id.clone());
+        assert!(matches!(
+            store.save(via_core),
+            Err(ProfileError::Invalid(_))
+        ));
+
+        let mut looped = bastion.clone();
+        looped.jump_profile = Some(gateway.id.clone());
+        assert!(matches!(store.save(looped), Err(ProfileError::Invalid(_))));
+
+        let mut dangling = router("dangling");
+        dangling.jump_profile = Some("missing".to_string());
+        assert!(matches!(
+            store.save(dangling),
+            Err(ProfileError::NotFound(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+/// Cap on output forwarded to the frontend; the rest is skipped and
+/// summarized. Skipped output still reaches the session log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Sustained forwarding rate, also the burst size
+    pub max_bytes_per_sec: usize,
+    /// Minimum time between two skip summaries while skipping
+    pub summary_interval_ms: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes_per_sec: 512 * 1024,
+            summary_interval_ms: 1000,
+        }
+    }
+}
+
+/// Body of a `session:{id}:skipped` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkipSummary {
+    pub skipped_bytes: u64,
+    pub skipped_lines: u64,
+}
+
+/// Token bucket deciding which chunks are forwarded.
+///
+/// Chunks are admitted or skipped whole, so forwarded chunks stay intact.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    tokens: f64,
+    last_refill: Instant,
+    skipped_bytes: u64,
+    skipped_lines: u64,
+    last_summary: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, now: Instant) -> Self {
+        Self {
+            config,
+            tokens: config.max_bytes_per_sec as f64,
+            last_refill: now,
+            skipped_bytes: 0,
+            skipped_lines: 0,
+            last_summary: now,
+        }
+    }
+
+    /// Whether `chunk` may be forwarded; skipped chunks are counted.
+    pub fn admit(&mut self, chunk: &[u8], now: Instant) -> bool {
+        let rate = self.config.max_bytes_per_sec as f64;
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.last_refill = now;
+
+        if self.tokens >= chunk.len() as f64 {
+            self.tokens -= chunk.len() as f64;
+            return true;
+        }
+
+        if self.skipped_bytes == 0 {
+            // Start the summary interval at the first skip
+            self.last_summary = now;
+        }
+        self.skipped_bytes += chunk.len() as u64;
+        self.skipped_lines += chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+        false
+    }
+
+    /// Summary of output skipped since the last summary, if due.
+    ///
+    /// `force` reports immediately, e.g. before forwarding resumes.
+    pub fn take_summary(&mut self, now: Instant, force: bool) -> Option<SkipSummary> {
+        if self.skipped_bytes == 0 {
+            return None;
+        }
+        let interval = Duration::from_millis(self.config.summary_interval_ms);
+        if !force && now < self.last_summary + interval {
+            return None;
+        }
+
+        self.last_summary = now;
+        Some(SkipSummary {
+            skipped_bytes: std::mem::take(&mut self.skipped_bytes),
+            skipped_lines: std::mem::take(&mut self.skipped_lines),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(now: Instant) -> RateLimiter {
+        RateLimiter::new(
+            RateLimitConfig {
+                max_bytes_per_sec: 100,
+                summary_interval_ms: 1000,
+            },
+            now,
+        )
+    }
+
+    #[test]
+    fn test_admit_and_refill() {
+        let start = Instant::now();
+        let mut limiter = limiter(start);
+
+        assert!(limiter.admit(&[b'a'; 60], start));
+        assert!(!limiter.admit(&[b'a'; 60], start));
+        assert!(limiter.admit(&[b'a'; 40], start));
+
+        // Half a second refills 50 bytes
+        let later = start + Duration::from_millis(500);
+        assert!(!limiter.admit(&[b'a'; 60], later));
+        assert!(limiter.admit(&[b'a'; 50], later));
+    }
+
+    #[test]
+    fn test_skip_summary() {
+        let start = Instant::now();
+        let mut limiter = limiter(start);
+        assert!(limiter.admit(&[b'a'; 100], start));
+        assert_eq!(limiter.take_summary(start, true), None);
+
+        assert!(!limiter.admit(b"line\nline\n", start));
+        assert!(!limiter.admit(b"line\n", start));
+        assert_eq!(limiter.take_summary(start, false), None);
+
+        let summary = limiter.take_summary(start + Duration::from_secs(1), false);
+        assert_eq!(
+            summary,
+            Some(SkipSummary {
+                skipped_bytes: 15,
+                skipped_lines: 3,
+            })
+        );
+        assert_eq!(
+            limiter.take_summary(start + Duration::from_secs(5), true),
+            None
+        );
+    }
+}
@@ -1,6 +1,4 @@
-use crate::session::{Protocol, ReconnectPolicy, SessionConfig, SessionManager, SessionState};
-use crate::ssh;
-use crate::telnet;
+use crate::session::{run_session, ReconnectPolicy, SessionConfig, SessionManager, SessionState};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tauri::Emitter;
@@ -94,6 +92,7 @@ impl ReconnectController {
             }
 
             // Attempt to connect
+            manager.metrics().record_reconnect_attempt();
             let result = self.attempt_connect(Arc::clone(&manager)).await;
 
             match result {
@@ -104,6 +103,7 @@ impl ReconnectController {
                         attempt = attempt,
                         "Reconnection successful"
                     );
+                    manager.metrics().record_reconnect(true);
                     return Ok(new_session_id);
                 }
                 Err(e) => {
@@ -136,6 +136,7 @@ impl ReconnectController {
             max_attempts = self.policy.max_retries,
             "Reconnection failed after all attempts"
         );
+        manager.metrics().record_reconnect(false);
         emit_state(&app_handle, &self.session_id, SessionState::Error);
         Err(format!(
             "Failed to reconnect after {} attempts",
@@ -157,12 +158,7 @@ impl ReconnectController {
         let result_tx_clone = result_tx.clone();
 
         tokio::spawn(async move {
-            let result = match config.protocol {
-                Protocol::Ssh => ssh::run_ssh_session(id.clone(), config, manager_clone).await,
-                Protocol::Telnet => {
-                    telnet::run_telnet_session(id.clone(), config, manager_clone).await
-                }
-            };
+            let result = run_session(id.clone(), config, manager_clone).await;
 
             let _ = match result {
                 Ok(()) => result_tx_clone.send(Ok(())).await,
@@ -217,6 +213,9 @@ fn emit_reconnect_status(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::charset::Charset;
+    use crate::session::{PayloadEncoding, Protocol};
+    use std::collections::HashMap;
 
     #[test]
     fn test_calculate_delay() {
@@ -228,8 +227,30 @@ mod tests {
                 protocol: Protocol::Ssh,
                 username: "test".to_string(),
                 password: "test".to_string(),
+                auth: Default::default(),
                 cols: 80,
                 rows: 24,
+                payload_encoding: PayloadEncoding::Json,
+                buffer: None,
+                charset: Charset::Utf8,
+                rate_limit: None,
+                delivery: None,
+                log_file: None,
+                log_timestamps: false,
+                capture_file: None,
+                keepalive: None,
+                activity: None,
+                terminal_type: None,
+                newline: Default::default(),
+                dialect: Default::default(),
+                jump_hosts: Vec::new(),
+                algorithms: None,
+                hardening: None,
+                connect_timeout_ms: None,
+                auth_methods: Vec::new(),
+                subsystem: None,
+                env: HashMap::new(),
+                plugins: Default::default(),
             },
             ReconnectPolicy {
                 enabled: true,
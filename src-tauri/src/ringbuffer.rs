@@ -1,5 +1,11 @@
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
-use tokio::sync::mpsc;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 use tracing::debug;
 
 /// Default buffer capacity: 256KB
@@ -11,6 +17,273 @@ const DEFAULT_WATERMARK_HIGH_PERCENT: usize = 80;
 /// Low watermark: 20% of capacity - resume reading when drained to this level
 const DEFAULT_WATERMARK_LOW_PERCENT: usize = 20;
 
+/// Smallest configurable capacity: 4KB
+const MIN_CAPACITY: usize = 4 * 1024;
+
+/// Largest configurable capacity: 64MB
+const MAX_CAPACITY: usize = 64 * 1024 * 1024;
+
+/// Default cap on bytes buffered across all sessions: 128MB
+const DEFAULT_GLOBAL_CAP: usize = 128 * 1024 * 1024;
+
+/// Acked delivery: resend what isn't acked after this long
+const DEFAULT_ACK_TIMEOUT_MS: u64 = 5_000;
+
+/// Shortest configurable ack timeout
+const MIN_ACK_TIMEOUT_MS: u64 = 100;
+
+/// Acked delivery: stop emitting with this many bytes unacked: 1MB
+const DEFAULT_MAX_UNACKED: usize = 1024 * 1024;
+
+/// Bytes buffered across all sessions, shared by every `SessionRingBuffer`.
+///
+/// While the total is above the cap, each buffer's effective capacity
+/// shrinks to its fair share (`cap / sessions`), so only sessions holding
+/// more than their share pause or drop.
+pub struct MemoryBudget {
+    cap: AtomicUsize,
+    used: AtomicUsize,
+    sessions: AtomicUsize,
+}
+
+/// Snapshot of the global budget for `get_memory_stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryStats {
+    pub cap: usize,
+    pub used: usize,
+    pub sessions: usize,
+    pub fair_share: usize,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(DEFAULT_GLOBAL_CAP)
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            cap: AtomicUsize::new(cap),
+            used: AtomicUsize::new(0),
+            sessions: AtomicUsize::new(0),
+        }
+    }
+
+    /// Change the global cap; must hold at least one minimum-size buffer.
+    pub fn set_cap(&self, cap: usize) -> Result<(), String> {
+        if cap < MIN_CAPACITY {
+            return Err(format!(
+                "Memory cap must be at least {} bytes",
+                MIN_CAPACITY
+            ));
+        }
+        self.cap.store(cap, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            cap: self.cap.load(Ordering::Relaxed),
+            used: self.used.load(Ordering::Relaxed),
+            sessions: self.sessions.load(Ordering::Relaxed),
+            fair_share: self.fair_share(),
+        }
+    }
+
+    fn over_cap(&self) -> bool {
+        self.used.load(Ordering::Relaxed) > self.cap.load(Ordering::Relaxed)
+    }
+
+    fn fair_share(&self) -> usize {
+        let sessions = self.sessions.load(Ordering::Relaxed).max(1);
+        self.cap.load(Ordering::Relaxed) / sessions
+    }
+}
+
+/// What happens to output that arrives while the buffer is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Keep everything and pause the producer at the high watermark
+    #[default]
+    Block,
+    /// Discard the oldest buffered bytes to make room
+    DropOldest,
+    /// Discard incoming bytes that don't fit
+    DropNewest,
+}
+
+/// Capacity and watermark settings for a session buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BufferConfig {
+    /// Buffer capacity in bytes
+    pub capacity: usize,
+    /// Pause network reads at this fill percentage
+    pub high_watermark_percent: usize,
+    /// Resume network reads once drained to this fill percentage
+    pub low_watermark_percent: usize,
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            high_watermark_percent: DEFAULT_WATERMARK_HIGH_PERCENT,
+            low_watermark_percent: DEFAULT_WATERMARK_LOW_PERCENT,
+            overflow_policy: OverflowPolicy::default(),
+        }
+    }
+}
+
+impl BufferConfig {
+    /// Check capacity bounds and that `low < high <= 100`.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_CAPACITY..=MAX_CAPACITY).contains(&self.capacity) {
+            return Err(format!(
+                "Buffer capacity must be between {} and {} bytes",
+                MIN_CAPACITY, MAX_CAPACITY
+            ));
+        }
+        if self.high_watermark_percent > 100
+            || self.low_watermark_percent >= self.high_watermark_percent
+        {
+            return Err("Buffer watermarks must satisfy low < high <= 100".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// How output reaches the frontend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryMode {
+    /// Emitted chunks leave the buffer; a webview that dies loses them
+    #[default]
+    BestEffort,
+    /// Emitted chunks stay in the buffer, numbered, until acked with
+    /// `ack_output`, and are resent when no ack comes in time
+    Acked,
+}
+
+/// Delivery settings of a session (`SessionConfig.delivery`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeliveryConfig {
+    pub mode: DeliveryMode,
+    /// Resend every unacked chunk once the oldest waited this long
+    pub ack_timeout_ms: u64,
+    /// Emission stops while this many bytes wait for an ack; they count
+    /// towards the buffer's watermarks, so reads pause as well
+    pub max_unacked_bytes: usize,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            mode: DeliveryMode::default(),
+            ack_timeout_ms: DEFAULT_ACK_TIMEOUT_MS,
+            max_unacked_bytes: DEFAULT_MAX_UNACKED,
+        }
+    }
+}
+
+impl DeliveryConfig {
+    pub fn acked(&self) -> bool {
+        self.mode == DeliveryMode::Acked
+    }
+
+    pub fn ack_timeout(&self) -> Duration {
+        Duration::from_millis(self.ack_timeout_ms)
+    }
+
+    /// Check the bounds, and that nothing on the way may drop acked output:
+    /// the buffer has to block and the session can't be rate limited
+    pub fn validate(&self, buffer: &BufferConfig, rate_limited: bool) -> Result<(), String> {
+        if !self.acked() {
+            return Ok(());
+        }
+        if self.ack_timeout_ms < MIN_ACK_TIMEOUT_MS {
+            return Err(format!(
+                "Ack timeout must be at least {}ms",
+                MIN_ACK_TIMEOUT_MS
+            ));
+        }
+        if !(MIN_CAPACITY..=MAX_CAPACITY).contains(&self.max_unacked_bytes) {
+            return Err(format!(
+                "Unacked bytes must be between {} and {}",
+                MIN_CAPACITY, MAX_CAPACITY
+            ));
+        }
+        if buffer.overflow_policy != OverflowPolicy::Block {
+            return Err("Acked delivery needs the block overflow policy".to_string());
+        }
+        if rate_limited {
+            return Err("Acked delivery can't be combined with a rate limit".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot of a session buffer for `get_buffer_stats` and `session:{id}:buffer`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BufferStats {
+    pub capacity: usize,
+    pub len: usize,
+    pub fill_percent: usize,
+    /// Whether the buffer is above its high watermark and not yet drained
+    pub paused: bool,
+    /// Total bytes pushed since the session started
+    pub total_pushed: u64,
+    /// Total bytes discarded instead of emitted
+    pub total_dropped: u64,
+    /// Times the buffer crossed its high watermark
+    pub pause_count: u64,
+    /// Times the buffer drained back to its low watermark
+    pub resume_count: u64,
+    /// Arrival of the oldest buffered chunk, in ms since the buffer was created
+    pub first_chunk_ms: Option<u64>,
+    /// Arrival of the newest buffered chunk, in ms since the buffer was created
+    pub last_chunk_ms: Option<u64>,
+    /// Emitted bytes waiting for an ack (acked delivery)
+    pub unacked: usize,
+    /// Highest sequence number acked, 0 before the first ack
+    pub acked_seq: u64,
+    /// Chunks sent again after an ack timeout
+    pub redelivered: u64,
+}
+
+/// Pushed data and when it arrived
+struct Segment {
+    data: Bytes,
+    arrived: Instant,
+}
+
+/// An emitted chunk waiting for its ack
+struct Held {
+    seq: u64,
+    data: Bytes,
+    sent: Instant,
+}
+
+/// Data popped from the buffer, derefs to its bytes
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub data: Bytes,
+    /// Arrival of the chunk's first byte
+    pub arrived: Instant,
+}
+
+impl Deref for Chunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
 /// Ring buffer with watermark-based backpressure for session data.
 ///
 /// This buffer sits between the TCP/SSH read loop and Tauri event emission
@@ -22,149 +295,482 @@ const DEFAULT_WATERMARK_LOW_PERCENT: usize = 20;
 /// 2. If buffer exceeds high watermark -> pause network reads
 /// 3. Frontend processes data -> calls drain notification
 /// 4. If buffer drops below low watermark -> resume network reads
+///
+/// Only `OverflowPolicy::Block` pauses the producer; the drop policies keep
+/// the buffer within capacity and count what they discard.
+///
+/// With a `MemoryBudget` attached, capacity and watermarks are scaled down to
+/// the fair share while the global total is over its cap.
+///
+/// Data is held as a deque of `Bytes` segments with the total length tracked
+/// separately, so pushing owned data and popping whole segments never copies.
+/// Each segment keeps a monotonic arrival time.
+///
+/// With acked delivery the emitter hands popped chunks back with `hold`;
+/// they are numbered from 1 and stay, counted against capacity, budget and
+/// watermarks, until `ack` releases them.
 pub struct SessionRingBuffer {
-    buffer: VecDeque<u8>,
+    segments: VecDeque<Segment>,
+    /// Emitted chunks not yet acked, oldest first
+    held: VecDeque<Held>,
+    /// Total bytes across `held`
+    held_len: usize,
+    /// Sequence number of the last held chunk
+    last_seq: u64,
+    acked_seq: u64,
+    redelivered: u64,
+    /// Reference point of the timestamps in `BufferStats`
+    created: Instant,
+    /// Total bytes across all segments
+    len: usize,
     capacity: usize,
-    watermark_high: usize,
-    watermark_low: usize,
+    watermark_high_percent: usize,
+    watermark_low_percent: usize,
+    overflow_policy: OverflowPolicy,
     session_id: String,
+    budget: Option<Arc<MemoryBudget>>,
+    /// Bytes currently counted against `budget`
+    accounted: usize,
+    /// Dropped bytes not yet reported via `take_dropped`
+    pending_dropped: u64,
+    /// Backpressure state, tracked for stats
+    paused: bool,
+    total_pushed: u64,
+    total_dropped: u64,
+    pause_count: u64,
+    resume_count: u64,
 }
 
 impl SessionRingBuffer {
-    /// Create a new ring buffer with default capacity (256KB).
-    pub fn new(session_id: String) -> Self {
-        Self::with_capacity(session_id, DEFAULT_CAPACITY)
+    /// Create a new ring buffer from a session's buffer config.
+    pub fn new(session_id: String, config: BufferConfig) -> Self {
+        let mut buffer = Self {
+            segments: VecDeque::new(),
+            held: VecDeque::new(),
+            held_len: 0,
+            last_seq: 0,
+            acked_seq: 0,
+            redelivered: 0,
+            created: Instant::now(),
+            len: 0,
+            capacity: 0,
+            watermark_high_percent: 0,
+            watermark_low_percent: 0,
+            overflow_policy: OverflowPolicy::default(),
+            session_id,
+            budget: None,
+            accounted: 0,
+            pending_dropped: 0,
+            paused: false,
+            total_pushed: 0,
+            total_dropped: 0,
+            pause_count: 0,
+            resume_count: 0,
+        };
+        buffer.set_config(config);
+        buffer
     }
 
-    /// Create a new ring buffer with specified capacity.
+    /// Create a new ring buffer with specified capacity and default watermarks.
+    #[cfg(test)]
     pub fn with_capacity(session_id: String, capacity: usize) -> Self {
-        let watermark_high = capacity * DEFAULT_WATERMARK_HIGH_PERCENT / 100;
-        let watermark_low = capacity * DEFAULT_WATERMARK_LOW_PERCENT / 100;
-
-        Self {
-            buffer: VecDeque::with_capacity(capacity),
-            capacity,
-            watermark_high,
-            watermark_low,
+        Self::new(
             session_id,
-        }
+            BufferConfig {
+                capacity,
+                ..BufferConfig::default()
+            },
+        )
     }
 
-    /// Push data into the buffer.
-    ///
-    /// Returns `true` if data was accepted, `false` if buffer is at capacity
-    /// (data is still pushed, but older data may be dropped in extreme cases).
+    /// Count this buffer against a global memory budget.
+    pub fn with_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        budget.sessions.fetch_add(1, Ordering::Relaxed);
+        self.budget = Some(budget);
+        self.sync_budget();
+        self
+    }
+
+    /// Apply new capacity and watermarks. Buffered data is kept, so a
+    /// smaller capacity takes effect as the buffer drains.
+    pub fn set_config(&mut self, config: BufferConfig) {
+        self.capacity = config.capacity;
+        self.watermark_high_percent = config.high_watermark_percent;
+        self.watermark_low_percent = config.low_watermark_percent;
+        self.overflow_policy = config.overflow_policy;
+
+        debug!(
+            session_id = %self.session_id,
+            capacity = self.capacity,
+            watermark_high = self.watermark_high(),
+            watermark_low = self.watermark_low(),
+            "Buffer config applied"
+        );
+    }
+
+    /// Copy data into the buffer, see [`push_bytes`](Self::push_bytes).
+    #[cfg(test)]
     pub fn push(&mut self, data: &[u8]) -> bool {
+        self.push_bytes(Bytes::copy_from_slice(data))
+    }
+
+    /// Push an owned segment into the buffer without copying.
+    ///
+    /// Returns `true` if data fit within capacity, `false` otherwise. What
+    /// happens to data that doesn't fit depends on the overflow policy.
+    pub fn push_bytes(&mut self, mut data: Bytes) -> bool {
         // If adding this data would exceed capacity, we're at backpressure
-        let will_exceed = self.buffer.len() + data.len() > self.capacity;
+        let capacity = self.effective_capacity();
+        let will_exceed = self.occupied() + data.len() > capacity;
 
         if will_exceed {
             debug!(
                 session_id = %self.session_id,
-                buffer_len = self.buffer.len(),
+                buffer_len = self.len,
                 incoming = data.len(),
-                capacity = self.capacity,
-                "Buffer at capacity, data may be delayed"
+                capacity,
+                policy = ?self.overflow_policy,
+                "Buffer at capacity"
             );
+
+            match self.overflow_policy {
+                // Accept data, the producer pauses on the high watermark
+                OverflowPolicy::Block => {}
+                OverflowPolicy::DropNewest => {
+                    let room = capacity.saturating_sub(self.len);
+                    self.record_dropped(data.len() - room);
+                    data.truncate(room);
+                }
+                OverflowPolicy::DropOldest => {
+                    if data.len() > capacity {
+                        self.record_dropped(data.len() - capacity);
+                        data = data.slice(data.len() - capacity..);
+                    }
+                    let excess = (self.len + data.len()).saturating_sub(capacity);
+                    self.discard_front(excess);
+                }
+            }
         }
 
-        // Always accept data, but signal backpressure
-        self.buffer.extend(data);
+        if !data.is_empty() {
+            self.len += data.len();
+            self.total_pushed += data.len() as u64;
+            self.segments.push_back(Segment {
+                data,
+                arrived: Instant::now(),
+            });
+        }
+        self.update_pressure();
 
         !will_exceed
     }
 
     /// Pop a chunk of data from the buffer.
     ///
-    /// Returns up to `max_size` bytes, or None if buffer is empty.
-    pub fn pop_chunk(&mut self, max_size: usize) -> Option<Vec<u8>> {
-        if self.buffer.is_empty() {
+    /// Returns up to `max_size` bytes, or None if buffer is empty. A large
+    /// front segment is split without copying; small segments are coalesced
+    /// into one contiguous chunk.
+    pub fn pop_chunk(&mut self, max_size: usize) -> Option<Chunk> {
+        let single = self.segments.len() == 1;
+        let front = self.segments.front_mut()?;
+        let arrived = front.arrived;
+
+        // Fast path: the front segment alone fills the chunk, or is all there is
+        if front.data.len() >= max_size || single {
+            let data = if front.data.len() > max_size {
+                front.data.split_to(max_size)
+            } else {
+                self.segments.pop_front()?.data
+            };
+            self.len -= data.len();
+            self.update_pressure();
+            return Some(Chunk { data, arrived });
+        }
+
+        let mut data = BytesMut::with_capacity(max_size.min(self.len));
+        while data.len() < max_size {
+            let Some(front) = self.segments.front_mut() else {
+                break;
+            };
+            let take = (max_size - data.len()).min(front.data.len());
+            if take == front.data.len() {
+                data.extend_from_slice(&front.data);
+                self.segments.pop_front();
+            } else {
+                data.extend_from_slice(&front.data.split_to(take));
+            }
+        }
+
+        self.len -= data.len();
+        self.update_pressure();
+        Some(Chunk {
+            data: data.freeze(),
+            arrived,
+        })
+    }
+
+    /// Keep an emitted chunk until it is acked; returns its sequence number
+    pub fn hold(&mut self, data: Bytes, now: Instant) -> u64 {
+        self.last_seq += 1;
+        self.held_len += data.len();
+        self.held.push_back(Held {
+            seq: self.last_seq,
+            data,
+            sent: now,
+        });
+        self.update_pressure();
+        self.last_seq
+    }
+
+    /// Release held chunks up to and including `seq`; returns the bytes
+    /// released, or None for a sequence number not handed out yet
+    pub fn ack(&mut self, seq: u64) -> Option<usize> {
+        if seq > self.last_seq {
             return None;
         }
+        let mut released = 0;
+        while self.held.front().is_some_and(|held| held.seq <= seq) {
+            if let Some(held) = self.held.pop_front() {
+                released += held.data.len();
+            }
+        }
+        self.held_len -= released;
+        self.acked_seq = self.acked_seq.max(seq);
+        self.update_pressure();
+        Some(released)
+    }
 
-        let drain_size = max_size.min(self.buffer.len());
-        let chunk: Vec<u8> = self.buffer.drain(..drain_size).collect();
+    /// Held chunks with their sequence numbers, oldest first
+    pub fn unacked(&self) -> Vec<(u64, Bytes)> {
+        self.held
+            .iter()
+            .map(|held| (held.seq, held.data.clone()))
+            .collect()
+    }
 
-        Some(chunk)
+    /// Bytes waiting for an ack
+    pub fn unacked_len(&self) -> usize {
+        self.held_len
+    }
+
+    /// Every held chunk, to send again, once the oldest was sent `timeout`
+    /// ago or longer; their timeouts restart
+    pub fn take_overdue(&mut self, now: Instant, timeout: Duration) -> Vec<(u64, Bytes)> {
+        if self
+            .held
+            .front()
+            .is_none_or(|held| now < held.sent + timeout)
+        {
+            return Vec::new();
+        }
+        for held in self.held.iter_mut() {
+            held.sent = now;
+        }
+        self.redelivered += self.held.len() as u64;
+        self.unacked()
+    }
+
+    /// When the oldest held chunk times out
+    pub fn next_redelivery(&self, timeout: Duration) -> Option<Instant> {
+        self.held.front().map(|held| held.sent + timeout)
     }
 
     /// Drain all data from the buffer.
     pub fn drain_all(&mut self) -> Vec<u8> {
-        self.buffer.drain(..).collect()
+        let mut data = Vec::with_capacity(self.len);
+        for segment in self.segments.drain(..) {
+            data.extend_from_slice(&segment.data);
+        }
+        self.len = 0;
+        self.update_pressure();
+        data
     }
 
     /// Check if reading should be paused (buffer above high watermark).
+    /// Always false for drop policies, which never block the producer.
     pub fn should_pause(&self) -> bool {
-        self.buffer.len() >= self.watermark_high
+        self.overflow_policy == OverflowPolicy::Block && self.occupied() >= self.watermark_high()
+    }
+
+    /// Take the number of bytes dropped since the last call.
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.pending_dropped)
     }
 
     /// Check if reading can resume (buffer below low watermark).
     pub fn can_resume(&self) -> bool {
-        self.buffer.len() <= self.watermark_low
+        self.occupied() <= self.watermark_low()
     }
 
     /// Get current buffer length.
     pub fn len(&self) -> usize {
-        self.buffer.len()
+        self.len
     }
 
     /// Check if buffer is empty.
     pub fn is_empty(&self) -> bool {
-        self.buffer.is_empty()
+        self.len == 0
     }
 
     /// Get buffer fill percentage (0-100).
     pub fn fill_percent(&self) -> usize {
-        (self.buffer.len() * 100) / self.capacity
+        (self.occupied() * 100) / self.capacity
+    }
+
+    /// Buffered and held bytes
+    fn occupied(&self) -> usize {
+        self.len + self.held_len
+    }
+
+    /// Snapshot current fill and lifetime counters.
+    pub fn stats(&self) -> BufferStats {
+        BufferStats {
+            capacity: self.capacity,
+            len: self.len,
+            fill_percent: self.fill_percent(),
+            paused: self.paused,
+            total_pushed: self.total_pushed,
+            total_dropped: self.total_dropped,
+            pause_count: self.pause_count,
+            resume_count: self.resume_count,
+            first_chunk_ms: self.segments.front().map(|s| self.since_created(s.arrived)),
+            last_chunk_ms: self.segments.back().map(|s| self.since_created(s.arrived)),
+            unacked: self.held_len,
+            acked_seq: self.acked_seq,
+            redelivered: self.redelivered,
+        }
+    }
+
+    fn since_created(&self, at: Instant) -> u64 {
+        at.duration_since(self.created).as_millis() as u64
+    }
+
+    /// Discard `count` bytes from the front of the buffer.
+    fn discard_front(&mut self, mut count: usize) {
+        self.record_dropped(count);
+        self.len -= count.min(self.len);
+        while count > 0 {
+            let Some(front) = self.segments.front_mut() else {
+                break;
+            };
+            if front.data.len() <= count {
+                count -= front.data.len();
+                self.segments.pop_front();
+            } else {
+                let _ = front.data.split_to(count);
+                count = 0;
+            }
+        }
+    }
+
+    fn record_dropped(&mut self, count: usize) {
+        self.total_dropped += count as u64;
+        self.pending_dropped += count as u64;
+    }
+
+    /// Capacity, reduced to the fair share while the global budget is exceeded.
+    fn effective_capacity(&self) -> usize {
+        match &self.budget {
+            Some(budget) if budget.over_cap() => self.capacity.min(budget.fair_share()),
+            _ => self.capacity,
+        }
+    }
+
+    fn watermark_high(&self) -> usize {
+        self.effective_capacity() * self.watermark_high_percent / 100
+    }
+
+    fn watermark_low(&self) -> usize {
+        self.effective_capacity() * self.watermark_low_percent / 100
+    }
+
+    /// Move the budget's count by the change in length since the last sync.
+    fn sync_budget(&mut self) {
+        let occupied = self.occupied();
+        if let Some(budget) = &self.budget {
+            if occupied > self.accounted {
+                budget
+                    .used
+                    .fetch_add(occupied - self.accounted, Ordering::Relaxed);
+            } else {
+                budget
+                    .used
+                    .fetch_sub(self.accounted - occupied, Ordering::Relaxed);
+            }
+            self.accounted = occupied;
+        }
+    }
+
+    /// Sync the budget and count watermark crossings with hysteresis.
+    fn update_pressure(&mut self) {
+        self.sync_budget();
+        if !self.paused && self.should_pause() {
+            self.paused = true;
+            self.pause_count += 1;
+        } else if self.paused && self.can_resume() {
+            self.paused = false;
+            self.resume_count += 1;
+        }
     }
 }
 
-/// Backpressure controller that manages the flow between network reads
-/// and frontend consumption.
+impl Drop for SessionRingBuffer {
+    fn drop(&mut self) {
+        if let Some(budget) = &self.budget {
+            budget.used.fetch_sub(self.accounted, Ordering::Relaxed);
+            budget.sessions.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Pause state of a session's network reads, shared by the transport and
+/// the emitter.
+///
+/// Both sides evaluate the same watermarks: the transport after each push,
+/// so reads stop as soon as the high watermark is hit, and the emitter after
+/// each pop, so reads resume once the buffer drains to the low watermark.
+/// The transport's read loop honors the state through `subscribe()`.
+#[derive(Clone)]
 pub struct BackpressureController {
-    /// Channel to signal pause/resume to the read loop
-    pause_tx: mpsc::Sender<bool>,
-    /// Current pause state
-    is_paused: bool,
+    pause_tx: watch::Sender<bool>,
     session_id: String,
 }
 
 impl BackpressureController {
-    pub fn new(session_id: String, pause_tx: mpsc::Sender<bool>) -> Self {
+    pub fn new(session_id: String) -> Self {
         Self {
-            pause_tx,
-            is_paused: false,
+            pause_tx: watch::Sender::new(false),
             session_id,
         }
     }
 
+    /// Pause state for a read loop: skip reads while `*rx.borrow()` is true
+    /// and wake on `rx.changed()`
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.pause_tx.subscribe()
+    }
+
     /// Update backpressure state based on buffer level.
     /// Returns true if state changed.
-    pub async fn update(&mut self, buffer: &SessionRingBuffer) -> bool {
-        let should_pause = buffer.should_pause();
-        let can_resume = buffer.can_resume();
-
-        if !self.is_paused && should_pause {
-            // Need to pause
-            self.is_paused = true;
+    pub fn update(&self, buffer: &SessionRingBuffer) -> bool {
+        let paused = self.is_paused();
+        if !paused && buffer.should_pause() {
             debug!(
                 session_id = %self.session_id,
                 buffer_fill = %buffer.fill_percent(),
                 "Backpressure: pausing reads"
             );
-            let _ = self.pause_tx.send(true).await;
+            self.pause_tx.send_replace(true);
             return true;
         }
 
-        if self.is_paused && can_resume {
-            // Can resume
-            self.is_paused = false;
+        if paused && buffer.can_resume() {
             debug!(
                 session_id = %self.session_id,
                 buffer_fill = %buffer.fill_percent(),
                 "Backpressure: resuming reads"
             );
-            let _ = self.pause_tx.send(false).await;
+            self.pause_tx.send_replace(false);
             return true;
         }
 
@@ -173,7 +779,7 @@ impl BackpressureController {
 
     /// Check if currently paused.
     pub fn is_paused(&self) -> bool {
-        self.is_paused
+        *self.pause_tx.borrow()
     }
 }
 
@@ -189,11 +795,278 @@ mod tests {
         assert_eq!(buffer.len(), 5);
 
         let chunk = buffer.pop_chunk(3);
-        assert_eq!(chunk, Some(vec![b'h', b'e', b'l']));
+        assert_eq!(chunk.as_deref(), Some(&[b'h', b'e', b'l'][..]));
         assert_eq!(buffer.len(), 2);
 
         let chunk = buffer.pop_chunk(10);
-        assert_eq!(chunk, Some(vec![b'l', b'o']));
+        assert_eq!(chunk.as_deref(), Some(&[b'l', b'o'][..]));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_backpressure_controller() {
+        let mut buffer = SessionRingBuffer::with_capacity("test".to_string(), 1000);
+        let controller = BackpressureController::new("test".to_string());
+        let pause_rx = controller.subscribe();
+
+        buffer.push(&[0u8; 500]);
+        assert!(!controller.update(&buffer));
+        buffer.push(&[0u8; 300]);
+        assert!(controller.update(&buffer));
+        assert!(*pause_rx.borrow());
+
+        // Hysteresis: stays paused until the low watermark
+        buffer.pop_chunk(500);
+        assert!(!controller.update(&buffer));
+        buffer.pop_chunk(100);
+        assert!(controller.update(&buffer));
+        assert!(!*pause_rx.borrow());
+    }
+
+    #[test]
+    fn test_chunk_timestamps() {
+        let mut buffer = SessionRingBuffer::with_capacity("test".to_string(), 1024);
+        assert_eq!(buffer.stats().first_chunk_ms, None);
+
+        buffer.push(b"first ");
+        let first = buffer.segments[0].arrived;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        buffer.push(b"second");
+
+        let stats = buffer.stats();
+        assert!(stats.last_chunk_ms.unwrap() >= stats.first_chunk_ms.unwrap() + 5);
+
+        // A coalesced chunk carries the arrival of its first byte
+        let chunk = buffer.pop_chunk(64).unwrap();
+        assert_eq!(&chunk[..], b"first second");
+        assert_eq!(chunk.arrived, first);
+        assert_eq!(buffer.stats().last_chunk_ms, None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut buffer = SessionRingBuffer::with_capacity("test".to_string(), 100);
+        buffer.push(&[0u8; 50]);
+        buffer.push(&[0u8; 40]);
+        buffer.push(&[0u8; 5]);
+
+        let stats = buffer.stats();
+        assert_eq!(stats.total_pushed, 95);
+        assert_eq!(stats.fill_percent, 95);
+        assert!(stats.paused);
+        assert_eq!(stats.pause_count, 1);
+
+        buffer.pop_chunk(50);
+        assert!(buffer.stats().paused);
+
+        buffer.pop_chunk(30);
+        let stats = buffer.stats();
+        assert!(!stats.paused);
+        assert_eq!(stats.resume_count, 1);
+        assert_eq!(stats.len, 15);
+    }
+
+    #[test]
+    fn test_overflow_drop_newest() {
+        let mut buffer = SessionRingBuffer::new(
+            "test".to_string(),
+            BufferConfig {
+                capacity: 10,
+                overflow_policy: OverflowPolicy::DropNewest,
+                ..BufferConfig::default()
+            },
+        );
+        assert!(buffer.push(b"abcdefgh"));
+        assert!(!buffer.push(b"ijkl"));
+        assert!(!buffer.should_pause());
+        assert_eq!(buffer.take_dropped(), 2);
+        assert_eq!(buffer.take_dropped(), 0);
+        assert_eq!(buffer.drain_all(), b"abcdefghij");
+        assert_eq!(buffer.stats().total_dropped, 2);
+    }
+
+    #[test]
+    fn test_overflow_drop_oldest() {
+        let mut buffer = SessionRingBuffer::new(
+            "test".to_string(),
+            BufferConfig {
+                capacity: 10,
+                overflow_policy: OverflowPolicy::DropOldest,
+                ..BufferConfig::default()
+            },
+        );
+        buffer.push(b"abc");
+        buffer.push(b"defgh");
+        assert!(!buffer.push(b"ijkl"));
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer.take_dropped(), 2);
+        assert_eq!(buffer.drain_all(), b"cdefghijkl");
+
+        // A single push larger than capacity keeps its tail
+        buffer.push(b"0123456789abcdef");
+        assert_eq!(buffer.take_dropped(), 6);
+        assert_eq!(buffer.drain_all(), b"6789abcdef");
+    }
+
+    #[test]
+    fn test_memory_budget_fair_share() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let config = BufferConfig {
+            capacity: 1000,
+            ..BufferConfig::default()
+        };
+        let mut heavy =
+            SessionRingBuffer::new("heavy".to_string(), config).with_budget(Arc::clone(&budget));
+        let mut light =
+            SessionRingBuffer::new("light".to_string(), config).with_budget(Arc::clone(&budget));
+
+        light.push(&[0u8; 10]);
+        heavy.push(&[0u8; 50]);
+        assert!(!heavy.should_pause());
+
+        // Over the global cap: heavy is above its 50 byte share, light is not
+        heavy.push(&[0u8; 50]);
+        assert_eq!(budget.stats().used, 110);
+        assert!(heavy.should_pause());
+        assert!(!light.should_pause());
+
+        heavy.pop_chunk(100);
+        assert!(heavy.can_resume());
+        drop(heavy);
+        let stats = budget.stats();
+        assert_eq!((stats.used, stats.sessions, stats.fair_share), (10, 1, 100));
+    }
+
+    #[test]
+    fn test_memory_budget_drop_policy() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let config = BufferConfig {
+            capacity: 1000,
+            overflow_policy: OverflowPolicy::DropOldest,
+            ..BufferConfig::default()
+        };
+        let mut a =
+            SessionRingBuffer::new("a".to_string(), config).with_budget(Arc::clone(&budget));
+        let _b = SessionRingBuffer::new("b".to_string(), config).with_budget(Arc::clone(&budget));
+
+        a.push(&[0u8; 120]);
+        // Now over the cap, the next push trims `a` to its 50 byte share
+        a.push(&[1u8; 10]);
+        assert_eq!(a.len(), 50);
+        assert_eq!(a.take_dropped(), 80);
+        assert_eq!(budget.stats().used, 50);
+    }
+
+    #[test]
+    fn test_set_config() {
+        let mut buffer = SessionRingBuffer::with_capacity("test".to_string(), 100);
+        buffer.push(&[0u8; 50]);
+        assert!(!buffer.should_pause());
+
+        buffer.set_config(BufferConfig {
+            capacity: 100,
+            high_watermark_percent: 40,
+            low_watermark_percent: 10,
+            overflow_policy: OverflowPolicy::Block,
+        });
+        assert!(buffer.should_pause());
+        assert_eq!(buffer.len(), 50);
+
+        buffer.pop_chunk(40);
+        assert!(buffer.can_resume());
+    }
+
+    #[test]
+    fn test_buffer_config_validate() {
+        assert!(BufferConfig::default().validate().is_ok());
+
+        let config = BufferConfig {
+            capacity: 16,
+            ..BufferConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = BufferConfig {
+            high_watermark_percent: 50,
+            low_watermark_percent: 50,
+            ..BufferConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = BufferConfig {
+            high_watermark_percent: 120,
+            ..BufferConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_acked_delivery() {
+        let mut buffer = SessionRingBuffer::with_capacity("test".to_string(), 100);
+        let start = Instant::now();
+        buffer.push(&[b'a'; 50]);
+        let chunk = buffer.pop_chunk(30).unwrap();
+        assert_eq!(buffer.hold(chunk.data, start), 1);
+        let chunk = buffer.pop_chunk(30).unwrap();
+        assert_eq!(buffer.hold(chunk.data, start), 2);
+
+        // Held bytes still count towards the watermarks
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.unacked_len(), 50);
+        assert!(!buffer.can_resume());
+
+        let timeout = Duration::from_millis(100);
+        assert!(buffer.take_overdue(start, timeout).is_empty());
+        let later = start + timeout;
+        let resent: Vec<u64> = buffer
+            .take_overdue(later, timeout)
+            .iter()
+            .map(|(seq, _)| *seq)
+            .collect();
+        assert_eq!(resent, [1, 2]);
+        assert_eq!(buffer.next_redelivery(timeout), Some(later + timeout));
+
+        assert_eq!(buffer.ack(3), None);
+        assert_eq!(buffer.ack(1), Some(30));
+        assert_eq!(buffer.ack(1), Some(0));
+        assert_eq!(buffer.ack(2), Some(20));
+        let stats = buffer.stats();
+        assert_eq!(
+            (stats.unacked, stats.acked_seq, stats.redelivered),
+            (0, 2, 2)
+        );
+        assert!(buffer.can_resume());
+
+        let buffer_config = BufferConfig::default();
+        let config = DeliveryConfig {
+            mode: DeliveryMode::Acked,
+            ..DeliveryConfig::default()
+        };
+        assert!(config.validate(&buffer_config, false).is_ok());
+        assert!(config.validate(&buffer_config, true).is_err());
+        let dropping = BufferConfig {
+            overflow_policy: OverflowPolicy::DropOldest,
+            ..buffer_config
+        };
+        assert!(config.validate(&dropping, false).is_err());
+        assert!(DeliveryConfig::default().validate(&dropping, true).is_ok());
+    }
+
+    #[test]
+    fn test_pop_chunk_segments() {
+        let mut buffer = SessionRingBuffer::with_capacity("test".to_string(), 1024);
+        buffer.push_bytes(Bytes::from_static(b"abc"));
+        buffer.push_bytes(Bytes::from_static(b""));
+        buffer.push_bytes(Bytes::from_static(b"defgh"));
+        buffer.push(b"ij");
+        assert_eq!(buffer.len(), 10);
+
+        // Coalesces across segments, splitting the last one
+        assert_eq!(buffer.pop_chunk(5).as_deref(), Some(&b"abcde"[..]));
+        // Front segment larger than the chunk is split in place
+        assert_eq!(buffer.pop_chunk(2).as_deref(), Some(&b"fg"[..]));
+        assert_eq!(buffer.pop_chunk(64).as_deref(), Some(&b"hij"[..]));
+        assert!(buffer.pop_chunk(64).is_none());
         assert!(buffer.is_empty());
     }
 
@@ -0,0 +1,329 @@
+//! Local user password rotation across devices.
+//!
+//! Each device gets its own background session with the current
+//! credentials, runs its dialect's password change, then a second session
+//! signs in with the new password to prove it works. Only a verified change
+//! counts as done; storing the new password in the profile or vault is left
+//! to the caller, which owns those stores.
+//!
+//! Commands carrying the password never reach a session log, and neither
+//! they nor their output appear in the report.
+
+use crate::batch::DeviceSession;
+use crate::command;
+use crate::jobs::JobControl;
+use crate::session::{DeviceDialect, SessionConfig, SessionError, SessionManager};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+const DEFAULT_CONCURRENCY: usize = 8;
+const MAX_CONCURRENCY: usize = 64;
+
+/// Per command; saving the configuration can take a while
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `passwd` asking for the password being replaced
+static CURRENT_PASSWORD_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)(?:current|old)[^\n]*password: ?\z").unwrap());
+
+/// `passwd` asking for the new password, twice
+static NEW_PASSWORD_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)(?:new|retype|confirm)[^\n]*password: ?\z").unwrap());
+
+/// `passwd` giving up, e.g. `passwd: Authentication token manipulation error`
+static PASSWD_FAILED_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?im)^passwd:[^\n]*(?:error|fail|unchanged)[^\n]*$").unwrap());
+
+#[derive(Error, Debug)]
+pub enum RotationError {
+    #[error("Password rotation is not supported for {0:?} devices")]
+    Unsupported(DeviceDialect),
+    #[error("Invalid rotation: {0}")]
+    Invalid(String),
+}
+
+impl From<RotationError> for String {
+    fn from(err: RotationError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct RotationOptions {
+    /// Default `DEFAULT_CONCURRENCY`
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+/// A device and the session config holding its current credentials
+pub struct RotationTarget {
+    pub id: String,
+    pub name: String,
+    pub config: SessionConfig,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RotationResult {
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    pub username: String,
+    /// The device accepted the change
+    pub changed: bool,
+    /// A new session signed in with the new password
+    pub verified: bool,
+    /// The new password replaced the stored one; set by the caller
+    pub stored: bool,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Commands changing `{user}`'s password to `{password}`, saving the
+/// configuration where the device needs it
+fn plan(dialect: DeviceDialect) -> Result<&'static [&'static str], RotationError> {
+    match dialect {
+        DeviceDialect::Vrp => Ok(&[
+            "system-view",
+            "aaa",
+            "local-user {user} password irreversible-cipher {password}",
+            "quit",
+            "quit",
+            "save",
+        ]),
+        DeviceDialect::Comware => Ok(&[
+            "system-view",
+            "local-user {user} class manage",
+            "password simple {password}",
+            "quit",
+            "quit",
+            "save force",
+        ]),
+        DeviceDialect::Ios => Ok(&[
+            "configure terminal",
+            "username {user} secret {password}",
+            "end",
+            "write memory",
+        ]),
+        // The password goes to `passwd`'s prompts, not the command line
+        DeviceDialect::Linux => Ok(&["passwd {user}"]),
+        dialect => Err(RotationError::Unsupported(dialect)),
+    }
+}
+
+/// Check the targets and the new password before any device is touched
+pub fn validate(targets: &[RotationTarget], password: &str) -> Result<(), RotationError> {
+    if targets.is_empty() {
+        return Err(RotationError::Invalid("no targets".to_string()));
+    }
+    // Spaces and `?` would end the password or ask the CLI for help
+    if password.is_empty() || password.contains(|c: char| c.is_whitespace() || c == '?') {
+        return Err(RotationError::Invalid(
+            "the new password must not be empty or contain spaces or `?`".to_string(),
+        ));
+    }
+    for target in targets {
+        plan(target.config.dialect)?;
+        if target.config.username.is_empty() {
+            return Err(RotationError::Invalid(format!(
+                "{} has no username",
+                target.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Rotate every target's login user to `password`; results keep the order
+/// of `targets`. Pausing the job holds devices not started yet, cancelling
+/// fails them.
+pub async fn rotate(
+    manager: &Arc<SessionManager>,
+    targets: Vec<RotationTarget>,
+    password: &str,
+    options: RotationOptions,
+    job: &JobControl,
+) -> Vec<RotationResult> {
+    let total = targets.len();
+    let concurrency = options
+        .concurrency
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .clamp(1, MAX_CONCURRENCY);
+    info!(job_id = %job.id(), targets = total, "Starting password rotation");
+    job.progress(0, total as u64, None);
+
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let password: Arc<str> = password.into();
+    let mut tasks = JoinSet::new();
+    for (index, mut target) in targets.into_iter().enumerate() {
+        // The change command carries the password
+        target.config.log_file = None;
+        let manager = Arc::clone(manager);
+        let permits = Arc::clone(&permits);
+        let password = Arc::clone(&password);
+        let signal = job.signal();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await.expect("semaphore not closed");
+            let started = Instant::now();
+            let mut result = RotationResult {
+                id: target.id.clone(),
+                name: target.name.clone(),
+                host: target.config.host.clone(),
+                username: target.config.username.clone(),
+                changed: false,
+                verified: false,
+                stored: false,
+                error: None,
+                duration_ms: 0,
+            };
+            if let Err(e) = signal.checkpoint().await {
+                result.error = Some(e.to_string());
+                return (index, result);
+            }
+            if let Err(e) = rotate_device(&manager, &target.config, &password, &mut result).await {
+                warn!(host = %result.host, error = %e, "Password rotation failed");
+                result.error = Some(e);
+            }
+            result.duration_ms = started.elapsed().as_millis() as u64;
+            (index, result)
+        });
+    }
+
+    let mut results: Vec<Option<RotationResult>> = vec![None; total];
+    let mut completed = 0;
+    while let Some(joined) = tasks.join_next().await {
+        let Ok((index, result)) = joined else {
+            warn!(job_id = %job.id(), "Password rotation task panicked");
+            continue;
+        };
+        completed += 1;
+        job.progress(completed, total as u64, Some(result.name.clone()));
+        results[index] = Some(result);
+    }
+    results.into_iter().flatten().collect()
+}
+
+async fn rotate_device(
+    manager: &Arc<SessionManager>,
+    config: &SessionConfig,
+    password: &str,
+    result: &mut RotationResult,
+) -> Result<(), String> {
+    let commands = plan(config.dialect).map_err(|e| e.to_string())?;
+    let session = DeviceSession::open(manager, config)
+        .await
+        .map_err(|e| format!("sign-in with the current password failed: {}", e))?;
+    let changed = change_password(manager, &session.id, commands, config, password).await;
+    session.close(manager).await;
+    changed?;
+    result.changed = true;
+    info!(host = %config.host, user = %config.username, "Password changed");
+
+    let mut verify = config.clone();
+    verify.password = password.to_string();
+    let session = DeviceSession::open(manager, &verify)
+        .await
+        .map_err(|e| format!("changed, but sign-in with the new password failed: {}", e))?;
+    session.close(manager).await;
+    result.verified = true;
+    Ok(())
+}
+
+/// Run the plan, stopping at the first command the device rejects. Errors
+/// name the plan's command template, never the filled-in password.
+async fn change_password(
+    manager: &SessionManager,
+    session_id: &str,
+    commands: &[&str],
+    config: &SessionConfig,
+    password: &str,
+) -> Result<(), String> {
+    let replies = [
+        (&*CURRENT_PASSWORD_RE, config.password.as_str()),
+        (&*NEW_PASSWORD_RE, password),
+    ];
+    for template in commands {
+        let command = fill(template, &config.username, password);
+        let output =
+            command::run_answering(manager, session_id, &command, &replies, COMMAND_TIMEOUT)
+                .await
+                .map_err(|e: SessionError| format!("`{}` failed: {}", redact(template), e))?;
+        let rejected = command::rejection(&output)
+            .or_else(|| PASSWD_FAILED_RE.find(&output).map(|m| m.as_str().trim()));
+        if let Some(line) = rejected {
+            return Err(format!("`{}` rejected: {}", redact(template), line));
+        }
+    }
+    Ok(())
+}
+
+fn fill(template: &str, user: &str, password: &str) -> String {
+    template
+        .replace("{user}", user)
+        .replace("{password}", password)
+}
+
+fn redact(template: &str) -> String {
+    template.replace("{password}", "********")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(dialect: DeviceDialect) -> RotationTarget {
+        RotationTarget {
+            id: "p1".to_string(),
+            name: "core-1".to_string(),
+            config: serde_json::from_value(serde_json::json!({
+                "host": "10.0.0.1",
+                "port": 22,
+                "protocol": "ssh",
+                "username": "admin",
+                "password": "old",
+                "cols": 80,
+                "rows": 24,
+                "dialect": dialect,
+            }))
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_plans_and_validate() {
+        let vrp = plan(DeviceDialect::Vrp).unwrap();
+        assert_eq!(
+            fill(vrp[2], "admin", "N3w-Secret"),
+            "local-user admin password irreversible-cipher N3w-Secret"
+        );
+        assert_eq!(
+            redact(vrp[2]),
+            "local-user {user} password irreversible-cipher ********"
+        );
+        assert!(plan(DeviceDialect::Linux)
+            .unwrap()
+            .iter()
+            .all(|c| !c.contains("{password}")));
+
+        assert!(validate(&[target(DeviceDialect::Ios)], "N3w-Secret").is_ok());
+        assert!(validate(&[], "N3w-Secret").is_err());
+        assert!(validate(&[target(DeviceDialect::Vrp)], "new secret").is_err());
+        assert!(matches!(
+            validate(&[target(DeviceDialect::Mml)], "N3w-Secret"),
+            Err(RotationError::Unsupported(DeviceDialect::Mml))
+        ));
+
+        assert!(NEW_PASSWORD_RE.is_match("Changing password for admin.\nNew password: "));
+        assert!(NEW_PASSWORD_RE.is_match("Retype new password:"));
+        assert!(CURRENT_PASSWORD_RE.is_match("Current password: "));
+        assert!(!NEW_PASSWORD_RE.is_match("Current password: "));
+        assert!(PASSWD_FAILED_RE.is_match("passwd: Authentication token manipulation error\n"));
+        assert!(!PASSWD_FAILED_RE.is_match("passwd: password updated successfully"));
+    }
+}
@@ -0,0 +1,1263 @@
+//! Automation scripts run against the sessions they are started on.
+//!
+//! Scripts are a small line-oriented language rather than a general purpose
+//! one: each line is a statement, so a script can only drive its sessions,
+//! look up log lines in the tracer and emit events. There is no file or
+//! network access, every wait is bounded by a timeout, and a run stops after
+//! `MAX_STEPS` statements, loop iterations included.
+//!
+//! ```text
+//! # Comments start with '#'; strings take \r \n \t \" \\ escapes and $var
+//! for device in "$sessions"      # the sessions the run was started on
+//!     use "$device"              # later statements drive this session
+//!     sendline "screen-length 0 temporary"
+//!     expect "<[^>]+>" 10
+//!     run "display version"
+//!     if "$output" matches "V200R0(19|20)"
+//!         emit version "$output"
+//!     else
+//!         log "$device needs an upgrade"
+//!     end
+//! end
+//! set serial "$output"
+//! export serial          # visible to later scripts and rules on the session
+//! match "$output"
+//! while not "$source"
+//!     run "display logbuffer"
+//!     match "$output"
+//! end
+//! ```
+//!
+//! Conditions are `[not] <text>`, holding unless the text is empty,
+//! `[not] <a> == <b>`, `[not] <a> != <b>` and `[not] <text> matches <regex>`.
+
+use crate::command::{self, OutputWatcher, DEFAULT_COMMAND_TIMEOUT};
+use crate::dryrun::DryRunReport;
+use crate::jobs::{JobControl, JobKind, JobRegistry};
+use crate::session::{SessionError, SessionManager};
+use crate::tracer::LogTracer;
+use crate::vars::Scope;
+use dashmap::DashMap;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Longest script accepted by `load`
+const MAX_STATEMENTS: usize = 1000;
+
+/// Longest single `sleep`
+const MAX_SLEEP_MS: u64 = 60_000;
+
+/// Most statements a run executes, loop iterations included
+const MAX_STEPS: usize = 100_000;
+
+#[derive(Error, Debug)]
+pub enum ScriptError {
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error("Script not found: {0}")]
+    NotFound(String),
+    #[error("Line {line}: {source}")]
+    Session {
+        line: usize,
+        #[source]
+        source: SessionError,
+    },
+    #[error("Line {line}: {message}")]
+    Failed { line: usize, message: String },
+    #[error("Stopped")]
+    Stopped,
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl From<ScriptError> for String {
+    fn from(err: ScriptError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Statement {
+    /// Send text as is
+    Send(String),
+    /// Send text followed by CR
+    SendLine(String),
+    /// Wait for a regex; sets `$before` and `$match`
+    Expect {
+        pattern: String,
+        timeout: Duration,
+    },
+    /// `command::run_command`; sets `$output` and `$prompt`
+    Run {
+        command: String,
+        timeout: Duration,
+    },
+    /// Match each line against the tracer index; sets `$source`
+    Match(String),
+    Emit {
+        name: String,
+        payload: String,
+    },
+    Set {
+        var: String,
+        value: String,
+    },
+    /// Copy a variable to the session scope, for later scripts and rules
+    Export(String),
+    Sleep(Duration),
+    Log(String),
+    Fail(String),
+    /// Drive another session of the run from here on; sets `$session`
+    Use(String),
+    /// Jumps to `otherwise` unless the condition holds
+    If {
+        condition: Condition,
+        otherwise: usize,
+    },
+    /// Ends the `if` branch, jumping to `end`
+    Else {
+        end: usize,
+    },
+    /// Jumps to `exit` unless the condition holds
+    While {
+        condition: Condition,
+        exit: usize,
+    },
+    /// Sets `var` to each whitespace-separated word of `over`, then jumps to
+    /// `exit`
+    For {
+        var: String,
+        over: String,
+        exit: usize,
+    },
+    /// Closes a block; loops jump back to their `header`
+    End {
+        header: Option<usize>,
+    },
+}
+
+/// Test of an `if` or `while`
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    negated: bool,
+    text: String,
+    test: Test,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Test {
+    /// `text` isn't empty
+    NonEmpty,
+    Equals(String),
+    /// `text` contains a match of the regex
+    Matches(String),
+}
+
+impl Condition {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let (negated, args) = match args.split_first() {
+            Some((first, rest)) if first == "not" => (true, rest),
+            _ => (false, args),
+        };
+        let (negated, test) = match args {
+            [_] => (negated, Test::NonEmpty),
+            [_, op, other] => match op.as_str() {
+                "==" => (negated, Test::Equals(other.clone())),
+                "!=" => (!negated, Test::Equals(other.clone())),
+                "matches" => {
+                    if !other.contains('$') {
+                        Regex::new(other).map_err(|e| format!("invalid pattern: {}", e))?;
+                    }
+                    (negated, Test::Matches(other.clone()))
+                }
+                other => return Err(format!("unknown comparison `{}`", other)),
+            },
+            _ => return Err(
+                "conditions take `[not] <text>` and an optional `==`, `!=` or `matches` operand"
+                    .to_string(),
+            ),
+        };
+        Ok(Self {
+            negated,
+            text: args[0].clone(),
+            test,
+        })
+    }
+
+    /// The strings interpolated to evaluate it
+    fn operands(&self) -> Vec<&str> {
+        match &self.test {
+            Test::NonEmpty => vec![&self.text],
+            Test::Equals(other) | Test::Matches(other) => vec![&self.text, other],
+        }
+    }
+
+    fn eval(&self, vars: &HashMap<String, String>) -> Result<bool, String> {
+        let text = interpolate(&self.text, vars)?;
+        let holds = match &self.test {
+            Test::NonEmpty => !text.is_empty(),
+            Test::Equals(other) => text == interpolate(other, vars)?,
+            Test::Matches(pattern) => Regex::new(&interpolate(pattern, vars)?)
+                .map_err(|e| format!("invalid pattern: {}", e))?
+                .is_match(&text),
+        };
+        Ok(holds != self.negated)
+    }
+}
+
+/// Loop state of a run or a dry run
+#[derive(Default)]
+struct Flow {
+    /// Words left to each running `for`, by statement index
+    loops: HashMap<usize, VecDeque<String>>,
+    steps: usize,
+    /// The last statement jumped back to a loop header
+    looping: bool,
+}
+
+impl Flow {
+    /// Count a statement against `MAX_STEPS`
+    fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(format!("stopped after {} statements", MAX_STEPS));
+        }
+        Ok(())
+    }
+
+    /// Start the `for` at `pc` over `words`
+    fn enter(&mut self, pc: usize, words: Vec<String>) {
+        self.loops.insert(pc, words.into());
+    }
+
+    /// Next word of the `for` at `pc`, None once the loop is over
+    fn next_word(&mut self, pc: usize) -> Option<String> {
+        let word = self.loops.get_mut(&pc).and_then(VecDeque::pop_front);
+        if word.is_none() {
+            self.loops.remove(&pc);
+        }
+        word
+    }
+
+    /// Record where control went after the statement
+    fn jumped(&mut self, statement: &Statement) {
+        self.looping = matches!(statement, Statement::End { header: Some(_) });
+    }
+}
+
+/// A parsed script
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub name: String,
+    /// Statements with their source line numbers
+    statements: Vec<(usize, Statement)>,
+}
+
+/// Loaded script as listed to the frontend
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptInfo {
+    pub name: String,
+    pub statements: usize,
+}
+
+/// Body of `script:{run_id}` events
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScriptEvent {
+    Log {
+        line: usize,
+        message: String,
+    },
+    Emit {
+        line: usize,
+        name: String,
+        payload: String,
+    },
+    Finished,
+    Failed {
+        error: String,
+    },
+    Stopped,
+}
+
+impl Script {
+    pub fn parse(name: &str, source: &str) -> Result<Self, ScriptError> {
+        let mut statements: Vec<(usize, Statement)> = Vec::new();
+        // Open `if`, `else`, `while` and `for` statements, innermost last
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let fail = |message: String| ScriptError::Parse { line, message };
+            let mut words = tokenize(text).map_err(fail)?.into_iter();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let args: Vec<String> = words.collect();
+            let mut statement = parse_statement(&keyword, &args).map_err(fail)?;
+            let at = statements.len();
+            match &mut statement {
+                Statement::If { .. } | Statement::While { .. } | Statement::For { .. } => {
+                    blocks.push((at, line));
+                }
+                Statement::Else { .. } => {
+                    let open = blocks.pop().map(|(open, _)| open);
+                    match open.map(|open| &mut statements[open].1) {
+                        Some(Statement::If { otherwise, .. }) => *otherwise = at + 1,
+                        _ => return Err(fail("`else` outside an `if`".to_string())),
+                    }
+                    blocks.push((at, line));
+                }
+                Statement::End { header } => {
+                    let (open, _) = blocks
+                        .pop()
+                        .ok_or_else(|| fail("`end` outside a block".to_string()))?;
+                    match &mut statements[open].1 {
+                        Statement::If { otherwise, .. } => *otherwise = at + 1,
+                        Statement::Else { end } => *end = at + 1,
+                        Statement::While { exit, .. } | Statement::For { exit, .. } => {
+                            *exit = at + 1;
+                            *header = Some(open);
+                        }
+                        _ => unreachable!("only blocks are open"),
+                    }
+                }
+                _ => {}
+            }
+            statements.push((line, statement));
+            if statements.len() > MAX_STATEMENTS {
+                return Err(fail(format!("more than {} statements", MAX_STATEMENTS)));
+            }
+        }
+        if let Some(&(_, line)) = blocks.last() {
+            return Err(ScriptError::Parse {
+                line,
+                message: "block without an `end`".to_string(),
+            });
+        }
+        Ok(Self {
+            name: name.to_string(),
+            statements,
+        })
+    }
+
+    pub fn info(&self) -> ScriptInfo {
+        ScriptInfo {
+            name: self.name.clone(),
+            statements: self.statements.len(),
+        }
+    }
+}
+
+impl Script {
+    /// Walk the statements as `execute` would, recording what would be sent
+    /// instead of sending it. `sessions` are the run's session ids with
+    /// their hosts, starting on the first; `session_vars` are the first
+    /// session's exported variables. Values the device would supply become
+    /// `<$name from line N>` placeholders, and a condition on one is planned
+    /// as holding, a `while` for one pass. Planning stops where the run
+    /// would fail.
+    pub fn dry_run(
+        &self,
+        sessions: &[(String, String)],
+        session_vars: BTreeMap<String, String>,
+    ) -> DryRunReport {
+        let (session_id, host) = &sessions[0];
+        let mut planner = Planner {
+            sessions,
+            session_id,
+            host,
+            report: DryRunReport::default(),
+            vars: session_vars.into_iter().collect(),
+            placeholders: Vec::new(),
+            flow: Flow::default(),
+        };
+        planner
+            .vars
+            .insert("session".to_string(), session_id.clone());
+        planner.vars.insert(
+            "sessions".to_string(),
+            sessions
+                .iter()
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        let mut pc = 0;
+        while let Some((line, statement)) = self.statements.get(pc) {
+            match planner.plan(pc, *line, statement) {
+                Ok(next) => pc = next,
+                Err(message) => {
+                    let session_id = planner.session_id;
+                    planner.report.issue(Some(session_id), Some(*line), message);
+                    break;
+                }
+            }
+        }
+        planner.report
+    }
+}
+
+/// State of a dry run, see `Script::dry_run`
+struct Planner<'a> {
+    sessions: &'a [(String, String)],
+    session_id: &'a str,
+    host: &'a str,
+    report: DryRunReport,
+    vars: HashMap<String, String>,
+    /// Values standing in for device output
+    placeholders: Vec<String>,
+    flow: Flow,
+}
+
+impl Planner<'_> {
+    /// Plan the statement at `pc`, returning the index of the next one
+    fn plan(&mut self, pc: usize, line: usize, statement: &Statement) -> Result<usize, String> {
+        self.flow.step()?;
+        let mut next = pc + 1;
+        match statement {
+            Statement::Send(value) => {
+                let data = interpolate(value, &self.vars)?;
+                self.report.send(self.session_id, self.host, line, data);
+            }
+            Statement::SendLine(value) => {
+                let data = format!("{}\r", interpolate(value, &self.vars)?);
+                self.report.send(self.session_id, self.host, line, data);
+            }
+            Statement::Expect { pattern, timeout } => {
+                let pattern = interpolate(pattern, &self.vars)?;
+                // A pattern built from device output is only known at run time
+                if !self.is_placeholder(&pattern) {
+                    Regex::new(&pattern).map_err(|e| format!("invalid pattern: {}", e))?;
+                }
+                self.report
+                    .wait(format!("expect /{}/ ({}s)", pattern, timeout.as_secs()));
+                self.answer("before", line);
+                self.answer("match", line);
+            }
+            Statement::Run { command, timeout } => {
+                let data = format!("{}\r", interpolate(command, &self.vars)?);
+                self.report.send(self.session_id, self.host, line, data);
+                self.report.wait(format!("prompt ({}s)", timeout.as_secs()));
+                self.answer("output", line);
+                self.answer("prompt", line);
+            }
+            Statement::Match(value) => {
+                interpolate(value, &self.vars)?;
+                self.answer("source", line);
+            }
+            Statement::Emit { name, payload } => {
+                interpolate(name, &self.vars)?;
+                interpolate(payload, &self.vars)?;
+            }
+            Statement::Set { var, value } => {
+                let value = interpolate(value, &self.vars)?;
+                self.vars.insert(var.clone(), value);
+            }
+            Statement::Export(var) => {
+                if !self.vars.contains_key(var) {
+                    return Err(format!("unknown variable `${}`", var));
+                }
+            }
+            Statement::Sleep(duration) => self
+                .report
+                .wait(format!("sleep {} ms", duration.as_millis())),
+            Statement::Log(value) => {
+                interpolate(value, &self.vars)?;
+            }
+            Statement::Fail(value) => {
+                return Err(format!(
+                    "the script fails here: {}",
+                    interpolate(value, &self.vars)?
+                ))
+            }
+            Statement::Use(value) => {
+                let id = interpolate(value, &self.vars)?;
+                let (id, host) = self
+                    .sessions
+                    .iter()
+                    .find(|(session_id, _)| *session_id == id)
+                    .ok_or_else(|| format!("`{}` isn't a session of this run", id))?;
+                self.session_id = id;
+                self.host = host;
+                self.vars.insert("session".to_string(), id.clone());
+            }
+            Statement::If {
+                condition,
+                otherwise,
+            } => {
+                let holds = match self.decide(condition)? {
+                    Some(holds) => holds,
+                    None => {
+                        self.assume(
+                            line,
+                            "the condition depends on device output, planned as holding",
+                        );
+                        true
+                    }
+                };
+                if !holds {
+                    next = *otherwise;
+                }
+            }
+            Statement::Else { end } => next = *end,
+            Statement::While { condition, exit } => {
+                let holds = match self.decide(condition)? {
+                    Some(holds) => holds,
+                    None if self.flow.looping => false,
+                    None => {
+                        self.assume(
+                            line,
+                            "the condition depends on device output, planned for one pass",
+                        );
+                        true
+                    }
+                };
+                if !holds {
+                    next = *exit;
+                }
+            }
+            Statement::For { var, over, exit } => {
+                if !self.flow.looping {
+                    let over = interpolate(over, &self.vars)?;
+                    let words = if self.is_placeholder(&over) {
+                        self.assume(
+                            line,
+                            "the words depend on device output, planned for one pass",
+                        );
+                        let word = format!("<${} from line {}>", var, line);
+                        self.placeholders.push(word.clone());
+                        vec![word]
+                    } else {
+                        over.split_whitespace().map(str::to_string).collect()
+                    };
+                    self.flow.enter(pc, words);
+                }
+                match self.flow.next_word(pc) {
+                    Some(word) => {
+                        self.vars.insert(var.clone(), word);
+                    }
+                    None => next = *exit,
+                }
+            }
+            Statement::End { header } => {
+                if let Some(header) = header {
+                    next = *header;
+                }
+            }
+        }
+        self.flow.jumped(statement);
+        Ok(next)
+    }
+
+    fn answer(&mut self, name: &str, line: usize) {
+        let value = format!("<${} from line {}>", name, line);
+        self.placeholders.push(value.clone());
+        self.vars.insert(name.to_string(), value);
+    }
+
+    fn is_placeholder(&self, value: &str) -> bool {
+        self.placeholders.iter().any(|p| value.contains(p.as_str()))
+    }
+
+    /// Evaluate a condition, None where it depends on device output
+    fn decide(&self, condition: &Condition) -> Result<Option<bool>, String> {
+        for operand in condition.operands() {
+            if self.is_placeholder(&interpolate(operand, &self.vars)?) {
+                return Ok(None);
+            }
+        }
+        condition.eval(&self.vars).map(Some)
+    }
+
+    /// Note a guess about device output, once per line
+    fn assume(&mut self, line: usize, message: &str) {
+        let noted = self
+            .report
+            .issues
+            .iter()
+            .any(|issue| issue.step == Some(line) && issue.message == message);
+        if !noted {
+            self.report
+                .issue(Some(self.session_id), Some(line), message.to_string());
+        }
+    }
+}
+
+fn parse_statement(keyword: &str, args: &[String]) -> Result<Statement, String> {
+    let arity = |min: usize, max: usize| {
+        if args.len() < min || args.len() > max {
+            Err(format!("`{}` takes {} to {} arguments", keyword, min, max))
+        } else {
+            Ok(())
+        }
+    };
+    let seconds = |arg: Option<&String>| match arg {
+        Some(value) => value
+            .parse::<u64>()
+            .map(Duration::from_secs)
+            .map_err(|_| format!("invalid timeout `{}`", value)),
+        None => Ok(DEFAULT_COMMAND_TIMEOUT),
+    };
+
+    let statement = match keyword {
+        "send" => {
+            arity(1, 1)?;
+            Statement::Send(args[0].clone())
+        }
+        "sendline" => {
+            arity(1, 1)?;
+            Statement::SendLine(args[0].clone())
+        }
+        "expect" => {
+            arity(1, 2)?;
+            // Patterns with variables are checked when they run
+            if !args[0].contains('$') {
+                Regex::new(&args[0]).map_err(|e| format!("invalid pattern: {}", e))?;
+            }
+            Statement::Expect {
+                pattern: args[0].clone(),
+                timeout: seconds(args.get(1))?,
+            }
+        }
+        "run" => {
+            arity(1, 2)?;
+            Statement::Run {
+                command: args[0].clone(),
+                timeout: seconds(args.get(1))?,
+            }
+        }
+        "match" => {
+            arity(1, 1)?;
+            Statement::Match(args[0].clone())
+        }
+        "emit" => {
+            arity(2, 2)?;
+            Statement::Emit {
+                name: args[0].clone(),
+                payload: args[1].clone(),
+            }
+        }
+        "set" => {
+            arity(2, 2)?;
+            if !is_identifier(&args[0]) {
+                return Err(format!("invalid variable name `{}`", args[0]));
+            }
+            Statement::Set {
+                var: args[0].clone(),
+                value: args[1].clone(),
+            }
+        }
+        "export" => {
+            arity(1, 1)?;
+            if !is_identifier(&args[0]) {
+                return Err(format!("invalid variable name `{}`", args[0]));
+            }
+            Statement::Export(args[0].clone())
+        }
+        "sleep" => {
+            arity(1, 1)?;
+            let ms = args[0]
+                .parse::<u64>()
+                .ok()
+                .filter(|ms| *ms <= MAX_SLEEP_MS)
+                .ok_or_else(|| format!("sleep takes 0 to {} ms", MAX_SLEEP_MS))?;
+            Statement::Sleep(Duration::from_millis(ms))
+        }
+        "log" => {
+            arity(1, 1)?;
+            Statement::Log(args[0].clone())
+        }
+        "fail" => {
+            arity(1, 1)?;
+            Statement::Fail(args[0].clone())
+        }
+        "use" => {
+            arity(1, 1)?;
+            Statement::Use(args[0].clone())
+        }
+        "if" => Statement::If {
+            condition: Condition::parse(args)?,
+            otherwise: 0,
+        },
+        "else" => {
+            arity(0, 0)?;
+            Statement::Else { end: 0 }
+        }
+        "while" => Statement::While {
+            condition: Condition::parse(args)?,
+            exit: 0,
+        },
+        "for" => {
+            if args.len() != 3 || args[1] != "in" {
+                return Err("`for` takes `<name> in <words>`".to_string());
+            }
+            if !is_identifier(&args[0]) {
+                return Err(format!("invalid variable name `{}`", args[0]));
+            }
+            Statement::For {
+                var: args[0].clone(),
+                over: args[2].clone(),
+                exit: 0,
+            }
+        }
+        "end" => {
+            arity(0, 0)?;
+            Statement::End { header: None }
+        }
+        other => return Err(format!("unknown statement `{}`", other)),
+    };
+    Ok(statement)
+}
+
+/// Split a line into words and quoted strings; `#` outside quotes starts a
+/// comment
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut word = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('r') => word.push('\r'),
+                        Some('n') => word.push('\n'),
+                        Some('t') => word.push('\t'),
+                        Some('"') => word.push('"'),
+                        Some('\\') => word.push('\\'),
+                        Some('$') => word.push_str("$$"),
+                        Some(other) => return Err(format!("unknown escape `\\{}`", other)),
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some(c) => word.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replace `$name` and `${name}` with variable values; `$$` is a literal `$`
+fn interpolate(text: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('$') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| "unterminated `${`".to_string())?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        if name.is_empty() {
+            out.push('$');
+        } else {
+            let value = vars
+                .get(name)
+                .ok_or_else(|| format!("unknown variable `${}`", name))?;
+            out.push_str(value);
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// What a running script may touch
+pub struct ScriptContext {
+    pub app_handle: tauri::AppHandle,
+    pub manager: Arc<SessionManager>,
+    pub tracer: Arc<Mutex<LogTracer>>,
+    /// Sessions `use` may switch to, the run starting on the first
+    pub sessions: Vec<String>,
+    pub run_id: String,
+}
+
+impl ScriptContext {
+    fn emit(&self, event: ScriptEvent) {
+        let name = format!("script:{}", self.run_id);
+        if let Err(e) = self.app_handle.emit(&name, &event) {
+            warn!(run_id = %self.run_id, error = %e, "Failed to emit script event");
+        }
+    }
+}
+
+/// Run the statements from the top, stopping at the first error. Pausing
+/// the job takes effect between statements.
+async fn execute(
+    script: &Script,
+    ctx: &ScriptContext,
+    job: &JobControl,
+) -> Result<(), ScriptError> {
+    let mut session_id = ctx.sessions[0].clone();
+    let mut vars: HashMap<String, String> = HashMap::new();
+    // Session variables exported earlier are visible, script ones win
+    vars.extend(
+        ctx.manager
+            .variables()
+            .snapshot(&Scope::Session(session_id.clone())),
+    );
+    vars.insert("session".to_string(), session_id.clone());
+    vars.insert("sessions".to_string(), ctx.sessions.join(" "));
+    let mut watcher = OutputWatcher::new(&ctx.manager, &session_id)
+        .map_err(|source| ScriptError::Session { line: 0, source })?;
+    let mut flow = Flow::default();
+
+    let total = script.statements.len() as u64;
+    let mut pc = 0;
+    while let Some((line, statement)) = script.statements.get(pc) {
+        let line = *line;
+        job.progress(pc as u64, total, Some(format!("line {}", line)));
+        job.checkpoint().await.map_err(|_| ScriptError::Stopped)?;
+        let failed = |message: String| ScriptError::Failed { line, message };
+        flow.step().map_err(failed)?;
+        let text = |value: &str| interpolate(value, &vars).map_err(failed);
+        let session_error = |source: SessionError| ScriptError::Session { line, source };
+        let mut next = pc + 1;
+
+        match statement {
+            Statement::Send(value) | Statement::SendLine(value) => {
+                let mut data = text(value)?;
+                if matches!(statement, Statement::SendLine(_)) {
+                    data.push('\r');
+                }
+                ctx.manager
+                    .send_data(&session_id, data.into_bytes())
+                    .await
+                    .map_err(session_error)?;
+            }
+            Statement::Expect { pattern, timeout } => {
+                let pattern = Regex::new(&text(pattern)?).map_err(|e| ScriptError::Failed {
+                    line,
+                    message: format!("invalid pattern: {}", e),
+                })?;
+                let expected = watcher
+                    .expect(&pattern, *timeout)
+                    .await
+                    .map_err(session_error)?;
+                store(ctx, &mut vars, "before", expected.before);
+                store(ctx, &mut vars, "match", expected.matched);
+            }
+            Statement::Run { command, timeout } => {
+                let command = text(command)?;
+                let result = command::run_command(&ctx.manager, &session_id, &command, *timeout)
+                    .await
+                    .map_err(session_error)?;
+                // Output up to here belongs to the command, not later expects
+                watcher = OutputWatcher::new(&ctx.manager, &session_id).map_err(session_error)?;
+                store(ctx, &mut vars, "output", result.output);
+                store(ctx, &mut vars, "prompt", result.prompt);
+            }
+            Statement::Match(value) => {
+                let value = text(value)?;
+                let tracer = ctx.tracer.lock().await;
+                let source = value
+                    .lines()
+                    .find_map(|l| tracer.match_log(l))
+                    .map(|location| format!("{}:{}", location.file, location.line))
+                    .unwrap_or_default();
+                store(ctx, &mut vars, "source", source);
+            }
+            Statement::Emit { name, payload } => ctx.emit(ScriptEvent::Emit {
+                line,
+                name: text(name)?,
+                payload: text(payload)?,
+            }),
+            Statement::Set { var, value } => {
+                let value = text(value)?;
+                store(ctx, &mut vars, var, value);
+            }
+            Statement::Export(var) => {
+                let value = vars
+                    .get(var)
+                    .cloned()
+                    .ok_or_else(|| failed(format!("unknown variable `${}`", var)))?;
+                ctx.manager
+                    .variables()
+                    .set(&Scope::Session(session_id.clone()), var, value)
+                    .map_err(|e| failed(e.to_string()))?;
+            }
+            Statement::Sleep(duration) => tokio::time::sleep(*duration).await,
+            Statement::Log(value) => ctx.emit(ScriptEvent::Log {
+                line,
+                message: text(value)?,
+            }),
+            Statement::Fail(value) => return Err(failed(text(value)?)),
+            Statement::Use(value) => {
+                let id = text(value)?;
+                if !ctx.sessions.contains(&id) {
+                    return Err(failed(format!("`{}` isn't a session of this run", id)));
+                }
+                watcher = OutputWatcher::new(&ctx.manager, &id).map_err(session_error)?;
+                store(ctx, &mut vars, "session", id.clone());
+                session_id = id;
+            }
+            Statement::If {
+                condition,
+                otherwise,
+            } => {
+                if !condition.eval(&vars).map_err(failed)? {
+                    next = *otherwise;
+                }
+            }
+            Statement::Else { end } => next = *end,
+            Statement::While { condition, exit } => {
+                if !condition.eval(&vars).map_err(failed)? {
+                    next = *exit;
+                }
+            }
+            Statement::For { var, over, exit } => {
+                if !flow.looping {
+                    let words = text(over)?.split_whitespace().map(str::to_string).collect();
+                    flow.enter(pc, words);
+                }
+                match flow.next_word(pc) {
+                    Some(word) => store(ctx, &mut vars, var, word),
+                    None => next = *exit,
+                }
+            }
+            Statement::End { header } => {
+                if let Some(header) = header {
+                    next = *header;
+                }
+            }
+        }
+        flow.jumped(statement);
+        pc = next;
+    }
+    Ok(())
+}
+
+/// Set a script variable, mirrored to the run's job scope for inspection
+fn store(ctx: &ScriptContext, vars: &mut HashMap<String, String>, name: &str, value: String) {
+    // Names were checked at parse time and the scope size is bounded
+    let _ = ctx
+        .manager
+        .variables()
+        .set(&Scope::Job(ctx.run_id.clone()), name, value.clone());
+    vars.insert(name.to_string(), value);
+}
+
+/// Loaded scripts; running ones are jobs in the `JobRegistry`
+pub struct ScriptManager {
+    scripts: DashMap<String, Arc<Script>>,
+}
+
+impl ScriptManager {
+    pub fn new() -> Self {
+        Self {
+            scripts: DashMap::new(),
+        }
+    }
+
+    /// Parse the script file at `path`, named after its file stem; loading
+    /// a name again replaces the script
+    pub fn load(&self, path: &Path) -> Result<ScriptInfo, ScriptError> {
+        let source = std::fs::read_to_string(path)?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "script".to_string());
+        let script = Script::parse(&name, &source)?;
+        info!(script = %name, statements = script.statements.len(), "Loaded script");
+        let info = script.info();
+        self.scripts.insert(name, Arc::new(script));
+        Ok(info)
+    }
+
+    pub fn list(&self) -> Vec<ScriptInfo> {
+        let mut scripts: Vec<ScriptInfo> = self.scripts.iter().map(|s| s.info()).collect();
+        scripts.sort_by(|a, b| a.name.cmp(&b.name));
+        scripts
+    }
+
+    /// Plan `name` on sessions without running it, see `Script::dry_run`
+    pub fn dry_run(
+        &self,
+        name: &str,
+        sessions: &[(String, String)],
+        session_vars: BTreeMap<String, String>,
+    ) -> Result<DryRunReport, ScriptError> {
+        let script = self
+            .scripts
+            .get(name)
+            .ok_or_else(|| ScriptError::NotFound(name.to_string()))?;
+        Ok(script.dry_run(sessions, session_vars))
+    }
+
+    /// Start `name` on the sessions of `ctx` as a job; progress arrives as
+    /// `script:{run_id}` events, and the run id is the job id
+    pub fn start(
+        &self,
+        name: &str,
+        mut ctx: ScriptContext,
+        jobs: &Arc<JobRegistry>,
+    ) -> Result<String, ScriptError> {
+        let script = self
+            .scripts
+            .get(name)
+            .map(|s| Arc::clone(&s))
+            .ok_or_else(|| ScriptError::NotFound(name.to_string()))?;
+        let job = jobs.start(
+            JobKind::Script,
+            format!("{} on {}", name, ctx.sessions.join(", ")),
+        );
+        let run_id = job.id().to_string();
+        ctx.run_id = run_id.clone();
+
+        tokio::spawn(async move {
+            info!(run_id = %ctx.run_id, script = %script.name, sessions = ?ctx.sessions, "Running script");
+            let result = tokio::select! {
+                result = execute(&script, &ctx, &job) => result,
+                _ = job.cancelled() => Err(ScriptError::Stopped),
+            };
+            let event = match &result {
+                Ok(()) => ScriptEvent::Finished,
+                Err(ScriptError::Stopped) => ScriptEvent::Stopped,
+                Err(e) => {
+                    warn!(run_id = %ctx.run_id, error = %e, "Script failed");
+                    ScriptEvent::Failed {
+                        error: e.to_string(),
+                    }
+                }
+            };
+            ctx.emit(event);
+            job.finish(
+                result
+                    .map(|()| format!("{} statements", script.statements.len()))
+                    .map_err(|e| e.to_string()),
+            );
+        });
+        Ok(run_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let source = r#"
+# Collect the version
+sendline "display version"   # trailing comment
+expect "<[^>]+>" 5
+run "display device"
+set greeting "hi \"$session\""
+export greeting
+sleep 200
+"#;
+        let script = Script::parse("version", source).unwrap();
+        assert_eq!(script.statements.len(), 6);
+        assert_eq!(
+            script.statements[0],
+            (3, Statement::SendLine("display version".to_string()))
+        );
+        assert_eq!(
+            script.statements[1].1,
+            Statement::Expect {
+                pattern: "<[^>]+>".to_string(),
+                timeout: Duration::from_secs(5),
+            }
+        );
+        assert!(matches!(
+            &script.statements[2].1,
+            Statement::Run { timeout, .. } if *timeout == DEFAULT_COMMAND_TIMEOUT
+        ));
+        assert_eq!(
+            script.statements[4].1,
+            Statement::Export("greeting".to_string())
+        );
+
+        for (bad, line) in [
+            ("frobnicate", 1),
+            ("send \"open", 1),
+            ("\nexpect \"(\"", 2),
+            ("sleep 999999", 1),
+            ("set 1-x y", 1),
+            ("log \"a\"\nif \"a\"", 2),
+            ("end", 1),
+            ("while \"a\"\nelse\nend", 2),
+            ("for x of \"a b\"\nend", 1),
+            ("if \"a\" ~ \"b\"\nend", 1),
+        ] {
+            match Script::parse("bad", bad) {
+                Err(ScriptError::Parse { line: at, .. }) => assert_eq!(at, line, "{}", bad),
+                other => panic!("{}: {:?}", bad, other.map(|s| s.statements)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_blocks() {
+        let source = "while \"$a\"\n  if \"$b\"\n  else\n    log \"c\"\n  end\nend";
+        let script = Script::parse("blocks", source).unwrap();
+        let statements: Vec<&Statement> = script.statements.iter().map(|(_, s)| s).collect();
+        assert!(matches!(statements[0], Statement::While { exit: 6, .. }));
+        assert!(matches!(statements[1], Statement::If { otherwise: 3, .. }));
+        assert_eq!(*statements[2], Statement::Else { end: 5 });
+        assert_eq!(*statements[4], Statement::End { header: None });
+        assert_eq!(*statements[5], Statement::End { header: Some(0) });
+
+        let condition = Condition::parse(&["not".into(), "$a".into(), "!=".into(), "b".into()]);
+        let vars = HashMap::from([("a".to_string(), "b".to_string())]);
+        assert!(condition.unwrap().eval(&vars).unwrap());
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let source = r#"
+run "display version"
+expect "$prompt" 5
+sendline "sysname ${site}-core"
+set copy "$output"
+fail "stop"
+sendline "never planned"
+"#;
+        let script = Script::parse("plan", source).unwrap();
+        let vars = BTreeMap::from([("site".to_string(), "lab".to_string())]);
+        let report = script.dry_run(&[("s1".to_string(), "10.0.0.1".to_string())], vars);
+        let sends: Vec<&str> = report.sends.iter().map(|s| s.data.as_str()).collect();
+        assert_eq!(sends, ["display version\r", "sysname lab-core\r"]);
+        assert_eq!(report.sends[0].step, 2);
+        // The expect waits on the run's prompt, known only at run time
+        assert_eq!(
+            report.sends[0].wait.as_deref(),
+            Some("expect /<$prompt from line 2>/ (5s)")
+        );
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].step, Some(6));
+
+        for (source, message) in [
+            ("sendline \"$missing\"", "unknown variable"),
+            ("set x \"(\"\nexpect \"$x\"", "invalid pattern"),
+        ] {
+            let script = Script::parse("plan", source).unwrap();
+            let report = script.dry_run(
+                &[("s1".to_string(), "10.0.0.1".to_string())],
+                BTreeMap::new(),
+            );
+            assert!(report.sends.is_empty());
+            assert!(report.issues[0].message.contains(message), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_dry_run_control_flow() {
+        let source = r#"
+for device in "$sessions"
+    use "$device"
+    if "$session" == "s2"
+        sendline "display version"
+    else
+        sendline "display device"
+    end
+end
+set n ""
+while not "$n" == "xxx"
+    set n "${n}x"
+    send "$n"
+end
+run "display alarm active"
+if "$output" matches "Critical"
+    sendline "display alarm history"
+end
+"#;
+        let script = Script::parse("flow", source).unwrap();
+        let sessions = [("s1", "10.0.0.1"), ("s2", "10.0.0.2")]
+            .map(|(id, host)| (id.to_string(), host.to_string()));
+        let report = script.dry_run(&sessions, BTreeMap::new());
+        let sends: Vec<(&str, &str)> = report
+            .sends
+            .iter()
+            .map(|s| (s.host.as_str(), s.data.as_str()))
+            .collect();
+        assert_eq!(
+            sends,
+            [
+                ("10.0.0.1", "display device\r"),
+                ("10.0.0.2", "display version\r"),
+                ("10.0.0.2", "x"),
+                ("10.0.0.2", "xx"),
+                ("10.0.0.2", "xxx"),
+                ("10.0.0.2", "display alarm active\r"),
+                ("10.0.0.2", "display alarm history\r"),
+            ]
+        );
+        // Only the condition on device output is a guess
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].step, Some(16));
+
+        for (source, message) in [
+            ("while \"y\"\nend", "stopped after"),
+            ("use \"s3\"", "isn't a session"),
+        ] {
+            let report = Script::parse("bad", source)
+                .unwrap()
+                .dry_run(&sessions, BTreeMap::new());
+            assert!(report.issues[0].message.contains(message), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let vars = HashMap::from([
+            ("output".to_string(), "slot 1".to_string()),
+            ("n".to_string(), "7".to_string()),
+        ]);
+        assert_eq!(
+            interpolate("got $output, ${n}x, $$5 and $", &vars).unwrap(),
+            "got slot 1, 7x, $5 and $"
+        );
+        assert!(interpolate("$missing", &vars).is_err());
+
+        // An escaped `\$` in a string stays literal
+        let words = tokenize(r#"log "cost \$n""#).unwrap();
+        assert_eq!(interpolate(&words[1], &vars).unwrap(), "cost $n");
+    }
+}
@@ -0,0 +1,683 @@
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Uncompressed size at which the open block is sealed: 64KB
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Open block size at which a single overlong line is cut anyway: 256KB
+const MAX_OPEN_SIZE: usize = 4 * BLOCK_SIZE;
+
+/// Default compressed size kept in memory per session: 32MB
+const DEFAULT_MAX_COMPRESSED: usize = 32 * 1024 * 1024;
+
+/// Default compressed size spilled to disk per session: 1GB
+const DEFAULT_MAX_SPILLED: u64 = 1024 * 1024 * 1024;
+
+/// Markers kept per session; the oldest goes first
+const MAX_MARKERS: usize = 1000;
+
+/// Largest spill file; disk space is reclaimed a whole file at a time
+const MAX_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Most results returned by one `search_output` call
+const MAX_SEARCH_RESULTS: usize = 1000;
+
+// CSI, OSC and two-byte escape sequences
+pub(crate) static ANSI_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-Z\\-_]|\r")
+        .unwrap()
+});
+
+/// A sealed run of whole lines, zstd-compressed
+struct Block {
+    first_line: u64,
+    line_count: u64,
+    raw_len: usize,
+    data: BlockData,
+}
+
+enum BlockData {
+    Memory(Vec<u8>),
+    /// Moved to a spill file segment
+    Spilled {
+        segment: u64,
+        offset: u64,
+        len: usize,
+    },
+}
+
+/// One append-only spill file, deleted when dropped
+struct Segment {
+    id: u64,
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), error = %e, "Failed to remove scrollback spill file");
+        }
+    }
+}
+
+/// Temp files holding blocks evicted from memory. The oldest file is
+/// deleted once the total passes `max_bytes`.
+struct Spill {
+    dir: PathBuf,
+    /// Unique per scrollback, so sessions never share files
+    prefix: String,
+    segments: VecDeque<Segment>,
+    next_id: u64,
+    segment_size: u64,
+    bytes: u64,
+    max_bytes: u64,
+}
+
+impl Spill {
+    fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self {
+            dir,
+            prefix: format!("scrollback-{}", Uuid::new_v4()),
+            segments: VecDeque::new(),
+            next_id: 0,
+            segment_size: (max_bytes / 4).clamp(1, MAX_SEGMENT_SIZE),
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    /// Append a compressed block; returns its segment and offset
+    fn write(&mut self, data: &[u8]) -> io::Result<(u64, u64)> {
+        let full = self
+            .segments
+            .back()
+            .is_none_or(|s| s.len + data.len() as u64 > self.segment_size);
+        if full {
+            std::fs::create_dir_all(&self.dir)?;
+            let path = self
+                .dir
+                .join(format!("{}-{}.zst", self.prefix, self.next_id));
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .read(true)
+                .write(true)
+                .open(&path)?;
+            debug!(path = %path.display(), "Opened scrollback spill file");
+            self.segments.push_back(Segment {
+                id: self.next_id,
+                path,
+                file,
+                len: 0,
+            });
+            self.next_id += 1;
+        }
+
+        let Some(segment) = self.segments.back_mut() else {
+            unreachable!("a segment was just created");
+        };
+        (&segment.file).seek(SeekFrom::Start(segment.len))?;
+        (&segment.file).write_all(data)?;
+        let offset = segment.len;
+        segment.len += data.len() as u64;
+        self.bytes += data.len() as u64;
+        Ok((segment.id, offset))
+    }
+
+    /// Delete the oldest files past `max_bytes`; returns the oldest
+    /// segment id still on disk
+    fn trim(&mut self) -> u64 {
+        while self.bytes > self.max_bytes && self.segments.len() > 1 {
+            if let Some(segment) = self.segments.pop_front() {
+                self.bytes -= segment.len;
+            }
+        }
+        self.segments.front().map_or(self.next_id, |s| s.id)
+    }
+
+    fn read(&self, segment: u64, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let file = self
+            .segments
+            .iter()
+            .find(|s| s.id == segment)
+            .map(|s| &s.file)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "spill segment deleted"))?;
+        let mut data = vec![0; len];
+        let mut file = file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Page of lines returned by `fetch_scrollback`
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrollbackPage {
+    /// Oldest line still retained
+    pub first_line: u64,
+    /// Line number one past the newest line
+    pub end_line: u64,
+    /// Line number of `lines[0]`
+    pub start_line: u64,
+    pub lines: Vec<String>,
+}
+
+/// A named point in the output, placed with `add_marker`
+#[derive(Debug, Clone, Serialize)]
+pub struct Marker {
+    pub id: String,
+    pub label: String,
+    /// Line the next output goes to: the prompt line, if output ends in one
+    pub line: u64,
+    /// Output bytes received before the marker
+    pub offset: u64,
+    /// Unix milliseconds
+    pub at: i64,
+}
+
+/// One line matched by `search_output`, with escape sequences stripped
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchMatch {
+    pub line: u64,
+    pub text: String,
+}
+
+/// Backend scrollback of a session's output.
+///
+/// Output is appended to an open block; once it reaches `BLOCK_SIZE` the
+/// complete lines in it are compressed into a sealed block indexed by line
+/// number. Past `max_compressed_bytes` the oldest blocks are spilled to
+/// temp files (see `with_spill`) or else evicted, so hours of device output
+/// stay resident in a few tens of MB.
+///
+/// Lines are split on `\n`. A line longer than `MAX_OPEN_SIZE` is cut and
+/// shows up as several lines.
+pub struct Scrollback {
+    blocks: VecDeque<Block>,
+    /// Number of leading blocks that are spilled
+    spilled: usize,
+    spill: Option<Spill>,
+    open: Vec<u8>,
+    /// Line number of the first line in `open`
+    open_first_line: u64,
+    /// Compressed bytes of blocks in memory
+    compressed_bytes: usize,
+    max_compressed_bytes: usize,
+    /// Output bytes appended since the session started
+    total_bytes: u64,
+    markers: VecDeque<Marker>,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_COMPRESSED).with_spill(
+            std::env::temp_dir().join("bspt-scrollback"),
+            DEFAULT_MAX_SPILLED,
+        )
+    }
+}
+
+impl Scrollback {
+    pub fn new(max_compressed_bytes: usize) -> Self {
+        Self {
+            blocks: VecDeque::new(),
+            spilled: 0,
+            spill: None,
+            open: Vec::new(),
+            open_first_line: 0,
+            compressed_bytes: 0,
+            max_compressed_bytes,
+            total_bytes: 0,
+            markers: VecDeque::new(),
+        }
+    }
+
+    /// Spill blocks past the memory cap to files in `dir`, keeping up to
+    /// `max_bytes` on disk before the oldest are evicted.
+    pub fn with_spill(mut self, dir: impl AsRef<Path>, max_bytes: u64) -> Self {
+        self.spill = Some(Spill::new(dir.as_ref().to_path_buf(), max_bytes));
+        self
+    }
+
+    /// Append raw session output.
+    pub fn append(&mut self, data: &[u8]) {
+        self.open.extend_from_slice(data);
+        self.total_bytes += data.len() as u64;
+
+        while self.open.len() >= BLOCK_SIZE {
+            match self.open.iter().rposition(|&b| b == b'\n') {
+                Some(pos) => self.seal(pos + 1),
+                None if self.open.len() >= MAX_OPEN_SIZE => self.seal(self.open.len()),
+                None => break,
+            }
+        }
+    }
+
+    /// Oldest retained line number.
+    pub fn first_line(&self) -> u64 {
+        self.blocks
+            .front()
+            .map_or(self.open_first_line, |b| b.first_line)
+    }
+
+    /// Line number one past the newest line, counting a trailing partial line.
+    pub fn end_line(&self) -> u64 {
+        self.open_first_line + split_lines(&self.open).len() as u64
+    }
+
+    /// Compressed bytes held in sealed blocks.
+    #[cfg(test)]
+    pub fn compressed_bytes(&self) -> usize {
+        self.compressed_bytes
+    }
+
+    /// Get up to `count` lines starting at `start_line`.
+    ///
+    /// A start before the oldest retained line is clamped to it.
+    pub fn fetch(&self, start_line: u64, count: usize) -> ScrollbackPage {
+        let start_line = start_line.max(self.first_line());
+        let end = start_line.saturating_add(count as u64);
+        let mut lines = Vec::new();
+
+        self.for_each_line(start_line, |number, line| {
+            if number >= end {
+                return false;
+            }
+            lines.push(String::from_utf8_lossy(line).into_owned());
+            true
+        });
+
+        ScrollbackPage {
+            first_line: self.first_line(),
+            end_line: self.end_line(),
+            start_line,
+            lines,
+        }
+    }
+
+    /// Raw output of the last `count` lines, as a terminal received it.
+    pub fn tail(&self, count: u64) -> Vec<u8> {
+        let start_line = self.end_line().saturating_sub(count);
+        let mut out = Vec::new();
+        self.for_each_line(start_line, |_, line| {
+            out.extend_from_slice(line);
+            out.push(b'\n');
+            true
+        });
+        // The prompt line has no newline yet
+        if !self.open.is_empty() && !self.open.ends_with(b"\n") {
+            out.pop();
+        }
+        out
+    }
+
+    /// Place a marker where the next output goes.
+    pub fn mark(&mut self, label: String) -> Marker {
+        let partial = !self.open.is_empty() && !self.open.ends_with(b"\n");
+        let marker = Marker {
+            id: Uuid::new_v4().to_string(),
+            label,
+            line: self.end_line() - partial as u64,
+            offset: self.total_bytes,
+            at: chrono::Utc::now().timestamp_millis(),
+        };
+        if self.markers.len() >= MAX_MARKERS {
+            self.markers.pop_front();
+        }
+        self.markers.push_back(marker.clone());
+        marker
+    }
+
+    /// Markers, oldest first.
+    pub fn markers(&self) -> Vec<Marker> {
+        self.markers.iter().cloned().collect()
+    }
+
+    pub fn marker(&self, id: &str) -> Option<&Marker> {
+        self.markers.iter().find(|marker| marker.id == id)
+    }
+
+    /// Find lines matching `query`, oldest first, ignoring escape sequences.
+    ///
+    /// `query` is a regex if `is_regex`, otherwise a literal substring.
+    pub fn search(
+        &self,
+        query: &str,
+        is_regex: bool,
+        case_sensitive: bool,
+        max_results: usize,
+    ) -> Result<Vec<SearchMatch>, regex::Error> {
+        let pattern = if is_regex {
+            query.to_string()
+        } else {
+            regex::escape(query)
+        };
+        let re = RegexBuilder::new(&pattern)
+            .case_insensitive(!case_sensitive)
+            .build()?;
+        let max_results = max_results.min(MAX_SEARCH_RESULTS);
+
+        let mut matches = Vec::new();
+        self.for_each_line(0, |number, line| {
+            let text = String::from_utf8_lossy(line);
+            let text = ANSI_RE.replace_all(&text, "");
+            if re.is_match(&text) {
+                matches.push(SearchMatch {
+                    line: number,
+                    text: text.into_owned(),
+                });
+            }
+            matches.len() < max_results
+        });
+
+        Ok(matches)
+    }
+
+    /// Visit lines from `start_line` on until `f` returns false, only
+    /// decompressing blocks that contain them.
+    fn for_each_line(&self, start_line: u64, mut f: impl FnMut(u64, &[u8]) -> bool) {
+        for block in &self.blocks {
+            if block.first_line + block.line_count <= start_line {
+                continue;
+            }
+            let data = match &block.data {
+                BlockData::Memory(data) => Cow::Borrowed(data),
+                BlockData::Spilled {
+                    segment,
+                    offset,
+                    len,
+                } => {
+                    let read = self
+                        .spill
+                        .as_ref()
+                        .map(|spill| spill.read(*segment, *offset, *len));
+                    match read {
+                        Some(Ok(data)) => Cow::Owned(data),
+                        Some(Err(e)) => {
+                            warn!(error = %e, first_line = block.first_line, "Failed to read spilled scrollback");
+                            continue;
+                        }
+                        None => continue,
+                    }
+                }
+            };
+            let raw = match zstd::bulk::decompress(&data, block.raw_len) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    warn!(error = %e, first_line = block.first_line, "Corrupt scrollback block");
+                    continue;
+                }
+            };
+            for (i, line) in split_lines(&raw).into_iter().enumerate() {
+                let number = block.first_line + i as u64;
+                if number >= start_line && !f(number, line) {
+                    return;
+                }
+            }
+        }
+
+        for (i, line) in split_lines(&self.open).into_iter().enumerate() {
+            let number = self.open_first_line + i as u64;
+            if number >= start_line && !f(number, line) {
+                return;
+            }
+        }
+    }
+
+    /// Compress the first `len` bytes of the open block.
+    fn seal(&mut self, len: usize) {
+        let raw: Vec<u8> = self.open.drain(..len).collect();
+        let line_count = split_lines(&raw).len() as u64;
+
+        let data = match zstd::bulk::compress(&raw, COMPRESSION_LEVEL) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(error = %e, "Scrollback compression failed, block discarded");
+                self.open_first_line += line_count;
+                return;
+            }
+        };
+
+        debug!(
+            raw = raw.len(),
+            compressed = data.len(),
+            lines = line_count,
+            "Sealed scrollback block"
+        );
+        self.compressed_bytes += data.len();
+        self.blocks.push_back(Block {
+            first_line: self.open_first_line,
+            line_count,
+            raw_len: raw.len(),
+            data: BlockData::Memory(data),
+        });
+        self.open_first_line += line_count;
+
+        while self.compressed_bytes > self.max_compressed_bytes
+            && self.blocks.len() - self.spilled > 1
+        {
+            self.spill_oldest();
+        }
+    }
+
+    /// Move the oldest in-memory block to disk, or evict it without a spill.
+    fn spill_oldest(&mut self) {
+        let block = &mut self.blocks[self.spilled];
+        let BlockData::Memory(data) = &block.data else {
+            unreachable!("blocks after the spilled ones are in memory");
+        };
+        let len = data.len();
+
+        let Some(spill) = self.spill.as_mut() else {
+            self.blocks.remove(self.spilled);
+            self.compressed_bytes -= len;
+            return;
+        };
+
+        match spill.write(data) {
+            Ok((segment, offset)) => {
+                block.data = BlockData::Spilled {
+                    segment,
+                    offset,
+                    len,
+                };
+                self.spilled += 1;
+                self.compressed_bytes -= len;
+
+                let oldest = spill.trim();
+                while let Some(Block {
+                    data: BlockData::Spilled { segment, .. },
+                    ..
+                }) = self.blocks.front()
+                {
+                    if *segment >= oldest {
+                        break;
+                    }
+                    self.blocks.pop_front();
+                    self.spilled -= 1;
+                }
+            }
+            Err(e) => {
+                // Fall back to evicting from memory, dropping what is on disk
+                warn!(error = %e, "Scrollback spill failed, history limited to memory");
+                self.spill = None;
+                self.blocks.drain(..self.spilled);
+                self.spilled = 0;
+            }
+        }
+    }
+}
+
+/// Split on `\n`, without an empty piece after a trailing newline.
+fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    data.split(|&b| b == b'\n').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(lines: usize) -> Scrollback {
+        let mut scrollback = Scrollback::default();
+        for i in 0..lines {
+            scrollback.append(format!("GE0/0/{} \x1b[32mup\x1b[0m\r\n", i).as_bytes());
+        }
+        scrollback
+    }
+
+    #[test]
+    fn test_fetch_across_blocks() {
+        let mut scrollback = filled(20_000);
+        scrollback.append(b"<HUAWEI>");
+        assert!(scrollback.blocks.len() > 1);
+        assert_eq!(scrollback.first_line(), 0);
+        assert_eq!(scrollback.end_line(), 20_001);
+
+        let page = scrollback.fetch(12_345, 3);
+        assert_eq!(page.start_line, 12_345);
+        assert_eq!(page.lines[0], "GE0/0/12345 \x1b[32mup\x1b[0m\r");
+        assert_eq!(page.lines.len(), 3);
+
+        let page = scrollback.fetch(19_999, 10);
+        assert_eq!(
+            page.lines,
+            vec!["GE0/0/19999 \x1b[32mup\x1b[0m\r", "<HUAWEI>"]
+        );
+    }
+
+    #[test]
+    fn test_markers() {
+        let mut scrollback = Scrollback::default();
+        scrollback.append(b"line 1\r\nline 2\r\n<Huawei>");
+        let marker = scrollback.mark("before reboot".to_string());
+        // The prompt line, where the command echo lands
+        assert_eq!(marker.line, 2);
+        assert_eq!(marker.offset, 24);
+        scrollback.append(b"reboot\r\nSystem will reboot!\r\n");
+
+        let found = scrollback.marker(&marker.id).unwrap();
+        let page = scrollback.fetch(found.line, 2);
+        assert_eq!(page.lines[0].trim_end(), "<Huawei>reboot");
+
+        let after = scrollback.mark("after".to_string());
+        assert_eq!(after.line, 4);
+        let labels: Vec<String> = scrollback.markers().into_iter().map(|m| m.label).collect();
+        assert_eq!(labels, ["before reboot", "after"]);
+    }
+
+    #[test]
+    fn test_tail_is_raw_output() {
+        let mut scrollback = filled(20_000);
+        scrollback.append(b"<HUAWEI>");
+        assert_eq!(
+            scrollback.tail(2),
+            b"GE0/0/19999 \x1b[32mup\x1b[0m\r\n<HUAWEI>".to_vec()
+        );
+        let all = scrollback.tail(u64::MAX);
+        assert!(all.starts_with(b"GE0/0/0 ") && all.ends_with(b"\r\n<HUAWEI>"));
+
+        scrollback.append(b"\r\n");
+        assert!(scrollback.tail(1).ends_with(b"<HUAWEI>\r\n"));
+    }
+
+    #[test]
+    fn test_search_strips_escapes() {
+        let scrollback = filled(5000);
+        let matches = scrollback
+            .search("GE0/0/4321 UP", false, false, 10)
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].line, 4321);
+        assert_eq!(matches[0].text, "GE0/0/4321 up");
+
+        let matches = scrollback
+            .search(r"^GE0/0/49\d\d up$", true, true, 5)
+            .unwrap();
+        assert_eq!(matches.len(), 5);
+        assert!(scrollback.search("(", true, true, 5).is_err());
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut scrollback = Scrollback::new(1024);
+        // Poorly compressible data so blocks stay large
+        let mut state = 1u64;
+        for _ in 0..(8 * BLOCK_SIZE / 64) {
+            let line: String = (0..63)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (b'!' + (state >> 58) as u8) as char
+                })
+                .collect();
+            scrollback.append(format!("{}\n", line).as_bytes());
+        }
+        assert_eq!(scrollback.blocks.len(), 1);
+        assert!(scrollback.first_line() > 0);
+        assert!(scrollback.compressed_bytes() > 1024);
+        assert_eq!(scrollback.fetch(0, 1).start_line, scrollback.first_line());
+    }
+
+    #[test]
+    fn test_spill_to_disk() {
+        let dir = std::env::temp_dir().join(format!("bspt-scrollback-test-{}", Uuid::new_v4()));
+        let mut scrollback = Scrollback::new(1024).with_spill(&dir, 64 * 1024 * 1024);
+        let total = 50_000;
+        for i in 0..total {
+            scrollback.append(format!("line {} {}\n", i, i * 7919 % 10007).as_bytes());
+        }
+        assert!(scrollback.spilled > 0);
+        assert_eq!(scrollback.blocks.len() - scrollback.spilled, 1);
+        assert!(std::fs::read_dir(&dir).unwrap().count() > 0);
+
+        // Spilled lines are paged back in
+        assert_eq!(scrollback.first_line(), 0);
+        let page = scrollback.fetch(10, 2);
+        assert_eq!(page.lines, vec!["line 10 9141", "line 11 7053"]);
+        let matches = scrollback.search("line 123 ", false, true, 10).unwrap();
+        assert_eq!(matches[0].line, 123);
+        assert_eq!(scrollback.end_line(), total);
+
+        drop(scrollback);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spill_evicts_oldest_file() {
+        let dir = std::env::temp_dir().join(format!("bspt-scrollback-test-{}", Uuid::new_v4()));
+        let mut scrollback = Scrollback::new(1024).with_spill(&dir, 64 * 1024);
+        for i in 0..200_000 {
+            scrollback.append(format!("line {} {}\n", i, i * 7919 % 10007).as_bytes());
+        }
+        let spill = scrollback.spill.as_ref().unwrap();
+        assert!(spill.bytes <= spill.max_bytes);
+        assert!(scrollback.first_line() > 0);
+
+        let first = scrollback.first_line();
+        let page = scrollback.fetch(0, 1);
+        assert_eq!(page.start_line, first);
+        assert_eq!(
+            page.lines[0],
+            format!("line {} {}", first, first * 7919 % 10007)
+        );
+
+        drop(scrollback);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
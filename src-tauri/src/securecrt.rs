@@ -0,0 +1,289 @@
+//! SecureCRT session import
+//!
+//! SecureCRT keeps one `.ini` file per session under its `Sessions`
+//! directory, with sub-directories as folders. Each line is
+//! `T:"Key"=Value` where `T` is `S` (string), `D` (hex dword) or one of the
+//! multi-line types (`B`, `Z`) whose continuation lines are ignored here.
+
+use crate::charset::Charset;
+use crate::profile::{ImportIssue, ImportReport, Profile, TerminalSettings};
+use crate::session::{DeviceDialect, Protocol};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use tracing::info;
+use walkdir::WalkDir;
+
+/// Per-folder and template files that are not sessions
+const NON_SESSION_FILES: [&str; 2] = ["__FolderData__.ini", "Default.ini"];
+
+/// Read every session under `root` into unsaved profiles (`imported`).
+/// Sub-directories become profile folders; report entries name sessions by
+/// their path relative to `root`, e.g. `Lab/core-1`.
+pub fn read_sessions(root: &Path) -> io::Result<ImportReport> {
+    if !root.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Not a directory: {}", root.display()),
+        ));
+    }
+
+    let mut report = ImportReport::default();
+    for entry in WalkDir::new(root).sort_by_file_name() {
+        let entry = entry.map_err(io::Error::from)?;
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy();
+        if !entry.file_type().is_file()
+            || !file_name.to_lowercase().ends_with(".ini")
+            || NON_SESSION_FILES.contains(&file_name.as_ref())
+        {
+            continue;
+        }
+
+        let name = session_name(root, path);
+        let text = decode(&std::fs::read(path)?);
+        match parse_session(&name, &text) {
+            Ok((profile, warnings)) => {
+                report
+                    .warnings
+                    .extend(warnings.into_iter().map(|message| ImportIssue {
+                        source: name.clone(),
+                        message,
+                    }));
+                report.imported.push(profile);
+            }
+            Err(message) => report.skipped.push(ImportIssue {
+                source: name,
+                message,
+            }),
+        }
+    }
+
+    info!(
+        root = %root.display(),
+        sessions = report.imported.len(),
+        skipped = report.skipped.len(),
+        "Read SecureCRT sessions"
+    );
+    Ok(report)
+}
+
+/// `Lab/core-1.ini` relative to the sessions root becomes `Lab/core-1`
+fn session_name(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path).with_extension("");
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Session files are UTF-8 (optionally with BOM) or UTF-16 with BOM
+fn decode(data: &[u8]) -> String {
+    match encoding_rs::Encoding::for_bom(data) {
+        Some((encoding, bom_len)) => encoding
+            .decode_without_bom_handling(&data[bom_len..])
+            .0
+            .into_owned(),
+        None => String::from_utf8_lossy(data).into_owned(),
+    }
+}
+
+/// Key → raw value for the single-line `S:` and `D:` entries
+fn parse_ini(text: &str) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    for line in text.lines() {
+        let Some(rest) = line
+            .strip_prefix("S:\"")
+            .or_else(|| line.strip_prefix("D:\""))
+        else {
+            continue;
+        };
+        if let Some((key, value)) = rest.split_once("\"=") {
+            values.insert(key.to_string(), value.trim_end().to_string());
+        }
+    }
+    values
+}
+
+fn dword(values: &HashMap<String, String>, key: &str) -> Option<u32> {
+    values
+        .get(key)
+        .and_then(|v| u32::from_str_radix(v.trim(), 16).ok())
+}
+
+fn string<'a>(values: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    values.get(key).map(|v| v.trim()).filter(|v| !v.is_empty())
+}
+
+/// Map SecureCRT's "Output Transformer Name" to a charset
+fn charset(name: &str) -> Option<Charset> {
+    let name = name.to_lowercase();
+    if name.contains("utf-8") || name.contains("utf8") {
+        Some(Charset::Utf8)
+    } else if name.contains("18030") {
+        Some(Charset::Gb18030)
+    } else if name.contains("gbk") || name.contains("2312") || name.contains("936") {
+        Some(Charset::Gbk)
+    } else if name.contains("8859-1") || name.contains("western") {
+        Some(Charset::Latin1)
+    } else {
+        None
+    }
+}
+
+/// Build a profile from one session file at `path` (`Lab/core-1`). Returns the reason when the
+/// session can't be imported, otherwise the profile plus warnings about
+/// settings that were dropped.
+fn parse_session(path: &str, text: &str) -> Result<(Profile, Vec<String>), String> {
+    let (folder, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let values = parse_ini(text);
+    let mut warnings = Vec::new();
+
+    let protocol_name = string(&values, "Protocol Name").unwrap_or("SSH2");
+    let (protocol, port) = match protocol_name.to_lowercase().as_str() {
+        "ssh2" => (Protocol::Ssh, dword(&values, "[SSH2] Port").unwrap_or(22)),
+        "telnet" => (Protocol::Telnet, dword(&values, "Port").unwrap_or(23)),
+        _ => return Err(format!("protocol {protocol_name} is not supported")),
+    };
+    let port = u16::try_from(port).map_err(|_| format!("invalid port {port}"))?;
+
+    let host = string(&values, "Hostname")
+        .ok_or_else(|| "no hostname".to_string())?
+        .to_string();
+
+    let mut terminal = TerminalSettings::default();
+    if let Some(cols) = dword(&values, "Cols").filter(|&c| c > 0) {
+        terminal.cols = cols;
+    }
+    if let Some(rows) = dword(&values, "Rows").filter(|&r| r > 0) {
+        terminal.rows = rows;
+    }
+    if let Some(transformer) = string(&values, "Output Transformer Name") {
+        match charset(transformer) {
+            Some(charset) => terminal.charset = charset,
+            None if transformer.eq_ignore_ascii_case("default") => {}
+            None => warnings.push(format!("character encoding {transformer} not supported")),
+        }
+    }
+
+    match string(&values, "Firewall Name") {
+        None | Some("None") => {}
+        Some(firewall) => match firewall.strip_prefix("Session:") {
+            Some(jump) => warnings.push(format!("jump through session {jump} not imported")),
+            None => warnings.push(format!("firewall {firewall} not imported")),
+        },
+    }
+
+    if dword(&values, "Use Login Script").is_some_and(|v| v != 0) {
+        warnings.push("logon script (expect/send) not imported".to_string());
+    }
+    if dword(&values, "Use Script File").is_some_and(|v| v != 0) {
+        let script = string(&values, "Script Filename V2")
+            .or_else(|| string(&values, "Script Filename"))
+            .unwrap_or("(unnamed)");
+        warnings.push(format!("logon script file {script} not imported"));
+    }
+
+    // Saved passwords are encrypted with SecureCRT's own key
+    if string(&values, "Password V2").is_some() || string(&values, "Password").is_some() {
+        warnings.push("saved password not imported, enter it on connect".to_string());
+    }
+
+    let profile = Profile {
+        id: String::new(),
+        name: name.to_string(),
+        folder: folder.to_string(),
+        tags: Vec::new(),
+        favorite: false,
+        host,
+        port,
+        protocol,
+        username: string(&values, "Username").unwrap_or_default().to_string(),
+        password: None,
+        prompt_credentials: false,
+        identity_file: None,
+        proxy_jump: None,
+        jump_profile: None,
+        connect_timeout_ms: None,
+        terminal,
+        dialect: DeviceDialect::default(),
+        logging: None,
+        plugins: Default::default(),
+        created_at: 0,
+        updated_at: 0,
+    };
+    Ok((profile, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SSH_SESSION: &str = "S:\"Username\"=admin\r\n\
+        S:\"Password V2\"=02:9f1c...\r\n\
+        S:\"Hostname\"=10.1.1.1\r\n\
+        D:\"[SSH2] Port\"=00000016\r\n\
+        S:\"Protocol Name\"=SSH2\r\n\
+        S:\"Firewall Name\"=Session:Lab/jump\r\n\
+        D:\"Use Login Script\"=00000001\r\n\
+        Z:\"Login Script V2\"=00000002\r\n \
+        ogin:\r\n \
+        admin\r\n\
+        B:\"Keymap\"=00000004\r\n \
+        00 01 02 03\r\n\
+        D:\"Cols\"=00000084\r\n\
+        D:\"Rows\"=00000032\r\n\
+        S:\"Output Transformer Name\"=Chinese Simplified (GB2312)\r\n";
+
+    #[test]
+    fn test_parse_session() {
+        let (profile, warnings) = parse_session("Lab/core-1", SSH_SESSION).unwrap();
+        assert_eq!(profile.host, "10.1.1.1");
+        assert_eq!(profile.port, 22);
+        assert_eq!(profile.protocol, Protocol::Ssh);
+        assert_eq!(profile.username, "admin");
+        assert!(profile.password.is_none());
+        assert_eq!((profile.terminal.cols, profile.terminal.rows), (132, 50));
+        assert_eq!(profile.terminal.charset, Charset::Gbk);
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].contains("Lab/jump"));
+
+        let telnet = "S:\"Hostname\"=r2\nS:\"Protocol Name\"=Telnet\nD:\"Port\"=00000907\n";
+        let (profile, warnings) = parse_session("r2", telnet).unwrap();
+        assert_eq!((profile.protocol, profile.port), (Protocol::Telnet, 2311));
+        assert!(warnings.is_empty());
+
+        let serial = "S:\"Protocol Name\"=Serial\nS:\"Hostname\"=\n";
+        assert!(parse_session("console", serial).is_err());
+    }
+
+    #[test]
+    fn test_read_sessions() {
+        let root =
+            std::env::temp_dir().join(format!("bspt-securecrt-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("Lab")).unwrap();
+        std::fs::write(root.join("Default.ini"), "S:\"Hostname\"=template\n").unwrap();
+        std::fs::write(root.join("Lab/__FolderData__.ini"), "S:\"Is Expanded\"=1\n").unwrap();
+        std::fs::write(root.join("Lab/core-1.ini"), SSH_SESSION).unwrap();
+        // UTF-8 with BOM, as written by recent SecureCRT versions
+        let mut bom = b"\xEF\xBB\xBF".to_vec();
+        bom.extend_from_slice(b"S:\"Hostname\"=10.2.2.2\nS:\"Protocol Name\"=Telnet\n");
+        std::fs::write(root.join("edge.ini"), bom).unwrap();
+        std::fs::write(root.join("old.ini"), "S:\"Protocol Name\"=SSH1\n").unwrap();
+
+        let report = read_sessions(&root).unwrap();
+        let names: Vec<_> = report
+            .imported
+            .iter()
+            .map(|p| (p.folder.as_str(), p.name.as_str()))
+            .collect();
+        assert_eq!(names, [("Lab", "core-1"), ("", "edge")]);
+        assert_eq!(report.imported[1].host, "10.2.2.2");
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].source, "old");
+        assert!(report.warnings.iter().all(|w| w.source == "Lab/core-1"));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
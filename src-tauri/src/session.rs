@@ -1,10 +1,38 @@
-use crate::ringbuffer::SessionRingBuffer;
+use crate::capture::CaptureTap;
+use crate::charset::Charset;
+use crate::events::EventBus;
+use crate::history::ConnectionHistory;
+use crate::known_hosts::KnownHosts;
+use crate::latency::LatencyTracker;
+use crate::metrics::Metrics;
+use crate::mux::ConnectionPool;
+use crate::passphrase::PassphrasePrompts;
+use crate::pipeline::{PipelineStages, Stage, StageStatus};
+use crate::plugin::{PluginRegistry, PluginSelection};
+use crate::plugin_session;
+use crate::ratelimit::RateLimitConfig;
+use crate::ringbuffer::{
+    BackpressureController, BufferConfig, BufferStats, DeliveryConfig, MemoryBudget,
+    SessionRingBuffer,
+};
+use crate::scrollback::{Marker, Scrollback, ScrollbackPage, SearchMatch};
+use crate::ssh;
+use crate::telnet;
+use crate::tunnel::TunnelRegistry;
+use crate::vars::VariableStore;
+use crate::views::ViewRegistry;
+use crate::zmodem::ZmodemCommand;
+use bytes::Bytes;
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 use tauri::AppHandle;
 use thiserror::Error;
-use tokio::sync::{mpsc, Mutex};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, Mutex};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -27,7 +55,8 @@ pub enum SessionState {
 }
 
 /// Policy for automatic reconnection with exponential backoff
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ReconnectPolicy {
     /// Whether auto-reconnect is enabled
     pub enabled: bool,
@@ -53,6 +82,254 @@ impl Default for ReconnectPolicy {
     }
 }
 
+/// Connection liveness probing (SSH keepalive, TCP keepalive for telnet)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeepaliveConfig {
+    /// Idle seconds before the first probe, 0 disables probing
+    pub interval_secs: u64,
+    /// Unanswered probes before the connection is considered lost, 0 never
+    /// gives up
+    pub max_missed: u32,
+    /// SSH only: disconnect after this many seconds without anything from
+    /// the server, 0 never does. Keepalive probes don't count as activity,
+    /// their replies do
+    pub inactivity_timeout_secs: u64,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            max_missed: 3,
+            inactivity_timeout_secs: 0,
+        }
+    }
+}
+
+/// Output silence that marks a session idle, for `session:{id}:idle` and
+/// `session:{id}:active`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActivityConfig {
+    /// Seconds without output before the session is idle, 0 disables
+    pub idle_after_secs: u64,
+    /// Output needed after going idle to count as active again, so a
+    /// redrawn prompt doesn't
+    pub active_min_bytes: usize,
+}
+
+impl Default for ActivityConfig {
+    fn default() -> Self {
+        Self {
+            idle_after_secs: 60,
+            active_min_bytes: 64,
+        }
+    }
+}
+
+/// Wire format of `session:{id}` data events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadEncoding {
+    /// JSON number array (one number per byte)
+    #[default]
+    Json,
+    /// Base64 string, decoded with `atob` on the frontend
+    Base64,
+}
+
+/// Terminal type sent in the SSH PTY request and telnet TERMINAL-TYPE
+pub const DEFAULT_TERMINAL_TYPE: &str = "xterm-256color";
+
+/// Connect timeout of sessions that don't set `connect_timeout_ms`
+pub const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 15_000;
+
+/// Command-line dialect spoken by the device; selects the prompt and
+/// pagination handling of the telnet output parser
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceDialect {
+    /// Huawei VRP router
+    #[default]
+    Vrp,
+    /// Cisco IOS
+    Ios,
+    /// H3C Comware
+    Comware,
+    /// Huawei MML (core network elements)
+    Mml,
+    /// Linux board shell
+    Linux,
+    Generic,
+}
+
+/// Line ending sent for Enter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewlineMode {
+    /// CR, as the terminal sends it
+    #[default]
+    Cr,
+    CrLf,
+    Lf,
+}
+
+impl NewlineMode {
+    /// Rewrite the CRs in frontend input to this line ending
+    pub fn translate(self, data: Vec<u8>) -> Vec<u8> {
+        let ending: &[u8] = match self {
+            NewlineMode::Cr => return data,
+            NewlineMode::CrLf => b"\r\n",
+            NewlineMode::Lf => b"\n",
+        };
+        if !data.contains(&b'\r') {
+            return data;
+        }
+        let mut out = Vec::with_capacity(data.len() + 8);
+        for byte in data {
+            if byte == b'\r' {
+                out.extend_from_slice(ending);
+            } else {
+                out.push(byte);
+            }
+        }
+        out
+    }
+}
+
+/// How an SSH session authenticates; telnet sessions ignore it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum SshAuth {
+    /// With `SessionConfig.password`
+    #[default]
+    Password,
+    /// With a private key (OpenSSH, PKCS#8 or PKCS#1 PEM), read from `path`
+    /// or given inline as `pem`
+    PublicKey {
+        #[serde(default)]
+        path: Option<String>,
+        #[serde(default)]
+        pem: Option<String>,
+        /// For an encrypted key
+        #[serde(default)]
+        passphrase: Option<String>,
+    },
+}
+
+impl SshAuth {
+    /// A key needs exactly one of `path` and `pem`
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            SshAuth::Password => Ok(()),
+            SshAuth::PublicKey { path, pem, .. } => match (path, pem) {
+                (Some(_), None) | (None, Some(_)) => Ok(()),
+                _ => Err("Key authentication needs either a key path or a PEM".to_string()),
+            },
+        }
+    }
+
+    /// The method without inline keys and passphrases, as kept in history
+    pub fn without_secrets(&self) -> Self {
+        match self {
+            SshAuth::PublicKey { path, .. } => SshAuth::PublicKey {
+                path: path.clone(),
+                pem: None,
+                passphrase: None,
+            },
+            SshAuth::Password => SshAuth::Password,
+        }
+    }
+
+    pub fn passphrase(&self) -> Option<&String> {
+        match self {
+            SshAuth::PublicKey { passphrase, .. } => passphrase.as_ref(),
+            SshAuth::Password => None,
+        }
+    }
+}
+
+/// SSH user authentication method, see `SessionConfig.auth_methods`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// The key of `SshAuth::PublicKey`; skipped with password auth
+    PublicKey,
+    /// Every prompt is answered with the password
+    KeyboardInteractive,
+    Password,
+}
+
+impl AuthMethod {
+    /// Order tried when a session names none
+    pub const DEFAULT_ORDER: [AuthMethod; 3] = [
+        AuthMethod::PublicKey,
+        AuthMethod::KeyboardInteractive,
+        AuthMethod::Password,
+    ];
+
+    /// Name in the SSH protocol
+    pub fn name(self) -> &'static str {
+        match self {
+            AuthMethod::PublicKey => "publickey",
+            AuthMethod::KeyboardInteractive => "keyboard-interactive",
+            AuthMethod::Password => "password",
+        }
+    }
+}
+
+/// Intermediate SSH host the session is tunnelled through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JumpHost {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// Password or private key, independent of the target's
+    #[serde(default)]
+    pub auth: SshAuth,
+}
+
+/// Built-in SSH algorithm lists, see `SshAlgorithms`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlgorithmPreset {
+    /// russh's defaults
+    #[default]
+    Modern,
+    /// The defaults followed by what old VRP images speak:
+    /// diffie-hellman-group1/14-sha1, ssh-rsa and the aes-cbc ciphers
+    Legacy,
+}
+
+/// SSH algorithms to offer, most preferred first. A list left empty comes
+/// from the preset
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshAlgorithms {
+    pub preset: AlgorithmPreset,
+    pub kex: Vec<String>,
+    pub host_key: Vec<String>,
+    pub cipher: Vec<String>,
+    pub mac: Vec<String>,
+}
+
+/// Transport hardening for security-sensitive networks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshHardening {
+    /// Rekey after this many bytes sent or received, at most 1 GiB; 0
+    /// keeps russh's 1 GiB
+    pub rekey_bytes: u64,
+    /// Rekey after this many seconds, 0 keeps russh's hour
+    pub rekey_secs: u64,
+    /// Offer only AES-GCM and AES-CTR ciphers with SHA-2 MACs; naming any
+    /// other cipher or MAC in `SessionConfig.algorithms` fails the connect
+    pub approved_only: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionConfig {
     pub host: String,
@@ -60,20 +337,138 @@ pub struct SessionConfig {
     pub protocol: Protocol,
     pub username: String,
     pub password: String,
+    /// Password or private key, for SSH
+    #[serde(default)]
+    pub auth: SshAuth,
     pub cols: u32,
     pub rows: u32,
+    #[serde(default)]
+    pub payload_encoding: PayloadEncoding,
+    /// Output buffer capacity and watermarks, None uses the global settings
+    #[serde(default)]
+    pub buffer: Option<BufferConfig>,
+    /// Character set of device output
+    #[serde(default)]
+    pub charset: Charset,
+    /// Cap on output forwarded to the terminal, None forwards everything
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Acked delivery of output to the frontend, None is best effort
+    #[serde(default)]
+    pub delivery: Option<DeliveryConfig>,
+    /// File that receives all session output
+    #[serde(default)]
+    pub log_file: Option<String>,
+    /// Prefix each line in `log_file` with the local time it arrived
+    #[serde(default)]
+    pub log_timestamps: bool,
+    /// File that receives the raw byte stream both ways, see capture.rs
+    #[serde(default)]
+    pub capture_file: Option<String>,
+    /// None uses the global settings
+    #[serde(default)]
+    pub keepalive: Option<KeepaliveConfig>,
+    /// None uses the global settings
+    #[serde(default)]
+    pub activity: Option<ActivityConfig>,
+    /// None uses `DEFAULT_TERMINAL_TYPE`
+    #[serde(default)]
+    pub terminal_type: Option<String>,
+    #[serde(default)]
+    pub newline: NewlineMode,
+    #[serde(default)]
+    pub dialect: DeviceDialect,
+    /// SSH hosts to tunnel through, outermost first (SSH sessions only)
+    #[serde(default)]
+    pub jump_hosts: Vec<JumpHost>,
+    /// Offered to the target and every jump host, None uses russh's
+    /// defaults (SSH sessions only)
+    #[serde(default)]
+    pub algorithms: Option<SshAlgorithms>,
+    /// Rekey limits and approved-only algorithms for the target and every
+    /// jump host, None keeps russh's behaviour (SSH sessions only)
+    #[serde(default)]
+    pub hardening: Option<SshHardening>,
+    /// How long the TCP connection to the device, or to each jump host,
+    /// may take; None uses `DEFAULT_CONNECT_TIMEOUT_MS`, 0 waits for the OS
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    /// Tried in order on the target until one is accepted, empty uses
+    /// `AuthMethod::DEFAULT_ORDER` (SSH sessions only)
+    #[serde(default)]
+    pub auth_methods: Vec<AuthMethod>,
+    /// SSH subsystem to start instead of a shell, such as `netconf`; the
+    /// session then has no PTY (SSH sessions only)
+    #[serde(default)]
+    pub subsystem: Option<String>,
+    /// Sent as `env` requests before the shell or subsystem starts, such as
+    /// `LANG`; servers drop names they don't accept. `TERM` replaces
+    /// `terminal_type` in the PTY request instead (SSH sessions only)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Transport, dialect and output processors from plugins, see plugin.rs
+    #[serde(default)]
+    pub plugins: PluginSelection,
+}
+
+impl SessionConfig {
+    pub fn terminal_type(&self) -> &str {
+        self.env
+            .get("TERM")
+            .or(self.terminal_type.as_ref())
+            .map_or(DEFAULT_TERMINAL_TYPE, String::as_str)
+    }
+
+    /// None waits for the OS to give up
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        match self
+            .connect_timeout_ms
+            .unwrap_or(DEFAULT_CONNECT_TIMEOUT_MS)
+        {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+}
+
+/// `TcpStream::connect`, failing with `TimedOut` after `timeout`
+pub(crate) async fn connect_tcp(addr: &str, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(addr).await;
+    };
+    tokio::time::timeout(timeout, TcpStream::connect(addr))
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no connection within {} ms", timeout.as_millis()),
+            ))
+        })
 }
 
 #[derive(Debug, Error)]
 pub enum SessionError {
     #[error("Session not found: {0}")]
     NotFound(String),
+    #[error("Window not found: {0}")]
+    WindowNotFound(String),
+    #[error("Marker not found: {0}")]
+    MarkerNotFound(String),
     #[error("Connection failed: {0}")]
     ConnectionFailed(String),
     #[error("Authentication failed: {0}")]
     AuthenticationFailed(String),
     #[error("Channel error: {0}")]
     ChannelError(String),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("Invalid search query: {0}")]
+    InvalidQuery(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    /// Disconnected while still connecting
+    #[error("Connect cancelled")]
+    Cancelled,
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 }
@@ -84,9 +479,18 @@ impl From<SessionError> for String {
     }
 }
 
+impl SessionError {
+    /// The state a connect that failed with this error ends in
+    pub fn end_state(&self) -> SessionState {
+        match self {
+            SessionError::Cancelled => SessionState::Disconnected,
+            _ => SessionState::Error,
+        }
+    }
+}
+
 pub struct SessionHandle {
     pub id: String,
-    #[allow(dead_code)]
     pub config: SessionConfig,
     #[allow(dead_code)]
     pub state: SessionState,
@@ -94,15 +498,54 @@ pub struct SessionHandle {
     pub shutdown_tx: mpsc::Sender<()>,
     pub resize_tx: mpsc::Sender<(u32, u32)>,
     pub auto_pagination_tx: Option<mpsc::Sender<bool>>,
+    /// BREAK requests with their length in ms; None where the transport
+    /// can't send one
+    pub break_tx: Option<mpsc::Sender<u32>>,
     /// Ring buffer for backpressure management
     pub buffer: Arc<Mutex<SessionRingBuffer>>,
+    /// Compressed history of session output
+    pub scrollback: Arc<Mutex<Scrollback>>,
     /// Channel to signal buffer drain from frontend
     pub drain_tx: mpsc::Sender<()>,
+    /// Pause state of the transport's network reads
+    pub backpressure: BackpressureController,
+    /// Live session output (see `EmitterHandle::output`)
+    pub output: broadcast::Sender<Bytes>,
+    /// Raw byte capture, started by `capture_file` or `start_capture`
+    pub capture: CaptureTap,
+    /// Answers to ZMODEM offers (see `zmodem_receive`)
+    pub zmodem_tx: mpsc::Sender<ZmodemCommand>,
+    /// Stages of the output data path, see pipeline.rs
+    pub pipeline: Arc<PipelineStages>,
 }
 
 pub struct SessionManager {
     sessions: DashMap<String, Arc<SessionHandle>>,
     app_handle: AppHandle,
+    /// Bytes buffered across all sessions
+    memory_budget: Arc<MemoryBudget>,
+    /// Successful connections, recorded by the session tasks
+    history: ConnectionHistory,
+    /// Accepted SSH host keys, see known_hosts.rs
+    known_hosts: Arc<KnownHosts>,
+    /// Key passphrases asked for by connecting sessions
+    passphrases: PassphrasePrompts,
+    /// VRP events parsed by the session tasks
+    events: EventBus,
+    /// Variables of automation steps, per session and per job
+    variables: VariableStore,
+    /// Counters for `get_metrics`
+    metrics: Metrics,
+    /// Round-trip samples per session
+    latency: LatencyTracker,
+    /// Plugins loaded from the plugins directory
+    plugins: PluginRegistry,
+    /// Windows showing each session
+    views: ViewRegistry,
+    /// SSH connections and their port forwards
+    tunnels: Arc<TunnelRegistry>,
+    /// SSH connections by the sessions running on them, see mux.rs
+    connections: ConnectionPool,
 }
 
 impl SessionManager {
@@ -110,13 +553,85 @@ impl SessionManager {
         Self {
             sessions: DashMap::new(),
             app_handle,
+            memory_budget: Arc::new(MemoryBudget::default()),
+            history: ConnectionHistory::default(),
+            known_hosts: Arc::new(KnownHosts::default()),
+            passphrases: PassphrasePrompts::default(),
+            events: EventBus::default(),
+            variables: VariableStore::default(),
+            metrics: Metrics::default(),
+            latency: LatencyTracker::default(),
+            plugins: PluginRegistry::default(),
+            views: ViewRegistry::default(),
+            tunnels: Arc::new(TunnelRegistry::default()),
+            connections: ConnectionPool::default(),
         }
     }
 
+    /// Persist connection history instead of keeping it in memory
+    pub fn with_history(mut self, history: ConnectionHistory) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Persist accepted host keys instead of keeping them in memory
+    pub fn with_known_hosts(mut self, known_hosts: KnownHosts) -> Self {
+        self.known_hosts = Arc::new(known_hosts);
+        self
+    }
+
     pub fn app_handle(&self) -> &AppHandle {
         &self.app_handle
     }
 
+    pub fn memory_budget(&self) -> &Arc<MemoryBudget> {
+        &self.memory_budget
+    }
+
+    pub fn history(&self) -> &ConnectionHistory {
+        &self.history
+    }
+
+    pub fn known_hosts(&self) -> &Arc<KnownHosts> {
+        &self.known_hosts
+    }
+
+    pub fn passphrases(&self) -> &PassphrasePrompts {
+        &self.passphrases
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    pub fn variables(&self) -> &VariableStore {
+        &self.variables
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn latency(&self) -> &LatencyTracker {
+        &self.latency
+    }
+
+    pub fn plugins(&self) -> &PluginRegistry {
+        &self.plugins
+    }
+
+    pub fn views(&self) -> &ViewRegistry {
+        &self.views
+    }
+
+    pub fn tunnels(&self) -> &Arc<TunnelRegistry> {
+        &self.tunnels
+    }
+
+    pub fn connections(&self) -> &ConnectionPool {
+        &self.connections
+    }
+
     pub fn generate_session_id() -> String {
         Uuid::new_v4().to_string()
     }
@@ -130,7 +645,19 @@ impl SessionManager {
         self.sessions.get(session_id).map(|r| Arc::clone(&r))
     }
 
+    /// Every open session
+    pub fn list(&self) -> Vec<Arc<SessionHandle>> {
+        self.sessions
+            .iter()
+            .map(|r| Arc::clone(r.value()))
+            .collect()
+    }
+
     pub fn remove(&self, session_id: &str) -> Option<Arc<SessionHandle>> {
+        self.latency.remove(session_id);
+        self.views.remove(session_id);
+        self.tunnels.remove(session_id);
+        // The session task leaves `connections` itself, see ssh.rs `serve`
         self.sessions.remove(session_id).map(|(_, v)| v)
     }
 
@@ -139,6 +666,8 @@ impl SessionManager {
             .get(session_id)
             .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
 
+        self.metrics.record_sent(session_id, data.len());
+        self.latency.sent(session_id, false);
         handle
             .input_tx
             .send(data)
@@ -146,6 +675,17 @@ impl SessionManager {
             .map_err(|e| SessionError::ChannelError(e.to_string()))
     }
 
+    /// Receive output recorded from now on
+    pub fn subscribe_output(
+        &self,
+        session_id: &str,
+    ) -> Result<broadcast::Receiver<Bytes>, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+        Ok(handle.output.subscribe())
+    }
+
     pub async fn resize(
         &self,
         session_id: &str,
@@ -190,6 +730,38 @@ impl SessionManager {
         Ok(())
     }
 
+    /// Send a BREAK, e.g. to stop a router's boot at its boot menu through a
+    /// console server
+    pub async fn send_break(&self, session_id: &str, duration_ms: u32) -> Result<(), SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let tx = handle.break_tx.as_ref().ok_or_else(|| {
+            SessionError::ChannelError("BREAK isn't supported by this transport".to_string())
+        })?;
+        tx.send(duration_ms)
+            .await
+            .map_err(|e| SessionError::ChannelError(e.to_string()))
+    }
+
+    /// Answer or cancel a ZMODEM transfer the device started
+    pub async fn zmodem(
+        &self,
+        session_id: &str,
+        command: ZmodemCommand,
+    ) -> Result<(), SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        handle
+            .zmodem_tx
+            .send(command)
+            .await
+            .map_err(|e| SessionError::ChannelError(e.to_string()))
+    }
+
     /// Notify the session that the frontend has drained buffer data.
     /// This signals the read loop to check if it can resume reading.
     pub async fn notify_drained(&self, session_id: &str) -> Result<(), SessionError> {
@@ -203,4 +775,199 @@ impl SessionManager {
             .await
             .map_err(|e| SessionError::ChannelError(e.to_string()))
     }
+
+    /// Release output of a session with acked delivery, up to and
+    /// including chunk `seq`
+    pub async fn ack_output(&self, session_id: &str, seq: u64) -> Result<(), SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+        if !handle.config.delivery.unwrap_or_default().acked() {
+            return Err(SessionError::InvalidConfig(
+                "session output isn't acked".to_string(),
+            ));
+        }
+
+        let mut buf = handle.buffer.lock().await;
+        buf.ack(seq).ok_or_else(|| {
+            SessionError::InvalidConfig(format!("output chunk {} wasn't sent yet", seq))
+        })?;
+        handle.backpressure.update(&buf);
+        drop(buf);
+        // Room in the unacked window, let the emitter continue
+        let _ = handle.drain_tx.try_send(());
+        Ok(())
+    }
+
+    /// Emitted output of a session with acked delivery not acked yet, with
+    /// the chunks' sequence numbers
+    pub async fn unacked_output(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<(u64, Bytes)>, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let unacked = handle.buffer.lock().await.unacked();
+        Ok(unacked)
+    }
+
+    /// Change buffer capacity and watermarks of a running session.
+    pub async fn set_buffer_config(
+        &self,
+        session_id: &str,
+        config: BufferConfig,
+    ) -> Result<(), SessionError> {
+        config.validate().map_err(SessionError::InvalidConfig)?;
+
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+        handle
+            .config
+            .delivery
+            .unwrap_or_default()
+            .validate(&config, handle.config.rate_limit.is_some())
+            .map_err(SessionError::InvalidConfig)?;
+
+        // A larger buffer may let paused reads continue
+        let mut buf = handle.buffer.lock().await;
+        buf.set_config(config);
+        handle.backpressure.update(&buf);
+        Ok(())
+    }
+
+    /// Get fill and backpressure counters of a session's output buffer.
+    pub async fn buffer_stats(&self, session_id: &str) -> Result<BufferStats, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let stats = handle.buffer.lock().await.stats();
+        Ok(stats)
+    }
+
+    /// Get up to `count` scrollback lines starting at `start_line`.
+    pub async fn fetch_scrollback(
+        &self,
+        session_id: &str,
+        start_line: u64,
+        count: usize,
+    ) -> Result<ScrollbackPage, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let page = handle.scrollback.lock().await.fetch(start_line, count);
+        Ok(page)
+    }
+
+    /// Get up to `count` scrollback lines from `before` lines ahead of a
+    /// marker.
+    pub async fn fetch_from_marker(
+        &self,
+        session_id: &str,
+        marker_id: &str,
+        before: u64,
+        count: usize,
+    ) -> Result<ScrollbackPage, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let scrollback = handle.scrollback.lock().await;
+        let line = scrollback
+            .marker(marker_id)
+            .ok_or_else(|| SessionError::MarkerNotFound(marker_id.to_string()))?
+            .line;
+        Ok(scrollback.fetch(line.saturating_sub(before), count))
+    }
+
+    /// Place a named marker at the current end of a session's output.
+    pub async fn add_marker(
+        &self,
+        session_id: &str,
+        label: String,
+    ) -> Result<Marker, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let marker = handle.scrollback.lock().await.mark(label);
+        Ok(marker)
+    }
+
+    pub async fn markers(&self, session_id: &str) -> Result<Vec<Marker>, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let markers = handle.scrollback.lock().await.markers();
+        Ok(markers)
+    }
+
+    pub fn pipeline(&self, session_id: &str) -> Result<Vec<StageStatus>, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        Ok(handle.pipeline.status())
+    }
+
+    /// Switch a stage of a session's data path; applies from the next read
+    pub fn set_pipeline_stage(
+        &self,
+        session_id: &str,
+        stage: Stage,
+        enabled: bool,
+    ) -> Result<Vec<StageStatus>, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        handle.pipeline.set(stage, enabled)?;
+        Ok(handle.pipeline.status())
+    }
+
+    /// Search a session's scrollback for lines matching `query`.
+    pub async fn search_output(
+        &self,
+        session_id: &str,
+        query: &str,
+        is_regex: bool,
+        case_sensitive: bool,
+        max_results: usize,
+    ) -> Result<Vec<SearchMatch>, SessionError> {
+        let handle = self
+            .get(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let scrollback = handle.scrollback.lock().await;
+        scrollback
+            .search(query, is_regex, case_sensitive, max_results)
+            .map_err(|e| SessionError::InvalidQuery(e.to_string()))
+    }
+}
+
+/// Run a session until it ends, over its plugin transport when the config
+/// names one, else over SSH or telnet
+pub async fn run_session(
+    session_id: String,
+    config: SessionConfig,
+    manager: Arc<SessionManager>,
+) -> Result<(), SessionError> {
+    let processors = manager
+        .plugins()
+        .processors(&config.plugins.processors)
+        .map_err(|e| SessionError::InvalidConfig(e.to_string()))?;
+    if config.plugins.transport.is_some() {
+        return plugin_session::run_plugin_session(session_id, config, manager, processors).await;
+    }
+    match config.protocol {
+        Protocol::Ssh => ssh::run_ssh_session(session_id, config, manager, processors).await,
+        Protocol::Telnet => {
+            telnet::run_telnet_session(session_id, config, manager, processors).await
+        }
+    }
 }
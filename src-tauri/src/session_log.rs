@@ -0,0 +1,83 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Append-only log of everything a session received, before any rate
+/// limiting or dropping on the way to the frontend.
+///
+/// Writes go straight to the file, so output up to a crash is kept.
+/// With `timestamps`, each line starts with the local time its first byte
+/// arrived.
+pub struct SessionLog {
+    path: PathBuf,
+    file: File,
+    timestamps: bool,
+    /// The next byte written starts a new line
+    at_line_start: bool,
+}
+
+impl SessionLog {
+    /// Open `path` for appending, creating parent directories as needed.
+    pub fn open(path: &Path, timestamps: bool) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            timestamps,
+            at_line_start: true,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if !self.timestamps {
+            return self.file.write_all(data);
+        }
+
+        let mut out = Vec::with_capacity(data.len() + 32);
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            if self.at_line_start {
+                let now = chrono::Local::now().format("[%Y-%m-%d %H:%M:%S%.3f] ");
+                out.extend_from_slice(now.to_string().as_bytes());
+            }
+            out.extend_from_slice(line);
+            self.at_line_start = line.ends_with(b"\n");
+        }
+        self.file.write_all(&out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamped_lines() {
+        let dir = std::env::temp_dir().join(format!("bspt-log-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("device.log");
+        let mut log = SessionLog::open(&path, true).unwrap();
+        log.write(b"<core-1>display ver").unwrap();
+        log.write(b"sion\r\nVRP (R) software\r\n<core-1>").unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.split('\n').collect();
+        assert_eq!(lines.len(), 3);
+        for line in &lines {
+            assert!(
+                line.starts_with('[') && line[24..].starts_with("] "),
+                "{line}"
+            );
+        }
+        assert!(lines[0].ends_with("<core-1>display version\r"));
+        assert!(lines[2].ends_with("] <core-1>"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,389 @@
+use crate::deeplink::DeepLinkSettings;
+use crate::device_archive;
+use crate::forward::ForwardSettings;
+use crate::ringbuffer::BufferConfig;
+use crate::session::{ActivityConfig, KeepaliveConfig, ReconnectPolicy, SessionConfig};
+use crate::tracer::{LogTracer, TracerLimits};
+use crate::vault::Vault;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// How often the settings file is checked for outside edits
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("Invalid settings: {0}")]
+    Invalid(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<SettingsError> for String {
+    fn from(err: SettingsError) -> String {
+        err.to_string()
+    }
+}
+
+/// Session logging defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingSettings {
+    /// Sessions without a `log_file` log to `<dir>/<host>-<session id>.log`;
+    /// None leaves them unlogged
+    pub session_log_dir: Option<String>,
+}
+
+/// Per-device archive of logs, configs, alarms and transcripts (see
+/// device_archive.rs)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveSettings {
+    /// Root of the archive; None disables it
+    pub dir: Option<String>,
+    /// Files older than this are removed; 0 keeps them forever
+    pub max_age_days: u64,
+    /// Oldest files are removed past this size per device; 0 is unlimited
+    pub max_device_mb: u64,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_age_days: 90,
+            max_device_mb: 1024,
+        }
+    }
+}
+
+/// Credential vault behaviour
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VaultSettings {
+    /// Lock the vault after this many seconds without use; 0 never locks
+    pub auto_lock_secs: u64,
+}
+
+impl Default for VaultSettings {
+    fn default() -> Self {
+        Self {
+            auto_lock_secs: 15 * 60,
+        }
+    }
+}
+
+/// Scheduled configuration backups (see backup.rs)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    /// Minutes between scheduled backups; 0 disables the schedule
+    pub interval_mins: u64,
+    /// Profiles backed up on schedule
+    pub profile_ids: Vec<String>,
+    pub command: String,
+    /// Snapshots kept per device; the oldest are pruned
+    pub keep: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            interval_mins: 0,
+            profile_ids: Vec::new(),
+            command: "display current-configuration".to_string(),
+            keep: 100,
+        }
+    }
+}
+
+/// App-wide defaults, stored as `settings.json` in the app config dir
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub reconnect: ReconnectPolicy,
+    pub buffer: BufferConfig,
+    pub logging: LoggingSettings,
+    pub archive: ArchiveSettings,
+    pub keepalive: KeepaliveConfig,
+    pub activity: ActivityConfig,
+    pub tracer: TracerLimits,
+    pub vault: VaultSettings,
+    pub backup: BackupSettings,
+    pub deep_links: DeepLinkSettings,
+    pub forwarding: ForwardSettings,
+}
+
+impl Settings {
+    fn validate(&self) -> Result<(), SettingsError> {
+        self.buffer.validate().map_err(SettingsError::Invalid)?;
+        if self.reconnect.backoff_multiplier < 1.0 {
+            return Err(SettingsError::Invalid(
+                "reconnect backoff_multiplier must be at least 1".to_string(),
+            ));
+        }
+        if self.backup.command.trim().is_empty() {
+            return Err(SettingsError::Invalid(
+                "backup command must not be empty".to_string(),
+            ));
+        }
+        self.forwarding.validate().map_err(SettingsError::Invalid)?;
+        Ok(())
+    }
+
+    /// Fill the parts of a new session's config that were left to the
+    /// global defaults
+    pub fn apply(&self, config: &mut SessionConfig, session_id: &str) {
+        config.buffer.get_or_insert(self.buffer);
+        config.keepalive.get_or_insert(self.keepalive);
+        config.activity.get_or_insert(self.activity);
+        if config.log_file.is_none() {
+            if let Some(dir) = &self.logging.session_log_dir {
+                let file = format!("{}-{}.log", config.host, session_id);
+                config.log_file = Some(Path::new(dir).join(file).to_string_lossy().into_owned());
+            } else if let Some(dir) = &self.archive.dir {
+                let file = device_archive::log_path(dir, &config.host, session_id);
+                config.log_file = Some(file.to_string_lossy().into_owned());
+            }
+        }
+    }
+}
+
+/// Settings file plus the last version read from or written to it.
+///
+/// Edits made outside the app are picked up by `spawn_watcher`; an edit that
+/// doesn't parse or validate is logged and the previous settings stay live.
+pub struct SettingsStore {
+    path: PathBuf,
+    settings: Settings,
+    /// Modification time of the file when last read or written
+    modified: Option<SystemTime>,
+}
+
+impl SettingsStore {
+    /// Load `path`, falling back to defaults when it is missing or invalid.
+    pub fn load(path: PathBuf) -> Self {
+        let mut store = Self {
+            path,
+            settings: Settings::default(),
+            modified: None,
+        };
+        match store.read() {
+            Ok(Some(settings)) => store.settings = settings,
+            Ok(None) => {}
+            Err(e) => {
+                warn!(path = %store.path.display(), error = %e, "Invalid settings file, using defaults")
+            }
+        }
+        info!(path = %store.path.display(), "Loaded settings");
+        store
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.clone()
+    }
+
+    pub fn set(&mut self, settings: Settings) -> Result<(), SettingsError> {
+        settings.validate()?;
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&settings)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        self.modified = modified_time(&self.path);
+        self.settings = settings;
+        Ok(())
+    }
+
+    /// Re-read the file if it changed since the last read or write. Returns
+    /// the new settings when they were applied.
+    pub fn reload_if_changed(&mut self) -> Option<Settings> {
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+
+        match self.read() {
+            Ok(Some(settings)) if settings != self.settings => {
+                self.settings = settings;
+                Some(self.settings.clone())
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!(path = %self.path.display(), error = %e, "Ignoring invalid settings file");
+                None
+            }
+        }
+    }
+
+    /// Parse and validate the file, recording its modification time.
+    /// `Ok(None)` when it doesn't exist.
+    fn read(&mut self) -> Result<Option<Settings>, SettingsError> {
+        self.modified = modified_time(&self.path);
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let settings: Settings = serde_json::from_slice(&data)?;
+        settings.validate()?;
+        Ok(Some(settings))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Push settings that apply to running components rather than new sessions
+pub async fn apply_live(settings: &Settings, tracer: &Mutex<LogTracer>, vault: &Mutex<Vault>) {
+    tracer.lock().await.set_limits(settings.tracer.clone());
+    vault
+        .lock()
+        .await
+        .set_auto_lock(Duration::from_secs(settings.vault.auto_lock_secs));
+}
+
+/// Poll the settings file and apply outside edits, emitting
+/// `settings-changed` with the new settings.
+pub fn spawn_watcher(
+    app_handle: tauri::AppHandle,
+    store: Arc<Mutex<SettingsStore>>,
+    tracer: Arc<Mutex<LogTracer>>,
+    vault: Arc<Mutex<Vault>>,
+) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(settings) = store.lock().await.reload_if_changed() else {
+                continue;
+            };
+
+            info!("Settings file changed, applying");
+            apply_live(&settings, &tracer, &vault).await;
+            if let Err(e) = app_handle.emit("settings-changed", &settings) {
+                warn!(error = %e, "Failed to emit settings change");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Protocol;
+    use std::collections::HashMap;
+
+    fn temp_path() -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("bspt-settings-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("settings.json");
+        (dir, path)
+    }
+
+    #[test]
+    fn test_partial_file_and_reload() {
+        let (dir, path) = temp_path();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, r#"{"keepalive": {"interval_secs": 10}}"#).unwrap();
+
+        let mut store = SettingsStore::load(path.clone());
+        assert_eq!(store.get().keepalive.interval_secs, 10);
+        assert_eq!(store.get().keepalive.max_missed, 3);
+        assert!(store.reload_if_changed().is_none());
+
+        // Outside edit; force a different mtime on coarse filesystems
+        store.modified = None;
+        std::fs::write(&path, r#"{"reconnect": {"max_retries": 2}}"#).unwrap();
+        let reloaded = store.reload_if_changed().unwrap();
+        assert_eq!(reloaded.reconnect.max_retries, 2);
+        assert_eq!(reloaded.keepalive.interval_secs, 30);
+
+        // Invalid edits keep the previous settings
+        store.modified = None;
+        std::fs::write(&path, r#"{"reconnect": {"backoff_multiplier": 0.5}}"#).unwrap();
+        assert!(store.reload_if_changed().is_none());
+        assert_eq!(store.get().reconnect.max_retries, 2);
+
+        let mut settings = store.get();
+        settings.buffer.capacity = 0;
+        assert!(matches!(
+            store.set(settings),
+            Err(SettingsError::Invalid(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_fills_defaults_only() {
+        let mut settings = Settings::default();
+        settings.keepalive.interval_secs = 5;
+        settings.logging.session_log_dir = Some("/var/log/bspt".to_string());
+
+        let mut config = SessionConfig {
+            host: "10.0.0.1".to_string(),
+            port: 23,
+            protocol: Protocol::Telnet,
+            username: String::new(),
+            password: String::new(),
+            auth: Default::default(),
+            cols: 80,
+            rows: 24,
+            payload_encoding: Default::default(),
+            buffer: None,
+            charset: Default::default(),
+            rate_limit: None,
+            delivery: None,
+            log_file: None,
+            log_timestamps: false,
+            capture_file: None,
+            keepalive: Some(KeepaliveConfig {
+                interval_secs: 60,
+                max_missed: 1,
+                inactivity_timeout_secs: 0,
+            }),
+            activity: None,
+            terminal_type: None,
+            newline: Default::default(),
+            dialect: Default::default(),
+            jump_hosts: Vec::new(),
+            algorithms: None,
+            hardening: None,
+            connect_timeout_ms: None,
+            auth_methods: Vec::new(),
+            subsystem: None,
+            env: HashMap::new(),
+            plugins: Default::default(),
+        };
+        settings.apply(&mut config, "abc");
+        assert_eq!(config.buffer, Some(settings.buffer));
+        assert_eq!(config.keepalive.unwrap().interval_secs, 60);
+        assert_eq!(config.activity, Some(settings.activity));
+        assert_eq!(
+            config.log_file.as_deref().map(Path::new),
+            Some(Path::new("/var/log/bspt/10.0.0.1-abc.log"))
+        );
+
+        // The archive logs sessions when no log dir is set
+        settings.logging.session_log_dir = None;
+        settings.archive.dir = Some("/srv/bspt".to_string());
+        config.log_file = None;
+        settings.apply(&mut config, "abc");
+        assert_eq!(
+            config.log_file.as_deref().map(Path::new),
+            Some(Path::new("/srv/bspt/10.0.0.1/logs/10.0.0.1-abc.log"))
+        );
+    }
+}
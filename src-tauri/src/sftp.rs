@@ -0,0 +1,803 @@
+//! SFTP (version 3, draft-ietf-secsh-filexfer-02) over SSH sessions.
+//!
+//! Each operation opens its own `sftp` subsystem channel on the session's
+//! connection (see tunnel.rs), so browsing and transfers don't touch the
+//! terminal and can run side by side. Requests on a channel go one at a
+//! time. Transfers move 32 KiB per request and report progress as
+//! `session:{id}:sftp`, at most every 250ms and once at the end.
+
+use crate::session::{SessionError, SessionManager};
+use russh::{client, ChannelMsg, ChannelStream};
+use serde::Serialize;
+use std::io;
+use std::time::Duration;
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const VERSION: u32 = 3;
+
+/// Bytes per read or write request, what every server takes
+const CHUNK: u32 = 32 * 1024;
+
+/// Longest reply accepted
+const MAX_PACKET: usize = 256 * 1024;
+
+/// Wait for one reply
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_RMDIR: u8 = 15;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+
+const OPEN_READ: u32 = 0x01;
+const OPEN_WRITE: u32 = 0x02;
+const OPEN_CREATE: u32 = 0x08;
+const OPEN_TRUNCATE: u32 = 0x10;
+
+const ATTR_SIZE: u32 = 0x01;
+const ATTR_UIDGID: u32 = 0x02;
+const ATTR_PERMISSIONS: u32 = 0x04;
+const ATTR_ACMODTIME: u32 = 0x08;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+#[derive(Error, Debug)]
+pub enum SftpError {
+    #[error(transparent)]
+    Session(#[from] SessionError),
+    #[error("Server does not support SFTP")]
+    Unsupported,
+    /// An `SSH_FXP_STATUS` other than OK
+    #[error("{path}: {message} (status {code})")]
+    Status {
+        path: String,
+        code: u32,
+        message: String,
+    },
+    #[error("Invalid SFTP reply: {0}")]
+    Protocol(String),
+    #[error("Timed out waiting for the server")]
+    Timeout,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<SftpError> for String {
+    fn from(err: SftpError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// A directory entry, as listed by `sftp_list`
+#[derive(Debug, Clone, Serialize)]
+pub struct SftpEntry {
+    pub name: String,
+    /// The listed directory joined with the name
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: Option<u64>,
+    /// Mode bits without the file type, e.g. 0o644
+    pub permissions: Option<u32>,
+    /// Unix seconds
+    pub modified: Option<u32>,
+}
+
+/// A listed directory: its absolute path, then directories and files by
+/// name, without `.` and `..`
+#[derive(Debug, Clone, Serialize)]
+pub struct SftpListing {
+    pub path: String,
+    pub entries: Vec<SftpEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Download,
+    Upload,
+}
+
+/// Body of `session:{id}:sftp` events, and the result of a transfer
+#[derive(Debug, Clone, Serialize)]
+pub struct SftpTransfer {
+    pub id: String,
+    pub session_id: String,
+    pub direction: Direction,
+    pub remote_path: String,
+    pub local_path: String,
+    pub bytes: u64,
+    /// None when the server doesn't report the size
+    pub total: Option<u64>,
+    pub done: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    modified: Option<u32>,
+}
+
+impl Attrs {
+    fn kind(&self) -> EntryKind {
+        match self.permissions.map(|mode| mode & 0o170000) {
+            Some(0o040000) => EntryKind::Directory,
+            Some(0o100000) => EntryKind::File,
+            Some(0o120000) => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        }
+    }
+}
+
+/// An outgoing packet, length-prefixed by `finish`
+struct Packet(Vec<u8>);
+
+impl Packet {
+    fn new(kind: u8) -> Self {
+        Self(vec![kind])
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn u64(mut self, value: u64) -> Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn string(self, value: &[u8]) -> Self {
+        let mut packet = self.u32(value.len() as u32);
+        packet.0.extend_from_slice(value);
+        packet
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut out = (self.0.len() as u32).to_be_bytes().to_vec();
+        out.extend(self.0);
+        out
+    }
+}
+
+/// Fields of an incoming packet
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SftpError> {
+        if self.data.len() < n {
+            return Err(SftpError::Protocol("truncated packet".to_string()));
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, SftpError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SftpError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], SftpError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, SftpError> {
+        Ok(String::from_utf8_lossy(self.bytes()?).into_owned())
+    }
+
+    fn attrs(&mut self) -> Result<Attrs, SftpError> {
+        let flags = self.u32()?;
+        let mut attrs = Attrs::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(self.u64()?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            self.take(8)?;
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(self.u32()?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            self.u32()?;
+            attrs.modified = Some(self.u32()?);
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.bytes()?;
+                self.bytes()?;
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+/// An SFTP conversation over any byte stream, normally a channel
+pub struct SftpClient<S> {
+    stream: S,
+    next_id: u32,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SftpClient<S> {
+    /// Exchange `SSH_FXP_INIT` and `SSH_FXP_VERSION`
+    async fn init(mut stream: S) -> Result<Self, SftpError> {
+        let init = Packet::new(FXP_INIT).u32(VERSION).finish();
+        stream.write_all(&init).await?;
+        let (kind, body) = read_packet(&mut stream).await?;
+        if kind != FXP_VERSION {
+            return Err(SftpError::Protocol(format!(
+                "expected VERSION, got {}",
+                kind
+            )));
+        }
+        let version = Reader::new(&body).u32()?;
+        if version < VERSION {
+            return Err(SftpError::Protocol(format!(
+                "server speaks version {}",
+                version
+            )));
+        }
+        Ok(Self { stream, next_id: 0 })
+    }
+
+    /// Send a request built on its id; the reply type and the body after
+    /// the id
+    async fn request(
+        &mut self,
+        kind: u8,
+        build: impl FnOnce(Packet) -> Packet,
+    ) -> Result<(u8, Vec<u8>), SftpError> {
+        self.next_id = self.next_id.wrapping_add(1);
+        let id = self.next_id;
+        let packet = build(Packet::new(kind).u32(id)).finish();
+        let exchange = async {
+            self.stream.write_all(&packet).await?;
+            read_packet(&mut self.stream).await
+        };
+        let (kind, mut body) = tokio::time::timeout(TIMEOUT, exchange)
+            .await
+            .map_err(|_| SftpError::Timeout)??;
+        if Reader::new(&body).u32()? != id {
+            return Err(SftpError::Protocol("reply to another request".to_string()));
+        }
+        body.drain(..4);
+        Ok((kind, body))
+    }
+
+    /// A request answered with a status
+    async fn status_request(
+        &mut self,
+        kind: u8,
+        path: &str,
+        build: impl FnOnce(Packet) -> Packet,
+    ) -> Result<(), SftpError> {
+        let (kind, body) = self.request(kind, build).await?;
+        match status(kind, &body, path)? {
+            Some(FX_OK) => Ok(()),
+            _ => Err(unexpected(kind)),
+        }
+    }
+
+    /// A request answered with a handle
+    async fn handle_request(
+        &mut self,
+        kind: u8,
+        path: &str,
+        build: impl FnOnce(Packet) -> Packet,
+    ) -> Result<Vec<u8>, SftpError> {
+        let (kind, body) = self.request(kind, build).await?;
+        status(kind, &body, path)?;
+        if kind != FXP_HANDLE {
+            return Err(unexpected(kind));
+        }
+        Ok(Reader::new(&body).bytes()?.to_vec())
+    }
+
+    pub async fn realpath(&mut self, path: &str) -> Result<String, SftpError> {
+        let (kind, body) = self
+            .request(FXP_REALPATH, |p| p.string(path.as_bytes()))
+            .await?;
+        status(kind, &body, path)?;
+        let mut reader = Reader::new(&body);
+        if kind != FXP_NAME || reader.u32()? == 0 {
+            return Err(unexpected(kind));
+        }
+        reader.string()
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<Attrs, SftpError> {
+        let (kind, body) = self
+            .request(FXP_STAT, |p| p.string(path.as_bytes()))
+            .await?;
+        status(kind, &body, path)?;
+        if kind != FXP_ATTRS {
+            return Err(unexpected(kind));
+        }
+        Reader::new(&body).attrs()
+    }
+
+    pub async fn list(&mut self, path: &str) -> Result<SftpListing, SftpError> {
+        let path = self
+            .realpath(if path.is_empty() { "." } else { path })
+            .await?;
+        let handle = self
+            .handle_request(FXP_OPENDIR, &path, |p| p.string(path.as_bytes()))
+            .await?;
+        let mut entries = Vec::new();
+        let listed = loop {
+            let (kind, body) = match self.request(FXP_READDIR, |p| p.string(&handle)).await {
+                Ok(reply) => reply,
+                Err(e) => break Err(e),
+            };
+            match status(kind, &body, &path) {
+                Ok(Some(FX_EOF)) => break Ok(()),
+                Ok(_) if kind == FXP_NAME => {}
+                Ok(_) => break Err(unexpected(kind)),
+                Err(e) => break Err(e),
+            }
+            let mut reader = Reader::new(&body);
+            for _ in 0..reader.u32()? {
+                let name = reader.string()?;
+                reader.string()?; // longname, ls -l style
+                let attrs = reader.attrs()?;
+                if name == "." || name == ".." {
+                    continue;
+                }
+                entries.push(SftpEntry {
+                    path: join(&path, &name),
+                    name,
+                    kind: attrs.kind(),
+                    size: attrs.size,
+                    permissions: attrs.permissions.map(|mode| mode & 0o7777),
+                    modified: attrs.modified,
+                });
+            }
+        };
+        self.close(&handle, &path).await?;
+        listed?;
+        entries.sort_by(|a, b| {
+            (a.kind != EntryKind::Directory, &a.name)
+                .cmp(&(b.kind != EntryKind::Directory, &b.name))
+        });
+        Ok(SftpListing { path, entries })
+    }
+
+    async fn open(&mut self, path: &str, flags: u32) -> Result<Vec<u8>, SftpError> {
+        self.handle_request(FXP_OPEN, path, |p| {
+            p.string(path.as_bytes()).u32(flags).u32(0)
+        })
+        .await
+    }
+
+    async fn close(&mut self, handle: &[u8], path: &str) -> Result<(), SftpError> {
+        self.status_request(FXP_CLOSE, path, |p| p.string(handle))
+            .await
+    }
+
+    /// Up to `CHUNK` bytes at `offset`; None at the end of the file
+    async fn read(
+        &mut self,
+        handle: &[u8],
+        offset: u64,
+        path: &str,
+    ) -> Result<Option<Vec<u8>>, SftpError> {
+        let (kind, body) = self
+            .request(FXP_READ, |p| p.string(handle).u64(offset).u32(CHUNK))
+            .await?;
+        if status(kind, &body, path)? == Some(FX_EOF) {
+            return Ok(None);
+        }
+        if kind != FXP_DATA {
+            return Err(unexpected(kind));
+        }
+        Ok(Some(Reader::new(&body).bytes()?.to_vec()))
+    }
+
+    async fn write(
+        &mut self,
+        handle: &[u8],
+        offset: u64,
+        data: &[u8],
+        path: &str,
+    ) -> Result<(), SftpError> {
+        self.status_request(FXP_WRITE, path, |p| {
+            p.string(handle).u64(offset).string(data)
+        })
+        .await
+    }
+
+    /// Remove a file, or an empty directory
+    pub async fn delete(&mut self, path: &str) -> Result<(), SftpError> {
+        let kind = if self.stat(path).await?.kind() == EntryKind::Directory {
+            FXP_RMDIR
+        } else {
+            FXP_REMOVE
+        };
+        self.status_request(kind, path, |p| p.string(path.as_bytes()))
+            .await
+    }
+}
+
+async fn read_packet<S: AsyncRead + Unpin>(stream: &mut S) -> Result<(u8, Vec<u8>), SftpError> {
+    let len = stream.read_u32().await? as usize;
+    if len == 0 || len > MAX_PACKET {
+        return Err(SftpError::Protocol(format!("packet of {} bytes", len)));
+    }
+    let mut packet = vec![0u8; len];
+    stream.read_exact(&mut packet).await?;
+    let kind = packet.remove(0);
+    Ok((kind, packet))
+}
+
+/// The code of a status reply, failing on errors; None for other replies
+fn status(kind: u8, body: &[u8], path: &str) -> Result<Option<u32>, SftpError> {
+    if kind != FXP_STATUS {
+        return Ok(None);
+    }
+    let mut reader = Reader::new(body);
+    let code = reader.u32()?;
+    if code == FX_OK || code == FX_EOF {
+        return Ok(Some(code));
+    }
+    // Version 3 servers may leave out the message
+    let message = reader.string().unwrap_or_default();
+    Err(SftpError::Status {
+        path: path.to_string(),
+        code,
+        message: if message.is_empty() {
+            status_name(code).to_string()
+        } else {
+            message
+        },
+    })
+}
+
+fn status_name(code: u32) -> &'static str {
+    match code {
+        2 => "No such file",
+        3 => "Permission denied",
+        5 => "Bad message",
+        8 => "Operation unsupported",
+        _ => "Failure",
+    }
+}
+
+fn unexpected(kind: u8) -> SftpError {
+    SftpError::Protocol(format!("unexpected reply type {}", kind))
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Start the `sftp` subsystem on a new channel of the session
+async fn connect(
+    manager: &SessionManager,
+    session_id: &str,
+) -> Result<SftpClient<ChannelStream<client::Msg>>, SftpError> {
+    manager
+        .get(session_id)
+        .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+    let connection = manager.tunnels().connection(session_id)?;
+    let channel = connection.lock().await.channel_open_session().await;
+    let mut channel = channel.map_err(|e| SessionError::ChannelError(e.to_string()))?;
+    channel
+        .request_subsystem(true, "sftp")
+        .await
+        .map_err(|e| SessionError::ChannelError(e.to_string()))?;
+    loop {
+        match tokio::time::timeout(TIMEOUT, channel.wait()).await {
+            Ok(Some(ChannelMsg::Success)) => break,
+            Ok(Some(ChannelMsg::Failure)) => return Err(SftpError::Unsupported),
+            Ok(Some(_)) => {}
+            Ok(None) => return Err(SessionError::ChannelError("channel closed".to_string()).into()),
+            Err(_) => return Err(SftpError::Timeout),
+        }
+    }
+    SftpClient::init(channel.into_stream()).await
+}
+
+pub async fn list(
+    manager: &SessionManager,
+    session_id: &str,
+    path: &str,
+) -> Result<SftpListing, SftpError> {
+    connect(manager, session_id).await?.list(path).await
+}
+
+pub async fn delete(
+    manager: &SessionManager,
+    session_id: &str,
+    path: &str,
+) -> Result<(), SftpError> {
+    connect(manager, session_id).await?.delete(path).await?;
+    info!(session_id = %session_id, path = %path, "SFTP delete");
+    Ok(())
+}
+
+/// Copy `remote_path` to `local_path`; a failed download leaves no file
+pub async fn download(
+    manager: &SessionManager,
+    session_id: &str,
+    remote_path: &str,
+    local_path: &str,
+) -> Result<SftpTransfer, SftpError> {
+    let mut progress = Progress::new(
+        manager,
+        session_id,
+        Direction::Download,
+        remote_path,
+        local_path,
+    );
+    let result = async {
+        let mut sftp = connect(manager, session_id).await?;
+        progress.transfer.total = sftp.stat(remote_path).await?.size;
+        let handle = sftp.open(remote_path, OPEN_READ).await?;
+        let mut file = tokio::fs::File::create(local_path).await?;
+        let copied = async {
+            while let Some(data) = sftp
+                .read(&handle, progress.transfer.bytes, remote_path)
+                .await?
+            {
+                file.write_all(&data).await?;
+                progress.advance(data.len());
+            }
+            file.flush().await?;
+            Ok::<_, SftpError>(())
+        }
+        .await;
+        sftp.close(&handle, remote_path).await?;
+        copied
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(local_path).await;
+    }
+    progress.finish(result)
+}
+
+/// Copy `local_path` to `remote_path`, replacing it
+pub async fn upload(
+    manager: &SessionManager,
+    session_id: &str,
+    local_path: &str,
+    remote_path: &str,
+) -> Result<SftpTransfer, SftpError> {
+    let mut progress = Progress::new(
+        manager,
+        session_id,
+        Direction::Upload,
+        remote_path,
+        local_path,
+    );
+    let result = async {
+        let mut file = tokio::fs::File::open(local_path).await?;
+        progress.transfer.total = Some(file.metadata().await?.len());
+        let mut sftp = connect(manager, session_id).await?;
+        let handle = sftp
+            .open(remote_path, OPEN_WRITE | OPEN_CREATE | OPEN_TRUNCATE)
+            .await?;
+        let copied = async {
+            let mut chunk = vec![0u8; CHUNK as usize];
+            loop {
+                let n = file.read(&mut chunk).await?;
+                if n == 0 {
+                    return Ok(());
+                }
+                sftp.write(&handle, progress.transfer.bytes, &chunk[..n], remote_path)
+                    .await?;
+                progress.advance(n);
+            }
+        }
+        .await;
+        sftp.close(&handle, remote_path).await?;
+        copied
+    }
+    .await;
+    progress.finish(result)
+}
+
+/// A transfer's `session:{id}:sftp` events
+struct Progress<'a> {
+    manager: &'a SessionManager,
+    event: String,
+    transfer: SftpTransfer,
+    last_emit: Instant,
+}
+
+impl<'a> Progress<'a> {
+    fn new(
+        manager: &'a SessionManager,
+        session_id: &str,
+        direction: Direction,
+        remote_path: &str,
+        local_path: &str,
+    ) -> Self {
+        let transfer = SftpTransfer {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            direction,
+            remote_path: remote_path.to_string(),
+            local_path: local_path.to_string(),
+            bytes: 0,
+            total: None,
+            done: false,
+            error: None,
+        };
+        info!(transfer_id = %transfer.id, session_id = %session_id, direction = ?direction, remote = %remote_path, local = %local_path, "SFTP transfer started");
+        Self {
+            manager,
+            event: format!("session:{}:sftp", session_id),
+            transfer,
+            last_emit: Instant::now(),
+        }
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.transfer.bytes += n as u64;
+        if self.last_emit.elapsed() >= PROGRESS_INTERVAL {
+            self.last_emit = Instant::now();
+            self.emit();
+        }
+    }
+
+    fn finish(mut self, result: Result<(), SftpError>) -> Result<SftpTransfer, SftpError> {
+        self.transfer.done = true;
+        self.transfer.error = result.as_ref().err().map(|e| e.to_string());
+        self.emit();
+        match &self.transfer.error {
+            None => {
+                info!(transfer_id = %self.transfer.id, bytes = self.transfer.bytes, "SFTP transfer done")
+            }
+            Some(e) => warn!(transfer_id = %self.transfer.id, error = %e, "SFTP transfer failed"),
+        }
+        result.map(|()| self.transfer)
+    }
+
+    fn emit(&self) {
+        if let Err(e) = self.manager.app_handle().emit(&self.event, &self.transfer) {
+            warn!(transfer_id = %self.transfer.id, error = %e, "Failed to emit SFTP progress");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(entries: &[(&str, u32, u64)]) -> Packet {
+        let mut packet = Packet::new(FXP_NAME).u32(entries.len() as u32);
+        for (name, mode, size) in entries {
+            packet = packet
+                .string(name.as_bytes())
+                .string(b"")
+                .u32(ATTR_SIZE | ATTR_PERMISSIONS | ATTR_ACMODTIME)
+                .u64(*size)
+                .u32(*mode)
+                .u32(0)
+                .u32(1_700_000_000);
+        }
+        packet
+    }
+
+    /// Answer each request the client sends with the next scripted reply,
+    /// checking the request type
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, script: Vec<(u8, Packet)>) {
+        let (kind, _) = read_packet(&mut stream).await.unwrap();
+        assert_eq!(kind, FXP_INIT);
+        let version = Packet::new(FXP_VERSION).u32(VERSION).finish();
+        stream.write_all(&version).await.unwrap();
+        for (expected, reply) in script {
+            let (kind, body) = read_packet(&mut stream).await.unwrap();
+            assert_eq!(kind, expected);
+            // Same id, then the scripted body
+            let mut out = vec![reply.0[0]];
+            out.extend_from_slice(&body[..4]);
+            out.extend_from_slice(&reply.0[1..]);
+            let mut packet = (out.len() as u32).to_be_bytes().to_vec();
+            packet.extend(out);
+            stream.write_all(&packet).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_directory() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let ok = || Packet::new(FXP_STATUS).u32(FX_OK).string(b"").string(b"");
+        let script = vec![
+            (FXP_REALPATH, name(&[("/flash/logs", 0o040755, 0)])),
+            (FXP_OPENDIR, Packet::new(FXP_HANDLE).string(b"h1")),
+            (
+                FXP_READDIR,
+                name(&[
+                    (".", 0o040755, 0),
+                    ("log.log", 0o100644, 52_431),
+                    ("archive", 0o040755, 0),
+                ]),
+            ),
+            (
+                FXP_READDIR,
+                Packet::new(FXP_STATUS).u32(FX_EOF).string(b"").string(b""),
+            ),
+            (FXP_CLOSE, ok()),
+            (
+                FXP_STAT,
+                Packet::new(FXP_ATTRS).u32(ATTR_PERMISSIONS).u32(0o100644),
+            ),
+            (
+                FXP_REMOVE,
+                Packet::new(FXP_STATUS).u32(3).string(b"").string(b""),
+            ),
+        ];
+        let server = tokio::spawn(serve(server, script));
+
+        let mut sftp = SftpClient::init(client).await.unwrap();
+        let listing = sftp.list("logs").await.unwrap();
+        assert_eq!(listing.path, "/flash/logs");
+        let names: Vec<(&str, EntryKind)> = listing
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.kind))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("archive", EntryKind::Directory),
+                ("log.log", EntryKind::File)
+            ]
+        );
+        let log = &listing.entries[1];
+        assert_eq!(log.path, "/flash/logs/log.log");
+        assert_eq!((log.size, log.permissions), (Some(52_431), Some(0o644)));
+
+        let denied = sftp.delete("/flash/logs/log.log").await.unwrap_err();
+        assert!(denied.to_string().contains("Permission denied"));
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,1027 @@
+//! SNMP trap receiver for alarms pushed by devices.
+//!
+//! Decodes SNMPv2c and SNMPv3 notifications (traps and informs) with a
+//! small BER reader; there is no MIB compiler, so a table of well-known
+//! OIDs gives names and the trap OID (plus Huawei's `hwBaseTrapSeverity`
+//! varbind) gives a severity. v2c informs are acknowledged.
+//!
+//! SNMPv3 is limited to what the crates at hand can verify: noAuthNoPriv
+//! and HMAC-SHA-256 authentication (RFC 7860). MD5 or SHA-1 authenticated
+//! and encrypted messages are dropped, and so are notifications from
+//! unknown users. Replay windows (engine boots and time) are not checked.
+
+use crate::profile::ProfileStore;
+use crate::session::SessionManager;
+use crate::syslog;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, warn};
+
+pub const TRAP_PORT: u16 = 162;
+
+/// Traps kept for `list_snmp_traps`
+const MAX_RECENT: usize = 1000;
+
+/// Traps kept for a slow subscriber
+const STREAM_CAPACITY: usize = 256;
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_IP_ADDRESS: u8 = 0x40;
+const TAG_COUNTER32: u8 = 0x41;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_TIMETICKS: u8 = 0x43;
+const TAG_OPAQUE: u8 = 0x44;
+const TAG_COUNTER64: u8 = 0x46;
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_NO_SUCH_INSTANCE: u8 = 0x81;
+const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+
+const PDU_RESPONSE: u8 = 0xa2;
+const PDU_INFORM: u8 = 0xa6;
+const PDU_TRAP: u8 = 0xa7;
+
+const VERSION_2C: i64 = 1;
+const VERSION_3: i64 = 3;
+const USM_SECURITY_MODEL: i64 = 3;
+const FLAG_AUTH: u8 = 0x01;
+const FLAG_PRIV: u8 = 0x02;
+
+/// usmHMAC192SHA256AuthProtocol sends the first 24 bytes of the HMAC
+const AUTH_PARAMS_LEN: usize = 24;
+
+const SYS_UPTIME: &str = "1.3.6.1.2.1.1.3.0";
+const SNMP_TRAP_OID: &str = "1.3.6.1.6.3.1.1.4.1.0";
+const COLD_START: &str = "1.3.6.1.6.3.1.1.5.1";
+const WARM_START: &str = "1.3.6.1.6.3.1.1.5.2";
+const LINK_DOWN: &str = "1.3.6.1.6.3.1.1.5.3";
+const LINK_UP: &str = "1.3.6.1.6.3.1.1.5.4";
+const AUTHENTICATION_FAILURE: &str = "1.3.6.1.6.3.1.1.5.5";
+const IF_DESCR: &str = "1.3.6.1.2.1.2.2.1.2";
+const IF_NAME: &str = "1.3.6.1.2.1.31.1.1.1.1";
+
+/// HUAWEI-BASE-TRAP-MIB `hwBaseTrapSeverity`, sent with Huawei's own alarms
+const HW_BASE_TRAP_SEVERITY: &str = "1.3.6.1.4.1.2011.5.25.129.1.1.1";
+
+/// Names of well-known OIDs; instance suffixes are kept (`ifDescr.5`)
+const OID_NAMES: &[(&str, &str)] = &[
+    ("1.3.6.1.2.1.1.3", "sysUpTime"),
+    ("1.3.6.1.6.3.1.1.4.1", "snmpTrapOID"),
+    ("1.3.6.1.6.3.1.1.4.3", "snmpTrapEnterprise"),
+    (COLD_START, "coldStart"),
+    (WARM_START, "warmStart"),
+    (LINK_DOWN, "linkDown"),
+    (LINK_UP, "linkUp"),
+    (AUTHENTICATION_FAILURE, "authenticationFailure"),
+    ("1.3.6.1.2.1.2.2.1.1", "ifIndex"),
+    (IF_DESCR, "ifDescr"),
+    ("1.3.6.1.2.1.2.2.1.3", "ifType"),
+    ("1.3.6.1.2.1.2.2.1.7", "ifAdminStatus"),
+    ("1.3.6.1.2.1.2.2.1.8", "ifOperStatus"),
+    (IF_NAME, "ifName"),
+    ("1.3.6.1.2.1.31.1.1.1.18", "ifAlias"),
+    (HW_BASE_TRAP_SEVERITY, "hwBaseTrapSeverity"),
+    ("1.3.6.1.4.1.2011", "huawei"),
+];
+
+#[derive(Error, Debug)]
+pub enum TrapError {
+    #[error("Trap receiver already running")]
+    AlreadyRunning,
+    #[error("Trap receiver not running")]
+    NotRunning,
+    #[error("Invalid trap receiver options: {0}")]
+    Invalid(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl From<TrapError> for String {
+    fn from(err: TrapError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrapSeverity {
+    Critical,
+    Major,
+    Minor,
+    Warning,
+    Info,
+    /// The alarm went away, e.g. `linkUp`
+    Cleared,
+    Unknown,
+}
+
+/// An SNMPv3 user notifications are accepted from
+#[derive(Debug, Clone, Deserialize)]
+pub struct SnmpUser {
+    pub name: String,
+    /// SHA-256 authentication password (at least 8 characters); without
+    /// one only noAuthNoPriv notifications are accepted
+    #[serde(default)]
+    pub auth_password: Option<String>,
+}
+
+/// Severity for traps whose OID is `oid` or below it; the longest
+/// matching rule wins over the built-in mapping
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeverityRule {
+    pub oid: String,
+    pub severity: TrapSeverity,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TrapOptions {
+    /// Default `0.0.0.0`
+    #[serde(default)]
+    pub bind: Option<String>,
+    /// Default `TRAP_PORT`
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// v2c communities accepted; empty accepts any
+    #[serde(default)]
+    pub communities: Vec<String>,
+    /// v3 users accepted; notifications from others are dropped
+    #[serde(default)]
+    pub users: Vec<SnmpUser>,
+    /// Device addresses accepted; empty accepts any
+    #[serde(default)]
+    pub allowed_sources: Vec<String>,
+    #[serde(default)]
+    pub severities: Vec<SeverityRule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnmpVersion {
+    V2c,
+    V3,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum SnmpValue {
+    Integer(i64),
+    /// Printable octet string
+    String(String),
+    /// Other octet strings as `aa:bb:..`
+    Hex(String),
+    Null,
+    Oid(String),
+    IpAddress(String),
+    Counter32(u64),
+    Gauge32(u64),
+    TimeTicks(u64),
+    Counter64(u64),
+    Opaque(String),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl fmt::Display for SnmpValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(n) => write!(f, "{}", n),
+            Self::Counter32(n) | Self::Gauge32(n) | Self::TimeTicks(n) | Self::Counter64(n) => {
+                write!(f, "{}", n)
+            }
+            Self::String(s)
+            | Self::Hex(s)
+            | Self::Oid(s)
+            | Self::IpAddress(s)
+            | Self::Opaque(s) => f.write_str(s),
+            Self::Null => f.write_str("null"),
+            Self::NoSuchObject => f.write_str("noSuchObject"),
+            Self::NoSuchInstance => f.write_str("noSuchInstance"),
+            Self::EndOfMibView => f.write_str("endOfMibView"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VarBind {
+    pub oid: String,
+    /// From the well-known OID table
+    pub name: Option<String>,
+    pub value: SnmpValue,
+}
+
+/// A received notification; also the body of `snmp-trap` events
+#[derive(Debug, Clone, Serialize)]
+pub struct SnmpTrap {
+    /// Unix milliseconds
+    pub received_at: i64,
+    pub source: String,
+    pub version: SnmpVersion,
+    /// v3 user name
+    pub user: Option<String>,
+    /// Sent as an inform (acknowledged for v2c)
+    pub inform: bool,
+    pub trap_oid: Option<String>,
+    pub trap_name: Option<String>,
+    /// `sysUpTime.0` in hundredths of a second
+    pub uptime_ticks: Option<u64>,
+    pub severity: TrapSeverity,
+    /// `ifDescr` or `ifName` carried by the trap
+    pub interface: Option<String>,
+    pub varbinds: Vec<VarBind>,
+    pub session_ids: Vec<String>,
+    pub profile_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TrapStatus {
+    pub running: bool,
+    pub addr: Option<String>,
+}
+
+/// What tagging a trap needs
+pub struct TrapContext {
+    pub manager: Arc<SessionManager>,
+    pub profiles: Arc<tokio::sync::Mutex<ProfileStore>>,
+}
+
+struct Running {
+    status: TrapStatus,
+    /// Dropped to stop the listener
+    _shutdown: watch::Sender<()>,
+}
+
+/// The receiver, started and stopped from the frontend
+pub struct TrapReceiver {
+    running: Mutex<Option<Running>>,
+    recent: Mutex<VecDeque<SnmpTrap>>,
+    stream: broadcast::Sender<SnmpTrap>,
+}
+
+impl TrapReceiver {
+    pub fn new() -> Self {
+        Self {
+            running: Mutex::new(None),
+            recent: Mutex::new(VecDeque::new()),
+            stream: broadcast::channel(STREAM_CAPACITY).0,
+        }
+    }
+
+    pub async fn start(
+        self: &Arc<Self>,
+        options: TrapOptions,
+        ctx: TrapContext,
+    ) -> Result<TrapStatus, TrapError> {
+        if self.running.lock().unwrap().is_some() {
+            return Err(TrapError::AlreadyRunning);
+        }
+        let allowed = options
+            .allowed_sources
+            .iter()
+            .map(|source| {
+                source
+                    .parse::<IpAddr>()
+                    .map(|ip| ip.to_canonical())
+                    .map_err(|_| TrapError::Invalid(format!("not an IP address: {}", source)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(user) = options
+            .users
+            .iter()
+            .find(|u| u.auth_password.as_ref().is_some_and(|p| p.len() < 8))
+        {
+            return Err(TrapError::Invalid(format!(
+                "the auth password of {} is shorter than 8 characters",
+                user.name
+            )));
+        }
+
+        let bind = options.bind.as_deref().unwrap_or("0.0.0.0");
+        let socket = UdpSocket::bind((bind, options.port.unwrap_or(TRAP_PORT))).await?;
+        let status = TrapStatus {
+            running: true,
+            addr: socket.local_addr().ok().map(|a| a.to_string()),
+        };
+        let (shutdown, stopped) = watch::channel(());
+        let receiver = Arc::clone(self);
+        tokio::spawn(async move {
+            receiver
+                .receive(socket, options, allowed, ctx, stopped)
+                .await
+        });
+
+        info!(addr = ?status.addr, "SNMP trap receiver started");
+        *self.running.lock().unwrap() = Some(Running {
+            status: status.clone(),
+            _shutdown: shutdown,
+        });
+        Ok(status)
+    }
+
+    pub fn stop(&self) -> Result<(), TrapError> {
+        self.running
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(TrapError::NotRunning)?;
+        info!("SNMP trap receiver stopped");
+        Ok(())
+    }
+
+    pub fn status(&self) -> TrapStatus {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|r| r.status.clone())
+            .unwrap_or_default()
+    }
+
+    /// The newest `limit` traps, oldest first
+    pub fn recent(&self, limit: usize) -> Vec<SnmpTrap> {
+        let recent = self.recent.lock().unwrap();
+        recent
+            .iter()
+            .skip(recent.len().saturating_sub(limit))
+            .cloned()
+            .collect()
+    }
+
+    /// Traps as they are received, for the alert rules
+    pub fn subscribe(&self) -> broadcast::Receiver<SnmpTrap> {
+        self.stream.subscribe()
+    }
+
+    async fn receive(
+        &self,
+        socket: UdpSocket,
+        options: TrapOptions,
+        allowed: Vec<IpAddr>,
+        ctx: TrapContext,
+        mut stopped: watch::Receiver<()>,
+    ) {
+        let mut keys = KeyCache::default();
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let (n, peer) = tokio::select! {
+                received = socket.recv_from(&mut buf) => match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!(error = %e, "SNMP trap receive failed");
+                        continue;
+                    }
+                },
+                _ = stopped.changed() => break,
+            };
+            let source = peer.ip().to_canonical();
+            if !allowed.is_empty() && !allowed.contains(&source) {
+                debug!(peer = %peer, "Dropping trap from a source not allowed");
+                continue;
+            }
+            let notification = match decode(&buf[..n], &options, &mut keys) {
+                Ok(notification) => notification,
+                Err(e) => {
+                    debug!(peer = %peer, error = %e, "Dropping SNMP message");
+                    continue;
+                }
+            };
+            if let Some(response) = &notification.response {
+                if let Err(e) = socket.send_to(response, peer).await {
+                    debug!(peer = %peer, error = %e, "Failed to acknowledge inform");
+                }
+            }
+
+            let mut trap = notification.trap;
+            trap.source = source.to_string();
+            trap.severity = severity(
+                trap.trap_oid.as_deref(),
+                &trap.varbinds,
+                &options.severities,
+            );
+            (trap.session_ids, trap.profile_ids) =
+                syslog::correlate(&ctx.manager, &ctx.profiles, source).await;
+            if let Err(e) = ctx.manager.app_handle().emit("snmp-trap", &trap) {
+                warn!(error = %e, "Failed to emit SNMP trap");
+            }
+            let _ = self.stream.send(trap.clone());
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() >= MAX_RECENT {
+                recent.pop_front();
+            }
+            recent.push_back(trap);
+        }
+    }
+}
+
+/// Localized SHA-256 keys by user name and engine id; deriving one hashes
+/// a megabyte, so it is done once per sender
+#[derive(Default)]
+struct KeyCache {
+    keys: HashMap<(String, Vec<u8>), Vec<u8>>,
+}
+
+impl KeyCache {
+    fn get(&mut self, user: &str, password: &str, engine_id: &[u8]) -> &[u8] {
+        self.keys
+            .entry((user.to_string(), engine_id.to_vec()))
+            .or_insert_with(|| localized_key(password, engine_id))
+    }
+}
+
+/// A decoded notification and the inform response to send, if any
+struct Notification {
+    trap: SnmpTrap,
+    response: Option<Vec<u8>>,
+}
+
+fn decode(raw: &[u8], options: &TrapOptions, keys: &mut KeyCache) -> Result<Notification, String> {
+    let mut message = Reader::new(Reader::new(raw).expect(TAG_SEQUENCE)?);
+    let version = message.integer()?;
+    let (version, user, community, pdu) = match version {
+        VERSION_2C => {
+            let community = message.octets()?;
+            if !options.communities.is_empty()
+                && !options
+                    .communities
+                    .iter()
+                    .any(|c| c.as_bytes() == community)
+            {
+                return Err("unknown community".to_string());
+            }
+            (SnmpVersion::V2c, None, Some(community), message.tlv()?)
+        }
+        VERSION_3 => {
+            let user = check_usm(raw, &mut message, options, keys)?;
+            let mut scoped = Reader::new(message.expect(TAG_SEQUENCE)?);
+            scoped.octets()?;
+            scoped.octets()?;
+            (SnmpVersion::V3, Some(user), None, scoped.tlv()?)
+        }
+        other => return Err(format!("unsupported SNMP version {}", other)),
+    };
+
+    let (tag, pdu) = pdu;
+    if tag != PDU_TRAP && tag != PDU_INFORM {
+        return Err(format!("not a notification (PDU {:#x})", tag));
+    }
+    let mut pdu = Reader::new(pdu);
+    let request_id = pdu.expect(TAG_INTEGER)?;
+    pdu.integer()?;
+    pdu.integer()?;
+    let varbinds_raw = pdu.expect(TAG_SEQUENCE)?;
+    let mut varbinds = Vec::new();
+    let mut list = Reader::new(varbinds_raw);
+    while !list.is_empty() {
+        let mut varbind = Reader::new(list.expect(TAG_SEQUENCE)?);
+        let oid = decode_oid(varbind.expect(TAG_OID)?)?;
+        let (tag, value) = varbind.tlv()?;
+        varbinds.push(VarBind {
+            name: oid_name(&oid),
+            value: decode_value(tag, value)?,
+            oid,
+        });
+    }
+
+    let value_of = |oid: &str| varbinds.iter().find(|v| v.oid == oid).map(|v| &v.value);
+    let trap_oid = match value_of(SNMP_TRAP_OID) {
+        Some(SnmpValue::Oid(oid)) => Some(oid.clone()),
+        _ => None,
+    };
+    let uptime_ticks = match value_of(SYS_UPTIME) {
+        Some(SnmpValue::TimeTicks(ticks)) => Some(*ticks),
+        _ => None,
+    };
+    let interface = varbinds
+        .iter()
+        .filter(|v| oid_under(&v.oid, IF_DESCR) || oid_under(&v.oid, IF_NAME))
+        .find_map(|v| match &v.value {
+            SnmpValue::String(name) => Some(name.clone()),
+            _ => None,
+        });
+
+    // Only v2c informs are acknowledged; a v3 response would need signing
+    let response = match (tag, community) {
+        (PDU_INFORM, Some(community)) => {
+            let pdu = [
+                encode(TAG_INTEGER, request_id),
+                encode(TAG_INTEGER, &[0]),
+                encode(TAG_INTEGER, &[0]),
+                encode(TAG_SEQUENCE, varbinds_raw),
+            ]
+            .concat();
+            let message = [
+                encode(TAG_INTEGER, &[VERSION_2C as u8]),
+                encode(TAG_OCTET_STRING, community),
+                encode(PDU_RESPONSE, &pdu),
+            ]
+            .concat();
+            Some(encode(TAG_SEQUENCE, &message))
+        }
+        _ => None,
+    };
+
+    Ok(Notification {
+        trap: SnmpTrap {
+            received_at: chrono::Utc::now().timestamp_millis(),
+            source: String::new(),
+            version,
+            user,
+            inform: tag == PDU_INFORM,
+            trap_name: trap_oid.as_deref().and_then(oid_name),
+            trap_oid,
+            uptime_ticks,
+            severity: TrapSeverity::Unknown,
+            interface,
+            varbinds,
+            session_ids: Vec::new(),
+            profile_ids: Vec::new(),
+        },
+        response,
+    })
+}
+
+/// Check a v3 message's security header against the configured users;
+/// returns the user name. `message` is left at the scoped PDU.
+fn check_usm(
+    raw: &[u8],
+    message: &mut Reader,
+    options: &TrapOptions,
+    keys: &mut KeyCache,
+) -> Result<String, String> {
+    let mut global = Reader::new(message.expect(TAG_SEQUENCE)?);
+    global.integer()?;
+    global.integer()?;
+    let flags = global.octets()?.first().copied().unwrap_or(0);
+    if global.integer()? != USM_SECURITY_MODEL {
+        return Err("not a USM message".to_string());
+    }
+    let mut usm = Reader::new(Reader::new(message.octets()?).expect(TAG_SEQUENCE)?);
+    let engine_id = usm.octets()?;
+    usm.integer()?;
+    usm.integer()?;
+    let name = String::from_utf8_lossy(usm.octets()?).into_owned();
+    let auth_params = usm.octets()?;
+
+    if flags & FLAG_PRIV != 0 {
+        return Err(format!("encrypted message from {}", name));
+    }
+    let user = options
+        .users
+        .iter()
+        .find(|u| u.name == name)
+        .ok_or_else(|| format!("unknown user {}", name))?;
+    match (&user.auth_password, flags & FLAG_AUTH != 0) {
+        (None, false) => {}
+        (None, true) => return Err(format!("no auth password configured for {}", name)),
+        (Some(_), false) => return Err(format!("unauthenticated message from {}", name)),
+        (Some(password), true) => {
+            if auth_params.len() != AUTH_PARAMS_LEN {
+                return Err(format!("unsupported authentication protocol from {}", name));
+            }
+            // The HMAC covers the whole message with its own field zeroed
+            let offset = auth_params.as_ptr() as usize - raw.as_ptr() as usize;
+            let mut zeroed = raw.to_vec();
+            zeroed[offset..offset + AUTH_PARAMS_LEN].fill(0);
+            let key = keys.get(&name, password, engine_id);
+            let mac = hmac_sha256(key, &zeroed);
+            let diff = mac[..AUTH_PARAMS_LEN]
+                .iter()
+                .zip(auth_params)
+                .fold(0, |acc, (a, b)| acc | (a ^ b));
+            if diff != 0 {
+                return Err(format!("authentication failed for {}", name));
+            }
+        }
+    }
+    Ok(name)
+}
+
+/// RFC 3414 password to key with SHA-256, localized to `engine_id`
+fn localized_key(password: &str, engine_id: &[u8]) -> Vec<u8> {
+    let password = password.as_bytes();
+    let mut hasher = Sha256::new();
+    let mut block = [0u8; 64];
+    let mut index = 0;
+    for _ in 0..(1_048_576 / block.len()) {
+        for byte in block.iter_mut() {
+            *byte = password[index % password.len()];
+            index += 1;
+        }
+        hasher.update(block);
+    }
+    let key = hasher.finalize();
+    Sha256::new()
+        .chain_update(key)
+        .chain_update(engine_id)
+        .chain_update(key)
+        .finalize()
+        .to_vec()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(data)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+fn severity(trap_oid: Option<&str>, varbinds: &[VarBind], rules: &[SeverityRule]) -> TrapSeverity {
+    if let Some(oid) = trap_oid {
+        if let Some(rule) = rules
+            .iter()
+            .filter(|rule| oid_under(oid, &rule.oid))
+            .max_by_key(|rule| rule.oid.len())
+        {
+            return rule.severity;
+        }
+    }
+    let huawei = varbinds
+        .iter()
+        .find(|v| oid_under(&v.oid, HW_BASE_TRAP_SEVERITY));
+    if let Some(SnmpValue::Integer(level)) = huawei.map(|v| &v.value) {
+        return match level {
+            1 => TrapSeverity::Critical,
+            2 => TrapSeverity::Major,
+            3 => TrapSeverity::Minor,
+            4 => TrapSeverity::Warning,
+            6 => TrapSeverity::Cleared,
+            _ => TrapSeverity::Unknown,
+        };
+    }
+    match trap_oid {
+        Some(LINK_DOWN) => TrapSeverity::Major,
+        Some(LINK_UP) => TrapSeverity::Cleared,
+        Some(COLD_START | WARM_START | AUTHENTICATION_FAILURE) => TrapSeverity::Warning,
+        _ => TrapSeverity::Unknown,
+    }
+}
+
+/// `oid` is `base` or below it
+fn oid_under(oid: &str, base: &str) -> bool {
+    oid.strip_prefix(base)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+fn oid_name(oid: &str) -> Option<String> {
+    OID_NAMES
+        .iter()
+        .filter(|(base, _)| oid_under(oid, base))
+        .max_by_key(|(base, _)| base.len())
+        .map(|(base, name)| format!("{}{}", name, &oid[base.len()..]))
+}
+
+/// BER reader over one constructed value's contents
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Next tag and contents; SNMP only uses single-byte tags
+    fn tlv(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let truncated = || "truncated message".to_string();
+        let (&tag, rest) = self.data.split_first().ok_or_else(truncated)?;
+        let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+        let len = if first & 0x80 == 0 {
+            first as usize
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return Err("bad length".to_string());
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0usize, |len, &b| (len << 8) | b as usize);
+            rest = &rest[count..];
+            len
+        };
+        if rest.len() < len {
+            return Err(truncated());
+        }
+        let (value, rest) = rest.split_at(len);
+        self.data = rest;
+        Ok((tag, value))
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<&'a [u8], String> {
+        match self.tlv()? {
+            (tag, value) if tag == expected => Ok(value),
+            (tag, _) => Err(format!("expected tag {:#x}, got {:#x}", expected, tag)),
+        }
+    }
+
+    fn integer(&mut self) -> Result<i64, String> {
+        decode_integer(self.expect(TAG_INTEGER)?)
+    }
+
+    fn octets(&mut self) -> Result<&'a [u8], String> {
+        self.expect(TAG_OCTET_STRING)
+    }
+}
+
+fn encode(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = value.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+fn decode_integer(bytes: &[u8]) -> Result<i64, String> {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return Err("bad integer".to_string());
+    }
+    let sign = if bytes[0] & 0x80 != 0 { -1i64 } else { 0 };
+    Ok(bytes.iter().fold(sign, |value, &b| (value << 8) | b as i64))
+}
+
+fn decode_unsigned(bytes: &[u8]) -> Result<u64, String> {
+    let bytes = match bytes {
+        [0, rest @ ..] => rest,
+        bytes => bytes,
+    };
+    if bytes.len() > 8 {
+        return Err("bad unsigned integer".to_string());
+    }
+    Ok(bytes.iter().fold(0u64, |value, &b| (value << 8) | b as u64))
+}
+
+fn decode_oid(bytes: &[u8]) -> Result<String, String> {
+    let (&first, rest) = bytes.split_first().ok_or("empty OID")?;
+    let (x, y) = match first {
+        0..=39 => (0, first),
+        40..=79 => (1, first - 40),
+        _ => (2, first - 80),
+    };
+    let mut oid = format!("{}.{}", x, y);
+    let mut arc: u64 = 0;
+    for &byte in rest {
+        arc = arc.checked_mul(128).ok_or("OID arc too large")? | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            oid.push_str(&format!(".{}", arc));
+            arc = 0;
+        }
+    }
+    Ok(oid)
+}
+
+fn decode_value(tag: u8, bytes: &[u8]) -> Result<SnmpValue, String> {
+    Ok(match tag {
+        TAG_INTEGER => SnmpValue::Integer(decode_integer(bytes)?),
+        TAG_OCTET_STRING => match std::str::from_utf8(bytes) {
+            Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+                SnmpValue::String(text.to_string())
+            }
+            _ => SnmpValue::Hex(hex(bytes)),
+        },
+        TAG_NULL => SnmpValue::Null,
+        TAG_OID => SnmpValue::Oid(decode_oid(bytes)?),
+        TAG_IP_ADDRESS => match <[u8; 4]>::try_from(bytes) {
+            Ok(octets) => SnmpValue::IpAddress(IpAddr::from(octets).to_string()),
+            Err(_) => return Err("bad IpAddress".to_string()),
+        },
+        TAG_COUNTER32 => SnmpValue::Counter32(decode_unsigned(bytes)?),
+        TAG_GAUGE32 => SnmpValue::Gauge32(decode_unsigned(bytes)?),
+        TAG_TIMETICKS => SnmpValue::TimeTicks(decode_unsigned(bytes)?),
+        TAG_OPAQUE => SnmpValue::Opaque(hex(bytes)),
+        TAG_COUNTER64 => SnmpValue::Counter64(decode_unsigned(bytes)?),
+        TAG_NO_SUCH_OBJECT => SnmpValue::NoSuchObject,
+        TAG_NO_SUCH_INSTANCE => SnmpValue::NoSuchInstance,
+        TAG_END_OF_MIB_VIEW => SnmpValue::EndOfMibView,
+        other => return Err(format!("unknown value type {:#x}", other)),
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_oid(oid: &str) -> Vec<u8> {
+        let arcs: Vec<u64> = oid.split('.').map(|a| a.parse().unwrap()).collect();
+        let mut out = vec![(arcs[0] * 40 + arcs[1]) as u8];
+        for &arc in &arcs[2..] {
+            let mut groups = vec![(arc & 0x7f) as u8];
+            let mut rest = arc >> 7;
+            while rest > 0 {
+                groups.push((rest & 0x7f) as u8 | 0x80);
+                rest >>= 7;
+            }
+            out.extend(groups.into_iter().rev());
+        }
+        encode(TAG_OID, &out)
+    }
+
+    fn varbind(oid: &str, value: Vec<u8>) -> Vec<u8> {
+        encode(TAG_SEQUENCE, &[encode_oid(oid), value].concat())
+    }
+
+    /// linkDown for interface 5, as a trap or inform PDU
+    fn link_down(tag: u8) -> Vec<u8> {
+        let varbinds = [
+            varbind(SYS_UPTIME, encode(TAG_TIMETICKS, &[0x01, 0x00])),
+            varbind(SNMP_TRAP_OID, encode_oid(LINK_DOWN)),
+            varbind("1.3.6.1.2.1.2.2.1.1.5", encode(TAG_INTEGER, &[5])),
+            varbind(
+                "1.3.6.1.2.1.2.2.1.2.5",
+                encode(TAG_OCTET_STRING, b"GigabitEthernet0/0/1"),
+            ),
+            varbind(
+                "1.3.6.1.4.1.2011.5.25.1.1",
+                encode(TAG_OCTET_STRING, &[0x00, 0xe0, 0xfc]),
+            ),
+        ]
+        .concat();
+        let pdu = [
+            encode(TAG_INTEGER, &[0x2a]),
+            encode(TAG_INTEGER, &[0]),
+            encode(TAG_INTEGER, &[0]),
+            encode(TAG_SEQUENCE, &varbinds),
+        ]
+        .concat();
+        encode(tag, &pdu)
+    }
+
+    #[test]
+    fn test_decode_v2c() {
+        let message = |community: &[u8], pdu: Vec<u8>| {
+            let body = [
+                encode(TAG_INTEGER, &[VERSION_2C as u8]),
+                encode(TAG_OCTET_STRING, community),
+                pdu,
+            ]
+            .concat();
+            encode(TAG_SEQUENCE, &body)
+        };
+        let options = TrapOptions {
+            communities: vec!["lab".to_string()],
+            ..Default::default()
+        };
+        let mut keys = KeyCache::default();
+
+        let trap = decode(&message(b"lab", link_down(PDU_TRAP)), &options, &mut keys)
+            .unwrap()
+            .trap;
+        assert_eq!(trap.version, SnmpVersion::V2c);
+        assert_eq!(trap.trap_oid.as_deref(), Some(LINK_DOWN));
+        assert_eq!(trap.trap_name.as_deref(), Some("linkDown"));
+        assert_eq!(trap.uptime_ticks, Some(256));
+        assert_eq!(trap.interface.as_deref(), Some("GigabitEthernet0/0/1"));
+        assert_eq!(trap.varbinds[2].name.as_deref(), Some("ifIndex.5"));
+        assert_eq!(
+            trap.varbinds[4].value,
+            SnmpValue::Hex("00:e0:fc".to_string())
+        );
+        assert_eq!(trap.varbinds[4].name.as_deref(), Some("huawei.5.25.1.1"));
+        assert_eq!(
+            severity(trap.trap_oid.as_deref(), &trap.varbinds, &[]),
+            TrapSeverity::Major
+        );
+        let rules = [SeverityRule {
+            oid: "1.3.6.1.6.3.1.1.5".to_string(),
+            severity: TrapSeverity::Info,
+        }];
+        assert_eq!(
+            severity(trap.trap_oid.as_deref(), &trap.varbinds, &rules),
+            TrapSeverity::Info
+        );
+
+        let inform = decode(&message(b"lab", link_down(PDU_INFORM)), &options, &mut keys).unwrap();
+        assert!(inform.trap.inform);
+        let response = inform.response.unwrap();
+        let mut reader = Reader::new(Reader::new(&response).expect(TAG_SEQUENCE).unwrap());
+        assert_eq!(reader.integer().unwrap(), VERSION_2C);
+        assert_eq!(reader.octets().unwrap(), b"lab");
+        let mut pdu = Reader::new(reader.expect(PDU_RESPONSE).unwrap());
+        assert_eq!(pdu.integer().unwrap(), 0x2a);
+
+        assert!(decode(
+            &message(b"public", link_down(PDU_TRAP)),
+            &options,
+            &mut keys
+        )
+        .is_err());
+        assert!(decode(
+            &message(b"lab", link_down(PDU_RESPONSE)),
+            &options,
+            &mut keys
+        )
+        .is_err());
+        assert!(decode(&[0x30, 0x05, 0x02], &options, &mut keys).is_err());
+    }
+
+    #[test]
+    fn test_decode_v3_auth() {
+        let engine_id = b"\x80\x00\x07\xdb\x03core-1";
+        let message = |flags: u8, auth: &[u8]| {
+            let usm = [
+                encode(TAG_OCTET_STRING, engine_id),
+                encode(TAG_INTEGER, &[1]),
+                encode(TAG_INTEGER, &[100]),
+                encode(TAG_OCTET_STRING, b"monitor"),
+                encode(TAG_OCTET_STRING, auth),
+                encode(TAG_OCTET_STRING, b""),
+            ]
+            .concat();
+            let global = [
+                encode(TAG_INTEGER, &[7]),
+                encode(TAG_INTEGER, &[0x05, 0xdc]),
+                encode(TAG_OCTET_STRING, &[flags]),
+                encode(TAG_INTEGER, &[USM_SECURITY_MODEL as u8]),
+            ]
+            .concat();
+            let scoped = [
+                encode(TAG_OCTET_STRING, engine_id),
+                encode(TAG_OCTET_STRING, b""),
+                link_down(PDU_TRAP),
+            ]
+            .concat();
+            let body = [
+                encode(TAG_INTEGER, &[VERSION_3 as u8]),
+                encode(TAG_SEQUENCE, &global),
+                encode(TAG_OCTET_STRING, &encode(TAG_SEQUENCE, &usm)),
+                encode(TAG_SEQUENCE, &scoped),
+            ]
+            .concat();
+            encode(TAG_SEQUENCE, &body)
+        };
+        let options = TrapOptions {
+            users: vec![SnmpUser {
+                name: "monitor".to_string(),
+                auth_password: Some("s3cret-pass".to_string()),
+            }],
+            ..Default::default()
+        };
+        let mut keys = KeyCache::default();
+
+        // Sign the way a device does: HMAC over the message with zeroed params
+        let unsigned = message(FLAG_AUTH, &[0; AUTH_PARAMS_LEN]);
+        let key = localized_key("s3cret-pass", engine_id);
+        let mac = hmac_sha256(&key, &unsigned);
+        let signed = message(FLAG_AUTH, &mac[..AUTH_PARAMS_LEN]);
+        let trap = decode(&signed, &options, &mut keys).unwrap().trap;
+        assert_eq!(trap.version, SnmpVersion::V3);
+        assert_eq!(trap.user.as_deref(), Some("monitor"));
+        assert_eq!(trap.trap_name.as_deref(), Some("linkDown"));
+
+        let mut tampered = signed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decode(&tampered, &options, &mut keys).is_err());
+        assert!(decode(&unsigned, &options, &mut keys).is_err());
+        assert!(decode(&message(0, b""), &options, &mut keys).is_err());
+        assert!(decode(
+            &message(FLAG_AUTH | FLAG_PRIV, &mac[..24]),
+            &options,
+            &mut keys
+        )
+        .is_err());
+    }
+}
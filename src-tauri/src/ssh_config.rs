@@ -0,0 +1,509 @@
+//! OpenSSH client config (`~/.ssh/config`) parsing
+//!
+//! Options are collected per `Host` block and resolved the way `ssh` does:
+//! every block whose patterns match the alias contributes, and the first
+//! value seen for an option wins. `Match` blocks and `Include` are not
+//! evaluated.
+
+use crate::profile::{ImportIssue, ImportReport, Profile, TerminalSettings};
+use crate::session::{DeviceDialect, JumpHost, Protocol, SshAuth};
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+use tracing::info;
+
+/// One `Host` block; options before the first block form an implicit `Host *`
+struct HostBlock {
+    patterns: Vec<String>,
+    /// Lowercased keyword and raw value, in file order
+    options: Vec<(String, String)>,
+}
+
+impl HostBlock {
+    /// OpenSSH semantics: any negated match excludes, otherwise any positive
+    /// match includes
+    fn matches(&self, alias: &str) -> bool {
+        let alias = alias.to_lowercase();
+        let mut matched = false;
+        for pattern in &self.patterns {
+            let pattern = pattern.to_lowercase();
+            match pattern.strip_prefix('!') {
+                Some(negated) if glob_match(negated, &alias) => return false,
+                Some(_) => {}
+                None => matched |= glob_match(&pattern, &alias),
+            }
+        }
+        matched
+    }
+}
+
+/// Options resolved for one host alias
+#[derive(Debug, Default, PartialEq)]
+pub struct HostOptions {
+    pub host_name: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    /// All `IdentityFile` lines, in the order ssh would try them
+    pub identity_files: Vec<String>,
+    pub proxy_jump: Option<String>,
+}
+
+pub struct SshConfig {
+    blocks: Vec<HostBlock>,
+    /// Directives that were read but not evaluated (`Match`, `Include`)
+    pub unsupported: Vec<String>,
+}
+
+impl SshConfig {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut blocks = vec![HostBlock {
+            patterns: vec!["*".to_string()],
+            options: Vec::new(),
+        }];
+        let mut unsupported = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((keyword, value)) = split_directive(line) else {
+                continue;
+            };
+
+            match keyword.as_str() {
+                "host" => blocks.push(HostBlock {
+                    patterns: value.split_whitespace().map(str::to_string).collect(),
+                    options: Vec::new(),
+                }),
+                "match" => {
+                    unsupported.push(line.to_string());
+                    // Options inside a Match block apply to no alias
+                    blocks.push(HostBlock {
+                        patterns: Vec::new(),
+                        options: Vec::new(),
+                    });
+                }
+                "include" => unsupported.push(line.to_string()),
+                _ => blocks
+                    .last_mut()
+                    .expect("implicit first block")
+                    .options
+                    .push((keyword, unquote(value).to_string())),
+            }
+        }
+
+        Self {
+            blocks,
+            unsupported,
+        }
+    }
+
+    /// Concrete host aliases (no wildcards or negations), in file order
+    pub fn aliases(&self) -> Vec<String> {
+        let mut aliases: Vec<String> = Vec::new();
+        for block in &self.blocks {
+            for pattern in &block.patterns {
+                let concrete = !pattern.contains(['*', '?', '!']);
+                if concrete && !aliases.contains(pattern) {
+                    aliases.push(pattern.clone());
+                }
+            }
+        }
+        aliases
+    }
+
+    pub fn resolve(&self, alias: &str) -> HostOptions {
+        let mut options = HostOptions::default();
+        for block in self.blocks.iter().filter(|b| b.matches(alias)) {
+            for (keyword, value) in &block.options {
+                match keyword.as_str() {
+                    "hostname" if options.host_name.is_none() => {
+                        options.host_name = Some(value.replace("%h", alias));
+                    }
+                    "port" if options.port.is_none() => options.port = value.parse().ok(),
+                    "user" if options.user.is_none() => options.user = Some(value.clone()),
+                    "identityfile" => options.identity_files.push(value.clone()),
+                    "proxyjump" if options.proxy_jump.is_none() => {
+                        options.proxy_jump = Some(value.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        options
+    }
+}
+
+/// `Keyword value` or `Keyword=value`, keyword lowercased
+fn split_directive(line: &str) -> Option<(String, &str)> {
+    let end = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (keyword, rest) = line.split_at(end);
+    let value = rest.trim_start().strip_prefix('=').unwrap_or(rest).trim();
+    Some((keyword.to_lowercase(), value))
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// `*` and `?` wildcards as used in `Host` patterns
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[u8], text: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some((b'*', rest)) => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            Some((b'?', rest)) => !text.is_empty() && matches(rest, &text[1..]),
+            Some((c, rest)) => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+    matches(pattern.as_bytes(), text.as_bytes())
+}
+
+/// Expand a leading `~/` against `home`
+fn expand_home(path: &str, home: &Path) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => home.join(rest).to_string_lossy().into_owned(),
+        None => path.to_string(),
+    }
+}
+
+/// What `ssh <alias>` would connect to, for prefilling a `SessionConfig`
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedHost {
+    pub alias: String,
+    /// Whether a `Host` pattern other than the implicit `*` matched
+    pub matched: bool,
+    pub host: String,
+    pub port: u16,
+    /// Empty when no `User` applies
+    pub username: String,
+    /// The first identity file as a key, else password authentication
+    pub auth: SshAuth,
+    /// Every `IdentityFile`, `~/` expanded
+    pub identity_files: Vec<String>,
+    /// `ProxyJump` hops, outermost first, each resolved through the config
+    pub jump_hosts: Vec<JumpHost>,
+    pub warnings: Vec<String>,
+}
+
+/// Resolve an alias into a connection target, following `ProxyJump`
+pub fn resolve_host(config: &SshConfig, alias: &str, home: &Path) -> ResolvedHost {
+    let options = config.resolve(alias);
+    let identity_files: Vec<String> = options
+        .identity_files
+        .iter()
+        .map(|path| expand_home(path, home))
+        .collect();
+    let mut resolved = ResolvedHost {
+        alias: alias.to_string(),
+        matched: config.blocks[1..].iter().any(|b| b.matches(alias)),
+        host: options.host_name.unwrap_or_else(|| alias.to_string()),
+        port: options.port.unwrap_or(22),
+        username: options.user.unwrap_or_default(),
+        auth: key_auth(&identity_files),
+        identity_files,
+        jump_hosts: Vec::new(),
+        warnings: config
+            .unsupported
+            .iter()
+            .map(|d| format!("{}: not evaluated", d))
+            .collect(),
+    };
+    if let Some(spec) = options.proxy_jump {
+        let mut seen = vec![alias.to_lowercase()];
+        resolved.jump_hosts = jumps(config, &spec, home, &mut seen, &mut resolved.warnings);
+    }
+    resolved
+}
+
+/// The hops of a `ProxyJump` value. The first hop's own `ProxyJump` comes
+/// before it, as ssh connects to it the same way
+fn jumps(
+    config: &SshConfig,
+    spec: &str,
+    home: &Path,
+    seen: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) -> Vec<JumpHost> {
+    if spec.eq_ignore_ascii_case("none") {
+        return Vec::new();
+    }
+    let mut hops = Vec::new();
+    for (i, hop) in spec.split(',').map(str::trim).enumerate() {
+        let Some((user, alias, port)) = parse_hop(hop) else {
+            warnings.push(format!("ProxyJump {}: not a [user@]host[:port]", hop));
+            continue;
+        };
+        let options = config.resolve(alias);
+        if i == 0 {
+            if let Some(inner) = &options.proxy_jump {
+                if seen.contains(&alias.to_lowercase()) {
+                    warnings.push(format!("ProxyJump {}: loops back, not followed", alias));
+                } else {
+                    seen.push(alias.to_lowercase());
+                    hops.extend(jumps(config, inner, home, seen, warnings));
+                }
+            }
+        }
+        let identity_files: Vec<String> = options
+            .identity_files
+            .iter()
+            .map(|path| expand_home(path, home))
+            .collect();
+        hops.push(JumpHost {
+            host: options.host_name.unwrap_or_else(|| alias.to_string()),
+            port: port.or(options.port).unwrap_or(22),
+            username: user
+                .map(str::to_string)
+                .or(options.user)
+                .unwrap_or_default(),
+            password: String::new(),
+            auth: key_auth(&identity_files),
+        });
+    }
+    hops
+}
+
+/// `[user@]host[:port]` or `ssh://[user@]host[:port]`, IPv6 in brackets
+fn parse_hop(hop: &str) -> Option<(Option<&str>, &str, Option<u16>)> {
+    let hop = hop.strip_prefix("ssh://").unwrap_or(hop);
+    let (user, rest) = match hop.rsplit_once('@') {
+        Some((user, rest)) => (Some(user), rest),
+        None => (None, hop),
+    };
+    let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+        let (host, after) = bracketed.split_once(']')?;
+        match after.strip_prefix(':') {
+            Some(port) => (host, Some(port.parse().ok()?)),
+            None if after.is_empty() => (host, None),
+            None => return None,
+        }
+    } else {
+        match rest.split_once(':') {
+            Some((host, port)) => (host, Some(port.parse().ok()?)),
+            None => (rest, None),
+        }
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((user, host, port))
+}
+
+fn key_auth(identity_files: &[String]) -> SshAuth {
+    match identity_files.first() {
+        Some(path) => SshAuth::PublicKey {
+            path: Some(path.clone()),
+            pem: None,
+            passphrase: None,
+        },
+        None => SshAuth::Password,
+    }
+}
+
+/// Turn every concrete `Host` alias into an unsaved SSH profile named after
+/// the alias
+pub fn profiles(config: &SshConfig, home: &Path) -> ImportReport {
+    let mut report = ImportReport::default();
+    for directive in &config.unsupported {
+        report.warnings.push(ImportIssue {
+            source: directive.clone(),
+            message: "not evaluated, options from it are missing".to_string(),
+        });
+    }
+
+    for alias in config.aliases() {
+        let options = config.resolve(&alias);
+        if options.identity_files.len() > 1 {
+            report.warnings.push(ImportIssue {
+                source: alias.clone(),
+                message: "only the first IdentityFile was imported".to_string(),
+            });
+        }
+
+        report.imported.push(Profile {
+            id: String::new(),
+            name: alias.clone(),
+            folder: String::new(),
+            tags: Vec::new(),
+            favorite: false,
+            host: options.host_name.unwrap_or_else(|| alias.clone()),
+            port: options.port.unwrap_or(22),
+            protocol: Protocol::Ssh,
+            username: options.user.unwrap_or_default(),
+            password: None,
+            prompt_credentials: false,
+            identity_file: options
+                .identity_files
+                .first()
+                .map(|path| expand_home(path, home)),
+            proxy_jump: options
+                .proxy_jump
+                .filter(|jump| !jump.eq_ignore_ascii_case("none")),
+            jump_profile: None,
+            connect_timeout_ms: None,
+            terminal: TerminalSettings::default(),
+            dialect: DeviceDialect::default(),
+            logging: None,
+            plugins: Default::default(),
+            created_at: 0,
+            updated_at: 0,
+        });
+    }
+
+    info!(hosts = report.imported.len(), "Read ssh config hosts");
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# Lab devices
+User ops
+
+Host bastion
+    HostName bastion.lab.example.com
+    Port 2222
+    IdentityFile ~/.ssh/lab_ed25519
+
+Host core-* !core-old
+    ProxyJump bastion
+    User admin
+
+Host core-1 core-2
+    HostName 10.0.0.%h
+    Port=830
+
+Host core-old
+    HostName 10.9.9.9
+
+Match user root
+    Port 22022
+
+Host *
+    IdentityFile ~/.ssh/id_rsa
+    Port 22
+";
+
+    #[test]
+    fn test_resolve_first_value_wins() {
+        let config = SshConfig::parse(CONFIG);
+        assert_eq!(
+            config.aliases(),
+            ["bastion", "core-1", "core-2", "core-old"]
+        );
+        assert_eq!(config.unsupported, ["Match user root"]);
+
+        let bastion = config.resolve("bastion");
+        assert_eq!(bastion.port, Some(2222));
+        // Global User precedes the block
+        assert_eq!(bastion.user.as_deref(), Some("ops"));
+        assert_eq!(
+            bastion.identity_files,
+            ["~/.ssh/lab_ed25519", "~/.ssh/id_rsa"]
+        );
+
+        let core = config.resolve("CORE-1");
+        assert_eq!(core.host_name.as_deref(), Some("10.0.0.CORE-1"));
+        assert_eq!(core.port, Some(830));
+        assert_eq!(core.proxy_jump.as_deref(), Some("bastion"));
+
+        let old = config.resolve("core-old");
+        assert!(old.proxy_jump.is_none());
+        assert_eq!(old.port, Some(22));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config = SshConfig::parse(CONFIG);
+        let report = profiles(&config, Path::new("/home/ops"));
+        assert_eq!(report.imported.len(), 4);
+
+        let bastion = &report.imported[0];
+        assert_eq!(bastion.host, "bastion.lab.example.com");
+        assert_eq!(bastion.protocol, Protocol::Ssh);
+        assert_eq!(
+            bastion.identity_file.as_deref(),
+            Some(
+                Path::new("/home/ops")
+                    .join(".ssh/lab_ed25519")
+                    .to_str()
+                    .unwrap()
+            )
+        );
+        // The top-level User applies before the core-* block
+        assert_eq!(report.imported[1].username, "ops");
+        // Match block plus bastion's second identity file
+        assert_eq!(report.warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_host() {
+        let config = SshConfig::parse(CONFIG);
+        let home = Path::new("/home/ops");
+        let key = |name: &str| home.join(".ssh").join(name).to_string_lossy().into_owned();
+
+        let core = resolve_host(&config, "core-2", home);
+        assert!(core.matched);
+        assert_eq!((core.host.as_str(), core.port), ("10.0.0.core-2", 830));
+        assert_eq!(core.identity_files, [key("id_rsa")]);
+        assert_eq!(core.jump_hosts.len(), 1);
+        let bastion = &core.jump_hosts[0];
+        assert_eq!(
+            (
+                bastion.host.as_str(),
+                bastion.port,
+                bastion.username.as_str()
+            ),
+            ("bastion.lab.example.com", 2222, "ops")
+        );
+        assert_eq!(
+            bastion.auth,
+            SshAuth::PublicKey {
+                path: Some(key("lab_ed25519")),
+                pem: None,
+                passphrase: None
+            }
+        );
+        assert_eq!(core.warnings, ["Match user root: not evaluated"]);
+
+        let config = SshConfig::parse(
+            "Host a\n  ProxyJump root@[fe80::1]:2200,ssh://c:22x,c\nHost c\n  ProxyJump d\nHost d\n  ProxyJump c\n",
+        );
+        let a = resolve_host(&config, "a", home);
+        let hosts: Vec<(&str, u16, &str)> = a
+            .jump_hosts
+            .iter()
+            .map(|j| (j.host.as_str(), j.port, j.username.as_str()))
+            .collect();
+        assert_eq!(hosts, [("fe80::1", 2200, "root"), ("c", 22, "")]);
+        assert_eq!(a.auth, SshAuth::Password);
+        assert_eq!(a.warnings.len(), 1);
+
+        // c jumps through d, which jumps through c
+        let c = resolve_host(&config, "c", home);
+        let hosts: Vec<&str> = c.jump_hosts.iter().map(|j| j.host.as_str()).collect();
+        assert_eq!(hosts, ["c", "d"]);
+        assert!(c.warnings[0].contains("loops back"));
+        assert!(!resolve_host(&config, "other", home).matched);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("core-?", "core-1"));
+        assert!(!glob_match("core-?", "core-10"));
+        assert!(glob_match("*.lab", "r1.lab"));
+        assert!(!glob_match("*.lab", "r1.lab.com"));
+    }
+}
@@ -0,0 +1,575 @@
+//! Syslog receiver for messages pushed by devices.
+//!
+//! Listens on UDP and/or TCP (RFC 6587 octet counting or one message per
+//! line) and parses RFC 3164, RFC 5424 and the Huawei info-center format
+//! (`%%01IFNET/4/LINK_STATE(l)[5]:...`). Each message is tagged with the
+//! open sessions and saved profiles whose host is its source address,
+//! optionally looked up in the `LogTracer`, kept in a bounded history and
+//! emitted as a `syslog` event.
+
+use crate::profile::ProfileStore;
+use crate::session::SessionManager;
+use crate::tracer::LogTracer;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, LazyLock, Mutex};
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{debug, info, warn};
+
+pub const SYSLOG_PORT: u16 = 514;
+
+/// Longest message accepted; longer TCP frames end the connection
+const MAX_MESSAGE: usize = 8192;
+
+/// Messages kept for `list_syslog_messages`
+const MAX_RECENT: usize = 1000;
+
+/// Messages waiting to be parsed before receivers drop new ones
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Messages kept for a slow subscriber
+const STREAM_CAPACITY: usize = 256;
+
+/// `<PRI>`
+static PRI_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^<(\d{1,3})>").unwrap());
+
+/// `1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD [MSG]`
+static RFC5424_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)^1 (\S+) (\S+) (\S+) (\S+) (\S+) (-|(?:\[[^\]]*\])+)(?: (.*))?$").unwrap()
+});
+
+/// `Oct 14 2026 12:00:00+08:00 HOST %%01IFNET/4/LINK_STATE(l)[5]:text`;
+/// the year, fraction and time zone are optional
+static HUAWEI_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?s)^([A-Z][a-z]{2} +\d{1,2}(?: \d{4})? \d{2}:\d{2}:\d{2}(?:\.\d+)?",
+        r"(?: ?[+-]\d{2}:\d{2}| [A-Z]{2,5}(?:[+-]\d{2}:\d{2})?)?) ",
+        r"(\S+) %%\d{2}([A-Za-z0-9_]+)/(\d)/([A-Za-z0-9_]+)(?:\([a-z]+\))?(?:\[\d+\])?:\s*(.*)$",
+    ))
+    .unwrap()
+});
+
+/// `Oct 14 12:00:00 HOST rest`
+static RFC3164_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)^([A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}) (\S+) (.*)$").unwrap()
+});
+
+/// `TAG[pid]: text` at the start of an RFC 3164 message
+static TAG_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)^([^:\[\s]+)(?:\[[^\]]*\])?: ?(.*)$").unwrap());
+
+#[derive(Error, Debug)]
+pub enum SyslogError {
+    #[error("Syslog receiver already running")]
+    AlreadyRunning,
+    #[error("Syslog receiver not running")]
+    NotRunning,
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl From<SyslogError> for String {
+    fn from(err: SyslogError) -> String {
+        err.to_string()
+    }
+}
+
+/// Where to listen; with neither port set, UDP on `SYSLOG_PORT`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SyslogOptions {
+    /// Default `0.0.0.0`
+    #[serde(default)]
+    pub bind: Option<String>,
+    #[serde(default)]
+    pub udp_port: Option<u16>,
+    #[serde(default)]
+    pub tcp_port: Option<u16>,
+    /// Look each message up in the tracer index
+    #[serde(default)]
+    pub trace: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogFormat {
+    Rfc3164,
+    Rfc5424,
+    Huawei,
+    /// No known header; the whole text is the message
+    Unknown,
+}
+
+/// A received message; also the body of `syslog` events
+#[derive(Debug, Clone, Serialize)]
+pub struct SyslogMessage {
+    /// Unix milliseconds
+    pub received_at: i64,
+    /// Sender's IP address
+    pub source: String,
+    pub transport: Transport,
+    pub format: SyslogFormat,
+    pub facility: Option<u8>,
+    /// 0 (emergency) to 7 (debug), from the PRI or the Huawei level
+    pub severity: Option<u8>,
+    /// As sent by the device
+    pub timestamp: Option<String>,
+    pub hostname: Option<String>,
+    /// RFC 3164 tag, RFC 5424 APP-NAME or Huawei module (`IFNET`)
+    pub app_name: Option<String>,
+    /// RFC 5424 MSGID or Huawei mnemonic (`LINK_STATE`)
+    pub msg_id: Option<String>,
+    pub message: String,
+    /// Open sessions to the source address
+    pub session_ids: Vec<String>,
+    /// Saved profiles for the source address
+    pub profile_ids: Vec<String>,
+    /// Tracer match as `file:line`, when `trace` is on
+    pub source_location: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyslogStatus {
+    pub running: bool,
+    pub udp_addr: Option<String>,
+    pub tcp_addr: Option<String>,
+    pub trace: bool,
+}
+
+/// What tagging a message needs
+pub struct SyslogContext {
+    pub manager: Arc<SessionManager>,
+    pub profiles: Arc<tokio::sync::Mutex<ProfileStore>>,
+    pub tracer: Arc<tokio::sync::Mutex<LogTracer>>,
+}
+
+struct Running {
+    status: SyslogStatus,
+    /// Dropped to stop the listeners
+    _shutdown: watch::Sender<()>,
+}
+
+/// The receiver, started and stopped from the frontend
+pub struct SyslogReceiver {
+    running: Mutex<Option<Running>>,
+    recent: Mutex<VecDeque<SyslogMessage>>,
+    stream: broadcast::Sender<SyslogMessage>,
+}
+
+impl SyslogReceiver {
+    pub fn new() -> Self {
+        Self {
+            running: Mutex::new(None),
+            recent: Mutex::new(VecDeque::new()),
+            stream: broadcast::channel(STREAM_CAPACITY).0,
+        }
+    }
+
+    /// Bind the listeners and start receiving
+    pub async fn start(
+        self: &Arc<Self>,
+        options: SyslogOptions,
+        ctx: SyslogContext,
+    ) -> Result<SyslogStatus, SyslogError> {
+        if self.running.lock().unwrap().is_some() {
+            return Err(SyslogError::AlreadyRunning);
+        }
+        let bind = options.bind.as_deref().unwrap_or("0.0.0.0");
+        let udp_port = match (options.udp_port, options.tcp_port) {
+            (None, None) => Some(SYSLOG_PORT),
+            (port, _) => port,
+        };
+        let udp = match udp_port {
+            Some(port) => Some(UdpSocket::bind((bind, port)).await?),
+            None => None,
+        };
+        let tcp = match options.tcp_port {
+            Some(port) => Some(TcpListener::bind((bind, port)).await?),
+            None => None,
+        };
+
+        let status = SyslogStatus {
+            running: true,
+            udp_addr: udp
+                .as_ref()
+                .and_then(|s| s.local_addr().ok())
+                .map(|a| a.to_string()),
+            tcp_addr: tcp
+                .as_ref()
+                .and_then(|l| l.local_addr().ok())
+                .map(|a| a.to_string()),
+            trace: options.trace,
+        };
+        let (shutdown, stopped) = watch::channel(());
+        let (queue, received) = mpsc::channel(QUEUE_CAPACITY);
+        if let Some(socket) = udp {
+            tokio::spawn(receive_udp(socket, queue.clone(), stopped.clone()));
+        }
+        if let Some(listener) = tcp {
+            tokio::spawn(accept_tcp(listener, queue, stopped.clone()));
+        }
+        tokio::spawn(Arc::clone(self).deliver(received, ctx, options.trace));
+
+        info!(udp = ?status.udp_addr, tcp = ?status.tcp_addr, "Syslog receiver started");
+        *self.running.lock().unwrap() = Some(Running {
+            status: status.clone(),
+            _shutdown: shutdown,
+        });
+        Ok(status)
+    }
+
+    pub fn stop(&self) -> Result<(), SyslogError> {
+        self.running
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(SyslogError::NotRunning)?;
+        info!("Syslog receiver stopped");
+        Ok(())
+    }
+
+    pub fn status(&self) -> SyslogStatus {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|r| r.status.clone())
+            .unwrap_or_default()
+    }
+
+    /// The newest `limit` messages, oldest first
+    pub fn recent(&self, limit: usize) -> Vec<SyslogMessage> {
+        let recent = self.recent.lock().unwrap();
+        recent
+            .iter()
+            .skip(recent.len().saturating_sub(limit))
+            .cloned()
+            .collect()
+    }
+
+    /// Messages as they are received, for the alert rules
+    pub fn subscribe(&self) -> broadcast::Receiver<SyslogMessage> {
+        self.stream.subscribe()
+    }
+
+    /// Parse, tag and publish queued messages until the listeners stop
+    async fn deliver(
+        self: Arc<Self>,
+        mut received: mpsc::Receiver<(String, IpAddr, Transport)>,
+        ctx: SyslogContext,
+        trace: bool,
+    ) {
+        while let Some((raw, source, transport)) = received.recv().await {
+            let mut message = parse(&raw, source, transport);
+            (message.session_ids, message.profile_ids) =
+                correlate(&ctx.manager, &ctx.profiles, source).await;
+            if trace {
+                let tracer = ctx.tracer.lock().await;
+                message.source_location = tracer
+                    .match_log(&message.message)
+                    .map(|location| format!("{}:{}", location.file, location.line));
+            }
+
+            if let Err(e) = ctx.manager.app_handle().emit("syslog", &message) {
+                warn!(error = %e, "Failed to emit syslog message");
+            }
+            let _ = self.stream.send(message.clone());
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() >= MAX_RECENT {
+                recent.pop_front();
+            }
+            recent.push_back(message);
+        }
+    }
+}
+
+/// Ids of the open sessions and saved profiles whose host is `source`
+pub async fn correlate(
+    manager: &SessionManager,
+    profiles: &tokio::sync::Mutex<ProfileStore>,
+    source: IpAddr,
+) -> (Vec<String>, Vec<String>) {
+    let sessions = manager
+        .list()
+        .iter()
+        .filter(|handle| same_host(&handle.config.host, source))
+        .map(|handle| handle.id.clone())
+        .collect();
+    let profiles = profiles
+        .lock()
+        .await
+        .list()
+        .iter()
+        .filter(|profile| same_host(&profile.host, source))
+        .map(|profile| profile.id.clone())
+        .collect();
+    (sessions, profiles)
+}
+
+/// A profile or session host naming `source`; host names aren't resolved
+fn same_host(host: &str, source: IpAddr) -> bool {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_ok_and(|ip| ip.to_canonical() == source.to_canonical())
+}
+
+async fn receive_udp(
+    socket: UdpSocket,
+    queue: mpsc::Sender<(String, IpAddr, Transport)>,
+    mut stopped: watch::Receiver<()>,
+) {
+    let mut buf = vec![0u8; MAX_MESSAGE];
+    loop {
+        let (n, peer) = tokio::select! {
+            received = socket.recv_from(&mut buf) => match received {
+                Ok(received) => received,
+                Err(e) => {
+                    // ICMP errors from earlier sends surface here; keep going
+                    debug!(error = %e, "Syslog UDP receive failed");
+                    continue;
+                }
+            },
+            _ = stopped.changed() => break,
+        };
+        let raw = String::from_utf8_lossy(&buf[..n]).into_owned();
+        if queue.try_send((raw, peer.ip(), Transport::Udp)).is_err() {
+            debug!(peer = %peer, "Syslog queue full, dropping message");
+        }
+    }
+}
+
+async fn accept_tcp(
+    listener: TcpListener,
+    queue: mpsc::Sender<(String, IpAddr, Transport)>,
+    mut stopped: watch::Receiver<()>,
+) {
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "Syslog TCP accept failed");
+                    continue;
+                }
+            },
+            _ = stopped.changed() => break,
+        };
+        debug!(peer = %peer, "Syslog TCP connection");
+        let queue = queue.clone();
+        let mut stopped = stopped.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                result = receive_tcp(BufReader::new(stream), peer, &queue) => {
+                    if let Err(e) = result {
+                        debug!(peer = %peer, error = %e, "Syslog TCP connection ended");
+                    }
+                }
+                _ = stopped.changed() => {}
+            }
+        });
+    }
+}
+
+async fn receive_tcp<R: AsyncBufRead + Unpin>(
+    mut reader: R,
+    peer: SocketAddr,
+    queue: &mpsc::Sender<(String, IpAddr, Transport)>,
+) -> io::Result<()> {
+    let mut frame = Vec::new();
+    while read_frame(&mut reader, &mut frame).await? {
+        let raw = String::from_utf8_lossy(&frame).into_owned();
+        if queue.send((raw, peer.ip(), Transport::Tcp)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Read one message: `LEN SP MSG` when the frame starts with a digit,
+/// else up to LF. False at the end of the stream.
+async fn read_frame<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    frame: &mut Vec<u8>,
+) -> io::Result<bool> {
+    frame.clear();
+    let first = match reader.fill_buf().await?.first() {
+        Some(&byte) => byte,
+        None => return Ok(false),
+    };
+    let too_long = || io::Error::new(io::ErrorKind::InvalidData, "syslog frame too long");
+    if first.is_ascii_digit() {
+        let mut len = Vec::new();
+        (&mut *reader).take(8).read_until(b' ', &mut len).await?;
+        let len: usize = std::str::from_utf8(&len)
+            .ok()
+            .and_then(|len| len.trim_end().parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad octet count"))?;
+        if len > MAX_MESSAGE {
+            return Err(too_long());
+        }
+        frame.resize(len, 0);
+        reader.read_exact(frame).await?;
+    } else {
+        (&mut *reader)
+            .take(MAX_MESSAGE as u64 + 1)
+            .read_until(b'\n', frame)
+            .await?;
+        if frame.len() > MAX_MESSAGE {
+            return Err(too_long());
+        }
+    }
+    while matches!(frame.last(), Some(b'\n' | b'\r' | b'\0')) {
+        frame.pop();
+    }
+    Ok(true)
+}
+
+/// Parse one message; correlation fields are left empty
+pub fn parse(raw: &str, source: IpAddr, transport: Transport) -> SyslogMessage {
+    let mut message = SyslogMessage {
+        received_at: chrono::Utc::now().timestamp_millis(),
+        source: source.to_canonical().to_string(),
+        transport,
+        format: SyslogFormat::Unknown,
+        facility: None,
+        severity: None,
+        timestamp: None,
+        hostname: None,
+        app_name: None,
+        msg_id: None,
+        message: String::new(),
+        session_ids: Vec::new(),
+        profile_ids: Vec::new(),
+        source_location: None,
+    };
+    let mut rest = raw.trim_end();
+    if let Some(caps) = PRI_RE.captures(rest) {
+        if let Ok(pri) = caps[1].parse::<u8>() {
+            message.facility = Some(pri / 8);
+            message.severity = Some(pri % 8);
+        }
+        rest = &rest[caps[0].len()..];
+    }
+    let field = |value: &str| (value != "-").then(|| value.to_string());
+
+    if let Some(caps) = RFC5424_RE.captures(rest) {
+        message.format = SyslogFormat::Rfc5424;
+        message.timestamp = field(&caps[1]);
+        message.hostname = field(&caps[2]);
+        message.app_name = field(&caps[3]);
+        message.msg_id = field(&caps[5]);
+        message.message = caps
+            .get(7)
+            .map(|m| m.as_str().trim_start_matches('\u{feff}').to_string())
+            .unwrap_or_default();
+    } else if let Some(caps) = HUAWEI_RE.captures(rest) {
+        message.format = SyslogFormat::Huawei;
+        message.timestamp = Some(caps[1].to_string());
+        message.hostname = Some(caps[2].to_string());
+        message.app_name = Some(caps[3].to_string());
+        message.severity = message.severity.or(caps[4].parse().ok());
+        message.msg_id = Some(caps[5].to_string());
+        message.message = caps[6].to_string();
+    } else if let Some(caps) = RFC3164_RE.captures(rest) {
+        message.format = SyslogFormat::Rfc3164;
+        message.timestamp = Some(caps[1].to_string());
+        message.hostname = Some(caps[2].to_string());
+        match TAG_RE.captures(&caps[3]) {
+            Some(tag) => {
+                message.app_name = Some(tag[1].to_string());
+                message.message = tag[2].to_string();
+            }
+            None => message.message = caps[3].to_string(),
+        }
+    } else {
+        message.message = rest.to_string();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        let source: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+
+        let huawei = parse(
+            "<188>Oct 14 2026 12:00:00+08:00 CORE-1 %%01IFNET/4/LINK_STATE(l)[5]:The line protocol IP on the interface GigabitEthernet0/0/1 has entered the DOWN state.",
+            source,
+            Transport::Udp,
+        );
+        assert_eq!(huawei.format, SyslogFormat::Huawei);
+        assert_eq!(huawei.source, "10.0.0.1");
+        assert_eq!((huawei.facility, huawei.severity), (Some(23), Some(4)));
+        assert_eq!(
+            huawei.timestamp.as_deref(),
+            Some("Oct 14 2026 12:00:00+08:00")
+        );
+        assert_eq!(huawei.hostname.as_deref(), Some("CORE-1"));
+        assert_eq!(huawei.app_name.as_deref(), Some("IFNET"));
+        assert_eq!(huawei.msg_id.as_deref(), Some("LINK_STATE"));
+        assert!(huawei.message.starts_with("The line protocol"));
+
+        let rfc5424 = parse(
+            "<34>1 2026-10-14T12:00:00.003Z edge-2 sshd 812 AUTH [origin ip=\"10.0.0.2\"] \u{feff}Failed password",
+            source,
+            Transport::Tcp,
+        );
+        assert_eq!(rfc5424.format, SyslogFormat::Rfc5424);
+        assert_eq!(rfc5424.severity, Some(2));
+        assert_eq!(rfc5424.app_name.as_deref(), Some("sshd"));
+        assert_eq!(rfc5424.msg_id.as_deref(), Some("AUTH"));
+        assert_eq!(rfc5424.message, "Failed password");
+
+        let rfc3164 = parse(
+            "<13>Oct  4 09:15:01 board-3 kernel[0]: eth0: link down",
+            source,
+            Transport::Udp,
+        );
+        assert_eq!(rfc3164.format, SyslogFormat::Rfc3164);
+        assert_eq!(rfc3164.timestamp.as_deref(), Some("Oct  4 09:15:01"));
+        assert_eq!(rfc3164.app_name.as_deref(), Some("kernel"));
+        assert_eq!(rfc3164.message, "eth0: link down");
+
+        let unknown = parse("just text\n", source, Transport::Udp);
+        assert_eq!(unknown.format, SyslogFormat::Unknown);
+        assert_eq!(
+            (unknown.severity, unknown.message.as_str()),
+            (None, "just text")
+        );
+
+        assert!(same_host("10.0.0.1", source));
+        assert!(!same_host("core-1.lab", source));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_framing() {
+        let data: &[u8] = b"11 <13>counted<13>line one\r\n<13>last";
+        let mut reader = BufReader::new(data);
+        let mut frame = Vec::new();
+        let mut frames = Vec::new();
+        while read_frame(&mut reader, &mut frame).await.unwrap() {
+            frames.push(String::from_utf8(frame.clone()).unwrap());
+        }
+        assert_eq!(frames, ["<13>counted", "<13>line one", "<13>last"]);
+
+        let mut reader = BufReader::new(&b"99999 x"[..]);
+        assert!(read_frame(&mut reader, &mut frame).await.is_err());
+    }
+}
@@ -1,13 +1,23 @@
+use crate::activity::spawn_activity;
+use crate::capture::CaptureTap;
+use crate::emitter::spawn_emitter;
+use crate::latency;
+use crate::pipeline::{Pipeline, PipelineStages};
+use crate::plugin::ProcessorChain;
 use crate::ringbuffer::SessionRingBuffer;
-use crate::session::{SessionConfig, SessionError, SessionHandle, SessionManager, SessionState};
-use crate::vrp::{VrpEvent, VrpParser};
+use crate::scrollback::Scrollback;
+use crate::session::{
+    connect_tcp, SessionConfig, SessionError, SessionHandle, SessionManager, SessionState,
+};
+use crate::zmodem::{ZmodemCommand, ZmodemTap};
+use bytes::Bytes;
 use socket2::{SockRef, TcpKeepalive};
 use std::sync::Arc;
 use std::time::Duration;
 use tauri::Emitter;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep_until, Instant};
 use tracing::{debug, error, info, warn};
 
 // Telnet protocol constants
@@ -18,6 +28,7 @@ const WONT: u8 = 252;
 const WILL: u8 = 251;
 const SB: u8 = 250; // Sub-negotiation Begin
 const SE: u8 = 240; // Sub-negotiation End
+const BRK: u8 = 243; // Break
 
 // Telnet options
 const OPT_ECHO: u8 = 1;
@@ -25,7 +36,7 @@ const OPT_SUPPRESS_GO_AHEAD: u8 = 3;
 const OPT_TERMINAL_TYPE: u8 = 24;
 const OPT_NAWS: u8 = 31; // Negotiate About Window Size
 
-struct TelnetParser {
+pub(crate) struct TelnetParser {
     state: TelnetParseState,
     subneg_option: u8,
     subneg_data: Vec<u8>,
@@ -45,7 +56,7 @@ enum TelnetParseState {
 }
 
 impl TelnetParser {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             state: TelnetParseState::Normal,
             subneg_option: 0,
@@ -53,7 +64,7 @@ impl TelnetParser {
         }
     }
 
-    fn parse(&mut self, input: &[u8]) -> (Vec<u8>, Vec<TelnetCommand>) {
+    pub(crate) fn parse(&mut self, input: &[u8]) -> (Vec<u8>, Vec<TelnetCommand>) {
         let mut output = Vec::with_capacity(input.len());
         let mut commands = Vec::new();
 
@@ -130,7 +141,7 @@ impl TelnetParser {
 
 #[derive(Debug)]
 #[allow(dead_code)]
-enum TelnetCommand {
+pub(crate) enum TelnetCommand {
     Will(u8),
     Wont(u8),
     Do(u8),
@@ -138,7 +149,12 @@ enum TelnetCommand {
     Subnegotiation(u8, Vec<u8>),
 }
 
-fn build_response(commands: &[TelnetCommand], cols: u32, rows: u32) -> Vec<u8> {
+fn build_response(
+    commands: &[TelnetCommand],
+    cols: u32,
+    rows: u32,
+    terminal_type: &str,
+) -> Vec<u8> {
     let mut response = Vec::new();
 
     for cmd in commands {
@@ -182,7 +198,7 @@ fn build_response(commands: &[TelnetCommand], cols: u32, rows: u32) -> Vec<u8> {
                         OPT_TERMINAL_TYPE,
                         0, // IS
                     ]);
-                    response.extend_from_slice(b"xterm-256color");
+                    response.extend_from_slice(terminal_type.as_bytes());
                     response.extend_from_slice(&[IAC, SE]);
                 }
             }
@@ -193,6 +209,18 @@ fn build_response(commands: &[TelnetCommand], cols: u32, rows: u32) -> Vec<u8> {
     response
 }
 
+/// Double IAC bytes so binary data reaches the peer unchanged
+fn escape_iac(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for &byte in data {
+        if byte == IAC {
+            out.push(IAC);
+        }
+        out.push(byte);
+    }
+    out
+}
+
 fn build_naws(cols: u32, rows: u32) -> Vec<u8> {
     let cols = cols as u16;
     let rows = rows as u16;
@@ -230,6 +258,7 @@ pub async fn run_telnet_session(
     session_id: String,
     config: SessionConfig,
     manager: Arc<SessionManager>,
+    processors: ProcessorChain,
 ) -> Result<(), SessionError> {
     let app_handle = manager.app_handle().clone();
 
@@ -238,12 +267,31 @@ pub async fn run_telnet_session(
     let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
     let (resize_tx, mut resize_rx) = mpsc::channel::<(u32, u32)>(16);
     let (auto_pagination_tx, mut auto_pagination_rx) = mpsc::channel::<bool>(16);
-    let (drain_tx, mut drain_rx) = mpsc::channel::<()>(16);
+    let (break_tx, mut break_rx) = mpsc::channel::<u32>(4);
+    let (drain_tx, drain_rx) = mpsc::channel::<()>(16);
+    let (zmodem_tx, mut zmodem_rx) = mpsc::channel::<ZmodemCommand>(4);
 
     // Create ring buffer for backpressure
-    let buffer = Arc::new(Mutex::new(SessionRingBuffer::new(session_id.clone())));
+    let buffer = SessionRingBuffer::new(session_id.clone(), config.buffer.unwrap_or_default())
+        .with_budget(Arc::clone(manager.memory_budget()));
+    let buffer = Arc::new(Mutex::new(buffer));
+    let scrollback = Arc::new(Mutex::new(Scrollback::default()));
+
+    // Emitter task drains the buffer to the frontend in coalesced chunks
+    let (emitter, backpressure) = spawn_emitter(
+        session_id.clone(),
+        app_handle.clone(),
+        Arc::clone(&buffer),
+        Arc::clone(&scrollback),
+        &config,
+        drain_rx,
+    );
+
+    // Raw byte capture, from the start when the config asks for it
+    let capture = CaptureTap::for_session(&session_id, &config);
 
     // Store session handle
+    let stages = Arc::new(PipelineStages::default());
     let handle = SessionHandle {
         id: session_id.clone(),
         config: config.clone(),
@@ -252,10 +300,23 @@ pub async fn run_telnet_session(
         shutdown_tx,
         resize_tx,
         auto_pagination_tx: Some(auto_pagination_tx),
+        break_tx: Some(break_tx),
         buffer: Arc::clone(&buffer),
+        scrollback,
         drain_tx,
+        backpressure: backpressure.clone(),
+        output: emitter.output(),
+        capture: capture.clone(),
+        zmodem_tx,
+        pipeline: Arc::clone(&stages),
     };
     manager.insert(handle);
+    spawn_activity(
+        session_id.clone(),
+        app_handle.clone(),
+        emitter.output().subscribe(),
+        config.activity.unwrap_or_default(),
+    );
 
     // Emit connecting state
     emit_state(&app_handle, &session_id, SessionState::Connecting);
@@ -264,46 +325,64 @@ pub async fn run_telnet_session(
     let addr = format!("{}:{}", config.host, config.port);
     info!(session_id = %session_id, addr = %addr, "Connecting to Telnet server");
 
-    let stream = match TcpStream::connect(&addr).await {
+    // disconnect_session drops the connect
+    let connected = tokio::select! {
+        connected = connect_tcp(&addr, config.connect_timeout()) => {
+            connected.map_err(|e| SessionError::ConnectionFailed(e.to_string()))
+        }
+        _ = shutdown_rx.recv() => Err(SessionError::Cancelled),
+    };
+    let stream = match connected {
         Ok(stream) => stream,
         Err(e) => {
             error!(session_id = %session_id, error = %e, "Telnet connection failed");
-            emit_state(&app_handle, &session_id, SessionState::Error);
+            emit_state(&app_handle, &session_id, e.end_state());
             manager.remove(&session_id);
-            return Err(SessionError::ConnectionFailed(e.to_string()));
+            return Err(e);
         }
     };
 
     // Configure TCP keepalive to detect connection loss
-    let sock_ref = SockRef::from(&stream);
-    let keepalive = TcpKeepalive::new()
-        .with_time(Duration::from_secs(30))
-        .with_interval(Duration::from_secs(10));
-
-    #[cfg(target_os = "linux")]
-    let keepalive = keepalive.with_retries(3);
-
-    if let Err(e) = sock_ref.set_tcp_keepalive(&keepalive) {
-        warn!(session_id = %session_id, error = %e, "Failed to set TCP keepalive");
-    } else {
-        debug!(session_id = %session_id, "TCP keepalive configured (30s idle, 10s interval)");
+    let keepalive_config = config.keepalive.unwrap_or_default();
+    if keepalive_config.interval_secs > 0 {
+        let sock_ref = SockRef::from(&stream);
+        let keepalive = TcpKeepalive::new()
+            .with_time(Duration::from_secs(keepalive_config.interval_secs))
+            .with_interval(Duration::from_secs(10));
+
+        #[cfg(target_os = "linux")]
+        let keepalive = keepalive.with_retries(keepalive_config.max_missed);
+
+        if let Err(e) = sock_ref.set_tcp_keepalive(&keepalive) {
+            warn!(session_id = %session_id, error = %e, "Failed to set TCP keepalive");
+        } else {
+            debug!(
+                session_id = %session_id,
+                idle_secs = keepalive_config.interval_secs,
+                "TCP keepalive configured (10s interval)"
+            );
+        }
     }
 
     emit_state(&app_handle, &session_id, SessionState::Connected);
     emit_state(&app_handle, &session_id, SessionState::Ready);
     info!(session_id = %session_id, "Telnet session ready");
+    manager.history().connected(&session_id, &config);
 
     let (mut reader, mut writer) = stream.into_split();
     let mut telnet_parser = TelnetParser::new();
-    let mut vrp_parser = VrpParser::new();
+    let mut pipeline = Pipeline::new(&session_id, &config, &manager, processors, stages);
+    let mut zmodem = ZmodemTap::new(&session_id, &app_handle, true);
     let mut read_buf = [0u8; 4096];
     let mut current_cols = config.cols;
     let mut current_rows = config.rows;
 
-    // Backpressure state
-    let mut is_paused = false;
+    // Backpressure state, shared with the emitter
+    let mut pause_rx = backpressure.subscribe();
 
     loop {
+        let is_paused = *pause_rx.borrow();
+        let zmodem_deadline = zmodem.deadline();
         tokio::select! {
             // Read from server (only if not paused due to backpressure)
             result = reader.read(&mut read_buf), if !is_paused => {
@@ -313,69 +392,52 @@ pub async fn run_telnet_session(
                         break;
                     }
                     Ok(n) => {
+                        capture.inbound(&read_buf[..n]);
                         let (data, commands) = telnet_parser.parse(&read_buf[..n]);
 
                         // Handle telnet commands
                         if !commands.is_empty() {
-                            let response = build_response(&commands, current_cols, current_rows);
+                            let response = build_response(&commands, current_cols, current_rows, config.terminal_type());
                             if !response.is_empty() {
+                                capture.outbound(&response);
                                 if let Err(e) = writer.write_all(&response).await {
                                     warn!(session_id = %session_id, error = %e, "Failed to send telnet response");
                                 }
                             }
                         }
 
-                        // Process through VRP parser for Huawei-specific handling
-                        let (vrp_data, vrp_events, auto_response) = vrp_parser.parse(&data);
-
-                        // Emit VRP events to frontend
-                        for event in vrp_events {
-                            let vrp_event_name = format!("session:{}:vrp", session_id);
-                            if let Err(e) = app_handle.emit(&vrp_event_name, &event) {
-                                warn!(session_id = %session_id, error = %e, "Failed to emit VRP event");
-                            }
-                            // Log significant events
-                            match &event {
-                                VrpEvent::ViewChange { view, hostname } => {
-                                    debug!(session_id = %session_id, view = ?view, hostname = %hostname, "VRP view change");
-                                }
-                                VrpEvent::Pagination { detected, auto_handled } => {
-                                    debug!(session_id = %session_id, detected = detected, auto_handled = auto_handled, "VRP pagination");
-                                }
-                                VrpEvent::BoardInfo(board) => {
-                                    debug!(session_id = %session_id, slot = %board.slot_id, board_type = %board.board_type, "VRP board detected");
-                                }
+                        // A ZMODEM transfer takes the raw bytes while it runs
+                        let (data, reply) = zmodem.inbound(data);
+                        if !reply.is_empty() {
+                            let reply = escape_iac(&reply);
+                            capture.outbound(&reply);
+                            if let Err(e) = writer.write_all(&reply).await {
+                                error!(session_id = %session_id, error = %e, "Failed to send ZMODEM data");
+                                break;
                             }
                         }
 
+                        // Charset, processors, VRP parsing and triggers (see pipeline.rs)
+                        let processed = pipeline.process(data);
+                        let data = processed.data;
+
                         // Send auto-response (e.g., space for pagination)
-                        if let Some(response) = auto_response {
+                        if let Some(response) = processed.auto_response {
+                            capture.outbound(&response);
                             if let Err(e) = writer.write_all(&response).await {
                                 warn!(session_id = %session_id, error = %e, "Failed to send VRP auto-response");
                             }
                         }
 
-                        // Buffer data and emit to frontend with backpressure control
-                        if !vrp_data.is_empty() {
+                        // Buffer data for the emitter with backpressure control
+                        if !data.is_empty() {
+                            debug!(session_id = %session_id, bytes = data.len(), "Received data from Telnet");
                             let mut buf = buffer.lock().await;
-                            buf.push(&vrp_data);
-
-                            // Check if we should pause reads
-                            if buf.should_pause() {
-                                is_paused = true;
-                                debug!(
-                                    session_id = %session_id,
-                                    buffer_fill = %buf.fill_percent(),
-                                    "Backpressure: pausing Telnet reads"
-                                );
-                            }
-
-                            // Emit data to frontend
-                            let event_name = format!("session:{}", session_id);
-                            debug!(session_id = %session_id, bytes = vrp_data.len(), "Received data from Telnet");
-                            if let Err(e) = app_handle.emit(&event_name, vrp_data) {
-                                error!(session_id = %session_id, error = %e, "Failed to emit data event");
-                            }
+                            buf.push_bytes(Bytes::from(data));
+                            backpressure.update(&buf);
+                            drop(buf);
+                            emitter.wake();
+                            latency::on_received(&manager, &session_id);
                         }
                     }
                     Err(e) => {
@@ -385,38 +447,53 @@ pub async fn run_telnet_session(
                 }
             }
 
-            // Handle drain notification from frontend
-            Some(()) = drain_rx.recv() => {
-                let mut buf = buffer.lock().await;
-                // Clear the buffer since frontend has processed the data
-                buf.drain_all();
-
-                // Check if we can resume reads
-                if is_paused && buf.can_resume() {
-                    is_paused = false;
-                    debug!(
-                        session_id = %session_id,
-                        buffer_fill = %buf.fill_percent(),
-                        "Backpressure: resuming Telnet reads"
-                    );
-                }
-            }
+            // Pause state changed, re-evaluate the read guard
+            Ok(()) = pause_rx.changed() => {}
 
             // Handle input from frontend
             Some(data) = input_rx.recv() => {
+                // Keystrokes would corrupt a running transfer
+                if zmodem.active() {
+                    continue;
+                }
                 debug!(session_id = %session_id, bytes = data.len(), "Sending data to Telnet");
+                let data = config.newline.translate(data);
+                capture.outbound(&data);
                 if let Err(e) = writer.write_all(&data).await {
                     error!(session_id = %session_id, error = %e, "Failed to send data");
                     break;
                 }
             }
 
+            // Answer to a ZMODEM offer
+            Some(command) = zmodem_rx.recv() => {
+                let reply = zmodem.command(command);
+                let reply = escape_iac(&reply);
+                capture.outbound(&reply);
+                if let Err(e) = writer.write_all(&reply).await {
+                    error!(session_id = %session_id, error = %e, "Failed to send ZMODEM data");
+                    break;
+                }
+            }
+
+            // ZMODEM peer gone quiet, or no answer to the offer
+            _ = sleep_until(zmodem_deadline.unwrap_or_else(Instant::now)), if zmodem_deadline.is_some() => {
+                let reply = zmodem.expire();
+                let reply = escape_iac(&reply);
+                capture.outbound(&reply);
+                if let Err(e) = writer.write_all(&reply).await {
+                    error!(session_id = %session_id, error = %e, "Failed to send ZMODEM data");
+                    break;
+                }
+            }
+
             // Handle resize requests
             Some((cols, rows)) = resize_rx.recv() => {
                 debug!(session_id = %session_id, cols = cols, rows = rows, "Resizing terminal");
                 current_cols = cols;
                 current_rows = rows;
                 let naws = build_naws(cols, rows);
+                capture.outbound(&naws);
                 if let Err(e) = writer.write_all(&naws).await {
                     warn!(session_id = %session_id, error = %e, "Failed to send NAWS");
                 }
@@ -425,7 +502,15 @@ pub async fn run_telnet_session(
             // Handle auto-pagination toggle
             Some(enabled) = auto_pagination_rx.recv() => {
                 debug!(session_id = %session_id, enabled = enabled, "Setting auto-pagination");
-                vrp_parser.auto_pagination = enabled;
+                pipeline.set_auto_pagination(enabled);
+            }
+
+            // Handle BREAK requests
+            Some(duration_ms) = break_rx.recv() => {
+                info!(session_id = %session_id, ignored_duration_ms = duration_ms, "Sending BREAK");
+                if let Err(e) = send_break(&mut writer, &capture).await {
+                    warn!(session_id = %session_id, error = %e, "Failed to send BREAK");
+                }
             }
 
             // Handle shutdown request
@@ -436,14 +521,26 @@ pub async fn run_telnet_session(
         }
     }
 
-    // Cleanup
+    // Cleanup: deliver the last output before reporting the disconnect
     info!(session_id = %session_id, "Telnet session ending");
+    emitter.close().await;
     emit_state(&app_handle, &session_id, SessionState::Disconnected);
+    manager.history().disconnected(&session_id);
     manager.remove(&session_id);
 
     Ok(())
 }
 
+/// IAC BREAK. Telnet gives a BREAK no length, so the requested duration is
+/// dropped and the console server applies its own.
+async fn send_break(
+    writer: &mut (impl AsyncWrite + Unpin),
+    capture: &CaptureTap,
+) -> std::io::Result<()> {
+    capture.outbound(&[IAC, BRK]);
+    writer.write_all(&[IAC, BRK]).await
+}
+
 fn emit_state(app_handle: &tauri::AppHandle, session_id: &str, state: SessionState) {
     let event_name = format!("session:{}:state", session_id);
     if let Err(e) = app_handle.emit(&event_name, state) {
@@ -454,3 +551,15 @@ fn emit_state(app_handle: &tauri::AppHandle, session_id: &str, state: SessionSta
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send_break() {
+        let mut written = Vec::new();
+        send_break(&mut written, &CaptureTap::default()).await.unwrap();
+        assert_eq!(written, [0xFF, 0xF3]);
+    }
+}
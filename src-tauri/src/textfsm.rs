@@ -0,0 +1,544 @@
+//! TextFSM templates: turn `display`/`show` output into records.
+//!
+//! Templates use the TextFSM format, so ntc-templates files work unchanged
+//! as long as their patterns are valid Rust regex (lookaround is not):
+//!
+//! ```text
+//! Value Filldown Slot (\d+)
+//! Value Required Port (\S+)
+//! Value Status (up|down)
+//!
+//! Start
+//!   ^Slot ${Slot}
+//!   ^${Port}\s+${Status} -> Record
+//! ```
+//!
+//! Value options are `Filldown`, `Fillup`, `Required`, `List` and `Key`;
+//! rule actions are `Next`/`Continue`, `Record`/`NoRecord`/`Clear`/
+//! `Clearall`, a new state, or `Error`. Records are emitted as JSON objects
+//! keyed by value name, `List` values as arrays.
+
+use crate::command::CommandOutput;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value as Json};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use thiserror::Error;
+
+/// Longest state name TextFSM accepts
+const MAX_STATE_NAME: usize = 48;
+
+// `-> [LineOp][.RecordOp] [NewState]`, `-> RecordOp [NewState]` and
+// `-> [NewState]`
+static ACTION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"^\s+(?P<line>Continue|Next|Error)(?:\.(?P<record>Clear|Clearall|Record|NoRecord))?(?:\s+(?P<state>\w+|".*"))?$"#,
+    )
+    .unwrap()
+});
+static RECORD_ACTION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^\s+(?P<record>Clear|Clearall|Record|NoRecord)(?:\s+(?P<state>\w+|".*"))?$"#)
+        .unwrap()
+});
+static STATE_ACTION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^(?:\s+(?P<state>\w+|".*"))?$"#).unwrap());
+
+/// `$$`, `${Name}` and `$Name` in rule patterns
+static SUBSTITUTION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$(?:\$|\{(\w+)\}|(\w+))").unwrap());
+
+#[derive(Error, Debug)]
+pub enum TextFsmError {
+    #[error("Template line {line}: {message}")]
+    Template { line: usize, message: String },
+    /// An `Error` action matched
+    #[error("Template rejected input line {line}: {message}")]
+    Rejected { line: usize, message: String },
+}
+
+impl From<TextFsmError> for String {
+    fn from(err: TextFsmError) -> String {
+        err.to_string()
+    }
+}
+
+/// Result of `run_command_parsed`
+#[derive(Debug, Clone, Serialize)]
+pub struct ParsedOutput {
+    #[serde(flatten)]
+    pub output: CommandOutput,
+    pub records: Vec<Map<String, Json>>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct ValueOptions {
+    filldown: bool,
+    fillup: bool,
+    required: bool,
+    list: bool,
+}
+
+#[derive(Debug)]
+struct ValueDef {
+    name: String,
+    options: ValueOptions,
+    /// The value's pattern with its outer group named
+    pattern: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineOp {
+    Next,
+    Continue,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordOp {
+    NoRecord,
+    Record,
+    Clear,
+    ClearAll,
+}
+
+#[derive(Debug)]
+struct Rule {
+    regex: Regex,
+    line_op: LineOp,
+    record_op: RecordOp,
+    /// New state, or the message of an `Error` action
+    state: Option<String>,
+}
+
+/// Line number and name of a state, and its numbered rule lines
+type StateBlock<'a> = (usize, &'a str, Vec<(usize, &'a str)>);
+
+/// A parsed template, reusable across inputs
+#[derive(Debug)]
+pub struct Template {
+    values: Vec<ValueDef>,
+    states: HashMap<String, Vec<Rule>>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TextFsmError> {
+        let mut lines = source
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim_end()))
+            .filter(|(_, line)| !line.trim_start().starts_with('#'))
+            .peekable();
+
+        let mut values: Vec<ValueDef> = Vec::new();
+        while let Some((number, line)) = lines.next_if(|(_, line)| !line.trim().is_empty()) {
+            let value = parse_value(line).map_err(|message| template_error(number, message))?;
+            if values.iter().any(|v| v.name == value.name) {
+                return Err(template_error(
+                    number,
+                    format!("duplicate value {}", value.name),
+                ));
+            }
+            values.push(value);
+        }
+
+        // State names first, so rules may refer to states defined later
+        let mut blocks: Vec<StateBlock> = Vec::new();
+        for (number, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line.starts_with([' ', '\t']) {
+                match blocks.last_mut() {
+                    Some((_, _, rules)) => rules.push((number, line.trim())),
+                    None => return Err(template_error(number, "rule outside a state".to_string())),
+                }
+            } else {
+                blocks.push((number, line, Vec::new()));
+            }
+        }
+        for (number, name, _) in &blocks {
+            let valid = name.len() <= MAX_STATE_NAME
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+            if !valid {
+                return Err(template_error(
+                    *number,
+                    format!("invalid state name {}", name),
+                ));
+            }
+        }
+        let names: Vec<&str> = blocks.iter().map(|(_, name, _)| *name).collect();
+        if !names.contains(&"Start") {
+            return Err(template_error(0, "missing Start state".to_string()));
+        }
+
+        let mut states = HashMap::new();
+        for (number, name, rules) in blocks {
+            if states.contains_key(name) {
+                return Err(template_error(number, format!("duplicate state {}", name)));
+            }
+            if name == "End" && !rules.is_empty() {
+                return Err(template_error(
+                    number,
+                    "End state takes no rules".to_string(),
+                ));
+            }
+            let rules = rules
+                .into_iter()
+                .map(|(number, line)| {
+                    parse_rule(line, &values, &names)
+                        .map_err(|message| template_error(number, message))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            states.insert(name.to_string(), rules);
+        }
+        Ok(Self { values, states })
+    }
+
+    /// Records found in `text`, as JSON objects in input order
+    pub fn parse_text(&self, text: &str) -> Result<Vec<Map<String, Json>>, TextFsmError> {
+        let mut run = Run::new(self);
+        let mut state = "Start";
+        'lines: for (index, line) in text.lines().enumerate() {
+            let rules = &self.states[state];
+            for rule in rules {
+                let Some(captures) = rule.regex.captures(line) else {
+                    continue;
+                };
+                for (i, value) in self.values.iter().enumerate() {
+                    if let Some(matched) = captures.name(&value.name) {
+                        run.assign(i, matched.as_str());
+                    }
+                }
+                if rule.line_op == LineOp::Error {
+                    return Err(TextFsmError::Rejected {
+                        line: index + 1,
+                        message: rule
+                            .state
+                            .as_deref()
+                            .map(|m| m.trim_matches('"').to_string())
+                            .unwrap_or_else(|| format!("`{}`", line.trim())),
+                    });
+                }
+                match rule.record_op {
+                    RecordOp::NoRecord => {}
+                    RecordOp::Record => run.record(),
+                    RecordOp::Clear => run.clear(false),
+                    RecordOp::ClearAll => run.clear(true),
+                }
+                if let Some(next) = &rule.state {
+                    state = next;
+                    if state == "End" {
+                        break 'lines;
+                    }
+                }
+                if rule.line_op == LineOp::Next {
+                    continue 'lines;
+                }
+            }
+        }
+        // An explicit EOF state (usually empty) suppresses the last record
+        if state != "End" && !self.states.contains_key("EOF") {
+            run.record();
+        }
+        Ok(run.into_records())
+    }
+}
+
+fn template_error(line: usize, message: String) -> TextFsmError {
+    TextFsmError::Template { line, message }
+}
+
+/// `Value [Option,...] Name (regex)`
+fn parse_value(line: &str) -> Result<ValueDef, String> {
+    let tokens: Vec<&str> = line.split(' ').collect();
+    if tokens[0] != "Value" {
+        return Err(format!("expected a Value line, got `{}`", line));
+    }
+    if tokens.len() < 3 {
+        return Err("a value needs a name and a pattern".to_string());
+    }
+    let (option_list, name, pattern) = if tokens[2].starts_with('(') {
+        (None, tokens[1], tokens[2..].join(" "))
+    } else if tokens.len() >= 4 {
+        (Some(tokens[1]), tokens[2], tokens[3..].join(" "))
+    } else {
+        return Err("a value needs a name and a pattern".to_string());
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("invalid value name {}", name));
+    }
+    if !pattern.starts_with('(') || !pattern.ends_with(')') {
+        return Err(format!("the pattern of {} must be in parentheses", name));
+    }
+    Regex::new(&pattern).map_err(|e| format!("invalid pattern for {}: {}", name, e))?;
+
+    let mut options = ValueOptions::default();
+    for option in option_list.into_iter().flat_map(|list| list.split(',')) {
+        match option {
+            "Filldown" => options.filldown = true,
+            "Fillup" => options.fillup = true,
+            "Required" => options.required = true,
+            "List" => options.list = true,
+            "Key" => {}
+            other => return Err(format!("unknown value option {}", other)),
+        }
+    }
+    Ok(ValueDef {
+        name: name.to_string(),
+        options,
+        pattern: format!("(?P<{}>{}", name, &pattern[1..]),
+    })
+}
+
+/// `^pattern [-> action]`
+fn parse_rule(line: &str, values: &[ValueDef], states: &[&str]) -> Result<Rule, String> {
+    if !line.starts_with('^') {
+        return Err(format!("rules start with ^, got `{}`", line));
+    }
+    let (pattern, action) = match line.rfind(" ->") {
+        Some(at) => (&line[..at], &line[at + 3..]),
+        None => (line, ""),
+    };
+
+    let mut missing = None;
+    let source = SUBSTITUTION_RE.replace_all(pattern, |caps: &regex::Captures| {
+        // `$$` is a literal `$`, i.e. the end of line anchor
+        let Some(name) = caps.get(1).or(caps.get(2)) else {
+            return "$".to_string();
+        };
+        match values.iter().find(|v| v.name == name.as_str()) {
+            Some(value) => value.pattern.clone(),
+            None => {
+                missing.get_or_insert_with(|| name.as_str().to_string());
+                String::new()
+            }
+        }
+    });
+    if let Some(name) = missing {
+        return Err(format!("unknown value {}", name));
+    }
+    let regex = Regex::new(&source).map_err(|e| format!("invalid rule: {}", e))?;
+
+    let (line_op, record_op, state) = if let Some(caps) = ACTION_RE.captures(action) {
+        let line_op = match &caps["line"] {
+            "Continue" => LineOp::Continue,
+            "Error" => LineOp::Error,
+            _ => LineOp::Next,
+        };
+        (
+            line_op,
+            caps.name("record").map(|m| m.as_str()),
+            caps.name("state"),
+        )
+    } else if let Some(caps) = RECORD_ACTION_RE.captures(action) {
+        (
+            LineOp::Next,
+            Some(caps.name("record").unwrap().as_str()),
+            caps.name("state"),
+        )
+    } else if let Some(caps) = STATE_ACTION_RE.captures(action) {
+        (LineOp::Next, None, caps.name("state"))
+    } else {
+        return Err(format!("invalid action `{}`", action.trim()));
+    };
+    let record_op = match record_op {
+        Some("Record") => RecordOp::Record,
+        Some("Clear") => RecordOp::Clear,
+        Some("Clearall") => RecordOp::ClearAll,
+        _ => RecordOp::NoRecord,
+    };
+    let state = state.map(|m| m.as_str().to_string());
+    if line_op != LineOp::Error {
+        if let Some(state) = &state {
+            if line_op == LineOp::Continue {
+                return Err("Continue cannot change state".to_string());
+            }
+            if state != "End" && !states.contains(&state.as_str()) {
+                return Err(format!("unknown state {}", state));
+            }
+        }
+    }
+    Ok(Rule {
+        regex,
+        line_op,
+        record_op,
+        state,
+    })
+}
+
+/// Value state while parsing one input
+struct Run<'a> {
+    template: &'a Template,
+    current: Vec<Vec<String>>,
+    /// Last assignment of `Filldown` values, restored by `clear`
+    filled: Vec<Option<Vec<String>>>,
+    records: Vec<Vec<Vec<String>>>,
+}
+
+impl<'a> Run<'a> {
+    fn new(template: &'a Template) -> Self {
+        let count = template.values.len();
+        Self {
+            template,
+            current: vec![Vec::new(); count],
+            filled: vec![None; count],
+            records: Vec::new(),
+        }
+    }
+
+    fn assign(&mut self, index: usize, text: &str) {
+        let options = self.template.values[index].options;
+        if options.list {
+            self.current[index].push(text.to_string());
+        } else {
+            self.current[index] = vec![text.to_string()];
+        }
+        if options.filldown {
+            self.filled[index] = Some(self.current[index].clone());
+        }
+        if options.fillup {
+            // Fill earlier records back to the last one that had the value
+            for record in self.records.iter_mut().rev() {
+                if !record[index].is_empty() {
+                    break;
+                }
+                record[index] = vec![text.to_string()];
+            }
+        }
+    }
+
+    fn record(&mut self) {
+        if self.template.values.is_empty() {
+            return;
+        }
+        let skip = self
+            .template
+            .values
+            .iter()
+            .zip(&self.current)
+            .any(|(value, current)| value.options.required && current.is_empty());
+        if skip {
+            self.clear(false);
+            return;
+        }
+        if self.current.iter().all(Vec::is_empty) {
+            return;
+        }
+        self.records.push(self.current.clone());
+        self.clear(false);
+    }
+
+    fn clear(&mut self, all: bool) {
+        for (index, value) in self.template.values.iter().enumerate() {
+            if all {
+                self.filled[index] = None;
+            }
+            self.current[index] = match (&self.filled[index], value.options.filldown) {
+                (Some(filled), true) => filled.clone(),
+                _ => Vec::new(),
+            };
+        }
+    }
+
+    fn into_records(self) -> Vec<Map<String, Json>> {
+        let values = &self.template.values;
+        self.records
+            .into_iter()
+            .map(|record| {
+                values
+                    .iter()
+                    .zip(record)
+                    .map(|(value, mut found)| {
+                        let json = if value.options.list {
+                            Json::from(found)
+                        } else {
+                            Json::from(found.pop().unwrap_or_default())
+                        };
+                        (value.name.clone(), json)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r"# display interface brief, grouped by slot
+Value Filldown Slot (\d+)
+Value Required Interface (\S+)
+Value Phy (up|down|\*down)
+Value List Errors (\d+)
+
+Start
+  ^Slot ${Slot}
+  ^Interface\s+PHY -> Ports
+  ^Error: -> Error
+
+Ports
+  ^${Interface}\s+${Phy}\s+${Errors}\s+\d+ -> Continue
+  ^\S+\s+\S+\s+\d+\s+${Errors}\s*$$ -> Record
+  ^\s*$$ -> Start
+";
+
+    #[test]
+    fn test_parse_text() {
+        let template = Template::parse(TEMPLATE).unwrap();
+        let output = "\
+Slot 1
+Interface                   PHY   InErrors  OutErrors
+GigabitEthernet1/0/0        up    0         0
+GigabitEthernet1/0/1        *down 12        3
+
+Slot 2
+Interface                   PHY   InErrors  OutErrors
+GigabitEthernet2/0/0        down  0         1
+";
+        let records = template.parse_text(output).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1]["Slot"], "1");
+        assert_eq!(records[1]["Interface"], "GigabitEthernet1/0/1");
+        assert_eq!(records[1]["Phy"], "*down");
+        assert_eq!(records[1]["Errors"], serde_json::json!(["12", "3"]));
+        assert_eq!(records[2]["Slot"], "2");
+
+        assert!(matches!(
+            template.parse_text("Error: Unrecognized command"),
+            Err(TextFsmError::Rejected { line: 1, .. })
+        ));
+
+        // Fillup values reach back to earlier records
+        let template = Template::parse(
+            "Value Name (\\w+)\nValue Fillup Vlan (\\d+)\n\nStart\n  ^port ${Name} -> Continue\n  ^port \\w+ -> Record\n  ^vlan ${Vlan}\n\nEOF\n",
+        )
+        .unwrap();
+        let records = template.parse_text("port a\nport b\nvlan 10").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["Vlan"], "10");
+        assert_eq!(records[1]["Vlan"], "10");
+    }
+
+    #[test]
+    fn test_template_errors() {
+        for (source, line) in [
+            ("Value Name \\d+\n\nStart\n", 1),
+            ("Value Bogus Name (\\d+)\n\nStart\n", 1),
+            ("Value Name (\\d+)\n\nStart\n  ^${Other}\n", 4),
+            ("Value Name (\\d+)\n\nStart\n  ^${Name} -> Missing\n", 4),
+            (
+                "Value Name (\\d+)\n\nStart\n  ^${Name} -> Continue.Record Start\n",
+                4,
+            ),
+            ("Value Name (\\d+)\n\nStart\n  ${Name}\n", 4),
+            ("Value Name (\\d+)\n\nOther\n  ^x\n", 0),
+        ] {
+            match Template::parse(source) {
+                Err(TextFsmError::Template { line: at, .. }) => assert_eq!(at, line, "{}", source),
+                other => panic!("{}: {:?}", source, other.map(|_| ())),
+            }
+        }
+    }
+}
@@ -0,0 +1,856 @@
+//! TFTP (RFC 1350) for devices pulling images and pushing files.
+//!
+//! `TftpServer` serves one file, under its file name, and returns after the
+//! first transfer; upgrades use it. `TftpService` is the long-running server
+//! started from the frontend: it serves a root directory to allowed
+//! clients, optionally accepts uploads into it, and reports every transfer
+//! as `tftp` events. Both honour the `blksize` option (RFC 2348) and roll
+//! block numbers over past 65535, so images over 32 MB work with devices
+//! that support either; the service also answers `tsize` (RFC 2349).
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+pub const TFTP_PORT: u16 = 69;
+
+const OP_RRQ: u16 = 1;
+const OP_WRQ: u16 = 2;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+const ERR_NOT_FOUND: u16 = 1;
+const ERR_ACCESS: u16 = 2;
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+const MAX_BLOCK_SIZE: usize = 65464;
+
+/// Wait for an ACK before sending a packet again
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_RETRIES: u32 = 5;
+
+pub struct TftpServer {
+    socket: UdpSocket,
+    name: String,
+    path: PathBuf,
+}
+
+impl TftpServer {
+    pub async fn bind(addr: SocketAddr, path: &Path) -> io::Result<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+        let socket = UdpSocket::bind(addr).await?;
+        Ok(Self {
+            socket,
+            name,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// The name devices request the file by
+    pub fn file_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Answer requests until one transfer of the file ends; returns the
+    /// bytes sent. `progress` gets the bytes acknowledged so far.
+    pub async fn serve_once(&self, mut progress: impl FnMut(u64)) -> io::Result<u64> {
+        let mut buf = [0u8; 1024];
+        loop {
+            let (n, peer) = self.socket.recv_from(&mut buf).await?;
+            let Some(Request {
+                opcode,
+                filename,
+                options,
+            }) = parse_request(&buf[..n])
+            else {
+                debug!(peer = %peer, "Ignoring malformed TFTP packet");
+                continue;
+            };
+            if opcode == OP_WRQ {
+                self.socket
+                    .send_to(&error_packet(ERR_ACCESS, "read only"), peer)
+                    .await?;
+                continue;
+            }
+            // Devices may ask for `/name` or `flash:/name`
+            let requested = filename.rsplit(['/', '\\']).next().unwrap_or(&filename);
+            if requested != self.name {
+                warn!(peer = %peer, file = %filename, "TFTP request for an unknown file");
+                self.socket
+                    .send_to(&error_packet(ERR_NOT_FOUND, "file not found"), peer)
+                    .await?;
+                continue;
+            }
+
+            info!(peer = %peer, file = %self.name, "TFTP transfer started");
+            let block_size = block_size(&options);
+            let local = SocketAddr::new(self.socket.local_addr()?.ip(), 0);
+            let socket = UdpSocket::bind(local).await?;
+            socket.connect(peer).await?;
+            let sent = send_file(&socket, &self.path, block_size, None, &mut progress).await;
+            match &sent {
+                Ok(bytes) => info!(peer = %peer, bytes, "TFTP transfer finished"),
+                Err(e) => warn!(peer = %peer, error = %e, "TFTP transfer failed"),
+            }
+            return sent;
+        }
+    }
+}
+
+/// A read or write request
+struct Request {
+    opcode: u16,
+    filename: String,
+    options: Vec<(String, String)>,
+}
+
+fn parse_request(packet: &[u8]) -> Option<Request> {
+    let opcode = u16::from_be_bytes([*packet.first()?, *packet.get(1)?]);
+    if opcode != OP_RRQ && opcode != OP_WRQ {
+        return None;
+    }
+    let mut fields = packet[2..]
+        .split(|&b| b == 0)
+        .map(|field| String::from_utf8_lossy(field).into_owned());
+    let filename = fields.next().filter(|name| !name.is_empty())?;
+    let _mode = fields.next()?;
+    let rest: Vec<String> = fields.filter(|field| !field.is_empty()).collect();
+    let options = rest
+        .chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    Some(Request {
+        opcode,
+        filename,
+        options,
+    })
+}
+
+fn option<'a>(options: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// The client's `blksize`, clamped to what fits a UDP datagram
+fn block_size(options: &[(String, String)]) -> Option<usize> {
+    option(options, "blksize")
+        .and_then(|value| value.parse::<usize>().ok())
+        .map(|size| size.clamp(8, MAX_BLOCK_SIZE))
+}
+
+/// OACK for the options agreed to, if any
+fn oack_packet(block_size: Option<usize>, tsize: Option<u64>) -> Option<Vec<u8>> {
+    if block_size.is_none() && tsize.is_none() {
+        return None;
+    }
+    let mut packet = OP_OACK.to_be_bytes().to_vec();
+    let options = [
+        ("blksize", block_size.map(|size| size as u64)),
+        ("tsize", tsize),
+    ];
+    for (name, value) in options {
+        if let Some(value) = value {
+            packet.extend_from_slice(name.as_bytes());
+            packet.push(0);
+            packet.extend_from_slice(value.to_string().as_bytes());
+            packet.push(0);
+        }
+    }
+    Some(packet)
+}
+
+fn ack_packet(block: u16) -> Vec<u8> {
+    [OP_ACK.to_be_bytes(), block.to_be_bytes()].concat()
+}
+
+fn error_packet(code: u16, message: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(message.len() + 5);
+    packet.extend_from_slice(&OP_ERROR.to_be_bytes());
+    packet.extend_from_slice(&code.to_be_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    packet
+}
+
+async fn send_file(
+    socket: &UdpSocket,
+    path: &Path,
+    block_size: Option<usize>,
+    tsize: Option<u64>,
+    progress: &mut impl FnMut(u64),
+) -> io::Result<u64> {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = socket
+                .send(&error_packet(ERR_NOT_FOUND, &e.to_string()))
+                .await;
+            return Err(e);
+        }
+    };
+
+    // Acknowledge the options before any data, as block 0
+    if let Some(oack) = oack_packet(block_size, tsize) {
+        send_until_acked(socket, &oack, 0).await?;
+    }
+
+    let block_size = block_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+    let mut block: u16 = 1;
+    let mut sent = 0u64;
+    let mut data = vec![0u8; block_size];
+    loop {
+        let len = read_full(&mut file, &mut data).await?;
+        let mut packet = Vec::with_capacity(len + 4);
+        packet.extend_from_slice(&OP_DATA.to_be_bytes());
+        packet.extend_from_slice(&block.to_be_bytes());
+        packet.extend_from_slice(&data[..len]);
+        send_until_acked(socket, &packet, block).await?;
+        sent += len as u64;
+        progress(sent);
+        // A short block ends the transfer
+        if len < block_size {
+            return Ok(sent);
+        }
+        block = block.wrapping_add(1);
+    }
+}
+
+/// Fill `buf` unless the file ends first
+async fn read_full(file: &mut tokio::fs::File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+/// Send `packet` until the peer acknowledges `block`. Duplicate ACKs of
+/// earlier blocks are ignored rather than answered, which would double the
+/// traffic for the rest of the transfer.
+async fn send_until_acked(socket: &UdpSocket, packet: &[u8], block: u16) -> io::Result<()> {
+    let mut buf = [0u8; 516];
+    send_until(socket, packet, OP_ACK, block, &mut buf).await?;
+    Ok(())
+}
+
+/// Send `packet` until the peer answers with `opcode` for `block`; returns
+/// the answer's length in `buf`
+async fn send_until(
+    socket: &UdpSocket,
+    packet: &[u8],
+    opcode: u16,
+    block: u16,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    for _ in 0..=MAX_RETRIES {
+        socket.send(packet).await?;
+        let deadline = Instant::now() + RETRANSMIT_TIMEOUT;
+        loop {
+            let n = match tokio::time::timeout_at(deadline, socket.recv(buf)).await {
+                Ok(received) => received?,
+                Err(_) => break,
+            };
+            if n < 4 {
+                continue;
+            }
+            let received = u16::from_be_bytes([buf[0], buf[1]]);
+            let number = u16::from_be_bytes([buf[2], buf[3]]);
+            if received == opcode && number == block {
+                return Ok(n);
+            }
+            if received == OP_ERROR {
+                let message = String::from_utf8_lossy(&buf[4..n]);
+                return Err(io::Error::other(format!(
+                    "peer aborted the transfer: {}",
+                    message.trim_end_matches('\0')
+                )));
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("block {} not acknowledged", block),
+    ))
+}
+
+/// Receive an upload into `path`. Data goes to `path.part` first, so a
+/// failed upload leaves any previous file in place.
+async fn receive_file(
+    socket: &UdpSocket,
+    path: &Path,
+    block_size: Option<usize>,
+    tsize: Option<u64>,
+    progress: &mut impl FnMut(u64),
+) -> io::Result<u64> {
+    let part = PathBuf::from(format!("{}.part", path.display()));
+    let mut file = match tokio::fs::File::create(&part).await {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = socket.send(&error_packet(ERR_ACCESS, &e.to_string())).await;
+            return Err(e);
+        }
+    };
+    let received = receive_blocks(socket, &mut file, block_size, tsize, progress).await;
+    drop(file);
+    match received {
+        Ok(bytes) => {
+            tokio::fs::rename(&part, path).await?;
+            Ok(bytes)
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part).await;
+            Err(e)
+        }
+    }
+}
+
+async fn receive_blocks(
+    socket: &UdpSocket,
+    file: &mut tokio::fs::File,
+    block_size: Option<usize>,
+    tsize: Option<u64>,
+    progress: &mut impl FnMut(u64),
+) -> io::Result<u64> {
+    // The OACK, or an ACK of block 0, asks for the first block
+    let mut reply = oack_packet(block_size, tsize).unwrap_or_else(|| ack_packet(0));
+    let block_size = block_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+    let mut buf = vec![0u8; block_size + 4];
+    let mut block: u16 = 1;
+    let mut received = 0u64;
+    loop {
+        let n = send_until(socket, &reply, OP_DATA, block, &mut buf).await?;
+        file.write_all(&buf[4..n]).await?;
+        received += (n - 4) as u64;
+        progress(received);
+        reply = ack_packet(block);
+        // A short block ends the transfer
+        if n - 4 < block_size {
+            file.flush().await?;
+            socket.send(&reply).await?;
+            return Ok(received);
+        }
+        block = block.wrapping_add(1);
+    }
+}
+
+/// Transfers kept for `list_tftp_transfers`
+const MAX_TRANSFERS: usize = 200;
+
+/// Least time between two progress events of one transfer
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Error, Debug)]
+pub enum TftpError {
+    #[error("TFTP server already running")]
+    AlreadyRunning,
+    #[error("TFTP server not running")]
+    NotRunning,
+    #[error("Invalid TFTP server options: {0}")]
+    Invalid(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl From<TftpError> for String {
+    fn from(err: TftpError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TftpOptions {
+    /// Directory served; must exist
+    pub root: String,
+    /// Default `0.0.0.0`
+    #[serde(default)]
+    pub bind: Option<String>,
+    /// Default `TFTP_PORT`
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Accept uploads into the root, replacing files of the same name
+    #[serde(default)]
+    pub allow_write: bool,
+    /// Client IPs accepted; empty accepts any
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TftpStatus {
+    pub running: bool,
+    pub addr: Option<String>,
+    pub root: Option<String>,
+    pub allow_write: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    /// The device downloads from the root
+    Read,
+    /// The device uploads into the root
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    Active,
+    Finished,
+    Failed,
+}
+
+/// One transfer; also the body of `tftp` events
+#[derive(Debug, Clone, Serialize)]
+pub struct TftpTransfer {
+    pub id: String,
+    pub peer: String,
+    /// Path under the root, `/`-separated
+    pub file: String,
+    pub direction: TransferDirection,
+    pub bytes: u64,
+    /// The file size, or the client's `tsize` for uploads
+    pub total: Option<u64>,
+    pub state: TransferState,
+    pub error: Option<String>,
+    /// Unix milliseconds
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+struct Running {
+    status: TftpStatus,
+    /// Dropped to stop the server and its transfers
+    _shutdown: watch::Sender<()>,
+}
+
+/// The directory server, started and stopped from the frontend
+pub struct TftpService {
+    running: Mutex<Option<Running>>,
+    transfers: Mutex<VecDeque<TftpTransfer>>,
+}
+
+impl TftpService {
+    pub fn new() -> Self {
+        Self {
+            running: Mutex::new(None),
+            transfers: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub async fn start(
+        self: &Arc<Self>,
+        options: TftpOptions,
+        app_handle: tauri::AppHandle,
+    ) -> Result<TftpStatus, TftpError> {
+        if self.running.lock().unwrap().is_some() {
+            return Err(TftpError::AlreadyRunning);
+        }
+        let root = PathBuf::from(&options.root);
+        if !root.is_dir() {
+            return Err(TftpError::Invalid(format!(
+                "{} is not a directory",
+                options.root
+            )));
+        }
+        let allowed = options
+            .allowed_clients
+            .iter()
+            .map(|client| {
+                client
+                    .parse::<IpAddr>()
+                    .map(|ip| ip.to_canonical())
+                    .map_err(|_| TftpError::Invalid(format!("not an IP address: {}", client)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let bind = options.bind.as_deref().unwrap_or("0.0.0.0");
+        let socket = UdpSocket::bind((bind, options.port.unwrap_or(TFTP_PORT))).await?;
+        let status = TftpStatus {
+            running: true,
+            addr: socket.local_addr().ok().map(|a| a.to_string()),
+            root: Some(root.display().to_string()),
+            allow_write: options.allow_write,
+        };
+        let (shutdown, stopped) = watch::channel(());
+        let service = Arc::clone(self);
+        let allow_write = options.allow_write;
+        tokio::spawn(async move {
+            service
+                .listen(socket, root, allow_write, allowed, app_handle, stopped)
+                .await
+        });
+
+        info!(addr = ?status.addr, root = ?status.root, "TFTP server started");
+        *self.running.lock().unwrap() = Some(Running {
+            status: status.clone(),
+            _shutdown: shutdown,
+        });
+        Ok(status)
+    }
+
+    /// Stop listening; transfers in progress are aborted
+    pub fn stop(&self) -> Result<(), TftpError> {
+        self.running
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(TftpError::NotRunning)?;
+        info!("TFTP server stopped");
+        Ok(())
+    }
+
+    pub fn status(&self) -> TftpStatus {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|r| r.status.clone())
+            .unwrap_or_default()
+    }
+
+    /// Recent transfers, oldest first
+    pub fn transfers(&self) -> Vec<TftpTransfer> {
+        self.transfers.lock().unwrap().iter().cloned().collect()
+    }
+
+    async fn listen(
+        self: Arc<Self>,
+        socket: UdpSocket,
+        root: PathBuf,
+        allow_write: bool,
+        allowed: Vec<IpAddr>,
+        app_handle: tauri::AppHandle,
+        mut stopped: watch::Receiver<()>,
+    ) {
+        let local = socket
+            .local_addr()
+            .map(|a| a.ip())
+            .unwrap_or(IpAddr::from([0, 0, 0, 0]));
+        let mut tasks = JoinSet::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let (n, peer) = tokio::select! {
+                received = socket.recv_from(&mut buf) => match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!(error = %e, "TFTP receive failed");
+                        continue;
+                    }
+                },
+                _ = stopped.changed() => break,
+            };
+            while tasks.try_join_next().is_some() {}
+
+            let refuse = |code: u16, message: &'static str| {
+                let socket = &socket;
+                async move {
+                    let _ = socket.send_to(&error_packet(code, message), peer).await;
+                }
+            };
+            if !allowed.is_empty() && !allowed.contains(&peer.ip().to_canonical()) {
+                warn!(peer = %peer, "TFTP request from a client not allowed");
+                refuse(ERR_ACCESS, "access denied").await;
+                continue;
+            }
+            let Some(request) = parse_request(&buf[..n]) else {
+                debug!(peer = %peer, "Ignoring malformed TFTP packet");
+                continue;
+            };
+            let direction = if request.opcode == OP_WRQ {
+                TransferDirection::Write
+            } else {
+                TransferDirection::Read
+            };
+            if direction == TransferDirection::Write && !allow_write {
+                refuse(ERR_ACCESS, "read only").await;
+                continue;
+            }
+            let Some((path, file)) = resolve(&root, &request.filename) else {
+                warn!(peer = %peer, file = %request.filename, "TFTP request outside the root");
+                refuse(ERR_ACCESS, "access denied").await;
+                continue;
+            };
+
+            let transfer = TftpTransfer {
+                id: uuid::Uuid::new_v4().to_string(),
+                peer: peer.to_string(),
+                file,
+                direction,
+                bytes: 0,
+                total: None,
+                state: TransferState::Active,
+                error: None,
+                started_at: chrono::Utc::now().timestamp_millis(),
+                finished_at: None,
+            };
+            let service = Arc::clone(&self);
+            let app_handle = app_handle.clone();
+            tasks.spawn(async move {
+                service
+                    .transfer(transfer, local, peer, path, request.options, app_handle)
+                    .await
+            });
+        }
+
+        tasks.shutdown().await;
+        let aborted: Vec<TftpTransfer> = self
+            .transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| t.state == TransferState::Active)
+            .cloned()
+            .collect();
+        for mut transfer in aborted {
+            transfer.state = TransferState::Failed;
+            transfer.error = Some("server stopped".to_string());
+            transfer.finished_at = Some(chrono::Utc::now().timestamp_millis());
+            self.record(&transfer, &app_handle);
+        }
+    }
+
+    async fn transfer(
+        &self,
+        mut transfer: TftpTransfer,
+        local: IpAddr,
+        peer: SocketAddr,
+        path: PathBuf,
+        options: Vec<(String, String)>,
+        app_handle: tauri::AppHandle,
+    ) {
+        let result = async {
+            let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+            socket.connect(peer).await?;
+            let block_size = block_size(&options);
+            let wants_tsize = option(&options, "tsize").is_some();
+            match transfer.direction {
+                TransferDirection::Read => {
+                    let size = match tokio::fs::metadata(&path).await {
+                        Ok(metadata) if metadata.is_file() => metadata.len(),
+                        _ => {
+                            let _ = socket
+                                .send(&error_packet(ERR_NOT_FOUND, "file not found"))
+                                .await;
+                            return Err(io::Error::new(io::ErrorKind::NotFound, "file not found"));
+                        }
+                    };
+                    transfer.total = Some(size);
+                    info!(peer = %peer, file = %transfer.file, "TFTP read started");
+                    self.record(&transfer, &app_handle);
+                    let mut progress = self.progress(&transfer, &app_handle);
+                    let tsize = wants_tsize.then_some(size);
+                    send_file(&socket, &path, block_size, tsize, &mut progress).await
+                }
+                TransferDirection::Write => {
+                    // `tsize` in a write request is the size on its way
+                    let tsize = option(&options, "tsize").and_then(|v| v.parse().ok());
+                    transfer.total = tsize;
+                    info!(peer = %peer, file = %transfer.file, "TFTP write started");
+                    self.record(&transfer, &app_handle);
+                    let mut progress = self.progress(&transfer, &app_handle);
+                    receive_file(&socket, &path, block_size, tsize, &mut progress).await
+                }
+            }
+        }
+        .await;
+
+        match result {
+            Ok(bytes) => {
+                info!(peer = %peer, file = %transfer.file, bytes, "TFTP transfer finished");
+                transfer.bytes = bytes;
+                transfer.state = TransferState::Finished;
+            }
+            Err(e) => {
+                warn!(peer = %peer, file = %transfer.file, error = %e, "TFTP transfer failed");
+                transfer.bytes = self.bytes(&transfer.id);
+                transfer.state = TransferState::Failed;
+                transfer.error = Some(e.to_string());
+            }
+        }
+        transfer.finished_at = Some(chrono::Utc::now().timestamp_millis());
+        self.record(&transfer, &app_handle);
+    }
+
+    /// A progress callback recording and emitting at most every
+    /// `PROGRESS_INTERVAL`
+    fn progress<'a>(
+        &'a self,
+        transfer: &TftpTransfer,
+        app_handle: &'a tauri::AppHandle,
+    ) -> impl FnMut(u64) + 'a {
+        let mut transfer = transfer.clone();
+        let mut last = Instant::now();
+        move |bytes| {
+            transfer.bytes = bytes;
+            if last.elapsed() >= PROGRESS_INTERVAL {
+                last = Instant::now();
+                self.record(&transfer, app_handle);
+            }
+        }
+    }
+
+    /// Bytes last recorded for a transfer
+    fn bytes(&self, id: &str) -> u64 {
+        self.transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .rfind(|t| t.id == id)
+            .map_or(0, |t| t.bytes)
+    }
+
+    /// Store the transfer's latest state and emit it as `tftp`
+    fn record(&self, transfer: &TftpTransfer, app_handle: &tauri::AppHandle) {
+        {
+            let mut transfers = self.transfers.lock().unwrap();
+            match transfers.iter_mut().rfind(|t| t.id == transfer.id) {
+                Some(existing) => *existing = transfer.clone(),
+                None => {
+                    if transfers.len() >= MAX_TRANSFERS {
+                        transfers.pop_front();
+                    }
+                    transfers.push_back(transfer.clone());
+                }
+            }
+        }
+        if let Err(e) = app_handle.emit("tftp", transfer) {
+            warn!(error = %e, "Failed to emit TFTP transfer");
+        }
+    }
+}
+
+/// The path a requested name maps to under `root`, and the name as
+/// recorded. `..`, drive or device prefixes (`flash:`) and empty names are
+/// refused; leading slashes are not.
+fn resolve(root: &Path, name: &str) -> Option<(PathBuf, String)> {
+    let name = name.replace('\\', "/");
+    let parts: Vec<&str> = name
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    if parts.is_empty() || parts.iter().any(|part| *part == ".." || part.contains(':')) {
+        return None;
+    }
+    let path = parts
+        .iter()
+        .fold(root.to_path_buf(), |path, part| path.join(part));
+    Some((path, parts.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serve_file() {
+        let dir = std::env::temp_dir().join(format!("bspt-tftp-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("V800R021.cc");
+        let image: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        std::fs::write(&path, &image).unwrap();
+
+        let server = TftpServer::bind("127.0.0.1:0".parse().unwrap(), &path)
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let serving = tokio::spawn(async move { server.serve_once(|_| {}).await });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut buf = [0u8; 2048];
+        client
+            .send_to(b"\x00\x01other.cc\0octet\0", addr)
+            .await
+            .unwrap();
+        let (n, _) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..4], b"\x00\x05\x00\x01", "{:?}", &buf[..n]);
+
+        client
+            .send_to(b"\x00\x01flash:/V800R021.cc\0octet\0blksize\x001024\0", addr)
+            .await
+            .unwrap();
+        let (n, transfer) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"\x00\x06blksize\x001024\x00");
+        client.send_to(b"\x00\x04\x00\x00", transfer).await.unwrap();
+
+        let mut received = Vec::new();
+        loop {
+            let (n, _) = client.recv_from(&mut buf).await.unwrap();
+            assert_eq!(u16::from_be_bytes([buf[0], buf[1]]), OP_DATA);
+            received.extend_from_slice(&buf[4..n]);
+            client
+                .send_to(&[0, 4, buf[2], buf[3]], transfer)
+                .await
+                .unwrap();
+            if n - 4 < 1024 {
+                break;
+            }
+        }
+        assert_eq!(received, image);
+        assert_eq!(serving.await.unwrap().unwrap(), 2500);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_receive_upload() {
+        let dir = std::env::temp_dir().join(format!("bspt-tftp-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("configs")).unwrap();
+        let (path, name) = resolve(&dir, "/configs\\vrpcfg.zip").unwrap();
+        assert_eq!(name, "configs/vrpcfg.zip");
+        assert!(resolve(&dir, "../etc/passwd").is_none());
+        assert!(resolve(&dir, "flash:/vrpcfg.zip").is_none());
+        assert!(resolve(&dir, "/").is_none());
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        server.connect(client.local_addr().unwrap()).await.unwrap();
+        let target = path.clone();
+        let receiving = tokio::spawn(async move {
+            receive_file(&server, &target, Some(512), Some(1200), &mut |_| {}).await
+        });
+
+        let mut buf = [0u8; 64];
+        let (n, transfer) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"\x00\x06blksize\x00512\x00tsize\x001200\x00");
+        let config: Vec<u8> = (0..1200u32).map(|i| (i % 251) as u8).collect();
+        for (index, chunk) in config.chunks(512).enumerate() {
+            let block = (index + 1) as u16;
+            let packet = [&OP_DATA.to_be_bytes()[..], &block.to_be_bytes(), chunk].concat();
+            client.send_to(&packet, transfer).await.unwrap();
+            let (n, _) = client.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], ack_packet(block));
+        }
+        assert_eq!(receiving.await.unwrap().unwrap(), 1200);
+        assert_eq!(std::fs::read(&path).unwrap(), config);
+        assert!(!dir.join("configs/vrpcfg.zip.part").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
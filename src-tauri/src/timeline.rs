@@ -0,0 +1,427 @@
+//! Per-device timeline of everything the app saw happen.
+//!
+//! Session markers, VRP events, syslog messages, SNMP traps and tracer
+//! matches are merged into one time-ordered list per device, so the
+//! question "what happened at 14:32:07" is one `query_timeline` call. A
+//! device is the host of the session an item came from; syslog and traps
+//! use the host of a session open to the sender, or the sender's address.
+//! Times are when the app received the item, not the device's clock.
+
+use crate::alerts::{self, AlertSeverity};
+use crate::events::SessionEvent;
+use crate::session::SessionManager;
+use crate::snmp::{SnmpTrap, TrapReceiver};
+use crate::syslog::{SyslogMessage, SyslogReceiver};
+use crate::vrp::VrpEvent;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Entries kept per device
+const MAX_ENTRIES: usize = 5000;
+
+/// Entries returned by a query unless it asks for fewer
+pub const DEFAULT_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelineSource {
+    /// Placed in a session's output with `add_timeline_marker`
+    Marker,
+    Vrp,
+    Syslog,
+    Trap,
+    /// A log line matched to source code
+    Tracer,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEntry {
+    /// Unix milliseconds
+    pub at: i64,
+    pub device: String,
+    pub source: TimelineSource,
+    pub severity: Option<AlertSeverity>,
+    pub title: String,
+    pub detail: String,
+    pub session_id: Option<String>,
+    /// Scrollback line of a marker, to jump to it in the output
+    pub line: Option<u64>,
+    /// Tracer match as `file:line`
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TimelineQuery {
+    /// One device; all devices when unset
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Unix milliseconds, inclusive
+    #[serde(default)]
+    pub from: Option<i64>,
+    #[serde(default)]
+    pub to: Option<i64>,
+    /// Empty means all sources
+    #[serde(default)]
+    pub sources: Vec<TimelineSource>,
+    /// Case-insensitive substring of the title or detail
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Newest entries kept when more match (default 500)
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    devices: DashMap<String, VecDeque<TimelineEntry>>,
+}
+
+impl Timeline {
+    /// Add an entry in time order; the oldest entry of a full device goes
+    pub fn record(&self, entry: TimelineEntry) {
+        let mut entries = self.devices.entry(entry.device.clone()).or_default();
+        // Items arrive nearly in order, so the insert point is near the end
+        let at = entries.partition_point(|e| e.at <= entry.at);
+        entries.insert(at, entry);
+        if entries.len() > MAX_ENTRIES {
+            entries.pop_front();
+        }
+    }
+
+    /// Matching entries of one or all devices, oldest first
+    pub fn query(&self, query: &TimelineQuery) -> Vec<TimelineEntry> {
+        let text = query.text.as_ref().map(|t| t.to_lowercase());
+        let matches = |e: &TimelineEntry| {
+            query.from.is_none_or(|from| e.at >= from)
+                && query.to.is_none_or(|to| e.at <= to)
+                && (query.sources.is_empty() || query.sources.contains(&e.source))
+                && text.as_ref().is_none_or(|t| {
+                    e.title.to_lowercase().contains(t) || e.detail.to_lowercase().contains(t)
+                })
+        };
+        let mut found: Vec<TimelineEntry> = self
+            .devices
+            .iter()
+            .filter(|d| query.device.as_ref().is_none_or(|device| d.key() == device))
+            .flat_map(|d| {
+                d.value()
+                    .iter()
+                    .filter(|e| matches(e))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        // Stable, so a device's own order stays for equal times
+        found.sort_by_key(|e| e.at);
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        found.split_off(found.len().saturating_sub(limit))
+    }
+
+    /// Devices with entries, sorted
+    pub fn devices(&self) -> Vec<String> {
+        let mut devices: Vec<String> = self.devices.iter().map(|d| d.key().clone()).collect();
+        devices.sort();
+        devices
+    }
+
+    pub fn clear(&self, device: Option<&str>) {
+        match device {
+            Some(device) => {
+                self.devices.remove(device);
+            }
+            None => self.devices.clear(),
+        }
+    }
+}
+
+/// Put a marker at the current end of a session's output
+pub async fn mark(
+    timeline: &Timeline,
+    manager: &SessionManager,
+    session_id: &str,
+    label: String,
+) -> Option<TimelineEntry> {
+    let handle = manager.get(session_id)?;
+    let line = handle.scrollback.lock().await.end_line();
+    let entry = TimelineEntry {
+        line: Some(line),
+        ..entry(
+            handle.config.host.clone(),
+            TimelineSource::Marker,
+            None,
+            label,
+            String::new(),
+            Some(session_id.to_string()),
+        )
+    };
+    timeline.record(entry.clone());
+    Some(entry)
+}
+
+/// Record a tracer match of a line from a session
+pub fn traced(
+    timeline: &Timeline,
+    manager: &SessionManager,
+    session_id: &str,
+    line: &str,
+    location: String,
+) {
+    let Some(handle) = manager.get(session_id) else {
+        return;
+    };
+    timeline.record(TimelineEntry {
+        location: Some(location.clone()),
+        ..entry(
+            handle.config.host.clone(),
+            TimelineSource::Tracer,
+            None,
+            location,
+            line.trim().to_string(),
+            Some(session_id.to_string()),
+        )
+    });
+}
+
+/// Follow VRP events, syslog and traps
+pub fn spawn_feeds(
+    timeline: &Arc<Timeline>,
+    manager: &Arc<SessionManager>,
+    syslog: &SyslogReceiver,
+    traps: &TrapReceiver,
+) {
+    let mut events = manager.events().subscribe();
+    let (store, sessions) = (Arc::clone(timeline), Arc::clone(manager));
+    tauri::async_runtime::spawn(async move {
+        while let Some(SessionEvent { session_id, event }) =
+            alerts::next(&mut events, "timeline VRP").await
+        {
+            if let Some(entry) = vrp_entry(&sessions, &session_id, &event) {
+                store.record(entry);
+            }
+        }
+    });
+
+    let mut messages = syslog.subscribe();
+    let (store, sessions) = (Arc::clone(timeline), Arc::clone(manager));
+    tauri::async_runtime::spawn(async move {
+        while let Some(message) = alerts::next(&mut messages, "timeline syslog").await {
+            for entry in syslog_entries(&sessions, message) {
+                store.record(entry);
+            }
+        }
+    });
+
+    let mut received = traps.subscribe();
+    let (store, sessions) = (Arc::clone(timeline), Arc::clone(manager));
+    tauri::async_runtime::spawn(async move {
+        while let Some(trap) = alerts::next(&mut received, "timeline trap").await {
+            store.record(trap_entry(&sessions, trap));
+        }
+    });
+}
+
+fn entry(
+    device: String,
+    source: TimelineSource,
+    severity: Option<AlertSeverity>,
+    title: String,
+    detail: String,
+    session_id: Option<String>,
+) -> TimelineEntry {
+    TimelineEntry {
+        at: chrono::Utc::now().timestamp_millis(),
+        device,
+        source,
+        severity,
+        title,
+        detail,
+        session_id,
+        line: None,
+        location: None,
+    }
+}
+
+/// Host of the first open session, else the sender
+pub(crate) fn device(manager: &SessionManager, session_ids: &[String], source: &str) -> String {
+    session_ids
+        .iter()
+        .find_map(|id| manager.get(id))
+        .map_or_else(|| source.to_string(), |handle| handle.config.host.clone())
+}
+
+/// Board changes, rejected commands and view changes; pagination is noise
+fn vrp_entry(
+    manager: &SessionManager,
+    session_id: &str,
+    event: &VrpEvent,
+) -> Option<TimelineEntry> {
+    let host = manager.get(session_id)?.config.host.clone();
+    let (severity, title, detail) = match event {
+        VrpEvent::BoardInfo(board) => {
+            let status = board.status.to_lowercase();
+            let severity = if status == "absent" || status == "offline" {
+                AlertSeverity::Major
+            } else {
+                AlertSeverity::Info
+            };
+            (
+                severity,
+                format!("Slot {} {}", board.slot_id, board.status),
+                board.board_type.clone(),
+            )
+        }
+        VrpEvent::CommandError { message } => (
+            AlertSeverity::Warning,
+            "Command rejected".to_string(),
+            message.clone(),
+        ),
+        VrpEvent::ViewChange { view, hostname } => (
+            AlertSeverity::Info,
+            format!("View {:?}", view),
+            hostname.clone(),
+        ),
+        VrpEvent::Pagination { .. } => return None,
+    };
+    Some(entry(
+        host,
+        TimelineSource::Vrp,
+        Some(severity),
+        title,
+        detail,
+        Some(session_id.to_string()),
+    ))
+}
+
+/// The message, plus a tracer entry when it was matched to source code
+fn syslog_entries(manager: &SessionManager, message: SyslogMessage) -> Vec<TimelineEntry> {
+    let device = device(manager, &message.session_ids, &message.source);
+    let tag = match (&message.app_name, &message.msg_id) {
+        (Some(app), Some(id)) => format!("{}/{}", app, id),
+        (Some(app), None) => app.clone(),
+        (None, Some(id)) => id.clone(),
+        (None, None) => "syslog".to_string(),
+    };
+    let session_id = message.session_ids.first().cloned();
+    let mut entries = vec![entry(
+        device.clone(),
+        TimelineSource::Syslog,
+        message.severity.map(AlertSeverity::from_syslog),
+        tag,
+        message.message.clone(),
+        session_id.clone(),
+    )];
+    if let Some(location) = message.source_location {
+        entries.push(TimelineEntry {
+            location: Some(location.clone()),
+            ..entry(
+                device,
+                TimelineSource::Tracer,
+                None,
+                location,
+                message.message,
+                session_id,
+            )
+        });
+    }
+    entries
+}
+
+fn trap_entry(manager: &SessionManager, trap: SnmpTrap) -> TimelineEntry {
+    let name = trap
+        .trap_name
+        .as_deref()
+        .or(trap.trap_oid.as_deref())
+        .unwrap_or("trap");
+    let title = match &trap.interface {
+        Some(interface) => format!("{} {}", name, interface),
+        None => name.to_string(),
+    };
+    let detail = trap
+        .varbinds
+        .iter()
+        .skip(2)
+        .map(|v| format!("{}={}", v.name.as_deref().unwrap_or(&v.oid), v.value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    entry(
+        device(manager, &trap.session_ids, &trap.source),
+        TimelineSource::Trap,
+        Some(trap.severity.into()),
+        title,
+        detail,
+        trap.session_ids.first().cloned(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(device: &str, source: TimelineSource, at: i64, title: &str) -> TimelineEntry {
+        TimelineEntry {
+            at,
+            ..entry(
+                device.to_string(),
+                source,
+                None,
+                title.to_string(),
+                "Interface GE0/0/1 turned into DOWN state".to_string(),
+                None,
+            )
+        }
+    }
+
+    #[test]
+    fn test_order_and_query() {
+        let timeline = Timeline::default();
+        timeline.record(at(
+            "10.0.0.1",
+            TimelineSource::Syslog,
+            2_000,
+            "IFNET/LINK_STATE",
+        ));
+        timeline.record(at("10.0.0.2", TimelineSource::Trap, 1_500, "linkDown"));
+        // Late arrival goes before newer entries of its device
+        timeline.record(at(
+            "10.0.0.1",
+            TimelineSource::Marker,
+            1_000,
+            "reboot slot 2",
+        ));
+        timeline.record(at("10.0.0.1", TimelineSource::Vrp, 3_000, "Slot 2 Offline"));
+
+        let all = timeline.query(&TimelineQuery::default());
+        let times: Vec<i64> = all.iter().map(|e| e.at).collect();
+        assert_eq!(times, [1_000, 1_500, 2_000, 3_000]);
+        assert_eq!(timeline.devices(), ["10.0.0.1", "10.0.0.2"]);
+
+        let window = timeline.query(&TimelineQuery {
+            device: Some("10.0.0.1".to_string()),
+            from: Some(1_000),
+            to: Some(2_500),
+            ..TimelineQuery::default()
+        });
+        let titles: Vec<&str> = window.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, ["reboot slot 2", "IFNET/LINK_STATE"]);
+
+        let filtered = timeline.query(&TimelineQuery {
+            sources: vec![TimelineSource::Trap, TimelineSource::Vrp],
+            text: Some("offline".to_string()),
+            ..TimelineQuery::default()
+        });
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].source, TimelineSource::Vrp);
+
+        // The newest entries are kept
+        let last = timeline.query(&TimelineQuery {
+            limit: Some(2),
+            ..TimelineQuery::default()
+        });
+        assert_eq!(last[0].at, 2_000);
+
+        timeline.clear(Some("10.0.0.1"));
+        assert_eq!(timeline.devices(), ["10.0.0.2"]);
+    }
+}
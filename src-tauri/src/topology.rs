@@ -0,0 +1,512 @@
+//! Lab topology from LLDP neighbor tables.
+//!
+//! Every selected session runs its dialect's LLDP neighbor command, the
+//! table is parsed into neighbors, and the neighbors of all devices are
+//! merged into one adjacency graph. A link seen from both ends, as LLDP
+//! usually reports it, becomes a single edge; neighbors that were not
+//! collected themselves still appear as nodes.
+
+use crate::command;
+use crate::jobs::JobControl;
+use crate::session::{DeviceDialect, SessionManager};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+/// Header cells of the neighbor tables, longest first so `Local Interface`
+/// wins over `Local Intf`
+const HEADERS: &[(&str, Column)] = &[
+    ("neighbor interface", Column::Port),
+    ("neighbour interface", Column::Port),
+    ("local interface", Column::Local),
+    ("neighbor device", Column::Device),
+    ("neighbour device", Column::Device),
+    ("neighbor intf", Column::Port),
+    ("neighbor dev", Column::Device),
+    ("system name", Column::Device),
+    ("local intf", Column::Local),
+    ("chassis id", Column::Chassis),
+    ("capability", Column::Other),
+    ("exptime(s)", Column::Other),
+    ("expire(s)", Column::Other),
+    ("hold-time", Column::Other),
+    ("holdtime", Column::Other),
+    ("device id", Column::Device),
+    ("port id", Column::Port),
+];
+
+/// Interface name prefixes and the short form they compare as, so
+/// `GigabitEthernet0/0/1` on one end matches `GE0/0/1` on the other
+const PORT_PREFIXES: &[(&str, &str)] = &[
+    ("xgigabitethernet", "xge"),
+    ("tengigabitethernet", "te"),
+    ("gigabitethernet", "ge"),
+    ("fastethernet", "fe"),
+    ("ethernet", "eth"),
+    ("gi", "ge"),
+    ("fa", "fe"),
+    ("et", "eth"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Column {
+    Local,
+    Device,
+    Port,
+    Chassis,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LldpNeighbor {
+    pub local_port: String,
+    /// System name the neighbor advertises
+    pub device: String,
+    pub port: Option<String>,
+    pub chassis_id: Option<String>,
+}
+
+/// What one session reported
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceNeighbors {
+    pub session_id: String,
+    pub host: String,
+    /// Hostname from the prompt, else the session's host
+    pub name: String,
+    pub neighbors: Vec<LldpNeighbor>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopologyNode {
+    pub id: String,
+    /// Set for devices the neighbors were collected from
+    pub session_id: Option<String>,
+    pub host: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopologyLink {
+    pub source: String,
+    pub source_port: String,
+    pub target: String,
+    pub target_port: Option<String>,
+    /// Both ends reported the link
+    pub confirmed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Topology {
+    pub nodes: Vec<TopologyNode>,
+    pub links: Vec<TopologyLink>,
+    pub devices: Vec<DeviceNeighbors>,
+}
+
+/// Command listing the LLDP neighbors, `None` where the dialect has none
+pub fn neighbor_command(dialect: DeviceDialect) -> Option<&'static str> {
+    match dialect {
+        DeviceDialect::Vrp => Some("display lldp neighbor brief"),
+        DeviceDialect::Comware => Some("display lldp neighbor-information list"),
+        DeviceDialect::Ios => Some("show lldp neighbors"),
+        DeviceDialect::Linux => Some("lldpctl -f keyvalue"),
+        DeviceDialect::Mml | DeviceDialect::Generic => None,
+    }
+}
+
+/// Cells of a table header line and the column each starts
+fn header_columns(line: &str) -> Vec<(usize, Column)> {
+    let lower = line.to_lowercase();
+    let mut taken = vec![false; lower.len()];
+    let mut columns = Vec::new();
+    for &(name, column) in HEADERS {
+        let mut from = 0;
+        while let Some(found) = lower[from..].find(name) {
+            let start = from + found;
+            let end = start + name.len();
+            from = end;
+            if taken[start..end].iter().any(|&t| t) {
+                continue;
+            }
+            taken[start..end].iter_mut().for_each(|t| *t = true);
+            columns.push((start, column));
+        }
+    }
+    columns.sort_by_key(|&(start, _)| start);
+    columns
+}
+
+/// Whitespace-separated cells of a line with their offsets
+fn cells(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split_whitespace()
+        .map(move |cell| (cell.as_ptr() as usize - line.as_ptr() as usize, cell))
+}
+
+/// Neighbors in a VRP, Comware or IOS table. Cells belong to the column
+/// whose header starts at or before them, so empty cells such as a missing
+/// capability do not shift the others.
+fn parse_table(output: &str) -> Option<Vec<LldpNeighbor>> {
+    let mut lines = output.lines();
+    let columns = lines.by_ref().find_map(|line| {
+        let columns = header_columns(line);
+        let has = |wanted| columns.iter().any(|&(_, c)| c == wanted);
+        (has(Column::Local) && has(Column::Device)).then_some(columns)
+    })?;
+
+    let mut neighbors = Vec::new();
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with("---") {
+            continue;
+        }
+        // `Total entries displayed: 2` and similar footers
+        if trimmed.to_lowercase().starts_with("total") {
+            break;
+        }
+        let mut row: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        for (offset, cell) in cells(line) {
+            let index = columns
+                .iter()
+                .rposition(|&(start, _)| start <= offset)
+                .unwrap_or(0);
+            row.entry(index).or_default().push(cell);
+        }
+        let get = |wanted| {
+            columns
+                .iter()
+                .position(|&(_, c)| c == wanted)
+                .and_then(|index| row.get(&index))
+                .map(|cells| cells.join(" "))
+        };
+        if let (Some(local_port), Some(device)) = (get(Column::Local), get(Column::Device)) {
+            neighbors.push(LldpNeighbor {
+                local_port,
+                device,
+                port: get(Column::Port),
+                chassis_id: get(Column::Chassis),
+            });
+        }
+    }
+    Some(neighbors)
+}
+
+/// Neighbors in `lldpctl -f keyvalue` output, lines like
+/// `lldp.eth0.chassis.name=switch-1`
+fn parse_keyvalue(output: &str) -> Option<Vec<LldpNeighbor>> {
+    let mut found = false;
+    // Per local interface: system name, port name, chassis MAC
+    type Remote<'a> = (Option<&'a str>, Option<&'a str>, Option<&'a str>);
+    let mut by_port: BTreeMap<&str, Remote> = BTreeMap::new();
+    for line in output.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let Some(rest) = key.strip_prefix("lldp.") else {
+            continue;
+        };
+        found = true;
+        let Some((local, field)) = rest.split_once('.') else {
+            continue;
+        };
+        let entry = by_port.entry(local).or_default();
+        match field {
+            "chassis.name" => entry.0 = Some(value),
+            "port.ifname" => entry.1 = Some(value),
+            // Older lldpd has no ifname; the description usually carries it
+            "port.descr" => entry.1 = entry.1.or(Some(value)),
+            "chassis.mac" => entry.2 = Some(value),
+            _ => {}
+        }
+    }
+    found.then(|| {
+        by_port
+            .into_iter()
+            .filter_map(|(local, (device, port, chassis))| {
+                Some(LldpNeighbor {
+                    local_port: local.to_string(),
+                    device: device.or(chassis)?.to_string(),
+                    port: port.map(str::to_string),
+                    chassis_id: chassis.map(str::to_string),
+                })
+            })
+            .collect()
+    })
+}
+
+/// Neighbors in a command's output; `None` when it holds no neighbor table
+pub fn parse_neighbors(dialect: DeviceDialect, output: &str) -> Option<Vec<LldpNeighbor>> {
+    match dialect {
+        // Without neighbors lldpctl prints nothing at all
+        DeviceDialect::Linux if output.trim().is_empty() => Some(Vec::new()),
+        DeviceDialect::Linux => parse_keyvalue(output),
+        _ => parse_table(output),
+    }
+}
+
+/// Hostname in a prompt: `<HUAWEI>`, `[~HUAWEI]`, `Switch#`, `root@board:~#`
+pub(crate) fn prompt_hostname(prompt: &str) -> Option<String> {
+    let prompt = prompt.trim();
+    let name = match prompt.split_once('@') {
+        Some((_, rest)) => rest.split([':', ' ', '$', '#']).next().unwrap_or_default(),
+        None => prompt.trim_matches(|c: char| "<>[]~*#$ ".contains(c)),
+    };
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Interface name compared across the two ends of a link
+fn port_key(port: &str) -> String {
+    let port = port.to_lowercase().replace(' ', "");
+    let split = port
+        .find(|c: char| !c.is_ascii_alphabetic() && c != '-')
+        .unwrap_or(port.len());
+    let (prefix, rest) = port.split_at(split);
+    let prefix = PORT_PREFIXES
+        .iter()
+        .find(|(long, _)| *long == prefix)
+        .map(|(_, short)| *short)
+        .unwrap_or(prefix);
+    format!("{}{}", prefix, rest)
+}
+
+/// Merge what every device reported into nodes and deduplicated links
+pub fn assemble(devices: Vec<DeviceNeighbors>) -> Topology {
+    let mut nodes: BTreeMap<String, TopologyNode> = BTreeMap::new();
+    for device in &devices {
+        nodes.insert(
+            device.name.clone(),
+            TopologyNode {
+                id: device.name.clone(),
+                session_id: Some(device.session_id.clone()),
+                host: Some(device.host.clone()),
+            },
+        );
+    }
+
+    let mut links: Vec<TopologyLink> = Vec::new();
+    for device in &devices {
+        for neighbor in &device.neighbors {
+            nodes
+                .entry(neighbor.device.clone())
+                .or_insert_with(|| TopologyNode {
+                    id: neighbor.device.clone(),
+                    session_id: None,
+                    host: None,
+                });
+            let local_port = port_key(&neighbor.local_port);
+            // The far end reported this link already, with our port as its remote
+            let reverse = links.iter_mut().find(|link| {
+                link.source == neighbor.device
+                    && link.target == device.name
+                    && link.target_port.as_deref().map(port_key).as_ref() == Some(&local_port)
+                    && neighbor
+                        .port
+                        .as_deref()
+                        .is_none_or(|port| port_key(port) == port_key(&link.source_port))
+            });
+            if let Some(link) = reverse {
+                link.confirmed = true;
+                continue;
+            }
+            links.push(TopologyLink {
+                source: device.name.clone(),
+                source_port: neighbor.local_port.clone(),
+                target: neighbor.device.clone(),
+                target_port: neighbor.port.clone(),
+                confirmed: false,
+            });
+        }
+    }
+
+    Topology {
+        nodes: nodes.into_values().collect(),
+        links,
+        devices,
+    }
+}
+
+async fn collect_device(
+    manager: &SessionManager,
+    session_id: &str,
+    timeout: Duration,
+) -> DeviceNeighbors {
+    let mut device = DeviceNeighbors {
+        session_id: session_id.to_string(),
+        host: String::new(),
+        name: String::new(),
+        neighbors: Vec::new(),
+        error: None,
+    };
+    let Some(handle) = manager.get(session_id) else {
+        device.name = session_id.to_string();
+        device.error = Some("session not found".to_string());
+        return device;
+    };
+    let dialect = handle.config.dialect;
+    device.host = handle.config.host.clone();
+    device.name = device.host.clone();
+    drop(handle);
+
+    let Some(command) = neighbor_command(dialect) else {
+        device.error = Some(format!("LLDP is not supported for {:?} devices", dialect));
+        return device;
+    };
+    match command::run_command(manager, session_id, command, timeout).await {
+        Ok(output) => {
+            if let Some(name) = prompt_hostname(&output.prompt) {
+                device.name = name;
+            }
+            if let Some(line) = command::rejection(&output.output) {
+                device.error = Some(format!("`{}` rejected: {}", command, line));
+            } else {
+                match parse_neighbors(dialect, &output.output) {
+                    Some(neighbors) => device.neighbors = neighbors,
+                    None => device.error = Some("no LLDP neighbor table in the output".to_string()),
+                }
+            }
+        }
+        Err(e) => device.error = Some(format!("`{}` failed: {}", command, e)),
+    }
+    device
+}
+
+/// Collect the neighbors of every session at once and assemble the graph.
+/// Sessions that fail keep their error in `devices` and add no links.
+pub async fn collect(
+    manager: &Arc<SessionManager>,
+    session_ids: &[String],
+    timeout: Duration,
+    job: &JobControl,
+) -> Topology {
+    let total = session_ids.len() as u64;
+    let mut tasks = JoinSet::new();
+    for (index, session_id) in session_ids.iter().cloned().enumerate() {
+        let manager = Arc::clone(manager);
+        tasks.spawn(async move { (index, collect_device(&manager, &session_id, timeout).await) });
+    }
+
+    let mut devices: Vec<Option<DeviceNeighbors>> = vec![None; session_ids.len()];
+    let mut completed = 0;
+    loop {
+        let joined = tokio::select! {
+            joined = tasks.join_next() => joined,
+            _ = job.cancelled() => break,
+        };
+        let Some(joined) = joined else {
+            break;
+        };
+        let Ok((index, device)) = joined else {
+            warn!(job_id = %job.id(), "Topology task panicked");
+            continue;
+        };
+        completed += 1;
+        job.progress(completed, total, Some(device.name.clone()));
+        devices[index] = Some(device);
+    }
+
+    let topology = assemble(devices.into_iter().flatten().collect());
+    info!(
+        sessions = session_ids.len(),
+        nodes = topology.nodes.len(),
+        links = topology.links.len(),
+        "Topology collected"
+    );
+    topology
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, neighbors: Vec<LldpNeighbor>) -> DeviceNeighbors {
+        DeviceNeighbors {
+            session_id: format!("s-{}", name),
+            host: format!("{}.lab", name),
+            name: name.to_string(),
+            neighbors,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_and_assemble() {
+        let vrp = "\
+Local Intf       Neighbor Dev             Neighbor Intf             Exptime(s)
+GE0/0/1          CORE-2                   GigabitEthernet0/0/2      101
+GE0/0/3          ACCESS-1                 Gi0/1                     98
+";
+        let core1 = parse_neighbors(DeviceDialect::Vrp, vrp).unwrap();
+        assert_eq!(core1.len(), 2);
+        assert_eq!(
+            core1[0],
+            LldpNeighbor {
+                local_port: "GE0/0/1".to_string(),
+                device: "CORE-2".to_string(),
+                port: Some("GigabitEthernet0/0/2".to_string()),
+                chassis_id: None,
+            }
+        );
+
+        // The capability cell of the second row is empty
+        let ios = "\
+Capability codes:
+    (R) Router, (B) Bridge, (T) Telephone, (C) DOCSIS Cable Device
+
+Device ID           Local Intf     Hold-time  Capability      Port ID
+CORE-1              Gi0/1          120        B,R             GE0/0/3
+unknown-ap          Gi0/5          90                         Gi0
+
+Total entries displayed: 2
+";
+        let access1 = parse_neighbors(DeviceDialect::Ios, ios).unwrap();
+        assert_eq!(access1.len(), 2);
+        assert_eq!(access1[1].device, "unknown-ap");
+        assert_eq!(access1[1].port.as_deref(), Some("Gi0"));
+
+        let comware = "\
+System Name          Local Interface Chassis ID      Port ID
+CORE-1               GE1/0/1         3822-d6aa-0001  GigabitEthernet0/0/4
+";
+        let comware = parse_neighbors(DeviceDialect::Comware, comware).unwrap();
+        assert_eq!(comware[0].local_port, "GE1/0/1");
+        assert_eq!(comware[0].chassis_id.as_deref(), Some("3822-d6aa-0001"));
+
+        let linux = "\
+lldp.eth0.via=LLDP
+lldp.eth0.chassis.mac=38:22:d6:aa:00:01
+lldp.eth0.chassis.name=CORE-2
+lldp.eth0.port.ifname=GigabitEthernet0/0/7
+";
+        let board = parse_neighbors(DeviceDialect::Linux, linux).unwrap();
+        assert_eq!(board[0].device, "CORE-2");
+        assert_eq!(board[0].port.as_deref(), Some("GigabitEthernet0/0/7"));
+        assert_eq!(parse_neighbors(DeviceDialect::Linux, "").unwrap(), []);
+        assert!(parse_neighbors(DeviceDialect::Vrp, "Info: LLDP is not enabled.").is_none());
+
+        assert_eq!(prompt_hostname("<CORE-1>").as_deref(), Some("CORE-1"));
+        assert_eq!(prompt_hostname("[~CORE-1]").as_deref(), Some("CORE-1"));
+        assert_eq!(prompt_hostname("ACCESS-1#").as_deref(), Some("ACCESS-1"));
+        assert_eq!(prompt_hostname("root@board:~# ").as_deref(), Some("board"));
+
+        let core2 = vec![LldpNeighbor {
+            local_port: "GigabitEthernet0/0/2".to_string(),
+            device: "CORE-1".to_string(),
+            port: Some("GE0/0/1".to_string()),
+            chassis_id: None,
+        }];
+        let topology = assemble(vec![
+            device("CORE-1", core1),
+            device("CORE-2", core2),
+            device("ACCESS-1", access1),
+        ]);
+        let ids: Vec<_> = topology.nodes.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, ["ACCESS-1", "CORE-1", "CORE-2", "unknown-ap"]);
+        assert!(topology.nodes[3].session_id.is_none());
+        // CORE-1 <-> CORE-2 and CORE-1 <-> ACCESS-1 from both ends, plus the AP
+        assert_eq!(topology.links.len(), 3);
+        assert!(topology.links[0].confirmed && topology.links[1].confirmed);
+        assert_eq!(topology.links[2].target, "unknown-ap");
+        assert!(!topology.links[2].confirmed);
+    }
+}
@@ -5,13 +5,15 @@
 
 use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
 use regex::Regex;
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::LazyLock;
+use streaming_iterator::StreamingIterator;
 use thiserror::Error;
 use tracing::{debug, info, warn};
-use streaming_iterator::StreamingIterator;
 use tree_sitter::{Parser, Query, QueryCursor};
 use walkdir::WalkDir;
 
@@ -26,6 +28,23 @@ pub enum TracerError {
     TreeSitterError(String),
     #[error("Tracer not indexed")]
     NotIndexed,
+    #[error("Git error: {0}")]
+    GitError(String),
+}
+
+/// Severity of a log call site, inferred from the callee at index time
+///
+/// Ordered from least to most severe so consumers can filter with `>=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Unknown,
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
 }
 
 /// Source location information for a log format string
@@ -35,13 +54,87 @@ pub struct SourceLocation {
     pub line: u32,
     pub function: String,
     pub format_string: String,
+    pub severity: Severity,
+}
+
+/// Definition site of a function symbol
+#[derive(Debug, Clone, Serialize)]
+pub struct SymbolLocation {
+    pub name: String,
+    pub file: String,
+    pub line: u32,
+}
+
+/// A single frame resolved from a backtrace / call trace line
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedFrame {
+    pub symbol: String,
+    /// Offset suffix as printed by the device, e.g. `+0x1c/0x40`
+    pub offset: Option<String>,
+    pub locations: Vec<SymbolLocation>,
+}
+
+/// Last-change information for a single source line, from `git blame`
+#[derive(Debug, Clone, Serialize)]
+pub struct BlameInfo {
+    pub commit: String,
+    pub author: String,
+    pub author_mail: String,
+    /// Author time as a Unix timestamp (seconds)
+    pub author_time: i64,
+    /// Author timezone offset as printed by git, e.g. `+0800`
+    pub author_tz: String,
+    pub summary: String,
+}
+
+/// Default cap on indexed patterns (~tens of MB of automaton at most)
+const DEFAULT_MAX_PATTERNS: usize = 200_000;
+
+/// Default cap on indexed function definitions
+const DEFAULT_MAX_SYMBOLS: usize = 500_000;
+
+/// Default cap on source file size; larger files are usually generated tables
+const DEFAULT_MAX_FILE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Size budget applied while indexing
+///
+/// When more patterns are found than `max_patterns`, the most specific
+/// (longest) patterns are kept, ties broken by file path then line, so the
+/// same tree always produces the same index. Both caps hold during the walk,
+/// so a huge tree never has more than the cap in memory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TracerLimits {
+    pub max_patterns: usize,
+    /// Function definitions past this many, in walk order, are skipped
+    pub max_symbols: usize,
+    /// Files larger than this are skipped
+    pub max_file_bytes: u64,
+}
+
+impl Default for TracerLimits {
+    fn default() -> Self {
+        Self {
+            max_patterns: DEFAULT_MAX_PATTERNS,
+            max_symbols: DEFAULT_MAX_SYMBOLS,
+            max_file_bytes: DEFAULT_MAX_FILE_BYTES,
+        }
+    }
 }
 
 /// Statistics about indexing operation
 #[derive(Debug, Clone, Serialize)]
 pub struct IndexStats {
     pub files_scanned: u32,
+    /// Files skipped for exceeding `max_file_bytes`
+    pub files_skipped: u32,
     pub patterns_indexed: u32,
+    /// Patterns dropped as duplicates or to honor `max_patterns`
+    pub patterns_pruned: u32,
+    pub symbols_indexed: u32,
+    /// Definitions skipped to honor `max_symbols`
+    pub symbols_pruned: u32,
+    pub automaton_build_ms: u64,
     pub duration_ms: u64,
 }
 
@@ -50,7 +143,16 @@ pub struct IndexStats {
 pub struct TracerStats {
     pub indexed: bool,
     pub pattern_count: usize,
+    pub symbol_count: usize,
     pub source_path: Option<String>,
+    /// HEAD commit of the indexed directory, if it is inside a git repository
+    pub git_commit: Option<String>,
+    /// Heap memory used by the AhoCorasick automaton
+    pub automaton_bytes: usize,
+    /// Approximate heap memory used by patterns and their source locations
+    pub pattern_bytes: usize,
+    pub automaton_build_ms: u64,
+    pub limits: TracerLimits,
 }
 
 /// Pattern entry for building AhoCorasick automaton
@@ -61,14 +163,25 @@ struct PatternEntry {
 
 /// Log tracer that maps log output to source code locations
 pub struct LogTracer {
-    /// Index mapping normalized patterns to source locations
-    index: HashMap<String, SourceLocation>,
     /// AhoCorasick automaton for efficient multi-pattern matching
     matcher: Option<AhoCorasick>,
     /// Ordered patterns for matcher index lookup
     patterns: Vec<String>,
+    /// Source locations, parallel to `patterns`
+    locations: Vec<SourceLocation>,
+    /// Function definitions keyed by name (static functions may repeat across files)
+    symbols: HashMap<String, Vec<SymbolLocation>>,
     /// Source directory that was indexed
     source_path: Option<String>,
+    /// HEAD commit of the indexed directory at index time
+    git_commit: Option<String>,
+    /// Size budget for the next index run
+    limits: TracerLimits,
+    /// Automaton build time of the last index run
+    automaton_build_ms: u64,
+    /// Lines looked up with `match_log`, and how many matched
+    lookups: AtomicU64,
+    matches: AtomicU64,
 }
 
 /// Regex for format specifiers like %d, %s, %x, etc.
@@ -76,21 +189,32 @@ static FORMAT_SPEC_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"%[-+0 #]*\d*\.?\d*[hlLzjt]*[diouxXeEfFgGaAcspn%]").unwrap()
 });
 
-/// Regex for escape sequences
-static ESCAPE_RE: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"\\[nrtv\\0]").unwrap()
+/// Regex for identifiers in backtrace lines, with an optional `+0xoff/0xlen` suffix
+static FRAME_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"([A-Za-z_][A-Za-z0-9_]*)(\+0x[0-9a-fA-F]+(?:/0x[0-9a-fA-F]+)?)?").unwrap()
 });
 
 impl LogTracer {
     pub fn new() -> Self {
         Self {
-            index: HashMap::new(),
             matcher: None,
             patterns: Vec::new(),
+            locations: Vec::new(),
+            symbols: HashMap::new(),
             source_path: None,
+            git_commit: None,
+            limits: TracerLimits::default(),
+            automaton_build_ms: 0,
+            lookups: AtomicU64::new(0),
+            matches: AtomicU64::new(0),
         }
     }
 
+    /// Set the size budget used by subsequent `index_directory` calls
+    pub fn set_limits(&mut self, limits: TracerLimits) {
+        self.limits = limits;
+    }
+
     /// Index a directory of C source files
     ///
     /// Walks directory recursively, parses .c/.h files with tree-sitter,
@@ -98,12 +222,18 @@ impl LogTracer {
     pub fn index_directory(&mut self, path: &Path) -> Result<IndexStats, TracerError> {
         let start = std::time::Instant::now();
         let mut files_scanned = 0u32;
-        let mut pattern_entries: Vec<PatternEntry> = Vec::new();
+        let mut files_skipped = 0u32;
+        let mut symbols_indexed = 0u32;
+        let mut symbols_pruned = 0u32;
+        let mut patterns_found = 0usize;
+        let mut budget = PatternBudget::new(self.limits.max_patterns);
 
         // Clear previous index
-        self.index.clear();
         self.patterns.clear();
+        self.locations.clear();
+        self.symbols.clear();
         self.matcher = None;
+        self.automaton_build_ms = 0;
 
         // Create tree-sitter parser
         let mut parser = Parser::new();
@@ -121,7 +251,7 @@ impl LogTracer {
                     (field_expression field: (field_identifier) @func)
                 ]
                 arguments: (argument_list
-                    (string_literal) @format_string))
+                    [(string_literal) (concatenated_string)] @format_string))
         "#;
 
         let query = Query::new(&language, query_str)
@@ -134,9 +264,19 @@ impl LogTracer {
             .capture_index_for_name("format_string")
             .ok_or_else(|| TracerError::TreeSitterError("No format_string capture".to_string()))?;
 
-        // Walk directory for .c and .h files
+        // Query for function definitions, used to resolve backtrace symbols.
+        // The function_declarator may be nested (e.g. pointer return types),
+        // so definitions are confirmed by walking up to a function_definition.
+        let symbol_query = Query::new(
+            &language,
+            "(function_declarator declarator: (identifier) @name)",
+        )
+        .map_err(|e| TracerError::TreeSitterError(e.to_string()))?;
+
+        // Walk directory for .c and .h files (sorted, so pruning is deterministic)
         for entry in WalkDir::new(path)
             .follow_links(true)
+            .sort_by_file_name()
             .into_iter()
             .filter_map(|e| e.ok())
         {
@@ -150,6 +290,13 @@ impl LogTracer {
                 continue;
             }
 
+            let file_len = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            if file_len > self.limits.max_file_bytes {
+                debug!(file = ?file_path, bytes = file_len, "Skipping oversized file");
+                files_skipped += 1;
+                continue;
+            }
+
             // Read and parse file
             let source = match std::fs::read_to_string(file_path) {
                 Ok(s) => s,
@@ -176,15 +323,15 @@ impl LogTracer {
             while let Some(m) = matches.next() {
                 let mut func_name = String::new();
                 let mut format_string = String::new();
+                let mut kernel_level = None;
                 let mut line = 0u32;
 
                 for capture in m.captures {
                     if capture.index == func_idx {
                         func_name = source[capture.node.byte_range()].to_string();
                     } else if capture.index == format_idx {
-                        let raw = &source[capture.node.byte_range()];
-                        // Remove quotes from string literal
-                        format_string = raw.trim_matches('"').to_string();
+                        format_string = literal_text(capture.node, &source);
+                        kernel_level = kernel_log_level(capture.node, &source);
                         line = capture.node.start_position().row as u32 + 1;
                     }
                 }
@@ -202,6 +349,7 @@ impl LogTracer {
                 let location = SourceLocation {
                     file: file_path.to_string_lossy().to_string(),
                     line,
+                    severity: classify_severity(&func_name, kernel_level),
                     function: func_name,
                     format_string: format_string.clone(),
                 };
@@ -209,48 +357,97 @@ impl LogTracer {
                 // Normalize format string for matching
                 let normalized = normalize_format_string(&format_string);
                 if normalized.len() >= 5 {
-                    pattern_entries.push(PatternEntry {
+                    patterns_found += 1;
+                    budget.offer(PatternEntry {
                         pattern: normalized,
                         location,
                     });
                 }
             }
-        }
 
-        // Build AhoCorasick automaton
-        if !pattern_entries.is_empty() {
-            let patterns: Vec<String> = pattern_entries.iter().map(|e| e.pattern.clone()).collect();
+            // Extract function definitions
+            let mut cursor = QueryCursor::new();
+            let mut matches = cursor.matches(&symbol_query, tree.root_node(), source.as_bytes());
 
-            // Store patterns for index lookup
-            self.patterns = patterns.clone();
+            while let Some(m) = matches.next() {
+                for capture in m.captures {
+                    if !is_function_definition(capture.node) {
+                        continue;
+                    }
+                    if symbols_indexed as usize >= self.limits.max_symbols {
+                        symbols_pruned += 1;
+                        continue;
+                    }
 
-            // Build index
-            for entry in &pattern_entries {
-                self.index
-                    .insert(entry.pattern.clone(), entry.location.clone());
+                    let name = source[capture.node.byte_range()].to_string();
+                    let location = SymbolLocation {
+                        name: name.clone(),
+                        file: file_path.to_string_lossy().to_string(),
+                        line: capture.node.start_position().row as u32 + 1,
+                    };
+                    self.symbols.entry(name).or_default().push(location);
+                    symbols_indexed += 1;
+                }
             }
+        }
+
+        let pattern_entries = budget.into_entries();
+        let patterns_pruned = (patterns_found - pattern_entries.len()) as u32;
+        if patterns_pruned > 0 {
+            info!(
+                found = patterns_found,
+                kept = pattern_entries.len(),
+                max_patterns = self.limits.max_patterns,
+                "Pruned log patterns"
+            );
+        }
+        if symbols_pruned > 0 {
+            info!(
+                skipped = symbols_pruned,
+                max_symbols = self.limits.max_symbols,
+                "Skipped function definitions"
+            );
+        }
+
+        // Build AhoCorasick automaton
+        if !pattern_entries.is_empty() {
+            let (patterns, locations): (Vec<String>, Vec<SourceLocation>) = pattern_entries
+                .into_iter()
+                .map(|e| (e.pattern, e.location))
+                .unzip();
 
             // Build automaton with leftmost-longest matching
+            let build_start = std::time::Instant::now();
             let automaton = AhoCorasickBuilder::new()
                 .match_kind(MatchKind::LeftmostLongest)
                 .build(&patterns)
                 .map_err(|e| TracerError::TreeSitterError(e.to_string()))?;
+            self.automaton_build_ms = build_start.elapsed().as_millis() as u64;
 
             self.matcher = Some(automaton);
+            self.patterns = patterns;
+            self.locations = locations;
         }
 
         self.source_path = Some(path.to_string_lossy().to_string());
+        self.git_commit = git_head(path);
 
         let duration = start.elapsed();
         let stats = IndexStats {
             files_scanned,
-            patterns_indexed: pattern_entries.len() as u32,
+            files_skipped,
+            patterns_indexed: self.patterns.len() as u32,
+            patterns_pruned,
+            symbols_indexed,
+            symbols_pruned,
+            automaton_build_ms: self.automaton_build_ms,
             duration_ms: duration.as_millis() as u64,
         };
 
         info!(
             files = files_scanned,
-            patterns = pattern_entries.len(),
+            patterns = self.patterns.len(),
+            symbols = symbols_indexed,
             duration_ms = stats.duration_ms,
             "Indexing complete"
         );
@@ -263,27 +460,76 @@ impl LogTracer {
     /// Returns the SourceLocation if a match is found
     pub fn match_log(&self, log_line: &str) -> Option<&SourceLocation> {
         let matcher = self.matcher.as_ref()?;
+        self.lookups.fetch_add(1, Ordering::Relaxed);
 
-        // Find all matches in the log line
-        for mat in matcher.find_iter(log_line) {
-            let pattern = &self.patterns[mat.pattern().as_usize()];
-            if let Some(location) = self.index.get(pattern) {
-                debug!(
-                    pattern = pattern,
-                    file = &location.file,
-                    line = location.line,
-                    "Log matched"
-                );
-                return Some(location);
-            }
-        }
+        // Leftmost-longest: the first match is the most specific pattern
+        let mat = matcher.find(log_line)?;
+        let index = mat.pattern().as_usize();
+        let location = &self.locations[index];
+        debug!(
+            pattern = &self.patterns[index],
+            file = &location.file,
+            line = location.line,
+            "Log matched"
+        );
+        self.matches.fetch_add(1, Ordering::Relaxed);
+        Some(location)
+    }
+
+    /// Lines looked up and lines matched since the app started
+    pub fn match_counts(&self) -> (u64, u64) {
+        (
+            self.lookups.load(Ordering::Relaxed),
+            self.matches.load(Ordering::Relaxed),
+        )
+    }
 
-        None
+    /// Resolve a function name to its definition site(s)
+    pub fn resolve_symbol(&self, name: &str) -> Vec<SymbolLocation> {
+        self.symbols.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Resolve every known symbol in a backtrace or call trace line
+    ///
+    /// Handles kernel-style `func+0x1c/0x40` frames as well as plain
+    /// `Call trace: func_a -> func_b` lines. Identifiers that are not indexed
+    /// function definitions are ignored, so surrounding text is harmless.
+    pub fn resolve_backtrace(&self, text: &str) -> Vec<ResolvedFrame> {
+        FRAME_RE
+            .captures_iter(text)
+            .filter_map(|caps| {
+                let symbol = caps.get(1)?.as_str();
+                let locations = self.symbols.get(symbol)?;
+                Some(ResolvedFrame {
+                    symbol: symbol.to_string(),
+                    offset: caps.get(2).map(|m| m.as_str().to_string()),
+                    locations: locations.clone(),
+                })
+            })
+            .collect()
     }
 
     /// Get the number of indexed patterns
     pub fn get_indexed_count(&self) -> usize {
-        self.index.len()
+        self.patterns.len()
+    }
+
+    /// Approximate heap usage of patterns and their locations
+    fn pattern_memory(&self) -> usize {
+        let strings: usize = self
+            .patterns
+            .iter()
+            .zip(&self.locations)
+            .map(|(p, l)| {
+                p.capacity()
+                    + l.file.capacity()
+                    + l.function.capacity()
+                    + l.format_string.capacity()
+            })
+            .sum();
+        strings
+            + self.patterns.capacity() * std::mem::size_of::<String>()
+            + self.locations.capacity() * std::mem::size_of::<SourceLocation>()
     }
 
     /// Check if the tracer has been indexed
@@ -295,8 +541,14 @@ impl LogTracer {
     pub fn get_stats(&self) -> TracerStats {
         TracerStats {
             indexed: self.is_indexed(),
-            pattern_count: self.index.len(),
+            pattern_count: self.get_indexed_count(),
+            symbol_count: self.symbols.values().map(Vec::len).sum(),
             source_path: self.source_path.clone(),
+            git_commit: self.git_commit.clone(),
+            automaton_bytes: self.matcher.as_ref().map_or(0, |m| m.memory_usage()),
+            pattern_bytes: self.pattern_memory(),
+            automaton_build_ms: self.automaton_build_ms,
+            limits: self.limits.clone(),
         }
     }
 }
@@ -347,18 +599,331 @@ fn is_log_function(name: &str) -> bool {
     false
 }
 
+/// Get the HEAD commit hash of the git repository containing `dir`
+fn git_head(dir: &Path) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
+
+/// Run `git blame` for a single line of a file. Blocks on git.
+pub fn git_blame(file: &Path, line: u32) -> Result<BlameInfo, TracerError> {
+    let invalid = || TracerError::GitError(format!("Invalid file path: {}", file.display()));
+    let dir = file.parent().ok_or_else(invalid)?;
+    // Relative to `dir`, which git changes into; an empty `dir` stays put
+    let name = file.file_name().ok_or_else(invalid)?;
+
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["blame", "--porcelain", "-L"])
+        .arg(format!("{},{}", line, line))
+        .arg("--")
+        .arg(name)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(TracerError::GitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    parse_blame_porcelain(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| TracerError::GitError("Unexpected git blame output".to_string()))
+}
+
+/// Parse the header of `git blame --porcelain` output for one line
+fn parse_blame_porcelain(output: &str) -> Option<BlameInfo> {
+    let mut lines = output.lines();
+    let commit = lines.next()?.split_whitespace().next()?.to_string();
+
+    let mut info = BlameInfo {
+        commit,
+        author: String::new(),
+        author_mail: String::new(),
+        author_time: 0,
+        author_tz: String::new(),
+        summary: String::new(),
+    };
+
+    for line in lines {
+        // The source line itself is prefixed with a tab and ends the header
+        if line.starts_with('\t') {
+            break;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "author" => info.author = value.to_string(),
+            "author-mail" => info.author_mail = value.trim_matches(['<', '>']).to_string(),
+            "author-time" => info.author_time = value.parse().unwrap_or_default(),
+            "author-tz" => info.author_tz = value.to_string(),
+            "summary" => info.summary = value.to_string(),
+            _ => {}
+        }
+    }
+
+    Some(info)
+}
+
+/// Order of pattern occurrences: longest first, then by file, line and
+/// walk order
+type PatternRank = (Reverse<usize>, String, u32, usize);
+
+/// The `max_patterns` best patterns offered so far, deduplicated
+///
+/// Filled during the walk, so it never holds more than `max_patterns`
+/// entries. A pattern found more than once counts at its best-ranked
+/// occurrence, and the result doesn't depend on the order of the offers.
+struct PatternBudget {
+    max_patterns: usize,
+    kept: BTreeMap<PatternRank, PatternEntry>,
+    /// Rank of each kept pattern
+    ranks: HashMap<String, PatternRank>,
+    offered: usize,
+}
+
+impl PatternBudget {
+    fn new(max_patterns: usize) -> Self {
+        Self {
+            max_patterns,
+            kept: BTreeMap::new(),
+            ranks: HashMap::new(),
+            offered: 0,
+        }
+    }
+
+    fn offer(&mut self, entry: PatternEntry) {
+        let rank = (
+            Reverse(entry.pattern.len()),
+            entry.location.file.clone(),
+            entry.location.line,
+            self.offered,
+        );
+        self.offered += 1;
+
+        if let Some(current) = self.ranks.get(&entry.pattern) {
+            if *current <= rank {
+                return;
+            }
+            let current = current.clone();
+            self.kept.remove(&current);
+        } else if self.kept.len() >= self.max_patterns {
+            match self.kept.last_key_value() {
+                Some((worst, _)) if *worst > rank => {}
+                _ => return,
+            }
+            if let Some((_, worst)) = self.kept.pop_last() {
+                self.ranks.remove(&worst.pattern);
+            }
+        }
+        self.ranks.insert(entry.pattern.clone(), rank.clone());
+        self.kept.insert(rank, entry);
+    }
+
+    /// The kept entries in the order they were offered
+    fn into_entries(self) -> Vec<PatternEntry> {
+        let mut kept: Vec<(usize, PatternEntry)> = self
+            .kept
+            .into_iter()
+            .map(|(rank, entry)| (rank.3, entry))
+            .collect();
+        kept.sort_by_key(|(offered, _)| *offered);
+        kept.into_iter().map(|(_, entry)| entry).collect()
+    }
+}
+
+/// Check whether a function_declarator identifier belongs to a definition
+/// (as opposed to a prototype or a function pointer declaration)
+fn is_function_definition(name_node: tree_sitter::Node) -> bool {
+    let mut node = name_node.parent();
+    while let Some(n) = node {
+        match n.kind() {
+            "function_declarator" | "pointer_declarator" | "parenthesized_declarator" => {
+                node = n.parent();
+            }
+            "function_definition" => return true,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Get the runtime text of a string_literal or concatenated_string node
+///
+/// Adjacent literals are joined; macro pieces between them (e.g. `PRIu64`)
+/// expand to conversion specifiers, so they become a wildcard space.
+fn literal_text(node: tree_sitter::Node, source: &str) -> String {
+    if node.kind() != "concatenated_string" {
+        return decode_c_string(&source[node.byte_range()]);
+    }
+
+    let mut text = String::new();
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if child.kind() == "string_literal" {
+            text.push_str(&decode_c_string(&source[child.byte_range()]));
+        } else {
+            text.push(' ');
+        }
+    }
+    text
+}
+
+/// Decode a C string literal (including quotes and prefix) to the text it produces
+///
+/// Handles `L`, `u`, `U` and `u8` prefixes plus simple, octal, hex and
+/// universal-character escapes. Narrow literals are decoded as bytes so that
+/// escaped UTF-8/GBK sequences survive; wide literals map escapes to code points.
+fn decode_c_string(raw: &str) -> String {
+    let (wide, body) = if let Some(rest) = raw.strip_prefix("u8") {
+        (false, rest)
+    } else if let Some(rest) = raw.strip_prefix(['L', 'u', 'U']) {
+        (true, rest)
+    } else {
+        (false, raw)
+    };
+    let body = body.strip_prefix('"').unwrap_or(body);
+    let body = body.strip_suffix('"').unwrap_or(body);
+
+    let mut out: Vec<u8> = Vec::with_capacity(body.len());
+    let push_code_point = |out: &mut Vec<u8>, value: u32| {
+        if !wide && value <= 0xFF {
+            out.push(value as u8);
+        } else {
+            let c = char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER);
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    };
+
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+
+        let Some(esc) = chars.next() else {
+            out.push(b'\\');
+            break;
+        };
+
+        match esc {
+            'n' => out.push(b'\n'),
+            't' => out.push(b'\t'),
+            'r' => out.push(b'\r'),
+            'v' => out.push(0x0b),
+            'f' => out.push(0x0c),
+            'a' => out.push(0x07),
+            'b' => out.push(0x08),
+            'e' => out.push(0x1b),
+            '0'..='7' => {
+                let mut value = esc.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(d) => {
+                            value = value * 8 + d;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                push_code_point(&mut out, value);
+            }
+            'x' => {
+                let mut value: u32 = 0;
+                while let Some(d) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    value = value.wrapping_mul(16).wrapping_add(d);
+                    chars.next();
+                }
+                push_code_point(&mut out, value);
+            }
+            'u' | 'U' => {
+                let digits = if esc == 'u' { 4 } else { 8 };
+                let mut value: u32 = 0;
+                for _ in 0..digits {
+                    match chars.peek().and_then(|c| c.to_digit(16)) {
+                        Some(d) => {
+                            value = value * 16 + d;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                let c = char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER);
+                out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            // \\, \", \', \? and unknown escapes produce the character itself
+            other => out.extend_from_slice(other.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Get the `KERN_*` level macro leading a `printk(KERN_ERR "...")` style literal
+fn kernel_log_level<'a>(node: tree_sitter::Node, source: &'a str) -> Option<&'a str> {
+    if node.kind() != "concatenated_string" {
+        return None;
+    }
+    let first = node.named_child(0)?;
+    let text = &source[first.byte_range()];
+    (first.kind() == "identifier" && text.starts_with("KERN_")).then_some(text)
+}
+
+/// Infer the severity of a log call from its callee name
+///
+/// An explicit kernel level wins. Otherwise the callee is searched for
+/// severity tokens, most severe first, so `DBG_ERR` classifies as an error.
+fn classify_severity(func_name: &str, kernel_level: Option<&str>) -> Severity {
+    match kernel_level {
+        Some("KERN_EMERG" | "KERN_ALERT" | "KERN_CRIT") => return Severity::Fatal,
+        Some("KERN_ERR") => return Severity::Error,
+        Some("KERN_WARNING") => return Severity::Warn,
+        Some("KERN_NOTICE" | "KERN_INFO") => return Severity::Info,
+        Some("KERN_DEBUG") => return Severity::Debug,
+        _ => {}
+    }
+
+    const RULES: &[(&[&str], Severity)] = &[
+        (
+            &["fatal", "panic", "emerg", "crit", "alert", "assert"],
+            Severity::Fatal,
+        ),
+        (&["err"], Severity::Error),
+        (&["warn"], Severity::Warn),
+        (&["info", "notice"], Severity::Info),
+        (&["dbg", "debug"], Severity::Debug),
+        (&["trace"], Severity::Trace),
+    ];
+
+    let name = func_name.to_lowercase();
+    RULES
+        .iter()
+        .find(|(tokens, _)| tokens.iter().any(|t| name.contains(t)))
+        .map_or(Severity::Unknown, |&(_, severity)| severity)
+}
+
 /// Normalize a format string for matching
 ///
 /// - Replaces format specifiers (%d, %s, etc.) with a wildcard marker
-/// - Handles escape sequences
+/// - Treats control characters (newlines, tabs) as whitespace
 /// - Extracts static prefix for efficient matching
+///
+/// Expects decoded literal text (see [`decode_c_string`]), not raw source.
 fn normalize_format_string(format_str: &str) -> String {
-    // Handle escape sequences
-    let unescaped = ESCAPE_RE.replace_all(format_str, " ");
-
     // Replace format specifiers with a common marker
     // We use space as a wildcard since logs often have spaces between values
-    let normalized = FORMAT_SPEC_RE.replace_all(&unescaped, " ");
+    let normalized = FORMAT_SPEC_RE.replace_all(format_str, " ");
 
     // Collapse multiple spaces and trim
     let collapsed: String = normalized
@@ -385,6 +950,181 @@ mod tests {
         assert!(!is_log_function("strcpy"));
     }
 
+    #[test]
+    fn test_resolve_backtrace() {
+        let dir = std::env::temp_dir().join(format!("bspt-tracer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("drv.c"),
+            "int probe(void);\n\nstatic char *board_name(int slot)\n{\n    return 0;\n}\n\nint probe(void)\n{\n    printf(\"probe failed: %d\\n\", board_name(1));\n    return 0;\n}\n",
+        )
+        .unwrap();
+
+        let mut tracer = LogTracer::new();
+        let stats = tracer.index_directory(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The prototype of probe() must not be counted as a definition
+        assert_eq!(stats.symbols_indexed, 2);
+        assert_eq!(tracer.resolve_symbol("board_name")[0].line, 3);
+        assert_eq!(tracer.resolve_symbol("probe")[0].line, 8);
+        assert!(tracer.resolve_symbol("printf").is_empty());
+
+        let frames = tracer.resolve_backtrace("Call trace: probe+0x1c/0x40 -> board_name");
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].symbol, "probe");
+        assert_eq!(frames[0].offset.as_deref(), Some("+0x1c/0x40"));
+        assert_eq!(frames[1].symbol, "board_name");
+        assert_eq!(frames[1].offset, None);
+    }
+
+    #[test]
+    fn test_parse_blame_porcelain() {
+        let output = "1f2e3d4c5b6a79880716253443526170819a0b1c 10 10 1\n\
+            author Zhang San\n\
+            author-mail <zhangsan@example.com>\n\
+            author-time 1700000000\n\
+            author-tz +0800\n\
+            committer Zhang San\n\
+            summary Fix slot probe timeout\n\
+            filename drv/board.c\n\
+            \tprintf(\"probe failed\");\n";
+
+        let info = parse_blame_porcelain(output).unwrap();
+        assert_eq!(info.commit, "1f2e3d4c5b6a79880716253443526170819a0b1c");
+        assert_eq!(info.author, "Zhang San");
+        assert_eq!(info.author_mail, "zhangsan@example.com");
+        assert_eq!(info.author_time, 1700000000);
+        assert_eq!(info.author_tz, "+0800");
+        assert_eq!(info.summary, "Fix slot probe timeout");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_git_blame_relative_path() {
+        let repo = std::env::temp_dir().join(format!("bspt-blame-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(repo.join("drv")).unwrap();
+        std::fs::write(repo.join("drv/board.c"), "int probe;\n").unwrap();
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args([
+                    "-c",
+                    "user.name=Zhang San",
+                    "-c",
+                    "user.email=zs@example.com",
+                ])
+                .args(args)
+                .output()
+                .is_ok_and(|output| output.status.success())
+        };
+        // Nothing to blame with where git is missing
+        if !git(&["init", "-q"]) {
+            return;
+        }
+        assert!(git(&["add", "."]));
+        assert!(git(&["commit", "-q", "-m", "Add board probe"]));
+
+        // The path as a walk from the working directory yields it
+        let cwd = std::env::current_dir().unwrap();
+        let mut relative = std::path::PathBuf::new();
+        for _ in cwd.components().skip(1) {
+            relative.push("..");
+        }
+        relative.push(repo.strip_prefix("/").unwrap().join("drv/board.c"));
+        let info = git_blame(&relative, 1).unwrap();
+        assert_eq!(info.author, "Zhang San");
+        assert_eq!(info.summary, "Add board probe");
+        std::fs::remove_dir_all(&repo).unwrap();
+    }
+
+    #[test]
+    fn test_classify_severity() {
+        assert_eq!(classify_severity("LOG_ERROR", None), Severity::Error);
+        assert_eq!(classify_severity("pr_warn", None), Severity::Warn);
+        assert_eq!(classify_severity("log_info", None), Severity::Info);
+        assert_eq!(classify_severity("DBG_PRINT", None), Severity::Debug);
+        assert_eq!(classify_severity("DBG_ERR", None), Severity::Error);
+        assert_eq!(classify_severity("TRACE", None), Severity::Trace);
+        assert_eq!(classify_severity("ASSERT", None), Severity::Fatal);
+        assert_eq!(classify_severity("printf", None), Severity::Unknown);
+        assert_eq!(
+            classify_severity("printk", Some("KERN_ERR")),
+            Severity::Error
+        );
+        assert_eq!(
+            classify_severity("printk", Some("KERN_CONT")),
+            Severity::Unknown
+        );
+        assert!(Severity::Error > Severity::Warn);
+    }
+
+    #[test]
+    fn test_pattern_budget() {
+        let entry = |pattern: &str, file: &str, line: u32| PatternEntry {
+            pattern: pattern.to_string(),
+            location: SourceLocation {
+                file: file.to_string(),
+                line,
+                function: "printf".to_string(),
+                format_string: pattern.to_string(),
+                severity: Severity::Unknown,
+            },
+        };
+        let entries = vec![
+            entry("short one", "a.c", 1),
+            entry("a much longer pattern", "b.c", 2),
+            entry("short one", "c.c", 3),
+            entry("medium pattern", "d.c", 4),
+            entry("medium pattern2", "a.c", 9),
+        ];
+
+        let prune = |entries: Vec<PatternEntry>, max_patterns: usize| {
+            let mut budget = PatternBudget::new(max_patterns);
+            for entry in entries {
+                budget.offer(entry);
+                assert!(budget.kept.len() <= max_patterns);
+            }
+            budget.into_entries()
+        };
+
+        let kept = prune(entries, 2);
+        let kept: Vec<_> = kept.iter().map(|e| e.pattern.as_str()).collect();
+        assert_eq!(kept, vec!["a much longer pattern", "medium pattern2"]);
+
+        // Duplicates keep their first file, whatever the walk order
+        let entries = vec![
+            entry("short one", "c.c", 3),
+            entry("medium pattern", "d.c", 4),
+            entry("short one", "a.c", 1),
+        ];
+        let kept = prune(entries, 5);
+        let kept: Vec<_> = kept
+            .iter()
+            .map(|e| (e.pattern.as_str(), e.location.file.as_str()))
+            .collect();
+        assert_eq!(kept, vec![("medium pattern", "d.c"), ("short one", "a.c")]);
+        assert!(prune(vec![entry("short one", "a.c", 1)], 0).is_empty());
+    }
+
+    #[test]
+    fn test_decode_c_string() {
+        assert_eq!(decode_c_string(r#""Error: %s\n""#), "Error: %s\n");
+        assert_eq!(decode_c_string(r#"L"wide %d\t""#), "wide %d\t");
+        assert_eq!(decode_c_string(r#"u8"slot %u""#), "slot %u");
+        assert_eq!(decode_c_string(r#"U"\u4e2d\U00006587""#), "中文");
+        assert_eq!(
+            decode_c_string(r#""path C:\\temp \"x\"""#),
+            "path C:\\temp \"x\""
+        );
+        // Octal and hex escapes in narrow literals are raw bytes (UTF-8 here)
+        assert_eq!(decode_c_string(r#""\xe4\xb8\xad\346\226\207""#), "中文");
+        assert_eq!(decode_c_string(r#""\101\x42""#), "AB");
+        // Wide literals take hex escapes as code points
+        assert_eq!(decode_c_string(r#"L"\x4e2d""#), "中");
+    }
+
     #[test]
     fn test_normalize_format_string() {
         assert_eq!(
@@ -392,7 +1132,7 @@ mod tests {
             "Hello , value is"
         );
         assert_eq!(
-            normalize_format_string("Error: %s\\n"),
+            normalize_format_string("Error: %s\n"),
             "Error:"
         );
         assert_eq!(
@@ -0,0 +1,686 @@
+//! Port forwarding over SSH sessions.
+//!
+//! A local forward (`ssh -L`) listens on a local port and carries each
+//! connection it accepts over a direct-tcpip channel of the session to
+//! `target_host:target_port`, resolved and reached by the SSH server. Only
+//! loopback is listened on unless a bind address is given.
+//!
+//! A remote forward (`ssh -R`) asks the server to listen on one of its
+//! ports. The server opens a forwarded-tcpip channel for each connection it
+//! accepts there, and `SshHandler` hands it back here to be carried to
+//! `target_host:target_port` as this machine sees it, e.g. the FTP server
+//! for a device that can only reach the managed host. TCP only.
+//!
+//! A dynamic forward (`ssh -D`) is a local SOCKS5 proxy: each client names
+//! its own target with a CONNECT request, which goes out over a
+//! direct-tcpip channel like a local forward's. No authentication is
+//! offered, so it listens only on loopback unless told otherwise.
+//!
+//! An SSH session shares its connection here once authenticated. Forwards
+//! belong to the session and close with it. Closing a forward stops its
+//! listener; connections already carried run until either side ends them.
+
+use crate::session::{SessionError, SessionManager};
+use crate::ssh::SshHandler;
+use dashmap::DashMap;
+use russh::{client, Channel};
+use serde::Serialize;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Listened on locally when the caller names no bind address
+const DEFAULT_BIND: &str = "127.0.0.1";
+
+/// Listened on by the server when the caller names no bind address
+const DEFAULT_REMOTE_BIND: &str = "localhost";
+
+/// How long a SOCKS client has to name its target
+const SOCKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0x00;
+const SOCKS_NO_METHOD: u8 = 0xff;
+const SOCKS_CONNECT: u8 = 0x01;
+const SOCKS_SUCCEEDED: u8 = 0x00;
+const SOCKS_REFUSED: u8 = 0x05;
+const SOCKS_BAD_COMMAND: u8 = 0x07;
+const SOCKS_BAD_ADDRESS: u8 = 0x08;
+
+/// An authenticated SSH connection, shared by its session and forwards.
+/// Requesting a remote forward needs it exclusively.
+pub type SshConnection = Arc<Mutex<client::Handle<SshHandler>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardKind {
+    /// `ssh -L`, a local port to a host reached from the server
+    Local,
+    /// `ssh -R`, a port on the server to a host reached from here
+    Remote,
+    /// `ssh -D`, a local SOCKS5 proxy out of the server
+    Dynamic,
+}
+
+/// A forward as listed by `list_forwards`
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwardInfo {
+    pub id: String,
+    pub session_id: String,
+    pub kind: ForwardKind,
+    /// Address listened on, locally or on the server by kind, with the
+    /// port picked for port 0
+    pub listen: String,
+    /// None for a dynamic forward, whose clients name their targets
+    pub target_host: Option<String>,
+    pub target_port: Option<u16>,
+    /// Connections carried right now
+    pub active: u64,
+    /// Connections carried since the forward opened
+    pub total: u64,
+    /// Connections refused by the server or the target, or dropped before
+    /// naming a SOCKS target
+    pub failed: u64,
+    /// Bytes carried to and from the targets, counted as connections close
+    pub bytes_to_target: u64,
+    pub bytes_from_target: u64,
+}
+
+#[derive(Default)]
+struct ForwardStats {
+    active: AtomicU64,
+    total: AtomicU64,
+    failed: AtomicU64,
+    bytes_to_target: AtomicU64,
+    bytes_from_target: AtomicU64,
+}
+
+impl ForwardStats {
+    fn failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct Forward {
+    info: ForwardInfo,
+    stats: Arc<ForwardStats>,
+    /// Accept loop of a local forward
+    task: Option<JoinHandle<()>>,
+    /// Address and port the server listens on for a remote forward
+    bound: Option<(String, u32)>,
+}
+
+impl Forward {
+    fn info(&self) -> ForwardInfo {
+        ForwardInfo {
+            active: self.stats.active.load(Ordering::Relaxed),
+            total: self.stats.total.load(Ordering::Relaxed),
+            failed: self.stats.failed.load(Ordering::Relaxed),
+            bytes_to_target: self.stats.bytes_to_target.load(Ordering::Relaxed),
+            bytes_from_target: self.stats.bytes_from_target.load(Ordering::Relaxed),
+            ..self.info.clone()
+        }
+    }
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// SSH connections and their forwards, owned by `SessionManager`
+#[derive(Default)]
+pub struct TunnelRegistry {
+    connections: DashMap<String, SshConnection>,
+    forwards: DashMap<String, Forward>,
+}
+
+impl TunnelRegistry {
+    /// Share a session's connection once it is authenticated
+    pub(crate) fn attach(&self, session_id: &str, connection: SshConnection) {
+        self.connections.insert(session_id.to_string(), connection);
+    }
+
+    /// The SSH connection of a session
+    pub fn connection(&self, session_id: &str) -> Result<SshConnection, SessionError> {
+        self.connections
+            .get(session_id)
+            .map(|connection| Arc::clone(&connection))
+            .ok_or_else(|| {
+                SessionError::InvalidConfig(format!("session {} has no SSH connection", session_id))
+            })
+    }
+
+    /// Forwards of one session, or of all sessions
+    pub fn list(&self, session_id: Option<&str>) -> Vec<ForwardInfo> {
+        let mut forwards: Vec<ForwardInfo> = self
+            .forwards
+            .iter()
+            .filter(|forward| session_id.is_none_or(|id| forward.info.session_id == id))
+            .map(|forward| forward.info())
+            .collect();
+        forwards.sort_by(|a, b| (&a.session_id, &a.listen).cmp(&(&b.session_id, &b.listen)));
+        forwards
+    }
+
+    /// Stop a forward, asking the server to stop listening for a remote
+    /// one; false if it is unknown
+    pub async fn close(&self, id: &str) -> bool {
+        let Some((_, forward)) = self.forwards.remove(id) else {
+            return false;
+        };
+        if let (Some((address, port)), Ok(connection)) =
+            (&forward.bound, self.connection(&forward.info.session_id))
+        {
+            let cancelled = connection
+                .lock()
+                .await
+                .cancel_tcpip_forward(address.as_str(), *port)
+                .await;
+            if let Err(e) = cancelled {
+                warn!(forward_id = %id, error = %e, "Server refused to cancel remote forward");
+            }
+        }
+        info!(forward_id = %id, listen = %forward.info.listen, "Forward closed");
+        true
+    }
+
+    /// Drop a session's connection and close its forwards
+    pub fn remove(&self, session_id: &str) {
+        self.connections.remove(session_id);
+        self.forwards
+            .retain(|_, forward| forward.info.session_id != session_id);
+    }
+
+    /// The remote forward of a session the server listens for on `port`
+    fn route(&self, session_id: &str, port: u32) -> Option<(Target, Arc<ForwardStats>)> {
+        self.forwards
+            .iter()
+            .find(|forward| {
+                forward.info.session_id == session_id
+                    && forward
+                        .bound
+                        .as_ref()
+                        .is_some_and(|(_, bound)| *bound == port)
+            })
+            .and_then(|forward| Some((target(&forward.info)?, Arc::clone(&forward.stats))))
+    }
+
+    /// Carry a connection the server accepted on a remote forward to its
+    /// target; one nothing was requested for is closed
+    pub(crate) fn forwarded(
+        &self,
+        session_id: &str,
+        channel: Channel<client::Msg>,
+        connected_port: u32,
+        originator: String,
+    ) {
+        let route = self.route(session_id, connected_port);
+        let session_id = session_id.to_string();
+        tokio::spawn(async move {
+            let Some(((id, host, port), stats)) = route else {
+                warn!(session_id = %session_id, port = connected_port, "Server forwarded a port nothing was requested for");
+                let _ = channel.close().await;
+                return;
+            };
+            match TcpStream::connect((host.as_str(), port)).await {
+                Ok(socket) => carry(&id, &originator, socket, channel, &stats, true).await,
+                Err(e) => {
+                    warn!(forward_id = %id, peer = %originator, error = %e, "Remote forward target unreachable");
+                    stats.failed();
+                    let _ = channel.close().await;
+                }
+            }
+        });
+    }
+}
+
+/// Forward id, target host and target port
+type Target = (String, String, u16);
+
+fn target(info: &ForwardInfo) -> Option<Target> {
+    Some((
+        info.id.clone(),
+        info.target_host.clone()?,
+        info.target_port?,
+    ))
+}
+
+/// Listen on `bind:local_port` (loopback by default, port 0 picks one) and
+/// forward each connection to `target_host:target_port` over the session
+pub async fn open_local_forward(
+    manager: &SessionManager,
+    session_id: &str,
+    bind: Option<&str>,
+    local_port: u16,
+    target_host: &str,
+    target_port: u16,
+) -> Result<ForwardInfo, SessionError> {
+    let target = checked_target(target_host, target_port)?;
+    listen(manager, session_id, bind, local_port, Some(target)).await
+}
+
+/// Listen on `bind:local_port` (loopback by default, port 0 picks one) as
+/// a SOCKS5 proxy whose connections go out over the session
+pub async fn open_dynamic_forward(
+    manager: &SessionManager,
+    session_id: &str,
+    bind: Option<&str>,
+    local_port: u16,
+) -> Result<ForwardInfo, SessionError> {
+    listen(manager, session_id, bind, local_port, None).await
+}
+
+/// A local or, without a target, dynamic forward
+async fn listen(
+    manager: &SessionManager,
+    session_id: &str,
+    bind: Option<&str>,
+    local_port: u16,
+    target: Option<(String, u16)>,
+) -> Result<ForwardInfo, SessionError> {
+    let connection = session_connection(manager, session_id)?;
+    let listener = TcpListener::bind((bind.unwrap_or(DEFAULT_BIND), local_port)).await?;
+    let info = ForwardInfo {
+        kind: if target.is_some() {
+            ForwardKind::Local
+        } else {
+            ForwardKind::Dynamic
+        },
+        ..new_info(session_id, listener.local_addr()?.to_string(), target)
+    };
+    opened(&info);
+
+    let stats = Arc::new(ForwardStats::default());
+    let task = tokio::spawn(serve_local(
+        listener,
+        connection,
+        info.clone(),
+        Arc::clone(&stats),
+    ));
+    manager.tunnels().forwards.insert(
+        info.id.clone(),
+        Forward {
+            info: info.clone(),
+            stats,
+            task: Some(task),
+            bound: None,
+        },
+    );
+    Ok(info)
+}
+
+/// Ask the server to listen on `bind:remote_port` (its loopback by
+/// default, port 0 lets it pick) and carry each connection it accepts to
+/// `target_host:target_port` from here
+pub async fn open_remote_forward(
+    manager: &SessionManager,
+    session_id: &str,
+    bind: Option<&str>,
+    remote_port: u16,
+    target_host: &str,
+    target_port: u16,
+) -> Result<ForwardInfo, SessionError> {
+    let target = checked_target(target_host, target_port)?;
+    let connection = session_connection(manager, session_id)?;
+
+    let bind = bind.unwrap_or(DEFAULT_REMOTE_BIND);
+    let picked = connection
+        .lock()
+        .await
+        .tcpip_forward(bind, remote_port as u32)
+        .await
+        .map_err(|e| {
+            SessionError::ChannelError(format!(
+                "server refused to listen on {}:{}: {}",
+                bind, remote_port, e
+            ))
+        })?;
+    // The server only reports the port when it picked one
+    let port = if remote_port == 0 {
+        picked
+    } else {
+        remote_port as u32
+    };
+    let info = ForwardInfo {
+        kind: ForwardKind::Remote,
+        ..new_info(session_id, format!("{}:{}", bind, port), Some(target))
+    };
+    opened(&info);
+
+    manager.tunnels().forwards.insert(
+        info.id.clone(),
+        Forward {
+            info: info.clone(),
+            stats: Arc::default(),
+            task: None,
+            bound: Some((bind.to_string(), port)),
+        },
+    );
+    Ok(info)
+}
+
+fn checked_target(host: &str, port: u16) -> Result<(String, u16), SessionError> {
+    let host = host.trim();
+    if host.is_empty() || port == 0 {
+        return Err(SessionError::InvalidConfig(
+            "forward needs a target host and port".to_string(),
+        ));
+    }
+    Ok((host.to_string(), port))
+}
+
+fn session_connection(
+    manager: &SessionManager,
+    session_id: &str,
+) -> Result<SshConnection, SessionError> {
+    manager
+        .get(session_id)
+        .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+    manager.tunnels().connection(session_id)
+}
+
+fn new_info(session_id: &str, listen: String, target: Option<(String, u16)>) -> ForwardInfo {
+    let (target_host, target_port) = target.unzip();
+    ForwardInfo {
+        id: Uuid::new_v4().to_string(),
+        session_id: session_id.to_string(),
+        kind: ForwardKind::Local,
+        listen,
+        target_host,
+        target_port,
+        active: 0,
+        total: 0,
+        failed: 0,
+        bytes_to_target: 0,
+        bytes_from_target: 0,
+    }
+}
+
+fn opened(info: &ForwardInfo) {
+    info!(
+        forward_id = %info.id,
+        session_id = %info.session_id,
+        kind = ?info.kind,
+        listen = %info.listen,
+        target = ?target(info).map(|(_, host, port)| format!("{}:{}", host, port)),
+        "Forward opened"
+    );
+}
+
+/// Accept loop of a local or dynamic forward
+async fn serve_local(
+    listener: TcpListener,
+    connection: SshConnection,
+    info: ForwardInfo,
+    stats: Arc<ForwardStats>,
+) {
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(forward_id = %info.id, error = %e, "Forward stopped accepting");
+                return;
+            }
+        };
+        let (connection, stats) = (Arc::clone(&connection), Arc::clone(&stats));
+        let (id, target) = (info.id.clone(), target(&info));
+        tokio::spawn(async move {
+            if let Err(e) = accepted(&id, socket, peer, target, connection, &stats).await {
+                debug!(forward_id = %id, peer = %peer, error = %e, "Forwarded connection failed");
+                stats.failed();
+            }
+        });
+    }
+}
+
+/// Open a channel for a connection accepted locally and carry it; without
+/// a fixed target, the SOCKS client names one first
+async fn accepted(
+    id: &str,
+    mut socket: TcpStream,
+    peer: std::net::SocketAddr,
+    target: Option<Target>,
+    connection: SshConnection,
+    stats: &ForwardStats,
+) -> io::Result<()> {
+    let socks = target.is_none();
+    let (host, port) = match target {
+        Some((_, host, port)) => (host, port),
+        None => tokio::time::timeout(SOCKS_TIMEOUT, socks_request(&mut socket))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no SOCKS request"))??,
+    };
+    let channel = connection
+        .lock()
+        .await
+        .channel_open_direct_tcpip(
+            host.as_str(),
+            port as u32,
+            peer.ip().to_string(),
+            peer.port() as u32,
+        )
+        .await;
+    // The forwarded connection must not keep the session's alive
+    drop(connection);
+    let channel = match channel {
+        Ok(channel) => channel,
+        Err(e) => {
+            if socks {
+                socks_reply(&mut socket, SOCKS_REFUSED).await?;
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("server refused {}:{}: {}", host, port, e),
+            ));
+        }
+    };
+    if socks {
+        socks_reply(&mut socket, SOCKS_SUCCEEDED).await?;
+    }
+    carry(id, &peer.to_string(), socket, channel, stats, false).await;
+    Ok(())
+}
+
+/// Read a SOCKS5 greeting and CONNECT request (RFC 1928) and return the
+/// target. Requests that can't be served are answered before failing.
+async fn socks_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> io::Result<(String, u16)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut greeting = [0u8; 2];
+    stream.read_exact(&mut greeting).await?;
+    if greeting[0] != SOCKS_VERSION {
+        return Err(invalid("not a SOCKS5 client"));
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS_NO_AUTH) {
+        stream.write_all(&[SOCKS_VERSION, SOCKS_NO_METHOD]).await?;
+        return Err(invalid("SOCKS client requires authentication"));
+    }
+    stream.write_all(&[SOCKS_VERSION, SOCKS_NO_AUTH]).await?;
+
+    // VER CMD RSV ATYP
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    if request[0] != SOCKS_VERSION {
+        return Err(invalid("not a SOCKS5 request"));
+    }
+    let host = match request[3] {
+        0x01 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        0x03 => {
+            let mut name = vec![0u8; stream.read_u8().await? as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| invalid("SOCKS host name is not UTF-8"))?
+        }
+        0x04 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        _ => {
+            socks_reply(stream, SOCKS_BAD_ADDRESS).await?;
+            return Err(invalid("unknown SOCKS address type"));
+        }
+    };
+    let port = stream.read_u16().await?;
+    if request[1] != SOCKS_CONNECT {
+        socks_reply(stream, SOCKS_BAD_COMMAND).await?;
+        return Err(invalid("only SOCKS CONNECT is supported"));
+    }
+    Ok((host, port))
+}
+
+/// Reply to a SOCKS5 request; the bound address isn't known, so it's zero
+async fn socks_reply<S: AsyncWrite + Unpin>(stream: &mut S, code: u8) -> io::Result<()> {
+    stream
+        .write_all(&[SOCKS_VERSION, code, 0, 0x01, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+/// Copy between a TCP connection and a channel until either side closes.
+/// `socket_is_target` for remote forwards, where the socket goes to the
+/// target rather than coming from a client.
+async fn carry(
+    id: &str,
+    peer: &str,
+    mut socket: TcpStream,
+    channel: Channel<client::Msg>,
+    stats: &ForwardStats,
+    socket_is_target: bool,
+) {
+    stats.active.fetch_add(1, Ordering::Relaxed);
+    stats.total.fetch_add(1, Ordering::Relaxed);
+    let mut stream = channel.into_stream();
+    match tokio::io::copy_bidirectional(&mut socket, &mut stream).await {
+        Ok((from_socket, from_channel)) => {
+            let (to_target, from_target) = if socket_is_target {
+                (from_channel, from_socket)
+            } else {
+                (from_socket, from_channel)
+            };
+            stats
+                .bytes_to_target
+                .fetch_add(to_target, Ordering::Relaxed);
+            stats
+                .bytes_from_target
+                .fetch_add(from_target, Ordering::Relaxed);
+            debug!(forward_id = %id, peer = %peer, to_target = to_target, from_target = from_target, "Forwarded connection closed");
+        }
+        Err(e) => {
+            debug!(forward_id = %id, peer = %peer, error = %e, "Forwarded connection failed");
+        }
+    }
+    stats.active.fetch_sub(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forward(session_id: &str, listen: &str) -> Forward {
+        Forward {
+            info: new_info(
+                session_id,
+                listen.to_string(),
+                Some(("192.168.1.1".to_string(), 443)),
+            ),
+            stats: Arc::default(),
+            task: Some(tokio::spawn(std::future::pending())),
+            bound: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forwards_by_session() {
+        let tunnels = TunnelRegistry::default();
+        for (session_id, listen) in [
+            ("s2", "127.0.0.1:8443"),
+            ("s1", "127.0.0.1:9443"),
+            ("s1", "127.0.0.1:8080"),
+        ] {
+            let forward = forward(session_id, listen);
+            tunnels.forwards.insert(forward.info.id.clone(), forward);
+        }
+        assert!(tunnels.connection("s1").is_err());
+
+        let mut remote = forward("s1", "localhost:2121");
+        remote.info.kind = ForwardKind::Remote;
+        remote.task = None;
+        remote.bound = Some(("localhost".to_string(), 2121));
+        tunnels.forwards.insert(remote.info.id.clone(), remote);
+        let ((_, host, port), _) = tunnels.route("s1", 2121).unwrap();
+        assert_eq!((host.as_str(), port), ("192.168.1.1", 443));
+        assert!(tunnels.route("s2", 2121).is_none());
+        assert!(tunnels.route("s1", 8080).is_none());
+
+        let listed: Vec<String> = tunnels.list(None).into_iter().map(|f| f.listen).collect();
+        assert_eq!(
+            listed,
+            [
+                "127.0.0.1:8080",
+                "127.0.0.1:9443",
+                "localhost:2121",
+                "127.0.0.1:8443"
+            ]
+        );
+        let s1 = tunnels.list(Some("s1"));
+        assert_eq!(s1.len(), 3);
+
+        assert!(tunnels.close(&s1[0].id).await);
+        assert!(!tunnels.close(&s1[0].id).await);
+        tunnels.remove("s1");
+        let left = tunnels.list(None);
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].session_id, "s2");
+    }
+
+    #[tokio::test]
+    async fn test_socks_request() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let mut request = vec![5, 2, 0x02, 0x00, 5, 1, 0, 3, 10];
+        request.extend_from_slice(b"router.lab");
+        request.extend_from_slice(&443u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+        let target = socks_request(&mut server).await.unwrap();
+        assert_eq!(target, ("router.lab".to_string(), 443));
+        let mut reply = [0u8; 2];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [5, 0]);
+
+        // BIND to 10.0.0.1:80 is refused with "command not supported"
+        let (mut client, mut server) = tokio::io::duplex(256);
+        client
+            .write_all(&[5, 1, 0, 5, 2, 0, 1, 10, 0, 0, 1, 0, 80])
+            .await
+            .unwrap();
+        assert!(socks_request(&mut server).await.is_err());
+        let mut reply = [0u8; 12];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply[..4], &[5, 0, 5, SOCKS_BAD_COMMAND]);
+
+        let (mut client, mut server) = tokio::io::duplex(256);
+        client.write_all(&[5, 1, 0x02]).await.unwrap();
+        assert!(socks_request(&mut server).await.is_err());
+        let mut reply = [0u8; 2];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [5, SOCKS_NO_METHOD]);
+    }
+}
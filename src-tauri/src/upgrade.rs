@@ -0,0 +1,698 @@
+//! Firmware upgrade workflow.
+//!
+//! A job walks one device through pre-checks, pulling the image from the
+//! built-in TFTP server, setting it as startup software, rebooting, waiting
+//! for the device to come back and post-checks, emitting `upgrade:{job_id}`
+//! events per step. The commands come from the device dialect's `Plan`;
+//! confirmations such as `Continue? [Y/N]:` are answered yes.
+//!
+//! An upgrade is a job (see `jobs.rs`) and can pause before any step until it
+//! is resumed or cancelled. Once the startup software was changed, a failure
+//! reports the commands that boot the previous image again; they are hints
+//! and never run on their own.
+
+use crate::batch::DeviceSession;
+use crate::command::{self, OutputWatcher, CONFIRM_RE, DEFAULT_COMMAND_TIMEOUT, RESPONSE_RE};
+use crate::jobs::{JobControl, JobKind, JobRegistry};
+use crate::session::{DeviceDialect, SessionConfig, SessionError, SessionManager};
+use crate::tftp::{TftpServer, TFTP_PORT};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+pub const DEFAULT_TRANSFER_TIMEOUT: Duration = Duration::from_secs(1800);
+
+/// Time from the reboot to the device accepting logins again
+pub const DEFAULT_REBOOT_TIMEOUT: Duration = Duration::from_secs(900);
+
+/// Time the device gets to go down before reconnecting is tried
+const REBOOT_GRACE: Duration = Duration::from_secs(30);
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time for the reboot command to be confirmed and the connection to drop
+const REBOOT_COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Grace for the TFTP transfer to finish after the copy command returned
+const TRANSFER_SETTLE: Duration = Duration::from_secs(5);
+
+/// Transfer progress is emitted in this many steps
+const PROGRESS_STEPS: u64 = 100;
+
+#[derive(Error, Debug)]
+pub enum UpgradeError {
+    #[error("Upgrades are not supported for {0:?} devices")]
+    Unsupported(DeviceDialect),
+    #[error("Invalid upgrade: {0}")]
+    Invalid(String),
+    #[error("Check failed: {0}")]
+    CheckFailed(String),
+    #[error("Upgrade aborted")]
+    Aborted,
+    #[error("{0}")]
+    Session(#[from] SessionError),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl From<UpgradeError> for String {
+    fn from(err: UpgradeError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    PreCheck,
+    Transfer,
+    SetStartup,
+    Reboot,
+    WaitReachable,
+    PostCheck,
+}
+
+const STEPS: [Step; 6] = [
+    Step::PreCheck,
+    Step::Transfer,
+    Step::SetStartup,
+    Step::Reboot,
+    Step::WaitReachable,
+    Step::PostCheck,
+];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UpgradeOptions {
+    /// Local image file, served to the device over TFTP under its file name
+    pub image_path: String,
+    /// Address the device reaches this machine at; taken from the route to
+    /// the device when None
+    pub tftp_address: Option<IpAddr>,
+    /// `display version` after the reboot must contain this
+    pub expect_version: Option<String>,
+    /// Pause the job before these steps, until `resume_job`
+    pub pause_before: Vec<Step>,
+    pub transfer_timeout_secs: Option<u64>,
+    pub reboot_timeout_secs: Option<u64>,
+}
+
+/// Emitted as `upgrade:{job_id}` events
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UpgradeEvent {
+    StepStarted {
+        step: Step,
+    },
+    /// Transcript of the step's commands
+    StepFinished {
+        step: Step,
+        output: String,
+    },
+    /// Waiting for `resume_job` before `step`
+    Paused {
+        step: Step,
+    },
+    Transfer {
+        sent: u64,
+        total: u64,
+    },
+    /// `rollback` restores the previous startup software, once it was changed
+    Failed {
+        step: Step,
+        error: String,
+        rollback: Vec<String>,
+    },
+    Finished {
+        duration_ms: u64,
+    },
+}
+
+/// Upgrade commands of one dialect. Commands take `{server}` (TFTP
+/// address), `{file}` (image name) and, for rollback, `{previous}`.
+struct Plan {
+    pre_checks: &'static [&'static str],
+    transfer: &'static str,
+    set_startup: &'static [&'static str],
+    reboot: &'static str,
+    post_checks: &'static [&'static str],
+    /// The running image in check output, and how `{previous}` is built
+    /// from its captures
+    current_image: (&'static str, &'static str),
+    /// The running image is reported under the upgraded file's name, so
+    /// post-checks can verify it
+    verify_image: bool,
+    /// Free storage in the `dir` output, and its unit in bytes
+    free_space: (&'static str, u64),
+    rollback: &'static [&'static str],
+}
+
+fn plan(dialect: DeviceDialect) -> Result<Plan, UpgradeError> {
+    match dialect {
+        DeviceDialect::Vrp => Ok(Plan {
+            pre_checks: &["display version", "display startup", "dir"],
+            transfer: "tftp {server} get {file}",
+            set_startup: &["startup system-software {file}"],
+            reboot: "reboot",
+            post_checks: &["display version", "display startup"],
+            current_image: (r"(?m)^\s*Startup system software:\s+(\S+)", "$1"),
+            verify_image: true,
+            free_space: (r"\(([\d,]+) KB free\)", 1024),
+            rollback: &["startup system-software {previous}", "reboot"],
+        }),
+        // An .ipe package unpacks into boot and system images, so the
+        // running images never carry the package name
+        DeviceDialect::Comware => Ok(Plan {
+            pre_checks: &["display version", "display boot-loader", "dir"],
+            transfer: "tftp {server} get {file}",
+            set_startup: &["boot-loader file flash:/{file} all main"],
+            reboot: "reboot",
+            post_checks: &["display version", "display boot-loader"],
+            current_image: (
+                r"Current software images:\s*\n\s*(\S+)\s*\n\s*(\S+)",
+                "boot $1 system $2",
+            ),
+            verify_image: false,
+            free_space: (r"\(([\d,]+) KB free\)", 1024),
+            rollback: &["boot-loader file {previous} all main", "reboot"],
+        }),
+        DeviceDialect::Ios => Ok(Plan {
+            pre_checks: &["show version", "dir flash:"],
+            transfer: "copy tftp://{server}/{file} flash:{file}",
+            set_startup: &[
+                "configure terminal",
+                "no boot system",
+                "boot system flash:{file}",
+                "end",
+                "write memory",
+            ],
+            reboot: "reload",
+            post_checks: &["show version"],
+            current_image: (r#"System image file is "([^"]+)""#, "$1"),
+            verify_image: true,
+            free_space: (r"\((\d+) bytes free\)", 1),
+            rollback: &[
+                "configure terminal",
+                "no boot system",
+                "boot system {previous}",
+                "end",
+                "write memory",
+                "reload",
+            ],
+        }),
+        other => Err(UpgradeError::Unsupported(other)),
+    }
+}
+
+impl Plan {
+    fn current_image(&self, output: &str) -> Option<String> {
+        let (pattern, template) = self.current_image;
+        let captures = Regex::new(pattern).unwrap().captures(output)?;
+        let mut image = String::new();
+        captures.expand(template, &mut image);
+        Some(image)
+    }
+
+    fn free_bytes(&self, output: &str) -> Option<u64> {
+        let (pattern, unit) = self.free_space;
+        let captures = Regex::new(pattern).unwrap().captures(output)?;
+        let digits: String = captures[1].chars().filter(char::is_ascii_digit).collect();
+        digits.parse::<u64>().ok().map(|n| n.saturating_mul(unit))
+    }
+}
+
+fn fill(template: &str, server: &str, file: &str, previous: &str) -> String {
+    template
+        .replace("{server}", server)
+        .replace("{file}", file)
+        .replace("{previous}", previous)
+}
+
+/// Check the options and start the upgrade as a job; returns its id.
+/// Cancelling the job stops it at its next pause point or step boundary;
+/// a step already running (a transfer, a reboot) is not interrupted.
+pub fn start_upgrade(
+    jobs: &Arc<JobRegistry>,
+    manager: Arc<SessionManager>,
+    config: SessionConfig,
+    options: UpgradeOptions,
+) -> Result<String, UpgradeError> {
+    let plan = plan(config.dialect)?;
+    let image = PathBuf::from(&options.image_path);
+    if !image.is_file() {
+        return Err(UpgradeError::Invalid(format!(
+            "{} is not a file",
+            options.image_path
+        )));
+    }
+    let file = image
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if file.is_empty() || file.contains(char::is_whitespace) {
+        return Err(UpgradeError::Invalid(
+            "the image file name must not contain spaces".to_string(),
+        ));
+    }
+    if options.tftp_address.is_none() && !config.jump_hosts.is_empty() {
+        return Err(UpgradeError::Invalid(
+            "tftp_address is needed for devices behind jump hosts".to_string(),
+        ));
+    }
+
+    let control = jobs.start(JobKind::Upgrade, format!("{} to {}", config.host, file));
+    let job_id = control.id().to_string();
+    let mut job = Job {
+        id: job_id.clone(),
+        manager,
+        config,
+        options,
+        plan,
+        image,
+        file,
+        control,
+        rollback: Vec::new(),
+    };
+    tokio::spawn(async move {
+        let result = job.run().await;
+        job.control.finish(result);
+    });
+    Ok(job_id)
+}
+
+struct Job {
+    id: String,
+    manager: Arc<SessionManager>,
+    config: SessionConfig,
+    options: UpgradeOptions,
+    plan: Plan,
+    image: PathBuf,
+    /// Image file name, as the device stores it
+    file: String,
+    control: JobControl,
+    /// Restores the previous image, once the startup software was changed
+    rollback: Vec<String>,
+}
+
+impl Job {
+    async fn run(&mut self) -> Result<String, String> {
+        let started = Instant::now();
+        info!(job_id = %self.id, host = %self.config.host, image = %self.file, "Starting upgrade");
+        let mut session = None;
+        let result = self.run_steps(&mut session).await;
+        if let Some(session) = session {
+            session.close(&self.manager).await;
+        }
+        match result {
+            Ok(()) => {
+                info!(job_id = %self.id, "Upgrade finished");
+                self.emit(UpgradeEvent::Finished {
+                    duration_ms: started.elapsed().as_millis() as u64,
+                });
+                Ok(format!("running {}", self.file))
+            }
+            Err((step, e)) => {
+                warn!(job_id = %self.id, step = ?step, error = %e, "Upgrade failed");
+                self.emit(UpgradeEvent::Failed {
+                    step,
+                    error: e.to_string(),
+                    rollback: self.rollback.clone(),
+                });
+                Err(format!("{:?}: {}", step, e))
+            }
+        }
+    }
+
+    async fn run_steps(
+        &mut self,
+        session: &mut Option<DeviceSession>,
+    ) -> Result<(), (Step, UpgradeError)> {
+        let mut previous = None;
+        for (index, step) in STEPS.into_iter().enumerate() {
+            self.control.progress(
+                index as u64,
+                STEPS.len() as u64,
+                Some(format!("{:?}", step)),
+            );
+            self.checkpoint(step).await.map_err(|e| (step, e))?;
+            self.emit(UpgradeEvent::StepStarted { step });
+            let output = match step {
+                Step::PreCheck => {
+                    let opened = DeviceSession::open(&self.manager, &self.config)
+                        .await
+                        .map_err(|e| (step, e.into()))?;
+                    let id = opened.id.clone();
+                    *session = Some(opened);
+                    let output = self.pre_check(&id).await.map_err(|e| (step, e))?;
+                    previous = self.plan.current_image(&output);
+                    output
+                }
+                Step::Transfer => {
+                    let id = session_id(session);
+                    self.transfer(&id).await.map_err(|e| (step, e))?
+                }
+                Step::SetStartup => {
+                    let id = session_id(session);
+                    if let Some(previous) = &previous {
+                        self.rollback = self
+                            .plan
+                            .rollback
+                            .iter()
+                            .map(|c| fill(c, "", &self.file, previous))
+                            .collect();
+                    }
+                    self.run_commands(&id, self.plan.set_startup, DEFAULT_COMMAND_TIMEOUT)
+                        .await
+                        .map_err(|e| (step, e))?
+                }
+                Step::Reboot => {
+                    let rebooting = session.take().expect("session open after pre-checks");
+                    self.reboot(&rebooting.id).await.map_err(|e| (step, e))?;
+                    rebooting.close(&self.manager).await;
+                    String::new()
+                }
+                Step::WaitReachable => {
+                    *session = Some(self.wait_reachable().await.map_err(|e| (step, e))?);
+                    String::new()
+                }
+                Step::PostCheck => {
+                    let id = session_id(session);
+                    self.post_check(&id).await.map_err(|e| (step, e))?
+                }
+            };
+            self.emit(UpgradeEvent::StepFinished { step, output });
+        }
+        Ok(())
+    }
+
+    /// Honour a cancel, and pause the job before `step` if it was asked to
+    async fn checkpoint(&self, step: Step) -> Result<(), UpgradeError> {
+        self.control
+            .checkpoint()
+            .await
+            .map_err(|_| UpgradeError::Aborted)?;
+        if !self.options.pause_before.contains(&step) {
+            return Ok(());
+        }
+        info!(job_id = %self.id, step = ?step, "Upgrade paused");
+        self.control.pause();
+        self.emit(UpgradeEvent::Paused { step });
+        self.control
+            .checkpoint()
+            .await
+            .map_err(|_| UpgradeError::Aborted)
+    }
+
+    async fn pre_check(&self, session_id: &str) -> Result<String, UpgradeError> {
+        let output = self
+            .run_commands(session_id, self.plan.pre_checks, DEFAULT_COMMAND_TIMEOUT)
+            .await?;
+        let size = std::fs::metadata(&self.image)?.len();
+        if let Some(free) = self.plan.free_bytes(&output) {
+            if free < size {
+                return Err(UpgradeError::CheckFailed(format!(
+                    "{} bytes free on the device, the image needs {}",
+                    free, size
+                )));
+            }
+        }
+        Ok(output)
+    }
+
+    async fn transfer(&self, session_id: &str) -> Result<String, UpgradeError> {
+        let server_ip = match self.options.tftp_address {
+            Some(ip) => ip,
+            None => local_address(&self.config.host, self.config.port).await?,
+        };
+        let server = TftpServer::bind(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), TFTP_PORT),
+            &self.image,
+        )
+        .await
+        .map_err(|e| {
+            UpgradeError::Invalid(format!("cannot serve TFTP on port {}: {}", TFTP_PORT, e))
+        })?;
+        info!(job_id = %self.id, addr = %server.local_addr()?, server = %server_ip, "Serving image over TFTP");
+        let total = std::fs::metadata(&self.image)?.len();
+        let timeout = self
+            .options
+            .transfer_timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TRANSFER_TIMEOUT);
+        let command = fill(
+            self.plan.transfer,
+            &server_ip.to_string(),
+            server.file_name(),
+            "",
+        );
+
+        let mut reported = 0;
+        let serving = server.serve_once(|sent| {
+            if sent == total || sent - reported >= total / PROGRESS_STEPS {
+                reported = sent;
+                self.emit(UpgradeEvent::Transfer { sent, total });
+            }
+        });
+        let copying = command::run_answering(&self.manager, session_id, &command, &[], timeout);
+        tokio::pin!(serving, copying);
+        let (sent, output) = tokio::select! {
+            sent = &mut serving => (sent?, copying.await?),
+            output = &mut copying => {
+                let output = output?;
+                match tokio::time::timeout(TRANSFER_SETTLE, serving).await {
+                    Ok(sent) => (sent?, output),
+                    Err(_) => {
+                        return Err(UpgradeError::CheckFailed(format!(
+                            "the device did not fetch the image: {}",
+                            output.lines().last().unwrap_or_default().trim()
+                        )));
+                    }
+                }
+            }
+        };
+        check_accepted(&output)?;
+        if sent != total {
+            return Err(UpgradeError::CheckFailed(format!(
+                "sent {} of {} bytes",
+                sent, total
+            )));
+        }
+        Ok(format!("{}\n{}\n", command, output))
+    }
+
+    /// Send the reboot command and answer its confirmations until the
+    /// device drops the connection
+    async fn reboot(&self, session_id: &str) -> Result<(), UpgradeError> {
+        let mut watcher = OutputWatcher::new(&self.manager, session_id)?;
+        self.manager
+            .send_data(session_id, format!("{}\r", self.plan.reboot).into_bytes())
+            .await?;
+        let deadline = Instant::now() + REBOOT_COMMAND_TIMEOUT;
+        let mut confirmed = false;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match watcher.expect(&RESPONSE_RE, remaining).await {
+                Ok(expected) if CONFIRM_RE.is_match(&expected.matched) => {
+                    confirmed = true;
+                    self.manager
+                        .send_data(
+                            session_id,
+                            command::confirmation_reply(&expected.matched)
+                                .as_bytes()
+                                .to_vec(),
+                        )
+                        .await?;
+                }
+                Ok(expected) => {
+                    return Err(UpgradeError::CheckFailed(format!(
+                        "the device did not reboot: {}",
+                        expected.before.trim()
+                    )));
+                }
+                Err(SessionError::ChannelError(_)) => return Ok(()),
+                // Some devices keep the connection open while going down
+                Err(SessionError::Timeout(_)) if confirmed => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    async fn wait_reachable(&self) -> Result<DeviceSession, UpgradeError> {
+        let timeout = self
+            .options
+            .reboot_timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_REBOOT_TIMEOUT);
+        let deadline = Instant::now() + timeout;
+        tokio::time::sleep(REBOOT_GRACE).await;
+        loop {
+            match DeviceSession::open(&self.manager, &self.config).await {
+                Ok(session) => return Ok(session),
+                Err(e) if Instant::now() + RECONNECT_INTERVAL < deadline => {
+                    info!(job_id = %self.id, error = %e, "Device not back yet");
+                    tokio::time::sleep(RECONNECT_INTERVAL).await;
+                }
+                Err(e) => {
+                    return Err(UpgradeError::CheckFailed(format!(
+                        "the device did not come back within {}s: {}",
+                        timeout.as_secs(),
+                        e
+                    )));
+                }
+            }
+        }
+    }
+
+    async fn post_check(&self, session_id: &str) -> Result<String, UpgradeError> {
+        let output = self
+            .run_commands(session_id, self.plan.post_checks, DEFAULT_COMMAND_TIMEOUT)
+            .await?;
+        if let Some(version) = &self.options.expect_version {
+            if !output.contains(version.as_str()) {
+                return Err(UpgradeError::CheckFailed(format!(
+                    "version {} not reported",
+                    version
+                )));
+            }
+        }
+        if self.plan.verify_image {
+            match self.plan.current_image(&output) {
+                Some(image) if image.ends_with(&self.file) => {}
+                image => {
+                    return Err(UpgradeError::CheckFailed(format!(
+                        "running {} instead of {}",
+                        image.as_deref().unwrap_or("an unknown image"),
+                        self.file
+                    )));
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Run plan commands in order, stopping at the first the CLI rejects;
+    /// returns the transcript
+    async fn run_commands(
+        &self,
+        session_id: &str,
+        commands: &[&str],
+        timeout: Duration,
+    ) -> Result<String, UpgradeError> {
+        let mut transcript = String::new();
+        for command in commands {
+            let command = fill(command, "", &self.file, "");
+            let output =
+                command::run_answering(&self.manager, session_id, &command, &[], timeout).await?;
+            transcript.push_str(&format!("{}\n{}\n", command, output));
+            check_accepted(&output)?;
+        }
+        Ok(transcript)
+    }
+
+    fn emit(&self, event: UpgradeEvent) {
+        let name = format!("upgrade:{}", self.id);
+        if let Err(e) = self.manager.app_handle().emit(&name, &event) {
+            warn!(job_id = %self.id, error = %e, "Failed to emit upgrade event");
+        }
+    }
+}
+
+fn session_id(session: &Option<DeviceSession>) -> String {
+    session
+        .as_ref()
+        .map(|s| s.id.clone())
+        .expect("session open after pre-checks")
+}
+
+fn check_accepted(output: &str) -> Result<(), UpgradeError> {
+    match command::rejection(output) {
+        Some(line) => Err(UpgradeError::CheckFailed(line.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// The local address packets to the device leave from
+async fn local_address(host: &str, port: u16) -> Result<IpAddr, UpgradeError> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)).await?;
+    socket.connect((host, port)).await?;
+    Ok(socket.local_addr()?.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plans() {
+        let vrp = plan(DeviceDialect::Vrp).unwrap();
+        let startup = "\
+MainBoard:
+  Configured startup system software:        cfcard:/V800R011.cc
+  Startup system software:                   cfcard:/V800R011.cc
+  Next startup system software:              cfcard:/V800R011.cc
+";
+        assert_eq!(
+            vrp.current_image(startup).as_deref(),
+            Some("cfcard:/V800R011.cc")
+        );
+        let dir = "Directory of cfcard:/\n\n1,961,936 KB total (1,057,696 KB free)";
+        assert_eq!(vrp.free_bytes(dir), Some(1_057_696 * 1024));
+        let rollback: Vec<String> = vrp
+            .rollback
+            .iter()
+            .map(|c| fill(c, "", "V800R021.cc", "cfcard:/V800R011.cc"))
+            .collect();
+        assert_eq!(
+            rollback,
+            ["startup system-software cfcard:/V800R011.cc", "reboot"]
+        );
+        assert_eq!(
+            fill(vrp.transfer, "10.0.0.5", "V800R021.cc", ""),
+            "tftp 10.0.0.5 get V800R021.cc"
+        );
+
+        let comware = plan(DeviceDialect::Comware).unwrap();
+        let boot_loader = "\
+Software images on slot 1:
+Current software images:
+  flash:/s5820-cmw710-boot-r2418.bin
+  flash:/s5820-cmw710-system-r2418.bin
+Main startup software images:
+";
+        assert_eq!(
+            comware.current_image(boot_loader).as_deref(),
+            Some("boot flash:/s5820-cmw710-boot-r2418.bin system flash:/s5820-cmw710-system-r2418.bin")
+        );
+
+        let ios = plan(DeviceDialect::Ios).unwrap();
+        assert_eq!(
+            ios.current_image(r#"System image file is "flash:c2900-15.6.bin""#)
+                .as_deref(),
+            Some("flash:c2900-15.6.bin")
+        );
+        assert_eq!(
+            ios.free_bytes("1621966848 bytes total (1390485504 bytes free)"),
+            Some(1_390_485_504)
+        );
+        assert!(matches!(
+            plan(DeviceDialect::Linux),
+            Err(UpgradeError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_accepted() {
+        assert!(check_accepted("Info: Transfer succeeded.").is_ok());
+        assert!(check_accepted("\nError: File can't be found.\n").is_err());
+        assert!(check_accepted("% Invalid input detected at '^' marker.").is_err());
+    }
+}
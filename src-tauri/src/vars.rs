@@ -0,0 +1,156 @@
+//! Variables shared between automation steps.
+//!
+//! Each scope is a flat map of names to strings. A session scope outlives
+//! the scripts and rules that fill it, so a serial number captured by one
+//! script can be used by the next one or by an automation rule's
+//! `{var}` placeholders; a job scope holds one script run's variables for
+//! inspection after it ended.
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Job scopes kept after their job; older ones are dropped first
+const MAX_JOB_SCOPES: usize = 100;
+
+/// Variables per scope
+const MAX_VARIABLES: usize = 1000;
+
+#[derive(Error, Debug)]
+pub enum VariableError {
+    #[error("Invalid variable name: {0}")]
+    InvalidName(String),
+    #[error("Too many variables (max {0})")]
+    TooMany(usize),
+}
+
+impl From<VariableError> for String {
+    fn from(err: VariableError) -> String {
+        err.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum Scope {
+    Session(String),
+    Job(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VariableScope {
+    pub scope: Scope,
+    pub variables: BTreeMap<String, String>,
+}
+
+#[derive(Default)]
+pub struct VariableStore {
+    scopes: DashMap<Scope, BTreeMap<String, String>>,
+    /// Job scopes, oldest first
+    jobs: Mutex<VecDeque<String>>,
+}
+
+impl VariableStore {
+    pub fn set(&self, scope: &Scope, name: &str, value: String) -> Result<(), VariableError> {
+        if !is_valid_name(name) {
+            return Err(VariableError::InvalidName(name.to_string()));
+        }
+        let new_scope = !self.scopes.contains_key(scope);
+        {
+            let mut vars = self.scopes.entry(scope.clone()).or_default();
+            if vars.len() >= MAX_VARIABLES && !vars.contains_key(name) {
+                return Err(VariableError::TooMany(MAX_VARIABLES));
+            }
+            vars.insert(name.to_string(), value);
+        }
+        if let (true, Scope::Job(id)) = (new_scope, scope) {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.push_back(id.clone());
+            while jobs.len() > MAX_JOB_SCOPES {
+                if let Some(old) = jobs.pop_front() {
+                    self.scopes.remove(&Scope::Job(old));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// False if the variable was not set
+    pub fn remove(&self, scope: &Scope, name: &str) -> bool {
+        self.scopes
+            .get_mut(scope)
+            .is_some_and(|mut vars| vars.remove(name).is_some())
+    }
+
+    pub fn snapshot(&self, scope: &Scope) -> BTreeMap<String, String> {
+        self.scopes
+            .get(scope)
+            .map(|vars| vars.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&self, scope: &Scope) {
+        self.scopes.remove(scope);
+    }
+
+    /// Every non-empty scope, sessions first
+    pub fn list(&self) -> Vec<VariableScope> {
+        let mut scopes: Vec<VariableScope> = self
+            .scopes
+            .iter()
+            .filter(|entry| !entry.value().is_empty())
+            .map(|entry| VariableScope {
+                scope: entry.key().clone(),
+                variables: entry.value().clone(),
+            })
+            .collect();
+        scopes.sort_by_key(|s| match &s.scope {
+            Scope::Session(id) => (0, id.clone()),
+            Scope::Job(id) => (1, id.clone()),
+        });
+        scopes
+    }
+}
+
+/// Letters, digits and `_`, as script `$var` names
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes() {
+        let store = VariableStore::default();
+        let session = Scope::Session("s1".to_string());
+        store
+            .set(&session, "serial", "2102351931P0".to_string())
+            .unwrap();
+        assert_eq!(store.snapshot(&session)["serial"], "2102351931P0");
+        assert!(store.snapshot(&Scope::Session("s2".to_string())).is_empty());
+        assert!(matches!(
+            store.set(&session, "bad name", String::new()),
+            Err(VariableError::InvalidName(_))
+        ));
+
+        for i in 0..=MAX_JOB_SCOPES {
+            store
+                .set(&Scope::Job(i.to_string()), "output", i.to_string())
+                .unwrap();
+        }
+        let scopes = store.list();
+        assert_eq!(scopes.len(), MAX_JOB_SCOPES + 1);
+        assert_eq!(scopes[0].scope, session);
+        assert!(store.snapshot(&Scope::Job("0".to_string())).is_empty());
+        assert_eq!(store.snapshot(&Scope::Job("1".to_string()))["output"], "1");
+
+        assert!(store.remove(&session, "serial"));
+        assert!(!store.remove(&session, "serial"));
+        store.clear(&Scope::Job("1".to_string()));
+        assert_eq!(store.list().len(), MAX_JOB_SCOPES - 1);
+    }
+}
@@ -0,0 +1,345 @@
+//! Local credential vault for systems without a usable keychain.
+//!
+//! Secrets live in `vault.json`, sealed as one blob with a key derived from
+//! a master password (see `crypto`). While unlocked the derived key and the
+//! decrypted secrets are kept in memory; locking, by hand or after a period
+//! without use, drops both.
+
+use crate::crypto::{CryptoError, KdfParams, Sealed, SecretKey, LOG_N};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+const VAULT_VERSION: u32 = 1;
+
+/// How often the auto-lock timer is checked
+const AUTO_LOCK_CHECK: Duration = Duration::from_secs(15);
+
+#[derive(Error, Debug)]
+pub enum VaultError {
+    #[error("Vault has not been created")]
+    NotCreated,
+    #[error("Vault already exists")]
+    AlreadyExists,
+    #[error("Vault is locked")]
+    Locked,
+    #[error("Wrong master password")]
+    WrongPassword,
+    #[error("Invalid vault: {0}")]
+    Invalid(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl From<CryptoError> for VaultError {
+    fn from(err: CryptoError) -> Self {
+        match err {
+            CryptoError::Invalid(message) => VaultError::Invalid(message),
+            CryptoError::Decrypt => VaultError::WrongPassword,
+        }
+    }
+}
+
+impl From<VaultError> for String {
+    fn from(err: VaultError) -> String {
+        err.to_string()
+    }
+}
+
+/// Vault secret holding the password of a profile
+pub fn profile_secret_name(profile_id: &str) -> String {
+    format!("profile:{profile_id}")
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    #[serde(flatten)]
+    sealed: Sealed,
+}
+
+struct Unlocked {
+    key: SecretKey,
+    kdf: KdfParams,
+    secrets: HashMap<String, String>,
+    last_used: Instant,
+}
+
+/// State reported to the frontend, e.g. to prompt for the master password
+/// at startup
+#[derive(Debug, Clone, Serialize)]
+pub struct VaultStatus {
+    pub created: bool,
+    pub unlocked: bool,
+    /// 0 when auto-lock is off
+    pub auto_lock_secs: u64,
+}
+
+pub struct Vault {
+    path: PathBuf,
+    unlocked: Option<Unlocked>,
+    /// Lock after this long without use; zero disables
+    auto_lock: Duration,
+    log_n: u8,
+}
+
+impl Vault {
+    pub fn new(path: PathBuf, auto_lock: Duration) -> Self {
+        Self {
+            path,
+            unlocked: None,
+            auto_lock,
+            log_n: LOG_N,
+        }
+    }
+
+    /// Cheaper key derivation so tests stay fast in debug builds
+    #[cfg(test)]
+    fn with_cost(mut self, log_n: u8) -> Self {
+        self.log_n = log_n;
+        self
+    }
+
+    pub fn status(&self) -> VaultStatus {
+        VaultStatus {
+            created: self.path.exists(),
+            unlocked: self.unlocked.is_some(),
+            auto_lock_secs: self.auto_lock.as_secs(),
+        }
+    }
+
+    /// Create an empty vault protected by `password`, leaving it unlocked
+    pub fn create(&mut self, password: &str) -> Result<(), VaultError> {
+        if self.path.exists() {
+            return Err(VaultError::AlreadyExists);
+        }
+        let kdf = KdfParams::generate(self.log_n);
+        let key = kdf.derive_key(password)?;
+        self.unlocked = Some(Unlocked {
+            key,
+            kdf,
+            secrets: HashMap::new(),
+            last_used: Instant::now(),
+        });
+        self.persist()?;
+        info!(path = %self.path.display(), "Created credential vault");
+        Ok(())
+    }
+
+    pub fn unlock(&mut self, password: &str) -> Result<(), VaultError> {
+        let (key, kdf, secrets) = self.read(password)?;
+        self.unlocked = Some(Unlocked {
+            key,
+            kdf,
+            secrets,
+            last_used: Instant::now(),
+        });
+        info!("Vault unlocked");
+        Ok(())
+    }
+
+    pub fn lock(&mut self) {
+        if self.unlocked.take().is_some() {
+            info!("Vault locked");
+        }
+    }
+
+    /// Re-encrypt the vault under `new`; works whether or not it is unlocked
+    pub fn change_password(&mut self, old: &str, new: &str) -> Result<(), VaultError> {
+        let (_, _, secrets) = self.read(old)?;
+        let kdf = KdfParams::generate(self.log_n);
+        let key = kdf.derive_key(new)?;
+        self.unlocked = Some(Unlocked {
+            key,
+            kdf,
+            secrets,
+            last_used: Instant::now(),
+        });
+        self.persist()?;
+        info!("Vault master password changed");
+        Ok(())
+    }
+
+    pub fn set_auto_lock(&mut self, auto_lock: Duration) {
+        self.auto_lock = auto_lock;
+    }
+
+    /// Lock if unused for longer than the auto-lock period. Returns whether
+    /// the vault was locked by this call.
+    pub fn lock_if_idle(&mut self) -> bool {
+        let idle = match &self.unlocked {
+            Some(unlocked) => unlocked.last_used.elapsed(),
+            None => return false,
+        };
+        if self.auto_lock.is_zero() || idle < self.auto_lock {
+            return false;
+        }
+        self.unlocked = None;
+        info!(idle_secs = idle.as_secs(), "Vault auto-locked");
+        true
+    }
+
+    pub fn secret(&mut self, name: &str) -> Result<Option<String>, VaultError> {
+        let unlocked = self.unlocked_mut()?;
+        Ok(unlocked.secrets.get(name).cloned())
+    }
+
+    pub fn set_secret(&mut self, name: &str, secret: String) -> Result<(), VaultError> {
+        self.unlocked_mut()?
+            .secrets
+            .insert(name.to_string(), secret);
+        self.persist()
+    }
+
+    pub fn remove_secret(&mut self, name: &str) -> Result<(), VaultError> {
+        if self.unlocked_mut()?.secrets.remove(name).is_some() {
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    /// Counts as use for auto-lock
+    fn unlocked_mut(&mut self) -> Result<&mut Unlocked, VaultError> {
+        let unlocked = self.unlocked.as_mut().ok_or(VaultError::Locked)?;
+        unlocked.last_used = Instant::now();
+        Ok(unlocked)
+    }
+
+    fn read(
+        &self,
+        password: &str,
+    ) -> Result<(SecretKey, KdfParams, HashMap<String, String>), VaultError> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(VaultError::NotCreated),
+            Err(e) => return Err(e.into()),
+        };
+        let file: VaultFile = serde_json::from_slice(&data)?;
+        if file.version != VAULT_VERSION {
+            return Err(VaultError::Invalid(format!(
+                "unsupported version {}",
+                file.version
+            )));
+        }
+        let key = file.sealed.kdf.derive_key(password)?;
+        let secrets = serde_json::from_slice(&file.sealed.open(&key)?)?;
+        Ok((key, file.sealed.kdf, secrets))
+    }
+
+    fn persist(&self) -> Result<(), VaultError> {
+        let unlocked = self.unlocked.as_ref().ok_or(VaultError::Locked)?;
+        let plaintext = serde_json::to_vec(&unlocked.secrets)?;
+        let file = VaultFile {
+            version: VAULT_VERSION,
+            sealed: Sealed::seal(&unlocked.key, unlocked.kdf.clone(), &plaintext)?,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&file)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Lock the vault once it has been idle for its auto-lock period, emitting
+/// `vault-locked`
+pub fn spawn_auto_lock(app_handle: tauri::AppHandle, vault: Arc<Mutex<Vault>>) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(AUTO_LOCK_CHECK);
+        loop {
+            interval.tick().await;
+            if vault.lock().await.lock_if_idle() {
+                if let Err(e) = app_handle.emit("vault-locked", ()) {
+                    warn!(error = %e, "Failed to emit vault lock");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_vault() -> (PathBuf, Vault) {
+        let dir = std::env::temp_dir().join(format!("bspt-vault-test-{}", uuid::Uuid::new_v4()));
+        let vault = Vault::new(dir.join("vault.json"), Duration::from_secs(60)).with_cost(4);
+        (dir, vault)
+    }
+
+    #[test]
+    fn test_lock_unlock_change_password() {
+        let (dir, mut vault) = temp_vault();
+        assert!(matches!(
+            vault.unlock("master"),
+            Err(VaultError::NotCreated)
+        ));
+
+        vault.create("master").unwrap();
+        assert!(matches!(
+            vault.create("again"),
+            Err(VaultError::AlreadyExists)
+        ));
+        vault
+            .set_secret("profile:1", "admin123".to_string())
+            .unwrap();
+
+        vault.lock();
+        assert!(!vault.status().unlocked);
+        assert!(matches!(vault.secret("profile:1"), Err(VaultError::Locked)));
+        assert!(matches!(
+            vault.unlock("nope"),
+            Err(VaultError::WrongPassword)
+        ));
+        assert!(!std::fs::read_to_string(dir.join("vault.json"))
+            .unwrap()
+            .contains("admin123"));
+
+        vault.unlock("master").unwrap();
+        assert_eq!(
+            vault.secret("profile:1").unwrap().as_deref(),
+            Some("admin123")
+        );
+
+        vault.change_password("master", "new master").unwrap();
+        vault.lock();
+        assert!(matches!(
+            vault.unlock("master"),
+            Err(VaultError::WrongPassword)
+        ));
+        vault.unlock("new master").unwrap();
+        vault.remove_secret("profile:1").unwrap();
+        assert!(vault.secret("profile:1").unwrap().is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_auto_lock() {
+        let (dir, mut vault) = temp_vault();
+        vault.create("master").unwrap();
+        assert!(!vault.lock_if_idle());
+
+        vault.set_auto_lock(Duration::ZERO);
+        vault.unlocked.as_mut().unwrap().last_used -= Duration::from_secs(3600);
+        assert!(!vault.lock_if_idle());
+
+        vault.set_auto_lock(Duration::from_secs(60));
+        assert!(vault.lock_if_idle());
+        assert!(!vault.status().unlocked);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
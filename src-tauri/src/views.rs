@@ -0,0 +1,241 @@
+//! Windows sharing a session.
+//!
+//! Session output events already reach every window. `attach` gives a
+//! second window, such as a detached tab, what it missed: the latest
+//! scrollback is sent to it alone as `session:{id}:replay` while the
+//! emitter is held, so every chunk is either in the replay or in a live
+//! event after it. The window drops `session:{id}` data until the replay
+//! arrives.
+//!
+//! Input needs no merging beyond the session's input queue, which takes
+//! each `send_input` whole. Sizes do: the terminal gets the smallest
+//! columns and rows among the windows showing it, so none of them wraps
+//! output laid out for a wider one.
+
+use crate::emitter::Payload;
+use crate::session::{SessionError, SessionManager};
+use dashmap::DashMap;
+use serde::Serialize;
+use tauri::{Emitter, EventTarget, Manager};
+use tracing::{info, warn};
+
+/// Scrollback lines replayed to an attaching window
+const REPLAY_LINES: u64 = 5000;
+
+/// Columns and rows
+type Size = (u32, u32);
+
+/// A window showing a session, listed in `session:{id}:views`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct View {
+    pub label: String,
+    /// Last size its terminal reported
+    pub cols: Option<u32>,
+    pub rows: Option<u32>,
+}
+
+#[derive(Default)]
+struct SessionViews {
+    views: Vec<View>,
+    /// Size last sent to the device
+    applied: Option<Size>,
+}
+
+impl SessionViews {
+    fn view(&mut self, label: &str) -> &mut View {
+        let index = match self.views.iter().position(|view| view.label == label) {
+            Some(index) => index,
+            None => {
+                self.views.push(View {
+                    label: label.to_string(),
+                    cols: None,
+                    rows: None,
+                });
+                self.views.len() - 1
+            }
+        };
+        &mut self.views[index]
+    }
+
+    /// The merged size, when it differs from the one applied
+    fn merge(&mut self) -> Option<Size> {
+        let cols = self.views.iter().filter_map(|view| view.cols).min()?;
+        let rows = self.views.iter().filter_map(|view| view.rows).min()?;
+        if self.applied == Some((cols, rows)) {
+            return None;
+        }
+        self.applied = Some((cols, rows));
+        Some((cols, rows))
+    }
+}
+
+/// Windows per session, owned by `SessionManager`
+#[derive(Default)]
+pub struct ViewRegistry {
+    sessions: DashMap<String, SessionViews>,
+}
+
+impl ViewRegistry {
+    fn attach(&self, session_id: &str, label: &str) -> Vec<View> {
+        let mut entry = self.sessions.entry(session_id.to_string()).or_default();
+        entry.view(label);
+        entry.views.clone()
+    }
+
+    /// Record a window's terminal size; returns the size to apply
+    fn resize(&self, session_id: &str, label: &str, cols: u32, rows: u32) -> Option<Size> {
+        let mut entry = self.sessions.entry(session_id.to_string()).or_default();
+        let view = entry.view(label);
+        view.cols = Some(cols);
+        view.rows = Some(rows);
+        entry.merge()
+    }
+
+    /// Forget a window; returns the remaining views and the size to apply
+    fn detach(&self, session_id: &str, label: &str) -> Option<(Vec<View>, Option<Size>)> {
+        let mut entry = self.sessions.get_mut(session_id)?;
+        let before = entry.views.len();
+        entry.views.retain(|view| view.label != label);
+        if entry.views.len() == before {
+            return None;
+        }
+        let size = entry.merge();
+        Some((entry.views.clone(), size))
+    }
+
+    /// Sessions the window was showing
+    fn sessions_of(&self, label: &str) -> Vec<String> {
+        self.sessions
+            .iter()
+            .filter(|entry| entry.views.iter().any(|view| view.label == label))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    pub fn list(&self, session_id: &str) -> Vec<View> {
+        self.sessions
+            .get(session_id)
+            .map(|entry| entry.views.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn remove(&self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+}
+
+fn emit_views(manager: &SessionManager, session_id: &str, views: &[View]) {
+    let event = format!("session:{}:views", session_id);
+    if let Err(e) = manager.app_handle().emit(&event, views) {
+        warn!(session_id = %session_id, error = %e, "Failed to emit views");
+    }
+}
+
+/// Show the session in window `label` as well, replaying its scrollback
+pub async fn attach(
+    manager: &SessionManager,
+    session_id: &str,
+    label: &str,
+) -> Result<Vec<View>, SessionError> {
+    let handle = manager
+        .get(session_id)
+        .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+    if manager.app_handle().get_webview_window(label).is_none() {
+        return Err(SessionError::WindowNotFound(label.to_string()));
+    }
+
+    let views = manager.views().attach(session_id, label);
+    {
+        // The emitter records and emits each chunk under this lock
+        let scrollback = handle.scrollback.lock().await;
+        let data = scrollback.tail(REPLAY_LINES);
+        let payload = Payload::encode(handle.config.payload_encoding, &data);
+        manager
+            .app_handle()
+            .emit_to(
+                EventTarget::webview_window(label),
+                &format!("session:{}:replay", session_id),
+                payload,
+            )
+            .map_err(|e| SessionError::ChannelError(e.to_string()))?;
+    }
+    info!(session_id = %session_id, window = %label, "View attached");
+    emit_views(manager, session_id, &views);
+    Ok(views)
+}
+
+/// Stop showing the session in window `label`
+pub async fn detach(
+    manager: &SessionManager,
+    session_id: &str,
+    label: &str,
+) -> Result<(), SessionError> {
+    let Some((views, size)) = manager.views().detach(session_id, label) else {
+        return Ok(());
+    };
+    info!(session_id = %session_id, window = %label, "View detached");
+    emit_views(manager, session_id, &views);
+    match size {
+        Some((cols, rows)) => manager.resize(session_id, cols, rows).await,
+        None => Ok(()),
+    }
+}
+
+/// A window's terminal resized; the session gets the merged size
+pub async fn resize(
+    manager: &SessionManager,
+    session_id: &str,
+    label: &str,
+    cols: u32,
+    rows: u32,
+) -> Result<(), SessionError> {
+    if manager.get(session_id).is_none() {
+        return Err(SessionError::NotFound(session_id.to_string()));
+    }
+    match manager.views().resize(session_id, label, cols, rows) {
+        Some((cols, rows)) => manager.resize(session_id, cols, rows).await,
+        None => Ok(()),
+    }
+}
+
+/// A window closed: detach it from every session it showed
+pub async fn window_closed(manager: &SessionManager, label: &str) {
+    for session_id in manager.views().sessions_of(label) {
+        if let Err(e) = detach(manager, &session_id, label).await {
+            warn!(session_id = %session_id, window = %label, error = %e, "Failed to detach closed window");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sizes_merge_across_views() {
+        let registry = ViewRegistry::default();
+        assert_eq!(registry.resize("s1", "main", 200, 50), Some((200, 50)));
+        // Same size again sends nothing
+        assert_eq!(registry.resize("s1", "main", 200, 50), None);
+
+        // A narrower, taller window: smallest of each
+        let views = registry.attach("s1", "detached-1");
+        assert_eq!(views.len(), 2);
+        assert_eq!(
+            registry.resize("s1", "detached-1", 120, 60),
+            Some((120, 50))
+        );
+        assert_eq!(registry.resize("s1", "main", 180, 50), None);
+        assert_eq!(registry.sessions_of("detached-1"), vec!["s1".to_string()]);
+
+        // Closing it gives the remaining window its own size back
+        let (views, size) = registry.detach("s1", "detached-1").unwrap();
+        assert_eq!(views.len(), 1);
+        assert_eq!(size, Some((180, 50)));
+        assert!(registry.detach("s1", "detached-1").is_none());
+        assert!(registry.sessions_of("detached-1").is_empty());
+
+        registry.remove("s1");
+        assert!(registry.list("s1").is_empty());
+    }
+}
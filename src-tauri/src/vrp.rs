@@ -1,6 +1,9 @@
+use crate::lines::LineAssembler;
+use crate::plugin::Dialect;
+use crate::session::DeviceDialect;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 
 /// VRP view modes (command prompt types)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -25,6 +28,10 @@ pub enum VrpEvent {
         auto_handled: bool,
     },
     BoardInfo(BoardInfo),
+    /// The CLI rejected a command
+    CommandError {
+        message: String,
+    },
 }
 
 /// Parsed board information from `display device`
@@ -41,6 +48,18 @@ pub struct BoardInfo {
 static PAGINATION_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"----\s*More\s*----").unwrap());
 
+// Cisco IOS pager: ` --More-- `
+static IOS_PAGINATION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"--More--").unwrap());
+
+// Rejected command: VRP `Error: Unrecognized command found at '^' position.`
+static VRP_ERROR_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*Error:\s*(.+?)\s*$").unwrap());
+
+// Comware and IOS: `% Unrecognized command found at '^' position.`,
+// `% Invalid input detected at '^' marker.`
+static PERCENT_ERROR_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*%\s*(.+?)\s*$").unwrap());
+
 static USER_VIEW_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"<([^>]+)>\s*$").unwrap());
 
@@ -58,14 +77,16 @@ static BOARD_RE: LazyLock<Regex> = LazyLock::new(|| {
 
 /// VRP stream parser for handling Huawei router-specific output
 pub struct VrpParser {
-    /// Buffer for accumulating partial lines
-    line_buffer: String,
     /// Current detected view
     current_view: VrpView,
     /// Current hostname
     hostname: String,
     /// Auto-handle pagination (send space)
     pub auto_pagination: bool,
+    /// Which of the patterns below apply to the device
+    dialect: DeviceDialect,
+    /// Plugin dialect replacing those patterns
+    plugin: Option<Arc<dyn Dialect>>,
 }
 
 impl Default for VrpParser {
@@ -77,25 +98,81 @@ impl Default for VrpParser {
 impl VrpParser {
     pub fn new() -> Self {
         Self {
-            line_buffer: String::new(),
             current_view: VrpView::Unknown,
             hostname: String::new(),
             auto_pagination: true,
+            dialect: DeviceDialect::Vrp,
+            plugin: None,
         }
     }
 
-    /// Parse incoming data and return (cleaned_data, events, auto_response)
+    /// Parser for another dialect. Comware shares VRP's prompts and pager;
+    /// IOS only gets pagination handling, and MML, Linux and generic devices
+    /// are passed through untouched.
+    pub fn with_dialect(dialect: DeviceDialect) -> Self {
+        Self {
+            dialect,
+            ..Self::new()
+        }
+    }
+
+    /// Parser for a plugin dialect, which only gets pagination and command
+    /// error handling
+    pub fn with_plugin(dialect: Arc<dyn Dialect>) -> Self {
+        Self {
+            dialect: DeviceDialect::Generic,
+            plugin: Some(dialect),
+            ..Self::new()
+        }
+    }
+
+    fn pagination_re(&self) -> Option<&Regex> {
+        if let Some(plugin) = &self.plugin {
+            return plugin.pagination();
+        }
+        match self.dialect {
+            DeviceDialect::Vrp | DeviceDialect::Comware => Some(&PAGINATION_RE),
+            DeviceDialect::Ios => Some(&IOS_PAGINATION_RE),
+            DeviceDialect::Mml | DeviceDialect::Linux | DeviceDialect::Generic => None,
+        }
+    }
+
+    fn pagination_response(&self) -> &[u8] {
+        match &self.plugin {
+            Some(plugin) => plugin.pagination_response(),
+            None => b" ",
+        }
+    }
+
+    fn error_re(&self) -> Option<&Regex> {
+        if let Some(plugin) = &self.plugin {
+            return plugin.command_error();
+        }
+        match self.dialect {
+            DeviceDialect::Vrp => Some(&VRP_ERROR_RE),
+            DeviceDialect::Comware | DeviceDialect::Ios => Some(&PERCENT_ERROR_RE),
+            DeviceDialect::Mml | DeviceDialect::Linux | DeviceDialect::Generic => None,
+        }
+    }
+
+    /// Parse the lines completed by the latest read plus the assembler's
+    /// partial line, and return (events, auto_response)
     /// auto_response contains bytes to send back automatically (e.g., space for pagination)
-    pub fn parse(&mut self, data: &[u8]) -> (Vec<u8>, Vec<VrpEvent>, Option<Vec<u8>>) {
+    pub fn parse(
+        &mut self,
+        lines: &[String],
+        assembler: &mut LineAssembler,
+    ) -> (Vec<VrpEvent>, Option<Vec<u8>>) {
         let mut events = Vec::new();
         let mut auto_response = None;
 
-        // Convert to string, handling incomplete UTF-8 gracefully
-        let text = String::from_utf8_lossy(data);
-        self.line_buffer.push_str(&text);
-
-        // Check for pagination
-        if PAGINATION_RE.is_match(&self.line_buffer) {
+        // Check for pagination; the marker is removed from the partial line
+        // so the redrawn line after it isn't detected again
+        let paginated = self.pagination_re().is_some_and(|re| {
+            let in_lines = lines.iter().any(|line| re.is_match(line));
+            assembler.strip_partial(re) || in_lines
+        });
+        if paginated {
             let handled = self.auto_pagination;
             events.push(VrpEvent::Pagination {
                 detected: true,
@@ -103,36 +180,46 @@ impl VrpParser {
             });
 
             if handled {
-                // Send space to continue
-                auto_response = Some(b" ".to_vec());
+                // Send space (or the plugin dialect's response) to continue
+                auto_response = Some(self.pagination_response().to_vec());
             }
+        }
 
-            // Remove the pagination marker from buffer
-            self.line_buffer = PAGINATION_RE.replace_all(&self.line_buffer, "").to_string();
+        if let Some(error_re) = self.error_re() {
+            for caps in lines.iter().filter_map(|line| error_re.captures(line)) {
+                events.push(VrpEvent::CommandError {
+                    message: caps[1].to_string(),
+                });
+            }
         }
 
-        // Check for view changes (prompt detection)
-        if let Some(view_event) = self.detect_view_change() {
-            events.push(view_event);
+        if !matches!(self.dialect, DeviceDialect::Vrp | DeviceDialect::Comware) {
+            return (events, auto_response);
         }
 
-        // Parse board information if present
-        for board in self.parse_boards() {
-            events.push(VrpEvent::BoardInfo(board));
+        // Check for view changes on the prompt line, which is normally still
+        // partial but may already be terminated
+        let prompt = match assembler.partial() {
+            partial if !partial.trim().is_empty() => partial,
+            _ => lines.last().map(String::as_str).unwrap_or_default(),
+        };
+        if let Some(view_event) = self.detect_view_change(prompt) {
+            events.push(view_event);
         }
 
-        // Clean the line buffer - keep only the last line if incomplete
-        if let Some(last_newline) = self.line_buffer.rfind('\n') {
-            self.line_buffer = self.line_buffer[last_newline + 1..].to_string();
+        // Parse board information if present (`display device` is VRP only)
+        if self.dialect == DeviceDialect::Vrp {
+            for board in Self::parse_boards(lines) {
+                events.push(VrpEvent::BoardInfo(board));
+            }
         }
 
-        // Return original data (we don't filter it, just detect events)
-        (data.to_vec(), events, auto_response)
+        (events, auto_response)
     }
 
-    fn detect_view_change(&mut self) -> Option<VrpEvent> {
+    fn detect_view_change(&mut self, prompt: &str) -> Option<VrpEvent> {
         // Check for user view prompt: <hostname>
-        if let Some(caps) = USER_VIEW_RE.captures(&self.line_buffer) {
+        if let Some(caps) = USER_VIEW_RE.captures(prompt) {
             let hostname = caps.get(1)?.as_str().to_string();
             if self.current_view != VrpView::User || self.hostname != hostname {
                 self.current_view = VrpView::User;
@@ -144,7 +231,7 @@ impl VrpParser {
             }
         }
         // Check for system/interface view prompt: [hostname] or [hostname-interface]
-        else if let Some(caps) = SYSTEM_VIEW_RE.captures(&self.line_buffer) {
+        else if let Some(caps) = SYSTEM_VIEW_RE.captures(prompt) {
             let full_prompt = caps.get(1)?.as_str();
             // Detect if it's an interface view (contains hyphen after hostname)
             let (view, hostname) = if full_prompt.contains('-') {
@@ -169,10 +256,10 @@ impl VrpParser {
         None
     }
 
-    fn parse_boards(&self) -> Vec<BoardInfo> {
+    fn parse_boards(lines: &[String]) -> Vec<BoardInfo> {
         let mut boards = Vec::new();
 
-        for line in self.line_buffer.lines() {
+        for line in lines {
             if let Some(caps) = BOARD_RE.captures(line) {
                 let slot_id = caps.get(1).map(|m| m.as_str().to_string()).unwrap_or_default();
                 let sub_slot = caps.get(2).map(|m| m.as_str().to_string()).unwrap_or_default();
@@ -208,7 +295,6 @@ impl VrpParser {
     /// Reset parser state
     #[allow(dead_code)]
     pub fn reset(&mut self) {
-        self.line_buffer.clear();
         self.current_view = VrpView::Unknown;
         self.hostname.clear();
     }
@@ -218,11 +304,17 @@ impl VrpParser {
 mod tests {
     use super::*;
 
+    fn parse(parser: &mut VrpParser, data: &[u8]) -> (Vec<VrpEvent>, Option<Vec<u8>>) {
+        let mut assembler = LineAssembler::new();
+        let lines = assembler.push(data);
+        parser.parse(&lines, &mut assembler)
+    }
+
     #[test]
     fn test_pagination_detection() {
         let mut parser = VrpParser::new();
         let data = b"Some output\r\n  ---- More ----";
-        let (_, events, auto) = parser.parse(data);
+        let (events, auto) = parse(&mut parser, data);
 
         assert!(events.iter().any(|e| matches!(e, VrpEvent::Pagination { detected: true, .. })));
         assert!(auto.is_some());
@@ -233,7 +325,7 @@ mod tests {
     fn test_user_view_detection() {
         let mut parser = VrpParser::new();
         let data = b"<Huawei>";
-        let (_, events, _) = parser.parse(data);
+        let (events, _) = parse(&mut parser, data);
 
         assert!(events.iter().any(|e| matches!(
             e,
@@ -245,7 +337,7 @@ mod tests {
     fn test_system_view_detection() {
         let mut parser = VrpParser::new();
         let data = b"[Huawei]";
-        let (_, events, _) = parser.parse(data);
+        let (events, _) = parse(&mut parser, data);
 
         assert!(events.iter().any(|e| matches!(
             e,
@@ -257,7 +349,7 @@ mod tests {
     fn test_interface_view_detection() {
         let mut parser = VrpParser::new();
         let data = b"[Huawei-GigabitEthernet0/0/1]";
-        let (_, events, _) = parser.parse(data);
+        let (events, _) = parse(&mut parser, data);
 
         assert!(events.iter().any(|e| matches!(
             e,
@@ -269,7 +361,7 @@ mod tests {
     fn test_board_parsing() {
         let mut parser = VrpParser::new();
         let data = b"0    -    SRUC    Present Master   192.168.1.1\r\n1    0    LPU     Present Slave    10.0.0.1\r\n";
-        let (_, events, _) = parser.parse(data);
+        let (events, _) = parse(&mut parser, data);
 
         let boards: Vec<_> = events
             .iter()
@@ -284,4 +376,53 @@ mod tests {
         assert_eq!(boards[0].ip, Some("192.168.1.1".to_string()));
         assert_eq!(boards[1].slot_id, "1");
     }
+
+    #[test]
+    fn test_pagination_redraw_not_detected_twice() {
+        let mut parser = VrpParser::new();
+        let mut assembler = LineAssembler::new();
+        let lines = assembler.push(b"line 1\r\n  ---- More ----");
+        let (events, auto) = parser.parse(&lines, &mut assembler);
+        assert_eq!(events.len(), 1);
+        assert!(auto.is_some());
+
+        let lines = assembler.push(b"\x1b[16D                \x1b[16Dline 2\r\n");
+        let (events, auto) = parser.parse(&lines, &mut assembler);
+        assert!(events.is_empty());
+        assert!(auto.is_none());
+    }
+
+    #[test]
+    fn test_dialects() {
+        let mut ios = VrpParser::with_dialect(DeviceDialect::Ios);
+        let (events, auto) = parse(&mut ios, b"Interface Gi0/1\r\n --More-- ");
+        assert_eq!(events.len(), 1);
+        assert_eq!(auto, Some(b" ".to_vec()));
+        let (events, _) = parse(&mut ios, b"<Router>");
+        assert!(events.is_empty());
+
+        let mut comware = VrpParser::with_dialect(DeviceDialect::Comware);
+        let (events, _) = parse(&mut comware, b"[H3C]");
+        assert!(matches!(
+            &events[..],
+            [VrpEvent::ViewChange {
+                view: VrpView::System,
+                ..
+            }]
+        ));
+
+        let (events, _) = parse(
+            &mut ios,
+            b"Router#sh ver x\r\n% Invalid input detected at '^' marker.\r\n",
+        );
+        assert!(matches!(
+            &events[..],
+            [VrpEvent::CommandError { message }] if message == "Invalid input detected at '^' marker."
+        ));
+
+        let mut linux = VrpParser::with_dialect(DeviceDialect::Linux);
+        let (events, auto) = parse(&mut linux, b"---- More ----\r\n[root@board ~]");
+        assert!(events.is_empty());
+        assert!(auto.is_none());
+    }
 }
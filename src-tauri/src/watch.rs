@@ -0,0 +1,232 @@
+//! `watch -d` for device CLIs: run a command on an interval and report
+//! only what changed.
+//!
+//! A watch is a job (`JobKind::Watch`), so `pause_job`, `resume_job` and
+//! `cancel_job` apply to it. Each run goes through `command::run_command`
+//! and is diffed against the previous output with the backup line diff. The
+//! first run sends the whole output; later runs send `watch:{id}` only when
+//! lines were added or removed, or when the run failed. A command the CLI
+//! rejects on the first run ends the watch, as does the session closing.
+
+use crate::backup::{diff_lines, DiffOp};
+use crate::command;
+use crate::jobs::{JobControl, JobKind, JobRegistry};
+use crate::session::{SessionError, SessionManager};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Emitter;
+use tracing::{info, warn};
+
+/// Interval when the caller gives none
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Shortest interval accepted
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A line added or removed since the previous run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WatchChange {
+    pub op: DiffOp,
+    /// 1-based, in the new output for additions and the old for removals
+    pub line: usize,
+    pub text: String,
+}
+
+/// Body of `watch:{id}` events
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchUpdate {
+    pub watch_id: String,
+    pub session_id: String,
+    /// From 1
+    pub run: u64,
+    /// Unix milliseconds
+    pub at: i64,
+    pub duration_ms: u64,
+    /// The whole output, on the first run
+    pub output: Option<String>,
+    pub changes: Vec<WatchChange>,
+    pub error: Option<String>,
+}
+
+/// Start watching `command` on a session; returns the watch (job) id
+pub fn start(
+    manager: &Arc<SessionManager>,
+    jobs: &Arc<JobRegistry>,
+    session_id: &str,
+    command: &str,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<String, SessionError> {
+    let command = command.trim();
+    if command.is_empty() {
+        return Err(SessionError::InvalidConfig(
+            "watch command is empty".to_string(),
+        ));
+    }
+    let host = manager
+        .get(session_id)
+        .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?
+        .config
+        .host
+        .clone();
+    let job = jobs.start(JobKind::Watch, format!("watch {} on {}", command, host));
+    let id = job.id().to_string();
+    info!(watch_id = %id, session_id = %session_id, command = %command, "Watch started");
+    let watch = Watch {
+        manager: Arc::clone(manager),
+        session_id: session_id.to_string(),
+        command: command.to_string(),
+        interval: interval.max(MIN_INTERVAL),
+        timeout,
+    };
+    tauri::async_runtime::spawn(async move {
+        let result = watch.run(&job).await;
+        job.finish(result);
+    });
+    Ok(id)
+}
+
+struct Watch {
+    manager: Arc<SessionManager>,
+    session_id: String,
+    command: String,
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl Watch {
+    async fn run(&self, job: &JobControl) -> Result<String, String> {
+        let mut previous: Option<String> = None;
+        let mut run = 0;
+        let mut changed_runs = 0;
+        while job.checkpoint().await.is_ok() {
+            run += 1;
+            let mut update = WatchUpdate {
+                watch_id: job.id().to_string(),
+                session_id: self.session_id.clone(),
+                run,
+                at: chrono::Utc::now().timestamp_millis(),
+                duration_ms: 0,
+                output: None,
+                changes: Vec::new(),
+                error: None,
+            };
+            let message = match command::run_command(
+                &self.manager,
+                &self.session_id,
+                &self.command,
+                self.timeout,
+            )
+            .await
+            {
+                Ok(result) => {
+                    update.duration_ms = result.duration_ms;
+                    match &previous {
+                        None => {
+                            if let Some(line) = command::rejection(&result.output) {
+                                return Err(format!("`{}` rejected: {}", self.command, line));
+                            }
+                            update.output = Some(result.output.clone());
+                        }
+                        Some(previous) => update.changes = changes(previous, &result.output),
+                    }
+                    if !update.changes.is_empty() {
+                        changed_runs += 1;
+                    }
+                    previous = Some(result.output);
+                    format!("run {}: {} lines changed", run, update.changes.len())
+                }
+                Err(SessionError::NotFound(_)) => {
+                    return Err("session closed".to_string());
+                }
+                Err(e) => {
+                    update.error = Some(e.to_string());
+                    format!("run {} failed: {}", run, e)
+                }
+            };
+            if update.output.is_some() || !update.changes.is_empty() || update.error.is_some() {
+                self.emit(&update);
+            }
+            job.progress(run, 0, Some(message));
+
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => {}
+                _ = job.cancelled() => break,
+            }
+        }
+        Ok(format!("{} runs, {} with changes", run, changed_runs))
+    }
+
+    fn emit(&self, update: &WatchUpdate) {
+        let event = format!("watch:{}", update.watch_id);
+        if let Err(e) = self.manager.app_handle().emit(&event, update) {
+            warn!(watch_id = %update.watch_id, error = %e, "Failed to emit watch update");
+        }
+    }
+}
+
+/// Added and removed lines between two outputs
+fn changes(old: &str, new: &str) -> Vec<WatchChange> {
+    let mut changes = Vec::new();
+    for hunk in diff_lines(old, new) {
+        let (mut old_line, mut new_line) = (hunk.old_start, hunk.new_start);
+        for line in hunk.lines {
+            match line.op {
+                DiffOp::Same => {
+                    old_line += 1;
+                    new_line += 1;
+                }
+                DiffOp::Removed => {
+                    changes.push(WatchChange {
+                        op: line.op,
+                        line: old_line,
+                        text: line.text,
+                    });
+                    old_line += 1;
+                }
+                DiffOp::Added => {
+                    changes.push(WatchChange {
+                        op: line.op,
+                        line: new_line,
+                        text: line.text,
+                    });
+                    new_line += 1;
+                }
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_between_runs() {
+        let mut lines = vec!["Interface  PHY   Protocol".to_string()];
+        lines.extend((1..=9).map(|port| format!("GE0/0/{}    up    up", port)));
+        lines[3] = "GE0/0/3    down  down".to_string();
+        let old = lines.join("\n");
+        lines[3] = "GE0/0/3    up    up".to_string();
+        lines[9] = "GE0/0/9    up    down".to_string();
+        let new = lines.join("\n");
+
+        let found = changes(&old, &new);
+        let summary: Vec<(DiffOp, usize, &str)> = found
+            .iter()
+            .map(|c| (c.op, c.line, c.text.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (DiffOp::Removed, 4, "GE0/0/3    down  down"),
+                (DiffOp::Added, 4, "GE0/0/3    up    up"),
+                (DiffOp::Removed, 10, "GE0/0/9    up    up"),
+                (DiffOp::Added, 10, "GE0/0/9    up    down"),
+            ]
+        );
+        assert!(changes(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,1232 @@
+//! ZMODEM transfers started on the device, `sz` or `rz` at a board's shell.
+//!
+//! The session loops hand raw output to a `ZmodemTap` before charset
+//! decoding. Once it sees the opening header of `sz` (ZRQINIT) or `rz`
+//! (ZRINIT) it stops passing output through, emits an `offer` on
+//! `session:{id}:zmodem` and holds the device until the frontend picks a
+//! directory (`zmodem_receive`) or files (`zmodem_send`), or cancels. The
+//! protocol then runs here: hex and binary headers, data subpackets with
+//! CRC-16 or CRC-32, ZRPOS restarts after errors, and sending in bursts that
+//! wait for the receiver's ZACK. Terminal output resumes after the closing
+//! ZFIN exchange, a cancel, or 30 seconds without a reply.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+
+// Frame types
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+const ZCAN: u8 = 16;
+
+// Data subpacket ends
+const ZCRCE: u8 = b'h';
+const ZCRCG: u8 = b'i';
+const ZCRCQ: u8 = b'j';
+const ZCRCW: u8 = b'k';
+
+// ZRINIT capabilities, in ZF0
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+const ESCCTL: u8 = 0x40;
+
+/// ZFILE conversion option: binary, no newline translation
+const ZCBIN: u8 = 1;
+
+/// Flow control bytes, dropped when they arrive unescaped
+const FLOW: [u8; 4] = [0x11, 0x13, 0x91, 0x93];
+
+/// Opening hex header of both sides after its `**` pads, before the
+/// type's second digit. ZDLE never shows up in text, so this alone marks it.
+const START: &[u8] = b"\x18B0";
+
+/// Abort sequence, as lrzsz sends it
+const CANCEL: &[u8] =
+    b"\x18\x18\x18\x18\x18\x18\x18\x18\x18\x18\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08";
+
+const SUBPACKET: usize = 1024;
+const MAX_SUBPACKET: usize = 8192;
+/// Data sent before waiting for the receiver's ZACK
+const BURST: u64 = 16 * 1024;
+/// Output held while the frontend decides
+const MAX_HELD: usize = 64 * 1024;
+const DECISION_TIMEOUT: Duration = Duration::from_secs(120);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Bytes between progress events
+const PROGRESS_STEP: u64 = 64 * 1024;
+
+/// Answer to an offer, sent to the session task
+#[derive(Debug, Clone)]
+pub enum ZmodemCommand {
+    /// Save the files the device's `sz` sends into this directory
+    Receive(PathBuf),
+    /// Send these files to the device's `rz`
+    Send(Vec<PathBuf>),
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// The device runs `sz`; answer with a directory
+    Receive,
+    /// The device runs `rz`; answer with files
+    Send,
+}
+
+/// Payload of `session:{id}:zmodem`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ZmodemEvent {
+    Offer {
+        direction: Direction,
+    },
+    File {
+        name: String,
+        size: Option<u64>,
+    },
+    Progress {
+        name: String,
+        bytes: u64,
+        size: Option<u64>,
+    },
+    FileDone {
+        name: String,
+        path: String,
+        bytes: u64,
+    },
+    /// The receiver already has the file
+    Skipped {
+        name: String,
+    },
+    Finished {
+        files: usize,
+    },
+    Failed {
+        error: String,
+    },
+    Cancelled,
+}
+
+/// CRC-16/XMODEM over the concatenated parts
+fn crc16(parts: &[&[u8]]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-32 (IEEE) over the concatenated parts
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Header {
+    kind: u8,
+    /// ZP0..ZP3, a little-endian position, or flags with ZF0 last
+    data: [u8; 4],
+}
+
+impl Header {
+    fn at(kind: u8, pos: u64) -> Self {
+        Self {
+            kind,
+            data: (pos as u32).to_le_bytes(),
+        }
+    }
+
+    fn flags(kind: u8, f0: u8) -> Self {
+        Self {
+            kind,
+            data: [0, 0, 0, f0],
+        }
+    }
+
+    fn pos(&self) -> u64 {
+        u32::from_le_bytes(self.data) as u64
+    }
+
+    fn f0(&self) -> u8 {
+        self.data[3]
+    }
+
+    fn hex(&self) -> Vec<u8> {
+        let crc = crc16(&[&[self.kind], &self.data]);
+        let mut out = b"**\x18B".to_vec();
+        for byte in [self.kind]
+            .iter()
+            .chain(&self.data)
+            .chain(&crc.to_be_bytes())
+        {
+            out.extend(format!("{:02x}", byte).bytes());
+        }
+        out.extend(b"\r\x8a");
+        if self.kind != ZFIN && self.kind != ZACK {
+            out.push(0x11);
+        }
+        out
+    }
+
+    fn binary(&self, crc32_mode: bool, escape_ctl: bool) -> Vec<u8> {
+        let mut raw = vec![self.kind];
+        raw.extend(self.data);
+        if crc32_mode {
+            let crc = crc32(&[&raw]);
+            raw.extend(crc.to_le_bytes());
+        } else {
+            let crc = crc16(&[&raw]);
+            raw.extend(crc.to_be_bytes());
+        }
+        let mut out = vec![ZPAD, ZDLE, if crc32_mode { ZBIN32 } else { ZBIN }];
+        escape_into(&mut out, &raw, escape_ctl);
+        out
+    }
+}
+
+/// ZDLE-escape `data`: ZDLE and flow control always, every control
+/// character with `escape_ctl`
+fn escape_into(out: &mut Vec<u8>, data: &[u8], escape_ctl: bool) {
+    for &byte in data {
+        let escape = matches!(byte, ZDLE | 0x10 | 0x90 | 0x11 | 0x91 | 0x13 | 0x93)
+            || (escape_ctl && byte & 0x60 == 0);
+        if escape {
+            out.extend([ZDLE, byte ^ 0x40]);
+        } else {
+            out.push(byte);
+        }
+    }
+}
+
+fn subpacket(data: &[u8], end: u8, crc32_mode: bool, escape_ctl: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 8 + 12);
+    escape_into(&mut out, data, escape_ctl);
+    out.extend([ZDLE, end]);
+    let crc = if crc32_mode {
+        crc32(&[data, &[end]]).to_le_bytes().to_vec()
+    } else {
+        crc16(&[data, &[end]]).to_be_bytes().to_vec()
+    };
+    escape_into(&mut out, &crc, escape_ctl);
+    out
+}
+
+#[derive(Debug, PartialEq)]
+enum Byte {
+    Plain(u8),
+    /// ZDLE and a subpacket end
+    End(u8),
+}
+
+/// One ZDLE-decoded byte at `*i`; `None` when more input is needed,
+/// `Err` for an escape that means nothing
+fn read_byte(buf: &[u8], i: &mut usize) -> Option<Result<Byte, ()>> {
+    loop {
+        let byte = *buf.get(*i)?;
+        *i += 1;
+        if FLOW.contains(&byte) {
+            continue;
+        }
+        if byte != ZDLE {
+            return Some(Ok(Byte::Plain(byte)));
+        }
+        loop {
+            let next = *buf.get(*i)?;
+            *i += 1;
+            return Some(match next {
+                _ if FLOW.contains(&next) => continue,
+                ZCRCE..=ZCRCW => Ok(Byte::End(next)),
+                b'l' => Ok(Byte::Plain(0x7f)),
+                b'm' => Ok(Byte::Plain(0xff)),
+                _ if next & 0x60 == 0x40 => Ok(Byte::Plain(next ^ 0x40)),
+                _ => Err(()),
+            });
+        }
+    }
+}
+
+/// `n` plain decoded bytes
+fn read_plain(buf: &[u8], i: &mut usize, n: usize) -> Option<Result<Vec<u8>, ()>> {
+    let mut out = Vec::with_capacity(n);
+    while out.len() < n {
+        match read_byte(buf, i)? {
+            Ok(Byte::Plain(byte)) => out.push(byte),
+            _ => return Some(Err(())),
+        }
+    }
+    Some(Ok(out))
+}
+
+#[derive(Debug, PartialEq)]
+enum Unit {
+    Header(Header),
+    Data(Vec<u8>, u8),
+    /// A data subpacket failed its CRC or was malformed
+    BadData,
+    /// Five CANs in a row
+    Abort,
+}
+
+/// Splits received bytes into headers and data subpackets
+#[derive(Default)]
+struct Decoder {
+    buf: Vec<u8>,
+    /// The last binary header used CRC-32, and so do its subpackets
+    crc32: bool,
+    /// A data subpacket comes next instead of a header
+    expect_data: bool,
+}
+
+impl Decoder {
+    fn next(&mut self) -> Option<Unit> {
+        if self.buf.windows(5).any(|w| w == [ZDLE; 5]) {
+            self.buf.clear();
+            return Some(Unit::Abort);
+        }
+        if self.expect_data {
+            return self.data();
+        }
+        loop {
+            let Some(start) = self.buf.iter().position(|&b| b == ZPAD) else {
+                self.buf.clear();
+                return None;
+            };
+            self.buf.drain(..start);
+            let mut i = 0;
+            while self.buf.get(i) == Some(&ZPAD) {
+                i += 1;
+            }
+            match self.buf.get(i) {
+                None => return None,
+                Some(&ZDLE) => {}
+                Some(_) => {
+                    self.buf.drain(..i);
+                    continue;
+                }
+            }
+            let format = *self.buf.get(i + 1)?;
+            i += 2;
+            let parsed = match format {
+                ZHEX => self.hex_header(&mut i),
+                ZBIN => self.binary_header(&mut i, false),
+                ZBIN32 => self.binary_header(&mut i, true),
+                _ => Some(Err(())),
+            };
+            match parsed? {
+                Ok(header) => {
+                    self.buf.drain(..i);
+                    if format != ZHEX {
+                        self.crc32 = format == ZBIN32;
+                    }
+                    return Some(Unit::Header(header));
+                }
+                // Not a header after all; look past this pad
+                Err(()) => {
+                    self.buf.drain(..1);
+                }
+            }
+        }
+    }
+
+    fn hex_header(&self, i: &mut usize) -> Option<Result<Header, ()>> {
+        let digits = self.buf.get(*i..*i + 14)?;
+        let mut raw = [0u8; 7];
+        for (byte, pair) in raw.iter_mut().zip(digits.chunks(2)) {
+            let text = std::str::from_utf8(pair).map_err(|_| ());
+            match text.and_then(|t| u8::from_str_radix(t, 16).map_err(|_| ())) {
+                Ok(value) => *byte = value,
+                Err(()) => return Some(Err(())),
+            }
+        }
+        *i += 14;
+        if crc16(&[&raw[..5]]).to_be_bytes() != raw[5..] {
+            return Some(Err(()));
+        }
+        // CR, LF (sent with the high bit set) and XON
+        while matches!(self.buf.get(*i), Some(b'\r' | b'\n' | 0x8a | 0x11)) {
+            *i += 1;
+        }
+        Some(Ok(Header {
+            kind: raw[0],
+            data: [raw[1], raw[2], raw[3], raw[4]],
+        }))
+    }
+
+    fn binary_header(&self, i: &mut usize, crc32_mode: bool) -> Option<Result<Header, ()>> {
+        let crc_len = if crc32_mode { 4 } else { 2 };
+        let raw = match read_plain(&self.buf, i, 5 + crc_len)? {
+            Ok(raw) => raw,
+            Err(()) => return Some(Err(())),
+        };
+        let valid = if crc32_mode {
+            crc32(&[&raw[..5]]).to_le_bytes() == raw[5..]
+        } else {
+            crc16(&[&raw[..5]]).to_be_bytes() == raw[5..]
+        };
+        Some(
+            valid
+                .then(|| Header {
+                    kind: raw[0],
+                    data: [raw[1], raw[2], raw[3], raw[4]],
+                })
+                .ok_or(()),
+        )
+    }
+
+    fn data(&mut self) -> Option<Unit> {
+        let mut i = 0;
+        let mut data = Vec::new();
+        let end = loop {
+            match read_byte(&self.buf, &mut i)? {
+                Ok(Byte::Plain(byte)) if data.len() < MAX_SUBPACKET => data.push(byte),
+                Ok(Byte::End(end)) => break Some(end),
+                _ => break None,
+            }
+        };
+        let crc = match end {
+            Some(_) => read_plain(&self.buf, &mut i, if self.crc32 { 4 } else { 2 })?.ok(),
+            None => None,
+        };
+        self.buf.drain(..i);
+        let (Some(end), Some(crc)) = (end, crc) else {
+            self.expect_data = false;
+            return Some(Unit::BadData);
+        };
+        let valid = if self.crc32 {
+            crc32(&[&data, &[end]]).to_le_bytes()[..] == crc[..]
+        } else {
+            crc16(&[&data, &[end]]).to_be_bytes()[..] == crc[..]
+        };
+        if !valid {
+            self.expect_data = false;
+            return Some(Unit::BadData);
+        }
+        if end == ZCRCE || end == ZCRCW {
+            self.expect_data = false;
+        }
+        Some(Unit::Data(data, end))
+    }
+}
+
+/// File name from a ZFILE, without any directory part
+fn safe_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    match name {
+        "" | "." | ".." => "download".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// `dir/name`, or `dir/name (1).ext` and so on when it exists
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    (1..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, ext)))
+        .find(|path| !path.exists())
+        .expect("unbounded range")
+}
+
+enum Flow {
+    Continue,
+    Done,
+    Failed(String),
+}
+
+struct Incoming {
+    name: String,
+    path: PathBuf,
+    out: BufWriter<File>,
+    size: Option<u64>,
+    received: u64,
+    reported: u64,
+}
+
+struct Receiver {
+    dir: PathBuf,
+    zrinit: Header,
+    /// Header whose data subpacket is expected
+    pending: u8,
+    file: Option<Incoming>,
+    files: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SendPhase {
+    /// ZFILE sent, waiting for ZRPOS
+    File,
+    /// Burst sent, waiting for ZACK
+    Data,
+    /// ZEOF sent, waiting for ZRINIT
+    Eof,
+    /// ZFIN sent, waiting for the receiver's ZFIN
+    Fin,
+}
+
+struct Outgoing {
+    name: String,
+    path: PathBuf,
+    file: File,
+    size: u64,
+    pos: u64,
+    reported: u64,
+}
+
+struct Sender {
+    queue: VecDeque<PathBuf>,
+    current: Option<Outgoing>,
+    phase: SendPhase,
+    crc32: bool,
+    escape_ctl: bool,
+    files: usize,
+    /// Files and bytes still to send, announced in every ZFILE
+    bytes_left: u64,
+}
+
+enum Stage {
+    /// Opening header seen, waiting for the frontend
+    Offered(Direction),
+    Receiving(Receiver),
+    Sending(Sender),
+}
+
+type EmitFn = Box<dyn Fn(ZmodemEvent) + Send>;
+
+/// Per-session ZMODEM detector and protocol driver
+pub struct ZmodemTap {
+    stage: Option<Stage>,
+    decoder: Decoder,
+    /// End of the last chunk held back as a possible split header
+    tail: Vec<u8>,
+    /// Escape all control characters (telnet mangles CR and NUL)
+    escape_ctl: bool,
+    deadline: Option<Instant>,
+    /// The transfer just ended; its trailer and `OO` are dropped
+    trailer: bool,
+    emit: EmitFn,
+}
+
+impl ZmodemTap {
+    pub fn new(session_id: &str, app_handle: &AppHandle, escape_ctl: bool) -> Self {
+        let event = format!("session:{}:zmodem", session_id);
+        let app_handle = app_handle.clone();
+        Self::with_emitter(
+            escape_ctl,
+            Box::new(move |e| {
+                if let Err(err) = app_handle.emit(&event, &e) {
+                    warn!(error = %err, "Failed to emit ZMODEM event");
+                }
+            }),
+        )
+    }
+
+    fn with_emitter(escape_ctl: bool, emit: EmitFn) -> Self {
+        Self {
+            stage: None,
+            decoder: Decoder::default(),
+            tail: Vec::new(),
+            escape_ctl,
+            deadline: None,
+            trailer: false,
+            emit,
+        }
+    }
+
+    /// A transfer is offered or running; session output and typed input
+    /// are held back
+    pub fn active(&self) -> bool {
+        self.stage.is_some()
+    }
+
+    /// When `expire` should be called
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Raw session output in; returns the output to show and the reply to
+    /// send to the device as is
+    pub fn inbound(&mut self, data: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+        let data = self.strip_trailer(data);
+        match &self.stage {
+            None => (self.detect(data), Vec::new()),
+            Some(Stage::Offered(_)) => {
+                let room = MAX_HELD.saturating_sub(self.decoder.buf.len());
+                self.decoder
+                    .buf
+                    .extend_from_slice(&data[..data.len().min(room)]);
+                (Vec::new(), Vec::new())
+            }
+            Some(_) => {
+                self.decoder.buf.extend_from_slice(&data);
+                self.deadline = Some(Instant::now() + IDLE_TIMEOUT);
+                self.advance()
+            }
+        }
+    }
+
+    /// Act on the frontend's answer; returns the bytes to send
+    pub fn command(&mut self, command: ZmodemCommand) -> Vec<u8> {
+        let direction = match &self.stage {
+            Some(Stage::Offered(direction)) => *direction,
+            Some(_) if matches!(command, ZmodemCommand::Cancel) => {
+                return self.fail(None);
+            }
+            _ => return Vec::new(),
+        };
+        let reply = match (command, direction) {
+            (ZmodemCommand::Cancel, _) => return self.fail(None),
+            (ZmodemCommand::Receive(dir), Direction::Receive) => self.start_receive(dir),
+            (ZmodemCommand::Send(files), Direction::Send) => self.start_send(files),
+            (_, direction) => Err(format!("the device expects to {:?}", direction)),
+        };
+        match reply {
+            Ok(reply) => {
+                self.deadline = Some(Instant::now() + IDLE_TIMEOUT);
+                reply
+            }
+            Err(error) => self.fail(Some(error)),
+        }
+    }
+
+    /// The deadline passed: give up on the transfer
+    pub fn expire(&mut self) -> Vec<u8> {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return self.fail(Some("no reply from the other side".to_string()));
+        }
+        Vec::new()
+    }
+
+    fn detect(&mut self, data: Vec<u8>) -> Vec<u8> {
+        let mut joined = std::mem::take(&mut self.tail);
+        joined.extend_from_slice(&data);
+        let found = joined
+            .windows(START.len() + 1)
+            .position(|w| w.starts_with(START) && matches!(w[START.len()], b'0' | b'1'));
+        let Some(at) = found else {
+            // Hold back what may be the start of a header split across reads
+            let held = (1..=START.len())
+                .rev()
+                .find(|&n| joined.ends_with(&START[..n]))
+                .unwrap_or(0);
+            self.tail = joined.split_off(joined.len() - held);
+            return joined;
+        };
+        let direction = match joined[at + START.len()] {
+            b'0' => Direction::Receive,
+            _ => Direction::Send,
+        };
+        info!(direction = ?direction, "ZMODEM transfer offered");
+        let pads = joined[..at]
+            .iter()
+            .rev()
+            .take(2)
+            .take_while(|&&b| b == ZPAD)
+            .count();
+        self.decoder = Decoder::default();
+        self.decoder.buf = [&[ZPAD, ZPAD], &joined[at..]].concat();
+        self.decoder.buf.truncate(MAX_HELD);
+        self.stage = Some(Stage::Offered(direction));
+        self.deadline = Some(Instant::now() + DECISION_TIMEOUT);
+        (self.emit)(ZmodemEvent::Offer { direction });
+        joined.truncate(at - pads);
+        joined
+    }
+
+    fn start_receive(&mut self, dir: PathBuf) -> Result<Vec<u8>, String> {
+        if !dir.is_dir() {
+            return Err(format!("{} is not a directory", dir.display()));
+        }
+        let esc = if self.escape_ctl { ESCCTL } else { 0 };
+        let zrinit = Header::flags(ZRINIT, CANFDX | CANOVIO | CANFC32 | esc);
+        // Repeated ZRQINITs were held; one ZRINIT answers them all
+        self.decoder = Decoder::default();
+        self.stage = Some(Stage::Receiving(Receiver {
+            dir,
+            zrinit,
+            pending: 0,
+            file: None,
+            files: 0,
+        }));
+        Ok(zrinit.hex())
+    }
+
+    fn start_send(&mut self, files: Vec<PathBuf>) -> Result<Vec<u8>, String> {
+        if files.is_empty() {
+            return Err("no files to send".to_string());
+        }
+        let mut bytes_left = 0;
+        for path in &files {
+            let meta = fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            if !meta.is_file() {
+                return Err(format!("{} is not a file", path.display()));
+            }
+            bytes_left += meta.len();
+        }
+        // The capabilities of the receiver, from its held ZRINIT
+        let mut flags = 0;
+        while let Some(unit) = self.decoder.next() {
+            if let Unit::Header(header) = unit {
+                if header.kind == ZRINIT {
+                    flags = header.f0();
+                    break;
+                }
+            }
+        }
+        self.decoder = Decoder::default();
+        let mut sender = Sender {
+            queue: files.into(),
+            current: None,
+            phase: SendPhase::File,
+            crc32: flags & CANFC32 != 0,
+            escape_ctl: self.escape_ctl || flags & ESCCTL != 0,
+            files: 0,
+            bytes_left,
+        };
+        let reply = sender.next_file(&self.emit).map_err(|e| e.to_string())?;
+        self.stage = Some(Stage::Sending(sender));
+        Ok(reply)
+    }
+
+    fn advance(&mut self) -> (Vec<u8>, Vec<u8>) {
+        let mut reply = Vec::new();
+        let mut flow = Flow::Continue;
+        while let Some(unit) = self.decoder.next() {
+            flow = match &mut self.stage {
+                Some(Stage::Receiving(receiver)) => {
+                    receiver.handle(unit, &mut self.decoder, &mut reply, &self.emit)
+                }
+                Some(Stage::Sending(sender)) => sender.handle(unit, &mut reply, &self.emit),
+                _ => Flow::Continue,
+            };
+            if !matches!(flow, Flow::Continue) {
+                break;
+            }
+        }
+        match flow {
+            Flow::Continue => (Vec::new(), reply),
+            Flow::Done => {
+                let (files, received) = match self.stage.take() {
+                    Some(Stage::Receiving(receiver)) => (receiver.files, true),
+                    Some(Stage::Sending(sender)) => (sender.files, false),
+                    _ => (0, false),
+                };
+                info!(files, "ZMODEM transfer finished");
+                (self.emit)(ZmodemEvent::Finished { files });
+                let rest = std::mem::take(&mut self.decoder.buf);
+                self.reset();
+                // Only `sz` follows the closing ZFIN with `OO`
+                self.trailer = received;
+                (self.strip_trailer(rest), reply)
+            }
+            Flow::Failed(error) => {
+                reply.extend(self.fail(Some(error)));
+                (Vec::new(), reply)
+            }
+        }
+    }
+
+    /// End the transfer, removing a partly received file; `None` is a
+    /// cancel from the frontend
+    fn fail(&mut self, error: Option<String>) -> Vec<u8> {
+        if let Some(Stage::Receiving(Receiver {
+            file: Some(file), ..
+        })) = self.stage.take()
+        {
+            drop(file.out);
+            let _ = fs::remove_file(&file.path);
+        }
+        match error {
+            Some(error) => {
+                warn!(error = %error, "ZMODEM transfer failed");
+                (self.emit)(ZmodemEvent::Failed { error });
+            }
+            None => {
+                info!("ZMODEM transfer cancelled");
+                (self.emit)(ZmodemEvent::Cancelled);
+            }
+        }
+        self.reset();
+        CANCEL.to_vec()
+    }
+
+    fn reset(&mut self) {
+        self.stage = None;
+        self.decoder = Decoder::default();
+        self.deadline = None;
+        self.tail.clear();
+    }
+
+    /// Drop the rest of the last header and the sender's `OO` from the
+    /// output following a transfer
+    fn strip_trailer(&mut self, data: Vec<u8>) -> Vec<u8> {
+        if !self.trailer {
+            return data;
+        }
+        let mut oo = 0;
+        let skip = data
+            .iter()
+            .take_while(|&&b| match b {
+                b'O' if oo < 2 => {
+                    oo += 1;
+                    true
+                }
+                b'\r' | b'\n' | 0x8a | 0x11 => oo == 0,
+                _ => false,
+            })
+            .count();
+        if skip < data.len() || oo == 2 {
+            self.trailer = false;
+        }
+        data[skip..].to_vec()
+    }
+}
+
+impl Receiver {
+    fn handle(
+        &mut self,
+        unit: Unit,
+        decoder: &mut Decoder,
+        reply: &mut Vec<u8>,
+        emit: &EmitFn,
+    ) -> Flow {
+        match unit {
+            Unit::Header(header) => match header.kind {
+                ZRQINIT => reply.extend(self.zrinit.hex()),
+                ZSINIT | ZFILE => {
+                    self.pending = header.kind;
+                    decoder.expect_data = true;
+                }
+                ZDATA => match &self.file {
+                    Some(file) if header.pos() == file.received => {
+                        self.pending = ZDATA;
+                        decoder.expect_data = true;
+                    }
+                    Some(file) => reply.extend(Header::at(ZRPOS, file.received).hex()),
+                    None => {}
+                },
+                // A ZEOF for another position is stale and ignored
+                ZEOF if self
+                    .file
+                    .as_ref()
+                    .is_some_and(|file| file.received == header.pos()) =>
+                {
+                    if let Err(e) = self.close_file(emit) {
+                        return Flow::Failed(e.to_string());
+                    }
+                    reply.extend(self.zrinit.hex());
+                }
+                ZFIN => {
+                    reply.extend(Header::at(ZFIN, 0).hex());
+                    return Flow::Done;
+                }
+                ZABORT | ZCAN | ZFERR => {
+                    return Flow::Failed("the sender aborted".to_string());
+                }
+                _ => {}
+            },
+            Unit::Data(data, end) => match self.pending {
+                ZSINIT => reply.extend(Header::at(ZACK, 0).hex()),
+                ZFILE => match self.open_file(&data, emit) {
+                    Ok(()) => reply.extend(Header::at(ZRPOS, 0).hex()),
+                    Err(e) => return Flow::Failed(e.to_string()),
+                },
+                ZDATA => {
+                    let Some(file) = &mut self.file else {
+                        return Flow::Continue;
+                    };
+                    if let Err(e) = file.out.write_all(&data) {
+                        return Flow::Failed(e.to_string());
+                    }
+                    file.received += data.len() as u64;
+                    if file.received - file.reported >= PROGRESS_STEP {
+                        file.reported = file.received;
+                        emit(ZmodemEvent::Progress {
+                            name: file.name.clone(),
+                            bytes: file.received,
+                            size: file.size,
+                        });
+                    }
+                    if end == ZCRCW || end == ZCRCQ {
+                        reply.extend(Header::at(ZACK, file.received).hex());
+                    }
+                }
+                _ => {}
+            },
+            Unit::BadData => {
+                if let Some(file) = &self.file {
+                    reply.extend(Header::at(ZRPOS, file.received).hex());
+                }
+            }
+            Unit::Abort => return Flow::Failed("the sender cancelled".to_string()),
+        }
+        Flow::Continue
+    }
+
+    /// Start the file a ZFILE announced: `name NUL size mtime mode ...`
+    fn open_file(&mut self, info: &[u8], emit: &EmitFn) -> io::Result<()> {
+        let mut fields = info.split(|&b| b == 0);
+        let name = safe_name(&String::from_utf8_lossy(fields.next().unwrap_or_default()));
+        let size = fields
+            .next()
+            .map(String::from_utf8_lossy)
+            .and_then(|meta| meta.split_whitespace().next()?.parse().ok());
+        let path = unique_path(&self.dir, &name);
+        let out = BufWriter::new(File::create_new(&path)?);
+        info!(name = %name, size = ?size, path = %path.display(), "Receiving file over ZMODEM");
+        emit(ZmodemEvent::File {
+            name: name.clone(),
+            size,
+        });
+        self.file = Some(Incoming {
+            name,
+            path,
+            out,
+            size,
+            received: 0,
+            reported: 0,
+        });
+        Ok(())
+    }
+
+    fn close_file(&mut self, emit: &EmitFn) -> io::Result<()> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(());
+        };
+        file.out.flush()?;
+        self.files += 1;
+        emit(ZmodemEvent::FileDone {
+            name: file.name,
+            path: file.path.to_string_lossy().into_owned(),
+            bytes: file.received,
+        });
+        Ok(())
+    }
+}
+
+impl Sender {
+    fn handle(&mut self, unit: Unit, reply: &mut Vec<u8>, emit: &EmitFn) -> Flow {
+        let header = match unit {
+            Unit::Header(header) => header,
+            Unit::Abort => return Flow::Failed("the receiver cancelled".to_string()),
+            Unit::Data(..) | Unit::BadData => return Flow::Continue,
+        };
+        let result = match (header.kind, self.phase) {
+            // The receiver missed our last header
+            (ZRINIT | ZNAK, SendPhase::File) => self.zfile(),
+            (ZNAK, SendPhase::Eof) => Ok(self.zeof()),
+            (ZRINIT | ZNAK, SendPhase::Fin) => Ok(Header::at(ZFIN, 0).hex()),
+            (ZRINIT, SendPhase::Eof) => {
+                if let Some(file) = self.current.take() {
+                    self.files += 1;
+                    emit(ZmodemEvent::FileDone {
+                        name: file.name,
+                        path: file.path.to_string_lossy().into_owned(),
+                        bytes: file.size,
+                    });
+                }
+                self.next_file(emit)
+            }
+            (ZRPOS, SendPhase::File | SendPhase::Data | SendPhase::Eof) => {
+                self.seek(header.pos(), emit)
+            }
+            (ZACK, SendPhase::Data) => {
+                match &self.current {
+                    Some(file) if file.pos == header.pos() => self.burst(emit),
+                    // Acknowledges data sent before a seek
+                    _ => Ok(Vec::new()),
+                }
+            }
+            (ZSKIP, _) => {
+                if let Some(file) = self.current.take() {
+                    self.bytes_left = self.bytes_left.saturating_sub(file.size);
+                    emit(ZmodemEvent::Skipped { name: file.name });
+                }
+                self.next_file(emit)
+            }
+            (ZFIN, SendPhase::Fin) => {
+                reply.extend(b"OO");
+                return Flow::Done;
+            }
+            (ZABORT | ZFERR | ZCAN, _) => return Flow::Failed("the receiver aborted".to_string()),
+            _ => Ok(Vec::new()),
+        };
+        match result {
+            Ok(bytes) => {
+                reply.extend(bytes);
+                Flow::Continue
+            }
+            Err(e) => Flow::Failed(e.to_string()),
+        }
+    }
+
+    /// Announce the next queued file, or finish with ZFIN
+    fn next_file(&mut self, emit: &EmitFn) -> io::Result<Vec<u8>> {
+        let Some(path) = self.queue.pop_front() else {
+            self.phase = SendPhase::Fin;
+            return Ok(Header::at(ZFIN, 0).hex());
+        };
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "upload".to_string());
+        info!(name = %name, size, "Sending file over ZMODEM");
+        emit(ZmodemEvent::File {
+            name: name.clone(),
+            size: Some(size),
+        });
+        self.current = Some(Outgoing {
+            name,
+            path,
+            file,
+            size,
+            pos: 0,
+            reported: 0,
+        });
+        self.zfile()
+    }
+
+    fn zfile(&mut self) -> io::Result<Vec<u8>> {
+        let Some(file) = &self.current else {
+            return Ok(Vec::new());
+        };
+        self.phase = SendPhase::File;
+        let mtime = file
+            .file
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        let mut info = file.name.as_bytes().to_vec();
+        info.push(0);
+        info.extend(
+            format!(
+                "{} {:o} 100644 0 {} {}",
+                file.size,
+                mtime,
+                self.queue.len() + 1,
+                self.bytes_left
+            )
+            .bytes(),
+        );
+        info.push(0);
+        let mut out = Header::flags(ZFILE, ZCBIN).binary(self.crc32, self.escape_ctl);
+        out.extend(subpacket(&info, ZCRCW, self.crc32, self.escape_ctl));
+        Ok(out)
+    }
+
+    fn zeof(&self) -> Vec<u8> {
+        let size = self.current.as_ref().map_or(0, |file| file.size);
+        Header::at(ZEOF, size).binary(self.crc32, self.escape_ctl)
+    }
+
+    fn seek(&mut self, pos: u64, emit: &EmitFn) -> io::Result<Vec<u8>> {
+        let Some(file) = &mut self.current else {
+            return Ok(Vec::new());
+        };
+        file.pos = pos.min(file.size);
+        file.file.seek(SeekFrom::Start(file.pos))?;
+        self.burst(emit)
+    }
+
+    /// ZDATA and up to `BURST` bytes, ending with ZCRCW to wait for the
+    /// receiver, or with ZCRCE and ZEOF at the end of the file
+    fn burst(&mut self, emit: &EmitFn) -> io::Result<Vec<u8>> {
+        let Some(file) = &mut self.current else {
+            return Ok(Vec::new());
+        };
+        let mut out = Header::at(ZDATA, file.pos).binary(self.crc32, self.escape_ctl);
+        let burst_end = file.pos + BURST;
+        let mut chunk = [0u8; SUBPACKET];
+        loop {
+            let n = file.file.read(&mut chunk)?;
+            file.pos += n as u64;
+            // A file that shrank while sending ends where it was read
+            if n == 0 {
+                file.size = file.pos;
+            }
+            let end = if file.pos >= file.size {
+                ZCRCE
+            } else if file.pos >= burst_end {
+                ZCRCW
+            } else {
+                ZCRCG
+            };
+            out.extend(subpacket(&chunk[..n], end, self.crc32, self.escape_ctl));
+            if end == ZCRCG {
+                continue;
+            }
+            if file.pos - file.reported >= PROGRESS_STEP || end == ZCRCE {
+                file.reported = file.pos;
+                emit(ZmodemEvent::Progress {
+                    name: file.name.clone(),
+                    bytes: file.pos,
+                    size: Some(file.size),
+                });
+            }
+            if end == ZCRCE {
+                self.bytes_left = self.bytes_left.saturating_sub(file.size);
+                self.phase = SendPhase::Eof;
+                out.extend(self.zeof());
+            } else {
+                self.phase = SendPhase::Data;
+            }
+            return Ok(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn tap(escape_ctl: bool) -> (ZmodemTap, Arc<Mutex<Vec<ZmodemEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let tap =
+            ZmodemTap::with_emitter(escape_ctl, Box::new(move |e| sink.lock().unwrap().push(e)));
+        (tap, events)
+    }
+
+    #[test]
+    fn test_transfer_between_taps() {
+        assert_eq!(crc16(&[b"123456789"]), 0x31C3);
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF4_3926);
+        assert_eq!(
+            Header::at(ZRQINIT, 0).hex(),
+            b"**\x18B00000000000000\r\x8a\x11".to_vec()
+        );
+        assert_eq!(safe_name("../../etc/passwd"), "passwd");
+        assert_eq!(safe_name("C:\\tmp\\.."), "download");
+
+        let dir = std::env::temp_dir().join(format!("bspt-zmodem-test-{}", uuid::Uuid::new_v4()));
+        let inbox = dir.join("inbox");
+        fs::create_dir_all(&inbox).unwrap();
+        // Every byte value, ZDLE and flow control included, over several bursts
+        let content: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let source = dir.join("fw.bin");
+        fs::write(&source, &content).unwrap();
+        fs::write(inbox.join("fw.bin"), b"older").unwrap();
+
+        // The device side runs `rz`, which the sending tap answers; the
+        // receiving tap plays the app for a device running `sz`
+        let (mut sender, sent_events) = tap(false);
+        let (mut receiver, received_events) = tap(true);
+
+        let (shown, reply) = receiver.inbound(b"$ sz fw.bin\r\n**\x18B0".to_vec());
+        assert_eq!(shown, b"$ sz fw.bin\r\n**");
+        assert!(reply.is_empty() && !receiver.active());
+        // The header's type digit arrives in the next read
+        let (shown, _) = receiver.inbound(b"0000000000000\r\x8a\x11".to_vec());
+        assert!(shown.is_empty() && receiver.active());
+        assert_eq!(
+            received_events.lock().unwrap()[0],
+            ZmodemEvent::Offer {
+                direction: Direction::Receive
+            }
+        );
+        let to_sender = receiver.command(ZmodemCommand::Receive(inbox.clone()));
+
+        let (shown, _) = sender.inbound([b"rz ready\r\n".as_slice(), &to_sender].concat());
+        assert_eq!(shown, b"rz ready\r\n");
+        let mut to_receiver = sender.command(ZmodemCommand::Send(vec![source.clone()]));
+
+        let mut corrupted = false;
+        for _ in 0..200 {
+            let mut data = std::mem::take(&mut to_receiver);
+            // One damaged subpacket, recovered with ZRPOS
+            if !corrupted && data.len() > 5000 {
+                data[3000] ^= 0x01;
+                corrupted = true;
+            }
+            let (shown, to_sender) = receiver.inbound(data);
+            assert!(shown.is_empty() && !to_sender.is_empty());
+            to_receiver = sender.inbound(to_sender).1;
+            if !sender.active() {
+                break;
+            }
+        }
+        assert!(corrupted && !receiver.active());
+        assert_eq!(to_receiver, b"OO");
+        let (shown, _) = receiver.inbound([to_receiver.as_slice(), b"$ "].concat());
+        assert_eq!(shown, b"$ ");
+
+        let saved = inbox.join("fw (1).bin");
+        assert_eq!(fs::read(&saved).unwrap(), content);
+        assert_eq!(fs::read(inbox.join("fw.bin")).unwrap(), b"older");
+        let received = received_events.lock().unwrap();
+        assert!(received.contains(&ZmodemEvent::FileDone {
+            name: "fw.bin".to_string(),
+            path: saved.to_string_lossy().into_owned(),
+            bytes: 40_000,
+        }));
+        assert_eq!(received.last(), Some(&ZmodemEvent::Finished { files: 1 }));
+        assert_eq!(
+            sent_events.lock().unwrap().last(),
+            Some(&ZmodemEvent::Finished { files: 1 })
+        );
+
+        // Cancelling an offer aborts the device's side
+        let (mut cancelled, events) = tap(false);
+        cancelled.inbound(Header::flags(ZRINIT, CANFC32).hex());
+        assert_eq!(cancelled.command(ZmodemCommand::Cancel), CANCEL);
+        assert!(!cancelled.active());
+        assert_eq!(events.lock().unwrap().last(), Some(&ZmodemEvent::Cancelled));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,17 @@
 import { memo } from "react";
 import { Handle, Position, NodeProps } from "reactflow";
-import type { TraceEvent } from "../../types";
+import type { Severity, TraceEvent } from "../../types";
 import { openInVSCode } from "../../utils/vscode";
 
+// Border color per log severity; unknown/trace keep the neutral border
+const SEVERITY_COLORS: Partial<Record<Severity, string>> = {
+  fatal: "#d32f2f",
+  error: "#f44336",
+  warn: "#ffb300",
+  info: "#64b5f6",
+  debug: "#81c784",
+};
+
 export interface TraceNodeData {
   label: string;
   trace: TraceEvent;
@@ -20,13 +29,15 @@ function TraceNodeComponent({ data }: NodeProps<TraceNodeData>) {
 
   return (
     <div
-      className="trace-node"
+      className={`trace-node severity-${trace.severity ?? "unknown"}`}
       onClick={handleClick}
       style={{
         padding: "8px 12px",
         borderRadius: "6px",
         background: "rgba(30, 30, 30, 0.9)",
-        border: "1px solid rgba(100, 100, 100, 0.5)",
+        border: `1px solid ${
+          SEVERITY_COLORS[trace.severity] ?? "rgba(100, 100, 100, 0.5)"
+        }`,
         cursor: "pointer",
         minWidth: "150px",
         fontSize: "12px",
@@ -13,6 +13,7 @@ import "@xterm/xterm/css/xterm.css";
 import { useThemeStore } from "../../stores/themeStore";
 import { useBlockStore } from "../../stores/blockStore";
 import { BlockDetector } from "../../utils/blockDetector";
+import { decodePayload, SessionPayload } from "../../utils/payload";
 import { getCurrentBufferLine } from "../../hooks/useGutterSync";
 import { BlockMarker } from "../../types/session";
 import { formatBlockTime, stripAnsi } from "../../utils/blockDetector";
@@ -221,10 +222,10 @@ export function BlockTerminal({ sessionId, activeBlockId }: BlockTerminalProps)
     let unlistenState: UnlistenFn | null = null;
 
     const setupListeners = async () => {
-      unlistenData = await listen<number[]>(
+      unlistenData = await listen<SessionPayload>(
         `session:${sessionId}`,
         (event) => {
-          const bytes = new Uint8Array(event.payload);
+          const bytes = decodePayload(event.payload);
           const text = new TextDecoder().decode(bytes);
 
           // Write to terminal
@@ -7,6 +7,7 @@ import { invoke } from "@tauri-apps/api/core";
 import { listen, UnlistenFn } from "@tauri-apps/api/event";
 import "@xterm/xterm/css/xterm.css";
 import { useThemeStore } from "../../stores/themeStore";
+import { decodePayload, SessionPayload } from "../../utils/payload";
 
 interface TerminalViewProps {
   sessionId: string;
@@ -109,10 +110,10 @@ export function TerminalView({ sessionId }: TerminalViewProps) {
     let unlistenState: UnlistenFn | null = null;
 
     const setupListeners = async () => {
-      unlistenData = await listen<number[]>(
+      unlistenData = await listen<SessionPayload>(
         `session:${sessionId}`,
         (event) => {
-          const bytes = new Uint8Array(event.payload);
+          const bytes = decodePayload(event.payload);
           term.write(bytes);
         }
       );
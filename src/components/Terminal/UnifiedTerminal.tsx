@@ -12,6 +12,8 @@ import { useBlockStore } from "../../stores/blockStore";
 import { useTracerStore } from "../../stores/tracerStore";
 import { useCommandBarStore } from "../../stores/commandBarStore";
 import { BlockDetector } from "../../utils/blockDetector";
+import { decodePayload, SessionPayload } from "../../utils/payload";
+import type { DataLossEvent, SkipSummary } from "../../types/session";
 import { useGutterSync, getCurrentBufferLine, useCollapsedRanges, useTerminalPool } from "../../hooks";
 import type { TerminalInstance } from "../../hooks";
 import { GutterOverlay } from "./GutterOverlay";
@@ -498,10 +500,13 @@ export function UnifiedTerminal({ sessionId, activeMarkerId: _activeMarkerId }:
     // === Setup Tauri listeners (only for new instances) ===
     let unlistenData: UnlistenFn | null = null;
     let unlistenState: UnlistenFn | null = null;
+    let unlistenDataLoss: UnlistenFn | null = null;
+    let unlistenSkipped: UnlistenFn | null = null;
 
     if (isNewInstance) {
+      // Backend coalesces output into batches, so acknowledge every write
       let pendingWrites = 0;
-      const BATCH_THRESHOLD = 10;
+      const BATCH_THRESHOLD = 1;
 
       const notifyDrain = () => {
         invoke("notify_buffer_drained", { sessionId }).catch((e) => {
@@ -510,10 +515,10 @@ export function UnifiedTerminal({ sessionId, activeMarkerId: _activeMarkerId }:
       };
 
       const setupListeners = async () => {
-        unlistenData = await listen<number[]>(
+        unlistenData = await listen<SessionPayload>(
           `session:${sessionId}`,
           (event) => {
-            const bytes = new Uint8Array(event.payload);
+            const bytes = decodePayload(event.payload);
             const text = new TextDecoder().decode(bytes);
 
             pendingWrites++;
@@ -545,11 +550,33 @@ export function UnifiedTerminal({ sessionId, activeMarkerId: _activeMarkerId }:
           }
         );
 
+        // Backend dropped output under a drop overflow policy
+        unlistenDataLoss = await listen<DataLossEvent>(
+          `session:${sessionId}:data_loss`,
+          (event) => {
+            const kb = (event.payload.dropped_bytes / 1024).toFixed(1);
+            term.write(`\r\n\x1b[33m[output dropped: ${kb} KB]\x1b[0m\r\n`);
+          }
+        );
+
+        // Backend skipped output over the session rate limit
+        unlistenSkipped = await listen<SkipSummary>(
+          `session:${sessionId}:skipped`,
+          (event) => {
+            const { skipped_bytes, skipped_lines } = event.payload;
+            const mb = (skipped_bytes / (1024 * 1024)).toFixed(1);
+            const lines = skipped_lines.toLocaleString("en-US");
+            term.write(`\r\n\x1b[33m[skipped ${mb} MB / ${lines} lines]\x1b[0m\r\n`);
+          }
+        );
+
         // Update pool instance with listeners
         const inst = pool.get(sessionId);
         if (inst) {
           inst.unlistenData = unlistenData ?? undefined;
           inst.unlistenState = unlistenState ?? undefined;
+          inst.unlistenDataLoss = unlistenDataLoss ?? undefined;
+          inst.unlistenSkipped = unlistenSkipped ?? undefined;
         }
       };
 
@@ -18,6 +18,8 @@ export interface TerminalInstance {
   detector: BlockDetector;
   unlistenData?: UnlistenFn;
   unlistenState?: UnlistenFn;
+  unlistenDataLoss?: UnlistenFn;
+  unlistenSkipped?: UnlistenFn;
   // Disposables for cleanup
   disposables: Array<{ dispose: () => void }>;
 }
@@ -66,6 +68,8 @@ export function useTerminalPool() {
         // Cleanup event listeners
         instance.unlistenData?.();
         instance.unlistenState?.();
+        instance.unlistenDataLoss?.();
+        instance.unlistenSkipped?.();
 
         // Cleanup disposables (xterm event handlers)
         instance.disposables.forEach((d) => d.dispose());
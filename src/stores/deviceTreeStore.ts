@@ -18,6 +18,7 @@ import {
   ReconnectStatus,
   ReconnectPolicy,
 } from "../types/session";
+import { DEFAULT_PAYLOAD_ENCODING } from "../utils/payload";
 
 interface DeviceTreeState {
   // Data
@@ -583,6 +584,8 @@ export const useDeviceTreeStore = create<DeviceTreeState>()(
             password,
             cols: 80,
             rows: 24,
+            payload_encoding: DEFAULT_PAYLOAD_ENCODING,
+            charset: "auto",
           };
 
           const sessionId = await invoke<string>("create_session", { config });
@@ -1,5 +1,6 @@
 import { create } from "zustand";
 import { invoke } from "@tauri-apps/api/core";
+import { PayloadEncoding } from "../types/session";
 
 export type Protocol = "ssh" | "telnet";
 
@@ -19,6 +20,7 @@ export interface SessionConfig {
   password: string;
   cols: number;
   rows: number;
+  payload_encoding?: PayloadEncoding;
 }
 
 export interface Session {
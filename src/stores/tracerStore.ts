@@ -58,7 +58,10 @@ export const useTracerStore = create<TracerState>()(
         if (!indexed) return null;
 
         try {
-          const location = await invoke<SourceLocation | null>("match_log_line", { line });
+          const location = await invoke<SourceLocation | null>("match_log_line", {
+            line,
+            sessionId,
+          });
           if (location) {
             // Auto-create trace event when match is found
             const event: TraceEvent = {
@@ -66,6 +69,7 @@ export const useTracerStore = create<TracerState>()(
               file: location.file,
               line: location.line,
               function: location.function,
+              severity: location.severity,
               timestamp: new Date(),
               matched_text: line.substring(0, 50),
               log_line: line,
@@ -104,9 +104,9 @@ export type SessionTreeNodeApi = NodeApi<TreeNodeData>;
 
 // VRP events emitted from backend
 export interface VrpEvent {
-  type: "view_change" | "pagination" | "board_info";
+  type: "view_change" | "pagination" | "board_info" | "command_error";
   sessionId: string;
-  data: VrpViewChange | VrpPagination | VrpBoardInfo;
+  data: VrpViewChange | VrpPagination | VrpBoardInfo | VrpCommandError;
 }
 
 export interface VrpViewChange {
@@ -119,6 +119,10 @@ export interface VrpPagination {
   autoHandled: boolean;
 }
 
+export interface VrpCommandError {
+  message: string;
+}
+
 export interface VrpBoardInfo {
   slot_id: string;
   sub_slot: string;
@@ -127,6 +131,231 @@ export interface VrpBoardInfo {
   ip?: string;
 }
 
+// Character set of device output
+export type Charset = "utf8" | "gbk" | "gb18030" | "latin1" | "auto";
+
+// Wire format of session data events
+export type PayloadEncoding = "json" | "base64";
+
+// Output buffer capacity and watermarks (set_buffer_config)
+export interface BufferConfig {
+  capacity: number;
+  high_watermark_percent: number;
+  low_watermark_percent: number;
+  overflow_policy: OverflowPolicy;
+}
+
+// Output buffer snapshot (get_buffer_stats / session:{id}:buffer event)
+export interface BufferStats {
+  capacity: number;
+  len: number;
+  fill_percent: number;
+  paused: boolean;
+  total_pushed: number;
+  total_dropped: number;
+  pause_count: number;
+  resume_count: number;
+  // Arrival of the oldest/newest buffered chunk, ms since the buffer was created
+  first_chunk_ms: number | null;
+  last_chunk_ms: number | null;
+  // Acked delivery: emitted bytes awaiting ack_output, highest acked seq
+  // (0 before the first ack), chunks resent after the ack timeout
+  unacked: number;
+  acked_seq: number;
+  redelivered: number;
+}
+
+// How session:{id} data reaches the frontend
+export type DeliveryMode = "best_effort" | "acked";
+
+// SessionConfig.delivery; "acked" needs the block overflow policy and no
+// rate limit
+export interface DeliveryConfig {
+  mode: DeliveryMode;
+  ack_timeout_ms: number;
+  max_unacked_bytes: number;
+}
+
+// session:{id} body with acked delivery: ack_output(seq) once handled;
+// skip a seq already seen, resent chunks keep theirs
+export interface AckedPayload {
+  seq: number;
+  data: number[] | string;
+}
+
+// Buffer overflow policy
+export type OverflowPolicy = "block" | "drop_oldest" | "drop_newest";
+
+// Payload of session:{id}:data_loss event
+export interface DataLossEvent {
+  dropped_bytes: number;
+  total_dropped: number;
+}
+
+// Output rate limit; skipped output is summarized and still logged
+export interface RateLimitConfig {
+  max_bytes_per_sec: number;
+  summary_interval_ms: number;
+}
+
+// Payload of session:{id}:skipped event
+export interface SkipSummary {
+  skipped_bytes: number;
+  skipped_lines: number;
+}
+
+// Aggregate buffered output across sessions (get_memory_stats)
+export interface MemoryStats {
+  cap: number;
+  used: number;
+  sessions: number;
+  fair_share: number;
+}
+
+// Page of backend scrollback lines (fetch_scrollback)
+export interface ScrollbackPage {
+  first_line: number;
+  end_line: number;
+  start_line: number;
+  lines: string[];
+}
+
+// add_marker / list_markers; fetch_scrollback takes a marker_id and "before"
+export interface Marker {
+  id: string;
+  label: string;
+  // Scrollback line: the prompt line if output ended in one
+  line: number;
+  // Output bytes received before the marker
+  offset: number;
+  // Unix milliseconds
+  at: number;
+}
+
+// get_pipeline / set_pipeline_stage, in data path order
+export type PipelineStage =
+  | "transport"
+  | "charset"
+  | "processors"
+  | "lines"
+  | "dialect"
+  | "triggers"
+  | "tracer"
+  | "emit";
+
+export interface StageStatus {
+  stage: PipelineStage;
+  enabled: boolean;
+  // Enabled and fed by every stage it reads from
+  active: boolean;
+}
+
+// attach_view / list_views and the "session:{id}:views" event. An attached
+// window drops "session:{id}" data until "session:{id}:replay" arrives.
+export interface View {
+  // Window label
+  label: string;
+  // Last size its terminal reported
+  cols: number | null;
+  rows: number | null;
+}
+
+// "local" is ssh -L, "remote" is ssh -R, "dynamic" is ssh -D (SOCKS5)
+export type ForwardKind = "local" | "remote" | "dynamic";
+
+// A port forward over an SSH session (open_local_forward /
+// open_remote_forward / open_dynamic_forward / list_forwards)
+export interface ForwardInfo {
+  id: string;
+  session_id: string;
+  kind: ForwardKind;
+  // Address listened on, locally or on the server by kind, e.g. "127.0.0.1:8443"
+  listen: string;
+  // null for a dynamic forward, whose clients name their targets
+  target_host: string | null;
+  target_port: number | null;
+  // Connections carried right now and since the forward opened
+  active: number;
+  total: number;
+  // Connections refused by the server or target, or bad SOCKS requests
+  failed: number;
+  // Counted as connections close
+  bytes_to_target: number;
+  bytes_from_target: number;
+}
+
+export type SftpEntryKind = "file" | "directory" | "symlink" | "other";
+
+export interface SftpEntry {
+  name: string;
+  // Absolute path on the device
+  path: string;
+  kind: SftpEntryKind;
+  size: number | null;
+  // Mode bits without the file type, e.g. 0o644
+  permissions: number | null;
+  // Unix seconds
+  modified: number | null;
+}
+
+// Result of sftp_list: directories first, then files, by name
+export interface SftpListing {
+  path: string;
+  entries: SftpEntry[];
+}
+
+// Payload of session:{id}:sftp events and result of sftp_download / sftp_upload
+export interface SftpTransfer {
+  id: string;
+  session_id: string;
+  direction: "download" | "upload";
+  remote_path: string;
+  local_path: string;
+  bytes: number;
+  // null when the server doesn't report the size
+  total: number | null;
+  done: boolean;
+  error: string | null;
+}
+
+// Scrollback line matched by search_output (escape sequences stripped)
+export interface SearchMatch {
+  line: number;
+  text: string;
+}
+
+// Output pipeline benchmark (run_benchmark)
+export type BenchmarkMode = "pipeline" | "telnet";
+
+export interface BenchmarkOptions {
+  total_bytes?: number;
+  chunk_size?: number;
+  payload_encoding?: PayloadEncoding;
+}
+
+export interface BenchmarkReport {
+  mode: BenchmarkMode;
+  payload_encoding: PayloadEncoding;
+  bytes: number;
+  events: number;
+  duration_ms: number;
+  throughput_mb_s: number;
+  latency_p50_ms: number;
+  latency_p90_ms: number;
+  latency_p99_ms: number;
+  latency_max_ms: number;
+}
+
+// SSH authentication; "public_key" takes a key path or an inline PEM
+export type SshAuth =
+  | { method: "password" }
+  | {
+      method: "public_key";
+      path?: string | null;
+      pem?: string | null;
+      passphrase?: string | null;
+    };
+
 // Session config for creating connections
 export interface SessionConfig {
   host: string;
@@ -134,8 +363,1401 @@ export interface SessionConfig {
   protocol: Protocol;
   username: string;
   password: string;
+  // Defaults to password
+  auth?: SshAuth;
+  cols: number;
+  rows: number;
+  payload_encoding?: PayloadEncoding;
+  // Omitted fields fall back to the global settings
+  buffer?: BufferConfig | null;
+  charset?: Charset;
+  rate_limit?: RateLimitConfig | null;
+  delivery?: DeliveryConfig | null;
+  log_file?: string | null;
+  log_timestamps?: boolean;
+  // Raw byte capture from connect, see replay_capture
+  capture_file?: string | null;
+  keepalive?: KeepaliveConfig | null;
+  // Each hop's TCP connect and SSH handshake; null is 15000, 0 waits for the OS
+  connect_timeout_ms?: number | null;
+  activity?: ActivityConfig | null;
+  // null uses "xterm-256color"
+  terminal_type?: string | null;
+  newline?: NewlineMode;
+  dialect?: DeviceDialect;
+  // SSH hosts to tunnel through, outermost first (SSH only)
+  jump_hosts?: JumpHost[];
+  // Offered to the target and every jump host, null uses the defaults (SSH only)
+  algorithms?: SshAlgorithms | null;
+  // Rekey limits and approved-only algorithms, null keeps the defaults (SSH only)
+  hardening?: SshHardening | null;
+  // Tried in order on the target, empty is publickey, keyboard-interactive, password (SSH only)
+  auth_methods?: AuthMethod[];
+  // Subsystem instead of a shell, e.g. "netconf", without a PTY (SSH only)
+  subsystem?: string | null;
+  // Sent before the shell starts, e.g. LANG; TERM replaces terminal_type (SSH only)
+  env?: Record<string, string>;
+  plugins?: PluginSelection;
+}
+
+export type AuthMethod = "public_key" | "keyboard_interactive" | "password";
+
+// Payload of session:{id}:auth, sent just before the ready state
+export interface AuthReport {
+  method: AuthMethod;
+  // Tried before `method`, in order
+  rejected: AuthMethod[];
+}
+
+// "legacy" adds diffie-hellman-group1/14-sha1, ssh-rsa and aes-cbc after the defaults
+export type AlgorithmPreset = "modern" | "legacy";
+
+// Algorithm names most preferred first, e.g. kex: ["diffie-hellman-group1-sha1"];
+// a list left empty comes from the preset
+export interface SshAlgorithms {
+  preset?: AlgorithmPreset;
+  kex?: string[];
+  host_key?: string[];
+  cipher?: string[];
+  mac?: string[];
+}
+
+export interface SshHardening {
+  // Bytes each way between key exchanges, at most 1 GiB; 0 keeps 1 GiB
+  rekey_bytes?: number;
+  // 0 keeps one hour
+  rekey_secs?: number;
+  // Only AES-GCM/AES-CTR ciphers and SHA-2 MACs
+  approved_only?: boolean;
+}
+
+// KEXINIT name-lists; cipher, mac and compression are client-to-server
+export interface SshAlgorithmLists {
+  kex: string[];
+  host_key: string[];
+  cipher: string[];
+  mac: string[];
+  compression: string[];
+}
+
+// First client algorithm the server offers, null where none matches
+export interface SshNegotiated {
+  kex: string | null;
+  host_key: string | null;
+  cipher: string | null;
+  // "<implicit>" with an AEAD cipher
+  mac: string | null;
+  compression: string | null;
+}
+
+// Payload of session:{id}:ssh_info, after authentication or a failed connect
+export interface SshInfo {
+  session_id: string;
+  host: string;
+  port: number;
+  // e.g. "SSH-2.0-HUAWEI-1.5"
+  server_version: string | null;
+  client_version: string | null;
+  // Pre-auth banner
+  banner: string | null;
+  // e.g. "ssh-rsa SHA256:..."
+  host_key: string | null;
+  server_algorithms: SshAlgorithmLists | null;
+  client_algorithms: SshAlgorithmLists | null;
+  negotiated: SshNegotiated | null;
+  error: string | null;
+}
+
+export interface JumpHost {
+  host: string;
+  port: number;
+  username: string;
+  password?: string;
+  // Password or private key, independent of the target's
+  auth?: SshAuth;
+}
+
+export type DeviceDialect = "vrp" | "ios" | "comware" | "mml" | "linux" | "generic";
+
+// Line ending sent for Enter
+export type NewlineMode = "cr" | "cr_lf" | "lf";
+
+export interface KeepaliveConfig {
+  // 0 disables probing
+  interval_secs: number;
+  // 0 never gives up
+  max_missed: number;
+  // SSH only: disconnect after this long without server data, 0 never
+  inactivity_timeout_secs?: number;
+}
+
+// session:{id}:idle and session:{id}:active thresholds
+export interface ActivityConfig {
+  // 0 disables
+  idle_after_secs: number;
+  // Output after going idle that counts as active again
+  active_min_bytes: number;
+}
+
+export type ActivityEvent =
+  | { state: "idle"; silent_ms: number }
+  | { state: "active"; idle_ms: number; bytes: number };
+
+// Recent connections (get_recent_connections / connect_recent)
+export interface ConnectionRecord {
+  id: string;
+  session_id: string;
+  host: string;
+  port: number;
+  protocol: Protocol;
+  username: string;
+  // Without pem and passphrase
+  auth?: SshAuth;
+  cols: number;
+  rows: number;
+  charset: Charset;
+  payload_encoding: PayloadEncoding;
+  terminal_type: string | null;
+  newline: NewlineMode;
+  dialect: DeviceDialect;
+  // Without passwords
+  jump_hosts: JumpHost[];
+  algorithms: SshAlgorithms | null;
+  hardening: SshHardening | null;
+  connect_timeout_ms: number | null;
+  auth_methods: AuthMethod[];
+  subsystem: string | null;
+  env: Record<string, string>;
+  plugins: PluginSelection;
+  // Unix time in milliseconds
+  connected_at: number;
+  // null while connected, or if the app exited first
+  duration_ms: number | null;
+}
+
+// Global settings (get_settings / set_settings, "settings-changed" event)
+export interface Settings {
+  reconnect: {
+    enabled: boolean;
+    max_retries: number;
+    initial_delay_ms: number;
+    max_delay_ms: number;
+    backoff_multiplier: number;
+  };
+  buffer: BufferConfig;
+  logging: {
+    session_log_dir: string | null;
+  };
+  archive: {
+    // null disables the device archive
+    dir: string | null;
+    // 0 keeps files forever
+    max_age_days: number;
+    // 0 is unlimited
+    max_device_mb: number;
+  };
+  keepalive: KeepaliveConfig;
+  activity: ActivityConfig;
+  tracer: TracerLimits;
+  vault: {
+    // 0 never locks
+    auto_lock_secs: number;
+  };
+  backup: BackupSettings;
+  deep_links: {
+    enabled: boolean;
+    // Open without confirmation: "10.0.0.1", "*.lab.example", "10.1.0.0/16"
+    trusted_hosts: string[];
+  };
+  forwarding: ForwardSettings;
+}
+
+// Remote event forwarding (forwarding settings / get_forwarder_status)
+export type ForwardKind = "session" | "alert" | "trigger";
+
+export interface ForwardSettings {
+  // udp://host[:port], tcp://host[:port] (syslog, 514) or http://host/path; null disables
+  url: string | null;
+  // Empty forwards all kinds
+  events: ForwardKind[];
+  // Least severe alert forwarded; null is any
+  min_severity: AlertSeverity | null;
+}
+
+// Body of each forwarded event (the syslog message or HTTP POST body)
+export interface ForwardRecord {
+  app: "bspt";
+  kind: ForwardKind;
+  // Unix milliseconds
+  at: number;
+  severity: AlertSeverity;
+  host: string | null;
+  session_id: string | null;
+  message: string;
+  // ConnectionRecord, Alert or { rule_id, rule_name }
+  data: unknown;
+}
+
+export interface ForwarderStatus {
+  sent: number;
+  // Queue full
+  dropped: number;
+  // Collector unreachable or refused
+  failed: number;
+  last_error: string | null;
+  last_sent_at: number | null;
+}
+
+export interface BackupSettings {
+  // 0 disables scheduled backups
+  interval_mins: number;
+  profile_ids: string[];
+  // Default "display current-configuration"
+  command: string;
+  // Snapshots kept per device
+  keep: number;
+}
+
+// Config backups (backup_configs / list_config_backups / diff_config_backups)
+export interface Snapshot {
+  id: string;
+  // Unix seconds
+  taken_at: number;
+  // SHA-256, hex
+  hash: string;
+  lines: number;
+  bytes: number;
+}
+
+export interface BackupResult {
+  device_id: string;
+  name: string;
+  snapshot: Snapshot | null;
+  // False when the config matched the latest snapshot
+  changed: boolean;
+  error: string | null;
+}
+
+export interface DiffHunk {
+  old_start: number;
+  new_start: number;
+  lines: { op: "same" | "added" | "removed"; text: string }[];
+}
+
+// open_deep_link / list_deep_links, "deep-link" and "deep-link:confirm" events
+export type LinkTarget =
+  | {
+      type: "host";
+      protocol: Protocol;
+      host: string;
+      port: number;
+      username: string | null;
+    }
+  // Saved profile by name or id
+  | { type: "profile"; name: string };
+
+export type LinkState =
+  // Waiting for confirm_deep_link / dismiss_deep_link
+  | { state: "pending" }
+  | { state: "opening" }
+  | { state: "opened"; session_id: string }
+  | { state: "dismissed" }
+  | { state: "failed"; error: string };
+
+export type DeepLink = {
+  link_id: string;
+  url: string;
+  target: LinkTarget;
+  // Unix seconds
+  received_at: number;
+} & LinkState;
+
+// "credential-request" event, answered with provide_credentials / cancel_credentials
+export interface CredentialRequest {
+  request_id: string;
+  profile_id: string;
+  profile_name: string;
+  host: string;
+  // Saved username, to prefill the prompt
+  username: string;
+}
+
+// How a server's SSH host key compares with the known_hosts store
+export type HostKeyStatus = "known" | "unknown" | "changed";
+
+// Payload of `session:{id}:hostkey`, answered with `answer_host_key`
+export interface HostKeyPrompt {
+  request_id: string;
+  session_id: string;
+  host: string;
+  port: number;
+  // e.g. "ssh-ed25519"
+  algorithm: string;
+  // "SHA256:..." as `ssh-keygen -l` prints it
+  fingerprint: string;
+  status: HostKeyStatus;
+}
+
+// Payload of `session:{id}:hostkey_changed`, answered with repin_host_key
+export interface HostKeyChanged {
+  request_id: string;
+  session_id: string;
+  host: string;
+  port: number;
+  algorithm: string;
+  old_fingerprint: string;
+  new_fingerprint: string;
+  // Unix time in milliseconds
+  pinned_at: number;
+}
+
+export interface HostPin {
+  host: string;
+  port: number;
+  algorithm: string;
+  fingerprint: string;
+  // Unix time in milliseconds
+  pinned_at: number;
+}
+
+export interface PassphrasePrompt {
+  request_id: string;
+  session_id: string;
+  // The target, or the jump host the key belongs to
+  host: string;
+  username: string;
+  // Key path as configured, or "inline key"
+  key: string;
+  // 1 for the first prompt of this key
+  attempt: number;
+  // The previous answer was wrong
+  retry: boolean;
+}
+
+export interface Credentials {
+  // null keeps the profile's username
+  username?: string | null;
+  // Password or one-time token
+  password: string;
+}
+
+// Credential vault (vault_status, "vault-locked" event)
+export interface VaultStatus {
+  created: boolean;
+  unlocked: boolean;
+  auto_lock_secs: number;
+}
+
+// run_command result
+export interface CommandOutput {
+  command: string;
+  // Without the echoed command and the final prompt
+  output: string;
+  prompt: string;
+  duration_ms: number;
+}
+
+// exec_command result: one command run without a PTY
+export interface ExecOutput {
+  command: string;
+  stdout: string;
+  stderr: string;
+  // null if the server sent none
+  exit_status: number | null;
+  // e.g. "TERM", when killed by a signal
+  exit_signal: string | null;
+  // Output beyond 4 MiB on either stream was dropped
+  truncated: boolean;
+  duration_ms: number;
+}
+
+// run_batch options and "batch:{job_id}" events
+export interface BatchOptions {
+  // Default 8, max 64
+  concurrency?: number | null;
+  command_timeout_ms?: number | null;
+  continue_on_error?: boolean;
+  // Fail devices whose port is closed without waiting for the connect timeout
+  skip_unreachable?: boolean;
+}
+
+export type CommandResult =
+  | ({ status: "ok" } & CommandOutput)
+  | { status: "failed"; command: string; error: string }
+  | { status: "skipped"; command: string };
+
+// run_command_parsed: records keyed by TextFSM value name
+export interface ParsedOutput extends CommandOutput {
+  records: Record<string, string | string[]>[];
+}
+
+// run_command_multi, by session id
+export type MultiCommandResult = Record<string, CommandResult>;
+
+export interface DeviceResult {
+  // Profile id, or the host for CSV rows
+  id: string;
+  name: string;
+  host: string;
+  success: boolean;
+  // Connect or login failure
+  error: string | null;
+  commands: CommandResult[];
+  duration_ms: number;
+}
+
+export interface BatchReport {
+  job_id: string;
+  succeeded: number;
+  failed: number;
+  devices: DeviceResult[];
+  duration_ms: number;
+}
+
+export type BatchEvent =
+  | { type: "device_started"; name: string }
+  | { type: "device_finished"; result: DeviceResult; completed: number; total: number }
+  | { type: "finished"; report: BatchReport };
+
+// Compliance rules (load_compliance_rules / check_compliance / check_compliance_live)
+export interface ComplianceRule {
+  id: string;
+  description?: string;
+  assert: "must_contain" | "must_not_contain";
+  pattern: string;
+  // Treat pattern as a regex instead of a literal substring
+  regex?: boolean;
+  // Regex of section header lines, e.g. "^interface GigabitEthernet"
+  section?: string | null;
+  severity?: "info" | "warning" | "error";
+}
+
+export interface RuleResult {
+  rule_id: string;
+  severity: "info" | "warning" | "error";
+  passed: boolean;
+  message: string;
+  evidence: { line: number; text: string }[];
+}
+
+export interface ComplianceReport {
+  device_id: string;
+  // Snapshot id or command
+  source: string;
+  passed: boolean;
+  failed: number;
+  results: RuleResult[];
+  error: string | null;
+}
+
+// Keystroke macros (stop_macro_record / list_macros / play_macro)
+export interface Macro {
+  name: string;
+  steps: { delay_ms: number; data: number[] }[];
+  created_at: number;
+}
+
+export interface PlaybackOptions {
+  // Default true: wait for the prompt after each line instead of the recorded delays
+  wait_for_prompt?: boolean;
+  prompt_timeout_ms?: number;
+  // Divides recorded delays, default 1
+  speed?: number;
+}
+
+// precheck_targets
+export interface PrecheckOptions {
+  // Default [22, 23]
+  ports?: number[] | null;
+  ping?: boolean;
+  // Per probe, default 2000
+  timeout_ms?: number | null;
+  // Default 64, max 256
+  concurrency?: number | null;
+}
+
+export interface HostReachability {
+  host: string;
+  reachable: boolean;
+  ports: { port: number; open: boolean; latency_ms: number | null; error: string | null }[];
+  ping_ms: number | null;
+}
+
+// scan_subnet
+export interface ScanOptions {
+  // Read what open ports send first and guess the vendor
+  banners?: boolean;
+  // Default 1000
+  timeout_ms?: number;
+  // Default 128, max 512
+  concurrency?: number;
+}
+
+export interface DiscoveredHost {
+  host: string;
+  ports: { port: number; latency_ms: number; banner: string | null }[];
+  vendor: string | null;
+  dialect: DeviceDialect | null;
+}
+
+// Change guard (capture_change_snapshot / list_change_snapshots / verify_change)
+export interface SnapshotInfo {
+  id: string;
+  label: string;
+  host: string;
+  // Unix seconds
+  taken_at: number;
+  commands: string[];
+}
+
+export interface GuardSnapshot {
+  id: string;
+  label: string;
+  host: string;
+  taken_at: number;
+  captures: { command: string; output: string }[];
+}
+
+export type GuardFinding =
+  | { type: "interface_down"; interface: string }
+  | { type: "interface_missing"; interface: string }
+  | { type: "interface_up"; interface: string }
+  | { type: "route_missing"; prefix: string }
+  | { type: "route_added"; prefix: string }
+  | { type: "command_failed"; command: string; error: string };
+
+export interface VerificationReport {
+  snapshot_id: string;
+  label: string;
+  host: string;
+  verified_at: number;
+  // No interface down or missing, route missing or failed command
+  passed: boolean;
+  findings: GuardFinding[];
+  diffs: { command: string; hunks: DiffHunk[] }[];
+}
+
+// dry_run_script / dry_run_batch / dry_run_macro
+export interface PlannedSend {
+  // Session id, or the batch target's name
+  target: string;
+  host: string;
+  // Script line, or the command or step number (from 1)
+  step: number;
+  // Exactly what would be written, line endings included
+  data: string;
+  // What the run waits for after sending, e.g. "prompt (30s)"
+  wait: string | null;
+}
+
+export interface DryRunReport {
+  sends: PlannedSend[];
+  issues: { target: string | null; step: number | null; message: string }[];
+}
+
+// rotate_passwords
+export interface RotationOptions {
+  // Devices changed at once, default 8, max 64
+  concurrency?: number | null;
+}
+
+export interface RotationResult {
+  // Profile id
+  id: string;
+  name: string;
+  host: string;
+  username: string;
+  // The device accepted the change
+  changed: boolean;
+  // A new session signed in with the new password
+  verified: boolean;
+  // The new password replaced the saved one or its vault entry
+  stored: boolean;
+  error: string | null;
+  duration_ms: number;
+}
+
+// collect_topology
+export interface LldpNeighbor {
+  local_port: string;
+  // System name the neighbor advertises
+  device: string;
+  port: string | null;
+  chassis_id: string | null;
+}
+
+export interface DeviceNeighbors {
+  session_id: string;
+  host: string;
+  // Prompt hostname, else the session's host
+  name: string;
+  neighbors: LldpNeighbor[];
+  error: string | null;
+}
+
+export interface TopologyNode {
+  id: string;
+  // Set for devices the neighbors were collected from
+  session_id: string | null;
+  host: string | null;
+}
+
+export interface TopologyLink {
+  source: string;
+  source_port: string;
+  target: string;
+  target_port: string | null;
+  // Both ends reported the link
+  confirmed: boolean;
+}
+
+export interface Topology {
+  nodes: TopologyNode[];
+  links: TopologyLink[];
+  devices: DeviceNeighbors[];
+}
+
+// audit_clocks
+export interface DeviceClock {
+  session_id: string;
+  host: string;
+  name: string;
+  // First line of the clock output
+  printed: string | null;
+  zone: string | null;
+  // Unix milliseconds
+  device_ms: number | null;
+  // Positive when the device is ahead
+  offset_ms: number | null;
+  uncertainty_ms: number | null;
+  // At least 2s off
+  skewed: boolean;
+  error: string | null;
+}
+
+export interface ClockAudit {
+  ntp_server: string | null;
+  // How far the local clock is behind the NTP server
+  local_offset_ms: number | null;
+  ntp_error: string | null;
+  checked_at: number;
+  devices: DeviceClock[];
+  // Largest offset in either direction
+  max_skew_ms: number | null;
+}
+
+// get_app_logs and "app:log" events
+export type LogLevel = "error" | "warn" | "info" | "debug" | "trace";
+
+export interface LogEntry {
+  seq: number;
+  // Unix milliseconds
+  timestamp_ms: number;
+  level: LogLevel;
+  // Module path, e.g. "bspt_lib::ssh"
+  target: string;
+  message: string;
+  fields: Record<string, string>;
+}
+
+export interface LogFilter {
+  // Least severe level included
+  level?: LogLevel | null;
+  // Substring of the target
+  target?: string | null;
+  // Case-insensitive substring of the message or a field value
+  text?: string | null;
+  // Only entries with a greater seq
+  since?: number | null;
+}
+
+// Syslog receiver (start_syslog / list_syslog_messages) and "syslog" events
+export interface SyslogOptions {
+  // Default "0.0.0.0"
+  bind?: string | null;
+  // With neither port set, UDP 514
+  udp_port?: number | null;
+  tcp_port?: number | null;
+  // Look messages up in the tracer index
+  trace?: boolean;
+}
+
+export interface SyslogStatus {
+  running: boolean;
+  udp_addr: string | null;
+  tcp_addr: string | null;
+  trace: boolean;
+}
+
+export interface SyslogMessage {
+  // Unix milliseconds
+  received_at: number;
+  source: string;
+  transport: "udp" | "tcp";
+  format: "rfc3164" | "rfc5424" | "huawei" | "unknown";
+  facility: number | null;
+  // 0 (emergency) to 7 (debug)
+  severity: number | null;
+  timestamp: string | null;
+  hostname: string | null;
+  // Tag, APP-NAME or Huawei module
+  app_name: string | null;
+  // MSGID or Huawei mnemonic
+  msg_id: string | null;
+  message: string;
+  session_ids: string[];
+  profile_ids: string[];
+  // "file:line" tracer match
+  source_location: string | null;
+}
+
+// SNMP trap receiver (start_trap_receiver / list_snmp_traps) and "snmp-trap" events
+export type TrapSeverity =
+  | "critical"
+  | "major"
+  | "minor"
+  | "warning"
+  | "info"
+  | "cleared"
+  | "unknown";
+
+export interface SnmpUser {
+  name: string;
+  // SHA-256 auth password, at least 8 characters; none means noAuthNoPriv
+  auth_password?: string | null;
+}
+
+export interface TrapOptions {
+  // Default "0.0.0.0"
+  bind?: string | null;
+  // Default 162
+  port?: number | null;
+  // v2c communities accepted; empty accepts any
+  communities?: string[];
+  // v3 users accepted
+  users?: SnmpUser[];
+  // Device IPs accepted; empty accepts any
+  allowed_sources?: string[];
+  // Trap OID prefix to severity, longest match wins
+  severities?: { oid: string; severity: TrapSeverity }[];
+}
+
+export interface TrapStatus {
+  running: boolean;
+  addr: string | null;
+}
+
+export type SnmpValue =
+  | { type: "integer" | "counter32" | "gauge32" | "time_ticks" | "counter64"; value: number }
+  | { type: "string" | "hex" | "oid" | "ip_address" | "opaque"; value: string }
+  | { type: "null" | "no_such_object" | "no_such_instance" | "end_of_mib_view" };
+
+export interface VarBind {
+  oid: string;
+  // e.g. "ifDescr.5" for well-known OIDs
+  name: string | null;
+  value: SnmpValue;
+}
+
+export interface SnmpTrap {
+  // Unix milliseconds
+  received_at: number;
+  source: string;
+  version: "v2c" | "v3";
+  user: string | null;
+  inform: boolean;
+  trap_oid: string | null;
+  trap_name: string | null;
+  // sysUpTime.0 in hundredths of a second
+  uptime_ticks: number | null;
+  severity: TrapSeverity;
+  interface: string | null;
+  varbinds: VarBind[];
+  session_ids: string[];
+  profile_ids: string[];
+}
+
+// TFTP server (start_tftp_server / list_tftp_transfers) and "tftp" events
+export interface TftpOptions {
+  // Directory served; must exist
+  root: string;
+  // Default "0.0.0.0"
+  bind?: string | null;
+  // Default 69
+  port?: number | null;
+  // Accept uploads, replacing files of the same name
+  allow_write?: boolean;
+  // Client IPs accepted; empty accepts any
+  allowed_clients?: string[];
+}
+
+export interface TftpStatus {
+  running: boolean;
+  addr: string | null;
+  root: string | null;
+  allow_write: boolean;
+}
+
+export interface TftpTransfer {
+  id: string;
+  peer: string;
+  // Path under the root
+  file: string;
+  // read: the device downloads, write: the device uploads
+  direction: "read" | "write";
+  bytes: number;
+  total: number | null;
+  state: "active" | "finished" | "failed";
+  error: string | null;
+  // Unix milliseconds
+  started_at: number;
+  finished_at: number | null;
+}
+
+// FTP server (start_ftp_server / list_ftp_transfers) and "ftp" events
+export interface FtpOptions {
+  // Directory the user is jailed to; must exist
+  root: string;
+  username: string;
+  password: string;
+  // Default "0.0.0.0"
+  bind?: string | null;
+  // Default 21
+  port?: number | null;
+  // Accept uploads, deletes and new directories
+  allow_write?: boolean;
+  // Client IPs accepted; empty accepts any
+  allowed_clients?: string[];
+  // IPv4 address PASV gives out, for NAT
+  passive_address?: string | null;
+}
+
+export interface FtpStatus {
+  running: boolean;
+  addr: string | null;
+  root: string | null;
+  username: string | null;
+  allow_write: boolean;
+}
+
+// Same shape as TftpTransfer; file is the virtual path, e.g. "/images/V800R021.cc"
+export type FtpTransfer = TftpTransfer;
+
+// Alerts (save_alert_rule / list_alerts) and "alert" events
+export type AlertSeverity = "critical" | "major" | "minor" | "warning" | "info";
+
+export type AlertSource = "trigger" | "vrp" | "syslog" | "trap";
+
+export interface AlertRule {
+  // Assigned on save when empty
+  id?: string;
+  name: string;
+  enabled?: boolean;
+  // Empty means all feeds
+  sources?: AlertSource[];
+  // Least severe level that alerts
+  min_severity?: AlertSeverity | null;
+  // Regex on the title or message
+  pattern?: string | null;
+  // Default 300
+  throttle_secs?: number;
+}
+
+export interface Alert {
+  id: string;
+  rule_id: string;
+  rule_name: string;
+  source: AlertSource;
+  severity: AlertSeverity;
+  title: string;
+  message: string;
+  host: string | null;
+  session_id: string | null;
+  // Unix milliseconds
+  first_at: number;
+  last_at: number;
+  count: number;
+  notified: boolean;
+}
+
+export interface AlertEvent {
+  alert: Alert;
+  // Show a desktop notification
+  notify: boolean;
+}
+
+// Capture (start_capture / stop_capture / get_capture_status / replay_capture)
+export interface CaptureStatus {
+  path: string;
+  // Unix milliseconds
+  started_at: number;
+  records: number;
+  bytes: number;
+}
+
+export interface CaptureHeader {
+  version: number;
+  session_id: string;
+  host: string;
+  port: number;
+  protocol: Protocol;
+  charset: Charset;
+  dialect: DeviceDialect;
+  started_at: number;
+}
+
+export interface ReplayReport {
+  header: CaptureHeader;
+  records: number;
+  bytes_in: number;
+  bytes_out: number;
+  duration_ms: number;
+  telnet_commands: number;
+  lines: number;
+  events: { offset_ms: number; event: VrpEvent }[];
+  auto_responses: number;
+  // Decoded output, up to 1MB
+  output: string;
+  output_truncated: boolean;
+}
+
+// "session:{id}:zmodem" event; an offer is answered with zmodem_receive
+// (direction "receive": the device runs sz) or zmodem_send ("send": rz)
+export type ZmodemEvent =
+  | { type: "offer"; direction: "receive" | "send" }
+  | { type: "file"; name: string; size: number | null }
+  | { type: "progress"; name: string; bytes: number; size: number | null }
+  | { type: "file_done"; name: string; path: string; bytes: number }
+  // The receiver already has the file
+  | { type: "skipped"; name: string }
+  | { type: "finished"; files: number }
+  | { type: "failed"; error: string }
+  | { type: "cancelled" };
+
+// Timeline (query_timeline / list_timeline_devices / add_timeline_marker)
+export type TimelineSource = "marker" | "vrp" | "syslog" | "trap" | "tracer";
+
+export interface TimelineEntry {
+  // Unix milliseconds
+  at: number;
+  device: string;
+  source: TimelineSource;
+  severity: AlertSeverity | null;
+  title: string;
+  detail: string;
+  session_id: string | null;
+  // Scrollback line of a marker
+  line: number | null;
+  // Tracer match as file:line
+  location: string | null;
+}
+
+export interface TimelineQuery {
+  // All devices when unset
+  device?: string | null;
+  // Unix milliseconds, inclusive
+  from?: number | null;
+  to?: number | null;
+  // Empty means all sources
+  sources?: TimelineSource[];
+  text?: string | null;
+  // Default 500, newest kept
+  limit?: number;
+}
+
+// Handoff bundle (export_session_bundle), written as one JSON file
+export interface BundleDevice {
+  session_id: string;
+  host: string;
+  port: number;
+  protocol: Protocol;
+  username: string;
+  dialect: DeviceDialect;
+  charset: Charset;
+  // user@host:port, outermost first
+  jump_hosts: string[];
+  // From the last prompt in the transcript
+  hostname: string | null;
+}
+
+export interface SessionBundle {
+  format: "bspt-session-bundle";
+  version: number;
+  // Unix milliseconds
+  exported_at: number;
+  device: BundleDevice;
+  boards: VrpBoardInfo[];
+  // vrp, syslog and trap entries, oldest first
+  alarms: TimelineEntry[];
+  tracer_hits: TimelineEntry[];
+  // Escape sequences removed, passwords masked
+  transcript: string;
+}
+
+export interface BundleSummary {
+  path: string;
+  bytes: number;
+  transcript_lines: number;
+  boards: number;
+  alarms: number;
+  tracer_hits: number;
+}
+
+// Latency (get_session_latency / probe_latency / session:{id}:latency)
+export interface LatencyStats {
+  samples: number;
+  last_ms: number | null;
+  min_ms: number | null;
+  max_ms: number | null;
+  avg_ms: number | null;
+  p95_ms: number | null;
+  jitter_ms: number | null;
+  // Average round trip of 500ms or more
+  laggy: boolean;
+  unanswered: number;
+  // Unix milliseconds
+  last_sample_at: number | null;
+}
+
+// Device archive (get_device_archive / list_archive_devices / prune_device_archive)
+export type ArchiveKind = 'logs' | 'configs' | 'alarms' | 'transcripts';
+
+export interface ArchivedFile {
+  kind: ArchiveKind;
+  name: string;
+  path: string;
+  bytes: number;
+  // Unix milliseconds
+  modified: number;
+}
+
+export interface DeviceListing {
+  host: string;
+  dir: string;
+  bytes: number;
+  // Newest first
+  files: ArchivedFile[];
+}
+
+export interface DeviceSummary {
+  host: string;
+  files: number;
+  bytes: number;
+}
+
+export interface PruneReport {
+  removed_files: number;
+  removed_bytes: number;
+}
+
+// Control API (start_control_api / get_control_api_status)
+export interface ControlStatus {
+  running: boolean;
+  // 127.0.0.1:<port>
+  addr: string | null;
+  // Bearer token every request needs
+  token: string | null;
+}
+
+// NETCONF (netconf_connect / netconf_get / netconf_get_config / netconf_edit_config / ...)
+export interface NetconfConfig {
+  host: string;
+  // Default 830
+  port?: number;
+  username: string;
+  password?: string;
+  jump_hosts?: JumpHost[];
+  // Wait for each reply, default 30
+  timeout_secs?: number | null;
+}
+
+export type NetconfDatastore = 'running' | 'candidate' | 'startup';
+
+export type NetconfDefaultOperation = 'merge' | 'replace' | 'none';
+
+export interface NetconfSessionInfo {
+  id: string;
+  host: string;
+  port: number;
+  // Assigned by the device in its hello
+  session_id: number | null;
+  capabilities: string[];
+  framing: 'end_of_message' | 'chunked';
+}
+
+// Plugins (list_plugins / reload_plugins), from <app data>/plugins/
+export interface PluginSelection {
+  // Connects instead of protocol
+  transport?: string | null;
+  // Parses output instead of dialect
+  dialect?: string | null;
+  // Applied in order after charset decoding
+  processors?: string[];
+}
+
+export interface PluginInfo {
+  name: string;
+  version: string;
+  dir: string;
+  transports: string[];
+  processors: string[];
+  dialects: string[];
+  // Why the plugin, or part of it, isn't available
+  error: string | null;
+}
+
+// Metrics (get_metrics / start_metrics_exporter / get_metrics_exporter_status)
+export interface SessionMetrics {
+  session_id: string;
+  host: string;
+  bytes_in: number;
+  bytes_out: number;
+  bytes_in_per_sec: number;
+  bytes_out_per_sec: number;
+  buffer_fill_percent: number;
+  paused: boolean;
+  pause_count: number;
+  bytes_dropped: number;
+  rtt_avg_ms: number | null;
+}
+
+export interface LatencyBucket {
+  // null for the overflow bucket
+  le_ms: number | null;
+  // Cumulative
+  count: number;
+}
+
+export interface MetricsSnapshot {
+  // Unix milliseconds
+  collected_at: number;
+  sessions: SessionMetrics[];
+  reconnects: { attempts: number; successes: number; failures: number };
+  tracer: { lookups: number; matches: number; match_rate: number };
+  commands: {
+    count: number;
+    sum_ms: number;
+    max_ms: number;
+    avg_ms: number;
+    buckets: LatencyBucket[];
+  };
+}
+
+export interface ExporterStatus {
+  running: boolean;
+  addr: string | null;
+}
+
+// watch_command and "watch:{id}" events
+export interface WatchChange {
+  op: "added" | "removed";
+  // 1-based, in the new output for additions and the old one for removals
+  line: number;
+  text: string;
+}
+
+export interface WatchUpdate {
+  watch_id: string;
+  session_id: string;
+  run: number;
+  // Unix milliseconds
+  at: number;
+  duration_ms: number;
+  // Whole output, first run only
+  output: string | null;
+  changes: WatchChange[];
+  error: string | null;
+}
+
+// Jobs (list_jobs / pause_job / resume_job / cancel_job)
+export type JobKind =
+  | "script"
+  | "batch"
+  | "backup"
+  | "upgrade"
+  | "rotation"
+  | "topology"
+  | "clock_audit"
+  | "watch";
+
+export type JobState = "running" | "paused" | "succeeded" | "failed" | "cancelled";
+
+// Also the body of "job:{id}:progress" events
+export interface JobInfo {
+  id: string;
+  kind: JobKind;
+  name: string;
+  state: JobState;
+  // Unix seconds
+  started_at: number;
+  finished_at: number | null;
+  done: number;
+  total: number;
+  message: string | null;
+  // Summary of a finished job, or its error
+  outcome: string | null;
+}
+
+// Firmware upgrades (start_upgrade; pause and cancel through the job commands)
+export type UpgradeStep =
+  | "pre_check"
+  | "transfer"
+  | "set_startup"
+  | "reboot"
+  | "wait_reachable"
+  | "post_check";
+
+export interface UpgradeOptions {
+  image_path: string;
+  // Default: the local address routing to the device
+  tftp_address?: string | null;
+  expect_version?: string | null;
+  pause_before?: UpgradeStep[];
+  transfer_timeout_secs?: number | null;
+  reboot_timeout_secs?: number | null;
+}
+
+// `upgrade:{job_id}` events
+export type UpgradeEvent =
+  | { type: "step_started"; step: UpgradeStep }
+  | { type: "step_finished"; step: UpgradeStep; output: string }
+  | { type: "paused"; step: UpgradeStep }
+  | { type: "transfer"; sent: number; total: number }
+  | { type: "failed"; step: UpgradeStep; error: string; rollback: string[] }
+  | { type: "finished"; duration_ms: number };
+
+// Automation rules (save_automation_rule / list_automation_rules)
+export type AutomationTrigger =
+  | { event: "board_status"; status: string; board_type?: string | null }
+  | { event: "command_error"; pattern?: string | null }
+  | { event: "view_change"; view?: VrpView | null };
+
+// Strings take {session} {hostname} {slot} {sub_slot} {board_type} {status} {message} {view}
+export type AutomationAction =
+  | { action: "send"; text: string }
+  | { action: "run_command"; command: string; timeout_ms?: number | null; store_as?: string | null }
+  | { action: "set_variable"; name: string; value: string }
+  | { action: "start_logging"; path: string; timestamps?: boolean }
+  | { action: "notify"; title: string; body?: string; severity?: AlertSeverity | null };
+
+export interface AutomationRule {
+  // Assigned on save when empty
+  id?: string;
+  name: string;
+  enabled?: boolean;
+  trigger: AutomationTrigger;
+  actions: AutomationAction[];
+  // Default 60
+  cooldown_secs?: number;
+}
+
+// `automation` events
+export type AutomationEvent =
+  | { type: "fired"; rule_id: string; rule_name: string; session_id: string }
+  | { type: "command_result"; rule_id: string; session_id: string; result: CommandOutput }
+  | { type: "notify"; rule_id: string; session_id: string; title: string; body: string }
+  | { type: "failed"; rule_id: string; session_id: string; error: string };
+
+// Variables (list_variables / set_variable / delete_variable)
+export type VariableScopeId = { kind: "session"; id: string } | { kind: "job"; id: string };
+
+export interface VariableScope {
+  scope: VariableScopeId;
+  variables: Record<string, string>;
+}
+
+// Automation scripts (load_script / list_scripts / run_script / stop_script)
+// run_script and dry_run_script take optional targets, more session ids for `use`
+export interface ScriptInfo {
+  name: string;
+  statements: number;
+}
+
+// "script:{run_id}" events
+export type ScriptEvent =
+  | { type: "log"; line: number; message: string }
+  | { type: "emit"; line: number; name: string; payload: string }
+  | { type: "finished" }
+  | { type: "failed"; error: string }
+  | { type: "stopped" };
+
+// Saved connection profiles (save_profile / list_profiles / search_profiles / connect_profile)
+export interface TerminalSettings {
+  // Default "xterm-256color"
+  terminal_type: string;
   cols: number;
   rows: number;
+  charset: Charset;
+  newline: NewlineMode;
+  payload_encoding: PayloadEncoding;
+}
+
+// Placeholders: {name} {folder} {host} {date} {time}
+export interface LoggingPolicy {
+  path_template: string;
+  timestamps?: boolean;
+}
+
+export interface Profile {
+  // Empty to create a new profile
+  id: string;
+  name: string;
+  // Slash-separated, e.g. "Lab/Core"; empty for the top level
+  folder?: string;
+  tags?: string[];
+  favorite?: boolean;
+  host: string;
+  port: number;
+  protocol: Protocol;
+  username?: string;
+  // Omitted to ask for it on connect
+  password?: string | null;
+  // Ask for credentials on every connect ("credential-request" event)
+  prompt_credentials?: boolean;
+  // SSH sessions authenticate with this key instead of the password
+  identity_file?: string | null;
+  proxy_jump?: string | null;
+  // Id of an SSH profile to tunnel through (may have its own jump_profile)
+  jump_profile?: string | null;
+  // See SessionConfig.connect_timeout_ms
+  connect_timeout_ms?: number | null;
+  terminal?: Partial<TerminalSettings>;
+  dialect?: DeviceDialect;
+  plugins?: PluginSelection;
+  logging?: LoggingPolicy | null;
+  // Unix time in milliseconds, set by the backend
+  created_at?: number;
+  updated_at?: number;
+}
+
+// Result of import_securecrt / import_ssh_config / import_profiles
+export type ConflictPolicy = "skip" | "overwrite" | "rename";
+
+export interface ImportIssue {
+  source: string;
+  message: string;
+}
+
+export interface ImportReport {
+  imported: Profile[];
+  skipped: ImportIssue[];
+  warnings: ImportIssue[];
+}
+
+// Result of resolve_ssh_host: what `ssh <alias>` would connect to
+export interface ResolvedHost {
+  alias: string;
+  // false when only the implicit `Host *` options applied
+  matched: boolean;
+  host: string;
+  port: number;
+  username: string;
+  // The first identity file, else password
+  auth: SshAuth;
+  identity_files: string[];
+  // ProxyJump hops, outermost first
+  jump_hosts: JumpHost[];
+  warnings: string[];
 }
 
 // Block-based terminal types
@@ -168,11 +1790,21 @@ export interface BlockMarker {
 }
 
 // Log Tracer types for source code linkage
+export type Severity =
+  | "unknown"
+  | "trace"
+  | "debug"
+  | "info"
+  | "warn"
+  | "error"
+  | "fatal";
+
 export interface TraceEvent {
   id: string;
   file: string;
   line: number;
   function: string;
+  severity: Severity;
   timestamp: Date;
   matched_text: string;
   log_line: string;
@@ -184,16 +1816,55 @@ export interface SourceLocation {
   line: number;
   function: string;
   format_string: string;
+  severity: Severity;
+}
+
+export interface SymbolLocation {
+  name: string;
+  file: string;
+  line: number;
+}
+
+export interface ResolvedFrame {
+  symbol: string;
+  offset: string | null;
+  locations: SymbolLocation[];
 }
 
 export interface IndexStats {
   files_scanned: number;
+  files_skipped: number;
   patterns_indexed: number;
+  patterns_pruned: number;
+  symbols_indexed: number;
+  symbols_pruned: number;
+  automaton_build_ms: number;
   duration_ms: number;
 }
 
+export interface TracerLimits {
+  max_patterns: number;
+  max_symbols: number;
+  max_file_bytes: number;
+}
+
 export interface TracerStats {
   indexed: boolean;
   pattern_count: number;
+  symbol_count: number;
   source_path: string | null;
+  git_commit: string | null;
+  automaton_bytes: number;
+  pattern_bytes: number;
+  automaton_build_ms: number;
+  limits: TracerLimits;
+}
+
+export interface BlameInfo {
+  commit: string;
+  author: string;
+  author_mail: string;
+  author_time: number;
+  author_tz: string;
+  summary: string;
 }
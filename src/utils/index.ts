@@ -1 +1,3 @@
 export { openInVSCode, openInVSCodeRemote, openInSystemEditor } from "./vscode";
+export { decodePayload, DEFAULT_PAYLOAD_ENCODING } from "./payload";
+export type { SessionPayload } from "./payload";
@@ -0,0 +1,21 @@
+import { PayloadEncoding } from "../types/session";
+
+// Body of a `session:{id}` data event: number array (json) or base64 string
+export type SessionPayload = number[] | string;
+
+// Encoding requested for new sessions; base64 is ~3x smaller on the wire
+export const DEFAULT_PAYLOAD_ENCODING: PayloadEncoding = "base64";
+
+// Decode a session data event into raw bytes
+export function decodePayload(payload: SessionPayload): Uint8Array {
+  if (typeof payload !== "string") {
+    return new Uint8Array(payload);
+  }
+
+  const binary = atob(payload);
+  const bytes = new Uint8Array(binary.length);
+  for (let i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i);
+  }
+  return bytes;
+}